@@ -1,4 +1,4 @@
-#![allow(clippy::unwrap_used)]
+#![allow(clippy::unwrap_used, clippy::indexing_slicing)]
 
 use criterion::{Criterion, black_box, criterion_group, criterion_main};
 use mnc::{sdds, vita49};
@@ -0,0 +1,51 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+// The writer's hot path: a full batch through NetworkSink, which is one
+// sendmmsg per batch. TTL 0 keeps the traffic on this host. Save a baseline
+// with `--save-baseline` before touching send_batch and compare against it.
+use std::sync::Arc;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use mnc::Packets;
+use mnc::sink::{Fanout, NetworkSink, Sink, TransmitCounters};
+
+const BATCH_SIZE: usize = 64;
+
+fn full_batch(bytes: usize) -> Packets {
+    let mut packets = Packets::new(BATCH_SIZE, bytes);
+    for packet in packets.iter_mut() {
+        packet.set_length(bytes);
+    }
+    packets
+}
+
+fn bench_send(c: &mut Criterion) {
+    let mut g = c.benchmark_group("send");
+    g.throughput(Throughput::Elements(BATCH_SIZE as u64));
+
+    for (name, mgroup) in [
+        ("one_group", "239.255.77.30"),
+        ("four_groups", "239.255.77.31-34"),
+    ] {
+        let mut sink = NetworkSink::new(
+            None,
+            mgroup,
+            &[39530],
+            0,
+            None,
+            Fanout::default(),
+            Arc::new(TransmitCounters::default()),
+        )
+        .expect("sink");
+        let packets = full_batch(64);
+
+        g.bench_function(name, |b| {
+            b.iter(|| sink.write_packets(packets.packets()).unwrap());
+        });
+    }
+
+    g.finish();
+}
+
+criterion_group!(benches, bench_send);
+criterion_main!(benches);
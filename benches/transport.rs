@@ -0,0 +1,69 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+// Reader -> writer hop with batches recycling through a memory channel,
+// the same shape as the real pipeline but without any socket I/O.
+use std::time::{Duration, Instant};
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use mnc::Packets;
+use mnc::transport::{self, BatchReceiver, BatchSender, TransportKind};
+
+const BATCHES: u64 = 100_000;
+const POOL_SIZE: usize = 64;
+
+fn run(kind: TransportKind, batches: u64) -> Duration {
+    let (mut data_tx, mut data_rx) = transport::bounded(kind, POOL_SIZE + 1);
+    let (memory_tx, memory_rx) = crossbeam_channel::bounded(POOL_SIZE + 1);
+    for _ in 0..POOL_SIZE {
+        memory_tx.send(Packets::new(4, 64)).unwrap();
+    }
+
+    let start = Instant::now();
+
+    let consumer = std::thread::spawn(move || {
+        let mut received = 0u64;
+        while received < batches {
+            if let Ok(packets) = data_rx.pop_timeout(Duration::from_millis(100)) {
+                received += 1;
+                let _ = memory_tx.send(packets);
+            }
+        }
+    });
+
+    for _ in 0..batches {
+        let mut packets = memory_rx.recv().unwrap();
+        loop {
+            match data_tx.try_push(packets) {
+                Ok(()) => break,
+                Err(crossbeam_channel::TrySendError::Full(p)) => packets = p,
+                Err(crossbeam_channel::TrySendError::Disconnected(_)) => return start.elapsed(),
+            }
+        }
+    }
+
+    consumer.join().unwrap();
+    start.elapsed()
+}
+
+fn bench_transport(c: &mut Criterion) {
+    let mut g = c.benchmark_group("transport");
+    g.throughput(Throughput::Elements(BATCHES));
+    g.sample_size(10);
+
+    g.bench_function("crossbeam_channel", |b| {
+        b.iter_custom(|iters| {
+            (0..iters)
+                .map(|_| run(TransportKind::Channel, BATCHES))
+                .sum()
+        });
+    });
+
+    g.bench_function("spsc_ring", |b| {
+        b.iter_custom(|iters| (0..iters).map(|_| run(TransportKind::Ring, BATCHES)).sum());
+    });
+
+    g.finish();
+}
+
+criterion_group!(benches, bench_transport);
+criterion_main!(benches);
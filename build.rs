@@ -0,0 +1,13 @@
+// recvmmsg and sendmmsg move a whole batch of datagrams per system call, but
+// only Linux has them. Everywhere else, and on Linux with the portable
+// feature, the reader and writer fall back to one call per datagram.
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(mmsg)");
+    println!("cargo::rerun-if-changed=build.rs");
+
+    let linux = std::env::var("CARGO_CFG_TARGET_OS").is_ok_and(|os| os == "linux");
+    let portable = std::env::var_os("CARGO_FEATURE_PORTABLE").is_some();
+    if linux && !portable {
+        println!("cargo::rustc-cfg=mmsg");
+    }
+}
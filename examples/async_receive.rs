@@ -0,0 +1,55 @@
+//! Receive packets from a multicast group with the async API.
+//!
+//!   cargo run --example async_receive --features tokio -- 239.1.1.1 29495 10
+use mnc::async_reader::{self, AsyncReaderConfig};
+use mnc::{MAX_PACKET_BYTES, PacketType, Packets, SharedState};
+use tokio::sync::mpsc;
+
+const BATCH_SIZE: usize = 64;
+const POOL_SIZE: usize = 8;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let mgroup = args.next().unwrap_or_else(|| "239.1.1.1".to_string());
+    let port = args.next().map(|p| p.parse()).transpose()?.unwrap_or(29495);
+    let max_count = args.next().map(|c| c.parse()).transpose()?.unwrap_or(10);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(async move {
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let (data_tx, mut data_rx) = mpsc::channel(POOL_SIZE);
+        let (memory_return_tx, memory_return_rx) = mpsc::channel(POOL_SIZE);
+        for _ in 0..POOL_SIZE {
+            memory_return_tx
+                .send(Packets::new(BATCH_SIZE, MAX_PACKET_BYTES))
+                .await?;
+        }
+
+        let reader = async_reader::spawn(AsyncReaderConfig {
+            iface: None,
+            mgroup,
+            port,
+            channels: (data_tx, memory_return_rx),
+            shared_state: shared_state.clone(),
+            max_count,
+        });
+
+        while let Some(packets) = data_rx.recv().await {
+            // An empty batch is the EOF sentinel
+            if packets.is_empty() {
+                break;
+            }
+            for packet in packets.iter() {
+                println!("{} bytes", packet.len());
+            }
+            // Hand the buffers back. The reader may already be done with the pool.
+            let _ = memory_return_tx.send(packets).await;
+        }
+
+        reader.await??;
+        Ok(())
+    })
+}
@@ -0,0 +1,273 @@
+/// Async counterpart of the network reader for tokio based embedders.
+/// The socket is the same socket2 Socket the sync reader uses, registered
+/// with tokio through AsyncFd so we keep recvmmsg batching.
+/// Packets still recycle: the consumer must hand every batch back through
+/// the memory return channel.
+use std::io::{self, IoSliceMut};
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use nix::sys::socket::{MsgFlags, SockaddrStorage};
+#[cfg(mmsg)]
+use nix::sys::socket::{MultiHeaders, recvmmsg};
+#[cfg(not(mmsg))]
+use nix::{errno::Errno, sys::socket::recvmsg};
+use socket2::Socket;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::{
+    MAX_PACKET_BYTES, SharedState,
+    error::{LibError, Result},
+    multicast::create_recv_socket,
+    packet::Packets,
+};
+
+// How often to wake up and look at should_exit on an idle socket.
+const EXIT_POLL: Duration = Duration::from_millis(100);
+
+/// Batch size comes from the Packets handed in through the memory channel.
+pub struct AsyncReaderConfig {
+    pub iface: Option<String>,
+    pub mgroup: String,
+    pub port: u16,
+    pub channels: (mpsc::Sender<Packets>, mpsc::Receiver<Packets>),
+    pub shared_state: SharedState,
+    pub max_count: u64,
+}
+
+/// Receives batches of multicast packets without blocking the runtime.
+pub struct AsyncMulticastReceiver {
+    socket: AsyncFd<Socket>,
+}
+
+impl AsyncMulticastReceiver {
+    /// Join the group. Must be called from within a tokio runtime.
+    pub fn new(iface: Option<&str>, mgroup: &str, port: u16) -> Result<Self> {
+        let socket = create_recv_socket(iface, mgroup, port)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket: AsyncFd::new(socket)?,
+        })
+    }
+
+    /// Fill packets with at least one datagram.
+    /// Returns None if should_exit was signaled while waiting.
+    pub async fn recv(
+        &mut self,
+        packets: &mut Packets,
+        shared_state: &SharedState,
+    ) -> Result<Option<usize>> {
+        loop {
+            if shared_state.should_exit() {
+                return Ok(None);
+            }
+
+            let Ok(guard) = tokio::time::timeout(EXIT_POLL, self.socket.readable()).await else {
+                continue;
+            };
+            let mut guard = guard?;
+
+            match guard.try_io(|socket| recv_batch(socket.as_raw_fd(), packets)) {
+                Ok(received) => return Ok(Some(received?)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+// MultiHeaders holds raw pointers and is not Send, so it can't live across an
+// await in a spawned task. Allocate it per batch like the sendmmsg writer does.
+#[cfg(mmsg)]
+fn recv_batch(fd: RawFd, packets: &mut Packets) -> io::Result<usize> {
+    let mut headers = MultiHeaders::<SockaddrStorage>::preallocate(packets.len(), None);
+
+    // Pooled packets may start empty, see initialize_memory_pool_with
+    for packet in packets.iter_mut() {
+        packet.ensure_capacity(MAX_PACKET_BYTES);
+        packet.set_length(MAX_PACKET_BYTES);
+    }
+
+    let mut iovecs: Vec<[IoSliceMut; 1]> = packets
+        .iter_mut()
+        .map(|packet| [IoSliceMut::new(packet.data_mut())])
+        .collect();
+
+    let byte_counts: Vec<usize> =
+        recvmmsg(fd, &mut headers, &mut iovecs, MsgFlags::MSG_DONTWAIT, None)
+            .map_err(io::Error::from)?
+            .map(|msg| msg.bytes)
+            .collect();
+
+    for (packet, &bytes) in packets.iter_mut().zip(byte_counts.iter()) {
+        packet.set_length(bytes);
+    }
+    packets.set_length(byte_counts.len());
+
+    Ok(byte_counts.len())
+}
+
+// One recvmsg per datagram for systems without recvmmsg. WouldBlock before
+// the first datagram goes back to tokio; after it, the batch is just short.
+#[cfg(not(mmsg))]
+fn recv_batch(fd: RawFd, packets: &mut Packets) -> io::Result<usize> {
+    let mut count = 0;
+    for packet in packets.iter_mut() {
+        packet.ensure_capacity(MAX_PACKET_BYTES);
+        packet.set_length(MAX_PACKET_BYTES);
+        let mut iov = [IoSliceMut::new(packet.data_mut())];
+        match recvmsg::<SockaddrStorage>(fd, &mut iov, None, MsgFlags::MSG_DONTWAIT) {
+            Ok(msg) => {
+                let bytes = msg.bytes;
+                packet.set_length(bytes);
+                count += 1;
+            }
+            Err(Errno::EAGAIN) if count > 0 => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    packets.set_length(count);
+
+    Ok(count)
+}
+
+/// Spawn the async reader on the current runtime.
+/// Same contract as the sync reader: an empty batch marks EOF.
+pub fn spawn(config: AsyncReaderConfig) -> tokio::task::JoinHandle<Result<()>> {
+    tokio::spawn(async move {
+        let shared_state = config.shared_state.clone();
+        run_async_reader(config)
+            .await
+            .inspect(|_| log::debug!("async reader exited"))
+            .inspect_err(|e| {
+                log::debug!("{e:?}");
+                shared_state.signal_exit()
+            })
+    })
+}
+
+pub async fn run_async_reader(
+    AsyncReaderConfig {
+        iface,
+        mgroup,
+        port,
+        channels: (data_tx, mut memory_return_rx),
+        shared_state,
+        max_count,
+    }: AsyncReaderConfig,
+) -> Result<()> {
+    let mut receiver = AsyncMulticastReceiver::new(iface.as_deref(), &mgroup, port)?;
+
+    // A batch we failed to hand off is reused rather than lost from the pool.
+    let mut spare: Option<Packets> = None;
+
+    loop {
+        let mut packets = match spare.take() {
+            Some(packets) => packets,
+            None => match memory_return_rx.recv().await {
+                Some(packets) => packets,
+                None => break,
+            },
+        };
+
+        if max_count > 0 && shared_state.get_read_count() >= max_count {
+            packets.set_length(0);
+            let _ = data_tx.send(packets).await;
+            break;
+        }
+
+        let Some(count_received) = receiver.recv(&mut packets, &shared_state).await? else {
+            break;
+        };
+
+        let mut send_count = count_received;
+        if max_count > 0 {
+            let remaining = max_count.saturating_sub(shared_state.get_read_count());
+            send_count = send_count.min(remaining as usize);
+        }
+        packets.set_length(send_count);
+        shared_state.add_read_bytes(packets.iter().map(|packet| packet.len() as u64).sum());
+
+        match data_tx.try_send(packets) {
+            Ok(()) => {}
+            Err(TrySendError::Full(packets)) => {
+                shared_state.add_dropped_batch();
+                log::warn!("dropping packets");
+                spare = Some(packets);
+            }
+            Err(TrySendError::Closed(_)) => {
+                return Err(LibError::Critical("channel disconnected".to_string()));
+            }
+        }
+
+        let already_sent = shared_state.add_read_count(send_count as u64);
+        if max_count > 0 && already_sent >= max_count {
+            let _ = data_tx.send(Packets::empty()).await;
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use super::*;
+    use crate::PacketType;
+
+    #[test]
+    fn test_receive_loopback() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("runtime");
+
+        runtime.block_on(async {
+            let shared_state = SharedState::new(PacketType::Binary, false);
+            let (data_tx, mut data_rx) = mpsc::channel(4);
+            let (memory_return_tx, memory_return_rx) = mpsc::channel(4);
+            for _ in 0..2 {
+                memory_return_tx
+                    .send(Packets::new(8, MAX_PACKET_BYTES))
+                    .await
+                    .expect("pool");
+            }
+
+            let handle = spawn(AsyncReaderConfig {
+                iface: None,
+                mgroup: "239.255.77.1".to_string(),
+                port: 39501,
+                channels: (data_tx, memory_return_rx),
+                shared_state: shared_state.clone(),
+                max_count: 3,
+            });
+
+            // Give the reader a moment to join before sending.
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+            for payload in [&b"one"[..], b"two", b"three"] {
+                sender.send_to(payload, "239.255.77.1:39501").expect("send");
+            }
+
+            let mut received = Vec::new();
+            while let Some(packets) = data_rx.recv().await {
+                if packets.is_empty() {
+                    break;
+                }
+                received.extend(packets.iter().map(|packet| packet.to_vec()));
+                // The reader may already be done with the pool
+                let _ = memory_return_tx.send(packets).await;
+            }
+
+            handle.await.expect("join").expect("reader");
+            assert_eq!(
+                received,
+                vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+            );
+        });
+    }
+}
@@ -0,0 +1,156 @@
+/// What a run is about to do, logged at startup so a log shows it without
+/// the command line, and printed as JSON by --print-config-json. Everything
+/// in it is settled before any thread starts; the join itself is checked
+/// later by the reader, with [`crate::multicast::verify_join`].
+use std::net::Ipv4Addr;
+
+use serde::Serialize;
+
+use crate::preflight::format_size;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StartupConfig {
+    /// The interface received on, None when not receiving from a group or
+    /// when it couldn't be found
+    pub interface: Option<Interface>,
+    /// The group received from, None when reading -i
+    pub group: Option<String>,
+    pub ports: Vec<u16>,
+    /// The -i file, or "-" for stdin
+    pub input: Option<String>,
+    pub rcvbuf: Option<RecvBuffer>,
+    pub packet_type: String,
+    pub outputs: Vec<Output>,
+    pub limits: Limits,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Interface {
+    pub name: String,
+    pub addr: Ipv4Addr,
+}
+
+/// SO_RCVBUF asked for and what the kernel gave, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RecvBuffer {
+    pub requested: usize,
+    pub granted: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Output {
+    /// A file name, "stdout", "discard", exec `command` or [eth:]group:port
+    pub sink: String,
+    /// How packets are laid out, None for datagrams sent as they are
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Limits {
+    pub count: Option<u64>,
+    pub duration_secs: Option<u64>,
+    pub rate: Option<u64>,
+    pub pps: Option<u64>,
+}
+
+impl StartupConfig {
+    /// The banner, a line for each part.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        match (&self.group, &self.input) {
+            (Some(group), _) => {
+                let ports: Vec<String> = self.ports.iter().map(u16::to_string).collect();
+                let on = self
+                    .interface
+                    .as_ref()
+                    .map_or_else(String::new, |interface| {
+                        format!(" on {} ({})", interface.name, interface.addr)
+                    });
+                lines.push(format!("source: {group} port {}{on}", ports.join(",")));
+            }
+            (None, Some(input)) if input == "-" => lines.push("source: stdin".to_string()),
+            (None, Some(input)) => lines.push(format!("source: {input}")),
+            (None, None) => {}
+        }
+        if let Some(rcvbuf) = self.rcvbuf {
+            lines.push(format!(
+                "rcvbuf: {} requested, {} granted",
+                format_size(rcvbuf.requested as u64),
+                format_size(rcvbuf.granted as u64)
+            ));
+        }
+        lines.push(format!("packet type: {}", self.packet_type));
+        for output in &self.outputs {
+            lines.push(match &output.format {
+                Some(format) => format!("output: {} as {format}", output.sink),
+                None => format!("output: {}", output.sink),
+            });
+        }
+        let limits = self.limits.describe();
+        if !limits.is_empty() {
+            lines.push(format!("limits: {}", limits.join(", ")));
+        }
+        lines
+    }
+}
+
+impl Limits {
+    fn describe(&self) -> Vec<String> {
+        let mut limits = Vec::new();
+        if let Some(count) = self.count {
+            limits.push(format!("-c {count}"));
+        }
+        if let Some(duration) = self.duration_secs {
+            limits.push(format!("--duration {duration}s"));
+        }
+        if let Some(rate) = self.rate {
+            limits.push(format!("--rate {rate}"));
+        }
+        if let Some(pps) = self.pps {
+            limits.push(format!("--pps {pps}"));
+        }
+        limits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let config = StartupConfig {
+            interface: Some(Interface {
+                name: "eth1".to_string(),
+                addr: Ipv4Addr::new(10, 0, 0, 5),
+            }),
+            group: Some("239.1.1.1".to_string()),
+            ports: vec![5000, 5001],
+            input: None,
+            rcvbuf: Some(RecvBuffer {
+                requested: 256 * 1024 * 1024,
+                granted: 416 * 1024,
+            }),
+            packet_type: "sdds".to_string(),
+            outputs: vec![Output {
+                sink: "capture.bin".to_string(),
+                format: Some("binary".to_string()),
+            }],
+            limits: Limits {
+                count: Some(1000),
+                duration_secs: Some(60),
+                ..Limits::default()
+            },
+        };
+        assert_eq!(
+            config.lines(),
+            [
+                "source: 239.1.1.1 port 5000,5001 on eth1 (10.0.0.5)",
+                "rcvbuf: 256.0 MiB requested, 416.0 KiB granted",
+                "packet type: sdds",
+                "output: capture.bin as binary",
+                "limits: -c 1000, --duration 60s",
+            ]
+        );
+    }
+}
@@ -0,0 +1,1171 @@
+/// The command line: every flag, and the parsers for the values clap can't
+/// take apart on its own.
+use clap::{Parser, ValueEnum};
+use regex::Regex;
+
+use crate::{
+    MAX_PACKET_BYTES,
+    convert::{self, Conversion},
+    fec, filter,
+    heartbeat::{self, HeartbeatConfig},
+    multicast,
+    packet::PacketType,
+    patch::{self, Patch},
+    police::{self, PoliceRate},
+    preflight,
+    reader::InputFormat,
+    sched::{self, CpuAssignment},
+    sdds::SddsEpoch,
+    sigmf,
+    sink::{Fanout, OutputFormat, SplitBy, TimestampFormat},
+    template::{self, Template},
+};
+
+// Every example is parsed by the test suite, so keep them runnable.
+pub const EXAMPLES: &str = "EXAMPLES:
+  # Receive from multicast group and display text payload
+  mnc 239.1.1.1 -o -
+
+  # Send string to multcast group as a single packet
+  echo \"Hello World\" | mnc 239.1.1.1 -i -
+
+  # Receive \"Hello World\" on eth1
+  mnc eth1:239.1.1.1
+
+  # Join from one of eth1's addresses when it has several
+  mnc eth1/10.1.2.3:239.1.1.1
+
+  # Receive and display statistics every second
+  mnc 239.1.1.1 -s
+
+  # Display a hex dump of the first packet received
+  mnc 239.1.1.1 -v
+
+  # Receive exactly 10 packets then exit
+  mnc 239.1.1.1 -c 10
+
+  # Send file contents to multicast group, each line a packet
+  mnc 239.1.1.1 -i ./file.txt
+
+  # Save multicast to file
+  mnc 239.1.1.1 -o ./output.txt
+
+  # Record to a file while watching on the terminal
+  mnc 239.1.1.1 -o ./output.txt -o -
+
+  # Send a capture and keep a copy of what was sent
+  mnc 239.1.1.1 -i ./capture.bin -t binary -o ./sent.bin --tx
+
+  # Convert a capture without touching the network
+  mnc 239.1.1.1 -i ./capture.bin -t binary -o - --local
+
+  # Record from eth0 and relay onto eth1
+  mnc eth0:239.1.1.1 -o ./capture.bin --tx=eth1:239.1.1.1
+
+  # Keep the multicast tree alive with a numbered packet every second
+  mnc 239.1.1.1 --heartbeat '1s:alive {seq}'
+
+  # Load test with 1000 numbered packets a second for a minute
+  mnc 239.1.1.1 --template 'load {seq} {time_iso}' --pps 1000 --duration 60
+
+  # Spread a capture over 16 groups, each packet to the next group in turn
+  mnc 239.1.1.1-16 -i ./capture.bin -t binary --fanout rr
+
+  # Stamp each received line with its arrival time
+  mnc 239.1.1.1 -o - --timestamps
+
+  # POST each JSON payload somewhere
+  mnc 239.1.1.1 --exec-per-packet 'curl -s -d @- http://collector/ingest'
+
+  # Show periodic SDDS statistics
+  mnc 239.1.1.1 -t sdds -s
+
+  # Show periodic VITA49 statistics with given port
+  mnc 239.1.1.1 -p 12345 -t vita49 -s
+
+  # Summarize what is being advertised over mDNS
+  mnc 224.0.0.251 -p 5353 -t mdns -v -c 10
+
+  # Check an overnight capture setup without joining the group
+  mnc eth1:239.1.1.1 -o ./capture.bin --dry-run --min-free 50G
+
+  # Run a saved setup from a config file
+  mnc --config site.toml --profile sensor-a
+
+  # Pin the receive path to isolated cores with realtime priority
+  mnc 239.1.1.1 -o ./capture.bin -t binary --cpu reader=2,writer=3 --rt-priority 50";
+
+#[derive(Parser)]
+#[command(name = "mnc")]
+#[command(group = clap::ArgGroup::new("text_sink").args(["output", "exec"]).multiple(false))]
+#[command(group = clap::ArgGroup::new("framed_input").args(["input", "expect"]).multiple(true))]
+#[command(about = "Multicast netcat - CLI utility for sending and receiving multicast packets")]
+#[command(after_help = EXAMPLES)]
+pub struct Args {
+    #[arg(
+        value_parser = parse_group_spec,
+        required_unless_present_any = ["config", "ctl"],
+        help = "[eth:]mgroup, or when receiving [eth:]mgroup[:port][?type=T]"
+    )]
+    pub mgroup: Option<GroupSpec>,
+
+    #[arg(
+        value_parser = parse_group_spec,
+        value_name = "MORE_GROUPS",
+        conflicts_with_all = ["ping", "echo"],
+        help = "More groups to receive at the same time, each [eth:]mgroup[:port][?type=T] with -p and -t for what it leaves out"
+    )]
+    pub more_groups: Vec<GroupSpec>,
+
+    #[arg(
+        short = 't',
+        long = "type",
+        default_value = "text",
+        help = "Multicast packet type"
+    )]
+    pub packet_type: PacketType,
+
+    #[arg(
+        short = 'i',
+        long = "input",
+        help = "Read packets from filename, or - for stdin"
+    )]
+    pub input: Option<String>,
+
+    #[arg(
+        long = "loop",
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Read the -i file N times, or over and over until -c, --duration or Ctrl-C when N is 0 or not given"
+    )]
+    pub repeat: Option<Option<u64>>,
+
+    #[arg(
+        long = "template",
+        value_parser = template::parse_template,
+        conflicts_with_all = ["input", "rx"],
+        help = "Send packets made from this instead of -i, filling in {seq}, {time_iso}, {time_unix_us} and {rand:N} for each; pace with --pps, stop with -c or --duration"
+    )]
+    pub template: Option<Template>,
+
+    #[arg(
+        short = 'o',
+        long = "output",
+        help = "Write packets to filename, or - for stdout. Repeat to write to several"
+    )]
+    pub output: Vec<String>,
+
+    #[arg(long = "rx", help = "Receive from the group, the default without -i")]
+    pub rx: bool,
+
+    #[arg(
+        long = "tx",
+        value_name = "[eth:]mgroup",
+        value_parser = parse_mgroup,
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Send to the group, the default with -i alone. Relay to another group with --tx=[eth:]mgroup"
+    )]
+    pub tx: Option<Option<(Option<String>, String)>>,
+
+    #[arg(
+        long = "local",
+        help = "Only copy -i to the outputs, never touching the network"
+    )]
+    pub local: bool,
+
+    #[arg(
+        short = 's',
+        long = "statistics",
+        help = "Display statistics every second"
+    )]
+    pub stats: bool,
+
+    #[arg(
+        long = "stats-on-change",
+        value_name = "PERCENT",
+        num_args = 0..=1,
+        require_equals = true,
+        value_parser = parse_change_percent,
+        help = "Print stats lines only when the rate moves more than PERCENT from the last interval, sequence numbers skip, a source comes or goes or a header field changes [default: 10]"
+    )]
+    pub stats_on_change: Option<Option<f64>>,
+
+    #[arg(
+        long = "sender-timeout",
+        value_name = "SECS",
+        default_value = "10",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "With statistics, log a sender as silent once nothing came from it for this many seconds"
+    )]
+    pub sender_timeout: u64,
+
+    #[arg(
+        long,
+        overrides_with = "numeric",
+        help = "Show sources by host name once a reverse DNS lookup in the background finds one"
+    )]
+    pub resolve: bool,
+
+    #[arg(
+        long,
+        overrides_with = "resolve",
+        help = "Show sources as addresses, without looking up their names [default]"
+    )]
+    pub numeric: bool,
+
+    #[arg(
+        short = 'p',
+        long = "port",
+        value_name = "PORT",
+        default_value = "29495",
+        value_parser = parse_ports,
+        help = "Multicast port. Receive on several with a list or range like 29495-29498"
+    )]
+    pub port: Ports,
+
+    #[arg(
+        short = 'b',
+        long = "batch-size",
+        default_value = "100",
+        help = "packets per recvmmsg"
+    )]
+    pub batch_size: usize,
+
+    #[arg(
+        long = "batch-stats",
+        help = "Show how many packets each recvmmsg call returned, to tune --batch-size: on the stats line and in the summary"
+    )]
+    pub batch_stats: bool,
+
+    #[arg(
+        long = "max-latency",
+        value_name = "MS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Send a part --lines-per-packet bundle, and push out what the outputs buffer, once it has waited MS milliseconds"
+    )]
+    pub max_latency: Option<u64>,
+
+    #[arg(
+        long = "stall-threshold",
+        value_name = "MS",
+        default_value = "10",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Count the reader as stalled when it gets back to the sockets more than MS milliseconds after datagrams were waiting, on the stats line and in the summary"
+    )]
+    pub stall_threshold: u64,
+
+    #[arg(
+        long = "unique",
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Count distinct payloads on the stats line and in the summary, exactly up to N of them and estimated past it [default: 100000]"
+    )]
+    pub unique: Option<Option<u64>>,
+
+    #[arg(
+        short = 'B',
+        long = "pool-size",
+        default_value = "100",
+        help = "Memory pool packet batches"
+    )]
+    pub pool_size: usize,
+
+    #[arg(
+        short = 'L',
+        long = "ttl",
+        default_value = "255",
+        help = "Multicast hop limit"
+    )]
+    pub ttl: u8,
+
+    #[arg(short = 'q', long = "quiet", help = "Quiet mode: suppress all output")]
+    pub quiet: bool,
+
+    #[arg(
+        short = 'c',
+        long = "count",
+        help = "Exit after count packets (0 = no limit): sent when sending -i to a group, read otherwise"
+    )]
+    pub count: Option<u64>,
+
+    #[arg(
+        long = "count-rx",
+        conflicts_with = "count_tx",
+        help = "-c counts packets read, whether or not they are written"
+    )]
+    pub count_rx: bool,
+
+    #[arg(
+        long = "count-tx",
+        help = "-c counts packets written, reading on until that many got through"
+    )]
+    pub count_tx: bool,
+
+    #[arg(
+        short = 'r',
+        long = "rate",
+        help = "Rate limit by adding rate noop instructions between sendmsg calls; --pps sets an actual rate"
+    )]
+    pub rate: Option<u64>,
+
+    #[arg(
+        long = "txtime",
+        conflicts_with = "rate",
+        help = "Have the qdisc launch each packet on schedule with SO_TXTIME (needs --pps and an etf or fq qdisc)"
+    )]
+    pub txtime: bool,
+
+    #[arg(
+        long = "pps",
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "rate",
+        help = "Send N packets a second, or launch them at that rate with --txtime, or make that many with --template"
+    )]
+    pub pps: Option<u64>,
+
+    #[arg(
+        long = "pps-burst",
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Packets --pps may send at once to catch up after falling behind [default: ten milliseconds' worth]"
+    )]
+    pub pps_burst: Option<u64>,
+
+    #[arg(
+        long = "speed",
+        value_name = "FACTOR",
+        value_parser = parse_speed,
+        help = "Replay timed input (--input-format jsonl or timed) or --pps this many times faster, e.g. 10 or 0.1; 0 ignores the recorded timing"
+    )]
+    pub speed: Option<f64>,
+
+    #[arg(
+        long = "stamp",
+        value_name = "OFFSET",
+        help = "Write the send time into each packet at byte OFFSET (u64 ns since the epoch, big endian)"
+    )]
+    pub stamp: Option<usize>,
+
+    #[arg(
+        long = "measure-latency",
+        value_name = "OFFSET",
+        help = "Report one-way latency each second from the sender's --stamp at OFFSET (needs synced clocks)"
+    )]
+    pub measure_latency: Option<usize>,
+
+    #[arg(
+        long = "stamp-seq",
+        value_name = "OFFSET",
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Number each packet (u32 big endian), in front of the payload or over the bytes at --stamp-seq=OFFSET"
+    )]
+    pub stamp_seq: Option<Option<usize>>,
+
+    #[arg(
+        long = "check-seq",
+        value_name = "OFFSET",
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Report loss, reordering and duplicates each second from the sender's --stamp-seq, removing a prepended number"
+    )]
+    pub check_seq: Option<Option<usize>>,
+
+    #[arg(
+        long = "verify-template",
+        value_name = "TEMPLATE",
+        value_parser = template::parse_template,
+        conflicts_with = "check_seq",
+        help = "Report loss, reordering and duplicates each second from the {seq} in the sender's --template"
+    )]
+    pub verify_template: Option<Template>,
+
+    #[arg(
+        long = "sample-rate",
+        visible_alias = "sdds-rate",
+        value_name = "HZ",
+        value_parser = parse_sample_rate,
+        help = "Sample rate of SDDS or VITA-49 data, for the SDDS time tag check and SigMF; read from the stream when not given"
+    )]
+    pub sample_rate: Option<f64>,
+
+    #[arg(
+        long = "sdds-epoch",
+        value_name = "YEAR|UNIX_SECONDS",
+        value_parser = parse_sdds_epoch,
+        help = "What SDDS time tags count from, to show them as UTC instead of day of year"
+    )]
+    pub sdds_epoch: Option<SddsEpoch>,
+
+    #[arg(
+        long = "timetag-offset",
+        requires = "sdds_epoch",
+        help = "Report how long after its SDDS time tag each packet arrived, and the drift between the clocks"
+    )]
+    pub timetag_offset: bool,
+
+    #[arg(
+        long = "drop-parity",
+        conflicts_with = "only_parity",
+        help = "Leave SDDS parity packets out of the outputs; statistics still count them, and so does -c"
+    )]
+    pub drop_parity: bool,
+
+    #[arg(
+        long = "only-parity",
+        help = "Write only SDDS parity packets, to check the FEC stream alone; -c still counts every packet"
+    )]
+    pub only_parity: bool,
+
+    #[arg(
+        long = "filter-stream-id",
+        value_name = "ID",
+        value_parser = filter::parse_stream_id,
+        help = "Write only VITA-49 frames with this stream id (decimal or 0x hex); repeat for several"
+    )]
+    pub filter_stream_id: Vec<u32>,
+
+    #[arg(
+        long = "filter-sdds-mode",
+        value_name = "N",
+        value_parser = clap::value_parser!(u8).range(0..=7),
+        help = "Write only SDDS packets in data mode N"
+    )]
+    pub filter_sdds_mode: Option<u8>,
+
+    #[arg(
+        long = "vita49-context-gap",
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Warn when more than N VITA-49 packets arrive without a context packet"
+    )]
+    pub vita49_context_gap: Option<u64>,
+
+    #[arg(
+        long = "fanout",
+        value_name = "MODE",
+        default_value = "rr",
+        help = "When sending to a list or range of groups, rotate through them (rr) or send every packet to each (dup)"
+    )]
+    pub fanout: Fanout,
+
+    #[arg(
+        long = "rewrite-dst",
+        help = "Send replayed packets to the group given here, not the destination recorded with each (jsonl dst)"
+    )]
+    pub rewrite_dst: bool,
+
+    #[arg(
+        long = "police",
+        value_name = "PPS[:BURST]",
+        value_parser = police::parse_police,
+        help = "Pass at most PPS packets a second on to the outputs and drop the rest, like a policer; BURST defaults to a tenth of a second's worth"
+    )]
+    pub police: Option<PoliceRate>,
+
+    #[arg(
+        long = "reorder",
+        value_name = "DEPTH",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Put packets back in sequence order (SDDS, VITA-49 or --check-seq numbers), holding up to DEPTH of them for a late one"
+    )]
+    pub reorder: Option<u64>,
+
+    #[arg(
+        long = "dejitter",
+        value_name = "MS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Hold each received packet MS milliseconds after it arrived before writing it, to even out network jitter"
+    )]
+    pub dejitter: Option<u64>,
+
+    #[arg(
+        long = "fec",
+        value_name = "K",
+        value_parser = clap::value_parser!(u8).range(1..=i64::from(fec::MAX_GROUP)),
+        conflicts_with_all = ["stamp", "stamp_seq"],
+        help = "Frame each packet sent with an FEC header and follow every K with an XOR parity packet, for --fec-decode"
+    )]
+    pub fec: Option<u8>,
+
+    #[arg(
+        long = "fec-decode",
+        value_name = "K",
+        value_parser = clap::value_parser!(u8).range(1..=i64::from(fec::MAX_GROUP)),
+        conflicts_with_all = ["reorder", "dejitter", "check_seq", "measure_latency"],
+        help = "Take the framing off a --fec K stream, drop its parity packets and rebuild one lost packet per group"
+    )]
+    pub fec_decode: Option<u8>,
+
+    #[arg(
+        long = "convert",
+        value_name = "FROM:TO",
+        value_parser = convert::parse_conversion,
+        conflicts_with_all = ["reorder", "dejitter", "fec_decode", "mark_gaps", "split_by"],
+        help = "Convert packets before they are written: vita49:rawN cuts the samples of VITA-49 data packets into records of N bytes"
+    )]
+    pub convert: Option<Conversion>,
+
+    #[arg(
+        long = "patch",
+        value_name = "OFFSET:HEX[:every=N]",
+        value_parser = patch::parse_patch,
+        help = "Overwrite the bytes at OFFSET of each packet sent, or of every Nth, e.g. 2:0000 or 0:80:every=100; repeat for several"
+    )]
+    pub patch: Vec<Patch>,
+
+    #[arg(
+        long = "patch-extend",
+        requires = "patch",
+        help = "Grow packets a --patch runs past the end of, with zeros in any gap, instead of sending them unpatched"
+    )]
+    pub patch_extend: bool,
+
+    #[arg(
+        long = "strip",
+        value_name = "N",
+        help = "Take the first N bytes off each packet before anything else sees it, dropping packets shorter than that"
+    )]
+    pub strip: Option<usize>,
+
+    #[arg(
+        long = "prepend",
+        value_name = "HEX",
+        value_parser = patch::parse_hex,
+        help = "Put these bytes in front of each packet sent, e.g. a header --strip takes off again"
+    )]
+    // Spelled out so clap takes one value of bytes rather than a list
+    pub prepend: Option<std::vec::Vec<u8>>,
+
+    #[arg(
+        long = "loop-guard",
+        value_name = "HEX",
+        value_parser = patch::parse_hex,
+        help = "Put these bytes after each packet sent, and drop packets received that end in them as our own come back"
+    )]
+    pub loop_guard: Option<std::vec::Vec<u8>>,
+
+    #[arg(
+        long = "allow-loop",
+        help = "Relay into the group being received, on the same interface, even though every packet comes back"
+    )]
+    pub allow_loop: bool,
+
+    #[arg(
+        short = 'v',
+        long = "verbose",
+        help = "Hex dump the first UDP payload, or as many as --dump says; only -c limits the run"
+    )]
+    pub verbose: bool,
+
+    #[arg(
+        long = "dump",
+        value_name = "N",
+        requires = "verbose",
+        help = "Packets -v hex dumps, 0 for all of them [default: 1, or all of them with -c]"
+    )]
+    pub dump: Option<u64>,
+
+    #[arg(
+        long = "legacy-verbose",
+        requires = "verbose",
+        help = "Let -v without -c stop after one packet, as it used to; deprecated, and gone in the next release"
+    )]
+    pub legacy_verbose: bool,
+
+    #[arg(
+        long = "dump-output",
+        value_name = "FILE",
+        requires = "verbose",
+        help = "Write -v hex dumps to a file instead of stdout, or stderr with -o -"
+    )]
+    pub dump_output: Option<String>,
+
+    #[arg(
+        long = "dump-diff",
+        requires = "verbose",
+        help = "Mark the bytes that changed since the previous packet in each -v hex dump, and list their offsets"
+    )]
+    pub dump_diff: bool,
+
+    #[arg(short = 'd', long = "debug", help = "Enable debug logging")]
+    pub debug: bool,
+
+    #[arg(
+        long = "log",
+        value_name = "TARGET",
+        default_value = "stderr",
+        value_parser = parse_log_target,
+        help = "Send log and stats lines to stderr, stdout, or file:PATH"
+    )]
+    pub log: LogTarget,
+
+    #[arg(
+        long = "fast-channel",
+        help = "Use a lock-free SPSC ring between threads instead of a channel"
+    )]
+    pub fast_channel: bool,
+
+    #[arg(
+        long,
+        help = "Allocate full size receive buffers up front instead of growing them with the traffic"
+    )]
+    pub preallocate: bool,
+
+    #[arg(
+        long = "cpu",
+        value_parser = sched::parse_cpu_assignment,
+        help = "Pin threads to cores, e.g. reader=2,writer=3,stats=4"
+    )]
+    pub cpu: Option<CpuAssignment>,
+
+    #[arg(
+        long = "rt-priority",
+        value_parser = clap::value_parser!(u8).range(1..=99),
+        help = "Run threads with SCHED_FIFO at this priority (1-99) where permitted"
+    )]
+    pub rt_priority: Option<u8>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["split_by", "exec", "exec_per_packet", "diagnose", "ping", "echo"],
+        help = "Once sockets and files are open, allow only the system calls moving packets needs and no file access; anything else kills mnc with SIGSYS"
+    )]
+    pub sandbox: bool,
+
+    #[arg(
+        long = "max-restarts",
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Reopen the group's sockets after a socket error, up to N times an hour, rather than ending the run"
+    )]
+    pub max_restarts: Option<u32>,
+
+    #[arg(
+        long = "max-packet",
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u32).range(1..=MAX_PACKET_BYTES as i64),
+        help = "Receive at most BYTES of each datagram, cutting longer ones short [default: 65536]"
+    )]
+    pub max_packet: Option<u32>,
+
+    #[arg(
+        long = "drop-truncated",
+        help = "Leave out datagrams longer than the receive buffer rather than keeping them cut short"
+    )]
+    pub drop_truncated: bool,
+
+    #[arg(
+        long = "control",
+        value_name = "SOCKET",
+        help = "Take commands while running on a unix datagram socket at SOCKET: rate N|off, pause, resume, filters on|off, stats"
+    )]
+    pub control: Option<String>,
+
+    #[arg(
+        long = "ctl",
+        num_args = 2,
+        value_names = ["SOCKET", "COMMAND"],
+        conflicts_with = "mgroup",
+        help = "Send COMMAND to the mnc running with --control SOCKET, print its answer and exit"
+    )]
+    pub ctl: Option<Vec<String>>,
+
+    #[arg(
+        long = "timestamps",
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "iso",
+        requires = "text_sink",
+        help = "Prefix text output with each packet's arrival time"
+    )]
+    pub timestamps: Option<TimestampFormat>,
+
+    #[arg(
+        long = "no-append-newline",
+        requires = "text_sink",
+        help = "Write text output packets as they are, without adding a newline to ones that lack it"
+    )]
+    pub no_append_newline: bool,
+
+    #[arg(
+        long = "label",
+        value_name = "LABEL",
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Start each line of text output and each stats line with [LABEL], or with each packet's group:port when given no value"
+    )]
+    pub label: Option<Option<String>>,
+
+    #[arg(
+        long = "output-format",
+        value_name = "FORMAT",
+        requires = "text_sink",
+        help = "Lay out -o and --exec output as text lines, length-prefixed binary, raw payloads back to back, JSON lines, or a SigMF recording, whatever the -o file extensions say"
+    )]
+    pub output_format: Option<OutputFormat>,
+
+    #[arg(
+        long = "input-format",
+        value_name = "FORMAT",
+        requires = "framed_input",
+        help = "Read -i and --expect as text lines, length-prefixed binary, raw records of --chunk bytes, or JSON lines, whatever the -i file extension says"
+    )]
+    pub input_format: Option<InputFormat>,
+
+    #[arg(
+        long = "chunk",
+        value_name = "BYTES",
+        help = "Record size for --input-format raw"
+    )]
+    pub chunk: Option<usize>,
+
+    #[arg(
+        long = "max-line-length",
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u32).range(1..=MAX_PACKET_BYTES as i64),
+        requires = "framed_input",
+        help = "Longest line text input may have, not counting the newline [default: 65536]"
+    )]
+    pub max_line_length: Option<u32>,
+
+    #[arg(
+        long = "split-long-lines",
+        requires = "framed_input",
+        help = "Send text input lines longer than --max-line-length as several packets instead of failing"
+    )]
+    pub split_long_lines: bool,
+
+    #[arg(
+        long = "lines-per-packet",
+        value_name = "N",
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "input",
+        help = "Bundle up to N lines of text input into each packet, as many as fit in 1472 bytes"
+    )]
+    pub lines_per_packet: Option<u32>,
+
+    #[arg(
+        long = "sigmf-datatype",
+        value_name = "DATATYPE",
+        value_parser = parse_sigmf_datatype,
+        help = "SigMF datatype like ci16_be, for VITA-49 streams that send no payload format"
+    )]
+    pub sigmf_datatype: Option<String>,
+
+    #[arg(
+        long = "split-by",
+        value_name = "KEY",
+        help = "Write each -o file as one file per sender or per VITA-49 stream id, named from it with {ip}, {port} and {stream} or with the key added before the extension"
+    )]
+    pub split_by: Option<SplitBy>,
+
+    #[arg(
+        long = "mark-gaps",
+        help = "Where -t sdds or vita49 sequence numbers skip, write a marker record to binary output, or zero samples to SigMF"
+    )]
+    pub mark_gaps: bool,
+
+    #[arg(
+        long = "strict-disk",
+        help = "Fail when an output file's disk fills up, instead of dropping packets until there is room again"
+    )]
+    pub strict_disk: bool,
+
+    #[arg(
+        long = "exec",
+        value_name = "CMD",
+        conflicts_with = "output",
+        help = "Stream packets to the stdin of a shell command, restarting it if it exits"
+    )]
+    pub exec: Option<String>,
+
+    #[arg(
+        long = "exec-per-packet",
+        value_name = "CMD",
+        conflicts_with_all = ["output", "exec"],
+        help = "Run a shell command for each packet with the payload on stdin"
+    )]
+    pub exec_per_packet: Option<String>,
+
+    #[arg(
+        long = "config",
+        value_name = "FILE",
+        help = "Read settings from a TOML file, flags on the command line take precedence"
+    )]
+    pub config: Option<String>,
+
+    #[arg(
+        long = "profile",
+        requires = "config",
+        help = "Apply the [profiles.<name>] table from the config file"
+    )]
+    pub profile: Option<String>,
+
+    #[arg(
+        long = "drain-timeout",
+        value_name = "SECS",
+        default_value = "1",
+        help = "Seconds to let queued packets drain once exit is signaled, 0 waits forever"
+    )]
+    pub drain_timeout: u64,
+
+    #[arg(
+        long = "duration",
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Stop after this many seconds"
+    )]
+    pub duration: Option<u64>,
+
+    #[arg(
+        long = "idle-timeout",
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Give up (exit 5) when no packets arrive for this many seconds"
+    )]
+    pub idle_timeout: Option<u64>,
+
+    #[arg(
+        long = "wait-first",
+        help = "Start the --duration and --idle-timeout clocks at the first packet rather than at startup"
+    )]
+    pub wait_first: bool,
+
+    #[arg(
+        long = "start-timeout",
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Give up (exit 5) when the first packet hasn't arrived after this many seconds"
+    )]
+    pub start_timeout: Option<u64>,
+
+    #[arg(
+        long = "fail-on-gap",
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Exit 6 as soon as more than N sequence numbers were skipped in all [default: 0]"
+    )]
+    pub fail_on_gap: Option<Option<u64>>,
+
+    #[arg(
+        long = "expect",
+        value_name = "FILE",
+        help = "Compare each packet with the next record of a file framed like -i, exiting 7 at the first difference or when packets are extra or missing"
+    )]
+    pub expect: Option<String>,
+
+    #[arg(
+        long = "expect-resync",
+        requires = "expect",
+        help = "Let --expect skip the records SDDS or VITA-49 sequence numbers show were lost, counting them as missing"
+    )]
+    pub expect_resync: bool,
+
+    #[arg(
+        long = "heartbeat",
+        value_name = "INTERVAL[:PAYLOAD|@FILE]",
+        value_parser = heartbeat::parse_heartbeat,
+        help = "Also send a packet to the group every interval like 1s or 500ms, on its own without -i. PAYLOAD may use strftime % specifiers and {seq}; @FILE is sent as is"
+    )]
+    pub heartbeat: Option<HeartbeatConfig>,
+
+    #[arg(
+        long,
+        help = "If nothing arrives within a few seconds, check rp_filter, the group membership, the interface counters and the wire, and report what looks wrong"
+    )]
+    pub diagnose: bool,
+
+    #[arg(
+        long = "ping",
+        conflicts_with_all = ["echo", "input", "output", "exec", "exec_per_packet", "tx", "rx", "local"],
+        help = "Send a numbered probe every second and report the replies from --echo, like ping"
+    )]
+    pub ping: bool,
+
+    #[arg(
+        long = "echo",
+        conflicts_with_all = ["input", "output", "exec", "exec_per_packet", "tx", "rx", "local"],
+        help = "Answer --ping probes sent to the group"
+    )]
+    pub echo: bool,
+
+    #[arg(
+        long = "reply-group",
+        value_name = "[eth:]mgroup",
+        value_parser = parse_mgroup,
+        help = "Group --echo replies on and --ping listens to, the group itself by default"
+    )]
+    pub reply_group: Option<(Option<String>, String)>,
+
+    #[arg(
+        long = "dry-run",
+        help = "Check interface, group, socket options and output path, then exit without joining"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long = "print-config-json",
+        conflicts_with_all = ["dry_run", "ping", "echo"],
+        help = "Print what the startup banner shows as JSON and exit, without joining"
+    )]
+    pub print_config_json: bool,
+
+    #[arg(
+        long = "min-free",
+        value_name = "SIZE",
+        value_parser = preflight::parse_size,
+        requires = "dry_run",
+        help = "With --dry-run, require this much free space for --output, e.g. 10G"
+    )]
+    pub min_free: Option<u64>,
+}
+
+/// Where log lines go, chosen with --log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogTarget {
+    Stderr,
+    Stdout,
+    File(String),
+}
+
+pub fn parse_sample_rate(s: &str) -> std::result::Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(format!("Expected a sample rate in Hz, got: {s}")),
+    }
+}
+
+pub fn parse_change_percent(s: &str) -> std::result::Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(percent) if percent.is_finite() && percent >= 0.0 => Ok(percent),
+        _ => Err(format!("Expected a percentage like 10 or 2.5, got: {s}")),
+    }
+}
+
+pub fn parse_sigmf_datatype(s: &str) -> std::result::Result<String, String> {
+    match sigmf::sample_bytes(s) {
+        Some(_) => Ok(s.to_string()),
+        None => Err(format!(
+            "Expected a SigMF datatype like ci16_be or rf32_le, got: {s}"
+        )),
+    }
+}
+
+pub fn parse_log_target(s: &str) -> std::result::Result<LogTarget, String> {
+    match s {
+        "stderr" => Ok(LogTarget::Stderr),
+        "stdout" => Ok(LogTarget::Stdout),
+        _ => match s.strip_prefix("file:") {
+            Some(path) if !path.is_empty() => Ok(LogTarget::File(path.to_string())),
+            _ => Err(format!("Expected stderr, stdout or file:PATH, got: {s}")),
+        },
+    }
+}
+
+/// -p: the port, or when receiving several of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ports(pub Vec<u16>);
+
+impl Ports {
+    /// The port everything but receiving uses
+    pub fn first(&self) -> u16 {
+        self.0.first().copied().unwrap_or_default()
+    }
+
+    pub fn is_many(&self) -> bool {
+        self.0.len() > 1
+    }
+}
+
+impl std::fmt::Display for Ports {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ports: Vec<String> = self.0.iter().map(u16::to_string).collect();
+        write!(f, "{}", ports.join(","))
+    }
+}
+
+/// A group on the command line, with the port and packet type it receives
+/// with when they aren't -p's and -t's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupSpec {
+    pub iface: Option<String>,
+    pub mgroup: String,
+    pub port: Option<u16>,
+    pub packet_type: Option<PacketType>,
+}
+
+impl GroupSpec {
+    pub fn new(iface: Option<String>, mgroup: String) -> Self {
+        Self {
+            iface,
+            mgroup,
+            port: None,
+            packet_type: None,
+        }
+    }
+
+    pub fn ports(&self, ports: &Ports) -> Vec<u16> {
+        match self.port {
+            Some(port) => vec![port],
+            None => ports.0.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for GroupSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(iface) = &self.iface {
+            write!(f, "{iface}:")?;
+        }
+        write!(f, "{}", self.mgroup)?;
+        if let Some(port) = self.port {
+            write!(f, ":{port}")?;
+        }
+        if let Some(packet_type) = self.packet_type {
+            write!(f, "?type={packet_type}")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_ports(s: &str) -> std::result::Result<Ports, String> {
+    multicast::parse_ports(s)
+        .map(Ports)
+        .map_err(|e| format!("Expected a port, list or range of ports ({e}), got: {s}"))
+}
+
+pub fn parse_speed(s: &str) -> std::result::Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed >= 0.0 => Ok(speed),
+        _ => Err(format!("Expected a speed factor of 0 or more, got: {s}")),
+    }
+}
+
+pub fn parse_sdds_epoch(s: &str) -> std::result::Result<SddsEpoch, String> {
+    let n = s
+        .parse::<i64>()
+        .map_err(|_| format!("Expected a year or unix seconds, got: {s}"))?;
+    SddsEpoch::from_number(n)
+}
+
+// Parse [eth:]mgroup into (eth, mgroup). eth may pin one of the interface's
+// addresses as eth0/10.1.2.3. For sending, mgroup may also be a list or range
+// of groups like 239.1.1.1,239.1.1.5 or 239.1.1.1-16.
+pub fn parse_mgroup(s: &str) -> std::result::Result<(Option<String>, String), String> {
+    let mgroup_regex = Regex::new(
+        r"^(?:(?P<iface>[^:]+):)?(?P<mgroup>\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}[\d.,-]*)$",
+    )
+    .map_err(|e| format!("Regex compilation error: {e:?}"))?;
+
+    let caps = mgroup_regex
+        .captures(s)
+        .ok_or_else(|| format!("Expected [eth:]mgroup, got: {s}"))?;
+
+    let iface = caps.name("iface").map(|m| m.as_str().to_string());
+    if let Some(iface) = &iface {
+        multicast::split_iface(iface).map_err(|e| e.to_string())?;
+    }
+
+    let mgroup = caps
+        .name("mgroup")
+        .ok_or_else(|| format!("Not a multicast address: {s}"))?
+        .as_str();
+    if mgroup.contains([',', '-']) {
+        multicast::parse_groups(mgroup).map_err(|e| e.to_string())?;
+    }
+
+    Ok((iface, mgroup.to_string()))
+}
+
+// Parse [eth:]mgroup[:port][?type=T]: a group with its own port and packet
+// type, for receiving several groups that differ in them.
+pub fn parse_group_spec(s: &str) -> std::result::Result<GroupSpec, String> {
+    let (group, query) = match s.split_once('?') {
+        Some((group, query)) => (group, Some(query)),
+        None => (s, None),
+    };
+    let (group, port) = match group.rsplit_once(':') {
+        Some((rest, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+            let port = port
+                .parse::<u16>()
+                .map_err(|_| format!("Expected a port after the group, got: {port}"))?;
+            (rest, Some(port))
+        }
+        _ => (group, None),
+    };
+    let (iface, mgroup) = parse_mgroup(group)?;
+    let mut spec = GroupSpec {
+        port,
+        ..GroupSpec::new(iface, mgroup)
+    };
+    for pair in query.into_iter().flat_map(|query| query.split('&')) {
+        match pair.split_once('=') {
+            Some(("type", value)) => {
+                spec.packet_type =
+                    Some(PacketType::from_str(value, true).map_err(|_| {
+                        format!("Expected a packet type after type=, got: {value}")
+                    })?);
+            }
+            _ => return Err(format!("Expected ?type=T after the group, got: ?{pair}")),
+        }
+    }
+    Ok(spec)
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_group_spec() {
+        let parsed = |s| parse_group_spec(s).expect("parse");
+        assert_eq!(
+            parsed("239.1.1.1"),
+            GroupSpec::new(None, "239.1.1.1".to_string())
+        );
+        let spec = parsed("eth1:239.1.1.1:29495?type=sdds");
+        assert_eq!(spec.iface.as_deref(), Some("eth1"));
+        assert_eq!(spec.mgroup, "239.1.1.1");
+        assert_eq!(spec.port, Some(29495));
+        assert_eq!(spec.packet_type, Some(PacketType::Sdds));
+        assert_eq!(spec.to_string(), "eth1:239.1.1.1:29495?type=sdds");
+
+        let spec = parsed("eth0/10.1.2.3:239.1.1.2?type=vita49");
+        assert_eq!(spec.iface.as_deref(), Some("eth0/10.1.2.3"));
+        assert_eq!(spec.port, None);
+        assert_eq!(spec.packet_type, Some(PacketType::Vita49));
+        assert_eq!(parsed("239.1.1.1-4:5000").mgroup, "239.1.1.1-4");
+
+        for bad in [
+            "239.1.1.1:70000",
+            "239.1.1.1?type=video",
+            "239.1.1.1?port=5000",
+            "239.1.1.1:5000:",
+        ] {
+            assert!(parse_group_spec(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_parse_mgroup_interfaces() {
+        let parsed = |s| parse_mgroup(s).expect("parse");
+        assert_eq!(
+            parsed("eth0.100:239.1.1.1"),
+            (Some("eth0.100".to_string()), "239.1.1.1".to_string())
+        );
+        assert_eq!(
+            parsed("eth0/10.1.2.3:239.1.1.1"),
+            (Some("eth0/10.1.2.3".to_string()), "239.1.1.1".to_string())
+        );
+        assert!(parse_mgroup("eth0/10.1:239.1.1.1").is_err());
+    }
+}
@@ -0,0 +1,1193 @@
+// TOML configuration file for long invocations.
+// Keys mirror the long CLI flags. Precedence, lowest to highest:
+// top level keys, the selected [profiles.<name>] table, flags given on the command line.
+use std::collections::BTreeMap;
+
+use clap::{ArgMatches, ValueEnum, parser::ValueSource};
+use serde::Deserialize;
+
+use crate::{
+    MAX_PACKET_BYTES,
+    cli::{
+        Args, Ports, parse_group_spec, parse_log_target, parse_mgroup, parse_ports,
+        parse_sigmf_datatype,
+    },
+    convert, fec, heartbeat,
+    packet::PacketType,
+    patch, police,
+    reader::InputFormat,
+    sched,
+    sdds::SddsEpoch,
+    sink::{Fanout, OutputFormat, SplitBy, TimestampFormat},
+    template,
+};
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    group: Option<String>,
+    more_groups: Option<Vec<String>>,
+    iface: Option<String>,
+    port: Option<PortSetting>,
+    #[serde(rename = "type")]
+    packet_type: Option<String>,
+    input: Option<String>,
+    #[serde(rename = "loop")]
+    repeat: Option<LoopPasses>,
+    template: Option<String>,
+    output: Option<Outputs>,
+    rx: Option<bool>,
+    tx: Option<Transmit>,
+    local: Option<bool>,
+    statistics: Option<bool>,
+    stats_on_change: Option<ChangeThreshold>,
+    sender_timeout: Option<u64>,
+    resolve: Option<bool>,
+    batch_size: Option<usize>,
+    batch_stats: Option<bool>,
+    max_latency: Option<u64>,
+    stall_threshold: Option<u64>,
+    unique: Option<UniqueLimit>,
+    pool_size: Option<usize>,
+    ttl: Option<u8>,
+    quiet: Option<bool>,
+    count: Option<u64>,
+    count_rx: Option<bool>,
+    count_tx: Option<bool>,
+    rate: Option<u64>,
+    txtime: Option<bool>,
+    pps: Option<u64>,
+    pps_burst: Option<u64>,
+    speed: Option<f64>,
+    stamp: Option<usize>,
+    measure_latency: Option<usize>,
+    stamp_seq: Option<SeqStamp>,
+    check_seq: Option<SeqStamp>,
+    verify_template: Option<String>,
+    #[serde(alias = "sdds-rate")]
+    sample_rate: Option<f64>,
+    sdds_epoch: Option<i64>,
+    timetag_offset: Option<bool>,
+    drop_parity: Option<bool>,
+    only_parity: Option<bool>,
+    filter_stream_id: Option<Vec<u32>>,
+    filter_sdds_mode: Option<u8>,
+    vita49_context_gap: Option<u64>,
+    ping: Option<bool>,
+    echo: Option<bool>,
+    reply_group: Option<String>,
+    fanout: Option<String>,
+    rewrite_dst: Option<bool>,
+    police: Option<String>,
+    reorder: Option<u64>,
+    dejitter: Option<u64>,
+    fec: Option<u8>,
+    fec_decode: Option<u8>,
+    convert: Option<String>,
+    patch: Option<Vec<String>>,
+    patch_extend: Option<bool>,
+    strip: Option<usize>,
+    prepend: Option<String>,
+    loop_guard: Option<String>,
+    allow_loop: Option<bool>,
+    verbose: Option<bool>,
+    dump: Option<u64>,
+    legacy_verbose: Option<bool>,
+    dump_output: Option<String>,
+    dump_diff: Option<bool>,
+    log: Option<String>,
+    debug: Option<bool>,
+    fast_channel: Option<bool>,
+    preallocate: Option<bool>,
+    cpu: Option<String>,
+    rt_priority: Option<u8>,
+    sandbox: Option<bool>,
+    max_restarts: Option<u32>,
+    max_packet: Option<u32>,
+    drop_truncated: Option<bool>,
+    control: Option<String>,
+    timestamps: Option<String>,
+    output_format: Option<String>,
+    input_format: Option<String>,
+    chunk: Option<usize>,
+    max_line_length: Option<u32>,
+    split_long_lines: Option<bool>,
+    lines_per_packet: Option<u32>,
+    no_append_newline: Option<bool>,
+    label: Option<LabelSetting>,
+    sigmf_datatype: Option<String>,
+    split_by: Option<String>,
+    mark_gaps: Option<bool>,
+    strict_disk: Option<bool>,
+    exec: Option<String>,
+    exec_per_packet: Option<String>,
+    drain_timeout: Option<u64>,
+    duration: Option<u64>,
+    idle_timeout: Option<u64>,
+    wait_first: Option<bool>,
+    start_timeout: Option<u64>,
+    fail_on_gap: Option<GapAllowance>,
+    expect: Option<String>,
+    expect_resync: Option<bool>,
+    heartbeat: Option<String>,
+    diagnose: Option<bool>,
+    profiles: Option<BTreeMap<String, Settings>>,
+}
+
+/// `output` takes one path or a list of them.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum Outputs {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<Outputs> for Vec<String> {
+    fn from(outputs: Outputs) -> Self {
+        match outputs {
+            Outputs::One(output) => vec![output],
+            Outputs::Many(outputs) => outputs,
+        }
+    }
+}
+
+/// `port = 5000`, or a list or range to receive on like `port = "5000-5003"`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum PortSetting {
+    One(u16),
+    Many(String),
+}
+
+/// `tx = true` sends to the group, `tx = "[eth:]mgroup"` relays elsewhere.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum Transmit {
+    Enabled(bool),
+    To(String),
+}
+
+/// `stamp_seq = true` prepends the number, `stamp_seq = 12` writes it at an offset.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum SeqStamp {
+    Enabled(bool),
+    At(usize),
+}
+
+impl From<SeqStamp> for Option<Option<usize>> {
+    fn from(stamp: SeqStamp) -> Self {
+        match stamp {
+            SeqStamp::Enabled(enabled) => enabled.then_some(None),
+            SeqStamp::At(offset) => Some(Some(offset)),
+        }
+    }
+}
+
+/// `label = true` labels lines with the group:port, `label = "name"` with name.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum LabelSetting {
+    Enabled(bool),
+    Named(String),
+}
+
+impl From<LabelSetting> for Option<Option<String>> {
+    fn from(label: LabelSetting) -> Self {
+        match label {
+            LabelSetting::Enabled(enabled) => enabled.then_some(None),
+            LabelSetting::Named(label) => Some(Some(label)),
+        }
+    }
+}
+
+/// `fail_on_gap = true` allows no gaps, `fail_on_gap = 10` up to 10 skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum GapAllowance {
+    Enabled(bool),
+    Up(u64),
+}
+
+impl From<GapAllowance> for Option<Option<u64>> {
+    fn from(allowance: GapAllowance) -> Self {
+        match allowance {
+            GapAllowance::Enabled(enabled) => enabled.then_some(None),
+            GapAllowance::Up(skipped) => Some(Some(skipped)),
+        }
+    }
+}
+
+/// `unique = true` for the default exact limit, `unique = 5000` for 5000.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum UniqueLimit {
+    Enabled(bool),
+    Up(u64),
+}
+
+impl From<UniqueLimit> for Option<Option<u64>> {
+    fn from(limit: UniqueLimit) -> Self {
+        match limit {
+            UniqueLimit::Enabled(enabled) => enabled.then_some(None),
+            UniqueLimit::Up(limit) => Some(Some(limit)),
+        }
+    }
+}
+
+/// `loop = true` to read the input over and over, `loop = 3` for three times.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum LoopPasses {
+    Enabled(bool),
+    Passes(u64),
+}
+
+impl From<LoopPasses> for Option<Option<u64>> {
+    fn from(passes: LoopPasses) -> Self {
+        match passes {
+            LoopPasses::Enabled(enabled) => enabled.then_some(None),
+            LoopPasses::Passes(passes) => Some(Some(passes)),
+        }
+    }
+}
+
+/// `stats_on_change = true` for the default threshold, `= 25` for 25%.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum ChangeThreshold {
+    Enabled(bool),
+    Percent(f64),
+}
+
+impl From<ChangeThreshold> for Option<Option<f64>> {
+    fn from(threshold: ChangeThreshold) -> Self {
+        match threshold {
+            ChangeThreshold::Enabled(enabled) => enabled.then_some(None),
+            ChangeThreshold::Percent(percent) => Some(Some(percent)),
+        }
+    }
+}
+
+impl Settings {
+    /// Overlay other on top of self, other's keys win.
+    fn overlay(self, other: Settings) -> Settings {
+        Settings {
+            group: other.group.or(self.group),
+            more_groups: other.more_groups.or(self.more_groups),
+            iface: other.iface.or(self.iface),
+            port: other.port.or(self.port),
+            packet_type: other.packet_type.or(self.packet_type),
+            input: other.input.or(self.input),
+            repeat: other.repeat.or(self.repeat),
+            template: other.template.or(self.template),
+            output: other.output.or(self.output),
+            rx: other.rx.or(self.rx),
+            tx: other.tx.or(self.tx),
+            local: other.local.or(self.local),
+            statistics: other.statistics.or(self.statistics),
+            stats_on_change: other.stats_on_change.or(self.stats_on_change),
+            sender_timeout: other.sender_timeout.or(self.sender_timeout),
+            resolve: other.resolve.or(self.resolve),
+            batch_size: other.batch_size.or(self.batch_size),
+            batch_stats: other.batch_stats.or(self.batch_stats),
+            max_latency: other.max_latency.or(self.max_latency),
+            stall_threshold: other.stall_threshold.or(self.stall_threshold),
+            unique: other.unique.or(self.unique),
+            pool_size: other.pool_size.or(self.pool_size),
+            ttl: other.ttl.or(self.ttl),
+            quiet: other.quiet.or(self.quiet),
+            count: other.count.or(self.count),
+            count_rx: other.count_rx.or(self.count_rx),
+            count_tx: other.count_tx.or(self.count_tx),
+            rate: other.rate.or(self.rate),
+            txtime: other.txtime.or(self.txtime),
+            pps: other.pps.or(self.pps),
+            pps_burst: other.pps_burst.or(self.pps_burst),
+            speed: other.speed.or(self.speed),
+            stamp: other.stamp.or(self.stamp),
+            measure_latency: other.measure_latency.or(self.measure_latency),
+            stamp_seq: other.stamp_seq.or(self.stamp_seq),
+            check_seq: other.check_seq.or(self.check_seq),
+            verify_template: other.verify_template.or(self.verify_template),
+            sample_rate: other.sample_rate.or(self.sample_rate),
+            sdds_epoch: other.sdds_epoch.or(self.sdds_epoch),
+            timetag_offset: other.timetag_offset.or(self.timetag_offset),
+            drop_parity: other.drop_parity.or(self.drop_parity),
+            only_parity: other.only_parity.or(self.only_parity),
+            filter_stream_id: other.filter_stream_id.or(self.filter_stream_id),
+            filter_sdds_mode: other.filter_sdds_mode.or(self.filter_sdds_mode),
+            vita49_context_gap: other.vita49_context_gap.or(self.vita49_context_gap),
+            ping: other.ping.or(self.ping),
+            echo: other.echo.or(self.echo),
+            reply_group: other.reply_group.or(self.reply_group),
+            fanout: other.fanout.or(self.fanout),
+            rewrite_dst: other.rewrite_dst.or(self.rewrite_dst),
+            police: other.police.or(self.police),
+            reorder: other.reorder.or(self.reorder),
+            dejitter: other.dejitter.or(self.dejitter),
+            fec: other.fec.or(self.fec),
+            fec_decode: other.fec_decode.or(self.fec_decode),
+            convert: other.convert.or(self.convert),
+            patch: other.patch.or(self.patch),
+            patch_extend: other.patch_extend.or(self.patch_extend),
+            strip: other.strip.or(self.strip),
+            prepend: other.prepend.or(self.prepend),
+            loop_guard: other.loop_guard.or(self.loop_guard),
+            allow_loop: other.allow_loop.or(self.allow_loop),
+            verbose: other.verbose.or(self.verbose),
+            dump: other.dump.or(self.dump),
+            legacy_verbose: other.legacy_verbose.or(self.legacy_verbose),
+            dump_output: other.dump_output.or(self.dump_output),
+            dump_diff: other.dump_diff.or(self.dump_diff),
+            log: other.log.or(self.log),
+            debug: other.debug.or(self.debug),
+            fast_channel: other.fast_channel.or(self.fast_channel),
+            preallocate: other.preallocate.or(self.preallocate),
+            cpu: other.cpu.or(self.cpu),
+            rt_priority: other.rt_priority.or(self.rt_priority),
+            sandbox: other.sandbox.or(self.sandbox),
+            max_restarts: other.max_restarts.or(self.max_restarts),
+            max_packet: other.max_packet.or(self.max_packet),
+            drop_truncated: other.drop_truncated.or(self.drop_truncated),
+            control: other.control.or(self.control),
+            timestamps: other.timestamps.or(self.timestamps),
+            output_format: other.output_format.or(self.output_format),
+            split_by: other.split_by.or(self.split_by),
+            mark_gaps: other.mark_gaps.or(self.mark_gaps),
+            strict_disk: other.strict_disk.or(self.strict_disk),
+            input_format: other.input_format.or(self.input_format),
+            chunk: other.chunk.or(self.chunk),
+            max_line_length: other.max_line_length.or(self.max_line_length),
+            split_long_lines: other.split_long_lines.or(self.split_long_lines),
+            lines_per_packet: other.lines_per_packet.or(self.lines_per_packet),
+            no_append_newline: other.no_append_newline.or(self.no_append_newline),
+            label: other.label.or(self.label),
+            sigmf_datatype: other.sigmf_datatype.or(self.sigmf_datatype),
+            exec: other.exec.or(self.exec),
+            exec_per_packet: other.exec_per_packet.or(self.exec_per_packet),
+            drain_timeout: other.drain_timeout.or(self.drain_timeout),
+            duration: other.duration.or(self.duration),
+            idle_timeout: other.idle_timeout.or(self.idle_timeout),
+            wait_first: other.wait_first.or(self.wait_first),
+            start_timeout: other.start_timeout.or(self.start_timeout),
+            fail_on_gap: other.fail_on_gap.or(self.fail_on_gap),
+            expect: other.expect.or(self.expect),
+            expect_resync: other.expect_resync.or(self.expect_resync),
+            heartbeat: other.heartbeat.or(self.heartbeat),
+            diagnose: other.diagnose.or(self.diagnose),
+            profiles: None,
+        }
+    }
+}
+
+/// Read a config file and resolve the requested profile.
+pub fn load(path: &str, profile: Option<&str>) -> Result<Settings, String> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| format!("Unable to read {path}: {e}"))?;
+    parse(&contents, profile).map_err(|e| format!("{path}: {e}"))
+}
+
+fn parse(contents: &str, profile: Option<&str>) -> Result<Settings, String> {
+    // toml errors quote the offending line, including unknown keys
+    let mut base: Settings = toml::from_str(contents).map_err(|e| e.to_string())?;
+    let mut profiles = base.profiles.take().unwrap_or_default();
+
+    if let Some((name, _)) = profiles.iter().find(|(_, p)| p.profiles.is_some()) {
+        return Err(format!("profile {name} cannot contain profiles"));
+    }
+
+    match profile {
+        Some(name) => {
+            let selected = profiles.remove(name).ok_or_else(|| {
+                let known: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                format!("no profile {name}, expected one of: {}", known.join(", "))
+            })?;
+            Ok(base.overlay(selected))
+        }
+        None => Ok(base),
+    }
+}
+
+fn from_cli(matches: &ArgMatches, id: &str) -> bool {
+    matches.value_source(id) == Some(ValueSource::CommandLine)
+}
+
+/// Fill in args from settings wherever the flag wasn't given on the command line.
+/// Values go through the same parsers the CLI uses.
+pub fn apply(args: &mut Args, settings: Settings, matches: &ArgMatches) -> Result<(), String> {
+    macro_rules! set {
+        ($key:ident => $field:ident) => {
+            if let Some(value) = settings.$key
+                && !from_cli(matches, stringify!($field))
+            {
+                args.$field = value.into();
+            }
+        };
+        ($key:ident => $field:ident, $parse:expr) => {
+            if let Some(value) = settings.$key
+                && !from_cli(matches, stringify!($field))
+            {
+                args.$field = ($parse)(value.as_str())
+                    .map_err(|e| format!("{}: {e}", stringify!($key).replace('_', "-")))?
+                    .into();
+            }
+        };
+    }
+
+    // The group may carry its own interface, an explicit one on the CLI wins
+    let cli_group = from_cli(matches, "mgroup");
+    set!(group => mgroup, parse_group_spec);
+    if let Some(iface) = settings.iface
+        && let Some(group) = args.mgroup.as_mut()
+        && !(cli_group && group.iface.is_some())
+    {
+        group.iface = Some(iface);
+    }
+    if let Some(groups) = settings.more_groups
+        && !from_cli(matches, "more_groups")
+    {
+        args.more_groups = groups
+            .iter()
+            .map(|s| parse_group_spec(s))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("more-groups: {e}"))?;
+    }
+
+    if let Some(port) = settings.port
+        && !from_cli(matches, "port")
+    {
+        args.port = match port {
+            PortSetting::One(port) => Ports(vec![port]),
+            PortSetting::Many(ports) => parse_ports(&ports).map_err(|e| format!("port: {e}"))?,
+        };
+    }
+    set!(packet_type => packet_type, |s| PacketType::from_str(s, true));
+    set!(input => input);
+    set!(repeat => repeat);
+    set!(template => template, template::parse_template);
+
+    // Direction flags on the CLI replace all of the file's
+    let cli_direction = ["rx", "tx", "local"].iter().any(|id| from_cli(matches, id));
+    if !cli_direction {
+        set!(rx => rx);
+        set!(local => local);
+        match settings.tx {
+            Some(Transmit::Enabled(enabled)) => args.tx = enabled.then_some(None),
+            Some(Transmit::To(group)) => {
+                args.tx = Some(Some(parse_mgroup(&group).map_err(|e| format!("tx: {e}"))?));
+            }
+            None => {}
+        }
+    }
+    set!(statistics => stats);
+    set!(stats_on_change => stats_on_change);
+    if let Some(Some(percent)) = args.stats_on_change
+        && !(percent.is_finite() && percent >= 0.0)
+    {
+        return Err(format!(
+            "stats-on-change: expected a percentage, got {percent}"
+        ));
+    }
+    if settings.sender_timeout == Some(0) {
+        return Err("sender-timeout: 0 is not in 1..".to_string());
+    }
+    set!(sender_timeout => sender_timeout);
+    // --numeric on the command line turns a configured --resolve off
+    if !from_cli(matches, "numeric") {
+        set!(resolve => resolve);
+    }
+    set!(batch_size => batch_size);
+    set!(batch_stats => batch_stats);
+    if settings.max_latency == Some(0) {
+        return Err("max-latency: must be at least 1".to_string());
+    }
+    set!(max_latency => max_latency);
+    if settings.stall_threshold == Some(0) {
+        return Err("stall-threshold: must be at least 1".to_string());
+    }
+    set!(stall_threshold => stall_threshold);
+    if settings.unique == Some(UniqueLimit::Up(0)) {
+        return Err("unique: 0 is not in 1..".to_string());
+    }
+    set!(unique => unique);
+    set!(pool_size => pool_size);
+    set!(ttl => ttl);
+    set!(quiet => quiet);
+    set!(count => count);
+    if settings.count_rx == Some(true) && settings.count_tx == Some(true) {
+        return Err("count-rx and count-tx can't both be set".to_string());
+    }
+    // Either on the CLI replaces the file's
+    if !["count_rx", "count_tx"]
+        .iter()
+        .any(|id| from_cli(matches, id))
+    {
+        set!(count_rx => count_rx);
+        set!(count_tx => count_tx);
+    }
+    set!(rate => rate);
+    set!(txtime => txtime);
+    if settings.pps == Some(0) {
+        return Err("pps: must be at least 1".to_string());
+    }
+    set!(pps => pps);
+    if settings.pps_burst == Some(0) {
+        return Err("pps-burst: must be at least 1".to_string());
+    }
+    set!(pps_burst => pps_burst);
+    if let Some(speed) = settings.speed
+        && !(speed.is_finite() && speed >= 0.0)
+    {
+        return Err(format!("speed: {speed} is not a speed factor of 0 or more"));
+    }
+    set!(speed => speed);
+    set!(stamp => stamp);
+    set!(measure_latency => measure_latency);
+    set!(stamp_seq => stamp_seq);
+    set!(check_seq => check_seq);
+    set!(verify_template => verify_template, template::parse_template);
+    if let Some(rate) = settings.sample_rate
+        && !(rate.is_finite() && rate > 0.0)
+    {
+        return Err(format!("sample-rate: {rate} is not a sample rate in Hz"));
+    }
+    set!(sample_rate => sample_rate);
+    if settings.vita49_context_gap == Some(0) {
+        return Err("vita49-context-gap: must be at least 1".to_string());
+    }
+    set!(vita49_context_gap => vita49_context_gap);
+    if let Some(epoch) = settings.sdds_epoch
+        && !from_cli(matches, "sdds_epoch")
+    {
+        args.sdds_epoch =
+            Some(SddsEpoch::from_number(epoch).map_err(|e| format!("sdds-epoch: {e}"))?);
+    }
+    set!(timetag_offset => timetag_offset);
+    if settings.drop_parity == Some(true) && settings.only_parity == Some(true) {
+        return Err("drop-parity and only-parity can't both be set".to_string());
+    }
+    set!(drop_parity => drop_parity);
+    set!(only_parity => only_parity);
+    set!(filter_stream_id => filter_stream_id);
+    if settings.filter_sdds_mode.is_some_and(|mode| mode > 7) {
+        return Err("filter-sdds-mode: must be 0 to 7".to_string());
+    }
+    set!(filter_sdds_mode => filter_sdds_mode);
+    set!(ping => ping);
+    set!(echo => echo);
+    set!(reply_group => reply_group, parse_mgroup);
+    set!(fanout => fanout, |s| Fanout::from_str(s, true));
+    set!(rewrite_dst => rewrite_dst);
+    set!(police => police, police::parse_police);
+    if settings.reorder == Some(0) {
+        return Err("reorder: must be at least 1".to_string());
+    }
+    set!(reorder => reorder);
+    if settings.dejitter == Some(0) {
+        return Err("dejitter: must be at least 1".to_string());
+    }
+    set!(dejitter => dejitter);
+    if settings
+        .fec
+        .is_some_and(|k| !(1..=fec::MAX_GROUP).contains(&k))
+    {
+        return Err(format!("fec: must be 1 to {}", fec::MAX_GROUP));
+    }
+    set!(fec => fec);
+    if settings
+        .fec_decode
+        .is_some_and(|k| !(1..=fec::MAX_GROUP).contains(&k))
+    {
+        return Err(format!("fec-decode: must be 1 to {}", fec::MAX_GROUP));
+    }
+    set!(fec_decode => fec_decode);
+    set!(convert => convert, convert::parse_conversion);
+    if let Some(patches) = settings.patch
+        && !from_cli(matches, "patch")
+    {
+        args.patch = patches
+            .iter()
+            .map(|s| patch::parse_patch(s))
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("patch: {e}"))?;
+    }
+    set!(patch_extend => patch_extend);
+    set!(strip => strip);
+    set!(prepend => prepend, patch::parse_hex);
+    set!(loop_guard => loop_guard, patch::parse_hex);
+    set!(allow_loop => allow_loop);
+    set!(verbose => verbose);
+    set!(dump => dump);
+    set!(legacy_verbose => legacy_verbose);
+    set!(dump_output => dump_output);
+    set!(dump_diff => dump_diff);
+    set!(log => log, parse_log_target);
+    set!(debug => debug);
+    set!(fast_channel => fast_channel);
+    set!(preallocate => preallocate);
+    set!(cpu => cpu, sched::parse_cpu_assignment);
+    if let Some(priority) = settings.rt_priority
+        && !(1..=99).contains(&priority)
+    {
+        return Err(format!("rt-priority: {priority} is not in 1..=99"));
+    }
+    set!(rt_priority => rt_priority);
+    set!(sandbox => sandbox);
+    if settings.max_restarts == Some(0) {
+        return Err("max-restarts: must be at least 1".to_string());
+    }
+    set!(max_restarts => max_restarts);
+    if let Some(max) = settings.max_packet
+        && !(1..=MAX_PACKET_BYTES as u32).contains(&max)
+    {
+        return Err(format!(
+            "max-packet: {max} is not in 1..={MAX_PACKET_BYTES}"
+        ));
+    }
+    set!(max_packet => max_packet);
+    set!(drop_truncated => drop_truncated);
+    set!(control => control);
+    set!(timestamps => timestamps, |s| TimestampFormat::from_str(s, true));
+    set!(output_format => output_format, |s| OutputFormat::from_str(s, true));
+    set!(split_by => split_by, |s| SplitBy::from_str(s, true));
+    set!(mark_gaps => mark_gaps);
+    set!(strict_disk => strict_disk);
+    set!(input_format => input_format, |s| InputFormat::from_str(s, true));
+    set!(chunk => chunk);
+    if let Some(max) = settings.max_line_length
+        && !(1..=MAX_PACKET_BYTES as u32).contains(&max)
+    {
+        return Err(format!(
+            "max-line-length: {max} is not in 1..={MAX_PACKET_BYTES}"
+        ));
+    }
+    set!(max_line_length => max_line_length);
+    set!(split_long_lines => split_long_lines);
+    if settings.lines_per_packet == Some(0) {
+        return Err("lines-per-packet: must be at least 1".to_string());
+    }
+    set!(lines_per_packet => lines_per_packet);
+    set!(no_append_newline => no_append_newline);
+    set!(label => label);
+    set!(sigmf_datatype => sigmf_datatype, parse_sigmf_datatype);
+    set!(drain_timeout => drain_timeout);
+    if settings.duration == Some(0) {
+        return Err("duration: must be at least 1".to_string());
+    }
+    set!(duration => duration);
+    if settings.idle_timeout == Some(0) {
+        return Err("idle-timeout: must be at least 1".to_string());
+    }
+    set!(idle_timeout => idle_timeout);
+    set!(wait_first => wait_first);
+    if settings.start_timeout == Some(0) {
+        return Err("start-timeout: 0 is not in 1..".to_string());
+    }
+    set!(start_timeout => start_timeout);
+    set!(fail_on_gap => fail_on_gap);
+    set!(expect => expect);
+    set!(expect_resync => expect_resync);
+    if args.expect_resync && args.expect.is_none() {
+        return Err("expect-resync needs expect".to_string());
+    }
+    set!(heartbeat => heartbeat, heartbeat::parse_heartbeat);
+    set!(diagnose => diagnose);
+
+    // Any sink on the CLI replaces all of the file's
+    let cli_sink = ["output", "exec", "exec_per_packet"]
+        .iter()
+        .any(|id| from_cli(matches, id));
+    if !cli_sink {
+        set!(output => output);
+        set!(exec => exec);
+        set!(exec_per_packet => exec_per_packet);
+    }
+    let sinks = [
+        !args.output.is_empty(),
+        args.exec.is_some(),
+        args.exec_per_packet.is_some(),
+    ];
+    if sinks.iter().filter(|&&set| set).count() > 1 {
+        return Err("only one of output, exec and exec-per-packet may be set".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+    use crate::{cli::GroupSpec, convert::Conversion};
+
+    const SITE: &str = r#"
+group = "239.1.1.1"
+port = 5000
+type = "sdds"
+statistics = true
+
+[profiles.sensor-a]
+group = "eth1:239.2.2.2"
+port = 6000
+
+[profiles.sensor-b]
+iface = "eth2"
+output = "./capture.bin"
+"#;
+
+    fn resolve(cli: &[&str], config: &str, profile: Option<&str>) -> Result<Args, String> {
+        let matches = Args::command()
+            .try_get_matches_from(std::iter::once("mnc").chain(cli.iter().copied()))
+            .map_err(|e| e.to_string())?;
+        let mut args = Args::from_arg_matches(&matches).map_err(|e| e.to_string())?;
+        apply(&mut args, parse(config, profile)?, &matches)?;
+        Ok(args)
+    }
+
+    #[test]
+    fn test_file_fills_in_defaults() {
+        let args = resolve(&["--config", "site.toml"], SITE, None).expect("resolve");
+        assert_eq!(
+            args.mgroup,
+            Some(GroupSpec::new(None, "239.1.1.1".to_string()))
+        );
+        assert_eq!(args.port, Ports(vec![5000]));
+        assert_eq!(args.packet_type, PacketType::Sdds);
+        assert!(args.stats);
+        // Untouched keys keep the CLI defaults
+        assert_eq!(args.batch_size, 100);
+    }
+
+    #[test]
+    fn test_cli_overrides_file() {
+        let args = resolve(
+            &[
+                "239.9.9.9",
+                "-p",
+                "7000",
+                "-t",
+                "text",
+                "--config",
+                "site.toml",
+            ],
+            SITE,
+            None,
+        )
+        .expect("resolve");
+        assert_eq!(
+            args.mgroup,
+            Some(GroupSpec::new(None, "239.9.9.9".to_string()))
+        );
+        assert_eq!(args.port, Ports(vec![7000]));
+        assert_eq!(args.packet_type, PacketType::Text);
+        assert!(args.stats);
+    }
+
+    #[test]
+    fn test_cli_default_value_does_not_override() {
+        // -p not given, so the clap default must not shadow the file
+        let args = resolve(&["239.9.9.9", "--config", "site.toml"], SITE, None).expect("resolve");
+        assert_eq!(args.port, Ports(vec![5000]));
+    }
+
+    #[test]
+    fn test_profile_precedence() {
+        let args = resolve(&["--config", "site.toml"], SITE, Some("sensor-a")).expect("resolve");
+        assert_eq!(
+            args.mgroup,
+            Some(GroupSpec::new(
+                Some("eth1".to_string()),
+                "239.2.2.2".to_string()
+            ))
+        );
+        assert_eq!(args.port, Ports(vec![6000]));
+        assert_eq!(args.packet_type, PacketType::Sdds);
+
+        let args = resolve(
+            &["--config", "site.toml", "-p", "1"],
+            SITE,
+            Some("sensor-a"),
+        )
+        .expect("resolve");
+        assert_eq!(args.port, Ports(vec![1]));
+    }
+
+    #[test]
+    fn test_profile_iface() {
+        let args = resolve(&["--config", "site.toml"], SITE, Some("sensor-b")).expect("resolve");
+        assert_eq!(
+            args.mgroup,
+            Some(GroupSpec::new(
+                Some("eth2".to_string()),
+                "239.1.1.1".to_string()
+            ))
+        );
+        assert_eq!(args.output, vec!["./capture.bin".to_string()]);
+
+        // An interface spelled out on the CLI wins over the file
+        let args = resolve(
+            &["eth0:239.1.1.1", "--config", "site.toml"],
+            SITE,
+            Some("sensor-b"),
+        )
+        .expect("resolve");
+        assert_eq!(
+            args.mgroup,
+            Some(GroupSpec::new(
+                Some("eth0".to_string()),
+                "239.1.1.1".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_more_groups() {
+        let config = r#"
+group = "eth1:239.1.1.1:29495?type=sdds"
+more-groups = ["eth1:239.1.1.2:5000?type=vita49", "239.1.1.3"]
+"#;
+        let args = resolve(&["--config", "site.toml"], config, None).expect("resolve");
+        let spec = args.mgroup.expect("group");
+        assert_eq!(spec.port, Some(29495));
+        assert_eq!(spec.packet_type, Some(PacketType::Sdds));
+        assert_eq!(
+            args.more_groups
+                .iter()
+                .map(GroupSpec::to_string)
+                .collect::<Vec<_>>(),
+            ["eth1:239.1.1.2:5000?type=vita49", "239.1.1.3"]
+        );
+
+        // Groups on the command line replace the file's
+        let args = resolve(
+            &["239.1.1.1", "239.2.2.2", "--config", "site.toml"],
+            config,
+            None,
+        )
+        .expect("resolve");
+        assert_eq!(
+            args.more_groups,
+            [GroupSpec::new(None, "239.2.2.2".to_string())]
+        );
+        assert!(
+            resolve(
+                &["--config", "site.toml"],
+                "more-groups = [\"239.1.1.1?port=1\"]",
+                None
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_cli_sink_replaces_file_sink() {
+        let args = resolve(
+            &["--config", "site.toml", "--exec", "cat"],
+            SITE,
+            Some("sensor-b"),
+        )
+        .expect("resolve");
+        assert!(args.output.is_empty());
+        assert_eq!(args.exec.as_deref(), Some("cat"));
+    }
+
+    #[test]
+    fn test_output_list() {
+        let args = resolve(
+            &["--config", "x"],
+            "group = \"239.1.1.1\"\noutput = [\"./capture.bin\", \"-\"]",
+            None,
+        )
+        .expect("resolve");
+        assert_eq!(
+            args.output,
+            vec!["./capture.bin".to_string(), "-".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_direction() {
+        let args =
+            resolve(&["--config", "x"], "input = \"a.bin\"\ntx = true", None).expect("resolve");
+        assert_eq!(args.tx, Some(None));
+
+        let args = resolve(&["--config", "x"], "tx = \"eth1:239.2.2.2\"", None).expect("resolve");
+        assert_eq!(
+            args.tx,
+            Some(Some((Some("eth1".to_string()), "239.2.2.2".to_string())))
+        );
+
+        // --local on the CLI replaces the file's tx
+        let args = resolve(&["--config", "x", "--local"], "tx = true", None).expect("resolve");
+        assert_eq!(args.tx, None);
+        assert!(args.local);
+    }
+
+    #[test]
+    fn test_count_direction() {
+        // --count-rx on the CLI replaces the file's count-tx
+        let args =
+            resolve(&["--config", "x", "--count-rx"], "count-tx = true", None).expect("resolve");
+        assert!(args.count_rx);
+        assert!(!args.count_tx);
+
+        assert!(resolve(&["--config", "x"], "count-rx = true\ncount-tx = true", None).is_err());
+    }
+
+    #[test]
+    fn test_unknown_key_names_line() {
+        let error = parse("group = \"239.1.1.1\"\nprot = 5000\n", None).expect_err("unknown key");
+        assert!(error.contains("line 2"), "{error}");
+        assert!(error.contains("prot"), "{error}");
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let error = parse(SITE, Some("sensor-c")).expect_err("unknown profile");
+        assert!(error.contains("sensor-a, sensor-b"), "{error}");
+    }
+
+    #[test]
+    fn test_fail_on_gap() {
+        let args = resolve(&["--config", "x"], "fail-on-gap = true", None).expect("resolve");
+        assert_eq!(args.fail_on_gap, Some(None));
+        let args = resolve(&["--config", "x"], "fail-on-gap = 10", None).expect("resolve");
+        assert_eq!(args.fail_on_gap, Some(Some(10)));
+        let args = resolve(&["--config", "x"], "fail-on-gap = false", None).expect("resolve");
+        assert_eq!(args.fail_on_gap, None);
+    }
+
+    #[test]
+    fn test_stats_on_change() {
+        let args = resolve(&["--config", "x"], "stats-on-change = true", None).expect("resolve");
+        assert_eq!(args.stats_on_change, Some(None));
+        let args = resolve(&["--config", "x"], "stats-on-change = 25", None).expect("resolve");
+        assert_eq!(args.stats_on_change, Some(Some(25.0)));
+        let args = resolve(&["--config", "x"], "stats-on-change = 2.5", None).expect("resolve");
+        assert_eq!(args.stats_on_change, Some(Some(2.5)));
+        assert!(resolve(&["--config", "x"], "stats-on-change = -1", None).is_err());
+    }
+
+    #[test]
+    fn test_max_latency() {
+        let args = resolve(&["--config", "x"], "max-latency = 20", None).expect("resolve");
+        assert_eq!(args.max_latency, Some(20));
+        assert!(resolve(&["--config", "x"], "max-latency = 0", None).is_err());
+    }
+
+    #[test]
+    fn test_duration_and_idle_timeout() {
+        let args =
+            resolve(&["--config", "x"], "duration = 60\nidle-timeout = 5", None).expect("resolve");
+        assert_eq!((args.duration, args.idle_timeout), (Some(60), Some(5)));
+        assert!(resolve(&["--config", "x"], "duration = 0", None).is_err());
+        assert!(resolve(&["--config", "x"], "idle-timeout = 0", None).is_err());
+    }
+
+    #[test]
+    fn test_pps() {
+        let args = resolve(&["--config", "x"], "pps = 1000\ntxtime = true", None).expect("resolve");
+        assert_eq!((args.pps, args.txtime), (Some(1000), true));
+        assert!(resolve(&["--config", "x"], "pps = 0", None).is_err());
+    }
+
+    #[test]
+    fn test_pps_burst() {
+        let args =
+            resolve(&["--config", "x"], "pps = 1000\npps-burst = 20", None).expect("resolve");
+        assert_eq!((args.pps, args.pps_burst), (Some(1000), Some(20)));
+        assert!(resolve(&["--config", "x"], "pps-burst = 0", None).is_err());
+    }
+
+    #[test]
+    fn test_loop() {
+        let args =
+            resolve(&["--config", "x"], "input = \"a.bin\"\nloop = true", None).expect("resolve");
+        assert_eq!(args.repeat, Some(None));
+        let args = resolve(&["--config", "x"], "loop = 3", None).expect("resolve");
+        assert_eq!(args.repeat, Some(Some(3)));
+        let args = resolve(&["--config", "x", "--loop=2"], "loop = 3", None).expect("resolve");
+        assert_eq!(args.repeat, Some(Some(2)));
+    }
+
+    #[test]
+    fn test_unique() {
+        let args = resolve(&["--config", "x"], "unique = true", None).expect("resolve");
+        assert_eq!(args.unique, Some(None));
+        let args = resolve(&["--config", "x"], "unique = 5000", None).expect("resolve");
+        assert_eq!(args.unique, Some(Some(5000)));
+        assert!(resolve(&["--config", "x"], "unique = 0", None).is_err());
+    }
+
+    #[test]
+    fn test_sender_timeout() {
+        let args = resolve(&["--config", "x"], "sender-timeout = 30", None).expect("resolve");
+        assert_eq!(args.sender_timeout, 30);
+        let args = resolve(
+            &["--config", "x", "--sender-timeout", "5"],
+            "sender-timeout = 30",
+            None,
+        )
+        .expect("resolve");
+        assert_eq!(args.sender_timeout, 5);
+        assert!(resolve(&["--config", "x"], "sender-timeout = 0", None).is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let args = resolve(&["--config", "x"], "resolve = true", None).expect("resolve");
+        assert!(args.resolve);
+        let args =
+            resolve(&["--config", "x", "--numeric"], "resolve = true", None).expect("resolve");
+        assert!(!args.resolve);
+    }
+
+    #[test]
+    fn test_max_packet() {
+        let args = resolve(
+            &["--config", "x"],
+            "max-packet = 1500\ndrop-truncated = true",
+            None,
+        )
+        .expect("resolve");
+        assert_eq!(args.max_packet, Some(1500));
+        assert!(args.drop_truncated);
+        assert!(resolve(&["--config", "x"], "max-packet = 0", None).is_err());
+        assert!(resolve(&["--config", "x"], "max-packet = 70000", None).is_err());
+    }
+
+    #[test]
+    fn test_convert() {
+        let args =
+            resolve(&["--config", "x"], "convert = \"vita49:raw1024\"", None).expect("resolve");
+        assert_eq!(args.convert, Some(Conversion::Vita49Raw(1024)));
+        assert!(resolve(&["--config", "x"], "convert = \"vita49:raw\"", None).is_err());
+    }
+
+    #[test]
+    fn test_mark_gaps() {
+        let args = resolve(&["--config", "x"], "mark-gaps = true", None).expect("resolve");
+        assert!(args.mark_gaps);
+    }
+
+    #[test]
+    fn test_dump() {
+        let args = resolve(
+            &["--config", "x"],
+            "verbose = true\ndump = 5\nlegacy-verbose = true",
+            None,
+        )
+        .expect("resolve");
+        assert!(args.verbose && args.legacy_verbose);
+        assert_eq!(args.dump, Some(5));
+    }
+
+    #[test]
+    fn test_max_restarts() {
+        let args = resolve(&["--config", "x"], "max-restarts = 5", None).expect("resolve");
+        assert_eq!(args.max_restarts, Some(5));
+        assert!(resolve(&["--config", "x"], "max-restarts = 0", None).is_err());
+    }
+
+    #[test]
+    fn test_expect() {
+        let config = "expect = \"./golden.bin\"\nexpect-resync = true";
+        let args = resolve(&["--config", "x"], config, None).expect("resolve");
+        assert_eq!(args.expect.as_deref(), Some("./golden.bin"));
+        assert!(args.expect_resync);
+        let args =
+            resolve(&["--config", "x", "--expect", "./other.bin"], config, None).expect("resolve");
+        assert_eq!(args.expect.as_deref(), Some("./other.bin"));
+        assert!(resolve(&["--config", "x"], "expect-resync = true", None).is_err());
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let args =
+            resolve(&["--config", "x"], "heartbeat = \"500ms:alive\"", None).expect("resolve");
+        let heartbeat = args.heartbeat.expect("heartbeat");
+        assert_eq!(heartbeat.interval, std::time::Duration::from_millis(500));
+        assert!(resolve(&["--config", "x"], "heartbeat = \"never\"", None).is_err());
+    }
+
+    #[test]
+    fn test_template() {
+        let config = "template = \"load {seq}\"\nverify-template = \"load {seq}\"";
+        let args = resolve(&["--config", "x"], config, None).expect("resolve");
+        assert!(args.template.is_some_and(|template| template.has_seq()));
+        assert!(args.verify_template.is_some());
+        assert!(resolve(&["--config", "x"], "template = \"{seq\"", None).is_err());
+    }
+
+    #[test]
+    fn test_label() {
+        let args = resolve(&["--config", "x"], "label = true", None).expect("resolve");
+        assert_eq!(args.label, Some(None));
+        let args = resolve(&["--config", "x"], "label = \"east\"", None).expect("resolve");
+        assert_eq!(args.label, Some(Some("east".to_string())));
+        let args =
+            resolve(&["--config", "x", "--label=west"], "label = \"east\"", None).expect("resolve");
+        assert_eq!(args.label, Some(Some("west".to_string())));
+    }
+
+    #[test]
+    fn test_patch_list() {
+        let args = resolve(
+            &["--config", "x"],
+            "patch = [\"2:0000\", \"0:80:every=10\"]",
+            None,
+        )
+        .expect("resolve");
+        assert_eq!(args.patch.len(), 2);
+        assert!(resolve(&["--config", "x"], "patch = [\"2:0\"]", None).is_err());
+    }
+
+    #[test]
+    fn test_strip_and_prepend() {
+        let args = resolve(
+            &["--config", "x"],
+            "strip = 8\nprepend = \"0xdeadbeef\"",
+            None,
+        )
+        .expect("resolve");
+        assert_eq!(args.strip, Some(8));
+        assert_eq!(args.prepend, Some(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert!(resolve(&["--config", "x"], "prepend = \"abc\"", None).is_err());
+    }
+
+    #[test]
+    fn test_loop_guard() {
+        let args = resolve(
+            &["--config", "x"],
+            "loop-guard = \"feed\"\nallow-loop = true",
+            None,
+        )
+        .expect("resolve");
+        assert_eq!(args.loop_guard, Some(vec![0xfe, 0xed]));
+        assert!(args.allow_loop);
+    }
+
+    #[test]
+    fn test_stream_id_list() {
+        let args =
+            resolve(&["--config", "x"], "filter-stream-id = [0x2a, 7]", None).expect("resolve");
+        assert_eq!(args.filter_stream_id, [42, 7]);
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        assert!(resolve(&["--config", "x"], "type = \"bogus\"", None).is_err());
+        assert!(resolve(&["--config", "x"], "group = \"nope\"", None).is_err());
+        assert!(resolve(&["--config", "x"], "rt-priority = 0", None).is_err());
+        assert!(resolve(&["--config", "x"], "dejitter = 0", None).is_err());
+        assert!(resolve(&["--config", "x"], "stall-threshold = 0", None).is_err());
+        assert!(resolve(&["--config", "x"], "fec = 0", None).is_err());
+        assert!(resolve(&["--config", "x"], "fec-decode = 128", None).is_err());
+        assert!(resolve(&["--config", "x"], "filter-sdds-mode = 8", None).is_err());
+        assert!(resolve(&["--config", "x"], "max-line-length = 0", None).is_err());
+        assert!(resolve(&["--config", "x"], "output = \"a\"\nexec = \"b\"", None).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+/// Steering a run while it goes, for --control: a unix datagram socket
+/// taking one text command per datagram and answering each to the sender's
+/// address, so `mnc --ctl` or socat can script it.
+///
+///   rate N | rate off   pace the writer to N packets a second, or not at all
+///   pause | resume      hold the writer, leaving queued packets queued
+///   filters on | off    apply the --filter-* and --drop-parity filters or not
+///   stats               the counts so far
+///
+/// The writer and reader read the settings in [`Control`] as they go; this
+/// thread only changes them.
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{
+    SharedState,
+    error::{LibError, Result},
+};
+
+// How often the control thread looks for exit between commands.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long `mnc --ctl` waits for the answer.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Longest command or answer.
+const MAX_MESSAGE: usize = 1024;
+
+const HELP: &str = "commands: rate N|off, pause, resume, filters on|off, stats";
+
+/// The settings --control changes, read by the pipeline as it runs.
+#[derive(Debug, Default)]
+pub struct Control {
+    paused: AtomicBool,
+    pauses: Mutex<Pauses>,
+    /// Packets a second, 0 for no limit
+    rate: AtomicU64,
+    filters_off: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct Pauses {
+    since: Option<Instant>,
+    total: Duration,
+}
+
+impl Control {
+    /// Hold the writer. False if it already was.
+    pub fn pause(&self) -> bool {
+        let Ok(mut pauses) = self.pauses.lock() else {
+            return false;
+        };
+        if pauses.since.is_some() {
+            return false;
+        }
+        pauses.since = Some(Instant::now());
+        self.paused.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Let the writer go again, returning how long it was held.
+    pub fn resume(&self) -> Option<Duration> {
+        let mut pauses = self.pauses.lock().ok()?;
+        let paused = pauses.since.take()?.elapsed();
+        pauses.total += paused;
+        self.paused.store(false, Ordering::Relaxed);
+        Some(paused)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Time spent paused so far, counting a pause still going on.
+    pub fn paused_for(&self) -> Duration {
+        self.pauses
+            .lock()
+            .map(|pauses| {
+                pauses.total + pauses.since.map_or(Duration::ZERO, |since| since.elapsed())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Pace the writer to pps packets a second, None for as fast as it goes.
+    pub fn set_rate(&self, pps: Option<u64>) {
+        self.rate.store(pps.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        match self.rate.load(Ordering::Relaxed) {
+            0 => None,
+            pps => Some(pps),
+        }
+    }
+
+    pub fn set_filters(&self, on: bool) {
+        self.filters_off.store(!on, Ordering::Relaxed);
+    }
+
+    pub fn filters_on(&self) -> bool {
+        !self.filters_off.load(Ordering::Relaxed)
+    }
+}
+
+/// Bind the control socket at path, replacing a socket a previous run left
+/// behind but nothing else.
+pub fn bind(path: &Path) -> Result<UnixDatagram> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(LibError::Critical(format!(
+                "--control {}: exists and isn't a socket",
+                path.display()
+            )));
+        }
+        fs::remove_file(path)?;
+    }
+    let socket = UnixDatagram::bind(path)
+        .map_err(|e| LibError::Critical(format!("--control {}: {e}", path.display())))?;
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    Ok(socket)
+}
+
+/// Spawn the control thread, which answers commands on socket until exit
+/// is signaled and then removes path.
+pub fn spawn(
+    socket: UnixDatagram,
+    path: PathBuf,
+    shared_state: SharedState,
+) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        log::info!("taking commands on {}", path.display());
+        let result = serve(&socket, &shared_state);
+        let _ = fs::remove_file(&path);
+        log::debug!("control exited");
+        result
+    })
+}
+
+fn serve(socket: &UnixDatagram, shared_state: &SharedState) -> Result<()> {
+    let mut buffer = [0u8; MAX_MESSAGE];
+    while !shared_state.should_exit() {
+        let (received, peer) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock
+                        | std::io::ErrorKind::TimedOut
+                        | std::io::ErrorKind::Interrupted
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let command = String::from_utf8_lossy(buffer.get(..received).unwrap_or_default());
+        let reply = handle(command.trim(), shared_state);
+        log::info!("control: {} -> {reply}", command.trim());
+        // A sender without an address of its own can't be answered
+        match peer.as_pathname() {
+            Some(peer) => {
+                if let Err(e) = socket.send_to(reply.as_bytes(), peer) {
+                    log::debug!("control: no answer to {}: {e}", peer.display());
+                }
+            }
+            None => log::debug!("control: sender has no address to answer"),
+        }
+    }
+    Ok(())
+}
+
+/// Carry out one command, returning the answer. Ones that fail start
+/// with "error:".
+fn handle(command: &str, shared_state: &SharedState) -> String {
+    let control = &shared_state.control;
+    let words: Vec<&str> = command.split_whitespace().collect();
+    match words.as_slice() {
+        ["rate", "off"] | ["rate", "0"] => {
+            control.set_rate(None);
+            "rate unlimited".to_string()
+        }
+        ["rate", pps] => match pps.parse::<u64>() {
+            Ok(pps) => {
+                control.set_rate(Some(pps));
+                format!("rate {pps} pkt/s")
+            }
+            Err(_) => format!("error: rate {pps}: not a number of packets a second"),
+        },
+        ["pause"] => {
+            if control.pause() {
+                "paused".to_string()
+            } else {
+                "already paused".to_string()
+            }
+        }
+        ["resume"] => match control.resume() {
+            Some(paused) => format!("resumed after {:.1}s", paused.as_secs_f64()),
+            None => "not paused".to_string(),
+        },
+        ["filters", "on"] => {
+            control.set_filters(true);
+            "filters on".to_string()
+        }
+        ["filters", "off"] => {
+            control.set_filters(false);
+            "filters off".to_string()
+        }
+        ["stats"] => format_stats(shared_state),
+        _ => format!("error: {command:?}: {HELP}"),
+    }
+}
+
+fn format_stats(shared_state: &SharedState) -> String {
+    let control = &shared_state.control;
+    format!(
+        "{} packets read, {} written, {} batches dropped; {}; rate {}; filters {}",
+        shared_state.get_read_count(),
+        shared_state.get_write_count(),
+        shared_state.get_dropped_batches(),
+        if control.is_paused() {
+            "paused"
+        } else {
+            "running"
+        },
+        control
+            .rate()
+            .map_or("unlimited".to_string(), |pps| format!("{pps} pkt/s")),
+        if control.filters_on() { "on" } else { "off" },
+    )
+}
+
+/// Send command to the control socket at path and return the answer, for
+/// `mnc --ctl`. The answer comes back to a socket of our own in the
+/// temporary directory, removed again afterwards.
+pub fn send(path: &Path, command: &str) -> Result<String> {
+    let own = std::env::temp_dir().join(format!("mnc-ctl-{}.sock", std::process::id()));
+    let _ = fs::remove_file(&own);
+    let socket = UnixDatagram::bind(&own)?;
+    let result = exchange(&socket, path, command);
+    let _ = fs::remove_file(&own);
+    result
+}
+
+fn exchange(socket: &UnixDatagram, path: &Path, command: &str) -> Result<String> {
+    socket
+        .send_to(command.as_bytes(), path)
+        .map_err(|e| LibError::Critical(format!("{}: {e}", path.display())))?;
+    socket.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    let mut buffer = [0u8; MAX_MESSAGE];
+    let received = socket
+        .recv(&mut buffer)
+        .map_err(|e| LibError::Critical(format!("{}: no answer: {e}", path.display())))?;
+    Ok(String::from_utf8_lossy(buffer.get(..received).unwrap_or_default()).into_owned())
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+    use crate::PacketType;
+
+    #[test]
+    fn test_pause_and_resume() {
+        let control = Control::default();
+        assert!(!control.is_paused());
+        assert_eq!(control.resume(), None);
+        assert!(control.pause());
+        assert!(!control.pause());
+        assert!(control.is_paused());
+        thread::sleep(Duration::from_millis(20));
+        let paused = control.resume().expect("was paused");
+        assert!(paused >= Duration::from_millis(20));
+        assert!(!control.is_paused());
+        assert_eq!(control.paused_for(), paused);
+    }
+
+    #[test]
+    fn test_commands() {
+        let shared_state = SharedState::new(PacketType::Text, false);
+        let control = &shared_state.control;
+
+        assert_eq!(handle("rate 5000", &shared_state), "rate 5000 pkt/s");
+        assert_eq!(control.rate(), Some(5000));
+        assert_eq!(handle("rate off", &shared_state), "rate unlimited");
+        assert_eq!(control.rate(), None);
+        assert!(handle("rate fast", &shared_state).starts_with("error:"));
+
+        assert_eq!(handle("pause", &shared_state), "paused");
+        assert_eq!(handle("pause", &shared_state), "already paused");
+        assert!(handle("resume", &shared_state).starts_with("resumed after "));
+        assert_eq!(handle("resume", &shared_state), "not paused");
+
+        assert_eq!(handle("filters off", &shared_state), "filters off");
+        assert!(!control.filters_on());
+        handle("filters on", &shared_state);
+        assert!(control.filters_on());
+
+        shared_state.add_read_count(3);
+        assert_eq!(
+            handle("stats", &shared_state),
+            "3 packets read, 0 written, 0 batches dropped; running; rate unlimited; filters on"
+        );
+        assert!(handle("speed 2", &shared_state).starts_with("error: \"speed 2\": commands:"));
+    }
+
+    #[test]
+    fn test_socket_round_trip() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("mnc-control-test-{}.sock", std::process::id()));
+        let shared_state = SharedState::new(PacketType::Text, false);
+        let handle = spawn(
+            bind(&path).expect("bind"),
+            path.clone(),
+            shared_state.clone(),
+        );
+
+        assert_eq!(send(&path, "rate 100").expect("answer"), "rate 100 pkt/s");
+        assert_eq!(shared_state.control.rate(), Some(100));
+
+        shared_state.signal_exit();
+        handle.join().expect("join").expect("served");
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,270 @@
+/// Conversions from one packet layout to another, between the reader and the
+/// outputs (`--convert FROM:TO`). Each is a [`Transform`]: it takes the
+/// reader's batches and gives back the packets they come to, which need not
+/// be one for one, so it may hold bytes over from one batch to the next
+/// until the stream ends.
+///
+/// vita49:rawN takes the payload out of every VITA-49 data packet and cuts
+/// the samples, back to back, into records of N bytes, for tools that only
+/// read fixed size sample records. Context and other packets carry no
+/// samples and are left out.
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    MAX_PACKET_BYTES,
+    packet::{Packet, PacketType, Packets},
+    vita49::{self, VrtKind},
+};
+
+/// What --convert turns packets into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conversion {
+    /// VITA-49 data payloads cut into records of this many bytes
+    Vita49Raw(usize),
+}
+
+impl Conversion {
+    /// The packet type converted from, which -t must say.
+    pub fn input_type(&self) -> PacketType {
+        match self {
+            Conversion::Vita49Raw(_) => PacketType::Vita49,
+        }
+    }
+
+    pub fn transform(&self, counters: Arc<ConvertCounters>) -> Box<dyn Transform> {
+        match *self {
+            Conversion::Vita49Raw(record_bytes) => Box::new(Vita49Raw::new(record_bytes, counters)),
+        }
+    }
+}
+
+impl FromStr for Conversion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once(':')
+            .ok_or_else(|| format!("{s}: expected FROM:TO, like vita49:raw1024"))?;
+        match (from, to.strip_prefix("raw")) {
+            ("vita49", Some(bytes)) => match bytes.parse::<usize>() {
+                Ok(bytes) if (1..=MAX_PACKET_BYTES).contains(&bytes) => {
+                    Ok(Conversion::Vita49Raw(bytes))
+                }
+                _ => Err(format!(
+                    "{s}: the record size after raw must be in 1..={MAX_PACKET_BYTES}"
+                )),
+            },
+            _ => Err(format!(
+                "{s}: the only conversion is vita49:rawN, like vita49:raw1024"
+            )),
+        }
+    }
+}
+
+impl Display for Conversion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conversion::Vita49Raw(bytes) => write!(f, "vita49:raw{bytes}"),
+        }
+    }
+}
+
+/// For clap and the config file.
+pub fn parse_conversion(s: &str) -> Result<Conversion, String> {
+    s.parse()
+}
+
+/// A stage that turns the reader's packets into others for the outputs.
+pub trait Transform: Send + fmt::Debug {
+    /// Convert the batch, appending the packets it completes to out.
+    fn push(&mut self, packets: &Packets, out: &mut Vec<Packet>);
+
+    /// Whatever is still held once the stream ends, appended to out.
+    fn flush(&mut self, out: &mut Vec<Packet>);
+
+    /// Take written packets back as buffers for the next ones.
+    fn reuse(&mut self, written: &mut Vec<Packet>);
+}
+
+/// Packets taken in and records given out, for the summary.
+#[derive(Debug, Default)]
+pub struct ConvertCounters {
+    taken: AtomicU64,
+    skipped: AtomicU64,
+    records: AtomicU64,
+}
+
+/// A snapshot of [`ConvertCounters`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ConvertTotals {
+    /// Every packet the conversion was given
+    pub taken: u64,
+    /// Of those, the ones with nothing to convert
+    pub skipped: u64,
+    /// Packets the conversion made
+    pub records: u64,
+}
+
+impl ConvertCounters {
+    pub fn get(&self) -> ConvertTotals {
+        ConvertTotals {
+            taken: self.taken.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            records: self.records.load(Ordering::Relaxed),
+        }
+    }
+
+    fn add(counter: &AtomicU64, count: u64) {
+        counter.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+/// vita49:rawN. A record takes its arrival time and source from the packet
+/// its first byte came from.
+#[derive(Debug)]
+pub struct Vita49Raw {
+    record_bytes: usize,
+    // The record being filled, None between records
+    filling: Option<Packet>,
+    spare: Vec<Packet>,
+    counters: Arc<ConvertCounters>,
+}
+
+impl Vita49Raw {
+    pub fn new(record_bytes: usize, counters: Arc<ConvertCounters>) -> Self {
+        Self {
+            record_bytes,
+            filling: None,
+            spare: Vec::new(),
+            counters,
+        }
+    }
+
+    fn add_samples(&mut self, from: &Packet, mut samples: &[u8], out: &mut Vec<Packet>) {
+        while !samples.is_empty() {
+            let record = self.filling.get_or_insert_with(|| {
+                let mut record = self
+                    .spare
+                    .pop()
+                    .unwrap_or_else(|| Packet::with_capacity(self.record_bytes));
+                record.set_length(0);
+                record.set_timestamp(from.timestamp());
+                record.set_source(from.source());
+                record.set_destination(from.destination());
+                record
+            });
+            let room = self.record_bytes - record.len();
+            let (now, later) = samples.split_at(room.min(samples.len()));
+            record.append(now);
+            samples = later;
+            if record.len() == self.record_bytes
+                && let Some(record) = self.filling.take()
+            {
+                out.push(record);
+                ConvertCounters::add(&self.counters.records, 1);
+            }
+        }
+    }
+}
+
+impl Transform for Vita49Raw {
+    fn push(&mut self, packets: &Packets, out: &mut Vec<Packet>) {
+        ConvertCounters::add(&self.counters.taken, packets.len() as u64);
+        for packet in packets.iter() {
+            match vita49::parse_vrt(packet) {
+                Some(vrt) if vrt.kind() == VrtKind::Data => {
+                    self.add_samples(packet, vrt.payload, out)
+                }
+                _ => ConvertCounters::add(&self.counters.skipped, 1),
+            }
+        }
+    }
+
+    fn flush(&mut self, out: &mut Vec<Packet>) {
+        if let Some(record) = self.filling.take() {
+            log::info!(
+                "{} bytes left at the end of the stream, written as a short last record",
+                record.len()
+            );
+            out.push(record);
+            ConvertCounters::add(&self.counters.records, 1);
+        }
+    }
+
+    fn reuse(&mut self, written: &mut Vec<Packet>) {
+        self.spare.append(written);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A VRLP frame holding a VITA-49 packet of type packet_type with the
+    // payload given, no timestamps or trailer
+    fn frame(packet_type: u32, payload: &[u8]) -> Vec<u8> {
+        let words = 2 + payload.len().div_ceil(4);
+        let mut frame = b"VRLP".to_vec();
+        frame.extend(((words + 3) as u32).to_be_bytes());
+        frame.extend(((packet_type << 28) | words as u32).to_be_bytes());
+        frame.extend(7u32.to_be_bytes());
+        frame.extend(payload);
+        frame.extend(b"VEND");
+        frame
+    }
+
+    fn batch(frames: &[Vec<u8>]) -> Packets {
+        let mut packets = Packets::new(frames.len(), 0);
+        for (packet, frame) in packets.iter_mut().zip(frames) {
+            packet.set_length(0);
+            packet.append(frame);
+        }
+        packets
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("vita49:raw1024".parse(), Ok(Conversion::Vita49Raw(1024)));
+        assert_eq!(Conversion::Vita49Raw(1024).to_string(), "vita49:raw1024");
+        assert!("vita49:raw0".parse::<Conversion>().is_err());
+        assert!("vita49:raw70000".parse::<Conversion>().is_err());
+        assert!("sdds:raw1024".parse::<Conversion>().is_err());
+        assert!("vita49".parse::<Conversion>().is_err());
+    }
+
+    #[test]
+    fn test_vita49_reblocked() {
+        let counters = Arc::new(ConvertCounters::default());
+        let mut transform = Conversion::Vita49Raw(8).transform(counters.clone());
+        let mut out = Vec::new();
+
+        // 12 bytes of samples, a context packet, then 8 more
+        transform.push(
+            &batch(&[frame(1, &[1; 12]), frame(4, &[9; 8]), frame(1, &[2; 8])]),
+            &mut out,
+        );
+        let records: Vec<Vec<u8>> = out.iter().map(|record| record.to_vec()).collect();
+        assert_eq!(records, [vec![1; 8], vec![1, 1, 1, 1, 2, 2, 2, 2]]);
+        transform.reuse(&mut out);
+        assert!(out.is_empty());
+
+        // The last 4 wait for the next frame, or the end
+        transform.push(&batch(&[b"not a frame".to_vec()]), &mut out);
+        assert!(out.is_empty());
+        transform.flush(&mut out);
+        let records: Vec<Vec<u8>> = out.iter().map(|record| record.to_vec()).collect();
+        assert_eq!(records, [vec![2; 4]]);
+
+        assert_eq!(
+            counters.get(),
+            ConvertTotals {
+                taken: 4,
+                skipped: 2,
+                records: 3
+            }
+        );
+    }
+}
@@ -0,0 +1,202 @@
+/// How busy each pipeline thread is, for the stats line: "is the reader
+/// pegged" is the first question when packets drop. Each thread registers
+/// itself as it starts, and statistics reads the CPU time the kernel keeps
+/// for it in /proc/self/task/TID/stat each interval. Elsewhere there's no
+/// per-thread figure, so the process as a whole is shown instead.
+#[cfg(target_os = "linux")]
+use std::fs::File;
+#[cfg(target_os = "linux")]
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The pipeline threads registered so far.
+#[derive(Debug, Default)]
+pub struct ThreadCpu {
+    threads: Mutex<Vec<Tracked>>,
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct Tracked {
+    name: &'static str,
+    // Opened by the thread itself, before --sandbox locks it down
+    #[cfg(target_os = "linux")]
+    stat: File,
+}
+
+impl ThreadCpu {
+    /// Record the calling thread as name. Without the stat file it's
+    /// simply left off the line.
+    #[cfg(target_os = "linux")]
+    pub fn register(&self, name: &'static str) {
+        let tid = nix::unistd::gettid();
+        let stat = match File::open(format!("/proc/self/task/{tid}/stat")) {
+            Ok(stat) => stat,
+            Err(e) => {
+                log::debug!("{name}: no CPU time from /proc: {e}");
+                return;
+            }
+        };
+        if let Ok(mut threads) = self.threads.lock() {
+            threads.push(Tracked { name, stat });
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn register(&self, _name: &'static str) {}
+
+    /// CPU time used so far by each thread still running, by name.
+    #[cfg(target_os = "linux")]
+    fn sample(&self) -> Vec<(&'static str, Duration)> {
+        let Ok(mut threads) = self.threads.lock() else {
+            return Vec::new();
+        };
+        let mut text = String::new();
+        threads
+            .iter_mut()
+            .filter_map(|thread| {
+                text.clear();
+                // A thread that has exited reads as an error
+                thread.stat.seek(SeekFrom::Start(0)).ok()?;
+                thread.stat.read_to_string(&mut text).ok()?;
+                Some((thread.name, parse_cpu_time(&text, *CLOCK_TICKS)?))
+            })
+            .collect()
+    }
+
+    /// The whole process, under the name "process".
+    #[cfg(not(target_os = "linux"))]
+    fn sample(&self) -> Vec<(&'static str, Duration)> {
+        use nix::sys::resource::{UsageWho, getrusage};
+        match getrusage(UsageWho::RUSAGE_SELF) {
+            Ok(usage) => {
+                let time = |t: nix::sys::time::TimeVal| {
+                    Duration::new(t.tv_sec() as u64, t.tv_usec() as u32 * 1000)
+                };
+                vec![(
+                    "process",
+                    time(usage.user_time()) + time(usage.system_time()),
+                )]
+            }
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+/// Ticks a second that /proc counts CPU time in.
+#[cfg(target_os = "linux")]
+static CLOCK_TICKS: std::sync::LazyLock<u64> =
+    std::sync::LazyLock::new(
+        || match nix::unistd::sysconf(nix::unistd::SysconfVar::CLK_TCK) {
+            Ok(Some(ticks)) if ticks > 0 => ticks as u64,
+            _ => 100,
+        },
+    );
+
+/// User and system time from a stat line. The command name can hold spaces
+/// and parentheses, so fields are counted from its closing one: utime and
+/// stime are the 14th and 15th fields.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_cpu_time(stat: &str, ticks_per_sec: u64) -> Option<Duration> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    let mut fields = fields.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    let nanos = (utime + stime).saturating_mul(1_000_000_000) / ticks_per_sec.max(1);
+    Some(Duration::from_nanos(nanos))
+}
+
+/// The statistics thread's view: the last sample, to take each interval's
+/// share from.
+#[derive(Debug)]
+pub struct CpuUsage {
+    last: Vec<(&'static str, Duration)>,
+    last_time: Instant,
+}
+
+impl CpuUsage {
+    pub fn new(threads: &ThreadCpu, now: Instant) -> Self {
+        Self {
+            last: threads.sample(),
+            last_time: now,
+        }
+    }
+
+    /// "  cpu: reader 97% statistics 3% writer 12%", each thread's share of
+    /// one core since the last line. Threads registered since then start
+    /// from nothing.
+    pub fn format(&mut self, threads: &ThreadCpu, now: Instant) -> String {
+        let mut sample = threads.sample();
+        sample.sort_by_key(|(name, _)| *name);
+        let elapsed = now.duration_since(self.last_time).as_secs_f64();
+        let line = format_usage(&self.last, &sample, elapsed);
+        self.last = sample;
+        self.last_time = now;
+        line
+    }
+}
+
+fn format_usage(
+    last: &[(&'static str, Duration)],
+    now: &[(&'static str, Duration)],
+    elapsed: f64,
+) -> String {
+    if now.is_empty() || elapsed <= 0.0 {
+        return String::new();
+    }
+    let shares: Vec<String> = now
+        .iter()
+        .map(|(name, used)| {
+            let before = last
+                .iter()
+                .find(|(last_name, _)| last_name == name)
+                .map_or(Duration::ZERO, |(_, before)| *before);
+            let percent = used.saturating_sub(before).as_secs_f64() / elapsed * 100.0;
+            format!("{name} {percent:.0}%")
+        })
+        .collect();
+    format!("  cpu: {}", shares.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_time() {
+        let stat = "4242 (mnc (x) y) S 1 4242 4242 0 -1 4194560 92 0 0 0 250 37 0 0 20 0 4 0 1000 \
+                    10000000 500 18446744073709551615";
+        assert_eq!(parse_cpu_time(stat, 100), Some(Duration::from_millis(2870)));
+        assert_eq!(parse_cpu_time("4242 (mnc) S 1", 100), None);
+        assert_eq!(parse_cpu_time("garbage", 100), None);
+    }
+
+    #[test]
+    fn test_format_usage() {
+        let last = [
+            ("reader", Duration::from_millis(1000)),
+            ("writer", Duration::from_millis(200)),
+        ];
+        let now = [
+            ("reader", Duration::from_millis(2940)),
+            ("statistics", Duration::from_millis(60)),
+            ("writer", Duration::from_millis(440)),
+        ];
+        assert_eq!(
+            format_usage(&last, &now, 2.0),
+            "  cpu: reader 97% statistics 3% writer 12%"
+        );
+        assert_eq!(format_usage(&last, &[], 2.0), "");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_registered_thread_is_sampled() {
+        let threads = ThreadCpu::default();
+        threads.register("test");
+        let sample = threads.sample();
+        assert_eq!(sample.len(), 1);
+        assert_eq!(sample.first().map(|(name, _)| *name), Some("test"));
+    }
+}
@@ -0,0 +1,228 @@
+/// --dejitter: holds every packet for a fixed delay after it arrived and
+/// releases it then, so the output keeps the spacing the sender intended and
+/// loses the jitter the network added. Packets are kept in a min-heap by
+/// release time and the writer sleeps until the next one is due.
+///
+/// Like reordering, held packets are swapped out of their batch for spare
+/// buffers, so batches go back to the pool as usual.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::packet::{Packet, Packets};
+
+// Most packets held at once. Beyond this the oldest are dropped, so a stream
+// far faster than the delay allows can't grow the buffer without bound.
+pub const MAX_HELD: usize = 16384;
+
+// Released later than this after its time counts as a late release
+const LATE_AFTER: Duration = Duration::from_millis(1);
+
+/// Buffer depth and what went wrong, shared with the stats line.
+#[derive(Debug, Default)]
+pub struct DejitterCounters {
+    held: AtomicU64,
+    late: AtomicU64,
+    overflowed: AtomicU64,
+}
+
+impl DejitterCounters {
+    /// (held now, late releases, dropped on overflow), the last two so far.
+    pub fn get(&self) -> (u64, u64, u64) {
+        (
+            self.held.load(Ordering::Relaxed),
+            self.late.load(Ordering::Relaxed),
+            self.overflowed.load(Ordering::Relaxed),
+        )
+    }
+}
+
+// A held packet, ordered by when it's due and then by arrival
+#[derive(Debug)]
+struct Held {
+    due: Instant,
+    order: u64,
+    packet: Packet,
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.order) == (other.due, other.order)
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.due, self.order).cmp(&(other.due, other.order))
+    }
+}
+
+#[derive(Debug)]
+pub struct Dejitter {
+    delay: Duration,
+    max_held: usize,
+    held: BinaryHeap<Reverse<Held>>,
+    arrivals: u64,
+    spare: Vec<Packet>,
+}
+
+impl Dejitter {
+    pub fn new(delay: Duration) -> Self {
+        Self::with_max_held(delay, MAX_HELD)
+    }
+
+    fn with_max_held(delay: Duration, max_held: usize) -> Self {
+        Self {
+            delay,
+            max_held,
+            held: BinaryHeap::new(),
+            arrivals: 0,
+            spare: Vec::new(),
+        }
+    }
+
+    /// Take the batch's packets, which arrived by now.
+    pub fn push(&mut self, packets: &mut Packets, now: Instant, counters: &DejitterCounters) {
+        for slot in packets.iter_mut() {
+            let spare = self
+                .spare
+                .pop()
+                .unwrap_or_else(|| Packet::with_capacity(slot.capacity()));
+            let packet = std::mem::replace(slot, spare);
+            self.hold(packet, now, counters);
+        }
+    }
+
+    /// Hold a packet until the delay has passed since it arrived: its kernel
+    /// receive time when it has one, else now.
+    pub fn hold(&mut self, packet: Packet, now: Instant, counters: &DejitterCounters) {
+        let age = packet
+            .timestamp()
+            .and_then(|arrived| SystemTime::now().duration_since(arrived).ok())
+            .unwrap_or_default();
+        let arrived = now.checked_sub(age).unwrap_or(now);
+        self.held.push(Reverse(Held {
+            due: arrived + self.delay,
+            order: self.arrivals,
+            packet,
+        }));
+        self.arrivals += 1;
+
+        // Full: the oldest go
+        while self.held.len() > self.max_held {
+            if let Some(Reverse(dropped)) = self.held.pop() {
+                self.spare.push(dropped.packet);
+                counters.overflowed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        counters
+            .held
+            .store(self.held.len() as u64, Ordering::Relaxed);
+    }
+
+    /// When the next packet is due.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.held.peek().map(|Reverse(held)| held.due)
+    }
+
+    /// Append the packets due by now to ready, in order.
+    pub fn release(&mut self, now: Instant, ready: &mut Vec<Packet>, counters: &DejitterCounters) {
+        while self.next_due().is_some_and(|due| due <= now) {
+            let Some(Reverse(held)) = self.held.pop() else {
+                break;
+            };
+            if now.saturating_duration_since(held.due) > LATE_AFTER {
+                counters.late.fetch_add(1, Ordering::Relaxed);
+            }
+            ready.push(held.packet);
+        }
+        counters
+            .held
+            .store(self.held.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Everything still held, in order, without waiting for it to be due.
+    pub fn flush(&mut self, ready: &mut Vec<Packet>, counters: &DejitterCounters) {
+        while let Some(Reverse(held)) = self.held.pop() {
+            ready.push(held.packet);
+        }
+        counters.held.store(0, Ordering::Relaxed);
+    }
+
+    /// Take written packets back as spare buffers.
+    pub fn reuse(&mut self, written: &mut Vec<Packet>) {
+        self.spare.append(written);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn batch(payloads: &[u8]) -> Packets {
+        let mut packets = Packets::new(payloads.len(), 1);
+        for (packet, payload) in packets.iter_mut().zip(payloads) {
+            packet.data_mut().fill(*payload);
+        }
+        packets
+    }
+
+    fn payloads(ready: &[Packet]) -> Vec<u8> {
+        ready
+            .iter()
+            .filter_map(|packet| packet.first().copied())
+            .collect()
+    }
+
+    #[test]
+    fn test_released_after_the_delay() {
+        let counters = DejitterCounters::default();
+        let mut dejitter = Dejitter::new(Duration::from_millis(50));
+        let start = Instant::now();
+        let mut ready = Vec::new();
+
+        dejitter.push(&mut batch(&[1, 2]), start, &counters);
+        dejitter.push(
+            &mut batch(&[3]),
+            start + Duration::from_millis(20),
+            &counters,
+        );
+        assert_eq!(dejitter.next_due(), Some(start + Duration::from_millis(50)));
+        assert_eq!(counters.get(), (3, 0, 0));
+
+        dejitter.release(start + Duration::from_millis(49), &mut ready, &counters);
+        assert!(ready.is_empty());
+        dejitter.release(start + Duration::from_millis(50), &mut ready, &counters);
+        assert_eq!(payloads(&ready), [1, 2]);
+        dejitter.reuse(&mut ready);
+
+        // Well past its time
+        dejitter.release(start + Duration::from_millis(90), &mut ready, &counters);
+        assert_eq!(payloads(&ready), [3]);
+        assert_eq!(counters.get(), (0, 1, 0));
+        assert_eq!(dejitter.next_due(), None);
+    }
+
+    #[test]
+    fn test_overflow_drops_the_oldest() {
+        let counters = DejitterCounters::default();
+        let mut dejitter = Dejitter::with_max_held(Duration::from_millis(10), 2);
+        let start = Instant::now();
+        let mut ready = Vec::new();
+
+        dejitter.push(&mut batch(&[1, 2, 3]), start, &counters);
+        dejitter.release(start + Duration::from_millis(10), &mut ready, &counters);
+        assert_eq!(payloads(&ready), [2, 3]);
+        assert_eq!(counters.get(), (0, 0, 1));
+    }
+}
@@ -0,0 +1,431 @@
+/// Checks behind --diagnose.
+/// When a receive stays silent, gather evidence for the usual suspects:
+/// reverse path filtering, a membership that never made it into the kernel,
+/// an interface with no multicast arriving, and traffic that is on the wire
+/// but never reaches the socket. Each check prints its own verdict.
+use std::fs;
+use std::net::Ipv4Addr;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+#[cfg(target_os = "linux")]
+use nix::net::if_::if_nametoindex;
+#[cfg(target_os = "linux")]
+use nix::sys::socket::{
+    AddressFamily, LinkAddr, SockFlag, SockProtocol, SockType, recvfrom, setsockopt, socket,
+    sockopt,
+};
+#[cfg(target_os = "linux")]
+use nix::sys::time::TimeVal;
+
+use crate::{SharedState, error::Result, preflight};
+
+// How long the multicast counter and the packet tap are sampled for.
+const SAMPLE_TIME: Duration = Duration::from_secs(2);
+
+// EtherType for IPv4 as carried in sockaddr_ll
+#[cfg(target_os = "linux")]
+const ETH_P_IP: u16 = 0x0800;
+
+/// Diagnose thread configuration.
+/// The checks run once, if nothing has been read after waiting for after.
+pub struct DiagnoseConfig {
+    pub shared_state: SharedState,
+    pub iface: Option<String>,
+    pub mgroup: String,
+    pub port: u16,
+    pub after: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Ok,
+    Problem,
+    /// The check couldn't run, usually for lack of privileges
+    Skipped,
+}
+
+/// One check's outcome, as a line a person can act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub check: &'static str,
+    pub verdict: Verdict,
+    pub detail: String,
+}
+
+impl Finding {
+    fn new(check: &'static str, verdict: Verdict, detail: impl Into<String>) -> Self {
+        Self {
+            check,
+            verdict,
+            detail: detail.into(),
+        }
+    }
+
+    fn log(&self) {
+        match self.verdict {
+            Verdict::Ok => log::info!("diagnose {}: ok, {}", self.check, self.detail),
+            Verdict::Problem => log::warn!("diagnose {}: {}", self.check, self.detail),
+            Verdict::Skipped => log::info!("diagnose {}: skipped, {}", self.check, self.detail),
+        }
+    }
+}
+
+/// Spawn the diagnose thread. It stays around until exit is signaled so that
+/// finishing the checks doesn't end the run.
+pub fn spawn(config: DiagnoseConfig) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        run_diagnose(&config);
+        while !config.shared_state.should_exit() {
+            thread::sleep(Duration::from_millis(100));
+        }
+        log::debug!("diagnose exited");
+        Ok(())
+    })
+}
+
+fn run_diagnose(
+    DiagnoseConfig {
+        shared_state,
+        iface,
+        mgroup,
+        port,
+        after,
+    }: &DiagnoseConfig,
+) {
+    let start = Instant::now();
+    while start.elapsed() < *after {
+        if shared_state.should_exit() || shared_state.get_read_count() > 0 {
+            return;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    log::warn!(
+        "nothing received from {mgroup}:{port} in {}s, diagnosing",
+        after.as_secs()
+    );
+
+    let group: Ipv4Addr = match mgroup.parse() {
+        Ok(group) => group,
+        Err(e) => return log::warn!("diagnose: {mgroup}: {e}"),
+    };
+    let interface = match preflight::resolve_interface(iface.as_deref(), &group) {
+        Ok(interface) => interface,
+        Err(e) => return log::warn!("diagnose interface: {e}"),
+    };
+    let name = interface.name.as_str();
+
+    if !interface.up {
+        Finding::new("interface", Verdict::Problem, format!("{name} is down")).log();
+    } else {
+        Finding::new(
+            "interface",
+            Verdict::Ok,
+            format!("{name} ({}) is up", interface.addr),
+        )
+        .log();
+    }
+
+    check_rp_filter(name).log();
+    check_membership(name, &group).log();
+
+    // Sample the counter and the wire over the same window
+    let tap = thread::spawn({
+        let name = name.to_string();
+        let port = *port;
+        move || check_wire(&name, &group, port)
+    });
+    check_multicast_counter(name).log();
+    let on_wire = tap.join().is_ok_and(|finding| {
+        finding.log();
+        finding.verdict == Verdict::Ok
+    });
+
+    if shared_state.get_read_count() > 0 {
+        log::info!("diagnose: packets started arriving while diagnosing");
+    } else if on_wire {
+        log::warn!(
+            "diagnose: traffic for {group}:{port} is on the wire but not reaching mnc; \
+             look at rp_filter and the host firewall (firewalld, nftables, iptables)"
+        );
+    }
+}
+
+/// Strict reverse path filtering drops multicast whose source isn't routed
+/// back out the interface it arrived on. The kernel uses the larger of the
+/// interface's and "all".
+fn check_rp_filter(iface: &str) -> Finding {
+    let read = |conf: &str| {
+        fs::read_to_string(format!("/proc/sys/net/ipv4/conf/{conf}/rp_filter"))
+            .ok()
+            .and_then(|value| value.trim().parse::<u8>().ok())
+    };
+
+    match (read(iface), read("all")) {
+        (Some(value), all) => rp_filter_finding(iface, value.max(all.unwrap_or(0))),
+        (None, _) => Finding::new(
+            "rp_filter",
+            Verdict::Skipped,
+            format!("unable to read rp_filter for {iface}"),
+        ),
+    }
+}
+
+fn rp_filter_finding(iface: &str, value: u8) -> Finding {
+    match value {
+        0 => Finding::new("rp_filter", Verdict::Ok, format!("disabled on {iface}")),
+        2 => Finding::new("rp_filter", Verdict::Ok, format!("loose on {iface}")),
+        _ => Finding::new(
+            "rp_filter",
+            Verdict::Problem,
+            format!(
+                "strict on {iface}, multicast from sources not routed via {iface} is dropped \
+                 (sysctl net.ipv4.conf.{iface}.rp_filter=2)"
+            ),
+        ),
+    }
+}
+
+fn check_membership(iface: &str, group: &Ipv4Addr) -> Finding {
+    let contents = match fs::read_to_string("/proc/net/igmp") {
+        Ok(contents) => contents,
+        Err(e) => {
+            return Finding::new(
+                "membership",
+                Verdict::Skipped,
+                format!("/proc/net/igmp: {e}"),
+            );
+        }
+    };
+
+    if parse_igmp(&contents)
+        .iter()
+        .any(|(device, joined)| device == iface && joined == group)
+    {
+        Finding::new(
+            "membership",
+            Verdict::Ok,
+            format!("{group} is joined on {iface}"),
+        )
+    } else {
+        Finding::new(
+            "membership",
+            Verdict::Problem,
+            format!("{group} is not joined on {iface}"),
+        )
+    }
+}
+
+/// (device, group) pairs from /proc/net/igmp. Device lines start with an
+/// index, group lines are indented under them. Groups are the raw network
+/// order word printed as hex.
+pub(crate) fn parse_igmp(contents: &str) -> Vec<(String, Ipv4Addr)> {
+    let mut memberships = Vec::new();
+    let mut device: Option<String> = None;
+
+    for line in contents.lines().skip(1) {
+        let mut fields = line.split_whitespace();
+        if line.starts_with(char::is_whitespace) {
+            if let (Some(device), Some(hex)) = (&device, fields.next())
+                && let Ok(word) = u32::from_str_radix(hex, 16)
+            {
+                memberships.push((device.clone(), Ipv4Addr::from(word.to_ne_bytes())));
+            }
+        } else {
+            device = fields
+                .nth(1)
+                .map(|name| name.trim_end_matches(':').to_string());
+        }
+    }
+
+    memberships
+}
+
+/// Whether any multicast is arriving on the interface at all.
+fn check_multicast_counter(iface: &str) -> Finding {
+    let path = format!("/sys/class/net/{iface}/statistics/multicast");
+    let read = || {
+        fs::read_to_string(&path)
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+
+    let Some(before) = read() else {
+        return Finding::new(
+            "rx counter",
+            Verdict::Skipped,
+            format!("unable to read {path}"),
+        );
+    };
+    thread::sleep(SAMPLE_TIME);
+    let Some(after) = read() else {
+        return Finding::new(
+            "rx counter",
+            Verdict::Skipped,
+            format!("unable to read {path}"),
+        );
+    };
+
+    let frames = after.saturating_sub(before);
+    if frames > 0 {
+        Finding::new(
+            "rx counter",
+            Verdict::Ok,
+            format!("{frames} multicast frames arrived on {iface}"),
+        )
+    } else {
+        Finding::new(
+            "rx counter",
+            Verdict::Problem,
+            format!(
+                "no multicast arrived on {iface}; check the sender, the switch port, \
+                 and that an IGMP querier (or static join) is forwarding the group"
+            ),
+        )
+    }
+}
+
+/// Watch the interface through an AF_PACKET socket for the group's traffic.
+/// Needs CAP_NET_RAW.
+fn check_wire(iface: &str, group: &Ipv4Addr, port: u16) -> Finding {
+    match count_on_wire(iface, group, port) {
+        Ok(0) => Finding::new(
+            "wire",
+            Verdict::Problem,
+            format!("no {group}:{port} traffic seen on {iface}"),
+        ),
+        Ok(seen) => Finding::new(
+            "wire",
+            Verdict::Ok,
+            format!("{seen} packets for {group}:{port} seen on {iface}"),
+        ),
+        Err(crate::error::LibError::Nix(Errno::EPERM)) => Finding::new(
+            "wire",
+            Verdict::Skipped,
+            "watching the wire needs root (CAP_NET_RAW)",
+        ),
+        Err(e) => Finding::new("wire", Verdict::Skipped, e.to_string()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn count_on_wire(_iface: &str, _group: &Ipv4Addr, _port: u16) -> Result<u64> {
+    Err(crate::error::LibError::Critical(
+        "watching the wire needs Linux (AF_PACKET)".to_string(),
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn count_on_wire(iface: &str, group: &Ipv4Addr, port: u16) -> Result<u64> {
+    let ifindex = if_nametoindex(iface)? as usize;
+    // Cooked socket: frames arrive without the link header
+    let fd = socket(
+        AddressFamily::Packet,
+        SockType::Datagram,
+        SockFlag::SOCK_CLOEXEC,
+        SockProtocol::EthAll,
+    )?;
+    setsockopt(&fd, sockopt::ReceiveTimeout, &TimeVal::new(0, 100_000))?;
+
+    let mut buffer = vec![0u8; 128];
+    let mut seen = 0;
+    let start = Instant::now();
+    while start.elapsed() < SAMPLE_TIME {
+        match recvfrom::<LinkAddr>(fd.as_raw_fd(), &mut buffer) {
+            Ok((bytes, Some(from)))
+                if from.ifindex() == ifindex && u16::from_be(from.protocol()) == ETH_P_IP =>
+            {
+                if buffer
+                    .get(..bytes)
+                    .is_some_and(|frame| is_udp_to(frame, group, port))
+                {
+                    seen += 1;
+                }
+            }
+            Ok(_) | Err(Errno::EAGAIN) | Err(Errno::EINTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(seen)
+}
+
+/// Whether an IPv4 packet is UDP addressed to group:port.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn is_udp_to(packet: &[u8], group: &Ipv4Addr, port: u16) -> bool {
+    let Some(&version_ihl) = packet.first() else {
+        return false;
+    };
+    let header_len = usize::from(version_ihl & 0x0f) * 4;
+    if version_ihl >> 4 != 4 || packet.get(9) != Some(&17) {
+        return false;
+    }
+
+    let destination = packet.get(16..20) == Some(&group.octets()[..]);
+    let dst_port = packet
+        .get(header_len + 2..header_len + 4)
+        .and_then(|bytes| <[u8; 2]>::try_from(bytes).ok())
+        .map(u16::from_be_bytes);
+    destination && dst_port == Some(port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_igmp() {
+        let group = |group: Ipv4Addr| u32::from_ne_bytes(group.octets());
+        let contents = format!(
+            "Idx\tDevice    : Count Querier\tGroup    Users Timer\tReporter\n\
+             1\tlo        :     1      V3\n\
+             \t\t\t\t{:08X}     1 0:00000000\t\t0\n\
+             2\teth0      :     2      V2\n\
+             \t\t\t\t{:08X}     1 0:00000000\t\t0\n\
+             \t\t\t\t{:08X}     1 0:00000000\t\t0\n",
+            group(Ipv4Addr::new(224, 0, 0, 1)),
+            group(Ipv4Addr::new(239, 1, 2, 3)),
+            group(Ipv4Addr::new(224, 0, 0, 1)),
+        );
+
+        assert_eq!(
+            parse_igmp(&contents),
+            vec![
+                ("lo".to_string(), Ipv4Addr::new(224, 0, 0, 1)),
+                ("eth0".to_string(), Ipv4Addr::new(239, 1, 2, 3)),
+                ("eth0".to_string(), Ipv4Addr::new(224, 0, 0, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rp_filter_finding() {
+        assert_eq!(rp_filter_finding("eth0", 0).verdict, Verdict::Ok);
+        assert_eq!(rp_filter_finding("eth0", 1).verdict, Verdict::Problem);
+        assert_eq!(rp_filter_finding("eth0", 2).verdict, Verdict::Ok);
+    }
+
+    #[test]
+    fn test_is_udp_to() {
+        let group = Ipv4Addr::new(239, 1, 2, 3);
+        let mut packet = vec![0u8; 28];
+        packet.splice(0..1, [0x45]);
+        packet.splice(9..10, [17]);
+        packet.splice(16..20, group.octets());
+        packet.splice(22..24, 29495u16.to_be_bytes());
+
+        assert!(is_udp_to(&packet, &group, 29495));
+        assert!(!is_udp_to(&packet, &group, 29496));
+        assert!(!is_udp_to(&packet, &Ipv4Addr::new(239, 1, 2, 4), 29495));
+        let truncated = packet.get(..22).unwrap_or_default();
+        assert!(!is_udp_to(truncated, &group, 29495));
+
+        // TCP to the same address
+        packet.splice(9..10, [6]);
+        assert!(!is_udp_to(&packet, &group, 29495));
+    }
+}
@@ -0,0 +1,120 @@
+/// The drain on exit, shared by the writer and statistics threads. Once exit
+/// is signaled each keeps taking batches off its queue, so what was read is
+/// still written, but only for --drain-timeout less a margin: main stops
+/// waiting for the threads at the timeout, and a thread cut off then could
+/// leave half a record in a file. Giving up early leaves time to finish the
+/// batch in hand and flush. Whatever is still queued is abandoned: counted
+/// and let go without being written.
+use std::time::{Duration, Instant};
+
+use crate::{SharedState, transport::BatchReceiver};
+
+/// Kept back from --drain-timeout to finish the batch in hand and flush,
+/// or a quarter of it when that's less.
+pub const DRAIN_MARGIN: Duration = Duration::from_millis(250);
+
+/// How long a thread has been draining, and how long it may.
+#[derive(Debug, Clone, Copy)]
+pub struct ExitDrain {
+    // None drains for as long as it takes
+    budget: Option<Duration>,
+    since: Option<Instant>,
+}
+
+impl ExitDrain {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            budget: timeout.map(|timeout| timeout.saturating_sub(DRAIN_MARGIN.min(timeout / 4))),
+            since: None,
+        }
+    }
+
+    /// Whether the thread has drained as long as it may. The clock starts
+    /// the first time this sees exit signaled.
+    pub fn spent(&mut self, shared_state: &SharedState) -> bool {
+        if !shared_state.should_exit() {
+            return false;
+        }
+        let since = *self.since.get_or_insert_with(Instant::now);
+        self.budget.is_some_and(|budget| since.elapsed() >= budget)
+    }
+
+    /// Let go of the batches queued when the thread gave up, without
+    /// waiting for more, and count their packets as abandoned.
+    pub fn abandon(
+        &self,
+        stage: &str,
+        data_rx: &mut dyn BatchReceiver,
+        shared_state: &SharedState,
+    ) -> u64 {
+        let (queued, _) = data_rx.occupancy();
+        let mut abandoned = 0;
+        for _ in 0..queued {
+            match data_rx.pop_timeout(Duration::ZERO) {
+                Ok(packets) => abandoned += packets.len() as u64,
+                Err(_) => break,
+            }
+        }
+        shared_state.add_abandoned(abandoned);
+        log::warn!(
+            "{stage} gave up draining after {:.1}s, {abandoned} queued packets abandoned",
+            self.since
+                .map_or(0.0, |since| since.elapsed().as_secs_f64())
+        );
+        abandoned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        PacketType, Packets,
+        transport::{self, TransportKind},
+    };
+
+    #[test]
+    fn test_budget_leaves_a_margin() {
+        assert_eq!(
+            ExitDrain::new(Some(Duration::from_secs(1))).budget,
+            Some(Duration::from_millis(750))
+        );
+        assert_eq!(
+            ExitDrain::new(Some(Duration::from_secs(10))).budget,
+            Some(Duration::from_millis(9750))
+        );
+        assert_eq!(
+            ExitDrain::new(Some(Duration::from_millis(100))).budget,
+            Some(Duration::from_millis(75))
+        );
+        assert_eq!(ExitDrain::new(None).budget, None);
+    }
+
+    #[test]
+    fn test_spent_once_exit_is_signaled() {
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let mut drain = ExitDrain::new(Some(Duration::ZERO));
+        assert!(!drain.spent(&shared_state));
+        shared_state.signal_exit();
+        assert!(drain.spent(&shared_state));
+
+        let mut drain = ExitDrain::new(None);
+        assert!(!drain.spent(&shared_state));
+    }
+
+    #[test]
+    fn test_abandon_counts_queued_packets() {
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let (mut data_tx, mut data_rx) = transport::bounded(TransportKind::Channel, 4);
+        for length in [3, 5] {
+            let mut packets = Packets::new(length, 8);
+            packets.set_length(length);
+            assert!(data_tx.try_push(packets).is_ok());
+        }
+
+        let drain = ExitDrain::new(Some(Duration::ZERO));
+        assert_eq!(drain.abandon("writer", &mut data_rx, &shared_state), 8);
+        assert_eq!(shared_state.get_abandoned(), 8);
+        assert_eq!(data_rx.occupancy().0, 0);
+    }
+}
@@ -0,0 +1,340 @@
+/// Hex dump output for -v.
+/// Dumps bypass the logger: no per-line prefix, no log level, and each packet
+/// is written under one lock so stats lines can't land in the middle of it.
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+
+use crate::{error::Result, packet::Packet};
+
+/// Where -v hex dumps go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DumpOutput {
+    Stdout,
+    /// For when packets are written to stdout
+    Stderr,
+    File(String),
+}
+
+enum DumpWriter {
+    // The standard streams are locked per packet
+    Stdout,
+    Stderr,
+    File(BufWriter<File>),
+}
+
+/// How --dump-diff marks the bytes that changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffStyle {
+    /// ANSI color, for a terminal
+    Color,
+    /// [xx], for files and pipes
+    Brackets,
+}
+
+// Offset ranges listed in a diff summary before the rest are left out
+const SUMMARY_RANGES: usize = 8;
+
+pub struct HexDump {
+    writer: DumpWriter,
+    diff: Option<DiffStyle>,
+    // The last packet dumped, to compare the next one with
+    previous: Option<Vec<u8>>,
+}
+
+impl HexDump {
+    pub fn create(output: &DumpOutput) -> Result<Self> {
+        let writer = match output {
+            DumpOutput::Stdout => DumpWriter::Stdout,
+            DumpOutput::Stderr => DumpWriter::Stderr,
+            DumpOutput::File(filename) => DumpWriter::File(BufWriter::new(File::create(filename)?)),
+        };
+        Ok(Self {
+            writer,
+            diff: None,
+            previous: None,
+        })
+    }
+
+    /// Mark the bytes that differ from the previous packet dumped, and sum
+    /// up where they are.
+    pub fn with_diff(mut self) -> Self {
+        let terminal = match self.writer {
+            DumpWriter::Stdout => io::stdout().is_terminal(),
+            DumpWriter::Stderr => io::stderr().is_terminal(),
+            DumpWriter::File(_) => false,
+        };
+        self.diff = Some(if terminal {
+            DiffStyle::Color
+        } else {
+            DiffStyle::Brackets
+        });
+        self
+    }
+
+    /// Banner, decoded header if any, then the bytes.
+    pub fn dump(&mut self, index: u64, packet: &Packet, header: Option<&str>) -> Result<()> {
+        let diff = self.diff.map(|style| Diff {
+            style,
+            previous: self.previous.as_deref(),
+        });
+        match &mut self.writer {
+            DumpWriter::File(file) => {
+                write_dump(file, index, packet, header, diff)?;
+                file.flush()?;
+            }
+            DumpWriter::Stdout => {
+                let mut stdout = io::stdout().lock();
+                write_dump(&mut stdout, index, packet, header, diff)?;
+                stdout.flush()?;
+            }
+            DumpWriter::Stderr => {
+                let mut stderr = io::stderr().lock();
+                write_dump(&mut stderr, index, packet, header, diff)?;
+                stderr.flush()?;
+            }
+        }
+        if self.diff.is_some() {
+            let previous = self.previous.get_or_insert_with(Vec::new);
+            previous.clear();
+            previous.extend_from_slice(packet);
+        }
+        Ok(())
+    }
+}
+
+/// The packet to compare with, None for the first one.
+#[derive(Debug, Clone, Copy)]
+struct Diff<'a> {
+    style: DiffStyle,
+    previous: Option<&'a [u8]>,
+}
+
+impl Diff<'_> {
+    /// Whether the byte at offset differs, or has nothing to compare with.
+    fn changed(&self, offset: usize, byte: u8) -> bool {
+        self.previous
+            .is_some_and(|previous| previous.get(offset) != Some(&byte))
+    }
+}
+
+fn write_dump(
+    out: &mut impl Write,
+    index: u64,
+    packet: &Packet,
+    header: Option<&str>,
+    diff: Option<Diff>,
+) -> io::Result<()> {
+    let arrival = packet
+        .timestamp()
+        .map(|time| {
+            let time: chrono::DateTime<chrono::Utc> = time.into();
+            time.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string()
+        })
+        .unwrap_or_else(|| "-".to_string());
+    write!(out, "packet #{index}  {} bytes  at {arrival}", packet.len())?;
+    match packet.ttl() {
+        Some(ttl) => writeln!(out, "  ttl {ttl}")?,
+        None => writeln!(out)?,
+    }
+
+    if let Some(header) = header {
+        writeln!(out, "{}", header.trim_end())?;
+    }
+
+    write_hex(out, packet, diff)?;
+    if let Some(Diff {
+        previous: Some(previous),
+        ..
+    }) = diff
+    {
+        writeln!(out, "{}", diff_summary(previous, packet))?;
+    }
+    writeln!(out)
+}
+
+// Look roughly like the output of od
+fn write_hex(out: &mut impl Write, data: &[u8], diff: Option<Diff>) -> io::Result<()> {
+    let brackets = diff.is_some_and(|diff| diff.style == DiffStyle::Brackets);
+    let blank = if brackets { "    " } else { "   " };
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let mut line = format!("{:08x}  ", i * 16);
+        let changed = |j: usize, byte: u8| diff.is_some_and(|diff| diff.changed(i * 16 + j, byte));
+
+        for (j, &byte) in chunk.iter().enumerate() {
+            match diff.map(|diff| diff.style) {
+                Some(DiffStyle::Color) if changed(j, byte) => {
+                    line.push_str(&format!("\x1b[1;31m{byte:02x}\x1b[0m "))
+                }
+                Some(DiffStyle::Brackets) if changed(j, byte) => {
+                    line.push_str(&format!("[{byte:02x}]"))
+                }
+                Some(DiffStyle::Brackets) => line.push_str(&format!(" {byte:02x} ")),
+                _ => line.push_str(&format!("{byte:02x} ")),
+            }
+            if j == 7 {
+                line.push(' ');
+            }
+        }
+
+        if chunk.len() < 16 {
+            for j in chunk.len()..16 {
+                line.push_str(blank);
+                if j == 7 {
+                    line.push(' ');
+                }
+            }
+        }
+
+        line.push_str(" |");
+        for (j, &byte) in chunk.iter().enumerate() {
+            let c = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            if !brackets && changed(j, byte) {
+                line.push_str(&format!("\x1b[1;31m{c}\x1b[0m"));
+            } else {
+                line.push(c);
+            }
+        }
+        line.push('|');
+        writeln!(out, "{line}")?;
+    }
+
+    Ok(())
+}
+
+/// "N bytes differ at offsets ...", and the length change if there was one.
+fn diff_summary(previous: &[u8], current: &[u8]) -> String {
+    let offsets: Vec<usize> = current
+        .iter()
+        .enumerate()
+        .filter(|(offset, byte)| previous.get(*offset) != Some(byte))
+        .map(|(offset, _)| offset)
+        .collect();
+
+    // Runs of neighbouring offsets as first..=last
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &offset in &offsets {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == offset => *last = offset,
+            _ => ranges.push((offset, offset)),
+        }
+    }
+    let mut listed: Vec<String> = ranges
+        .iter()
+        .take(SUMMARY_RANGES)
+        .map(|&(first, last)| {
+            if first == last {
+                format!("0x{first:x}")
+            } else {
+                format!("0x{first:x}-0x{last:x}")
+            }
+        })
+        .collect();
+    if ranges.len() > SUMMARY_RANGES {
+        listed.push("...".to_string());
+    }
+
+    let mut summary = match offsets.len() {
+        0 => "no bytes differ".to_string(),
+        1 => format!("1 byte differs at offset {}", listed.join(", ")),
+        n => format!("{n} bytes differ at offsets {}", listed.join(", ")),
+    };
+    if previous.len() != current.len() {
+        summary.push_str(&format!(
+            "; length {} bytes, was {}",
+            current.len(),
+            previous.len()
+        ));
+    }
+    summary
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::packet::Packets;
+
+    #[test]
+    fn test_write_dump() {
+        let mut packets = Packets::new(1, 64);
+        for packet in packets.iter_mut() {
+            let payload = b"hello, multicast world";
+            if let Some(data) = packet.data_mut().get_mut(..payload.len()) {
+                data.copy_from_slice(payload);
+            }
+            packet.set_length(payload.len());
+            packet.set_timestamp(Some(
+                SystemTime::UNIX_EPOCH + Duration::from_micros(1_714_660_282_123_456),
+            ));
+            packet.set_ttl(Some(62));
+        }
+
+        let mut out = Vec::new();
+        for packet in packets.iter() {
+            write_dump(&mut out, 3, packet, Some("Header:\n  field: 1\n"), None).expect("dump");
+        }
+
+        assert_eq!(
+            String::from_utf8(out).expect("utf8"),
+            "packet #3  22 bytes  at 2024-05-02T14:31:22.123456Z  ttl 62\n\
+             Header:\n  field: 1\n\
+             00000000  68 65 6c 6c 6f 2c 20 6d  75 6c 74 69 63 61 73 74  |hello, multicast|\n\
+             00000010  20 77 6f 72 6c 64                                 | world|\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn test_write_hex_diff() {
+        let diff = Diff {
+            style: DiffStyle::Brackets,
+            previous: Some(b"abcd"),
+        };
+        let mut out = Vec::new();
+        write_hex(&mut out, b"abXde", Some(diff)).expect("hex");
+        assert_eq!(
+            String::from_utf8(out).expect("utf8"),
+            "00000000   61  62 [58] 64 [65]                                              |abXde|\n"
+        );
+
+        let diff = Diff {
+            style: DiffStyle::Color,
+            previous: Some(b"ab"),
+        };
+        let mut out = Vec::new();
+        write_hex(&mut out, b"aB", Some(diff)).expect("hex");
+        assert_eq!(
+            String::from_utf8(out).expect("utf8"),
+            "00000000  61 \x1b[1;31m42\x1b[0m                                             |a\x1b[1;31mB\x1b[0m|\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_summary() {
+        assert_eq!(diff_summary(b"abcd", b"abcd"), "no bytes differ");
+        assert_eq!(
+            diff_summary(b"abcd", b"abXd"),
+            "1 byte differs at offset 0x2"
+        );
+        assert_eq!(
+            diff_summary(b"abcdefgh", b"XYcdeZgh"),
+            "3 bytes differ at offsets 0x0-0x1, 0x5"
+        );
+        assert_eq!(
+            diff_summary(b"ab", b"abcd"),
+            "2 bytes differ at offsets 0x2-0x3; length 4 bytes, was 2"
+        );
+        assert_eq!(
+            diff_summary(b"abcd", b"ab"),
+            "no bytes differ; length 2 bytes, was 4"
+        );
+        let alternating: Vec<u8> = (0..40).map(|i| if i % 2 == 0 { 1 } else { 0 }).collect();
+        assert!(diff_summary(&[0; 40], &alternating).ends_with("0xc, 0xe, ..."));
+    }
+}
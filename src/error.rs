@@ -22,6 +22,11 @@ pub enum LibError {
     TryRecvBatch(#[from] crossbeam_channel::TryRecvError),
     #[error(transparent)]
     RecvTimeoutBatch(#[from] crossbeam_channel::RecvTimeoutError),
+    #[error("gave up draining with packets still queued")]
+    DrainIncomplete,
+    /// --expect saw the stream differ from the file
+    #[error("{0}")]
+    ExpectFailed(String),
     #[error("{0}")]
     Critical(String),
 }
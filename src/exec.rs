@@ -0,0 +1,223 @@
+/// Child process sinks for the writer.
+/// The child runs in its own process group so a terminal Ctrl-C reaches only
+/// mnc, which then drains, closes the child's stdin and reaps it.
+use std::io::{self, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Restart backoff doubles from here up to MAX_BACKOFF.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+// A child that exits this many times in a row, each time within
+// STABLE_RUN of starting, is considered broken rather than flaky.
+const MAX_RESTARTS: u32 = 5;
+const STABLE_RUN: Duration = Duration::from_secs(10);
+
+// How long a child gets to finish after its stdin is closed. Kept under
+// main's shutdown timeout so a Ctrl-C still reaps the child.
+const EXIT_GRACE: Duration = Duration::from_millis(500);
+
+fn spawn_shell(command: &str) -> io::Result<Child> {
+    Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .process_group(0)
+        .spawn()
+}
+
+/// Close stdin, give the child a moment to exit on its own, then kill it.
+fn reap(mut child: Child) -> Option<ExitStatus> {
+    drop(child.stdin.take());
+
+    let deadline = Instant::now() + EXIT_GRACE;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Some(status),
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            Ok(None) | Err(_) => break,
+        }
+    }
+
+    log::warn!("exec: child {} did not exit, killing it", child.id());
+    let _ = child.kill();
+    child.wait().ok()
+}
+
+/// Long running child fed through its stdin, restarted with backoff when it exits.
+pub struct ExecWriter {
+    command: String,
+    child: Option<Child>,
+    started: Instant,
+    restarts: u32,
+    initial_backoff: Duration,
+}
+
+impl ExecWriter {
+    pub fn new(command: &str) -> io::Result<Self> {
+        Self::with_backoff(command, INITIAL_BACKOFF)
+    }
+
+    fn with_backoff(command: &str, initial_backoff: Duration) -> io::Result<Self> {
+        log::info!("exec: starting `{command}`");
+        Ok(Self {
+            command: command.to_string(),
+            child: Some(spawn_shell(command)?),
+            started: Instant::now(),
+            restarts: 0,
+            initial_backoff,
+        })
+    }
+
+    fn stdin(&mut self) -> io::Result<&mut ChildStdin> {
+        self.child
+            .as_mut()
+            .and_then(|child| child.stdin.as_mut())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+
+    fn restart(&mut self) -> io::Result<()> {
+        let status = self.child.take().and_then(reap);
+        let status = status.map_or("unknown status".to_string(), |status| status.to_string());
+
+        if self.started.elapsed() > STABLE_RUN {
+            self.restarts = 0;
+        }
+        self.restarts += 1;
+        if self.restarts > MAX_RESTARTS {
+            return Err(io::Error::other(format!(
+                "exec: `{}` keeps exiting ({status}), giving up after {MAX_RESTARTS} restarts",
+                self.command
+            )));
+        }
+
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << (self.restarts - 1))
+            .min(MAX_BACKOFF);
+        log::warn!(
+            "exec: `{}` exited ({status}), restarting in {backoff:?}",
+            self.command
+        );
+        thread::sleep(backoff);
+
+        self.child = Some(spawn_shell(&self.command)?);
+        self.started = Instant::now();
+        Ok(())
+    }
+}
+
+impl Write for ExecWriter {
+    // A child that dies mid-frame loses the rest of that frame; the new child
+    // picks up from the next write.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.stdin()?.write(buf) {
+                Ok(written) => return Ok(written),
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => self.restart()?,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stdin()?.flush() {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            result => result,
+        }
+    }
+}
+
+impl Drop for ExecWriter {
+    fn drop(&mut self) {
+        if let Some(status) = self.child.take().and_then(reap) {
+            log::debug!("exec: `{}` exited ({status})", self.command);
+        }
+    }
+}
+
+/// Run the command once with payload on its stdin and wait for it.
+/// Returns whether the command succeeded; failing to spawn is an error.
+pub fn run_once(command: &str, payload: &[u8]) -> io::Result<bool> {
+    let mut child = spawn_shell(command)?;
+
+    let written = match child.stdin.as_mut() {
+        Some(stdin) => stdin.write_all(payload),
+        None => Ok(()),
+    };
+    if let Err(e) = written
+        && e.kind() != io::ErrorKind::BrokenPipe
+    {
+        reap(child);
+        return Err(e);
+    }
+
+    Ok(reap(child).is_some_and(|status| status.success()))
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("mnc-exec-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_stream_to_child() {
+        let path = temp_path("stream");
+        let mut writer = ExecWriter::new(&format!("cat > {}", path.display())).expect("spawn");
+        writer.write_all(b"one\ntwo\n").expect("write");
+        drop(writer);
+
+        assert_eq!(std::fs::read(&path).expect("read"), b"one\ntwo\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_restart_after_child_exits() {
+        let path = temp_path("restart");
+        // Each child takes one line and exits
+        let mut writer = ExecWriter::with_backoff(
+            &format!("head -n 1 >> {}", path.display()),
+            Duration::from_millis(1),
+        )
+        .expect("spawn");
+
+        for line in [&b"a\n"[..], b"b\n", b"c\n"] {
+            writer.write_all(line).expect("write");
+            // Let the child exit so the next write sees the broken pipe
+            thread::sleep(Duration::from_millis(50));
+        }
+        drop(writer);
+
+        let written = std::fs::read_to_string(&path).expect("read");
+        assert!(written.starts_with("a\n"));
+        assert!(written.contains("c\n"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_persistent_failure() {
+        let mut writer =
+            ExecWriter::with_backoff("exit 3", Duration::from_millis(1)).expect("spawn");
+        thread::sleep(Duration::from_millis(50));
+
+        let result = (0..100).try_for_each(|_| {
+            writer.write_all(b"x")?;
+            thread::sleep(Duration::from_millis(5));
+            Ok::<(), io::Error>(())
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_run_once() {
+        assert!(run_once("grep -q hello", b"hello world").expect("run"));
+        assert!(!run_once("grep -q hello", b"goodbye").expect("run"));
+    }
+}
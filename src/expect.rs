@@ -0,0 +1,525 @@
+/// --expect: compares what arrives against a golden file, framed like an
+/// input file, one record per packet in order. The first packet that differs
+/// fails the run with where it differs; so do packets past the last record
+/// and records left over when the stream ends.
+///
+/// With resync, records whose SDDS or VITA-49 sequence number the stream has
+/// already passed count as missing rather than mismatched, so loss on the
+/// way doesn't fail every packet after it.
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::{
+    MAX_PACKET_BYTES,
+    error::{LibError, Result},
+    gaps,
+    packet::Packet,
+    pcap::{PcapInput, Record},
+    reader::{InputFraming, decode_payload, read_full, read_line_capped, read_timed_header},
+    reorder::SeqSource,
+    resync::FrameScanner,
+    sink::Sink,
+    vita49::VrlpSync,
+};
+
+// Bytes shown either side of the first difference
+const DIFF_BEFORE: usize = 4;
+const DIFF_BYTES: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct ExpectConfig {
+    pub path: String,
+    pub framing: InputFraming,
+    /// Skip records the stream's sequence numbers say were lost
+    pub resync: Option<SeqSource>,
+}
+
+/// How the comparison went, shared with the summary.
+#[derive(Debug, Default)]
+pub struct ExpectCounters {
+    matched: AtomicU64,
+    mismatched: AtomicU64,
+    missing: AtomicU64,
+    extra: AtomicU64,
+    failed: AtomicBool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpectTotals {
+    pub matched: u64,
+    pub mismatched: u64,
+    pub missing: u64,
+    pub extra: u64,
+}
+
+impl ExpectCounters {
+    pub fn get(&self) -> ExpectTotals {
+        ExpectTotals {
+            matched: self.matched.load(Ordering::Relaxed),
+            mismatched: self.mismatched.load(Ordering::Relaxed),
+            missing: self.missing.load(Ordering::Relaxed),
+            extra: self.extra.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether the stream has already failed to match.
+    pub fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// The records of a file, framed like input.
+struct Records<R> {
+    reader: R,
+    framing: InputFraming,
+    line_number: u64,
+    json: String,
+    frames: FrameScanner<VrlpSync>,
+    // Opened on the first record
+    pcap: Option<PcapInput>,
+}
+
+impl<R: BufRead> Records<R> {
+    fn new(reader: R, framing: InputFraming) -> Self {
+        Self {
+            reader,
+            framing,
+            line_number: 0,
+            json: String::new(),
+            frames: FrameScanner::new(VrlpSync),
+            pcap: None,
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<Vec<u8>>> {
+        match self.framing {
+            InputFraming::Lines(limit) => {
+                let mut line = Vec::new();
+                let read = read_line_capped(&mut self.reader, &mut line, limit)?;
+                Ok((read > 0).then_some(line))
+            }
+            InputFraming::LengthPrefixed => {
+                let mut length = [0u8; 4];
+                // Markers --mark-gaps wrote where packets were missing aren't packets
+                loop {
+                    match self.reader.read_exact(&mut length) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                        Err(e) => return Err(e.into()),
+                    }
+                    if gaps::marker_missing(u32::from_le_bytes(length)).is_none() {
+                        break;
+                    }
+                }
+                let length = u32::from_le_bytes(length) as usize;
+                if length > MAX_PACKET_BYTES {
+                    return Err(LibError::Critical(format!(
+                        "Packet too large: {length} bytes"
+                    )));
+                }
+                let mut record = vec![0; length];
+                self.reader.read_exact(&mut record)?;
+                Ok(Some(record))
+            }
+            InputFraming::Timed => match read_timed_header(&mut self.reader)? {
+                Some((_, length)) => {
+                    let mut record = vec![0; length];
+                    self.reader.read_exact(&mut record)?;
+                    Ok(Some(record))
+                }
+                None => Ok(None),
+            },
+            InputFraming::Chunks(chunk) => {
+                let mut record = vec![0; chunk];
+                let length = read_full(&mut self.reader, &mut record)?;
+                record.truncate(length);
+                Ok((length > 0).then_some(record))
+            }
+            InputFraming::Vrlp => Ok(self
+                .frames
+                .next_frame(&mut self.reader)?
+                .map(<[u8]>::to_vec)),
+            InputFraming::JsonLines => loop {
+                self.json.clear();
+                let read = self.reader.read_line(&mut self.json)?;
+                self.line_number += 1;
+                if read == 0 {
+                    return Ok(None);
+                }
+                // Blank lines carry no record
+                if !self.json.trim().is_empty() {
+                    return Ok(Some(decode_payload(&self.json, self.line_number)?.1));
+                }
+            },
+            // Records without a UDP datagram weren't packets
+            InputFraming::Pcap => {
+                let input = match &mut self.pcap {
+                    Some(input) => input,
+                    None => self.pcap.insert(PcapInput::open(&mut self.reader)?),
+                };
+                while let Some((_, record)) = input.next_record(&mut self.reader)? {
+                    if let Record::Udp(payload) = record {
+                        return Ok(Some(payload.to_vec()));
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// The first byte where expected and got differ, the shorter one's length
+/// if one is a prefix of the other, None if they are the same.
+fn first_difference(expected: &[u8], got: &[u8]) -> Option<usize> {
+    let common = expected
+        .iter()
+        .zip(got)
+        .position(|(expected, got)| expected != got);
+    match common {
+        Some(offset) => Some(offset),
+        None if expected.len() == got.len() => None,
+        None => Some(expected.len().min(got.len())),
+    }
+}
+
+fn hex_window(data: &[u8], start: usize) -> String {
+    let window = data
+        .get(start..)
+        .unwrap_or_default()
+        .iter()
+        .take(DIFF_BYTES);
+    let mut hex = String::new();
+    for byte in window {
+        if !hex.is_empty() {
+            hex.push(' ');
+        }
+        let _ = write!(hex, "{byte:02x}");
+    }
+    if hex.is_empty() { "(end)".into() } else { hex }
+}
+
+/// A few bytes of each from just before offset, like
+/// "at byte 6 (from 2): expected 03 04 05 06, got 03 04 ff 06".
+fn hex_diff(expected: &[u8], got: &[u8], offset: usize) -> String {
+    let start = offset.saturating_sub(DIFF_BEFORE);
+    let mut diff = format!(
+        "at byte {offset} (from {start}): expected {}, got {}",
+        hex_window(expected, start),
+        hex_window(got, start)
+    );
+    if expected.len() != got.len() {
+        let _ = write!(
+            diff,
+            "; expected {} bytes, got {}",
+            expected.len(),
+            got.len()
+        );
+    }
+    diff
+}
+
+pub struct ExpectSink<R = BufReader<File>> {
+    name: String,
+    records: Records<R>,
+    // How many records and packets have been gone through
+    record_index: u64,
+    packet_index: u64,
+    resync: Option<SeqSource>,
+    counters: Arc<ExpectCounters>,
+}
+
+impl ExpectSink {
+    pub fn open(config: &ExpectConfig, counters: Arc<ExpectCounters>) -> Result<Self> {
+        let file = File::open(&config.path)
+            .map_err(|e| LibError::Critical(format!("Failed to open {}: {e}", config.path)))?;
+        Ok(Self::new(
+            format!("expect {}", config.path),
+            BufReader::new(file),
+            config,
+            counters,
+        ))
+    }
+}
+
+impl<R: BufRead> ExpectSink<R> {
+    fn new(name: String, reader: R, config: &ExpectConfig, counters: Arc<ExpectCounters>) -> Self {
+        Self {
+            name,
+            records: Records::new(reader, config.framing),
+            record_index: 0,
+            packet_index: 0,
+            resync: config.resync,
+            counters,
+        }
+    }
+
+    fn is_text(&self) -> bool {
+        matches!(self.records.framing, InputFraming::Lines(_))
+    }
+
+    /// Lines are compared without their newline, which the wire may not have.
+    fn comparable<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        match data.strip_suffix(b"\n") {
+            Some(line) if self.is_text() => line,
+            _ => data,
+        }
+    }
+
+    /// The record to compare packet with, past any the stream has skipped.
+    fn record_for(&mut self, packet: &[u8]) -> Result<Option<Vec<u8>>> {
+        let seq = self
+            .resync
+            .zip(self.resync.and_then(|source| source.read_frame(packet)));
+        loop {
+            let Some(record) = self.records.next()? else {
+                return Ok(None);
+            };
+            self.record_index += 1;
+            let Some((source, seq)) = seq else {
+                return Ok(Some(record));
+            };
+            let Some(expected) = source.read_frame(&record) else {
+                return Ok(Some(record));
+            };
+            let ahead = source.ahead(expected, seq);
+            if ahead == 0 || source.is_behind(ahead) {
+                return Ok(Some(record));
+            }
+            log::debug!(
+                "record {} (sequence {expected}) never arrived",
+                self.record_index
+            );
+            self.counters.missing.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn fail(&self, message: String) -> LibError {
+        self.counters.failed.store(true, Ordering::Relaxed);
+        LibError::ExpectFailed(message)
+    }
+}
+
+impl<R: BufRead + Send> Sink for ExpectSink<R> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn write_packets(&mut self, packets: &[Packet]) -> Result<()> {
+        for packet in packets {
+            self.packet_index += 1;
+            let Some(record) = self.record_for(packet)? else {
+                self.counters.extra.fetch_add(1, Ordering::Relaxed);
+                return Err(self.fail(format!(
+                    "packet {} arrived after the last of {} records",
+                    self.packet_index, self.record_index
+                )));
+            };
+            let (expected, got) = (self.comparable(&record), self.comparable(packet));
+            match first_difference(expected, got) {
+                None => {
+                    self.counters.matched.fetch_add(1, Ordering::Relaxed);
+                }
+                Some(offset) => {
+                    self.counters.mismatched.fetch_add(1, Ordering::Relaxed);
+                    return Err(self.fail(format!(
+                        "packet {} differs from record {} {}",
+                        self.packet_index,
+                        self.record_index,
+                        hex_diff(expected, got, offset)
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whatever is left of the file never arrived.
+    fn flush(&mut self) -> Result<()> {
+        let first = self.record_index + 1;
+        let mut missing = 0;
+        while self.records.next()?.is_some() {
+            self.record_index += 1;
+            missing += 1;
+        }
+        if missing == 0 {
+            return Ok(());
+        }
+        self.counters.missing.fetch_add(missing, Ordering::Relaxed);
+        Err(self.fail(format!(
+            "{missing} records never arrived, from record {first}"
+        )))
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{reader::LineLimit, sdds::HEADER_BYTES};
+
+    fn sink(
+        file: &[u8],
+        framing: InputFraming,
+        resync: Option<SeqSource>,
+    ) -> ExpectSink<Cursor<Vec<u8>>> {
+        let config = ExpectConfig {
+            path: "golden".into(),
+            framing,
+            resync,
+        };
+        ExpectSink::new(
+            "expect golden".into(),
+            Cursor::new(file.to_vec()),
+            &config,
+            Arc::new(ExpectCounters::default()),
+        )
+    }
+
+    fn packet(data: &[u8]) -> Packet {
+        let mut packet = Packet::with_capacity(data.len());
+        packet.data_mut()[..data.len()].copy_from_slice(data);
+        packet.set_length(data.len());
+        packet
+    }
+
+    fn length_prefixed(records: &[&[u8]]) -> Vec<u8> {
+        let mut file = Vec::new();
+        for record in records {
+            file.extend_from_slice(&(record.len() as u32).to_le_bytes());
+            file.extend_from_slice(record);
+        }
+        file
+    }
+
+    fn sdds(seq: u16, fill: u8) -> Vec<u8> {
+        let mut frame = vec![fill; HEADER_BYTES + 8];
+        frame[2..4].copy_from_slice(&seq.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_first_difference() {
+        assert_eq!(first_difference(b"abc", b"abc"), None);
+        assert_eq!(first_difference(b"abc", b"abd"), Some(2));
+        assert_eq!(first_difference(b"abc", b"ab"), Some(2));
+        assert_eq!(first_difference(b"", b"a"), Some(0));
+    }
+
+    #[test]
+    fn test_hex_diff() {
+        let expected = [1, 2, 3, 4, 5, 6, 7, 8];
+        let got = [1, 2, 3, 4, 5, 6, 0xff, 8];
+        assert_eq!(
+            hex_diff(&expected, &got, 6),
+            "at byte 6 (from 2): expected 03 04 05 06 07 08, got 03 04 05 06 ff 08"
+        );
+        assert_eq!(
+            hex_diff(&expected[..2], &expected, 2),
+            "at byte 2 (from 0): expected 01 02, got 01 02 03 04 05 06 07 08; \
+             expected 2 bytes, got 8"
+        );
+    }
+
+    #[test]
+    fn test_matching_stream() {
+        let file = length_prefixed(&[b"one", b"two"]);
+        let mut sink = sink(&file, InputFraming::LengthPrefixed, None);
+        assert!(sink.write_packets(&[packet(b"one")]).is_ok());
+        assert!(sink.write_packets(&[packet(b"two")]).is_ok());
+        assert!(sink.flush().is_ok());
+        let totals = sink.counters.get();
+        assert_eq!(totals.matched, 2);
+        assert!(!sink.counters.failed());
+    }
+
+    #[test]
+    fn test_mismatch_reports_where() {
+        let file = length_prefixed(&[b"one", b"two"]);
+        let mut sink = sink(&file, InputFraming::LengthPrefixed, None);
+        let result = sink.write_packets(&[packet(b"one"), packet(b"twx")]);
+        assert!(
+            matches!(&result, Err(LibError::ExpectFailed(message))
+                if message.starts_with("packet 2 differs from record 2 at byte 2")),
+            "{result:?}"
+        );
+        assert_eq!(sink.counters.get().mismatched, 1);
+        assert!(sink.counters.failed());
+    }
+
+    #[test]
+    fn test_extra_and_missing() {
+        let file = length_prefixed(&[b"one"]);
+        let mut extra = sink(&file, InputFraming::LengthPrefixed, None);
+        assert!(
+            extra
+                .write_packets(&[packet(b"one"), packet(b"two")])
+                .is_err()
+        );
+        assert_eq!(extra.counters.get().extra, 1);
+
+        let file = length_prefixed(&[b"one", b"two", b"three"]);
+        let mut missing = sink(&file, InputFraming::LengthPrefixed, None);
+        assert!(missing.write_packets(&[packet(b"one")]).is_ok());
+        assert!(missing.flush().is_err());
+        assert_eq!(missing.counters.get().missing, 2);
+    }
+
+    #[test]
+    fn test_lines_ignore_newline() {
+        let mut sink = sink(
+            b"hello\nworld\n",
+            InputFraming::Lines(LineLimit::default()),
+            None,
+        );
+        assert!(
+            sink.write_packets(&[packet(b"hello"), packet(b"world\n")])
+                .is_ok()
+        );
+        assert!(sink.flush().is_ok());
+    }
+
+    #[test]
+    fn test_resync_skips_lost_records() {
+        let records: Vec<Vec<u8>> = (0..4).map(|seq| sdds(seq, 7)).collect();
+        let records: Vec<&[u8]> = records.iter().map(Vec::as_slice).collect();
+        let file = length_prefixed(&records);
+
+        let mut strict = sink(&file, InputFraming::LengthPrefixed, None);
+        assert!(
+            strict
+                .write_packets(&[packet(records[0]), packet(records[2])])
+                .is_err()
+        );
+
+        let mut resync = sink(&file, InputFraming::LengthPrefixed, Some(SeqSource::Sdds));
+        assert!(
+            resync
+                .write_packets(&[packet(records[0]), packet(records[2])])
+                .is_ok()
+        );
+        assert!(resync.write_packets(&[packet(records[3])]).is_ok());
+        assert!(resync.flush().is_ok());
+        let totals = resync.counters.get();
+        assert_eq!((totals.matched, totals.missing), (3, 1));
+
+        // Same number, different samples, still fails
+        let mut resync = sink(&file, InputFraming::LengthPrefixed, Some(SeqSource::Sdds));
+        assert!(resync.write_packets(&[packet(&sdds(1, 8))]).is_err());
+    }
+
+    #[test]
+    fn test_jsonl_records() {
+        let file = b"{\"payload_b64\":\"aGVsbG8=\"}\n\n{\"payload_b64\":\"d29ybGQ=\"}\n";
+        let mut sink = sink(file, InputFraming::JsonLines, None);
+        assert!(
+            sink.write_packets(&[packet(b"hello"), packet(b"world")])
+                .is_ok()
+        );
+        assert!(sink.flush().is_ok());
+    }
+}
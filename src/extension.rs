@@ -0,0 +1,345 @@
+/// File layouts told apart by extension, one table for -o and -i alike, so
+/// `-o capture.jsonl` writes what `-i capture.jsonl` reads. --output-format
+/// and --input-format always win over it. Extensions for layouts mnc has
+/// no writer or reader for are known too, so the warning can say so rather
+/// than call them unknown.
+use std::fmt::{self, Display};
+use std::path::Path;
+
+use crate::{packet::PacketType, reader::InputFormat, sink::OutputFormat};
+
+/// What an extension says a file holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Text,
+    Binary,
+    Raw,
+    Jsonl,
+    Vrlp,
+    Sigmf,
+    Csv,
+    Pcap,
+    Pcapng,
+}
+
+const EXTENSIONS: &[(&str, FileKind)] = &[
+    ("txt", FileKind::Text),
+    ("log", FileKind::Text),
+    ("bin", FileKind::Binary),
+    ("raw", FileKind::Raw),
+    ("jsonl", FileKind::Jsonl),
+    ("ndjson", FileKind::Jsonl),
+    ("vrlp", FileKind::Vrlp),
+    ("sigmf", FileKind::Sigmf),
+    ("sigmf-data", FileKind::Sigmf),
+    ("sigmf-meta", FileKind::Sigmf),
+    ("csv", FileKind::Csv),
+    ("pcap", FileKind::Pcap),
+    ("pcapng", FileKind::Pcapng),
+];
+
+impl Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FileKind::Text => "text",
+            FileKind::Binary => "binary",
+            FileKind::Raw => "raw",
+            FileKind::Jsonl => "JSON lines",
+            FileKind::Vrlp => "VRLP",
+            FileKind::Sigmf => "SigMF",
+            FileKind::Csv => "CSV",
+            FileKind::Pcap => "pcap",
+            FileKind::Pcapng => "pcapng",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What a file name's extension says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Detected {
+    /// No extension, or not a file: stdout, stdin, /dev/null
+    Nothing,
+    Kind(FileKind),
+    /// A kind of file gzipped, like capture.jsonl.gz; the kind is None when
+    /// .gz is all there is
+    Gzip(Option<FileKind>),
+    /// An extension not in the table, without its dot
+    Unknown(String),
+}
+
+/// Look up the extension of path, ignoring case.
+pub fn detect(path: &str) -> Detected {
+    if path == "-" {
+        return Detected::Nothing;
+    }
+    let extension = |path: &Path| {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase)
+    };
+    let kind = |extension: &str| {
+        EXTENSIONS
+            .iter()
+            .find(|(name, _)| *name == extension)
+            .map(|(_, kind)| *kind)
+    };
+    let path = Path::new(path);
+    match extension(path).as_deref() {
+        None => Detected::Nothing,
+        Some("gz") => Detected::Gzip(
+            path.file_stem()
+                .and_then(|stem| extension(Path::new(stem)))
+                .and_then(|inner| kind(&inner)),
+        ),
+        Some(other) => match kind(other) {
+            Some(kind) => Detected::Kind(kind),
+            None => Detected::Unknown(other.to_string()),
+        },
+    }
+}
+
+/// A layout for a file, and what chose it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chosen<F> {
+    /// --output-format or --input-format, whatever the extension says
+    Given(F),
+    FromExtension(F),
+    /// The packet type's, with why when the extension named another
+    Default(Option<String>),
+}
+
+impl<F: Copy> Chosen<F> {
+    /// The layout, None for the packet type's.
+    pub fn format(&self) -> Option<F> {
+        match self {
+            Chosen::Given(format) | Chosen::FromExtension(format) => Some(*format),
+            Chosen::Default(_) => None,
+        }
+    }
+}
+
+/// The layout to write output in: given, or what its extension says.
+pub fn output_format(
+    output: &str,
+    given: Option<OutputFormat>,
+    packet_type: PacketType,
+) -> Chosen<OutputFormat> {
+    if let Some(format) = given {
+        return Chosen::Given(format);
+    }
+    let kind = match detect(output) {
+        Detected::Nothing => return Chosen::Default(None),
+        Detected::Kind(kind) => kind,
+        Detected::Gzip(_) => {
+            return Chosen::Default(Some(format!(
+                "mnc doesn't compress its output, --exec 'gzip > {output}' does"
+            )));
+        }
+        Detected::Unknown(extension) => {
+            return Chosen::Default(Some(format!(".{extension} isn't an extension mnc knows")));
+        }
+    };
+    let samples = matches!(packet_type, PacketType::Sdds | PacketType::Vita49);
+    match kind {
+        FileKind::Text => Chosen::FromExtension(OutputFormat::Text),
+        FileKind::Binary => Chosen::FromExtension(OutputFormat::Binary),
+        FileKind::Raw => Chosen::FromExtension(OutputFormat::Raw),
+        FileKind::Jsonl => Chosen::FromExtension(OutputFormat::Jsonl),
+        // Raw VITA-49 packets are VRLP frames back to back
+        FileKind::Vrlp if packet_type == PacketType::Vita49 => {
+            Chosen::FromExtension(OutputFormat::Raw)
+        }
+        FileKind::Vrlp => Chosen::Default(Some("VRLP files hold -t vita49 packets".to_string())),
+        FileKind::Sigmf if samples => Chosen::FromExtension(OutputFormat::Sigmf),
+        FileKind::Sigmf => Chosen::Default(Some(
+            "SigMF recordings are only made of -t sdds and -t vita49".to_string(),
+        )),
+        FileKind::Pcap => Chosen::FromExtension(OutputFormat::Pcap),
+        FileKind::Csv | FileKind::Pcapng => {
+            Chosen::Default(Some(format!("mnc doesn't write {kind} files")))
+        }
+    }
+}
+
+/// The layout to read input in: given, or what its extension says. Raw
+/// records need a size, so .raw only chooses raw along with --chunk.
+pub fn input_format(
+    input: &str,
+    given: Option<InputFormat>,
+    chunk: Option<usize>,
+) -> Chosen<InputFormat> {
+    if let Some(format) = given {
+        return Chosen::Given(format);
+    }
+    let kind = match detect(input) {
+        Detected::Nothing => return Chosen::Default(None),
+        Detected::Kind(kind) => kind,
+        Detected::Gzip(_) => {
+            return Chosen::Default(Some(format!(
+                "mnc doesn't read compressed files, zcat {input} | mnc -i - does"
+            )));
+        }
+        Detected::Unknown(extension) => {
+            return Chosen::Default(Some(format!(".{extension} isn't an extension mnc knows")));
+        }
+    };
+    match kind {
+        FileKind::Text => Chosen::FromExtension(InputFormat::Text),
+        FileKind::Binary => Chosen::FromExtension(InputFormat::Binary),
+        FileKind::Raw if chunk.is_some() => Chosen::FromExtension(InputFormat::Raw),
+        FileKind::Raw => Chosen::Default(Some("raw records need --chunk".to_string())),
+        FileKind::Jsonl => Chosen::FromExtension(InputFormat::Jsonl),
+        FileKind::Vrlp => Chosen::FromExtension(InputFormat::Vrlp),
+        FileKind::Pcap => Chosen::FromExtension(InputFormat::Pcap),
+        FileKind::Sigmf | FileKind::Csv | FileKind::Pcapng => {
+            Chosen::Default(Some(format!("mnc doesn't read {kind} files")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect("capture.txt"), Detected::Kind(FileKind::Text));
+        assert_eq!(detect("mnc.log"), Detected::Kind(FileKind::Text));
+        assert_eq!(detect("capture.bin"), Detected::Kind(FileKind::Binary));
+        assert_eq!(detect("capture.raw"), Detected::Kind(FileKind::Raw));
+        assert_eq!(detect("capture.jsonl"), Detected::Kind(FileKind::Jsonl));
+        assert_eq!(detect("capture.ndjson"), Detected::Kind(FileKind::Jsonl));
+        assert_eq!(detect("./pass.VRLP"), Detected::Kind(FileKind::Vrlp));
+        assert_eq!(detect("pass.sigmf"), Detected::Kind(FileKind::Sigmf));
+        assert_eq!(detect("pass.sigmf-data"), Detected::Kind(FileKind::Sigmf));
+        assert_eq!(detect("pass.sigmf-meta"), Detected::Kind(FileKind::Sigmf));
+        assert_eq!(detect("capture.csv"), Detected::Kind(FileKind::Csv));
+        assert_eq!(detect("capture.pcap"), Detected::Kind(FileKind::Pcap));
+        assert_eq!(detect("capture.pcapng"), Detected::Kind(FileKind::Pcapng));
+        assert_eq!(
+            detect("capture.jsonl.gz"),
+            Detected::Gzip(Some(FileKind::Jsonl))
+        );
+        assert_eq!(
+            detect("capture.pcap.GZ"),
+            Detected::Gzip(Some(FileKind::Pcap))
+        );
+        assert_eq!(detect("capture.gz"), Detected::Gzip(None));
+        assert_eq!(detect("capture.dat"), Detected::Unknown("dat".to_string()));
+        assert_eq!(detect("capture"), Detected::Nothing);
+        assert_eq!(detect("/dev/null"), Detected::Nothing);
+        assert_eq!(detect("./runs.d/capture"), Detected::Nothing);
+        assert_eq!(detect("-"), Detected::Nothing);
+    }
+
+    #[test]
+    fn test_output_format() {
+        let chosen = |output| output_format(output, None, PacketType::Sdds);
+        assert_eq!(
+            chosen("capture.txt"),
+            Chosen::FromExtension(OutputFormat::Text)
+        );
+        assert_eq!(
+            chosen("capture.bin"),
+            Chosen::FromExtension(OutputFormat::Binary)
+        );
+        assert_eq!(
+            chosen("capture.raw"),
+            Chosen::FromExtension(OutputFormat::Raw)
+        );
+        assert_eq!(
+            chosen("capture.jsonl"),
+            Chosen::FromExtension(OutputFormat::Jsonl)
+        );
+        assert_eq!(
+            chosen("pass.sigmf-data"),
+            Chosen::FromExtension(OutputFormat::Sigmf)
+        );
+        assert_eq!(chosen("capture"), Chosen::Default(None));
+        assert_eq!(chosen("-"), Chosen::Default(None));
+        assert_eq!(
+            chosen("capture.pcap"),
+            Chosen::FromExtension(OutputFormat::Pcap)
+        );
+        for output in [
+            "capture.pcapng",
+            "capture.csv",
+            "capture.vrlp",
+            "capture.jsonl.gz",
+            "capture.dat",
+        ] {
+            assert!(
+                matches!(chosen(output), Chosen::Default(Some(_))),
+                "{output}"
+            );
+        }
+        assert_eq!(
+            output_format("capture.vrlp", None, PacketType::Vita49),
+            Chosen::FromExtension(OutputFormat::Raw)
+        );
+        assert!(matches!(
+            output_format("pass.sigmf", None, PacketType::Text),
+            Chosen::Default(Some(_))
+        ));
+
+        // --output-format wins
+        assert_eq!(
+            output_format(
+                "capture.jsonl",
+                Some(OutputFormat::Binary),
+                PacketType::Sdds
+            ),
+            Chosen::Given(OutputFormat::Binary)
+        );
+        assert_eq!(
+            output_format("capture.pcap", Some(OutputFormat::Raw), PacketType::Sdds),
+            Chosen::Given(OutputFormat::Raw)
+        );
+    }
+
+    #[test]
+    fn test_input_format() {
+        assert_eq!(
+            input_format("capture.txt", None, None),
+            Chosen::FromExtension(InputFormat::Text)
+        );
+        assert_eq!(
+            input_format("capture.bin", None, None),
+            Chosen::FromExtension(InputFormat::Binary)
+        );
+        assert_eq!(
+            input_format("capture.ndjson", None, None),
+            Chosen::FromExtension(InputFormat::Jsonl)
+        );
+        assert_eq!(
+            input_format("pass.vrlp", None, None),
+            Chosen::FromExtension(InputFormat::Vrlp)
+        );
+        assert_eq!(
+            input_format("capture.raw", None, Some(1024)),
+            Chosen::FromExtension(InputFormat::Raw)
+        );
+        assert!(matches!(
+            input_format("capture.raw", None, None),
+            Chosen::Default(Some(_))
+        ));
+        assert!(matches!(
+            input_format("capture.jsonl.gz", None, None),
+            Chosen::Default(Some(_))
+        ));
+        assert_eq!(
+            input_format("capture.pcap", None, None),
+            Chosen::FromExtension(InputFormat::Pcap)
+        );
+        assert!(matches!(
+            input_format("capture.pcapng", None, None),
+            Chosen::Default(Some(_))
+        ));
+        assert_eq!(input_format("-", None, None), Chosen::Default(None));
+        assert_eq!(
+            input_format("capture.jsonl", Some(InputFormat::Text), None),
+            Chosen::Given(InputFormat::Text)
+        );
+    }
+}
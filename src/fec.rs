@@ -0,0 +1,645 @@
+/// XOR forward error correction for any payload, the idea behind SDDS's
+/// parity packets. The sender (`--fec K`) puts a small header on every packet
+/// and after each K of them sends a parity packet, the XOR of their payloads
+/// padded to the longest. The receiver (`--fec-decode K`) takes the headers
+/// off again, drops the parity packets, and rebuilds a packet when exactly
+/// one of its group went missing. A group that arrives whole is checked
+/// against its parity.
+///
+/// Header, 8 bytes, integers big endian:
+///
+/// | bytes | field |
+/// |-------|-------|
+/// | 0..2  | magic 0xFE 0xC0 |
+/// | 2     | K |
+/// | 3     | index in the group, or 0x80 plus how many packets a parity packet covers |
+/// | 4..6  | group number, wrapping |
+/// | 6..8  | payload length; for parity, the XOR of the lengths it covers |
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use crate::packet::{Packet, Packets};
+
+pub const HEADER_BYTES: usize = 8;
+
+const MAGIC: [u8; 2] = [0xFE, 0xC0];
+
+const PARITY_FLAG: u8 = 0x80;
+
+/// The largest K, so an index never reaches the parity flag.
+pub const MAX_GROUP: u8 = 127;
+
+// Groups still open for late packets on the receive side. Older ones are
+// settled: rebuilt, checked, or counted as unrecoverable.
+const OPEN_GROUPS: u16 = 4;
+
+/// Data and parity packets, and what the receiver made of them.
+#[derive(Debug, Default)]
+pub struct FecCounters {
+    data: AtomicU64,
+    parity: AtomicU64,
+    recovered: AtomicU64,
+    unrecoverable: AtomicU64,
+    corrupt: AtomicU64,
+}
+
+/// A snapshot of [`FecCounters`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FecTotals {
+    pub data: u64,
+    pub parity: u64,
+    /// Rebuilt from the rest of their group
+    pub recovered: u64,
+    /// Missing from a group that lost more than one, or its parity
+    pub unrecoverable: u64,
+    /// Groups that didn't add up to their parity, or headers that didn't fit
+    pub corrupt: u64,
+}
+
+impl FecCounters {
+    pub fn get(&self) -> FecTotals {
+        FecTotals {
+            data: self.data.load(Ordering::Relaxed),
+            parity: self.parity.load(Ordering::Relaxed),
+            recovered: self.recovered.load(Ordering::Relaxed),
+            unrecoverable: self.unrecoverable.load(Ordering::Relaxed),
+            corrupt: self.corrupt.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Counted by the sender as it frames them.
+    pub fn add_sent(&self, data: u64, parity: u64) {
+        Self::add(&self.data, data);
+        Self::add(&self.parity, parity);
+    }
+
+    fn add(counter: &AtomicU64, count: u64) {
+        counter.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+impl FecTotals {
+    /// What was counted after earlier.
+    pub fn since(&self, earlier: &FecTotals) -> FecTotals {
+        FecTotals {
+            data: self.data - earlier.data,
+            parity: self.parity - earlier.parity,
+            recovered: self.recovered - earlier.recovered,
+            unrecoverable: self.unrecoverable - earlier.unrecoverable,
+            corrupt: self.corrupt - earlier.corrupt,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Header {
+    k: u8,
+    parity: bool,
+    // Index of a data packet, or how many a parity packet covers
+    index: u8,
+    group: u16,
+    length: u16,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let header: [u8; HEADER_BYTES] = data.get(..HEADER_BYTES)?.try_into().ok()?;
+        let [m0, m1, k, index, g0, g1, l0, l1] = header;
+        if [m0, m1] != MAGIC {
+            return None;
+        }
+        Some(Self {
+            k,
+            parity: index & PARITY_FLAG != 0,
+            index: index & !PARITY_FLAG,
+            group: u16::from_be_bytes([g0, g1]),
+            length: u16::from_be_bytes([l0, l1]),
+        })
+    }
+
+    fn write(&self, data: &mut [u8]) {
+        let index = if self.parity {
+            self.index | PARITY_FLAG
+        } else {
+            self.index
+        };
+        let [g0, g1] = self.group.to_be_bytes();
+        let [l0, l1] = self.length.to_be_bytes();
+        if let Some(header) = data.get_mut(..HEADER_BYTES) {
+            header.copy_from_slice(&[MAGIC[0], MAGIC[1], self.k, index, g0, g1, l0, l1]);
+        }
+    }
+}
+
+fn wire_length(length: usize) -> u16 {
+    u16::try_from(length).unwrap_or(u16::MAX)
+}
+
+/// XOR data into acc, growing acc with zeros to fit it.
+fn xor_into(acc: &mut Vec<u8>, data: &[u8]) {
+    if acc.len() < data.len() {
+        acc.resize(data.len(), 0);
+    }
+    for (acc, byte) in acc.iter_mut().zip(data) {
+        *acc ^= byte;
+    }
+}
+
+/// Write header and payload into packet.
+fn frame_into(packet: &mut Packet, header: Header, payload: &[u8]) {
+    let length = HEADER_BYTES + payload.len();
+    packet.ensure_capacity(length);
+    packet.set_length(length);
+    let data = packet.data_mut();
+    header.write(data);
+    if let Some(rest) = data.get_mut(HEADER_BYTES..length) {
+        rest.copy_from_slice(payload);
+    }
+}
+
+/// Frames one destination's packets into groups of k and makes their parity.
+#[derive(Debug)]
+pub struct FecEncoder {
+    k: u8,
+    group: u16,
+    // Data packets in the current group so far
+    count: u8,
+    parity: Vec<u8>,
+    length_xor: u16,
+}
+
+impl FecEncoder {
+    pub fn new(k: u8) -> Self {
+        Self {
+            k: k.clamp(1, MAX_GROUP),
+            group: 0,
+            count: 0,
+            parity: Vec::new(),
+            length_xor: 0,
+        }
+    }
+
+    /// Frame payload into packet as the next data packet. True when that
+    /// completed a group, so its parity should follow.
+    pub fn encode(&mut self, packet: &mut Packet, payload: &[u8]) -> bool {
+        let length = wire_length(payload.len());
+        let header = Header {
+            k: self.k,
+            parity: false,
+            index: self.count,
+            group: self.group,
+            length,
+        };
+        frame_into(packet, header, payload);
+        xor_into(&mut self.parity, payload);
+        self.length_xor ^= length;
+        self.count += 1;
+        self.count >= self.k
+    }
+
+    /// Frame the parity of the group so far into packet and start the next
+    /// group. False, leaving packet alone, when the group is empty.
+    pub fn parity(&mut self, packet: &mut Packet) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+        let header = Header {
+            k: self.k,
+            parity: true,
+            index: self.count,
+            group: self.group,
+            length: self.length_xor,
+        };
+        frame_into(packet, header, &self.parity);
+        self.parity.clear();
+        self.length_xor = 0;
+        self.count = 0;
+        self.group = self.group.wrapping_add(1);
+        true
+    }
+}
+
+#[derive(Debug)]
+struct Group {
+    number: u16,
+    k: u8,
+    data: Vec<Option<Vec<u8>>>,
+    // The XOR of the lengths, how many it covers, and the payload
+    parity: Option<(u16, u8, Vec<u8>)>,
+    // Rebuilt or checked; late packets are duplicates
+    settled: bool,
+}
+
+/// Takes the FEC framing off received packets and rebuilds what it can.
+#[derive(Debug)]
+pub struct FecDecoder {
+    k: u8,
+    groups: Vec<Group>,
+    newest: Option<u16>,
+    recovered: Vec<Packet>,
+    spare: Vec<Vec<u8>>,
+    warned_k: bool,
+}
+
+impl FecDecoder {
+    pub fn new(k: u8) -> Self {
+        Self {
+            k,
+            groups: Vec::new(),
+            newest: None,
+            recovered: Vec::new(),
+            spare: Vec::new(),
+            warned_k: false,
+        }
+    }
+
+    /// Strip the headers off the batch's data packets and take out the
+    /// parity packets. Packets without a header are left as they are.
+    /// A packet rebuilt when its group's parity arrives takes the parity
+    /// packet's place; one rebuilt when a late data packet completes the
+    /// group waits in [`FecDecoder::take_recovered`].
+    pub fn decode(&mut self, packets: &mut Packets, counters: &FecCounters) {
+        let mut keep = Vec::with_capacity(packets.len());
+        for packet in packets.iter_mut() {
+            keep.push(self.absorb(packet, counters));
+        }
+        let mut keep = keep.into_iter();
+        packets.retain(|_| keep.next().unwrap_or(true));
+    }
+
+    /// Whether packet goes on, with its header taken off.
+    fn absorb(&mut self, packet: &mut Packet, counters: &FecCounters) -> bool {
+        let Some(header) = Header::parse(packet) else {
+            return true;
+        };
+        if header.parity {
+            FecCounters::add(&counters.parity, 1);
+        } else {
+            FecCounters::add(&counters.data, 1);
+        }
+
+        let payload = packet.get(HEADER_BYTES..).unwrap_or_default();
+        let fits = header.k == self.k
+            && if header.parity {
+                (1..=header.k).contains(&header.index)
+            } else {
+                header.index < header.k && usize::from(header.length) == payload.len()
+            };
+        if !fits {
+            if header.k != self.k && !self.warned_k {
+                self.warned_k = true;
+                log::warn!(
+                    "FEC groups of {} on the wire, expected {} (further mismatches are counted as corrupt)",
+                    header.k,
+                    self.k
+                );
+            }
+            FecCounters::add(&counters.corrupt, 1);
+            packet.remove_prefix(HEADER_BYTES);
+            return !header.parity;
+        }
+
+        let Some(slot) = self.group_for(header.group, counters) else {
+            // From a group already given up on
+            packet.remove_prefix(HEADER_BYTES);
+            return !header.parity;
+        };
+        let mut buffer = self.spare.pop().unwrap_or_default();
+        buffer.clear();
+        buffer.extend_from_slice(payload);
+        let group = match self.groups.get_mut(slot) {
+            Some(group) => group,
+            None => return !header.parity,
+        };
+
+        let keep = if header.parity {
+            if group.parity.is_none() {
+                group.parity = Some((header.length, header.index, buffer));
+            }
+            false
+        } else {
+            match group.data.get_mut(usize::from(header.index)) {
+                Some(entry @ None) => {
+                    *entry = Some(buffer);
+                    packet.remove_prefix(HEADER_BYTES);
+                    true
+                }
+                // Already here, or already rebuilt
+                _ => false,
+            }
+        };
+        let Some(rebuilt) = settle(group, counters)
+            .and_then(|index| group.data.get(index))
+            .and_then(Option::as_deref)
+        else {
+            return keep;
+        };
+        if header.parity {
+            // In the parity packet's place, right after the rest of its group
+            set_payload(packet, rebuilt);
+            true
+        } else {
+            let mut packet = Packet::with_capacity(rebuilt.len());
+            set_payload(&mut packet, rebuilt);
+            packet.set_timestamp(Some(SystemTime::now()));
+            self.recovered.push(packet);
+            keep
+        }
+    }
+
+    /// Index into groups of number, opening it and settling the groups it
+    /// pushes out of the window. None when number is older than the window.
+    fn group_for(&mut self, number: u16, counters: &FecCounters) -> Option<usize> {
+        if let Some(slot) = self.groups.iter().position(|group| group.number == number) {
+            return Some(slot);
+        }
+        let newest = *self.newest.get_or_insert(number);
+        let ahead = number.wrapping_sub(newest);
+        if ahead >= u16::MAX / 2 {
+            // Behind the newest: still open, or long gone
+            if newest.wrapping_sub(number) >= OPEN_GROUPS {
+                return None;
+            }
+        } else {
+            self.newest = Some(number);
+            let (open, closed): (Vec<Group>, Vec<Group>) = std::mem::take(&mut self.groups)
+                .into_iter()
+                .partition(|group| number.wrapping_sub(group.number) < OPEN_GROUPS);
+            self.groups = open;
+            for group in closed {
+                self.close(group, counters);
+            }
+        }
+        self.groups.push(Group {
+            number,
+            k: self.k,
+            data: vec![None; usize::from(self.k)],
+            parity: None,
+            settled: false,
+        });
+        Some(self.groups.len() - 1)
+    }
+
+    /// Count what a group never got back, and keep its buffers.
+    fn close(&mut self, group: Group, counters: &FecCounters) {
+        let expected = group
+            .parity
+            .as_ref()
+            .map_or(usize::from(group.k), |(_, covers, _)| usize::from(*covers));
+        let present = group.data.iter().take(expected).flatten().count();
+        if !group.settled && present < expected {
+            FecCounters::add(&counters.unrecoverable, (expected - present) as u64);
+        }
+        self.spare.extend(group.data.into_iter().flatten());
+        self.spare
+            .extend(group.parity.map(|(_, _, payload)| payload));
+    }
+
+    /// Packets rebuilt by late data packets since the last call, appended
+    /// to ready.
+    pub fn take_recovered(&mut self, ready: &mut Vec<Packet>) {
+        ready.append(&mut self.recovered);
+    }
+
+    /// Settle every group still open, at the end of the stream.
+    pub fn flush(&mut self, counters: &FecCounters) {
+        for group in std::mem::take(&mut self.groups) {
+            self.close(group, counters);
+        }
+    }
+}
+
+/// Rebuild the one missing packet of group, or check a whole one against
+/// its parity, once there is enough of it. The index of a rebuilt packet,
+/// whose payload is then in group.data.
+fn settle(group: &mut Group, counters: &FecCounters) -> Option<usize> {
+    if group.settled {
+        return None;
+    }
+    let (length_xor, covers, parity) = group.parity.as_ref()?;
+    let covered = group.data.get(..usize::from(*covers))?;
+    let missing: Vec<usize> = covered
+        .iter()
+        .enumerate()
+        .filter_map(|(index, data)| data.is_none().then_some(index))
+        .collect();
+
+    let mut payload = parity.clone();
+    let mut length = *length_xor;
+    for data in covered.iter().flatten() {
+        xor_into(&mut payload, data);
+        length ^= wire_length(data.len());
+    }
+
+    match missing.as_slice() {
+        [] => {
+            group.settled = true;
+            // The XOR of everything, parity included, is all zeros
+            if length != 0 || payload.iter().any(|byte| *byte != 0) {
+                log::debug!("FEC group {} doesn't match its parity", group.number);
+                FecCounters::add(&counters.corrupt, 1);
+            }
+            None
+        }
+        [index] => {
+            group.settled = true;
+            let length = usize::from(length);
+            if length > payload.len() {
+                FecCounters::add(&counters.corrupt, 1);
+                return None;
+            }
+            payload.truncate(length);
+            *group.data.get_mut(*index)? = Some(payload);
+            FecCounters::add(&counters.recovered, 1);
+            Some(*index)
+        }
+        _ => None,
+    }
+}
+
+/// Replace what packet holds with payload.
+fn set_payload(packet: &mut Packet, payload: &[u8]) {
+    packet.ensure_capacity(payload.len());
+    packet.set_length(payload.len());
+    if let Some(data) = packet.data_mut().get_mut(..payload.len()) {
+        data.copy_from_slice(payload);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    fn payload(n: u8, length: usize) -> Vec<u8> {
+        (0..length)
+            .map(|i| n.wrapping_mul(31).wrapping_add(i as u8))
+            .collect()
+    }
+
+    // The wire packets for payloads sent with groups of k
+    fn encode(k: u8, payloads: &[Vec<u8>], flush: bool) -> Vec<Vec<u8>> {
+        let mut encoder = FecEncoder::new(k);
+        let mut wire = Vec::new();
+        let mut packet = Packet::default();
+        for payload in payloads {
+            let full = encoder.encode(&mut packet, payload);
+            wire.push(packet.to_vec());
+            if full && encoder.parity(&mut packet) {
+                wire.push(packet.to_vec());
+            }
+        }
+        if flush && encoder.parity(&mut packet) {
+            wire.push(packet.to_vec());
+        }
+        wire
+    }
+
+    // What the decoder passes on for the wire packets, with packets rebuilt
+    // by late ones last
+    fn decode(k: u8, wire: &[Vec<u8>], counters: &FecCounters) -> Vec<Vec<u8>> {
+        let mut decoder = FecDecoder::new(k);
+        let mut packets = Packets::new(wire.len(), 0);
+        for (packet, data) in packets.iter_mut().zip(wire) {
+            packet.ensure_capacity(data.len());
+            packet.data_mut()[..data.len()].copy_from_slice(data);
+            packet.set_length(data.len());
+        }
+        decoder.decode(&mut packets, counters);
+        let mut out: Vec<Vec<u8>> = packets.iter().map(|packet| packet.to_vec()).collect();
+        let mut recovered = Vec::new();
+        decoder.take_recovered(&mut recovered);
+        decoder.flush(counters);
+        out.extend(recovered.iter().map(|packet| packet.to_vec()));
+        out
+    }
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = Header {
+            k: 8,
+            parity: true,
+            index: 5,
+            group: 0xBEEF,
+            length: 1234,
+        };
+        let mut data = [0u8; HEADER_BYTES];
+        header.write(&mut data);
+        assert_eq!(&data[..4], &[0xFE, 0xC0, 8, 0x85]);
+        assert_eq!(Header::parse(&data), Some(header));
+        assert_eq!(Header::parse(b"not a header"), None);
+    }
+
+    #[test]
+    fn test_parity_every_k() {
+        let payloads: Vec<Vec<u8>> = (0..7).map(|n| payload(n, 10)).collect();
+        let wire = encode(3, &payloads, true);
+        // 3 data + parity, 3 data + parity, 1 data + partial parity
+        assert_eq!(wire.len(), 10);
+        let parity: Vec<usize> = wire
+            .iter()
+            .enumerate()
+            .filter(|(_, packet)| Header::parse(packet).is_some_and(|header| header.parity))
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(parity, vec![3, 7, 9]);
+    }
+
+    #[test]
+    fn test_clean_stream_passes_through() {
+        let payloads: Vec<Vec<u8>> = (0..6).map(|n| payload(n, 5 + n as usize)).collect();
+        let counters = FecCounters::default();
+        let out = decode(3, &encode(3, &payloads, false), &counters);
+        assert_eq!(out, payloads);
+        let totals = counters.get();
+        assert_eq!((totals.data, totals.parity), (6, 2));
+        assert_eq!(
+            (totals.recovered, totals.unrecoverable, totals.corrupt),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_rebuilds_one_missing_per_group() {
+        // Lengths differ so the padding and length XOR matter
+        let payloads: Vec<Vec<u8>> = (0..8).map(|n| payload(n, 20 + 3 * n as usize)).collect();
+        let mut wire = encode(4, &payloads, false);
+        // Second packet of the first group, last of the second
+        wire.remove(8);
+        wire.remove(1);
+        let counters = FecCounters::default();
+        let out = decode(4, &wire, &counters);
+
+        // Each rebuilt in its group's parity packet's place
+        let mut expected = payloads.clone();
+        let first_second = expected.remove(1);
+        expected.insert(3, first_second);
+        assert_eq!(out, expected);
+        assert_eq!(counters.get().recovered, 2);
+    }
+
+    #[test]
+    fn test_late_packet_completes_group() {
+        let payloads: Vec<Vec<u8>> = (0..3).map(|n| payload(n, 9)).collect();
+        let mut wire = encode(3, &payloads, false);
+        // The second lost, the third arriving after the parity
+        let third = wire.remove(2);
+        wire.remove(1);
+        wire.push(third);
+        let counters = FecCounters::default();
+        let out = decode(3, &wire, &counters);
+        assert_eq!(
+            out,
+            vec![
+                payloads[0].clone(),
+                payloads[2].clone(),
+                payloads[1].clone()
+            ]
+        );
+        assert_eq!(counters.get().recovered, 1);
+    }
+
+    #[test]
+    fn test_two_missing_is_unrecoverable() {
+        let payloads: Vec<Vec<u8>> = (0..4).map(|n| payload(n, 16)).collect();
+        let mut wire = encode(4, &payloads, false);
+        wire.remove(2);
+        wire.remove(0);
+        let counters = FecCounters::default();
+        let out = decode(4, &wire, &counters);
+        assert_eq!(out.len(), 2);
+        let totals = counters.get();
+        assert_eq!((totals.recovered, totals.unrecoverable), (0, 2));
+    }
+
+    #[test]
+    fn test_partial_group_at_the_end() {
+        let payloads: Vec<Vec<u8>> = (0..5).map(|n| payload(n, 12)).collect();
+        let mut wire = encode(4, &payloads, true);
+        // The last data packet, covered by the short parity after it
+        wire.remove(5);
+        let counters = FecCounters::default();
+        let out = decode(4, &wire, &counters);
+        assert_eq!(out, payloads);
+        assert_eq!(counters.get().recovered, 1);
+    }
+
+    #[test]
+    fn test_corrupt_group_is_counted() {
+        let payloads: Vec<Vec<u8>> = (0..3).map(|n| payload(n, 12)).collect();
+        let mut wire = encode(3, &payloads, false);
+        wire[1][HEADER_BYTES + 2] ^= 0xFF;
+        let counters = FecCounters::default();
+        let out = decode(3, &wire, &counters);
+        assert_eq!(out.len(), 3);
+        assert_eq!(counters.get().corrupt, 1);
+    }
+
+    #[test]
+    fn test_unframed_packets_pass_through() {
+        let counters = FecCounters::default();
+        let out = decode(4, &[b"plain".to_vec()], &counters);
+        assert_eq!(out, vec![b"plain".to_vec()]);
+        assert_eq!(counters.get(), FecTotals::default());
+    }
+}
@@ -0,0 +1,193 @@
+/// Protocol-aware filters between the reader and the writer. A packet goes on
+/// to the outputs only if every filter keeps it; statistics has already seen
+/// and counted it either way. A packet a filter can't parse doesn't match,
+/// and is counted apart from the ones that were parsed and didn't match.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{packet::Packets, sdds, vita49};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketFilter {
+    /// --drop-parity: SDDS data packets only
+    DropParity,
+    /// --only-parity: SDDS parity packets only
+    OnlyParity,
+    /// --filter-stream-id: VITA-49 frames whose first VRT packet has one of these stream ids
+    StreamIds(Vec<u32>),
+    /// --filter-sdds-mode: SDDS packets in this data mode
+    SddsMode(u8),
+}
+
+/// What a filter made of a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Keep,
+    Drop,
+    /// Too short or malformed for the filter to tell
+    Unparsed,
+}
+
+impl PacketFilter {
+    pub fn check(&self, packet: &[u8]) -> Verdict {
+        let keep = match self {
+            PacketFilter::DropParity | PacketFilter::OnlyParity => {
+                if packet.len() < sdds::HEADER_BYTES {
+                    return Verdict::Unparsed;
+                }
+                let parity = sdds::is_parity(sdds::frame_sequence_number(packet));
+                parity == (*self == PacketFilter::OnlyParity)
+            }
+            PacketFilter::StreamIds(ids) => {
+                let Some(vrt) = vita49::parse_vrt(packet) else {
+                    return Verdict::Unparsed;
+                };
+                vrt.stream_id.is_some_and(|id| ids.contains(&id))
+            }
+            PacketFilter::SddsMode(mode) => {
+                if packet.len() < sdds::HEADER_BYTES {
+                    return Verdict::Unparsed;
+                }
+                sdds::data_mode(packet) == *mode
+            }
+        };
+        if keep { Verdict::Keep } else { Verdict::Drop }
+    }
+}
+
+/// Packets kept from the outputs, shared with the stats line and summary.
+#[derive(Debug, Default)]
+pub struct FilterCounters {
+    filtered: AtomicU64,
+    unparsed: AtomicU64,
+}
+
+impl FilterCounters {
+    /// (didn't match, couldn't be parsed) so far.
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.filtered.load(Ordering::Relaxed),
+            self.unparsed.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Every filter asked for, applied together.
+#[derive(Debug, Default)]
+pub struct Filters(Vec<PacketFilter>);
+
+impl Filters {
+    pub fn new(filters: Vec<PacketFilter>) -> Self {
+        Self(filters)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Take out the packets some filter doesn't keep, in place.
+    pub fn apply(&self, packets: &mut Packets, counters: &FilterCounters) {
+        packets.retain(|packet| {
+            let verdict = self
+                .0
+                .iter()
+                .map(|filter| filter.check(packet))
+                .find(|verdict| *verdict != Verdict::Keep)
+                .unwrap_or(Verdict::Keep);
+            match verdict {
+                Verdict::Keep => return true,
+                Verdict::Drop => counters.filtered.fetch_add(1, Ordering::Relaxed),
+                Verdict::Unparsed => counters.unparsed.fetch_add(1, Ordering::Relaxed),
+            };
+            false
+        });
+    }
+}
+
+/// Parse a stream id, decimal or 0x hex.
+pub fn parse_stream_id(s: &str) -> std::result::Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("Expected a 32-bit stream id like 42 or 0x2a, got: {s}"))
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn sdds_packet(seq: u16, mode: u8) -> Vec<u8> {
+        let mut packet = vec![0u8; sdds::HEADER_BYTES];
+        packet.splice(0..2, [0x80 | mode, 16]);
+        packet.splice(2..4, seq.to_be_bytes());
+        packet
+    }
+
+    fn vrt_frame(vrt: &[u32]) -> Vec<u8> {
+        let mut frame = b"VRLP\x00\x10\x00\x00".to_vec();
+        frame.extend(vrt.iter().flat_map(|word| word.to_be_bytes()));
+        frame
+    }
+
+    #[test]
+    fn test_parity() {
+        let data = sdds_packet(33, 0);
+        let parity = sdds_packet(64, 0);
+        assert_eq!(PacketFilter::DropParity.check(&data), Verdict::Keep);
+        assert_eq!(PacketFilter::DropParity.check(&parity), Verdict::Drop);
+        assert_eq!(PacketFilter::OnlyParity.check(&data), Verdict::Drop);
+        assert_eq!(PacketFilter::OnlyParity.check(&parity), Verdict::Keep);
+        assert_eq!(PacketFilter::DropParity.check(&[0; 4]), Verdict::Unparsed);
+    }
+
+    #[test]
+    fn test_stream_ids() {
+        let filter = PacketFilter::StreamIds(vec![1, 3]);
+        let stream = |id| vrt_frame(&[(1 << 28) | 3, id, 0]);
+        assert_eq!(filter.check(&stream(3)), Verdict::Keep);
+        assert_eq!(filter.check(&stream(2)), Verdict::Drop);
+        // Type 0 has no stream id
+        assert_eq!(filter.check(&vrt_frame(&[2, 0])), Verdict::Drop);
+        assert_eq!(filter.check(b"not vita"), Verdict::Unparsed);
+    }
+
+    #[test]
+    fn test_sdds_mode() {
+        let filter = PacketFilter::SddsMode(2);
+        assert_eq!(filter.check(&sdds_packet(1, 2)), Verdict::Keep);
+        assert_eq!(filter.check(&sdds_packet(1, 1)), Verdict::Drop);
+        assert_eq!(filter.check(&[0x82]), Verdict::Unparsed);
+    }
+
+    #[test]
+    fn test_filters_count_apart() {
+        let filters = Filters::new(vec![PacketFilter::DropParity, PacketFilter::SddsMode(2)]);
+        let counters = FilterCounters::default();
+        let mut packets = Packets::new(4, sdds::HEADER_BYTES);
+        let payloads = [
+            sdds_packet(1, 2),
+            sdds_packet(32, 2),
+            sdds_packet(2, 1),
+            vec![0; 4],
+        ];
+        for (packet, payload) in packets.iter_mut().zip(&payloads) {
+            if let Some(data) = packet.data_mut().get_mut(..payload.len()) {
+                data.copy_from_slice(payload);
+            }
+            packet.set_length(payload.len());
+        }
+
+        filters.apply(&mut packets, &counters);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(counters.get(), (2, 1));
+    }
+
+    #[test]
+    fn test_parse_stream_id() {
+        assert_eq!(parse_stream_id("42"), Ok(42));
+        assert_eq!(parse_stream_id("0x2A"), Ok(42));
+        assert!(parse_stream_id("0x1_0000_0000").is_err());
+        assert!(parse_stream_id("ch1").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+/// The sequence numbers SDDS and VITA-49 packets carry, followed for gaps.
+/// The statistics thread counts what they skip, and with --mark-gaps the
+/// outputs record where they skipped: binary files get a marker record in
+/// place of the missing packets, and SigMF recordings a run of zero samples
+/// as long as the missing packets would have been, with an annotation.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::{packet::PacketType, sdds, vita49};
+
+/// Set in a binary record's length word, which then holds the number of
+/// packets missing at that point rather than the length of a payload.
+/// No packet comes near the lengths it covers.
+pub const GAP_MARKER_FLAG: u32 = 0x8000_0000;
+
+// Marker locations kept for the summary; the rest are only counted.
+const LISTED_MARKERS: usize = 5;
+
+/// Where a packet type's sequence numbers jumped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap {
+    pub expected: u16,
+    pub seq: u16,
+    /// Sequence numbers between the two, expected included
+    pub skipped: u64,
+}
+
+impl Gap {
+    /// SDDS sequence numbers skipped that would have been data packets
+    /// rather than parity ones.
+    pub fn sdds_data_packets(&self) -> u64 {
+        (0..self.skipped)
+            .filter(|i| !sdds::is_parity(self.expected.wrapping_add(*i as u16)))
+            .count() as u64
+    }
+}
+
+/// The last sequence number seen, for the gap to the next.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameSeq {
+    last: Option<u16>,
+}
+
+impl FrameSeq {
+    /// An SDDS sequence number, 16 bits. Every 32nd is a parity packet,
+    /// which isn't checked but is what the next one follows on from.
+    pub fn sdds(&mut self, seq: u16) -> Option<Gap> {
+        if sdds::is_parity(seq) {
+            self.last = Some(seq);
+            return None;
+        }
+        let gap = self.last.and_then(|prev_seq| {
+            let expected = prev_seq.wrapping_add(1);
+            let skipped = if seq >= expected {
+                (seq - expected) as u64
+            } else {
+                (u16::MAX - expected + seq + 1) as u64
+            };
+            (skipped > 0).then_some(Gap {
+                expected,
+                seq,
+                skipped,
+            })
+        });
+        self.last = Some(seq);
+        gap
+    }
+
+    /// A VITA-49 sequence number, 12 bits.
+    pub fn vita49(&mut self, seq: u16) -> Option<Gap> {
+        let gap = self.last.and_then(|prev_seq| {
+            let expected = (prev_seq + 1) & 0xFFF;
+            let skipped = if seq >= expected {
+                (seq - expected) as u64
+            } else {
+                0x1000 - expected as u64 + seq as u64
+            };
+            (skipped > 0).then_some(Gap {
+                expected,
+                seq,
+                skipped,
+            })
+        });
+        self.last = Some(seq);
+        gap
+    }
+
+    /// The gap before packet, for the packet types with sequence numbers.
+    pub fn observe(&mut self, packet_type: PacketType, packet: &[u8]) -> Option<Gap> {
+        match packet_type {
+            PacketType::Sdds => self.sdds(sdds::frame_sequence_number(packet)),
+            PacketType::Vita49 => self.vita49(vita49::parse_header(packet).frame_sequence_number),
+            _ => None,
+        }
+    }
+}
+
+/// --mark-gaps for one output: its packets' sequence numbers, and the
+/// records it wrote so a marker can say where it is.
+#[derive(Debug)]
+pub struct GapMarker {
+    packet_type: PacketType,
+    seq: FrameSeq,
+    records: u64,
+    marks: Arc<GapMarks>,
+}
+
+impl GapMarker {
+    pub fn new(packet_type: PacketType, marks: Arc<GapMarks>) -> Self {
+        Self {
+            packet_type,
+            seq: FrameSeq::default(),
+            records: 0,
+            marks,
+        }
+    }
+
+    /// The gap before packet, if any. Keepalives carry no sequence number.
+    pub fn observe(&mut self, packet: &[u8]) -> Option<Gap> {
+        if packet.is_empty() {
+            return None;
+        }
+        self.seq.observe(self.packet_type, packet)
+    }
+
+    /// Count a record written, returning where it went, from 1.
+    pub fn add_record(&mut self) -> u64 {
+        self.records += 1;
+        self.records
+    }
+
+    /// The output started a new file; its records count from 1 again.
+    pub fn new_file(&mut self) {
+        self.records = 0;
+    }
+
+    /// A marker for missing packets was written at location.
+    pub fn mark(&self, location: String, missing: u64) {
+        log::debug!("gap of {missing} packets marked at {location}");
+        self.marks.add(location, missing);
+    }
+}
+
+/// The length word of a marker for missing packets.
+pub fn marker_length_word(missing: u64) -> u32 {
+    GAP_MARKER_FLAG | missing.min((GAP_MARKER_FLAG - 1) as u64) as u32
+}
+
+/// The packets missing, when a binary record's length word is a marker.
+pub fn marker_missing(length_word: u32) -> Option<u64> {
+    (length_word & GAP_MARKER_FLAG != 0).then_some((length_word & !GAP_MARKER_FLAG) as u64)
+}
+
+/// The markers the outputs wrote, shared with the final summary.
+#[derive(Debug, Default)]
+pub struct GapMarks {
+    markers: AtomicU64,
+    missing: AtomicU64,
+    locations: Mutex<Vec<String>>,
+}
+
+/// What the outputs marked.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GapMarkTotals {
+    pub markers: u64,
+    pub missing: u64,
+    /// The first few markers, like "capture.bin record 12"
+    pub locations: Vec<String>,
+}
+
+impl GapMarks {
+    /// A marker for missing packets written at location.
+    pub fn add(&self, location: String, missing: u64) {
+        let before = self.markers.fetch_add(1, Ordering::Relaxed);
+        self.missing.fetch_add(missing, Ordering::Relaxed);
+        if (before as usize) < LISTED_MARKERS
+            && let Ok(mut locations) = self.locations.lock()
+        {
+            locations.push(location);
+        }
+    }
+
+    pub fn get(&self) -> GapMarkTotals {
+        GapMarkTotals {
+            markers: self.markers.load(Ordering::Relaxed),
+            missing: self.missing.load(Ordering::Relaxed),
+            locations: self
+                .locations
+                .lock()
+                .map(|locations| locations.clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl GapMarkTotals {
+    /// For the summary: "3 for 40 missing packets, at capture.bin record 12, ..."
+    pub fn format(&self) -> String {
+        let mut s = format!("{} for {} missing packets", self.markers, self.missing);
+        if !self.locations.is_empty() {
+            s.push_str(&format!(", at {}", self.locations.join(", ")));
+        }
+        let unlisted = self.markers.saturating_sub(self.locations.len() as u64);
+        if unlisted > 0 {
+            s.push_str(&format!(" and {unlisted} more"));
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sdds_gaps() {
+        let mut seq = FrameSeq::default();
+        assert_eq!(seq.sdds(1), None);
+        assert_eq!(seq.sdds(2), None);
+        assert_eq!(
+            seq.sdds(5),
+            Some(Gap {
+                expected: 3,
+                seq: 5,
+                skipped: 2
+            })
+        );
+        // The parity packet is followed on from, not checked
+        assert_eq!(seq.sdds(32), None);
+        assert_eq!(seq.sdds(33), None);
+
+        let mut seq = FrameSeq::default();
+        seq.sdds(u16::MAX - 1);
+        let gap = seq.sdds(2).expect("wrapped gap");
+        assert_eq!(gap.skipped, 3);
+        // 0 is a parity packet
+        assert_eq!(gap.sdds_data_packets(), 2);
+    }
+
+    #[test]
+    fn test_vita49_gaps() {
+        let mut seq = FrameSeq::default();
+        assert_eq!(seq.vita49(0xFFE), None);
+        assert_eq!(seq.vita49(0xFFF), None);
+        assert_eq!(seq.vita49(0), None);
+        assert_eq!(
+            seq.vita49(0xFFE),
+            Some(Gap {
+                expected: 1,
+                seq: 0xFFE,
+                skipped: 0xFFD
+            })
+        );
+        assert_eq!(
+            seq.vita49(3),
+            Some(Gap {
+                expected: 0xFFF,
+                seq: 3,
+                skipped: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_marker_length_word() {
+        let word = marker_length_word(7);
+        assert_eq!(word.to_le_bytes(), [7, 0, 0, 0x80]);
+        assert_eq!(marker_missing(word), Some(7));
+        assert_eq!(marker_missing(1024), None);
+        assert_eq!(
+            marker_missing(marker_length_word(u64::MAX)),
+            Some(0x7FFF_FFFF)
+        );
+    }
+
+    #[test]
+    fn test_totals_format() {
+        let marks = GapMarks::default();
+        for record in 1..=7 {
+            marks.add(format!("capture.bin record {record}"), 2);
+        }
+        assert_eq!(
+            marks.get().format(),
+            "7 for 14 missing packets, at capture.bin record 1, capture.bin record 2, \
+             capture.bin record 3, capture.bin record 4, capture.bin record 5 and 2 more"
+        );
+    }
+}
@@ -0,0 +1,237 @@
+/// --heartbeat: a packet the writer makes up itself every interval, to keep
+/// a multicast tree alive when nothing else is being sent. It goes out
+/// between whatever batches the reader hands over, so it works alongside a
+/// relay or replay as well as on its own.
+use std::fmt::Write as _;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{
+    Utc,
+    format::{Item, StrftimeItems},
+};
+
+use crate::{
+    MAX_PACKET_BYTES,
+    error::{LibError, Result},
+    packet::Packet,
+};
+
+/// Counts up by one for each heartbeat, from 0.
+pub const SEQ_TOKEN: &str = "{seq}";
+
+const DEFAULT_PAYLOAD: &str = "mnc heartbeat {seq}";
+
+/// From `--heartbeat INTERVAL[:PAYLOAD|@FILE]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub payload: HeartbeatPayload,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeartbeatPayload {
+    /// strftime specifiers and {seq} are filled in for each heartbeat
+    Template(String),
+    /// Sent byte for byte
+    File(String),
+}
+
+/// Parse INTERVAL[:PAYLOAD|@FILE], the interval in seconds or with an ms,
+/// s or m suffix.
+pub fn parse_heartbeat(s: &str) -> std::result::Result<HeartbeatConfig, String> {
+    let (interval, payload) = match s.split_once(':') {
+        Some((interval, payload)) => (interval, Some(payload)),
+        None => (s, None),
+    };
+    let interval = parse_interval(interval)?;
+    let payload = match payload {
+        Some(path) if path.starts_with('@') => {
+            HeartbeatPayload::File(path.trim_start_matches('@').to_string())
+        }
+        Some(template) => HeartbeatPayload::Template(template.to_string()),
+        None => HeartbeatPayload::Template(DEFAULT_PAYLOAD.to_string()),
+    };
+    if let HeartbeatPayload::Template(template) = &payload
+        && StrftimeItems::new(template).any(|item| item == Item::Error)
+    {
+        return Err(format!(
+            "Heartbeat payload has an unknown % specifier, write %% for a %: {template}"
+        ));
+    }
+    Ok(HeartbeatConfig { interval, payload })
+}
+
+fn parse_interval(s: &str) -> std::result::Result<Duration, String> {
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 1e-3)
+    } else if let Some(secs) = s.strip_suffix('s') {
+        (secs, 1.0)
+    } else if let Some(mins) = s.strip_suffix('m') {
+        (mins, 60.0)
+    } else {
+        (s, 1.0)
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n > 0.0 => Duration::try_from_secs_f64(n * scale)
+            .ok()
+            .filter(|interval| !interval.is_zero())
+            .ok_or_else(|| format!("Heartbeat interval out of range: {s}")),
+        _ => Err(format!(
+            "Expected a heartbeat interval like 1s or 500ms, got: {s}"
+        )),
+    }
+}
+
+/// When the next heartbeat is due, and the packet to send then.
+#[derive(Debug)]
+pub struct Heartbeat {
+    interval: Duration,
+    payload: Payload,
+    due: Instant,
+    seq: u64,
+    packet: Packet,
+}
+
+#[derive(Debug)]
+enum Payload {
+    Template(String),
+    Fixed(Vec<u8>),
+}
+
+impl Heartbeat {
+    /// The first heartbeat is due at once.
+    pub fn new(config: &HeartbeatConfig) -> Result<Self> {
+        let payload = match &config.payload {
+            HeartbeatPayload::Template(template) => Payload::Template(template.clone()),
+            HeartbeatPayload::File(path) => {
+                let payload = std::fs::read(path).map_err(|e| {
+                    LibError::Critical(format!("Failed to read heartbeat {path}: {e}"))
+                })?;
+                if payload.len() > MAX_PACKET_BYTES {
+                    return Err(LibError::Critical(format!(
+                        "Heartbeat {path} is {} bytes, more than a packet holds",
+                        payload.len()
+                    )));
+                }
+                Payload::Fixed(payload)
+            }
+        };
+        Ok(Self {
+            interval: config.interval,
+            payload,
+            due: Instant::now(),
+            seq: 0,
+            packet: Packet::with_capacity(0),
+        })
+    }
+
+    pub fn due(&self) -> Instant {
+        self.due
+    }
+
+    /// The heartbeat to send if one is due by now. A writer that was held
+    /// up sends one, not one for every interval it missed.
+    pub fn poll(&mut self, now: Instant) -> Option<&[Packet]> {
+        if now < self.due {
+            return None;
+        }
+        self.due += self.interval;
+        if self.due <= now {
+            self.due = now + self.interval;
+        }
+
+        let payload = match &self.payload {
+            Payload::Template(template) => expand(template, self.seq).into_bytes(),
+            Payload::Fixed(payload) => payload.clone(),
+        };
+        self.seq += 1;
+        let length = payload.len().min(MAX_PACKET_BYTES);
+        self.packet.ensure_capacity(length);
+        if let Some(data) = self.packet.data_mut().get_mut(..length)
+            && let Some(payload) = payload.get(..length)
+        {
+            data.copy_from_slice(payload);
+        }
+        self.packet.set_length(length);
+        self.packet.set_timestamp(Some(SystemTime::now()));
+        Some(std::slice::from_ref(&self.packet))
+    }
+}
+
+/// The template with {seq} and the strftime specifiers filled in, in UTC.
+fn expand(template: &str, seq: u64) -> String {
+    let template = template.replace(SEQ_TOKEN, &seq.to_string());
+    let mut payload = String::new();
+    // Checked for bad specifiers when parsed
+    let _ = write!(payload, "{}", Utc::now().format(&template));
+    payload
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_heartbeat() {
+        let config = parse_heartbeat("1s").expect("parse");
+        assert_eq!(config.interval, Duration::from_secs(1));
+        assert_eq!(
+            config.payload,
+            HeartbeatPayload::Template(DEFAULT_PAYLOAD.to_string())
+        );
+
+        let config = parse_heartbeat("500ms:alive {seq}").expect("parse");
+        assert_eq!(config.interval, Duration::from_millis(500));
+        assert_eq!(
+            config.payload,
+            HeartbeatPayload::Template("alive {seq}".to_string())
+        );
+
+        // Only the first colon separates, times have their own
+        let config = parse_heartbeat("2:%H:%M:%S").expect("parse");
+        assert_eq!(config.interval, Duration::from_secs(2));
+        assert_eq!(
+            config.payload,
+            HeartbeatPayload::Template("%H:%M:%S".to_string())
+        );
+
+        let config = parse_heartbeat("1m:@./keepalive.bin").expect("parse");
+        assert_eq!(config.interval, Duration::from_secs(60));
+        assert_eq!(
+            config.payload,
+            HeartbeatPayload::File("./keepalive.bin".to_string())
+        );
+
+        assert!(parse_heartbeat("0").is_err());
+        assert!(parse_heartbeat("fast").is_err());
+        assert!(parse_heartbeat("1s:100%").is_err());
+        assert!(parse_heartbeat("1s:100%%").is_ok());
+    }
+
+    #[test]
+    fn test_poll() {
+        let config = parse_heartbeat("1s:beat {seq} %%").expect("parse");
+        let mut heartbeat = Heartbeat::new(&config).expect("heartbeat");
+        let start = heartbeat.due();
+
+        let packets = heartbeat.poll(start).expect("due at once");
+        assert_eq!(packets.first().map(|p| &p[..]), Some(&b"beat 0 %"[..]));
+        assert!(heartbeat.poll(start + Duration::from_millis(999)).is_none());
+        let packets = heartbeat.poll(start + Duration::from_secs(1)).expect("due");
+        assert_eq!(packets.first().map(|p| &p[..]), Some(&b"beat 1 %"[..]));
+
+        // Late by several intervals: one heartbeat, then back on the interval
+        let late = start + Duration::from_secs(10);
+        assert!(heartbeat.poll(late).is_some());
+        assert!(heartbeat.poll(late).is_none());
+        assert_eq!(heartbeat.due(), late + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_expand_timestamp() {
+        let payload = expand("%Y {seq}", 7);
+        let year = Utc::now().format("%Y").to_string();
+        assert_eq!(payload, format!("{year} 7"));
+    }
+}
@@ -0,0 +1,153 @@
+/// One-way latency between two hosts with synchronized clocks.
+/// The sender writes its wall clock into each packet (`--stamp`) and the
+/// receiver subtracts that from the kernel receive time. Stamps are u64
+/// nanoseconds since the unix epoch, big endian.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const STAMP_BYTES: usize = 8;
+
+fn nanos_since_epoch(time: SystemTime) -> i128 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_nanos() as i128,
+        Err(before) => -(before.duration().as_nanos() as i128),
+    }
+}
+
+/// Write now into packet at offset. False when the packet is too short.
+pub fn stamp(packet: &mut [u8], offset: usize, now: SystemTime) -> bool {
+    let Some(field) = packet.get_mut(offset..offset + STAMP_BYTES) else {
+        return false;
+    };
+    field.copy_from_slice(&(nanos_since_epoch(now) as u64).to_be_bytes());
+    true
+}
+
+/// The stamp at offset, if the packet is long enough to hold one.
+pub fn read_stamp(packet: &[u8], offset: usize) -> Option<u64> {
+    let field = packet.get(offset..offset + STAMP_BYTES)?;
+    let bytes: [u8; STAMP_BYTES] = field.try_into().ok()?;
+    Some(u64::from_be_bytes(bytes))
+}
+
+/// Latencies seen over one statistics interval.
+#[derive(Debug, Default)]
+pub struct Latency {
+    samples: Vec<i64>,
+    unstamped: u64,
+}
+
+impl Latency {
+    pub fn observe(&mut self, packet: &[u8], offset: usize, arrival: Option<SystemTime>) {
+        match (read_stamp(packet, offset), arrival) {
+            (Some(sent), Some(arrival)) => {
+                let latency = nanos_since_epoch(arrival) - sent as i128;
+                self.samples
+                    .push(latency.clamp(i64::MIN as i128, i64::MAX as i128) as i64);
+            }
+            _ => self.unstamped += 1,
+        }
+    }
+
+    /// Packets that arrived before the sender's stamp, which only happens
+    /// when the clocks disagree.
+    pub fn negative(&self) -> usize {
+        self.samples.iter().filter(|latency| **latency < 0).count()
+    }
+
+    /// "  latency: min 12.1us avg 15.0us max 40.2us p99 38.7us", for the stats line.
+    /// Negative latencies are kept as they are and called out, not clamped.
+    pub fn format(&mut self) -> String {
+        self.samples.sort_unstable();
+        let (Some(min), Some(max)) = (self.samples.first(), self.samples.last()) else {
+            return match self.unstamped {
+                0 => String::new(),
+                n => format!("  latency: - ({n} unstamped)"),
+            };
+        };
+
+        let count = self.samples.len();
+        let sum: i128 = self.samples.iter().map(|latency| *latency as i128).sum();
+        let avg = sum / count as i128;
+        // Nearest rank
+        let p99 = self
+            .samples
+            .get((count * 99).div_ceil(100).saturating_sub(1))
+            .copied()
+            .unwrap_or(*max);
+
+        let mut s = format!(
+            "  latency: min {} avg {} max {} p99 {}",
+            micros(*min as i128),
+            micros(avg),
+            micros(*max as i128),
+            micros(p99 as i128)
+        );
+        match self.negative() {
+            0 => {}
+            n => s.push_str(&format!(" ({n} negative, clocks out of sync?)")),
+        }
+        if self.unstamped > 0 {
+            s.push_str(&format!(" ({} unstamped)", self.unstamped));
+        }
+        s
+    }
+}
+
+fn micros(nanos: i128) -> String {
+    format!("{:.1}us", nanos as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn at(nanos: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_nanos(nanos)
+    }
+
+    #[test]
+    fn test_stamp_round_trip() {
+        let mut packet = [0u8; 16];
+        assert!(stamp(&mut packet, 4, at(1_714_660_282_123_456_789)));
+        assert_eq!(read_stamp(&packet, 4), Some(1_714_660_282_123_456_789));
+        assert_eq!(packet.first(), Some(&0));
+
+        assert!(!stamp(&mut packet, 9, at(1)));
+        assert_eq!(read_stamp(&packet, 9), None);
+    }
+
+    #[test]
+    fn test_latency_format() {
+        let mut latency = Latency::default();
+        assert_eq!(latency.format(), "");
+
+        let mut packet = [0u8; 8];
+        for delay in 1..=100u64 {
+            stamp(&mut packet, 0, at(1_000_000_000));
+            latency.observe(&packet, 0, Some(at(1_000_000_000 + delay * 1000)));
+        }
+        latency.observe(b"short", 0, Some(at(0)));
+        assert_eq!(
+            latency.format(),
+            "  latency: min 1.0us avg 50.5us max 100.0us p99 99.0us (1 unstamped)"
+        );
+    }
+
+    #[test]
+    fn test_negative_latency_is_flagged() {
+        let mut latency = Latency::default();
+        let mut packet = [0u8; 8];
+        stamp(&mut packet, 0, at(2_000_000));
+        latency.observe(&packet, 0, Some(at(1_000_000)));
+        stamp(&mut packet, 0, at(2_000_000));
+        latency.observe(&packet, 0, Some(at(2_500_000)));
+
+        assert_eq!(latency.negative(), 1);
+        assert_eq!(
+            latency.format(),
+            "  latency: min -1000.0us avg -250.0us max 500.0us p99 500.0us (1 negative, clocks out of sync?)"
+        );
+    }
+}
@@ -1,2 +1,490 @@
+//! mnc - multicast netcat.
+//!
+//! The CLI is a thin wrapper around this library, so the same receive/decode
+//! machinery can be embedded in other tools. A pipeline is three threads
+//! connected by bounded channels:
+//!
+//! - [`reader`] pulls packets from the network, a file, or stdin
+//! - [`statistics`] (optional) decodes headers and prints periodic counts
+//! - [`writer`] sends packets to the network, a file, or stdout
+//!
+//! Packet buffers are allocated once into a memory pool and recycled from the
+//! writer back to the reader, so whoever consumes the data channel must return
+//! each [`Packets`] batch to the memory return channel.
+//!
+//! Receive ten packets from a group programmatically:
+//!
+//! ```no_run
+//! use mnc::crossbeam_channel::bounded;
+//! use mnc::{PacketType, SharedState, initialize_memory_pool, reader};
+//!
+//! let shared_state = SharedState::new(PacketType::Binary, false);
+//! let (data_tx, data_rx) = bounded(8);
+//! let (memory_return_tx, memory_return_rx) = bounded(8);
+//! initialize_memory_pool(memory_return_tx.clone(), 64, 4, shared_state.clone());
+//!
+//! let handle = reader::spawn(reader::ReaderConfig {
+//!     input: None,
+//!     template: None,
+//!     pps: None,
+//!     joins: vec![reader::Join {
+//!         iface: None,
+//!         mgroup: "239.1.1.1".to_string(),
+//!         ports: vec![29495],
+//!     }],
+//!     batch_size: 64,
+//!     channels: (Box::new(data_tx), memory_return_rx),
+//!     shared_state: shared_state.clone(),
+//!     max_count: 10,
+//!     input_framing: None,
+//!     adaptive_buffers: false,
+//!     speed: 1.0,
+//!     placement: Default::default(),
+//!     sandbox: false,
+//!     max_restarts: None,
+//!     max_packet: mnc::MAX_PACKET_BYTES,
+//!     drop_truncated: false,
+//!     loop_guard: None,
+//!     stall_threshold: reader::DEFAULT_STALL_THRESHOLD,
+//!     repeat: None,
+//! });
+//!
+//! while let Ok(packets) = data_rx.recv() {
+//!     // An empty batch is the EOF sentinel
+//!     if packets.is_empty() {
+//!         break;
+//!     }
+//!     for packet in packets.iter() {
+//!         println!("{} bytes", packet.len());
+//!     }
+//!     // Hand the buffers back. The reader may already be done with the pool.
+//!     let _ = memory_return_tx.send(packets);
+//! }
+//!
+//! handle.join().map_err(|_| "reader panicked")??;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use crossbeam_channel::Sender;
+
+#[cfg(feature = "tokio")]
+pub mod async_reader;
+pub mod banner;
+pub mod cli;
+pub mod config;
+pub mod control;
+pub mod convert;
+pub mod cpu;
+pub mod dejitter;
+pub mod diagnose;
+pub mod drain;
+pub mod dump;
+pub mod error;
+pub mod exec;
+pub mod expect;
+pub mod extension;
+pub mod fec;
+pub mod filter;
+pub mod gaps;
+pub mod heartbeat;
+pub mod latency;
+pub mod loop_guard;
+pub mod mdns;
+pub mod multicast;
+pub mod nic;
+pub mod pace;
+pub mod packet;
+pub mod patch;
+pub mod pcap;
+pub mod ping;
+pub mod pipeline;
+pub mod police;
+pub mod preflight;
+pub mod progress;
+pub mod reader;
+pub mod reorder;
+pub mod resolve;
+pub mod resync;
+pub mod sandbox;
+pub mod sched;
 pub mod sdds;
+pub mod senders;
+pub mod sequence;
+pub mod setup;
+pub mod sigmf;
+pub mod sink;
+pub mod statistics;
+pub mod systemd;
+pub mod template;
+pub mod transport;
+pub mod txtime;
+pub mod unique;
 pub mod vita49;
+pub mod writer;
+
+use control::Control;
+use convert::ConvertCounters;
+use cpu::ThreadCpu;
+/// Re-exported so embedders build channels with the same version we use.
+pub use crossbeam_channel;
+use dejitter::DejitterCounters;
+pub use error::{LibError, Result};
+use expect::ExpectCounters;
+use fec::FecCounters;
+use filter::FilterCounters;
+use gaps::GapMarks;
+use loop_guard::OwnTraffic;
+pub use packet::{Packet, PacketType, Packets};
+use patch::PatchCounters;
+use progress::InputProgress;
+use reader::{BatchFillCounters, StallCounters};
+use reorder::ReorderCounters;
+use resolve::HostNames;
+use sink::{SplitCounters, TransmitCounters};
+use unique::UniqueTotals;
+
+/// Max UDP Packet size in bytes
+pub const MAX_PACKET_BYTES: usize = 65536;
+
+/// Some global variables to help control thread shutdown.
+/// Cheap to clone; every clone shares the same counters and exit flag.
+#[derive(Clone)]
+pub struct SharedState {
+    pub read_count: Arc<AtomicU64>,
+    pub write_count: Arc<AtomicU64>,
+    /// Payload bytes behind read_count and write_count.
+    pub read_bytes: Arc<AtomicU64>,
+    pub write_bytes: Arc<AtomicU64>,
+    /// Batches the reader dropped because the data channel was full.
+    pub dropped_batches: Arc<AtomicU64>,
+    /// Packets --police dropped for exceeding the rate.
+    pub policed: Arc<AtomicU64>,
+    /// Packets too short for --strip to take its bytes off, dropped.
+    pub too_short: Arc<AtomicU64>,
+    /// Packets output files dropped while their disk was full.
+    pub disk_full: Arc<AtomicU64>,
+    /// Datagrams longer than their receive buffer, whether kept cut short
+    /// or dropped by --drop-truncated.
+    pub truncated: Arc<AtomicU64>,
+    /// Times --max-restarts reopened the reader's sockets.
+    pub restarts: Arc<AtomicU64>,
+    /// Packets still queued when a thread gave up draining on exit.
+    pub abandoned: Arc<AtomicU64>,
+    /// Exit conditions:
+    /// - should_exit is immediate: ctrl-c and errors.
+    /// - any other normal exit is indicated by an empty packet batch (sentinel value)
+    pub should_exit: Arc<AtomicBool>,
+    /// Set with should_exit when --fail-on-gap saw more sequence gaps than allowed.
+    pub gap_failed: Arc<AtomicBool>,
+    /// Set by SIGUSR1 for a stats line out of cycle, cleared once printed.
+    pub snapshot: Arc<AtomicBool>,
+    /// Set by SIGUSR2 to turn hex dumps on or off.
+    pub toggle_dump: Arc<AtomicBool>,
+    /// Set by SIGHUP for the writer to reopen its output files.
+    pub reopen: Arc<AtomicBool>,
+    /// Published by the reader when sending from a file.
+    pub input_progress: Arc<InputProgress>,
+    /// Published by the network sink when transmitting.
+    pub transmit: Arc<TransmitCounters>,
+    /// Published by --reorder.
+    pub reorder: Arc<ReorderCounters>,
+    /// Published by the --drop-parity and --filter-* filters.
+    pub filters: Arc<FilterCounters>,
+    /// Published by --dejitter.
+    pub dejitter: Arc<DejitterCounters>,
+    /// Published by --split-by.
+    pub split: Arc<SplitCounters>,
+    /// Published by --expect.
+    pub expect: Arc<ExpectCounters>,
+    /// Published by --convert.
+    pub convert: Arc<ConvertCounters>,
+    /// Published by --fec and --fec-decode.
+    pub fec: Arc<FecCounters>,
+    /// Published by --patch.
+    pub patch: Arc<PatchCounters>,
+    /// Published by the reader, for --batch-stats.
+    pub batch_fill: Arc<BatchFillCounters>,
+    /// Published by the reader when receiving from a group.
+    pub stalls: Arc<StallCounters>,
+    /// Published by the statistics thread, for --unique.
+    pub unique: Arc<UniqueTotals>,
+    /// Published by the network sink and the reader, to keep a relay from
+    /// forwarding its own packets.
+    pub own_traffic: Arc<OwnTraffic>,
+    /// Published by the outputs, for --mark-gaps.
+    pub gap_marks: Arc<GapMarks>,
+    /// Each pipeline thread, registered as it starts.
+    pub cpu: Arc<ThreadCpu>,
+    /// Source host names, looked up once --resolve starts it.
+    pub names: Arc<HostNames>,
+    /// Changed by --control commands, read by the writer as it goes.
+    pub control: Arc<Control>,
+    pub packet_type: PacketType,
+    pub verbose: bool,
+}
+
+impl SharedState {
+    pub fn new(packet_type: PacketType, verbose: bool) -> Self {
+        Self {
+            read_count: Arc::new(AtomicU64::new(0)),
+            write_count: Arc::new(AtomicU64::new(0)),
+            read_bytes: Arc::new(AtomicU64::new(0)),
+            write_bytes: Arc::new(AtomicU64::new(0)),
+            dropped_batches: Arc::new(AtomicU64::new(0)),
+            policed: Arc::new(AtomicU64::new(0)),
+            too_short: Arc::new(AtomicU64::new(0)),
+            disk_full: Arc::new(AtomicU64::new(0)),
+            truncated: Arc::new(AtomicU64::new(0)),
+            restarts: Arc::new(AtomicU64::new(0)),
+            abandoned: Arc::new(AtomicU64::new(0)),
+            should_exit: Arc::new(AtomicBool::new(false)),
+            gap_failed: Arc::new(AtomicBool::new(false)),
+            snapshot: Arc::new(AtomicBool::new(false)),
+            toggle_dump: Arc::new(AtomicBool::new(false)),
+            reopen: Arc::new(AtomicBool::new(false)),
+            input_progress: Arc::new(InputProgress::default()),
+            transmit: Arc::new(TransmitCounters::default()),
+            reorder: Arc::new(ReorderCounters::default()),
+            filters: Arc::new(FilterCounters::default()),
+            dejitter: Arc::new(DejitterCounters::default()),
+            split: Arc::new(SplitCounters::default()),
+            expect: Arc::new(ExpectCounters::default()),
+            convert: Arc::new(ConvertCounters::default()),
+            fec: Arc::new(FecCounters::default()),
+            patch: Arc::new(PatchCounters::default()),
+            batch_fill: Arc::new(BatchFillCounters::default()),
+            stalls: Arc::new(StallCounters::default()),
+            unique: Arc::new(UniqueTotals::default()),
+            own_traffic: Arc::new(OwnTraffic::default()),
+            gap_marks: Arc::new(GapMarks::default()),
+            cpu: Arc::new(ThreadCpu::default()),
+            names: Arc::new(HostNames::default()),
+            control: Arc::new(Control::default()),
+            packet_type,
+            verbose,
+        }
+    }
+
+    pub fn add_read_count(&self, delta: u64) -> u64 {
+        self.read_count.fetch_add(delta, Ordering::Relaxed) + delta
+    }
+    pub fn get_read_count(&self) -> u64 {
+        self.read_count.load(Ordering::Relaxed)
+    }
+    /// Claim up to wanted reads without taking the count past max_count
+    /// (0 is unlimited). Atomic, so -c stays exact with several producers.
+    /// Returns how many were granted and the new count.
+    pub fn reserve_read_count(&self, max_count: u64, wanted: u64) -> (u64, u64) {
+        if max_count == 0 {
+            return (wanted, self.add_read_count(wanted));
+        }
+
+        let claim = |count: u64| count.saturating_add(wanted).min(max_count.max(count));
+        let previous =
+            match self
+                .read_count
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                    Some(claim(count))
+                }) {
+                Ok(previous) | Err(previous) => previous,
+            };
+        let count = claim(previous);
+        (count - previous, count)
+    }
+    pub fn add_write_count(&self, delta: u64) -> u64 {
+        self.write_count.fetch_add(delta, Ordering::Relaxed) + delta
+    }
+    pub fn get_write_count(&self) -> u64 {
+        self.write_count.load(Ordering::Relaxed)
+    }
+    pub fn add_read_bytes(&self, delta: u64) {
+        self.read_bytes.fetch_add(delta, Ordering::Relaxed);
+    }
+    pub fn get_read_bytes(&self) -> u64 {
+        self.read_bytes.load(Ordering::Relaxed)
+    }
+    pub fn add_write_bytes(&self, delta: u64) {
+        self.write_bytes.fetch_add(delta, Ordering::Relaxed);
+    }
+    pub fn get_write_bytes(&self) -> u64 {
+        self.write_bytes.load(Ordering::Relaxed)
+    }
+    pub fn add_dropped_batch(&self) {
+        self.dropped_batches.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn get_dropped_batches(&self) -> u64 {
+        self.dropped_batches.load(Ordering::Relaxed)
+    }
+    pub fn add_policed(&self, count: u64) {
+        self.policed.fetch_add(count, Ordering::Relaxed);
+    }
+    pub fn get_policed(&self) -> u64 {
+        self.policed.load(Ordering::Relaxed)
+    }
+    pub fn add_too_short(&self, count: u64) {
+        self.too_short.fetch_add(count, Ordering::Relaxed);
+    }
+    pub fn get_too_short(&self) -> u64 {
+        self.too_short.load(Ordering::Relaxed)
+    }
+    pub fn get_disk_full(&self) -> u64 {
+        self.disk_full.load(Ordering::Relaxed)
+    }
+    /// Returns how many were counted before this one.
+    pub fn add_truncated(&self) -> u64 {
+        self.truncated.fetch_add(1, Ordering::Relaxed)
+    }
+    pub fn get_truncated(&self) -> u64 {
+        self.truncated.load(Ordering::Relaxed)
+    }
+    pub fn add_restart(&self) {
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn get_restarts(&self) -> u64 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+    pub fn add_abandoned(&self, count: u64) {
+        self.abandoned.fetch_add(count, Ordering::Relaxed);
+    }
+    pub fn get_abandoned(&self) -> u64 {
+        self.abandoned.load(Ordering::Relaxed)
+    }
+    pub fn signal_exit(&self) {
+        self.should_exit.store(true, Ordering::Relaxed);
+    }
+    pub fn should_exit(&self) -> bool {
+        self.should_exit.load(Ordering::Relaxed)
+    }
+    pub fn signal_gap_failed(&self) {
+        self.gap_failed.store(true, Ordering::Relaxed);
+        self.signal_exit();
+    }
+    pub fn gap_failed(&self) -> bool {
+        self.gap_failed.load(Ordering::Relaxed)
+    }
+    /// Point SIGUSR1 and SIGUSR2 at the snapshot and toggle_dump flags.
+    /// The handlers only store to the atomics; whoever takes them does the work.
+    pub fn register_signals(&self) -> std::io::Result<()> {
+        signal_hook::flag::register(signal_hook::consts::SIGUSR1, Arc::clone(&self.snapshot))?;
+        signal_hook::flag::register(signal_hook::consts::SIGUSR2, Arc::clone(&self.toggle_dump))?;
+        Ok(())
+    }
+    /// Point SIGHUP at the reopen flag. Only worth it with output files, as
+    /// it takes away the default of exiting when the terminal goes away.
+    pub fn register_reopen(&self) -> std::io::Result<()> {
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&self.reopen))?;
+        Ok(())
+    }
+    /// Whether a snapshot was asked for since the last call.
+    pub fn take_snapshot(&self) -> bool {
+        self.snapshot.swap(false, Ordering::Relaxed)
+    }
+    /// Whether hex dumps should flip since the last call.
+    pub fn take_dump_toggle(&self) -> bool {
+        self.toggle_dump.swap(false, Ordering::Relaxed)
+    }
+    /// Whether output files should be reopened since the last call.
+    pub fn take_reopen(&self) -> bool {
+        self.reopen.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Push some packets into the memory return channel.
+///
+/// This will get read by the reader and data populated.
+/// Note: We are minimizing memory (re)allocations, so
+/// make sure the packets that are injected into are not dropped
+/// except on exit.
+pub fn initialize_memory_pool(
+    memory_return_tx: Sender<Packets>,
+    batch_size: usize,
+    pool_size: usize,
+    shared_state: SharedState,
+) {
+    initialize_memory_pool_with(
+        memory_return_tx,
+        batch_size,
+        MAX_PACKET_BYTES,
+        pool_size,
+        shared_state,
+    )
+}
+
+/// Like [`initialize_memory_pool`] with packet_bytes per packet buffer.
+/// Readers grow undersized buffers as they need them, so 0 defers all
+/// payload allocation until traffic arrives.
+pub fn initialize_memory_pool_with(
+    memory_return_tx: Sender<Packets>,
+    batch_size: usize,
+    packet_bytes: usize,
+    pool_size: usize,
+    shared_state: SharedState,
+) {
+    std::thread::spawn(move || {
+        log::debug!("allocating memory pool with {pool_size} buffers");
+
+        for i in 0..pool_size {
+            if shared_state.should_exit() {
+                log::debug!("memory pool thread exiting with {i}/{pool_size}");
+                return;
+            }
+
+            let packets = Packets::new(batch_size, packet_bytes);
+            if let Err(e) = memory_return_tx.send(packets) {
+                log::debug!("memory pool initialization incomplete: {e:?}");
+                return;
+            }
+        }
+
+        log::debug!("memory pool initialization complete");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_read_count() {
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        assert_eq!(shared_state.reserve_read_count(10, 4), (4, 4));
+        assert_eq!(shared_state.reserve_read_count(10, 4), (4, 8));
+        assert_eq!(shared_state.reserve_read_count(10, 4), (2, 10));
+        assert_eq!(shared_state.reserve_read_count(10, 4), (0, 10));
+
+        // Already past the limit (unlimited producer raced ahead) must not wrap
+        shared_state.add_read_count(5);
+        assert_eq!(shared_state.reserve_read_count(10, 4), (0, 15));
+
+        assert_eq!(shared_state.reserve_read_count(0, 4), (4, 19));
+    }
+
+    #[test]
+    fn test_reserve_read_count_concurrent() {
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let granted = Arc::new(AtomicU64::new(0));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let shared_state = shared_state.clone();
+                let granted = granted.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let (count, _) = shared_state.reserve_read_count(1234, 3);
+                        granted.fetch_add(count, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().is_ok());
+        }
+
+        assert_eq!(granted.load(Ordering::Relaxed), 1234);
+        assert_eq!(shared_state.get_read_count(), 1234);
+    }
+}
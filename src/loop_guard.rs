@@ -0,0 +1,90 @@
+/// Loop protection for relays. A relay whose output finds its way back to
+/// its input, straight away through a group it both joins and sends to or
+/// round through another relay, forwards the same packets for ever. The
+/// network sink publishes the address its packets leave from and the reader
+/// drops any that arrive from there; with --loop-guard the sink also appends
+/// a marker to every packet, and the reader drops any that end in it,
+/// however many hops they took to come back.
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Our own packets come back, published for the reader, the stats line and
+/// the summary.
+#[derive(Debug, Default)]
+pub struct OwnTraffic {
+    /// The network sink's source address and port in one, 0 until known
+    sender: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl OwnTraffic {
+    pub fn set_sender(&self, sender: SocketAddrV4) {
+        let packed = (u32::from(*sender.ip()) as u64) << 16 | sender.port() as u64;
+        self.sender.store(packed, Ordering::Relaxed);
+    }
+
+    /// Where the packets we send come from, once the sink is open.
+    pub fn sender(&self) -> Option<SocketAddrV4> {
+        match self.sender.load(Ordering::Relaxed) {
+            0 => None,
+            packed => Some(SocketAddrV4::new(
+                Ipv4Addr::from((packed >> 16) as u32),
+                packed as u16,
+            )),
+        }
+    }
+
+    /// Returns how many were dropped before these.
+    pub fn add_dropped(&self, count: u64) -> u64 {
+        self.dropped.fetch_add(count, Ordering::Relaxed)
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Why a packet is one of ours, None when it isn't: it came from our own
+/// send socket, or it ends in the --loop-guard marker.
+pub fn own_packet(
+    source: Option<SocketAddrV4>,
+    payload: &[u8],
+    sender: Option<SocketAddrV4>,
+    marker: Option<&[u8]>,
+) -> Option<&'static str> {
+    if source.is_some() && source == sender {
+        return Some("sent from our own socket");
+    }
+    if marker.is_some_and(|marker| payload.ends_with(marker)) {
+        return Some("carrying our --loop-guard marker");
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sender_round_trips() {
+        let own = OwnTraffic::default();
+        assert_eq!(own.sender(), None);
+        let sender = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 2), 40123);
+        own.set_sender(sender);
+        assert_eq!(own.sender(), Some(sender));
+    }
+
+    #[test]
+    fn test_own_packet() {
+        let sender = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 2), 40123);
+        let other = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 9), 40123);
+        let marker = &[0xfe, 0xed][..];
+
+        assert!(own_packet(Some(sender), b"data", Some(sender), None).is_some());
+        assert!(own_packet(Some(other), b"data", Some(sender), None).is_none());
+        // Not yet known, or no source to go on
+        assert!(own_packet(None, b"data", None, None).is_none());
+        assert!(own_packet(Some(other), b"data\xfe\xed", Some(sender), Some(marker)).is_some());
+        assert!(own_packet(Some(other), b"\xfe\xeddata", Some(sender), Some(marker)).is_none());
+    }
+}
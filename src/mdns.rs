@@ -0,0 +1,553 @@
+// mDNS is plain DNS wire format on 224.0.0.251:5353 (RFC 6762).
+//
+//   Header: 12 bytes
+// bytes
+//   2           Transaction ID
+//   2           Flags (QR is the top bit)
+//   2           Question count
+//   2           Answer count
+//   2           Authority count
+//   2           Additional count
+//
+// Followed by the question section and three resource record sections.
+// Names may be compressed with pointers back into the packet, so everything
+// here has to assume a hostile sender: pointer loops, bogus lengths, counts
+// that run off the end of the datagram.
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub const HEADER_SIZE: usize = 12;
+
+/// Max compression pointers followed while decoding a single name.
+const MAX_POINTER_FOLLOWS: usize = 16;
+
+/// RFC 1035 limit on the presentation length of a name.
+const MAX_NAME_LENGTH: usize = 255;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+
+pub struct DnsHeader {
+    pub id: u16,
+    pub flags: u16,
+    pub question_count: u16,
+    pub answer_count: u16,
+    pub authority_count: u16,
+    pub additional_count: u16,
+}
+
+impl DnsHeader {
+    pub fn is_response(&self) -> bool {
+        (self.flags & 0x8000) != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Question,
+    Answer,
+    Authority,
+    Additional,
+}
+
+impl std::fmt::Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Section::Question => write!(f, "QD"),
+            Section::Answer => write!(f, "AN"),
+            Section::Authority => write!(f, "NS"),
+            Section::Additional => write!(f, "AR"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    /// Questions carry no rdata
+    None,
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    Txt(Vec<String>),
+    Other(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub section: Section,
+    pub name: String,
+    pub rtype: u16,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+impl std::fmt::Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "  {} {:<5} {}",
+            self.section,
+            type_name(self.rtype),
+            self.name
+        )?;
+        match &self.data {
+            RecordData::None => Ok(()),
+            RecordData::A(addr) => write!(f, " -> {addr} ttl={}", self.ttl),
+            RecordData::Aaaa(addr) => write!(f, " -> {addr} ttl={}", self.ttl),
+            RecordData::Ptr(target) => write!(f, " -> {target} ttl={}", self.ttl),
+            RecordData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(
+                f,
+                " -> {target}:{port} prio={priority} weight={weight} ttl={}",
+                self.ttl
+            ),
+            RecordData::Txt(strings) => write!(f, " -> [{}] ttl={}", strings.join(", "), self.ttl),
+            RecordData::Other(len) => write!(f, " ({len} bytes) ttl={}", self.ttl),
+        }
+    }
+}
+
+pub struct DnsMessage {
+    pub header: DnsHeader,
+    pub records: Vec<Record>,
+    /// Set when the counts in the header promised more than we could decode.
+    pub truncated: bool,
+}
+
+impl std::fmt::Display for DnsMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "mDNS {} id={} qd={} an={} ns={} ar={}{}",
+            if self.header.is_response() {
+                "response"
+            } else {
+                "query"
+            },
+            self.header.id,
+            self.header.question_count,
+            self.header.answer_count,
+            self.header.authority_count,
+            self.header.additional_count,
+            if self.truncated { " (truncated)" } else { "" },
+        )?;
+        for record in &self.records {
+            writeln!(f, "{record}")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn type_name(rtype: u16) -> String {
+    match rtype {
+        TYPE_A => "A".to_string(),
+        TYPE_PTR => "PTR".to_string(),
+        TYPE_TXT => "TXT".to_string(),
+        TYPE_AAAA => "AAAA".to_string(),
+        TYPE_SRV => "SRV".to_string(),
+        47 => "NSEC".to_string(),
+        255 => "ANY".to_string(),
+        other => format!("T{other}"),
+    }
+}
+
+fn read_u16(packet: &[u8], pos: usize) -> Option<u16> {
+    packet
+        .get(pos..pos.checked_add(2)?)
+        .and_then(|b| b.try_into().ok())
+        .map(u16::from_be_bytes)
+}
+
+fn read_u32(packet: &[u8], pos: usize) -> Option<u32> {
+    packet
+        .get(pos..pos.checked_add(4)?)
+        .and_then(|b| b.try_into().ok())
+        .map(u32::from_be_bytes)
+}
+
+pub fn parse_header(packet: &[u8]) -> Option<DnsHeader> {
+    Some(DnsHeader {
+        id: read_u16(packet, 0)?,
+        flags: read_u16(packet, 2)?,
+        question_count: read_u16(packet, 4)?,
+        answer_count: read_u16(packet, 6)?,
+        authority_count: read_u16(packet, 8)?,
+        additional_count: read_u16(packet, 10)?,
+    })
+}
+
+/// Decode a (possibly compressed) name starting at offset.
+/// Returns the dotted name and the offset just past the name in the record.
+pub fn read_name(packet: &[u8], offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut pos = offset;
+    let mut end = None;
+    let mut follows = 0;
+
+    loop {
+        let len = *packet.get(pos)? as usize;
+        match len & 0xC0 {
+            0x00 if len == 0 => {
+                if name.is_empty() {
+                    name.push('.');
+                }
+                return Some((name, end.unwrap_or(pos + 1)));
+            }
+            0x00 => {
+                let label = packet.get(pos + 1..pos + 1 + len)?;
+                if name.len() + len + 1 > MAX_NAME_LENGTH {
+                    return None;
+                }
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(&String::from_utf8_lossy(label));
+                pos += 1 + len;
+            }
+            0xC0 => {
+                let low = *packet.get(pos + 1)? as usize;
+                follows += 1;
+                if follows > MAX_POINTER_FOLLOWS {
+                    return None;
+                }
+                end.get_or_insert(pos + 2);
+                pos = ((len & 0x3F) << 8) | low;
+            }
+            // 0x40 and 0x80 label types are reserved
+            _ => return None,
+        }
+    }
+}
+
+fn read_txt(rdata: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut pos = 0;
+    while let Some(&len) = rdata.get(pos) {
+        let Some(s) = rdata.get(pos + 1..pos + 1 + len as usize) else {
+            break;
+        };
+        strings.push(String::from_utf8_lossy(s).into_owned());
+        pos += 1 + len as usize;
+    }
+    strings
+}
+
+fn read_record(packet: &[u8], offset: usize, section: Section) -> Option<(Record, usize)> {
+    let (name, pos) = read_name(packet, offset)?;
+    let rtype = read_u16(packet, pos)?;
+    // Class is ignored; mDNS reuses the top bit for unicast-response/cache-flush.
+    read_u16(packet, pos + 2)?;
+
+    if section == Section::Question {
+        let record = Record {
+            section,
+            name,
+            rtype,
+            ttl: 0,
+            data: RecordData::None,
+        };
+        return Some((record, pos + 4));
+    }
+
+    let ttl = read_u32(packet, pos + 4)?;
+    let rdlength = read_u16(packet, pos + 8)? as usize;
+    let rdata_start = pos + 10;
+    let rdata = packet.get(rdata_start..rdata_start + rdlength)?;
+
+    let data = match rtype {
+        TYPE_A => RecordData::A(Ipv4Addr::from(<[u8; 4]>::try_from(rdata).ok()?)),
+        TYPE_AAAA => RecordData::Aaaa(Ipv6Addr::from(<[u8; 16]>::try_from(rdata).ok()?)),
+        TYPE_PTR => RecordData::Ptr(read_name(packet, rdata_start)?.0),
+        TYPE_SRV => RecordData::Srv {
+            priority: read_u16(rdata, 0)?,
+            weight: read_u16(rdata, 2)?,
+            port: read_u16(rdata, 4)?,
+            target: read_name(packet, rdata_start + 6)?.0,
+        },
+        TYPE_TXT => RecordData::Txt(read_txt(rdata)),
+        _ => RecordData::Other(rdlength),
+    };
+
+    let record = Record {
+        section,
+        name,
+        rtype,
+        ttl,
+        data,
+    };
+    Some((record, rdata_start + rdlength))
+}
+
+/// Decode as much of the message as possible.
+/// Returns None only when there isn't even a header.
+pub fn parse_message(packet: &[u8]) -> Option<DnsMessage> {
+    let header = parse_header(packet)?;
+
+    let sections = [
+        (Section::Question, header.question_count),
+        (Section::Answer, header.answer_count),
+        (Section::Authority, header.authority_count),
+        (Section::Additional, header.additional_count),
+    ];
+
+    let mut records = Vec::new();
+    let mut truncated = false;
+    let mut pos = HEADER_SIZE;
+
+    // Every decoded record advances pos by at least 5 bytes, and any
+    // decoding failure stops the walk, so this is bounded by the packet size.
+    'sections: for (section, count) in sections {
+        for _ in 0..count {
+            match read_record(packet, pos, section) {
+                Some((record, next)) => {
+                    records.push(record);
+                    pos = next;
+                }
+                None => {
+                    truncated = true;
+                    break 'sections;
+                }
+            }
+        }
+    }
+
+    Some(DnsMessage {
+        header,
+        records,
+        truncated,
+    })
+}
+
+/// Service names are the owner names of PTR records, e.g. _http._tcp.local
+pub fn service_names(message: &DnsMessage) -> impl Iterator<Item = &str> {
+    message
+        .records
+        .iter()
+        .filter(|record| record.rtype == TYPE_PTR && record.name.starts_with('_'))
+        .map(|record| record.name.as_str())
+}
+
+#[derive(Default)]
+pub struct MdnsState {
+    pub queries: u64,
+    pub responses: u64,
+    pub malformed: u64,
+    pub services: HashSet<String>,
+}
+
+impl MdnsState {
+    pub fn process(&mut self, packet: &[u8]) {
+        let Some(message) = parse_message(packet) else {
+            self.malformed += 1;
+            return;
+        };
+
+        if message.header.is_response() {
+            self.responses += 1;
+        } else {
+            self.queries += 1;
+        }
+
+        if message.truncated {
+            self.malformed += 1;
+        }
+
+        for service in service_names(&message) {
+            if !self.services.contains(service) {
+                self.services.insert(service.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn push_name(packet: &mut Vec<u8>, name: &str) {
+        for label in name.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+    }
+
+    // Response with a PTR answer whose rdata and the following SRV/A records
+    // use compression pointers back to the first name.
+    fn sample_response() -> Vec<u8> {
+        let mut packet = vec![0, 0, 0x84, 0x00, 0, 0, 0, 3, 0, 0, 0, 1];
+
+        // AN PTR _http._tcp.local -> web._http._tcp.local
+        push_name(&mut packet, "_http._tcp.local");
+        packet.extend_from_slice(&[0, 12, 0, 1, 0, 0, 0, 120, 0, 6]);
+        packet.extend_from_slice(&[3, b'w', b'e', b'b', 0xC0, 12]);
+
+        // AN SRV web._http._tcp.local -> host.local:8080
+        let instance = packet.len() - 6;
+        packet.extend_from_slice(&[0xC0, instance as u8]);
+        packet.extend_from_slice(&[0, 33, 0x80, 1, 0, 0, 0, 120, 0, 18]);
+        packet.extend_from_slice(&[0, 0, 0, 0, 0x1F, 0x90]);
+        push_name(&mut packet, "host.local");
+
+        // AN TXT web._http._tcp.local
+        packet.extend_from_slice(&[0xC0, instance as u8]);
+        packet.extend_from_slice(&[0, 16, 0x80, 1, 0, 0, 0, 120, 0, 7]);
+        packet.extend_from_slice(&[6, b'p', b'a', b't', b'h', b'=', b'/']);
+
+        // AR A host.local -> 192.168.1.5
+        push_name(&mut packet, "host.local");
+        packet.extend_from_slice(&[0, 1, 0x80, 1, 0, 0, 0, 120, 0, 4, 192, 168, 1, 5]);
+
+        packet
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let packet = sample_response();
+        let message = parse_message(&packet).expect("header");
+
+        assert!(message.header.is_response());
+        assert!(!message.truncated);
+        assert_eq!(message.records.len(), 4);
+
+        let ptr = message.records.first().expect("ptr");
+        assert_eq!(ptr.name, "_http._tcp.local");
+        assert_eq!(
+            ptr.data,
+            RecordData::Ptr("web._http._tcp.local".to_string())
+        );
+
+        let srv = message.records.get(1).expect("srv");
+        assert_eq!(srv.name, "web._http._tcp.local");
+        assert_eq!(
+            srv.data,
+            RecordData::Srv {
+                priority: 0,
+                weight: 0,
+                port: 8080,
+                target: "host.local".to_string()
+            }
+        );
+
+        let txt = message.records.get(2).expect("txt");
+        assert_eq!(txt.data, RecordData::Txt(vec!["path=/".to_string()]));
+
+        let a = message.records.get(3).expect("a");
+        assert_eq!(a.section, Section::Additional);
+        assert_eq!(a.data, RecordData::A(Ipv4Addr::new(192, 168, 1, 5)));
+
+        assert_eq!(
+            service_names(&message).collect::<Vec<_>>(),
+            vec!["_http._tcp.local"]
+        );
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        push_name(&mut packet, "_ipp._tcp.local");
+        packet.extend_from_slice(&[0, 12, 0x80, 1]);
+
+        let mut state = MdnsState::default();
+        state.process(&packet);
+        assert_eq!(state.queries, 1);
+        assert_eq!(state.responses, 0);
+        assert_eq!(state.malformed, 0);
+
+        // Services asked about count as well as services advertised
+        state.process(&sample_response());
+        assert_eq!(state.responses, 1);
+        assert_eq!(state.services.len(), 2);
+    }
+
+    #[test]
+    fn test_pointer_loop() {
+        // Question name is a pointer to itself
+        let packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xC0, 12, 0, 1, 0, 1];
+        assert!(read_name(&packet, 12).is_none());
+
+        let message = parse_message(&packet).expect("header");
+        assert!(message.truncated);
+        assert!(message.records.is_empty());
+
+        // Two pointers bouncing between each other
+        let packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xC0, 14, 0xC0, 12];
+        assert!(read_name(&packet, 12).is_none());
+    }
+
+    #[test]
+    fn test_truncated_records() {
+        let full = sample_response();
+        for len in 0..full.len() {
+            let Some(truncated) = full.get(..len) else {
+                continue;
+            };
+            match parse_message(truncated) {
+                None => assert!(len < HEADER_SIZE),
+                Some(message) => {
+                    assert!(message.truncated);
+                    assert!(message.records.len() < 4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_huge_counts() {
+        // Header claims 65535 of everything but carries nothing
+        let packet = vec![
+            0, 0, 0x84, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        let message = parse_message(&packet).expect("header");
+        assert!(message.truncated);
+        assert!(message.records.is_empty());
+    }
+
+    #[test]
+    fn test_fuzz_malformed() {
+        // Deterministic xorshift so failures are reproducible
+        let mut seed = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        let template = sample_response();
+        for _ in 0..2000 {
+            // Random garbage
+            let len = (next() % 512) as usize;
+            let garbage: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+            let _ = parse_message(&garbage);
+
+            // A valid packet with a few bytes flipped
+            let mut mutated = template.clone();
+            for _ in 0..4 {
+                let idx = (next() as usize) % mutated.len();
+                if let Some(byte) = mutated.get_mut(idx) {
+                    *byte = next() as u8;
+                }
+            }
+            if let Some(message) = parse_message(&mutated) {
+                for record in &message.records {
+                    assert!(record.name.len() <= MAX_NAME_LENGTH);
+                }
+            }
+        }
+    }
+}
@@ -1,10 +1,42 @@
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
 use std::os::fd::{AsRawFd, RawFd};
 
+use nix::errno::Errno;
 use nix::ifaddrs::getifaddrs;
 use socket2::{Domain, Protocol, Socket, Type};
 
-use crate::error::{LibError, Result};
+use crate::{
+    diagnose,
+    error::{LibError, Result},
+};
+
+// Large receiver buffer (256MB) to handle higher packet rates
+pub const RECV_BUFFER_BYTES: usize = 256 * 1024 * 1024;
+
+/// What the address given for a group is, which decides how its sockets
+/// are set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressKind {
+    /// 224.0.0.0/4: joined to receive, sent to through IP_MULTICAST_IF
+    Multicast,
+    /// Any other host address: bound to receive, connected to to send.
+    /// 0.0.0.0 receives on every address of this host.
+    Unicast,
+}
+
+/// Tell a group from a unicast address. Broadcast needs SO_BROADCAST and
+/// isn't supported.
+pub fn classify(addr: &Ipv4Addr) -> Result<AddressKind> {
+    if addr.is_multicast() {
+        Ok(AddressKind::Multicast)
+    } else if addr.is_broadcast() {
+        Err(LibError::Critical(format!(
+            "{addr} is broadcast, give a multicast group (224.0.0.0/4) or a unicast address"
+        )))
+    } else {
+        Ok(AddressKind::Unicast)
+    }
+}
 
 pub fn create_recv_socket(iface: Option<&str>, mgroup: &str, port: u16) -> Result<Socket> {
     let mcast_addr: Ipv4Addr = mgroup.parse()?;
@@ -12,20 +44,28 @@ pub fn create_recv_socket(iface: Option<&str>, mgroup: &str, port: u16) -> Resul
     let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
     socket.set_reuse_address(true)?;
 
-    // Large receiver buffer (256MB) to handle higher packet rates
-    set_recv_buffer_size(&socket, 256 * 1024 * 1024)?;
+    set_recv_buffer_size(&socket, RECV_BUFFER_BYTES)?;
 
-    // Let the kernel determine the default address if not specified by user
-    let iface_addr = if let Some(iface_name) = iface {
-        get_interface_addr(iface_name)?
-    } else {
-        get_default_interface_for_multicast(&mcast_addr)?
-    };
+    if classify(&mcast_addr)? == AddressKind::Unicast {
+        bind_unicast(&socket, mcast_addr, port)?;
+        socket.set_nonblocking(false)?;
+        socket.set_read_timeout(Some(std::time::Duration::from_millis(100)))?;
+        return Ok(socket);
+    }
+
+    let iface_addr = recv_interface(iface, &mcast_addr)?;
 
     // IP_MULTICAST_IF
     socket.set_multicast_if_v4(&iface_addr)?;
 
-    let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+    // Bound to INADDR_ANY, the socket also gets other groups joined on this
+    // host for the same port, told apart by IP_PKTINFO. Without it, bound to
+    // the group, the kernel only hands over the group's own.
+    #[cfg(mmsg)]
+    let bind_ip = Ipv4Addr::UNSPECIFIED;
+    #[cfg(not(mmsg))]
+    let bind_ip = mcast_addr;
+    let bind_addr = SocketAddr::new(IpAddr::V4(bind_ip), port);
     socket.bind(&bind_addr.into())?;
 
     // IP_ADD_MEMBERSHIP
@@ -37,51 +77,328 @@ pub fn create_recv_socket(iface: Option<&str>, mgroup: &str, port: u16) -> Resul
     Ok(socket)
 }
 
+/// Unicast receives on the address itself, one of this host's, so a
+/// remote one is refused here rather than by a bare EADDRNOTAVAIL.
+fn bind_unicast(socket: &Socket, addr: Ipv4Addr, port: u16) -> Result<()> {
+    let bind_addr = SocketAddr::new(IpAddr::V4(addr), port);
+    match socket.bind(&bind_addr.into()) {
+        Ok(()) => Ok(()),
+        Err(e) if e.raw_os_error() == Some(Errno::EADDRNOTAVAIL as i32) => {
+            Err(LibError::Critical(format!(
+                "{addr} is not an address of this host, receive unicast on one of its own or on 0.0.0.0"
+            )))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Join mgroup on a socket systemd bound already, for socket activation.
+/// Joining a group the socket is already in is fine. A unicast socket is
+/// taken as systemd bound it.
+pub fn adopt_recv_socket(socket: Socket, iface: Option<&str>, mgroup: &str) -> Result<Socket> {
+    let mcast_addr: Ipv4Addr = mgroup.parse()?;
+
+    set_recv_buffer_size(&socket, RECV_BUFFER_BYTES)?;
+
+    if classify(&mcast_addr)? == AddressKind::Multicast {
+        let iface_addr = recv_interface(iface, &mcast_addr)?;
+        socket.set_multicast_if_v4(&iface_addr)?;
+        match socket.join_multicast_v4(&mcast_addr, &iface_addr) {
+            Ok(()) => {}
+            Err(e) if e.raw_os_error() == Some(Errno::EADDRINUSE as i32) => {
+                log::debug!("socket from systemd is already in {mcast_addr}")
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    socket.set_nonblocking(false)?;
+    socket.set_read_timeout(Some(std::time::Duration::from_millis(100)))?;
+
+    Ok(socket)
+}
+
+/// Read back the interface a receive socket was set to and look for the
+/// group in /proc/net/igmp, to confirm the join took where it was meant to.
+/// What doesn't match is warned about; the join isn't undone.
+pub fn verify_join(socket: &Socket, iface: Option<&str>, mcast_addr: &Ipv4Addr) -> Result<()> {
+    let expected = recv_interface(iface, mcast_addr)?;
+    let actual = socket.multicast_if_v4()?;
+    if actual != expected {
+        log::warn!("IP_MULTICAST_IF reads back {actual}, not {expected}");
+    }
+
+    let name = getifaddrs()?
+        .find(|ifaddr| {
+            ifaddr
+                .address
+                .as_ref()
+                .and_then(|address| address.as_sockaddr_in())
+                .is_some_and(|sockaddr| sockaddr.ip() == expected)
+        })
+        .map(|ifaddr| ifaddr.interface_name);
+    let contents = match std::fs::read_to_string("/proc/net/igmp") {
+        Ok(contents) => contents,
+        Err(e) => {
+            log::debug!("join not checked, /proc/net/igmp: {e}");
+            return Ok(());
+        }
+    };
+    let joined: Vec<String> = diagnose::parse_igmp(&contents)
+        .into_iter()
+        .filter(|(_, group)| group == mcast_addr)
+        .map(|(device, _)| device)
+        .collect();
+    match name {
+        Some(name) if joined.contains(&name) => {
+            log::info!("joined {mcast_addr} on {name} ({expected})")
+        }
+        Some(name) if joined.is_empty() => {
+            log::warn!("{mcast_addr} is not in /proc/net/igmp, the join on {name} didn't take")
+        }
+        Some(name) => log::warn!(
+            "{mcast_addr} is joined on {}, not on {name}",
+            joined.join(", ")
+        ),
+        None => log::warn!("no interface has {expected}, the address joined on"),
+    }
+    Ok(())
+}
+
+/// Let the kernel determine the default address if not specified by user
+fn recv_interface(iface: Option<&str>, mcast_addr: &Ipv4Addr) -> Result<Ipv4Addr> {
+    match iface {
+        Some(iface_name) => get_interface_addr(iface_name),
+        None => get_default_interface_for_multicast(mcast_addr),
+    }
+}
+
+// Most groups a destination list or range may expand to.
+pub const MAX_GROUPS: usize = 1024;
+
+// Most ports to receive on at once, each is a socket of its own.
+pub const MAX_PORTS: usize = 64;
+
 pub fn create_send_socket(iface: Option<&str>, mgroup: &str, port: u16, ttl: u8) -> Result<Socket> {
     let mcast_addr: Ipv4Addr = mgroup.parse()?;
+    let socket = create_unconnected_send_socket(iface, &mcast_addr, ttl)?;
 
+    let dest_addr = SocketAddr::new(IpAddr::V4(mcast_addr), port);
+    socket.connect(&dest_addr.into())?;
+
+    Ok(socket)
+}
+
+/// A send socket for several groups: every message carries its own
+/// destination. mcast_addr only picks the interface when iface is None.
+/// For a unicast address the route picks it, and iface only the source
+/// address.
+pub fn create_unconnected_send_socket(
+    iface: Option<&str>,
+    mcast_addr: &Ipv4Addr,
+    ttl: u8,
+) -> Result<Socket> {
     let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
     socket.set_reuse_address(true)?;
 
+    if classify(mcast_addr)? == AddressKind::Unicast {
+        let source = match iface {
+            Some(iface_name) => get_interface_addr(iface_name)?,
+            None => Ipv4Addr::UNSPECIFIED,
+        };
+        socket.bind(&SocketAddr::new(IpAddr::V4(source), 0).into())?;
+        socket.set_ttl(ttl.into())?;
+        socket.set_nonblocking(false)?;
+        return Ok(socket);
+    }
+
     // Let the kernel determine the default address if not specified by user
     let iface_addr = if let Some(iface_name) = iface {
         get_interface_addr(iface_name)?
     } else {
-        get_default_interface_for_multicast(&mcast_addr)?
+        get_default_interface_for_multicast(mcast_addr)?
     };
 
     // IP_MULTICAST_IF
     socket.set_multicast_if_v4(&iface_addr)?;
 
+    // Bound now rather than on the first send, so the port packets leave from
+    // is known before they do, see NetworkSink::source
+    socket.bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0).into())?;
+
     // Useful troublehooting for network engineers
     socket.set_multicast_ttl_v4(ttl.into())?;
 
-    let dest_addr = SocketAddr::new(IpAddr::V4(mcast_addr), port);
-    socket.connect(&dest_addr.into())?;
-
     socket.set_nonblocking(false)?;
 
     Ok(socket)
 }
 
+/// Expand a port expression into ports, in order.
+/// Comma separated, each a port or a range like 29495-29498.
+pub fn parse_ports(expr: &str) -> Result<Vec<u16>> {
+    let port = |s: &str| {
+        s.parse::<u16>()
+            .map_err(|_| LibError::Critical(format!("{s}: not a port")))
+    };
+    let mut ports = Vec::new();
+
+    for item in expr.split(',') {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => (port(first)?, port(last)?),
+            None => {
+                let port = port(item)?;
+                (port, port)
+            }
+        };
+        if last < first {
+            return Err(LibError::Critical(format!("{item}: range runs backwards")));
+        }
+        if (last - first) as usize >= MAX_PORTS.saturating_sub(ports.len()) {
+            return Err(LibError::Critical(format!(
+                "{expr}: more than {MAX_PORTS} ports"
+            )));
+        }
+        for port in first..=last {
+            if ports.contains(&port) {
+                return Err(LibError::Critical(format!("{expr}: {port} is given twice")));
+            }
+            ports.push(port);
+        }
+    }
+
+    Ok(ports)
+}
+
+/// Expand a destination expression into groups, in order.
+/// Comma separated, each a group or a range: 239.1.1.1-16 counts up the last
+/// octet, 239.1.1.250-239.1.2.5 may cross octets.
+/// A lone address may be unicast as well.
+pub fn parse_groups(expr: &str) -> Result<Vec<Ipv4Addr>> {
+    if let Ok(addr) = expr.parse::<Ipv4Addr>() {
+        classify(&addr)?;
+        return Ok(vec![addr]);
+    }
+
+    let mut groups = Vec::new();
+
+    for item in expr.split(',') {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => {
+                let first: Ipv4Addr = first.parse()?;
+                let last = match last.parse::<u8>() {
+                    Ok(octet) => {
+                        let [a, b, c, _] = first.octets();
+                        Ipv4Addr::new(a, b, c, octet)
+                    }
+                    Err(_) => last.parse()?,
+                };
+                (first, last)
+            }
+            None => {
+                let group: Ipv4Addr = item.parse()?;
+                (group, group)
+            }
+        };
+
+        let (start, end) = (u32::from(first), u32::from(last));
+        if end < start {
+            return Err(LibError::Critical(format!("{item}: range runs backwards")));
+        }
+        if (end - start) as usize >= MAX_GROUPS.saturating_sub(groups.len()) {
+            return Err(LibError::Critical(format!(
+                "{expr}: more than {MAX_GROUPS} groups"
+            )));
+        }
+
+        for addr in start..=end {
+            let group = Ipv4Addr::from(addr);
+            if !group.is_multicast() {
+                return Err(LibError::Critical(format!(
+                    "{group} is not a multicast address (224.0.0.0/4)"
+                )));
+            }
+            groups.push(group);
+        }
+    }
+
+    Ok(groups)
+}
+
 pub fn socket_to_raw_fd(socket: &Socket) -> RawFd {
     socket.as_raw_fd()
 }
 
-pub fn get_interface_addr(iface_name: &str) -> Result<Ipv4Addr> {
-    for ifaddr in getifaddrs()? {
-        if ifaddr.interface_name == iface_name
-            && let Some(address) = ifaddr.address
-            && let Some(sockaddr) = address.as_sockaddr_in()
-        {
-            let ip_bits = sockaddr.ip();
-            return Ok(ip_bits);
+/// Split `eth0/10.1.2.3` into the interface name and the address pinned on it.
+pub fn split_iface(iface: &str) -> Result<(&str, Option<Ipv4Addr>)> {
+    match iface.split_once('/') {
+        Some((name, address)) => {
+            let address = address.parse().map_err(|_| {
+                LibError::Critical(format!("{iface}: {address} is not an IPv4 address"))
+            })?;
+            Ok((name, Some(address)))
         }
+        None => Ok((iface, None)),
+    }
+}
+
+/// The address to join and send from on an interface, given as `name` or
+/// `name/address`. See [`choose_address`] for which one wins.
+pub fn get_interface_addr(iface: &str) -> Result<Ipv4Addr> {
+    let (name, pinned) = split_iface(iface)?;
+    let candidates: Vec<(Ipv4Addr, u32)> = getifaddrs()?
+        .filter(|ifaddr| ifaddr.interface_name == name)
+        .filter_map(|ifaddr| {
+            let addr = ifaddr.address.as_ref()?.as_sockaddr_in()?.ip();
+            let prefix = ifaddr
+                .netmask
+                .as_ref()
+                .and_then(|netmask| netmask.as_sockaddr_in())
+                .map_or(32, |netmask| netmask.ip().to_bits().count_ones());
+            Some((addr, prefix))
+        })
+        .collect();
+
+    let chosen = choose_address(name, &candidates, pinned)?;
+    // Only worth saying when there was a choice to make
+    if candidates.len() > 1 {
+        let all: Vec<String> = candidates
+            .iter()
+            .map(|(addr, prefix)| format!("{addr}/{prefix}"))
+            .collect();
+        log::info!("{name}: using {chosen} of {}", all.join(", "));
+    } else {
+        log::debug!("{name}: using {chosen}");
+    }
+    Ok(chosen)
+}
+
+/// Pick from an interface's addresses and prefix lengths, in the order the
+/// kernel lists them (primaries before their secondaries). A pinned address
+/// must be one of them. Otherwise the first that isn't a /32 wins, since a
+/// /32 next to a subnet address is a VIP and joins sent from it tend to be
+/// filtered upstream.
+pub fn choose_address(
+    name: &str,
+    candidates: &[(Ipv4Addr, u32)],
+    pinned: Option<Ipv4Addr>,
+) -> Result<Ipv4Addr> {
+    if let Some(pinned) = pinned {
+        return candidates
+            .iter()
+            .any(|(addr, _)| *addr == pinned)
+            .then_some(pinned)
+            .ok_or_else(|| LibError::Critical(format!("{pinned} is not an address of {name}")));
     }
 
-    Err(LibError::Critical(format!(
-        "Interface {iface_name} not found or has no IPv4 address"
-    )))
+    candidates
+        .iter()
+        .find(|(_, prefix)| *prefix < 32)
+        .or(candidates.first())
+        .map(|(addr, _)| *addr)
+        .ok_or_else(|| {
+            LibError::Critical(format!("Interface {name} not found or has no IPv4 address"))
+        })
 }
 
 pub fn get_default_interface_for_multicast(mcast_addr: &Ipv4Addr) -> Result<Ipv4Addr> {
@@ -110,3 +427,125 @@ pub fn set_recv_buffer_size(socket: &Socket, size: usize) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_address() {
+        let primary = Ipv4Addr::new(10, 1, 2, 3);
+        let vip = Ipv4Addr::new(10, 9, 9, 9);
+        let secondary = Ipv4Addr::new(10, 1, 2, 4);
+
+        // The VIP listed first still loses to the subnet address
+        let candidates = [(vip, 32), (primary, 24), (secondary, 24)];
+        assert_eq!(
+            choose_address("eth0", &candidates, None).expect("primary"),
+            primary
+        );
+        assert_eq!(
+            choose_address("eth0", &candidates, Some(vip)).expect("pinned"),
+            vip
+        );
+        assert!(choose_address("eth0", &candidates, Some(Ipv4Addr::LOCALHOST)).is_err());
+
+        // Nothing but /32s, so the first
+        assert_eq!(
+            choose_address("lo", &[(vip, 32), (primary, 32)], None).expect("first"),
+            vip
+        );
+        assert!(choose_address("eth0", &[], None).is_err());
+    }
+
+    #[test]
+    fn test_split_iface() {
+        assert_eq!(split_iface("eth0.100").expect("vlan"), ("eth0.100", None));
+        assert_eq!(
+            split_iface("eth0/10.1.2.3").expect("pinned"),
+            ("eth0", Some(Ipv4Addr::new(10, 1, 2, 3)))
+        );
+        assert!(split_iface("eth0/10.1.2").is_err());
+    }
+
+    #[test]
+    fn test_parse_groups() {
+        let group = |d| Ipv4Addr::new(239, 1, 1, d);
+
+        assert_eq!(parse_groups("239.1.1.1").expect("one"), vec![group(1)]);
+        assert_eq!(
+            parse_groups("239.1.1.1,239.1.1.9").expect("list"),
+            vec![group(1), group(9)]
+        );
+        assert_eq!(
+            parse_groups("239.1.1.1-16").expect("range"),
+            (1..=16).map(group).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            parse_groups("239.1.1.255-239.1.2.1").expect("across octets"),
+            vec![
+                group(255),
+                Ipv4Addr::new(239, 1, 2, 0),
+                Ipv4Addr::new(239, 1, 2, 1)
+            ]
+        );
+        assert_eq!(
+            parse_groups("239.1.1.3,239.1.1.1-2").expect("mixed").len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_classify() {
+        for (addr, kind) in [
+            ([239, 1, 1, 1], AddressKind::Multicast),
+            ([224, 0, 0, 251], AddressKind::Multicast),
+            ([239, 255, 255, 255], AddressKind::Multicast),
+            ([10, 1, 1, 1], AddressKind::Unicast),
+            ([127, 0, 0, 1], AddressKind::Unicast),
+            ([223, 255, 255, 255], AddressKind::Unicast),
+            ([240, 0, 0, 1], AddressKind::Unicast),
+            ([0, 0, 0, 0], AddressKind::Unicast),
+        ] {
+            assert_eq!(classify(&Ipv4Addr::from(addr)).ok(), Some(kind), "{addr:?}");
+        }
+        assert!(classify(&Ipv4Addr::BROADCAST).is_err());
+    }
+
+    #[test]
+    fn test_parse_groups_rejects() {
+        assert!(parse_groups("239.1.1.9-2").is_err());
+        // Unicast only on its own
+        assert_eq!(
+            parse_groups("10.1.1.1").expect("unicast"),
+            vec![Ipv4Addr::new(10, 1, 1, 1)]
+        );
+        assert!(parse_groups("10.1.1.1,10.1.1.2").is_err());
+        assert!(parse_groups("10.1.1.1-4").is_err());
+        assert!(parse_groups("239.1.1.1,10.1.1.1").is_err());
+        assert!(parse_groups("255.255.255.255").is_err());
+        assert!(parse_groups("239.255.255.255-240.0.0.1").is_err());
+        assert!(parse_groups("239.0.0.0-239.1.0.0").is_err());
+        assert!(parse_groups("239.1.1.1,").is_err());
+    }
+
+    #[test]
+    fn test_parse_ports() {
+        assert_eq!(parse_ports("29495").expect("one"), vec![29495]);
+        assert_eq!(
+            parse_ports("29495-29498").expect("range"),
+            vec![29495, 29496, 29497, 29498]
+        );
+        assert_eq!(
+            parse_ports("5000,29495-29496").expect("mixed"),
+            vec![5000, 29495, 29496]
+        );
+
+        assert!(parse_ports("29498-29495").is_err());
+        assert!(parse_ports("5000,5000").is_err());
+        assert!(parse_ports("1-65535").is_err());
+        assert!(parse_ports("70000").is_err());
+        assert!(parse_ports("5000,").is_err());
+    }
+}
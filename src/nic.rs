@@ -0,0 +1,164 @@
+/// Drops below the socket, for the stats line: SO_RXQ_OVFL only counts
+/// packets the socket queue had no room for, while a NIC that runs out of
+/// ring buffers drops them before the kernel sees them at all. Those show up
+/// in /sys/class/net/IFACE/statistics, which are sampled here. A driver that
+/// doesn't keep a counter has no file for it, and it's left off.
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const SYSFS_NET: &str = "/sys/class/net";
+
+/// The counters read, and the names they're shown under on the stats line.
+const COUNTERS: [(&str, &str); 3] = [
+    ("rx_dropped", "dropped"),
+    ("rx_missed_errors", "missed"),
+    ("rx_fifo_errors", "fifo"),
+];
+
+/// One interface's drop counters, the files kept open so --sandbox doesn't
+/// stop them being read later.
+#[derive(Debug)]
+pub struct NicCounters {
+    iface: String,
+    files: Vec<(&'static str, &'static str, File)>,
+}
+
+/// One reading of each counter there is, in [`COUNTERS`] order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NicSample(Vec<(&'static str, &'static str, u64)>);
+
+impl NicCounters {
+    /// The counters of iface, or None if it has none of them.
+    pub fn open(iface: &str) -> Option<Self> {
+        Self::open_in(Path::new(SYSFS_NET), iface)
+    }
+
+    fn open_in(root: &Path, iface: &str) -> Option<Self> {
+        let statistics = root.join(iface).join("statistics");
+        let files: Vec<_> = COUNTERS
+            .iter()
+            .filter_map(|(counter, short)| {
+                let file = File::open(statistics.join(counter)).ok()?;
+                Some((*counter, *short, file))
+            })
+            .collect();
+        if files.is_empty() {
+            log::debug!("{iface}: no drop counters in {}", statistics.display());
+            return None;
+        }
+        Some(Self {
+            iface: iface.to_string(),
+            files,
+        })
+    }
+
+    pub fn iface(&self) -> &str {
+        &self.iface
+    }
+
+    /// Each counter's value now. One that can't be read is left out.
+    pub fn sample(&mut self) -> NicSample {
+        let mut text = String::new();
+        NicSample(
+            self.files
+                .iter_mut()
+                .filter_map(|(counter, short, file)| {
+                    text.clear();
+                    file.seek(SeekFrom::Start(0)).ok()?;
+                    file.read_to_string(&mut text).ok()?;
+                    Some((*counter, *short, text.trim().parse().ok()?))
+                })
+                .collect(),
+        )
+    }
+}
+
+impl NicSample {
+    /// "  nic: dropped 3 missed 0 fifo 0", each counter's rise since last.
+    /// Empty with no counters.
+    pub fn format_since(&self, last: &NicSample) -> String {
+        if self.0.is_empty() {
+            return String::new();
+        }
+        let counts: Vec<String> = self
+            .0
+            .iter()
+            .map(|(counter, short, value)| {
+                let before = last
+                    .0
+                    .iter()
+                    .find(|(last_counter, _, _)| last_counter == counter)
+                    .map_or(*value, |(_, _, before)| *before);
+                // Counters restart from zero when the driver is reloaded
+                format!("{short} {}", value.saturating_sub(before))
+            })
+            .collect();
+        format!("  nic: {}", counts.join(" "))
+    }
+
+    /// "rx_dropped 1204 (+3), rx_fifo_errors 0 (+0)" for the summary: the
+    /// interface's totals, and how much of that was since start.
+    pub fn format_totals(&self, start: &NicSample) -> String {
+        self.0
+            .iter()
+            .map(|(counter, _, value)| {
+                let before = start
+                    .0
+                    .iter()
+                    .find(|(start_counter, _, _)| start_counter == counter)
+                    .map_or(*value, |(_, _, before)| *before);
+                format!("{counter} {value} (+{})", value.saturating_sub(before))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    fn fake_sysfs(name: &str, counters: &[(&str, &str)]) -> std::path::PathBuf {
+        let root = std::env::temp_dir().join(format!("mnc-nic-{name}-{}", std::process::id()));
+        let statistics = root.join("eth9").join("statistics");
+        std::fs::create_dir_all(&statistics).expect("mkdir");
+        for (counter, value) in counters {
+            std::fs::write(statistics.join(counter), value).expect("write");
+        }
+        root
+    }
+
+    #[test]
+    fn test_deltas_and_totals() {
+        let root = fake_sysfs(
+            "deltas",
+            &[("rx_dropped", "100\n"), ("rx_fifo_errors", "7\n")],
+        );
+        let mut nic = NicCounters::open_in(&root, "eth9").expect("counters");
+        assert_eq!(nic.iface(), "eth9");
+        let start = nic.sample();
+        assert_eq!(start.format_since(&start), "  nic: dropped 0 fifo 0");
+
+        let statistics = root.join("eth9").join("statistics");
+        std::fs::write(statistics.join("rx_dropped"), "104\n").expect("write");
+        let now = nic.sample();
+        // rx_missed_errors has no file, so it's left off
+        assert_eq!(now.format_since(&start), "  nic: dropped 4 fifo 0");
+        assert_eq!(
+            now.format_totals(&start),
+            "rx_dropped 104 (+4), rx_fifo_errors 7 (+0)"
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_no_counters() {
+        let root = fake_sysfs("none", &[]);
+        assert!(NicCounters::open_in(&root, "eth9").is_none());
+        assert!(NicCounters::open_in(&root, "missing0").is_none());
+        assert_eq!(NicSample::default().format_since(&NicSample::default()), "");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
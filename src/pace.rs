@@ -0,0 +1,189 @@
+/// --pps: a token bucket that holds packets back to a rate rather than
+/// dropping them, the way --police does. Tokens come in at the rate, up to
+/// the burst, and each packet sent takes one; short of tokens, the sender
+/// sleeps until enough have come in rather than spinning.
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::TrySendError;
+
+use crate::{SharedState, packet::Packets, transport::BatchSender};
+
+// Packets go out a millisecond's worth at a time, so high rates still fill
+// sendmmsg batches.
+const SLICE: Duration = Duration::from_millis(1);
+
+// The burst unless one is given. A sleep that runs long earns tokens that a
+// bucket only a slice deep would throw away, so it holds several slices and
+// the rate still averages out.
+const DEFAULT_BURST: Duration = Duration::from_millis(10);
+
+// Longest sleep between looks at whether it's time to exit.
+const EXIT_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+pub struct TokenBucket {
+    // Most tokens the bucket holds, DEFAULT_BURST's worth when None
+    burst: Option<u64>,
+    tokens: f64,
+    // When tokens was last topped up, None until pacing starts
+    refilled: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub fn new(burst: Option<u64>) -> Self {
+        Self {
+            burst,
+            ..Default::default()
+        }
+    }
+
+    /// Start afresh with a full bucket the next time, after time unpaced.
+    pub fn reset(&mut self) {
+        self.refilled = None;
+    }
+
+    /// How many of wanted packets may go at now at pps, taking their
+    /// tokens, or how long until a slice of them may. Starts with a full
+    /// bucket.
+    pub fn grant(&mut self, now: Instant, pps: u64, wanted: usize) -> Result<usize, Duration> {
+        let pps = pps.max(1) as f64;
+        let slice = (pps * SLICE.as_secs_f64()).max(1.0);
+        let burst = self
+            .burst
+            .map_or(pps * DEFAULT_BURST.as_secs_f64(), |burst| burst as f64)
+            .max(1.0);
+        let slice = slice.min(burst).min(wanted as f64).max(1.0).floor();
+
+        self.tokens = match self.refilled {
+            Some(refilled) => {
+                let elapsed = now.saturating_duration_since(refilled).as_secs_f64();
+                (self.tokens + elapsed * pps).min(burst)
+            }
+            None => burst,
+        };
+        self.refilled = Some(now);
+
+        if self.tokens >= slice {
+            self.tokens -= slice;
+            Ok(slice as usize)
+        } else {
+            Err(Duration::from_secs_f64((slice - self.tokens) / pps))
+        }
+    }
+
+    /// Sleep until some of wanted packets may go at pps and return how
+    /// many, or 0 if exit was signaled first.
+    pub fn take(&mut self, pps: u64, wanted: usize, shared_state: &SharedState) -> usize {
+        loop {
+            if shared_state.should_exit() {
+                return 0;
+            }
+            match self.grant(Instant::now(), pps, wanted) {
+                Ok(granted) => return granted,
+                Err(wait) => thread::sleep(wait.min(EXIT_POLL)),
+            }
+        }
+    }
+}
+
+/// The reader's data channel at --pps: each batch waits for its packets'
+/// tokens before going on, so a file is read at the rate and the reader
+/// isn't done until the last packet is due.
+pub struct PacedSender {
+    data_tx: Box<dyn BatchSender>,
+    bucket: TokenBucket,
+    pps: u64,
+    shared_state: SharedState,
+}
+
+impl PacedSender {
+    pub fn new(
+        data_tx: Box<dyn BatchSender>,
+        pps: u64,
+        burst: Option<u64>,
+        shared_state: SharedState,
+    ) -> Self {
+        Self {
+            data_tx,
+            bucket: TokenBucket::new(burst),
+            pps,
+            shared_state,
+        }
+    }
+}
+
+impl BatchSender for PacedSender {
+    fn try_push(&mut self, packets: Packets) -> Result<(), TrySendError<Packets>> {
+        let mut due = 0;
+        while due < packets.len() {
+            match self
+                .bucket
+                .take(self.pps, packets.len() - due, &self.shared_state)
+            {
+                // Exiting, whatever is left goes at once
+                0 => break,
+                granted => due += granted,
+            }
+        }
+        self.data_tx.try_push(packets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grant() {
+        let mut bucket = TokenBucket::new(None);
+        let start = Instant::now();
+
+        // A millisecond's worth at a time, starting with ten of them
+        for _ in 0..10 {
+            assert_eq!(bucket.grant(start, 100_000, 500), Ok(100));
+        }
+        assert_eq!(bucket.grant(start, 100_000, 500), Err(SLICE));
+        let later = start + Duration::from_micros(500);
+        assert_eq!(
+            bucket.grant(later, 100_000, 500),
+            Err(Duration::from_micros(500))
+        );
+        assert_eq!(
+            bucket.grant(later + Duration::from_micros(500), 100_000, 30),
+            Ok(30)
+        );
+
+        // A sleep that ran long is made up for
+        let overslept = later + Duration::from_micros(1800);
+        assert_eq!(bucket.grant(overslept, 100_000, 500), Ok(100));
+        assert_eq!(bucket.grant(overslept, 100_000, 500), Ok(100));
+
+        // One at a time at low rates, 100ms apart at 10 pps
+        let mut bucket = TokenBucket::new(None);
+        assert_eq!(bucket.grant(start, 10, 5), Ok(1));
+        assert_eq!(bucket.grant(start, 10, 5), Err(Duration::from_millis(100)));
+        let later = start + Duration::from_millis(100);
+        assert_eq!(bucket.grant(later, 10, 5), Ok(1));
+
+        // A long pause earns no more than the burst
+        let mut bucket = TokenBucket::new(Some(3));
+        assert_eq!(bucket.grant(start, 10, 5), Ok(1));
+        let much_later = start + Duration::from_secs(60);
+        let granted: usize = (0..5)
+            .map_while(|_| bucket.grant(much_later, 10, 1).ok())
+            .sum();
+        assert_eq!(granted, 3);
+    }
+
+    #[test]
+    fn test_take_gives_way_to_exit() {
+        let shared_state = SharedState::new(crate::PacketType::Binary, false);
+        let mut bucket = TokenBucket::new(None);
+        assert_eq!(bucket.take(1, 1, &shared_state), 1);
+        shared_state.signal_exit();
+        let started = Instant::now();
+        assert_eq!(bucket.take(1, 1, &shared_state), 0);
+        assert!(started.elapsed() < EXIT_POLL);
+    }
+}
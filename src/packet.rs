@@ -1,4 +1,6 @@
+use std::net::SocketAddrV4;
 use std::ops::Deref;
+use std::time::SystemTime;
 
 // Currently we only support header parsing for these types.
 // Hopefuly we can add more in the future.
@@ -10,6 +12,7 @@ pub enum PacketType {
     Binary,
     Vita49,
     Sdds,
+    Mdns,
 }
 
 impl std::fmt::Display for PacketType {
@@ -19,6 +22,7 @@ impl std::fmt::Display for PacketType {
             PacketType::Binary => write!(f, "binary"),
             PacketType::Vita49 => write!(f, "vita49"),
             PacketType::Sdds => write!(f, "sdds"),
+            PacketType::Mdns => write!(f, "mdns"),
         }
     }
 }
@@ -29,6 +33,12 @@ impl std::fmt::Display for PacketType {
 pub struct Packet {
     data: Vec<u8>,
     length: usize,
+    timestamp: Option<SystemTime>,
+    ttl: Option<u8>,
+    destination: Option<SocketAddrV4>,
+    source: Option<SocketAddrV4>,
+    seq: Option<u32>,
+    truncated: usize,
 }
 
 impl Packet {
@@ -36,6 +46,12 @@ impl Packet {
         Self {
             data: vec![0u8; capacity],
             length: capacity,
+            timestamp: None,
+            ttl: None,
+            destination: None,
+            source: None,
+            seq: None,
+            truncated: 0,
         }
     }
 
@@ -47,9 +63,113 @@ impl Packet {
         self.length
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
     pub fn data_mut(&mut self) -> &mut [u8] {
         &mut self.data
     }
+
+    /// Drop the first n bytes, e.g. a header added in transit.
+    pub fn remove_prefix(&mut self, n: usize) {
+        let length = self.len().min(self.data.len());
+        let n = n.min(length);
+        self.data.copy_within(n..length, 0);
+        self.length = length - n;
+    }
+
+    /// Put bytes in front of the payload, growing the buffer to fit.
+    pub fn prepend(&mut self, bytes: &[u8]) {
+        let length = self.len().min(self.data.len());
+        self.ensure_capacity(length + bytes.len());
+        self.data.copy_within(..length, bytes.len());
+        if let Some(front) = self.data.get_mut(..bytes.len()) {
+            front.copy_from_slice(bytes);
+        }
+        self.length = length + bytes.len();
+    }
+
+    /// Put bytes after the end of the packet.
+    pub fn append(&mut self, bytes: &[u8]) {
+        let length = self.len().min(self.data.len());
+        self.ensure_capacity(length + bytes.len());
+        if let Some(back) = self.data.get_mut(length..length + bytes.len()) {
+            back.copy_from_slice(bytes);
+        }
+        self.length = length + bytes.len();
+    }
+
+    /// Grow the buffer to at least capacity bytes. Buffers are recycled, so
+    /// this only allocates the first time a packet needs the room.
+    pub fn ensure_capacity(&mut self, capacity: usize) {
+        if self.data.len() < capacity {
+            self.data.resize(capacity, 0);
+        }
+    }
+
+    /// Bytes available without growing.
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Arrival time as seen by the reader, kernel time when the socket provides it.
+    pub fn timestamp(&self) -> Option<SystemTime> {
+        self.timestamp
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: Option<SystemTime>) {
+        self.timestamp = timestamp
+    }
+
+    /// IP TTL the datagram arrived with, for packets read from the network.
+    pub fn ttl(&self) -> Option<u8> {
+        self.ttl
+    }
+
+    pub fn set_ttl(&mut self, ttl: Option<u8>) {
+        self.ttl = ttl
+    }
+
+    /// Where the datagram was addressed to: the group from IP_PKTINFO and the
+    /// port for received packets, or the recorded destination for replays.
+    pub fn destination(&self) -> Option<SocketAddrV4> {
+        self.destination
+    }
+
+    pub fn set_destination(&mut self, destination: Option<SocketAddrV4>) {
+        self.destination = destination
+    }
+
+    /// The sender's address and port, for received packets.
+    pub fn source(&self) -> Option<SocketAddrV4> {
+        self.source
+    }
+
+    pub fn set_source(&mut self, source: Option<SocketAddrV4>) {
+        self.source = source
+    }
+
+    /// The --stamp-seq number --check-seq read, kept after a prepended one
+    /// has been removed.
+    pub fn seq(&self) -> Option<u32> {
+        self.seq
+    }
+
+    pub fn set_seq(&mut self, seq: Option<u32>) {
+        self.seq = seq
+    }
+
+    /// Bytes of the datagram that didn't fit the buffer, for a datagram the
+    /// kernel cut short and kept without --drop-truncated. The original is this
+    /// much longer than the payload.
+    pub fn truncated(&self) -> usize {
+        self.truncated
+    }
+
+    pub fn set_truncated(&mut self, truncated: usize) {
+        self.truncated = truncated
+    }
 }
 
 impl Deref for Packet {
@@ -92,6 +212,11 @@ impl Packets {
         }
     }
 
+    #[allow(clippy::indexing_slicing)]
+    pub fn packets(&self) -> &[Packet] {
+        &self.packets[..self.length]
+    }
+
     #[allow(clippy::indexing_slicing)]
     pub fn packets_mut(&mut self) -> &mut [Packet] {
         &mut self.packets[..self.length]
@@ -102,6 +227,12 @@ impl Packets {
         self.length = length.min(self.packets.len());
     }
 
+    /// Make every packet in the batch available again, including the ones
+    /// retain moved past the length
+    pub fn restore(&mut self) {
+        self.length = self.packets.len();
+    }
+
     pub fn len(&self) -> usize {
         self.length
     }
@@ -119,4 +250,64 @@ impl Packets {
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Packet> {
         self.packets[..self.length].iter_mut()
     }
+
+    /// Keep only the packets keep says yes to, in order. Dropped packets
+    /// move past the length so their buffers stay with the batch.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Packet) -> bool) {
+        let mut kept = 0;
+        for index in 0..self.length {
+            if self.packets.get(index).is_some_and(&mut keep) {
+                self.packets.swap(kept, index);
+                kept += 1;
+            }
+        }
+        self.length = kept;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retain_keeps_order_and_buffers() {
+        let mut packets = Packets::new(5, 8);
+        for (n, packet) in packets.iter_mut().enumerate() {
+            packet.set_length(1);
+            packet.data_mut().fill(n as u8);
+        }
+
+        packets.retain(|packet| packet.first().is_some_and(|n| n % 2 == 0));
+        let kept: Vec<Vec<u8>> = packets.iter().map(|packet| packet.to_vec()).collect();
+        assert_eq!(kept, vec![vec![0], vec![2], vec![4]]);
+
+        // The dropped packets' buffers come back with the full length
+        packets.set_length(5);
+        assert_eq!(packets.len(), 5);
+    }
+
+    #[test]
+    fn test_prepend_then_remove_prefix() {
+        let mut packet = Packet::with_capacity(4);
+        packet.data_mut().copy_from_slice(b"VRLP");
+        packet.set_length(4);
+
+        packet.prepend(b"shim");
+        assert_eq!(&packet[..], b"shimVRLP");
+        packet.remove_prefix(4);
+        assert_eq!(&packet[..], b"VRLP");
+    }
+
+    #[test]
+    fn test_append() {
+        // Past the length, not the end of the buffer
+        let mut packet = Packet::with_capacity(8);
+        packet.set_length(0);
+        packet.append(b"VRLP");
+        assert_eq!(&packet[..], b"VRLP");
+        packet.append(b"end");
+        assert_eq!(&packet[..], b"VRLPend");
+        packet.append(b"more");
+        assert_eq!(&packet[..], b"VRLPendmore");
+    }
 }
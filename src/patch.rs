@@ -0,0 +1,224 @@
+/// --patch: rewrite bytes of the packets sent, for negative testing, like
+/// forcing an SDDS sequence number or flipping a flag bit. The network sink
+/// patches its own copy of each packet, so statistics and the other outputs
+/// see them as they were read.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{MAX_PACKET_BYTES, packet::Packet};
+
+/// From `--patch OFFSET:HEXBYTES[:every=N]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+    /// Patch every Nth packet, 1 for all of them
+    pub every: u64,
+}
+
+/// Parse OFFSET:HEXBYTES[:every=N], the offset in decimal or 0x hex.
+pub fn parse_patch(s: &str) -> std::result::Result<Patch, String> {
+    let usage = || format!("Expected OFFSET:HEXBYTES[:every=N] like 2:00ff, got: {s}");
+    let mut parts = s.split(':');
+    let (Some(offset), Some(hex)) = (parts.next(), parts.next()) else {
+        return Err(usage());
+    };
+
+    let offset = match offset
+        .strip_prefix("0x")
+        .or_else(|| offset.strip_prefix("0X"))
+    {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => offset.parse(),
+    }
+    .map_err(|_| usage())?;
+
+    let bytes = parse_hex(hex)?;
+    if offset + bytes.len() > MAX_PACKET_BYTES {
+        return Err(format!("Patch ends past the largest packet: {s}"));
+    }
+
+    let every = match parts.next() {
+        None => 1,
+        Some(every) => match every.strip_prefix("every=").map(str::parse::<u64>) {
+            Some(Ok(n)) if n > 0 => n,
+            _ => return Err(format!("Expected every=N above 0, got: {every}")),
+        },
+    };
+    if parts.next().is_some() {
+        return Err(usage());
+    }
+    Ok(Patch {
+        offset,
+        bytes,
+        every,
+    })
+}
+
+/// Parse at least one byte of hex, two digits each, with an optional 0x.
+/// Also --prepend's header.
+pub fn parse_hex(s: &str) -> std::result::Result<Vec<u8>, String> {
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    let error = || format!("Expected bytes as two hex digits each, got: {s}");
+    if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return Err(error());
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(error)?;
+    if bytes.len() > MAX_PACKET_BYTES {
+        return Err(format!("{} bytes is more than a packet holds", bytes.len()));
+    }
+    Ok(bytes)
+}
+
+/// Patches applied, and those skipped because the packet was too short.
+#[derive(Debug, Default)]
+pub struct PatchCounters {
+    applied: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl PatchCounters {
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.applied.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Applies every patch that is due to each packet in turn.
+#[derive(Debug)]
+pub struct Patcher {
+    patches: Vec<Patch>,
+    /// Grow a packet a patch runs past the end of, rather than skipping it
+    extend: bool,
+    // Packets seen so far, for every=N
+    packets: u64,
+}
+
+impl Patcher {
+    pub fn new(patches: Vec<Patch>, extend: bool) -> Self {
+        Self {
+            patches,
+            extend,
+            packets: 0,
+        }
+    }
+
+    /// Patch the next packet. Any gap an extended packet grows over is
+    /// zeros.
+    pub fn apply(&mut self, packet: &mut Packet, counters: &PatchCounters) {
+        self.packets += 1;
+        for patch in &self.patches {
+            if !self.packets.is_multiple_of(patch.every) {
+                continue;
+            }
+            let end = patch.offset + patch.bytes.len();
+            let length = packet.len();
+            if end > length {
+                if !self.extend {
+                    counters.skipped.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                packet.ensure_capacity(end);
+                if let Some(gap) = packet.data_mut().get_mut(length..patch.offset) {
+                    gap.fill(0);
+                }
+                packet.set_length(end);
+            }
+            if let Some(data) = packet.data_mut().get_mut(patch.offset..end) {
+                data.copy_from_slice(&patch.bytes);
+                counters.applied.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+
+    fn packet(payload: &[u8]) -> Packet {
+        let mut packet = Packet::with_capacity(64);
+        packet.data_mut()[..payload.len()].copy_from_slice(payload);
+        packet.set_length(payload.len());
+        packet
+    }
+
+    #[test]
+    fn test_parse_patch() {
+        assert_eq!(
+            parse_patch("2:00ff"),
+            Ok(Patch {
+                offset: 2,
+                bytes: vec![0x00, 0xff],
+                every: 1
+            })
+        );
+        assert_eq!(
+            parse_patch("0x10:0x80:every=5"),
+            Ok(Patch {
+                offset: 16,
+                bytes: vec![0x80],
+                every: 5
+            })
+        );
+        assert!(parse_patch("2").is_err());
+        assert!(parse_patch("2:f").is_err());
+        assert!(parse_patch("2:zz").is_err());
+        assert!(parse_patch("x:00").is_err());
+        assert!(parse_patch("2:00:every=0").is_err());
+        assert!(parse_patch("2:00:often").is_err());
+        assert!(parse_patch("65535:0000").is_err());
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("deadBEEF"), Ok(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(parse_hex("0x00"), Ok(vec![0x00]));
+        assert!(parse_hex("").is_err());
+        assert!(parse_hex("0x").is_err());
+        assert!(parse_hex("abc").is_err());
+        assert!(parse_hex("+1").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let counters = PatchCounters::default();
+        let mut patcher = Patcher::new(
+            vec![
+                parse_patch("1:58").expect("parse"),
+                parse_patch("3:5959:every=2").expect("parse"),
+            ],
+            false,
+        );
+
+        let mut first = packet(b"abcd");
+        patcher.apply(&mut first, &counters);
+        assert_eq!(&first[..], b"aXcd");
+        // Every second packet, which is too short for it
+        let mut second = packet(b"abcd");
+        patcher.apply(&mut second, &counters);
+        assert_eq!(&second[..], b"aXcd");
+        assert_eq!(counters.get(), (2, 1));
+    }
+
+    #[test]
+    fn test_apply_extends() {
+        let counters = PatchCounters::default();
+        let mut patcher = Patcher::new(vec![parse_patch("6:5a").expect("parse")], true);
+        let mut packet = packet(b"abcdefgh");
+        packet.set_length(3);
+        patcher.apply(&mut packet, &counters);
+        assert_eq!(&packet[..], b"abc\0\0\0Z");
+        assert_eq!(counters.get(), (1, 0));
+    }
+}
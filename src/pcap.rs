@@ -0,0 +1,513 @@
+/// --output-format pcap: each packet as a classic pcap record, for Wireshark
+/// and tcpdump. The Ethernet, IPv4 and UDP headers around the payload are
+/// made up from what the packet carries (source, group and port, TTL), since
+/// the kernel doesn't hand them over, and the timestamp is the arrival time.
+/// Packets read from a file without a recorded destination are put down as
+/// sent from 0.0.0.0:0 to the group and first port on the command line.
+/// A datagram longer than --max-packet is recorded with what was received and
+/// the length it was sent with, like a frame cut short by the snaplen.
+///
+/// --input-format pcap reads captures back, tcpdump's or ours, and takes the
+/// UDP payload out of each record. Records that don't hold a whole UDP
+/// datagram (other protocols, fragments, ones cut short by the snaplen) are
+/// skipped. pcapng isn't read.
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::SystemTime;
+
+use crate::{
+    error::{LibError, Result},
+    packet::Packet,
+};
+
+/// Longest frame kept whole. Longer ones are cut to it, and their record
+/// gives the length they had.
+pub const SNAPLEN: u32 = 65535;
+
+// Microsecond timestamps, the classic magic every reader knows
+const MAGIC: u32 = 0xa1b2_c3d4;
+// The same with nanosecond timestamps, as tcpdump --nano writes
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+// A pcapng section header block
+const MAGIC_PCAPNG: u32 = 0x0a0d_0d0a;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+// A record longer than any frame is a corrupt file rather than a packet
+const MAX_RECORD_BYTES: usize = 256 * 1024;
+
+const ETHERNET_BYTES: usize = 14;
+const IPV4_BYTES: usize = 20;
+const UDP_BYTES: usize = 8;
+/// Made up headers in front of each payload.
+pub const HEADER_BYTES: usize = ETHERNET_BYTES + IPV4_BYTES + UDP_BYTES;
+
+// Locally administered, so it can't be mistaken for a real interface's
+const SOURCE_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0];
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+// BSD loopback's address family for IPv4
+const AF_INET: u32 = 2;
+const PROTOCOL_UDP: u8 = 17;
+// For packets that didn't come with one
+const DEFAULT_TTL: u8 = 64;
+
+/// The file header, once at the start.
+pub fn write_header(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(&MAGIC.to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&4u16.to_le_bytes())?;
+    // Timestamps are UTC and their accuracy isn't claimed
+    out.write_all(&0i32.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&SNAPLEN.to_le_bytes())?;
+    out.write_all(&LINKTYPE_ETHERNET.to_le_bytes())
+}
+
+/// One packet's record: its arrival time, lengths and the frame, sent to
+/// fallback if the packet doesn't say where it went.
+pub fn write_record(
+    out: &mut impl Write,
+    packet: &Packet,
+    fallback: Option<SocketAddrV4>,
+) -> io::Result<()> {
+    let since = packet
+        .timestamp()
+        .unwrap_or_else(SystemTime::now)
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    // A datagram the kernel cut short is recorded at its original length
+    let original = packet.len() + packet.truncated();
+    let frame_len = (HEADER_BYTES + original) as u32;
+    let kept = (HEADER_BYTES + packet.len()).min(SNAPLEN as usize) as u32;
+    out.write_all(&(since.as_secs() as u32).to_le_bytes())?;
+    out.write_all(&since.subsec_micros().to_le_bytes())?;
+    out.write_all(&kept.to_le_bytes())?;
+    out.write_all(&frame_len.to_le_bytes())?;
+
+    let unknown = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0);
+    let source = packet.source().unwrap_or(unknown);
+    let destination = packet.destination().or(fallback).unwrap_or(unknown);
+    let mut headers = [0u8; HEADER_BYTES];
+    let (ethernet, rest) = headers.split_at_mut(ETHERNET_BYTES);
+    let (ip, udp) = rest.split_at_mut(IPV4_BYTES);
+    fill_ethernet(ethernet, *destination.ip());
+    fill_ipv4(ip, source, destination, packet.ttl(), original);
+    fill_udp(udp, source, destination, original);
+    out.write_all(&headers)?;
+
+    let payload_kept = (kept as usize).saturating_sub(HEADER_BYTES);
+    out.write_all(packet.get(..payload_kept).unwrap_or(packet))
+}
+
+// A group's own MAC (01:00:5e and its low 23 bits), or none for unicast
+fn fill_ethernet(header: &mut [u8], destination: Ipv4Addr) {
+    let mut mac = [0u8; 6];
+    if destination.is_multicast() {
+        let [_, b, c, d] = destination.octets();
+        mac = [0x01, 0x00, 0x5e, b & 0x7f, c, d];
+    }
+    put(header, 0, &mac);
+    put(header, 6, &SOURCE_MAC);
+    put(header, 12, &ETHERTYPE_IPV4.to_be_bytes());
+}
+
+fn fill_ipv4(
+    header: &mut [u8],
+    source: SocketAddrV4,
+    destination: SocketAddrV4,
+    ttl: Option<u8>,
+    payload: usize,
+) {
+    // Lengths past 16 bits are capped, the record's orig_len has the rest
+    let total = (IPV4_BYTES + UDP_BYTES + payload).min(u16::MAX as usize) as u16;
+    put(header, 0, &[0x45, 0]);
+    put(header, 2, &total.to_be_bytes());
+    put(header, 8, &[ttl.unwrap_or(DEFAULT_TTL), PROTOCOL_UDP]);
+    put(header, 12, &source.ip().octets());
+    put(header, 16, &destination.ip().octets());
+    let checksum = ipv4_checksum(header);
+    put(header, 10, &checksum.to_be_bytes());
+}
+
+// No checksum, which IPv4 allows for UDP
+fn fill_udp(header: &mut [u8], source: SocketAddrV4, destination: SocketAddrV4, payload: usize) {
+    let length = (UDP_BYTES + payload).min(u16::MAX as usize) as u16;
+    put(header, 0, &source.port().to_be_bytes());
+    put(header, 2, &destination.port().to_be_bytes());
+    put(header, 4, &length.to_be_bytes());
+}
+
+fn put(header: &mut [u8], at: usize, bytes: &[u8]) {
+    if let Some(field) = header.get_mut(at..at + bytes.len()) {
+        field.copy_from_slice(bytes);
+    }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum: u32 = header
+        .chunks(2)
+        .map(|word| match word {
+            [high, low] => u32::from(u16::from_be_bytes([*high, *low])),
+            [high] => u32::from(*high) << 8,
+            _ => 0,
+        })
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A pcap file being read back, its header already taken.
+#[derive(Debug)]
+pub struct PcapInput {
+    big_endian: bool,
+    link_type: u32,
+    record: Vec<u8>,
+}
+
+/// What a record held.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Record<'a> {
+    Udp(&'a [u8]),
+    /// Not a whole UDP datagram, and why
+    Skipped(&'static str),
+}
+
+impl PcapInput {
+    /// Read the file header, refusing what isn't a pcap file or has a link
+    /// type the payloads can't be found in.
+    pub fn open(reader: &mut impl Read) -> Result<Self> {
+        let mut header = [0u8; 24];
+        let filled = crate::reader::read_full(reader, &mut header)?;
+        let word = |at: usize, big_endian: bool| {
+            let bytes = [0, 1, 2, 3].map(|i| header.get(at + i).copied().unwrap_or_default());
+            if big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            }
+        };
+        let cut_short =
+            || LibError::Critical(format!("pcap file header cut short at {filled} bytes"));
+        let big_endian = match word(0, false) {
+            _ if filled < 4 => return Err(cut_short()),
+            MAGIC | MAGIC_NANOS => false,
+            magic if magic.swap_bytes() == MAGIC || magic.swap_bytes() == MAGIC_NANOS => true,
+            MAGIC_PCAPNG => {
+                return Err(LibError::Critical(
+                    "pcapng isn't read, convert it with editcap -F pcap".to_string(),
+                ));
+            }
+            magic => {
+                return Err(LibError::Critical(format!(
+                    "not a pcap file, it starts with {magic:#010x}"
+                )));
+            }
+        };
+        if filled < header.len() {
+            return Err(cut_short());
+        }
+        let link_type = word(20, big_endian) & 0xffff;
+        if !matches!(
+            link_type,
+            LINKTYPE_NULL
+                | LINKTYPE_ETHERNET
+                | LINKTYPE_RAW
+                | LINKTYPE_LINUX_SLL
+                | LINKTYPE_IPV4
+                | LINKTYPE_LINUX_SLL2
+        ) {
+            return Err(LibError::Critical(format!(
+                "pcap link type {link_type} isn't read, only Ethernet, Linux cooked, loopback and raw IP"
+            )));
+        }
+        Ok(Self {
+            big_endian,
+            link_type,
+            record: Vec::new(),
+        })
+    }
+
+    /// The next record and the bytes it took up in the file, None at the
+    /// end. A file that ends inside a record is an error.
+    pub fn next_record(&mut self, reader: &mut impl Read) -> Result<Option<(u64, Record<'_>)>> {
+        let mut header = [0u8; 16];
+        match crate::reader::read_full(reader, &mut header)? {
+            0 => return Ok(None),
+            16 => {}
+            _ => {
+                return Err(LibError::Critical(
+                    "pcap file ends inside a record header".to_string(),
+                ));
+            }
+        }
+        let [_, _, incl_len, orig_len] = [0, 4, 8, 12].map(|at| {
+            let bytes = [0, 1, 2, 3].map(|i| header.get(at + i).copied().unwrap_or_default());
+            if self.big_endian {
+                u32::from_be_bytes(bytes) as usize
+            } else {
+                u32::from_le_bytes(bytes) as usize
+            }
+        });
+        if incl_len > MAX_RECORD_BYTES {
+            return Err(LibError::Critical(format!(
+                "pcap record of {incl_len} bytes, the file is corrupt"
+            )));
+        }
+        self.record.resize(incl_len, 0);
+        reader.read_exact(&mut self.record)?;
+        let bytes = (header.len() + incl_len) as u64;
+        let record = if incl_len < orig_len {
+            Record::Skipped("cut short by the capture's snaplen")
+        } else {
+            udp_payload(self.link_type, &self.record)
+        };
+        Ok(Some((bytes, record)))
+    }
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*bytes.get(at)?, *bytes.get(at + 1)?]))
+}
+
+// The IPv4 packet in a frame of link_type, None when it holds something else
+fn ipv4_of(link_type: u32, frame: &[u8]) -> Option<&[u8]> {
+    match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut at = 12;
+            // 802.1Q and 802.1ad tags, stacked or not
+            while matches!(u16_at(frame, at)?, ETHERTYPE_VLAN | ETHERTYPE_QINQ) {
+                at += 4;
+            }
+            (u16_at(frame, at)? == ETHERTYPE_IPV4).then(|| frame.get(at + 2..))?
+        }
+        LINKTYPE_LINUX_SLL => (u16_at(frame, 14)? == ETHERTYPE_IPV4).then(|| frame.get(16..))?,
+        LINKTYPE_LINUX_SLL2 => (u16_at(frame, 0)? == ETHERTYPE_IPV4).then(|| frame.get(20..))?,
+        // The family is in the capturing host's byte order
+        LINKTYPE_NULL => {
+            let family = u32::from_le_bytes(frame.get(..4)?.try_into().ok()?);
+            (family == AF_INET || family.swap_bytes() == AF_INET).then(|| frame.get(4..))?
+        }
+        _ => Some(frame),
+    }
+}
+
+fn udp_payload(link_type: u32, frame: &[u8]) -> Record<'_> {
+    let Some(ip) = ipv4_of(link_type, frame).filter(|ip| ip.first().is_some_and(|b| b >> 4 == 4))
+    else {
+        return Record::Skipped("not IPv4");
+    };
+    let header_bytes = ip.first().map_or(0, |b| usize::from(b & 0x0f) * 4);
+    if ip.get(9) != Some(&PROTOCOL_UDP) {
+        return Record::Skipped("not UDP");
+    }
+    // More fragments to come, or not the first
+    if u16_at(ip, 6).is_none_or(|flags| flags & 0x3fff != 0) {
+        return Record::Skipped("an IP fragment");
+    }
+    let udp_length = ip
+        .get(header_bytes..)
+        .and_then(|udp| u16_at(udp, 4))
+        .map_or(0, usize::from);
+    match ip.get(header_bytes + UDP_BYTES..header_bytes + udp_length.max(UDP_BYTES)) {
+        Some(payload) => Record::Udp(payload),
+        None => Record::Skipped("shorter than its UDP length"),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::indexing_slicing)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn packet(payload: &[u8]) -> Packet {
+        let mut packet = Packet::with_capacity(payload.len());
+        packet.data_mut().copy_from_slice(payload);
+        packet.set_timestamp(Some(
+            SystemTime::UNIX_EPOCH + Duration::from_micros(1_792_211_205_682_873),
+        ));
+        packet.set_source(Some("10.1.2.3:40000".parse().expect("source")));
+        packet.set_destination(Some("239.129.2.3:29495".parse().expect("group")));
+        packet.set_ttl(Some(7));
+        packet
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().expect("u32"))
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut out = Vec::new();
+        write_header(&mut out).expect("header");
+        write_record(&mut out, &packet(b"hello"), None).expect("record");
+
+        assert_eq!(u32_at(&out, 0), MAGIC);
+        assert_eq!(u32_at(&out, 16), SNAPLEN);
+        assert_eq!(u32_at(&out, 20), LINKTYPE_ETHERNET);
+
+        let record = &out[24..];
+        assert_eq!(u32_at(record, 0), 1_792_211_205);
+        assert_eq!(u32_at(record, 4), 682_873);
+        assert_eq!(u32_at(record, 8), 47);
+        assert_eq!(u32_at(record, 12), 47);
+
+        let frame = &record[16..];
+        assert_eq!(frame.len(), 47);
+        assert_eq!(&frame[..6], &[0x01, 0x00, 0x5e, 0x01, 2, 3]);
+        let ip = &frame[14..34];
+        assert_eq!(ip[8], 7);
+        assert_eq!(ip[9], PROTOCOL_UDP);
+        assert_eq!(&ip[12..16], &[10, 1, 2, 3]);
+        assert_eq!(&ip[16..20], &[239, 129, 2, 3]);
+        // A header with its checksum in sums to all ones
+        assert_eq!(ipv4_checksum(ip), 0);
+        let udp = &frame[34..42];
+        assert_eq!(u16::from_be_bytes([udp[0], udp[1]]), 40000);
+        assert_eq!(u16::from_be_bytes([udp[2], udp[3]]), 29495);
+        assert_eq!(u16::from_be_bytes([udp[4], udp[5]]), 13);
+        assert_eq!(&frame[42..], b"hello");
+    }
+
+    #[test]
+    fn test_fallback_destination() {
+        let mut replayed = packet(b"hello");
+        replayed.set_destination(None);
+        let fallback = Some("239.1.1.1:5000".parse().expect("group"));
+        let destination = |fallback| {
+            let mut frame = Vec::new();
+            write_record(&mut frame, &replayed, fallback).expect("record");
+            (
+                frame[16 + 30..16 + 34].to_vec(),
+                u16::from_be_bytes([frame[16 + 36], frame[16 + 37]]),
+            )
+        };
+        assert_eq!(destination(fallback), (vec![239, 1, 1, 1], 5000));
+        assert_eq!(destination(None), (vec![0, 0, 0, 0], 0));
+
+        // A recorded destination wins
+        let mut frame = Vec::new();
+        write_record(&mut frame, &packet(b"hello"), fallback).expect("record");
+        assert_eq!(&frame[16 + 30..16 + 34], &[239, 129, 2, 3]);
+    }
+
+    #[test]
+    fn test_read_back() {
+        let mut file = Vec::new();
+        write_header(&mut file).expect("header");
+        for payload in [&b"one"[..], b"", b"three"] {
+            write_record(&mut file, &packet(payload), None).expect("record");
+        }
+
+        let mut reader = file.as_slice();
+        let mut input = PcapInput::open(&mut reader).expect("open");
+        let mut payloads = Vec::new();
+        while let Some((bytes, record)) = input.next_record(&mut reader).expect("record") {
+            if let Record::Udp(payload) = record {
+                assert_eq!(bytes as usize, 16 + HEADER_BYTES + payload.len());
+                payloads.push(payload.to_vec());
+            }
+        }
+        assert_eq!(payloads, [&b"one"[..], b"", b"three"]);
+    }
+
+    #[test]
+    fn test_records_without_udp_skipped() {
+        let mut frame = Vec::new();
+        write_record(&mut frame, &packet(b"hello"), None).expect("record");
+        let frame = frame.split_off(16);
+
+        let mut tcp = frame.clone();
+        tcp[23] = 6;
+        assert_eq!(
+            udp_payload(LINKTYPE_ETHERNET, &tcp),
+            Record::Skipped("not UDP")
+        );
+        let mut arp = frame.clone();
+        arp[12..14].copy_from_slice(&[0x08, 0x06]);
+        assert_eq!(
+            udp_payload(LINKTYPE_ETHERNET, &arp),
+            Record::Skipped("not IPv4")
+        );
+        let mut fragment = frame.clone();
+        fragment[20] = 0x20;
+        assert_eq!(
+            udp_payload(LINKTYPE_ETHERNET, &fragment),
+            Record::Skipped("an IP fragment")
+        );
+        assert_eq!(
+            udp_payload(LINKTYPE_ETHERNET, &frame[..frame.len() - 1]),
+            Record::Skipped("shorter than its UDP length")
+        );
+
+        // Tagged, cooked and raw IP all find the same payload
+        let mut tagged = frame[..12].to_vec();
+        tagged.extend([0x81, 0x00, 0x00, 0x64]);
+        tagged.extend(&frame[12..]);
+        let mut cooked = vec![0; 14];
+        cooked.extend(&frame[12..]);
+        for (link_type, frame) in [
+            (LINKTYPE_ETHERNET, &tagged[..]),
+            (LINKTYPE_LINUX_SLL, &cooked),
+            (LINKTYPE_RAW, &frame[14..]),
+        ] {
+            assert_eq!(udp_payload(link_type, frame), Record::Udp(b"hello"));
+        }
+    }
+
+    #[test]
+    fn test_open_refuses() {
+        assert!(PcapInput::open(&mut &[0u8; 4][..]).is_err());
+        assert!(PcapInput::open(&mut &MAGIC.to_le_bytes()[..]).is_err());
+        let mut pcapng = MAGIC_PCAPNG.to_le_bytes().to_vec();
+        pcapng.resize(24, 0);
+        assert!(PcapInput::open(&mut pcapng.as_slice()).is_err());
+
+        let mut header = Vec::new();
+        write_header(&mut header).expect("header");
+        // 802.11
+        header[20] = 105;
+        assert!(PcapInput::open(&mut header.as_slice()).is_err());
+        // Written big endian
+        let mut swapped = MAGIC.to_be_bytes().to_vec();
+        swapped.extend([0; 16]);
+        swapped.extend(LINKTYPE_ETHERNET.to_be_bytes());
+        assert!(PcapInput::open(&mut swapped.as_slice()).is_ok_and(|input| input.big_endian));
+    }
+
+    #[test]
+    fn test_truncated_datagram_keeps_its_length() {
+        let mut truncated = packet(b"hello");
+        truncated.set_truncated(95);
+        let mut out = Vec::new();
+        write_record(&mut out, &truncated, None).expect("record");
+
+        // Only what was received is in the frame, its headers say 100 bytes
+        assert_eq!(u32_at(&out, 8), 47);
+        assert_eq!(u32_at(&out, 12), (HEADER_BYTES + 100) as u32);
+        let frame = &out[16..];
+        assert_eq!(u16::from_be_bytes([frame[16], frame[17]]), 128);
+        assert_eq!(u16::from_be_bytes([frame[38], frame[39]]), 108);
+        assert_eq!(&frame[42..], b"hello");
+    }
+
+    #[test]
+    fn test_long_packet_cut_to_snaplen() {
+        let payload = vec![0xab; 65507];
+        let mut out = Vec::new();
+        write_record(&mut out, &packet(&payload), None).expect("record");
+
+        assert_eq!(u32_at(&out, 8), SNAPLEN);
+        assert_eq!(u32_at(&out, 12), (HEADER_BYTES + payload.len()) as u32);
+        assert_eq!(out.len(), 16 + SNAPLEN as usize);
+    }
+}
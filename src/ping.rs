@@ -0,0 +1,323 @@
+/// Round trip checks: `--ping` sends numbered probes to the group and listens
+/// on the reply group, `--echo` answers them.
+/// A probe is "MNCP", a kind byte, the pinger's id, a sequence number and the
+/// pinger's send time, all big endian. The responder only flips the kind, so
+/// the round trip is timed on the pinger's clock alone.
+use std::collections::HashSet;
+use std::io;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use crate::{
+    SharedState,
+    error::Result,
+    multicast::{create_recv_socket, create_send_socket},
+};
+
+pub const PROBE_BYTES: usize = 21;
+const MAGIC: &[u8; 4] = b"MNCP";
+const PROBE: u8 = 0;
+const REPLY: u8 = 1;
+
+/// Time between probes, as with ping.
+pub const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Probe {
+    reply: bool,
+    id: u32,
+    seq: u32,
+    // Nanoseconds since the pinger started
+    sent: u64,
+}
+
+impl Probe {
+    fn encode(&self) -> [u8; PROBE_BYTES] {
+        let mut bytes = [0u8; PROBE_BYTES];
+        let fields = MAGIC
+            .iter()
+            .copied()
+            .chain([if self.reply { REPLY } else { PROBE }])
+            .chain(self.id.to_be_bytes())
+            .chain(self.seq.to_be_bytes())
+            .chain(self.sent.to_be_bytes());
+        for (byte, field) in bytes.iter_mut().zip(fields) {
+            *byte = field;
+        }
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != PROBE_BYTES || bytes.get(..4)? != MAGIC {
+            return None;
+        }
+        let reply = match *bytes.get(4)? {
+            PROBE => false,
+            REPLY => true,
+            _ => return None,
+        };
+        Some(Self {
+            reply,
+            id: u32::from_be_bytes(bytes.get(5..9)?.try_into().ok()?),
+            seq: u32::from_be_bytes(bytes.get(9..13)?.try_into().ok()?),
+            sent: u64::from_be_bytes(bytes.get(13..21)?.try_into().ok()?),
+        })
+    }
+}
+
+/// Ping and echo configuration. Replies go to reply_iface:reply_group.
+pub struct PingConfig {
+    pub shared_state: SharedState,
+    pub iface: Option<String>,
+    pub mgroup: String,
+    pub reply_iface: Option<String>,
+    pub reply_group: String,
+    pub port: u16,
+    pub ttl: u8,
+    /// Probes to send, or to answer with --echo; 0 until Ctrl-C
+    pub count: u64,
+}
+
+/// What came back, summarized like ping does on exit.
+#[derive(Debug, Default)]
+pub struct PingSummary {
+    pub sent: u64,
+    answered: HashSet<u32>,
+    rtts: Vec<Duration>,
+}
+
+impl PingSummary {
+    /// Record a reply. False if the probe was already answered.
+    fn record(&mut self, seq: u32, rtt: Duration) -> bool {
+        self.rtts.push(rtt);
+        self.answered.insert(seq)
+    }
+
+    pub fn answered(&self) -> u64 {
+        self.answered.len() as u64
+    }
+
+    pub fn lost(&self) -> u64 {
+        self.sent.saturating_sub(self.answered())
+    }
+
+    pub fn lines(&self, mgroup: &str) -> Vec<String> {
+        let loss = match self.sent {
+            0 => 0.0,
+            sent => self.lost() as f64 * 100.0 / sent as f64,
+        };
+        let mut lines = vec![
+            format!("--- {mgroup} ping statistics ---"),
+            format!(
+                "{} probes sent, {} answered, {loss:.1}% loss",
+                self.sent,
+                self.answered()
+            ),
+        ];
+
+        if let (Some(min), Some(max)) = (self.rtts.iter().min(), self.rtts.iter().max()) {
+            let avg = self.rtts.iter().sum::<Duration>() / self.rtts.len() as u32;
+            lines.push(format!(
+                "rtt min/avg/max = {:.3}/{:.3}/{:.3} ms",
+                millis(*min),
+                millis(avg),
+                millis(*max)
+            ));
+        }
+        lines
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn sockets(config: &PingConfig, listen_on_reply: bool) -> Result<(UdpSocket, UdpSocket)> {
+    let (listen_iface, listen_group, send_iface, send_group) = if listen_on_reply {
+        (
+            &config.reply_iface,
+            &config.reply_group,
+            &config.iface,
+            &config.mgroup,
+        )
+    } else {
+        (
+            &config.iface,
+            &config.mgroup,
+            &config.reply_iface,
+            &config.reply_group,
+        )
+    };
+
+    let listen = create_recv_socket(listen_iface.as_deref(), listen_group, config.port)?;
+    let send = create_send_socket(send_iface.as_deref(), send_group, config.port, config.ttl)?;
+    Ok((listen.into(), send.into()))
+}
+
+/// Nothing arrived within the socket's read timeout.
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted
+    )
+}
+
+/// Probe once per PING_INTERVAL until count probes have had an interval to
+/// come back, or until exit is signaled.
+pub fn ping(config: &PingConfig) -> Result<PingSummary> {
+    let (listen, send) = sockets(config, true)?;
+    let id = std::process::id();
+    let started = Instant::now();
+    let mut summary = PingSummary::default();
+    let mut next_probe = Instant::now();
+    let mut buffer = [0u8; PROBE_BYTES + 1];
+
+    log::info!(
+        "PING {} ({PROBE_BYTES} byte probes), replies on {}",
+        config.mgroup,
+        config.reply_group
+    );
+
+    while !config.shared_state.should_exit() {
+        if Instant::now() >= next_probe {
+            if config.count > 0 && summary.sent >= config.count {
+                break;
+            }
+            let probe = Probe {
+                reply: false,
+                id,
+                seq: summary.sent as u32,
+                sent: started.elapsed().as_nanos() as u64,
+            };
+            send.send(&probe.encode())?;
+            summary.sent += 1;
+            next_probe += PING_INTERVAL;
+        }
+
+        let (received, from) = match listen.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        // Our own probes come back too when replies share the group
+        let Some(reply) = buffer.get(..received).and_then(Probe::parse) else {
+            continue;
+        };
+        if !reply.reply || reply.id != id {
+            continue;
+        }
+
+        let rtt = started
+            .elapsed()
+            .saturating_sub(Duration::from_nanos(reply.sent));
+        let duplicate = if summary.record(reply.seq, rtt) {
+            ""
+        } else {
+            " (DUP!)"
+        };
+        log::info!(
+            "{received} bytes from {}: seq={} time={:.3} ms{duplicate}",
+            from.ip(),
+            reply.seq,
+            millis(rtt)
+        );
+    }
+
+    Ok(summary)
+}
+
+/// Answer probes on the group by sending them back on the reply group.
+/// Anything that isn't a probe is ignored, so echoing onto the same group
+/// can't loop.
+pub fn echo(config: &PingConfig) -> Result<u64> {
+    let (listen, send) = sockets(config, false)?;
+    let mut buffer = [0u8; PROBE_BYTES + 1];
+    let mut echoed = 0u64;
+
+    log::info!(
+        "answering probes on {}, replies to {}",
+        config.mgroup,
+        config.reply_group
+    );
+
+    while !config.shared_state.should_exit() && (config.count == 0 || echoed < config.count) {
+        let (received, from) = match listen.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let Some(probe) = buffer.get(..received).and_then(Probe::parse) else {
+            continue;
+        };
+        if probe.reply {
+            continue;
+        }
+
+        let reply = Probe {
+            reply: true,
+            ..probe
+        };
+        send.send(&reply.encode())?;
+        echoed += 1;
+        log::debug!("echoed seq={} from {}", probe.seq, from.ip());
+    }
+
+    Ok(echoed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_round_trip() {
+        let probe = Probe {
+            reply: false,
+            id: 4242,
+            seq: 7,
+            sent: 1_234_567_890,
+        };
+        let bytes = probe.encode();
+        assert_eq!(bytes.get(..5), Some(&b"MNCP\0"[..]));
+        assert_eq!(Probe::parse(&bytes), Some(probe));
+
+        assert_eq!(Probe::parse(b"hello, multicast world"), None);
+        assert_eq!(Probe::parse(bytes.get(..20).unwrap_or_default()), None);
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut summary = PingSummary {
+            sent: 4,
+            ..Default::default()
+        };
+        assert!(summary.record(0, Duration::from_micros(100)));
+        assert!(summary.record(1, Duration::from_micros(300)));
+        // A second responder
+        assert!(!summary.record(1, Duration::from_micros(500)));
+
+        assert_eq!(summary.lost(), 2);
+        assert_eq!(
+            summary.lines("239.1.1.1"),
+            vec![
+                "--- 239.1.1.1 ping statistics ---",
+                "4 probes sent, 2 answered, 50.0% loss",
+                "rtt min/avg/max = 0.100/0.300/0.500 ms",
+            ]
+        );
+
+        let silent = PingSummary {
+            sent: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            silent.lines("239.1.1.1"),
+            vec![
+                "--- 239.1.1.1 ping statistics ---",
+                "3 probes sent, 0 answered, 100.0% loss",
+            ]
+        );
+    }
+}
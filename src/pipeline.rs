@@ -0,0 +1,951 @@
+/// Running mnc as the command line asks: the options are checked, the
+/// pipeline's threads spawned and watched over, and the totals summed up.
+use std::io::IsTerminal;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use clap::{ArgMatches, CommandFactory, FromArgMatches, error::ErrorKind};
+use crossbeam_channel::{Receiver, Sender, bounded};
+
+use crate::{
+    MAX_PACKET_BYTES, Packets, SharedState,
+    cli::{Args, LogTarget, Ports},
+    config, control, diagnose,
+    dump::DumpOutput,
+    error,
+    expect::ExpectConfig,
+    initialize_memory_pool_with, multicast, nic,
+    pace::PacedSender,
+    ping, preflight, progress, reader, sandbox,
+    sched::ThreadPlacement,
+    sequence::SeqField,
+    setup::{
+        self, CountDirection, Setup, count_direction, drain_timeout, dry_run, dump_count,
+        log_chosen, startup_config, wants_statistics,
+    },
+    sigmf::SigmfConfig,
+    sink::{Label, OutputFormat, SplitKey},
+    statistics, systemd,
+    transport::{self, BatchSender, TransportKind},
+    unique,
+    writer::{self, ExecCommand},
+};
+
+// Exit codes scripts can rely on. 0 is a clean finish: -c reached,
+// --duration elapsed, EOF or a signal. 2 is clap's usage error.
+const EXIT_DROPS: u8 = 3;
+const EXIT_IO: u8 = 4;
+const EXIT_IDLE: u8 = 5;
+const EXIT_GAP: u8 = 6;
+const EXIT_MISMATCH: u8 = 7;
+
+// Without a terminal, log file send progress every this many percent.
+const PROGRESS_LOG_STEP: u64 = 10;
+
+// How often a systemd service's status line is brought up to date.
+const SYSTEMD_STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+// --diagnose starts gathering evidence after this long without a packet.
+const DIAGNOSE_AFTER: Duration = Duration::from_secs(3);
+
+/// Run mnc as matches ask, and return the exit code. Usage errors come back
+/// as a clap::Error for the caller to report the way clap does.
+pub fn run(matches: &ArgMatches) -> anyhow::Result<u8> {
+    let mut args = Args::from_arg_matches(matches)?;
+
+    if let Some([socket, command]) = args.ctl.as_deref() {
+        let answer = control::send(Path::new(socket), command)?;
+        println!("{answer}");
+        return Ok(if answer.starts_with("error:") { 1 } else { 0 });
+    }
+
+    if let Some(path) = &args.config {
+        config::load(path, args.profile.as_deref())
+            .and_then(|settings| config::apply(&mut args, settings, matches))
+            .map_err(|e| Args::command().error(ErrorKind::InvalidValue, e))?;
+    }
+    let setup = setup::validate(&mut args)?;
+
+    let log_level = if args.debug {
+        "debug"
+    } else if args.quiet {
+        "warn"
+    } else {
+        "info"
+    };
+    let log_target = match &args.log {
+        LogTarget::Stderr => env_logger::Target::Stderr,
+        LogTarget::Stdout => env_logger::Target::Stdout,
+        LogTarget::File(path) => match std::fs::File::create(path) {
+            Ok(file) => env_logger::Target::Pipe(Box::new(file)),
+            Err(e) => {
+                eprintln!("error: --log file:{path}: {e}");
+                return Ok(EXIT_IO);
+            }
+        },
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level))
+        .target(log_target)
+        .init();
+    if args.legacy_verbose {
+        log::warn!("--legacy-verbose is deprecated and goes away in the next release, use -c 1");
+    } else if args.verbose && args.count.is_none() {
+        log::warn!(
+            "-v no longer stops after the first packet, add -c 1 for that (or --legacy-verbose, for this release only)"
+        );
+    }
+    for (output, chosen) in &setup.output_formats {
+        log_chosen("writing", output, chosen, args.packet_type);
+    }
+    if let (Some(input), Some(chosen)) = (&args.input, &setup.input_format) {
+        log_chosen("reading", input, chosen, args.packet_type);
+    }
+
+    if args.dry_run {
+        return Ok(match dry_run(&args, &setup.mode, &setup.groups) {
+            Ok(()) => 0,
+            Err(e) => {
+                log::error!("dry run failed: {e}");
+                EXIT_IO
+            }
+        });
+    }
+
+    if args.ping || args.echo {
+        return ping_or_echo(
+            &args,
+            setup.iface.clone(),
+            setup.mgroup.clone(),
+            setup.max_count,
+        );
+    }
+
+    let startup = startup_config(&args, &setup.mode, &setup.groups, setup.max_count);
+    if args.print_config_json {
+        println!("{}", serde_json::to_string_pretty(&startup)?);
+        return Ok(0);
+    }
+    for line in startup.lines() {
+        log::info!("{line}");
+    }
+
+    // The writer stops at -c either way. The reader does too, unless it's
+    // counting writes and filters or --police may drop some of what it reads.
+    let count_direction = count_direction(&args, &setup.mode);
+
+    // Exit toggles for threads
+    let shared_state = SharedState::new(args.packet_type, args.verbose);
+    shared_state.control.set_rate(setup.writer_pps);
+    if args.resolve {
+        shared_state.names.start();
+    }
+    // Drops at the NIC, before the socket counts them
+    let nic_iface = setup
+        .iface
+        .as_deref()
+        .filter(|_| setup.mode.receive)
+        .and_then(|iface| multicast::split_iface(iface).ok())
+        .map(|(name, _)| name.to_string());
+    let mut nic = nic_iface.as_deref().and_then(nic::NicCounters::open);
+    let nic_start = nic.as_mut().map(nic::NicCounters::sample);
+
+    let running = spawn(&args, &setup, &shared_state, count_direction, nic_iface)?;
+    let outcome = supervise(&args, &shared_state, running);
+    let lost = summarize(
+        &args,
+        &setup,
+        &shared_state,
+        count_direction,
+        nic.as_mut().zip(nic_start),
+    );
+
+    Ok(exit_code(
+        outcome.failed,
+        shared_state.expect.failed(),
+        shared_state.gap_failed(),
+        outcome.idled_out,
+        lost + shared_state.get_disk_full(),
+    ))
+}
+
+/// The pipeline's threads, and what run holds on to while they go.
+struct Running {
+    threads: Vec<(&'static str, JoinHandle<error::Result<()>>)>,
+    // A heartbeat's data channel, closed on exit in place of a reader's
+    idle_input: Option<Box<dyn BatchSender>>,
+    // SIGHUP under --sandbox, when the output files can't be reopened
+    hangup: Option<Arc<AtomicBool>>,
+}
+
+/// How the pipeline finished, for the exit code.
+struct Outcome {
+    failed: bool,
+    idled_out: bool,
+}
+
+/// Spawn the pipeline's threads and set up the signals that steer them.
+fn spawn(
+    args: &Args,
+    setup: &Setup,
+    shared_state: &SharedState,
+    count_direction: CountDirection,
+    nic_iface: Option<String>,
+) -> anyhow::Result<Running> {
+    let &Setup {
+        ref iface,
+        ref mgroup,
+        ref mode,
+        ref groups,
+        ref group_types,
+        ref output_formats,
+        input_framing,
+        writes_stdout,
+        sigmf,
+        ref filters,
+        txtime,
+        template_pps,
+        input_pps,
+        expect_resync,
+        reorder,
+        max_count,
+        ..
+    } = setup;
+    let several_groups = groups.len() > 1;
+    let control_socket = args
+        .control
+        .as_deref()
+        .map(|path| control::bind(Path::new(path)))
+        .transpose()?;
+    let mut all_threads: Vec<_> = Vec::new();
+
+    // Memory return channel: Writer -> Reader for packet recycling
+    let (memory_return_tx, memory_return_rx): (Sender<Packets>, Receiver<Packets>) =
+        bounded(args.pool_size + 1);
+
+    // Memory allocation is expensive at high pps, so do this in a background thread.
+    // Unless asked to preallocate, packets start empty and the reader sizes them
+    initialize_memory_pool_with(
+        memory_return_tx.clone(),
+        args.batch_size,
+        if args.preallocate {
+            args.max_packet
+                .map_or(MAX_PACKET_BYTES, |bytes| bytes as usize)
+        } else {
+            0
+        },
+        args.pool_size,
+        shared_state.clone(),
+    );
+
+    let cpu = args.cpu.unwrap_or_default();
+    let placement = |cpu: Option<usize>| ThreadPlacement {
+        cpu,
+        rt_priority: args.rt_priority,
+    };
+
+    // Reader -> [Statistics] -> Writer -> Reader (memory return)
+    let transport_kind = if args.fast_channel {
+        TransportKind::Ring
+    } else {
+        TransportKind::Channel
+    };
+    let label = args.label.clone().map(|label| match label {
+        Some(label) => Label::Fixed(label),
+        None => Label::Destination(
+            groups
+                .iter()
+                .map(|group| format!("{}:{}", group.mgroup, Ports(group.ports(&args.port))))
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+    });
+    let (reader_tx, reader_rx) = transport::bounded(transport_kind, args.pool_size + 1);
+    let reader_tx = match input_pps {
+        Some(pps) => Box::new(PacedSender::new(
+            reader_tx,
+            pps,
+            args.pps_burst,
+            shared_state.clone(),
+        )),
+        None => reader_tx,
+    };
+    let writer_rx = if wants_statistics(args) {
+        let (stats_tx, stats_rx) = transport::bounded(transport_kind, args.pool_size + 1);
+
+        // Statistics gives us some useful information about the packets
+        log::debug!("spawning statistics thread");
+        let handle = statistics::spawn(statistics::StatisticsConfig {
+            channels: (reader_rx, stats_tx),
+            shared_state: shared_state.clone(),
+            dump_output: match &args.dump_output {
+                Some(filename) => DumpOutput::File(filename.clone()),
+                None if writes_stdout => DumpOutput::Stderr,
+                None => DumpOutput::Stdout,
+            },
+            dump_diff: args.dump_diff,
+            dump_count: dump_count(args),
+            transmit: mode.transmit.is_some(),
+            measure_latency: args.measure_latency,
+            check_seq: args.check_seq.map(SeqField::from_offset),
+            verify_template: args.verify_template.clone(),
+            sdds_rate: args.sample_rate,
+            sdds_epoch: args.sdds_epoch,
+            timetag_offset: args.timetag_offset,
+            vita49_context_gap: args.vita49_context_gap,
+            fail_on_gap: args.fail_on_gap.map(Option::unwrap_or_default),
+            groups: if several_groups {
+                groups
+                    .iter()
+                    .zip(group_types)
+                    .flat_map(|(group, &packet_type)| {
+                        let address = group.mgroup.parse::<Ipv4Addr>().ok();
+                        group.ports(&args.port).into_iter().filter_map(move |port| {
+                            Some((SocketAddrV4::new(address?, port), packet_type))
+                        })
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            per_port: args.port.is_many(),
+            filters: !filters.is_empty(),
+            police: args.police.is_some(),
+            reorder: args.reorder.is_some(),
+            dejitter: args.dejitter.is_some(),
+            fec: args.fec.is_some() || args.fec_decode.is_some(),
+            patch: !args.patch.is_empty(),
+            strip: args.strip,
+            memory_return: memory_return_tx.clone(),
+            // Files and stdin have no senders
+            senders: (mode.receive && args.input.is_none())
+                .then(|| Duration::from_secs(args.sender_timeout)),
+            nic: nic_iface.clone(),
+            batch_fill: args.batch_stats,
+            unique: args
+                .unique
+                .map(|limit| limit.unwrap_or(unique::DEFAULT_EXACT_LIMIT)),
+            drain_timeout: drain_timeout(args),
+            label: label.as_ref().map(|label| label.as_str().to_string()),
+            on_change: args
+                .stats_on_change
+                .map(|percent| percent.unwrap_or(statistics::DEFAULT_CHANGE_PERCENT)),
+            placement: placement(cpu.stats),
+            sandbox: args.sandbox,
+        });
+
+        all_threads.push(("statistics", handle));
+
+        stats_rx
+    } else {
+        reader_rx
+    };
+
+    // --loop hands the end of each pass back to the pool
+    let repeat = args.repeat.map(|passes| reader::Repeat {
+        passes: passes.unwrap_or(0),
+        memory_return: memory_return_tx.clone(),
+    });
+
+    // Writer sends packets to network/file/stdout. Discards all packets by default.
+    log::debug!("spawning writer thread");
+    let (tx_iface, tx_mgroup) = mode.transmit.clone().unwrap_or_default();
+    let writer_handle = writer::spawn(writer::WriterConfig {
+        outputs: output_formats
+            .iter()
+            .map(|(output, chosen)| (output.clone(), chosen.format()))
+            .collect(),
+        to_network: mode.transmit.is_some(),
+        iface: tx_iface,
+        mgroup: tx_mgroup,
+        ports: args.port.0.clone(),
+        ttl: args.ttl,
+        channels: (writer_rx, memory_return_tx),
+        shared_state: shared_state.clone(),
+        rate: args.rate,
+        txtime,
+        pace_burst: args.pps_burst,
+        max_latency: args.max_latency.map(Duration::from_millis),
+        stamp: args.stamp,
+        stamp_seq: args.stamp_seq.map(SeqField::from_offset),
+        fec: args.fec,
+        patches: args.patch.clone(),
+        patch_extend: args.patch_extend,
+        prepend: args.prepend.clone().unwrap_or_default(),
+        loop_guard: args.loop_guard.clone().unwrap_or_default(),
+        fanout: args.fanout,
+        packet_destinations: args.input.is_some() && !args.rewrite_dst,
+        filters: filters.clone(),
+        police: args.police,
+        reorder,
+        dejitter: args.dejitter.map(Duration::from_millis),
+        fec_decode: args.fec_decode,
+        convert: args.convert,
+        max_count,
+        timestamps: args.timestamps,
+        append_newline: !args.no_append_newline,
+        label,
+        output_format: args.output_format,
+        split_by: args.split_by,
+        mark_gaps: args.mark_gaps,
+        strict_disk: args.strict_disk,
+        expect: args.expect.clone().map(|path| ExpectConfig {
+            path,
+            framing: input_framing,
+            resync: expect_resync,
+        }),
+        heartbeat: args.heartbeat.clone(),
+        sigmf: sigmf.then(|| SigmfConfig {
+            packet_type: args.packet_type,
+            sample_rate: args.sample_rate,
+            datatype: args.sigmf_datatype.clone(),
+            sdds_epoch: args.sdds_epoch,
+        }),
+        exec: match (&args.exec, &args.exec_per_packet) {
+            (Some(command), _) => Some(ExecCommand {
+                command: command.clone(),
+                per_packet: false,
+            }),
+            (None, Some(command)) => Some(ExecCommand {
+                command: command.clone(),
+                per_packet: true,
+            }),
+            (None, None) => None,
+        },
+        drain_timeout: drain_timeout(args),
+        placement: placement(cpu.writer),
+        sandbox: args.sandbox,
+    });
+    all_threads.push(("writer", writer_handle));
+
+    // Nothing is read for a heartbeat on its own. Its channel is closed on
+    // exit instead, which is the writer's cue to stop as a reader's would be.
+    let mut idle_input = None;
+    if !mode.receive && args.input.is_none() && args.template.is_none() {
+        idle_input = Some(reader_tx);
+    } else {
+        // Reader pulls packets from network/file/stdin
+        log::debug!("spawning reader thread");
+        let reader_handle = reader::spawn(reader::ReaderConfig {
+            input: args.input.clone(),
+            template: args.template.clone(),
+            pps: template_pps,
+            input_framing: Some(input_framing),
+            joins: groups
+                .iter()
+                .map(|group| reader::Join {
+                    iface: group.iface.clone(),
+                    mgroup: group.mgroup.clone(),
+                    ports: group.ports(&args.port),
+                })
+                .collect(),
+            batch_size: args.batch_size,
+            channels: (reader_tx, memory_return_rx),
+            shared_state: shared_state.clone(),
+            max_count: match count_direction {
+                // Stages that drop packets, or parity, make reads outnumber writes
+                CountDirection::Tx
+                    if !filters.is_empty()
+                        || args.police.is_some()
+                        || args.fec_decode.is_some() =>
+                {
+                    0
+                }
+                _ => max_count,
+            },
+            adaptive_buffers: !args.preallocate,
+            speed: args.speed.unwrap_or(1.0),
+            placement: placement(cpu.reader),
+            sandbox: args.sandbox,
+            max_restarts: args.max_restarts,
+            max_packet: args
+                .max_packet
+                .map_or(MAX_PACKET_BYTES, |bytes| bytes as usize),
+            drop_truncated: args.drop_truncated,
+            loop_guard: args.loop_guard.clone(),
+            stall_threshold: Duration::from_millis(args.stall_threshold),
+            repeat,
+        });
+        all_threads.push(("reader", reader_handle));
+    }
+
+    // Replaying a large capture is otherwise silent until it finishes
+    if args.input.as_deref().is_some_and(|input| input != "-") {
+        let handle = progress::spawn(progress::ProgressConfig {
+            shared_state: shared_state.clone(),
+            interactive: !args.quiet && std::io::stderr().is_terminal(),
+            log_step: PROGRESS_LOG_STEP,
+        });
+        all_threads.push(("progress", handle));
+    }
+
+    if let (Some(socket), Some(path)) = (control_socket, args.control.as_deref()) {
+        let handle = control::spawn(socket, PathBuf::from(path), shared_state.clone());
+        all_threads.push(("control", handle));
+    }
+
+    if args.diagnose && mode.receive {
+        let handle = diagnose::spawn(diagnose::DiagnoseConfig {
+            shared_state: shared_state.clone(),
+            iface: iface.clone(),
+            mgroup: mgroup.clone(),
+            port: args.port.first(),
+            after: DIAGNOSE_AFTER,
+        });
+        all_threads.push(("diagnose", handle));
+    }
+
+    let ctrl_c = shared_state.clone();
+    ctrlc::set_handler(move || {
+        log::debug!("Exiting...");
+        ctrl_c.signal_exit();
+    })?;
+    // SIGUSR1 snapshots the stats, SIGUSR2 flips hex dumps. Without a stats
+    // thread to take them, a snapshot is just the totals below.
+    shared_state.register_signals()?;
+    // SIGHUP is logrotate asking for the output files to be reopened
+    let mut hangup = None;
+    if output_formats
+        .iter()
+        .any(|(output, chosen)| output != "-" && chosen.format() != Some(OutputFormat::Sigmf))
+    {
+        if args.sandbox {
+            // A sandboxed writer can't open them again, so only say so
+            let flag = Arc::new(AtomicBool::new(false));
+            signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&flag))?;
+            hangup = Some(flag);
+        } else {
+            shared_state.register_reopen()?;
+        }
+    }
+
+    // The pipeline threads locked themselves down once set up
+    if args.sandbox {
+        match sandbox::enter("main")? {
+            sandbox::Enforced::SeccompOnly => {
+                log::warn!("sandboxed without Landlock, which this kernel lacks")
+            }
+            _ => log::info!("sandboxed"),
+        }
+    }
+
+    Ok(Running {
+        threads: all_threads,
+        idle_input,
+        hangup,
+    })
+}
+
+/// Watch over the running threads until they're done, or exit is signaled
+/// and --drain-timeout runs out.
+fn supervise(args: &Args, shared_state: &SharedState, running: Running) -> Outcome {
+    let Running {
+        threads: mut all_threads,
+        mut idle_input,
+        hangup,
+    } = running;
+    let has_statistics = wants_statistics(args);
+
+    // The first stage to fail decides the exit code; the others usually
+    // just report the broken channel it left behind.
+    let mut failed_stage: Option<&str> = None;
+    let mut exiting_since: Option<Instant> = None;
+    let drain_timeout = drain_timeout(args);
+    let started = Instant::now();
+    let mut last_read = (started, 0u64);
+    let mut last_status = (started, 0u64);
+    let mut idled_out = false;
+    let mut first_packet: Option<Instant> = None;
+    loop {
+        if hangup
+            .as_ref()
+            .is_some_and(|flag| flag.swap(false, Ordering::Relaxed))
+        {
+            log::warn!("SIGHUP: output files can't be reopened under --sandbox");
+        }
+
+        if !has_statistics && shared_state.take_snapshot() {
+            log::info!(
+                "{} packets read, {} written",
+                shared_state.get_read_count(),
+                shared_state.get_write_count()
+            );
+        }
+
+        if !shared_state.should_exit() {
+            let read = shared_state.get_read_count();
+            if first_packet.is_none() && read > 0 {
+                first_packet = Some(Instant::now());
+            }
+            // --wait-first times the run from the first packet instead
+            let timed_from = if args.wait_first {
+                first_packet
+            } else {
+                Some(started)
+            };
+
+            if let (Some(duration), Some(since)) = (args.duration, timed_from)
+                && since.elapsed() >= Duration::from_secs(duration)
+            {
+                log::info!("{duration}s elapsed");
+                shared_state.signal_exit();
+            }
+
+            if read != last_read.1 {
+                last_read = (Instant::now(), read);
+            } else if let Some(idle) = args.idle_timeout
+                && timed_from.is_some()
+                && last_read.0.elapsed() >= Duration::from_secs(idle)
+            {
+                log::warn!("no packets for {idle}s");
+                idled_out = true;
+                shared_state.signal_exit();
+            }
+
+            if let Some(timeout) = args.start_timeout
+                && first_packet.is_none()
+                && started.elapsed() >= Duration::from_secs(timeout)
+            {
+                log::warn!("no first packet after {timeout}s");
+                idled_out = true;
+                shared_state.signal_exit();
+            }
+
+            if shared_state.expect.failed() {
+                shared_state.signal_exit();
+            }
+
+            // For systemctl status, when running as a Type=notify service
+            if last_status.0.elapsed() >= SYSTEMD_STATUS_INTERVAL {
+                let rate = (read - last_status.1) as f64 / last_status.0.elapsed().as_secs_f64();
+                systemd::notify(&format!(
+                    "STATUS={read} packets read, {rate:.1} pkt/s, {} written",
+                    shared_state.get_write_count()
+                ));
+                last_status = (Instant::now(), read);
+            }
+        }
+        if shared_state.should_exit() {
+            idle_input.take();
+        }
+
+        // Give the writer --drain-timeout to flush what's queued once exit has been signaled.
+        if shared_state.should_exit() {
+            if exiting_since.is_none() {
+                systemd::notify("STOPPING=1");
+            }
+            let since = *exiting_since.get_or_insert_with(Instant::now);
+            if drain_timeout.is_some_and(|timeout| since.elapsed() > timeout) {
+                let stages: Vec<&str> = all_threads.iter().map(|(stage, _)| *stage).collect();
+                log::error!(
+                    "Timed out waiting {}s for {}",
+                    args.drain_timeout,
+                    stages.join(", ")
+                );
+                break;
+            }
+        }
+
+        let mut still_running = Vec::new();
+        for (stage, handle) in all_threads.into_iter() {
+            // Non-blocking check if thread has finished
+            if !handle.is_finished() {
+                still_running.push((stage, handle));
+                continue;
+            }
+
+            let result = handle
+                .join()
+                .unwrap_or_else(|e| Err(error::LibError::Critical(format!("panicked: {e:?}"))));
+            match result {
+                Ok(()) => {}
+                // Logged by the stage, and counted below as abandoned
+                Err(error::LibError::DrainIncomplete) => {}
+                // Logged as the sink failed, and exits with its own code
+                Err(error::LibError::ExpectFailed(_)) => {}
+                Err(e) => match failed_stage {
+                    None => {
+                        log::error!("{stage} failed: {e}");
+                        failed_stage = Some(stage);
+                    }
+                    Some(first) => log::debug!("{stage} failed after {first}: {e}"),
+                },
+            }
+            shared_state.signal_exit();
+        }
+        all_threads = still_running;
+
+        if all_threads.is_empty() {
+            break;
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    Outcome {
+        failed: failed_stage.is_some(),
+        idled_out,
+    }
+}
+
+/// Log the totals once the pipeline is done, and return how many packets
+/// were read but never written.
+fn summarize(
+    args: &Args,
+    setup: &Setup,
+    shared_state: &SharedState,
+    count_direction: CountDirection,
+    nic: Option<(&mut nic::NicCounters, nic::NicSample)>,
+) -> u64 {
+    let &Setup {
+        ref mode,
+        ref filters,
+        max_count,
+        ..
+    } = setup;
+    let read = shared_state.get_read_count();
+    let written = shared_state.get_write_count();
+    // Policed, filtered and too short to strip packets were dropped on purpose
+    let policed = shared_state.get_policed();
+    let too_short = shared_state.get_too_short();
+    // Counted as written, though a file on a full disk never got them
+    let disk_full = shared_state.get_disk_full();
+    let (filtered, unparsed) = shared_state.filters.get();
+    // Parity packets are read but never written, rebuilt ones the other way round
+    let fec = shared_state.fec.get();
+    let (parity, recovered) = match args.fec_decode {
+        Some(_) => (fec.parity, fec.recovered),
+        None => (0, 0),
+    };
+    // Converted packets come out as however many records they make
+    let convert = shared_state.convert.get();
+    let (passed, unwritten) = match args.convert {
+        Some(_) => (convert.taken, convert.records.saturating_sub(written)),
+        None => (written.saturating_sub(recovered), 0),
+    };
+    // Counting writes, whatever was read past the count is left unsent on purpose
+    let lost = if count_direction == CountDirection::Tx && max_count > 0 && written >= max_count {
+        0
+    } else {
+        read.saturating_sub(parity)
+            .saturating_sub(passed)
+            .saturating_sub(policed)
+            .saturating_sub(filtered + unparsed)
+            .saturating_sub(too_short)
+            + unwritten
+    };
+    let mut summary = format!("{read} packets read, {written} written");
+    if !filters.is_empty() {
+        summary.push_str(&format!(", {filtered} filtered"));
+        if unparsed > 0 {
+            summary.push_str(&format!(", {unparsed} unparsed"));
+        }
+    }
+    if args.police.is_some() {
+        summary.push_str(&format!(", {policed} policed"));
+    }
+    if args.strip.is_some() {
+        summary.push_str(&format!(", {too_short} too short to strip"));
+    }
+    if let Some(conversion) = args.convert {
+        summary.push_str(&format!(
+            ", {} converted {conversion} into {} records",
+            convert.taken - convert.skipped,
+            convert.records
+        ));
+        if convert.skipped > 0 {
+            summary.push_str(&format!(", {} with nothing to convert", convert.skipped));
+        }
+    }
+    if disk_full > 0 {
+        summary.push_str(&format!(", {disk_full} lost to a full disk"));
+    }
+    let own = shared_state.own_traffic.dropped();
+    if own > 0 {
+        summary.push_str(&format!(", {own} of our own dropped"));
+    }
+    let truncated = shared_state.get_truncated();
+    if truncated > 0 {
+        summary.push_str(&format!(
+            ", {truncated} truncated{}",
+            if args.drop_truncated {
+                " and dropped"
+            } else {
+                ""
+            }
+        ));
+    }
+    let abandoned = shared_state.get_abandoned();
+    if abandoned > 0 {
+        summary.push_str(&format!(", {abandoned} abandoned on exit"));
+    }
+    if args.max_restarts.is_some() {
+        summary.push_str(&format!(
+            ", {} reader restarts",
+            shared_state.get_restarts()
+        ));
+    }
+    let (stalls, worst) = shared_state.stalls.get();
+    if stalls > 0 {
+        summary.push_str(&format!(
+            ", {}",
+            reader::format_stalls(stalls, worst, shared_state.stalls.threshold())
+        ));
+    }
+    summary.push_str(&format!(
+        "; {} read, {} written",
+        preflight::format_size(shared_state.get_read_bytes()),
+        preflight::format_size(shared_state.get_write_bytes())
+    ));
+    log::info!("{summary}");
+    if args.batch_stats {
+        let calls = shared_state.batch_fill.get();
+        log::info!(
+            "{} recvmmsg calls, {}",
+            calls.iter().sum::<u64>(),
+            reader::format_batch_fill(&calls)
+        );
+    }
+    if args.mark_gaps {
+        log::info!("gap markers: {}", shared_state.gap_marks.get().format());
+    }
+    if args.unique.is_some() && wants_statistics(args) {
+        let distinct = shared_state.unique.get();
+        log::info!("unique: {} / total: {read}", unique::format_count(distinct));
+    }
+    if let Some((nic, start)) = nic {
+        let totals = nic.sample().format_totals(&start);
+        log::info!("nic {}: {totals}", nic.iface());
+    }
+    if mode.transmit.is_some() && args.port.is_many() {
+        let sent: Vec<String> = shared_state
+            .transmit
+            .get()
+            .per_destination
+            .iter()
+            .map(|(destination, sent)| format!("{destination} {sent}"))
+            .collect();
+        log::info!("sent: {}", sent.join(", "));
+    }
+    if let Some(split_by) = args.split_by {
+        let totals = shared_state.split.get();
+        let files: Vec<String> = totals
+            .iter()
+            .map(|(key, bytes)| {
+                let key = match key {
+                    SplitKey::Source(source) => shared_state.names.format(*source),
+                    key => key.to_string(),
+                };
+                format!("{key} {}", preflight::format_size(*bytes))
+            })
+            .collect();
+        log::info!("{} {}: {}", totals.len(), split_by.noun(), files.join(", "));
+    }
+    if args.expect.is_some() {
+        let totals = shared_state.expect.get();
+        log::info!(
+            "expect: {} matched, {} mismatched, {} missing, {} extra",
+            totals.matched,
+            totals.mismatched,
+            totals.missing,
+            totals.extra
+        );
+    }
+    if !args.patch.is_empty() {
+        let (applied, skipped) = shared_state.patch.get();
+        log::info!("patch: {applied} applied, {skipped} skipped as past the end of the packet");
+    }
+    if args.fec.is_some() {
+        log::info!("fec: {} data, {} parity sent", fec.data, fec.parity);
+    }
+    if args.fec_decode.is_some() {
+        log::info!(
+            "fec: {} data, {} parity, {} recovered, {} unrecoverable, {} corrupt",
+            fec.data,
+            fec.parity,
+            fec.recovered,
+            fec.unrecoverable,
+            fec.corrupt
+        );
+    }
+    if lost > 0 {
+        log::warn!("{lost} packets were read but never written");
+    }
+
+    lost
+}
+
+/// --ping or --echo instead of the reader and writer threads.
+/// A ping that lost probes exits as if packets were dropped.
+fn ping_or_echo(
+    args: &Args,
+    iface: Option<String>,
+    mgroup: String,
+    count: u64,
+) -> anyhow::Result<u8> {
+    let shared_state = SharedState::new(args.packet_type, false);
+    let ctrl_c = shared_state.clone();
+    ctrlc::set_handler(move || ctrl_c.signal_exit())?;
+
+    let (reply_iface, reply_group) = args
+        .reply_group
+        .clone()
+        .unwrap_or_else(|| (iface.clone(), mgroup.clone()));
+    let config = ping::PingConfig {
+        shared_state,
+        iface,
+        mgroup,
+        reply_iface,
+        reply_group,
+        port: args.port.first(),
+        ttl: args.ttl,
+        count,
+    };
+
+    if args.echo {
+        return Ok(match ping::echo(&config) {
+            Ok(echoed) => {
+                log::info!("{echoed} probes answered");
+                0
+            }
+            Err(e) => {
+                log::error!("echo failed: {e}");
+                EXIT_IO
+            }
+        });
+    }
+
+    Ok(match ping::ping(&config) {
+        Ok(summary) => {
+            for line in summary.lines(&config.mgroup) {
+                log::info!("{line}");
+            }
+            if summary.lost() > 0 { EXIT_DROPS } else { 0 }
+        }
+        Err(e) => {
+            log::error!("ping failed: {e}");
+            EXIT_IO
+        }
+    })
+}
+
+// Failures trump a mismatch with --expect, then a sequence gap, then an idle
+// timeout, then drops.
+fn exit_code(failed: bool, mismatched: bool, gap_failed: bool, idled_out: bool, lost: u64) -> u8 {
+    if failed {
+        EXIT_IO
+    } else if mismatched {
+        EXIT_MISMATCH
+    } else if gap_failed {
+        EXIT_GAP
+    } else if idled_out {
+        EXIT_IDLE
+    } else if lost > 0 {
+        EXIT_DROPS
+    } else {
+        0
+    }
+}
@@ -0,0 +1,115 @@
+/// --police: a token bucket between the reader and the writer that passes
+/// packets within the rate and drops the rest, the way a network policer
+/// does. Packets are never queued or delayed, only passed or dropped.
+use std::time::Instant;
+
+/// Packets per second and how many may arrive back to back, from
+/// `--police PPS[:BURST]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoliceRate {
+    pub pps: u64,
+    pub burst: u64,
+}
+
+/// Parse PPS[:BURST]. Without a burst, a tenth of a second's packets may
+/// arrive at once, since a whole recvmmsg batch arrives at the same instant.
+pub fn parse_police(s: &str) -> std::result::Result<PoliceRate, String> {
+    let number = |n: &str| match n.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Expected PPS[:BURST] above 0, got: {s}")),
+    };
+    let (pps, burst) = match s.split_once(':') {
+        Some((pps, burst)) => (number(pps)?, number(burst)?),
+        None => {
+            let pps = number(s)?;
+            (pps, (pps / 10).max(1))
+        }
+    };
+    Ok(PoliceRate { pps, burst })
+}
+
+#[derive(Debug)]
+pub struct Policer {
+    rate: PoliceRate,
+    tokens: f64,
+    last: Option<Instant>,
+}
+
+impl Policer {
+    /// Starts with a full bucket.
+    pub fn new(rate: PoliceRate) -> Self {
+        Self {
+            rate,
+            tokens: rate.burst as f64,
+            last: None,
+        }
+    }
+
+    /// Whether a packet arriving at now is within the rate. One that is
+    /// takes a token; one that isn't should be dropped.
+    pub fn conform(&mut self, now: Instant) -> bool {
+        if let Some(last) = self.last {
+            let elapsed = now.saturating_duration_since(last).as_secs_f64();
+            self.tokens =
+                (self.tokens + elapsed * self.rate.pps as f64).min(self.rate.burst as f64);
+        }
+        self.last = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_police() {
+        assert_eq!(
+            parse_police("1000"),
+            Ok(PoliceRate {
+                pps: 1000,
+                burst: 100
+            })
+        );
+        assert_eq!(parse_police("5"), Ok(PoliceRate { pps: 5, burst: 1 }));
+        assert_eq!(
+            parse_police("1000:10"),
+            Ok(PoliceRate {
+                pps: 1000,
+                burst: 10
+            })
+        );
+        assert!(parse_police("0").is_err());
+        assert!(parse_police("1000:").is_err());
+        assert!(parse_police("fast").is_err());
+    }
+
+    #[test]
+    fn test_policer() {
+        let mut policer = Policer::new(PoliceRate { pps: 100, burst: 3 });
+        let start = Instant::now();
+
+        // The burst passes, the rest of the instant is dropped
+        let passed = (0..10).filter(|_| policer.conform(start)).count();
+        assert_eq!(passed, 3);
+
+        // 10ms earns one packet at 100 pps
+        let later = start + Duration::from_millis(10);
+        assert!(policer.conform(later));
+        assert!(!policer.conform(later));
+
+        // A long pause refills no more than the burst
+        let much_later = later + Duration::from_secs(10);
+        let passed = (0..10).filter(|_| policer.conform(much_later)).count();
+        assert_eq!(passed, 3);
+    }
+}
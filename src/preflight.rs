@@ -0,0 +1,211 @@
+/// Checks behind --dry-run.
+/// Nothing here joins a group or sends a packet, so running them never puts
+/// a membership report on the wire.
+use std::fs::{self, OpenOptions};
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+use nix::ifaddrs::getifaddrs;
+use nix::net::if_::InterfaceFlags;
+use nix::sys::statvfs::statvfs;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{
+    error::{LibError, Result},
+    multicast::{
+        classify, get_default_interface_for_multicast, get_interface_addr, parse_groups,
+        split_iface,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceStatus {
+    pub name: String,
+    pub addr: Ipv4Addr,
+    pub up: bool,
+    pub multicast: bool,
+}
+
+/// The group must be an IPv4 multicast or unicast address, see
+/// [`classify`], and the port non-zero.
+pub fn check_group(mgroup: &str, port: u16) -> Result<Ipv4Addr> {
+    let addr: Ipv4Addr = mgroup.parse()?;
+    classify(&addr)?;
+    if port == 0 {
+        return Err(LibError::Critical("port 0 is not usable".to_string()));
+    }
+
+    Ok(addr)
+}
+
+/// Like [`check_group`] for a list or range of groups. Returns the first,
+/// which decides the interface when none is given.
+pub fn check_groups(mgroup: &str, port: u16) -> Result<Ipv4Addr> {
+    let groups = parse_groups(mgroup)?;
+    for group in &groups {
+        check_group(&group.to_string(), port)?;
+    }
+    groups
+        .first()
+        .copied()
+        .ok_or_else(|| LibError::Critical(format!("{mgroup}: no groups")))
+}
+
+/// Find the interface we would use, by name or by the route to the group.
+pub fn resolve_interface(iface: Option<&str>, mgroup: &Ipv4Addr) -> Result<InterfaceStatus> {
+    let (name, addr) = match iface {
+        Some(iface) => (Some(split_iface(iface)?.0), get_interface_addr(iface)?),
+        None => (None, get_default_interface_for_multicast(mgroup)?),
+    };
+
+    for ifaddr in getifaddrs()? {
+        let found = ifaddr
+            .address
+            .as_ref()
+            .and_then(|address| address.as_sockaddr_in())
+            .is_some_and(|sockaddr| sockaddr.ip() == addr);
+        if found && name.is_none_or(|name| ifaddr.interface_name == name) {
+            return Ok(InterfaceStatus {
+                name: ifaddr.interface_name,
+                addr,
+                up: ifaddr.flags.contains(InterfaceFlags::IFF_UP),
+                multicast: ifaddr.flags.contains(InterfaceFlags::IFF_MULTICAST),
+            });
+        }
+    }
+
+    Err(LibError::Critical(match name {
+        Some(name) => format!("Interface {name} not found or has no IPv4 address"),
+        None => format!("No interface routes to {mgroup}"),
+    }))
+}
+
+/// Ask for a receive buffer on a scratch socket and report what the kernel granted.
+pub fn probe_recv_buffer(size: usize) -> Result<usize> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_recv_buffer_size(size)?;
+    Ok(socket.recv_buffer_size()?)
+}
+
+/// Make sure path can be written without touching existing contents,
+/// and that its filesystem has at least min_free bytes. Returns bytes free.
+pub fn check_output(path: &str, min_free: u64) -> Result<u64> {
+    let path = Path::new(path);
+
+    if path.exists() {
+        OpenOptions::new().append(true).open(path)?;
+    } else {
+        OpenOptions::new().write(true).create_new(true).open(path)?;
+        fs::remove_file(path)?;
+    }
+
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let stats = statvfs(dir)?;
+    let free = stats.blocks_available() as u64 * stats.fragment_size() as u64;
+
+    if free < min_free {
+        return Err(LibError::Critical(format!(
+            "{} has {} free, need {}",
+            dir.display(),
+            format_size(free),
+            format_size(min_free)
+        )));
+    }
+
+    Ok(free)
+}
+
+/// Parse a byte count with an optional K/M/G/T suffix (powers of 1024).
+pub fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let s = s.trim();
+    let (digits, shift) = match s.char_indices().last() {
+        Some((idx, 'K' | 'k')) => (s.get(..idx), 10),
+        Some((idx, 'M' | 'm')) => (s.get(..idx), 20),
+        Some((idx, 'G' | 'g')) => (s.get(..idx), 30),
+        Some((idx, 'T' | 't')) => (s.get(..idx), 40),
+        _ => (Some(s), 0),
+    };
+
+    digits
+        .and_then(|digits| digits.parse::<u64>().ok())
+        .and_then(|value| value.checked_mul(1 << shift))
+        .ok_or_else(|| format!("Expected a size like 512M or 10G, got: {s}"))
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS.get(unit).unwrap_or(&"B"))
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_group() {
+        assert!(check_group("239.1.1.1", 29495).is_ok());
+        assert!(check_group("10.1.1.1", 29495).is_ok());
+        assert!(check_group("255.255.255.255", 29495).is_err());
+        assert!(check_group("239.1.1.1", 0).is_err());
+        assert!(check_group("239.1.1.300", 29495).is_err());
+    }
+
+    #[test]
+    fn test_check_groups() {
+        assert_eq!(
+            check_groups("239.1.1.5-8", 29495).ok(),
+            Some(Ipv4Addr::new(239, 1, 1, 5))
+        );
+        assert!(check_groups("239.1.1.1,10.1.1.1", 29495).is_err());
+        assert!(check_groups("239.1.1.1-2", 0).is_err());
+    }
+
+    #[test]
+    fn test_resolve_interface() {
+        let group = Ipv4Addr::new(239, 1, 1, 1);
+        let lo = resolve_interface(Some("lo"), &group).expect("lo");
+        assert_eq!(lo.addr, Ipv4Addr::LOCALHOST);
+        assert!(lo.up);
+        assert!(resolve_interface(Some("no-such-iface0"), &group).is_err());
+    }
+
+    #[test]
+    fn test_check_output() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("mnc-preflight-{}", std::process::id()));
+        let path = path.to_str().expect("utf8 path");
+
+        assert!(check_output(path, 0).is_ok());
+        // Checking must not leave the file behind
+        assert!(!Path::new(path).exists());
+        assert!(check_output(path, u64::MAX).is_err());
+        assert!(check_output("/nonexistent-dir/capture.bin", 0).is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4K"), Ok(4096));
+        assert_eq!(parse_size("10G"), Ok(10 << 30));
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("99999999T").is_err());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512.0 B");
+        assert_eq!(format_size(3 << 29), "1.5 GiB");
+    }
+}
@@ -0,0 +1,214 @@
+/// Progress reporting while sending from a file.
+/// The reader publishes how far into the file it is; this thread renders it,
+/// in place on a terminal or as a log line every few percent otherwise.
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{SharedState, error::Result, preflight::format_size};
+
+// How often the progress line is refreshed.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How far the reader has got through its input.
+#[derive(Debug, Default)]
+pub struct InputProgress {
+    offset: AtomicU64,
+    size: AtomicU64,
+    done: AtomicBool,
+}
+
+impl InputProgress {
+    /// Total input size in bytes. Left at 0 when the input can't be sized.
+    pub fn set_size(&self, size: u64) {
+        self.size.store(size, Ordering::Relaxed);
+    }
+    pub fn add_offset(&self, delta: u64) {
+        self.offset.fetch_add(delta, Ordering::Relaxed);
+    }
+    /// The reader has stopped, whether at EOF, -c or an error.
+    pub fn finish(&self) {
+        self.done.store(true, Ordering::Relaxed);
+    }
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Relaxed)
+    }
+    /// (offset, size) in bytes
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.offset.load(Ordering::Relaxed),
+            self.size.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Progress thread configuration.
+/// Interactive redraws one line on stderr; otherwise a log line is emitted
+/// each time another log_step percent of the file has been read.
+pub struct ProgressConfig {
+    pub shared_state: SharedState,
+    pub interactive: bool,
+    pub log_step: u64,
+}
+
+/// Spawn the progress thread. It ends once the reader finishes or exit is signaled.
+pub fn spawn(config: ProgressConfig) -> JoinHandle<Result<()>> {
+    thread::spawn(move || {
+        run_progress(&config);
+        log::debug!("progress exited");
+        Ok(())
+    })
+}
+
+fn run_progress(
+    ProgressConfig {
+        shared_state,
+        interactive,
+        log_step,
+    }: &ProgressConfig,
+) {
+    let progress = &shared_state.input_progress;
+    let start = Instant::now();
+    let mut last = (start, 0u64);
+    let mut logged_percent = 0;
+
+    loop {
+        thread::sleep(PROGRESS_INTERVAL);
+        let finished = progress.is_done() || shared_state.should_exit();
+
+        let (offset, size) = progress.get();
+        if size == 0 {
+            if finished {
+                return;
+            }
+            continue;
+        }
+
+        let records = shared_state.get_read_count();
+        if finished {
+            let line = format_final(offset, size, records, start.elapsed());
+            if *interactive {
+                let _ = writeln!(io::stderr(), "\r{line}\x1b[K");
+            } else {
+                log::info!("{line}");
+            }
+            return;
+        }
+
+        let now = Instant::now();
+        let rate = rate(offset.saturating_sub(last.1), now.duration_since(last.0));
+        last = (now, offset);
+
+        let line = format_line(offset, size, records, rate);
+        if *interactive {
+            let _ = write!(io::stderr(), "\r{line}\x1b[K");
+        } else if let Some(step) = next_step(percent(offset, size), logged_percent, *log_step) {
+            logged_percent = step;
+            log::info!("{line}");
+        }
+    }
+}
+
+fn percent(offset: u64, size: u64) -> f64 {
+    if size == 0 {
+        return 0.0;
+    }
+    (offset.min(size) as f64 / size as f64) * 100.0
+}
+
+/// Bytes per second over the last interval.
+fn rate(bytes: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 { bytes as f64 / secs } else { 0.0 }
+}
+
+/// The highest multiple of step reached past the last one logged, if any.
+fn next_step(percent: f64, logged: u64, step: u64) -> Option<u64> {
+    if step == 0 {
+        return None;
+    }
+    let reached = (percent as u64 / step) * step;
+    (reached > logged).then_some(reached)
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{:.1}s", duration.as_secs_f64()),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60),
+    }
+}
+
+fn format_line(offset: u64, size: u64, records: u64, rate: f64) -> String {
+    let eta = if rate > 0.0 {
+        let remaining = size.saturating_sub(offset) as f64 / rate;
+        format_duration(Duration::from_secs_f64(remaining))
+    } else {
+        "-".to_string()
+    };
+
+    format!(
+        "sent {} / {} ({:.1}%)  records: {records}  rate: {}/s  eta: {eta}",
+        format_size(offset),
+        format_size(size),
+        percent(offset, size),
+        format_size(rate as u64),
+    )
+}
+
+fn format_final(offset: u64, size: u64, records: u64, elapsed: Duration) -> String {
+    format!(
+        "sent {} / {} ({:.1}%)  records: {records}  elapsed: {}",
+        format_size(offset),
+        format_size(size),
+        percent(offset, size),
+        format_duration(elapsed),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_step() {
+        assert_eq!(next_step(9.9, 0, 10), None);
+        assert_eq!(next_step(10.0, 0, 10), Some(10));
+        // A jump over several steps logs once, at the highest
+        assert_eq!(next_step(37.0, 10, 10), Some(30));
+        assert_eq!(next_step(37.0, 30, 10), None);
+        assert_eq!(next_step(100.0, 90, 10), Some(100));
+        assert_eq!(next_step(50.0, 0, 0), None);
+    }
+
+    #[test]
+    fn test_format_line() {
+        assert_eq!(
+            format_line(1 << 30, 4 << 30, 1000, (512 << 20) as f64),
+            "sent 1.0 GiB / 4.0 GiB (25.0%)  records: 1000  rate: 512.0 MiB/s  eta: 6.0s"
+        );
+        assert!(format_line(0, 100, 0, 0.0).ends_with("eta: -"));
+    }
+
+    #[test]
+    fn test_format_final() {
+        assert_eq!(
+            format_final(100, 100, 7, Duration::from_secs(3725)),
+            "sent 100.0 B / 100.0 B (100.0%)  records: 7  elapsed: 1h02m05s"
+        );
+    }
+
+    #[test]
+    fn test_input_progress() {
+        let progress = InputProgress::default();
+        progress.set_size(10);
+        progress.add_offset(4);
+        progress.add_offset(6);
+        assert_eq!(progress.get(), (10, 10));
+        assert!(!progress.is_done());
+        progress.finish();
+        assert!(progress.is_done());
+    }
+}
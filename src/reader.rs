@@ -1,34 +1,268 @@
 /// The reader thread pulls Packets from a memory pool initially.
 /// The Packets are the recycled through the writer thread to
 /// sidestep memory allocation as it is a large performance hit.
+use std::collections::{HashSet, VecDeque};
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, IoSliceMut};
+use std::io::{self, BufRead, BufReader, IoSliceMut, Read, Seek, SeekFrom};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::fd::AsFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
 
-use crossbeam_channel::{Receiver, Sender};
-use nix::sys::socket::{MsgFlags, MultiHeaders, SockaddrStorage, recvmmsg};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use nix::errno::Errno;
+#[cfg(mmsg)]
+use nix::libc;
+use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
+#[cfg(not(mmsg))]
+use nix::sys::socket::recvmsg;
+#[cfg(mmsg)]
+use nix::sys::socket::{ControlMessageOwned, MultiHeaders, RecvMsg, recvmmsg, setsockopt, sockopt};
+use nix::sys::socket::{MsgFlags, SockaddrStorage};
+#[cfg(mmsg)]
+use nix::sys::time::TimeSpec;
+use socket2::Socket;
 
 use crate::{
     MAX_PACKET_BYTES, SharedState,
     error::{LibError, Result},
-    multicast::{create_recv_socket, socket_to_raw_fd},
+    gaps, loop_guard,
+    multicast::{self, adopt_recv_socket, create_recv_socket, socket_to_raw_fd},
     packet::{PacketType, Packets},
+    pcap::{PcapInput, Record},
+    resync::{FrameScanner, FrameSync},
+    sandbox,
+    sched::{self, ThreadPlacement},
+    systemd,
+    template::{Rng, Template},
+    transport::BatchSender,
+    vita49::VrlpSync,
 };
 
-pub struct ReaderConfig {
-    pub input: Option<String>,
+/// File and stdin layout chosen with --input-format, instead of the packet type's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    /// One packet per line
+    Text,
+    /// Each payload after its u32 little endian length
+    Binary,
+    /// Each payload after its u64 little endian arrival time in nanoseconds
+    /// and u32 little endian length, paced by the arrival times
+    Timed,
+    /// Fixed size records with nothing between them, the size given by --chunk
+    Raw,
+    /// One JSON object per line with the payload in payload_b64, paced by
+    /// delay_us or timestamp_us when present
+    Jsonl,
+    /// VITA-49 VRLP frames back to back, each found by its header and
+    /// trailer, skipping anything between them
+    Vrlp,
+    /// The UDP payloads of a pcap capture, skipping records without one
+    Pcap,
+}
+
+/// How packets are laid out in an input file or stdin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFraming {
+    Lines(LineLimit),
+    LengthPrefixed,
+    /// Length prefixed records after their arrival time, see [`read_timed_header`]
+    Timed,
+    /// Every n bytes is a packet; the last may be short
+    Chunks(usize),
+    JsonLines,
+    /// VRLP frames found again by scanning, see [`FrameScanner`]
+    Vrlp,
+    /// pcap records, see [`crate::pcap::PcapInput`]
+    Pcap,
+}
+
+/// Lines in text input longer than this, not counting the newline, are an
+/// error unless --split-long-lines
+pub const DEFAULT_MAX_LINE_BYTES: usize = 64 * 1024;
+
+/// The longest line text input may have, and what happens to a longer one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineLimit {
+    pub max: usize,
+    /// Send a long line as packets of max bytes rather than failing
+    pub split: bool,
+    /// Lines bundled into each packet, as many as fit in [`MAX_BUNDLE_BYTES`]
+    pub per_packet: usize,
+    /// Longest a part bundle waits for more lines, from its first
+    pub max_latency: Option<Duration>,
+}
+
+impl Default for LineLimit {
+    fn default() -> Self {
+        Self {
+            max: DEFAULT_MAX_LINE_BYTES,
+            split: false,
+            per_packet: 1,
+            max_latency: None,
+        }
+    }
+}
+
+/// Bundled lines stop short of this, what fits in one Ethernet frame, so a
+/// bundle isn't fragmented. A longer line still goes on its own.
+pub const MAX_BUNDLE_BYTES: usize = 1472;
+
+// How long a part bundle waits on stdin for its next line before it's sent.
+const BUNDLE_WAIT: Duration = Duration::from_millis(10);
+
+/// How long a bundle begun at started may wait for its next line at now:
+/// BUNDLE_WAIT, but no later than max_latency after it began.
+fn bundle_wait(started: Instant, max_latency: Option<Duration>, now: Instant) -> Duration {
+    max_latency.map_or(BUNDLE_WAIT, |max| {
+        BUNDLE_WAIT.min((started + max).saturating_duration_since(now))
+    })
+}
+
+/// Where file and stdin packets are read from.
+trait Input: BufRead {
+    /// Whether more input turns up within timeout. Only a terminal or pipe
+    /// on stdin can leave a read waiting, so anything else always has more.
+    fn more_within(&mut self, _timeout: Duration) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+impl Input for BufReader<File> {}
+
+impl Input for &[u8] {}
+
+impl Input for BufReader<io::StdinLock<'static>> {
+    fn more_within(&mut self, timeout: Duration) -> Result<bool> {
+        if !self.buffer().is_empty() {
+            return Ok(true);
+        }
+        let stdin = io::stdin();
+        let mut fds = [PollFd::new(stdin.as_fd(), PollFlags::POLLIN)];
+        let timeout = PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX);
+        match poll(&mut fds, timeout) {
+            // Readable, or closed, which the read then finds
+            Ok(ready) => Ok(ready > 0),
+            Err(Errno::EINTR) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl InputFraming {
+    pub fn for_packet_type(packet_type: PacketType) -> Self {
+        match packet_type {
+            PacketType::Text => InputFraming::Lines(LineLimit::default()),
+            _ => InputFraming::LengthPrefixed,
+        }
+    }
+
+    /// Lines framing with limit in place of the default, any other unchanged.
+    pub fn with_line_limit(self, limit: LineLimit) -> Self {
+        match self {
+            InputFraming::Lines(_) => InputFraming::Lines(limit),
+            framing => framing,
+        }
+    }
+
+    /// The packet type's framing unless format says otherwise. Raw needs a
+    /// chunk size, which is only meaningful for raw.
+    pub fn new(
+        format: Option<InputFormat>,
+        packet_type: PacketType,
+        chunk: Option<usize>,
+    ) -> std::result::Result<Self, String> {
+        match (format, chunk) {
+            (Some(InputFormat::Raw), Some(chunk)) if (1..=MAX_PACKET_BYTES).contains(&chunk) => {
+                Ok(InputFraming::Chunks(chunk))
+            }
+            (Some(InputFormat::Raw), Some(chunk)) => {
+                Err(format!("--chunk {chunk} is not in 1..={MAX_PACKET_BYTES}"))
+            }
+            (Some(InputFormat::Raw), None) => Err("--input-format raw needs --chunk".to_string()),
+            (_, Some(_)) => Err("--chunk only applies to --input-format raw".to_string()),
+            (None, None) => Ok(InputFraming::for_packet_type(packet_type)),
+            (Some(InputFormat::Text), None) => Ok(InputFraming::Lines(LineLimit::default())),
+            (Some(InputFormat::Binary), None) => Ok(InputFraming::LengthPrefixed),
+            (Some(InputFormat::Timed), None) => Ok(InputFraming::Timed),
+            (Some(InputFormat::Jsonl), None) => Ok(InputFraming::JsonLines),
+            (Some(InputFormat::Vrlp), None) => Ok(InputFraming::Vrlp),
+            (Some(InputFormat::Pcap), None) => Ok(InputFraming::Pcap),
+        }
+    }
+}
+
+/// A group to join, and the ports to receive it on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Join {
     pub iface: Option<String>,
     pub mgroup: String,
-    pub port: u16,
+    pub ports: Vec<u16>,
+}
+
+/// Reader thread configuration.
+/// With no input the reader joins the groups and receives with recvmmsg.
+pub struct ReaderConfig {
+    pub input: Option<String>,
+    /// Make packets from --template instead of reading any
+    pub template: Option<Template>,
+    /// --template packets a second, as fast as they are taken when None
+    pub pps: Option<u64>,
+    /// One socket for each group and port, all feeding the same channel
+    pub joins: Vec<Join>,
     pub batch_size: usize,
-    pub channels: (Sender<Packets>, Receiver<Packets>),
+    pub channels: (Box<dyn BatchSender>, Receiver<Packets>),
     pub shared_state: SharedState,
     pub max_count: u64,
+    /// File and stdin layout, the packet type's when None
+    pub input_framing: Option<InputFraming>,
+    /// Start with small receive buffers and grow them with the traffic,
+    /// rather than using full size batches from the first packet
+    pub adaptive_buffers: bool,
+    /// Replay speed for timed input: recorded gaps are divided by it
+    pub speed: f64,
+    pub placement: ThreadPlacement,
+    /// Lock the thread down once the input is open
+    pub sandbox: bool,
+    /// Open the group's sockets afresh after a socket error, up to this
+    /// many times in any hour, rather than ending the run
+    pub max_restarts: Option<u32>,
+    /// Largest datagram received whole, longer ones are cut to it
+    pub max_packet: usize,
+    /// Leave datagrams that were cut short out of the stream
+    pub drop_truncated: bool,
+    /// Drop packets ending in this marker as our own come back, see
+    /// [`crate::loop_guard`]
+    pub loop_guard: Option<Vec<u8>>,
+    /// A gap between recvmmsg calls longer than this, with datagrams
+    /// waiting, counts as a stall
+    pub stall_threshold: Duration,
+    /// Read the input file again each time it ends, for --loop
+    pub repeat: Option<Repeat>,
 }
 
+/// How often --loop reads the input file.
+#[derive(Debug, Clone)]
+pub struct Repeat {
+    /// Times through the file, 0 for ever
+    pub passes: u64,
+    /// The memory pool, where the end of every pass but the last goes back
+    /// rather than on to the writer
+    pub memory_return: Sender<Packets>,
+}
+
+/// Spawn the reader thread. Any error also signals exit to the other threads.
 pub fn spawn(config: ReaderConfig) -> JoinHandle<Result<()>> {
     thread::spawn(move || {
-        run_reader(&config)
+        let mut config = config;
+        sched::apply("reader", &config.placement);
+        config.shared_state.cpu.register("reader");
+        let result = run_reader(&mut config);
+        config.shared_state.input_progress.finish();
+        result
             .inspect(|_| log::debug!("reader exited"))
             .inspect_err(|e| {
                 log::debug!("{e:?}");
@@ -40,131 +274,445 @@ pub fn spawn(config: ReaderConfig) -> JoinHandle<Result<()>> {
 pub fn run_reader(
     ReaderConfig {
         input,
-        iface,
-        mgroup,
-        port,
+        template,
+        pps,
+        joins,
         batch_size,
         channels,
         shared_state,
         max_count,
-    }: &ReaderConfig,
+        input_framing,
+        adaptive_buffers,
+        speed,
+        placement: _,
+        sandbox,
+        max_restarts,
+        max_packet,
+        drop_truncated,
+        loop_guard,
+        stall_threshold,
+        repeat,
+    }: &mut ReaderConfig,
 ) -> Result<()> {
+    let framing =
+        input_framing.unwrap_or_else(|| InputFraming::for_packet_type(shared_state.packet_type));
+    let ready = || -> Result<()> {
+        systemd::notify("READY=1");
+        if *sandbox {
+            sandbox::enter("reader")?;
+        }
+        Ok(())
+    };
+    if let Some(template) = template {
+        log::info!("sending packets made from the template");
+        ready()?;
+        return generate_from_template(
+            template,
+            *pps,
+            *batch_size,
+            channels,
+            shared_state,
+            *max_count,
+        );
+    }
     match &input {
         Some(filename) if filename == "-" => {
             log::info!("reading from stdin");
-            read_from_stdin(channels, shared_state, *max_count)
+            ready()?;
+            read_from_stdin(framing, channels, shared_state, *max_count, *speed)
         }
         Some(filename) => {
             log::info!("reading from {filename}");
-            read_from_file(filename, channels, shared_state, *max_count)
+            let file = File::open(filename)?;
+            ready()?;
+            match repeat {
+                Some(repeat) => read_from_file_repeatedly(
+                    file,
+                    framing,
+                    repeat,
+                    channels,
+                    shared_state,
+                    *max_count,
+                    *speed,
+                ),
+                None => read_from_file(file, framing, channels, shared_state, *max_count, *speed),
+            }
         }
         None => {
-            let iface_str = match iface {
-                Some(iface_str) => format!("{iface_str}:"),
-                None => "".to_string(),
-            };
-            log::info!("reading from {iface_str}{mgroup}");
-            read_from_network(
-                iface.as_deref(),
+            shared_state.stalls.set_threshold(*stall_threshold);
+            let mut targets = Vec::new();
+            for Join {
+                iface,
                 mgroup,
-                *port,
-                *batch_size,
-                channels,
-                shared_state,
-                *max_count,
-            )
+                ports,
+            } in joins.iter()
+            {
+                let iface_str = match iface {
+                    Some(iface_str) => format!("{iface_str}:"),
+                    None => "".to_string(),
+                };
+                match ports.as_slice() {
+                    [_] => log::info!("reading from {iface_str}{mgroup}"),
+                    _ => log::info!("reading from {iface_str}{mgroup} ports {ports:?}"),
+                }
+                let group: Ipv4Addr = mgroup.parse()?;
+                targets.extend(ports.iter().map(|&port| SocketAddrV4::new(group, port)));
+            }
+            let mut sizing = BufferSizing::new(*adaptive_buffers, *batch_size, *max_packet);
+            let mut sockets = open_sockets(joins)?;
+            for join in joins.iter() {
+                let group: Ipv4Addr = join.mgroup.parse()?;
+                if group.is_multicast()
+                    && let Some(socket) = targets
+                        .iter()
+                        .position(|target| *target.ip() == group)
+                        .and_then(|index| sockets.get(index))
+                {
+                    multicast::verify_join(socket, join.iface.as_deref(), &group)?;
+                }
+            }
+            // Only now is the group joined
+            ready()?;
+            let mut budget = max_restarts.map(RestartBudget::new);
+            // The batch in hand when a read failed, so restarts don't bleed
+            // the memory pool
+            let mut spare = None;
+            loop {
+                let mut error = match read_from_network(
+                    &sockets,
+                    &targets,
+                    &mut sizing,
+                    *drop_truncated,
+                    loop_guard.as_deref(),
+                    channels,
+                    shared_state,
+                    *max_count,
+                    &mut spare,
+                ) {
+                    Ok(()) => return Ok(()),
+                    Err(e) => e,
+                };
+                // Reopening can fail too while an interface is down
+                sockets = loop {
+                    match budget.as_mut() {
+                        Some(budget) if is_transient(&error) && !shared_state.should_exit() => {
+                            budget.spend(&error, Instant::now())?;
+                        }
+                        _ => return Err(error),
+                    }
+                    shared_state.add_restart();
+                    drop(std::mem::take(&mut sockets));
+                    if !pause(RESTART_DELAY, shared_state) {
+                        return Ok(());
+                    }
+                    match open_sockets(joins) {
+                        Ok(sockets) => break sockets,
+                        Err(e) => error = e,
+                    }
+                };
+            }
         }
     }
 }
 
-pub fn read_from_network(
-    iface: Option<&str>,
-    mgroup: &str,
-    port: u16,
-    batch_size: usize,
-    (data_tx, memory_return_rx): &(Sender<Packets>, Receiver<Packets>),
+// Wait before reopening the sockets, so a flapping interface gets a moment
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+// --max-restarts counts the restarts within this long
+const RESTART_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Errors a fresh socket might get past: anything from the socket calls,
+/// rather than the pipeline's own channels closing.
+fn is_transient(error: &LibError) -> bool {
+    matches!(error, LibError::Io(_) | LibError::Nix(_))
+}
+
+/// Sleep for delay, waking early on exit. False if exit was signaled.
+fn pause(delay: Duration, shared_state: &SharedState) -> bool {
+    let until = Instant::now() + delay;
+    while Instant::now() < until {
+        if shared_state.should_exit() {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS as u64).min(delay));
+    }
+    !shared_state.should_exit()
+}
+
+/// --max-restarts: restarts within the last hour, each one logged.
+#[derive(Debug)]
+struct RestartBudget {
+    limit: u32,
+    recent: VecDeque<Instant>,
+}
+
+impl RestartBudget {
+    fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Take one restart for error, or hand the error back once the hour's
+    /// budget is spent.
+    fn spend(&mut self, error: &LibError, now: Instant) -> Result<()> {
+        while self
+            .recent
+            .front()
+            .is_some_and(|&at| now.duration_since(at) >= RESTART_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= self.limit as usize {
+            return Err(LibError::Critical(format!(
+                "reader: {error}, giving up after {} restarts in an hour",
+                self.recent.len()
+            )));
+        }
+        self.recent.push_back(now);
+        log::warn!(
+            "reader: {error}, reopening the sockets ({} of {} restarts this hour)",
+            self.recent.len(),
+            self.limit
+        );
+        Ok(())
+    }
+}
+
+/// One socket per port in each group, or the one systemd passed for it.
+fn open_sockets(joins: &[Join]) -> Result<Vec<Socket>> {
+    joins
+        .iter()
+        .flat_map(|join| join.ports.iter().map(move |port| (join, *port)))
+        .map(|(join, port)| {
+            let iface = join.iface.as_deref();
+            match systemd::take_activated_socket(port) {
+                Some(socket) => {
+                    log::info!("receiving on port {port} with the socket systemd passed");
+                    adopt_recv_socket(socket, iface, &join.mgroup)
+                }
+                None => create_recv_socket(iface, &join.mgroup, port),
+            }
+        })
+        .collect()
+}
+
+/// Each batch comes from one socket, the group and port of its target.
+/// Every socket that poll finds readable gets a turn before polling again,
+/// so a busy port can't starve the others.
+#[allow(clippy::too_many_arguments)]
+fn read_from_network(
+    sockets: &[Socket],
+    targets: &[SocketAddrV4],
+    sizing: &mut BufferSizing,
+    drop_truncated: bool,
+    loop_guard: Option<&[u8]>,
+    (data_tx, memory_return_rx): &mut (Box<dyn BatchSender>, Receiver<Packets>),
     shared_state: &SharedState,
     max_count: u64,
+    spare: &mut Option<Packets>,
 ) -> Result<()> {
-    let socket = create_recv_socket(iface, mgroup, port)?;
-    let fd = socket_to_raw_fd(&socket);
+    let mut receivers = sockets
+        .iter()
+        .map(|socket| DatagramReceiver::new(socket, sizing.max_batch))
+        .collect::<Result<Vec<_>>>()?;
+    let mut ready = VecDeque::with_capacity(sockets.len());
+    // Groups joined here share ports with each other, so what one socket
+    // gets for another group is dropped without a warning
+    let mut foreign: HashSet<Ipv4Addr> = targets.iter().map(|target| *target.ip()).collect();
 
-    let mut headers = MultiHeaders::<SockaddrStorage>::preallocate(batch_size, None);
-    let mut byte_counts: Vec<usize> = Vec::with_capacity(batch_size);
+    let mut received: Vec<(usize, Ancillary, bool)> = Vec::with_capacity(sizing.max_batch);
+    // When the last recvmmsg returned, for the stall counters
+    let mut last_return: Option<SystemTime> = None;
 
+    // A batch that comes back with nothing in it is kept for the next round
+    // rather than dropped, so an idle group doesn't bleed the memory pool.
     loop {
+        if shared_state.should_exit() {
+            break;
+        }
+
         // Pull a recycled Packets from the memory pool (blocking)
-        let mut packets = memory_return_rx.recv()?;
+        let mut packets = match spare.take() {
+            Some(packets) => packets,
+            None => memory_return_rx.recv()?,
+        };
 
         if max_count > 0 && shared_state.get_read_count() >= max_count {
             // Send empty packets to signal EOF
             // Do not signal_exit() to give the other threads a chance
             // to finish processing what's left in the channels.
             packets.set_length(0);
-            write_packets_to_channel(packets, data_tx)?;
+            write_packets_to_channel(packets, data_tx, shared_state)?;
             break;
         }
 
-        // Reset packet buffer lengths to MAX_PACKET_BYTES for reuse
-        for packet in packets.iter_mut() {
-            packet.set_length(MAX_PACKET_BYTES);
+        // Wait for traffic in short slices so exit is seen promptly on an
+        // idle group, rather than sitting in recvmmsg until the next packet.
+        if ready.is_empty()
+            && let Err(e) = wait_readable(sockets, &mut ready)
+        {
+            *spare = Some(packets);
+            return Err(e);
         }
+        let Some(index) = ready.pop_front() else {
+            *spare = Some(packets);
+            continue;
+        };
+        let (Some(socket), Some(receiver), Some(&target)) = (
+            sockets.get(index),
+            receivers.get_mut(index),
+            targets.get(index),
+        ) else {
+            *spare = Some(packets);
+            continue;
+        };
 
-        // Create iovecs pointing to our persisten buffers.
-        let mut iovecs: Vec<[IoSliceMut; 1]> = packets
-            .iter_mut()
-            .map(|packet| [IoSliceMut::new(packet.data_mut())])
-            .collect();
+        // Only hand recvmmsg as much of the batch as the traffic needs,
+        // growing buffers that are smaller than the current packet size.
+        // Each gets the current packet size and no more, so --max-packet
+        // holds even for buffers grown larger earlier.
+        packets.set_length(sizing.batch);
+        for packet in packets.iter_mut() {
+            packet.ensure_capacity(sizing.packet_bytes);
+            packet.set_length(sizing.packet_bytes);
+        }
 
-        byte_counts.clear();
-        match recvmmsg(
-            fd,
-            &mut headers,
-            &mut iovecs,
-            MsgFlags::MSG_WAITFORONE,
-            None,
-        ) {
-            Ok(msgs) => {
-                byte_counts.extend(msgs.into_iter().map(|msg| msg.bytes));
-            }
-            Err(nix::errno::Errno::EAGAIN) => {
-                // Retry on EAGAIN
-            }
-            Err(e) => return Err(e.into()),
+        received.clear();
+        if let Err(e) = receiver.receive(socket, &mut packets, &mut received) {
+            *spare = Some(packets);
+            return Err(e);
         }
 
         if shared_state.should_exit() {
             break;
         }
 
-        let count_received = byte_counts.iter().take_while(|&&bytes| bytes > 0).count();
+        // How full the call was, before other groups' packets come out
+        shared_state.batch_fill.record(received.len());
+        // Away from the socket since the last call, less any time it sat
+        // idle before the first of these datagrams came in
+        let returned = SystemTime::now();
+        if let (Some(last), Some((_, first, _))) = (last_return, received.first()) {
+            shared_state.stalls.record(
+                returned
+                    .duration_since(last.max(first.arrival))
+                    .unwrap_or_default(),
+            );
+        }
+        last_return = Some(returned);
+        // A unicast socket has only what was sent to its own address
+        if target.ip().is_multicast() {
+            keep_group(&mut packets, &mut received, *target.ip(), &mut foreign);
+        }
+        check_truncated(
+            &mut packets,
+            &mut received,
+            sizing,
+            drop_truncated,
+            shared_state,
+        );
+        drop_own(&mut packets, &mut received, loop_guard, shared_state);
+
+        // Zero-length datagrams are legal (keepalives), so count messages, not bytes
+        let count_received = received.len();
+        sizing.observe(count_received);
 
         // Make sure we only send up to user specified max packets
-        let mut send_count = count_received;
-        if max_count > 0 {
-            let remaining = max_count - shared_state.get_read_count();
-            if send_count as u64 > remaining {
-                send_count = remaining as usize;
-            }
-        }
+        let (send_count, already_sent) =
+            shared_state.reserve_read_count(max_count, count_received as u64);
 
-        packets.set_length(send_count);
+        packets.set_length(send_count as usize);
 
         // Set each packet length to what recvmmsg tells us
-        #[allow(clippy::indexing_slicing)]
-        for (idx, &bytes_received) in byte_counts.iter().enumerate().take(send_count) {
-            packets.packets_mut()[idx].set_length(bytes_received);
+        for (packet, &(bytes_received, ancillary, _)) in packets.iter_mut().zip(received.iter()) {
+            packet.set_length(bytes_received.min(packet.len()));
+            packet.set_truncated(bytes_received.saturating_sub(packet.len()));
+            packet.set_timestamp(Some(ancillary.arrival));
+            packet.set_ttl(ancillary.ttl);
+            packet.set_source(ancillary.source);
+            // Without IP_PKTINFO, the socket's own group stands in
+            let group = ancillary
+                .destination
+                .or(Some(*target.ip()).filter(|ip| !ip.is_unspecified()));
+            packet.set_destination(group.map(|group| SocketAddrV4::new(group, target.port())));
         }
 
-        if !packets.is_empty() {
+        if packets.is_empty() {
+            *spare = Some(packets);
+        } else {
             // Send to next thread
-            write_packets_to_channel(packets, data_tx)?;
+            write_packets_to_channel(packets, data_tx, shared_state)?;
+        }
+
+        if max_count > 0 && already_sent >= max_count {
+            // Send empty packets to signal EOF
+            write_eof_to_channel(data_tx)?;
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// --template: packets made up rather than read, numbered from 0. With pps
+/// each is due at its own moment from the start and a batch holds the ones
+/// that have come due; without, every batch is full.
+fn generate_from_template(
+    template: &Template,
+    pps: Option<u64>,
+    batch_size: usize,
+    (data_tx, memory_return_rx): &mut (Box<dyn BatchSender>, Receiver<Packets>),
+    shared_state: &SharedState,
+    max_count: u64,
+) -> Result<()> {
+    let mut rng = Rng::from_time();
+    let mut payload = Vec::new();
+    let started = Instant::now();
+    let mut seq = 0u64;
+
+    loop {
+        // Pull a recycled Packets from the memory pool (blocking)
+        let mut packets = memory_return_rx.recv()?;
+
+        let mut wanted = batch_size.max(1) as u64;
+        if let Some(pps) = pps {
+            let next = started + Duration::from_secs_f64(seq as f64 / pps as f64);
+            if !sleep_until(next, shared_state) {
+                break;
+            }
+            let due = (started.elapsed().as_secs_f64() * pps as f64) as u64 + 1;
+            wanted = wanted.min(due.saturating_sub(seq).max(1));
         }
+        if shared_state.should_exit() {
+            break;
+        }
+
+        let (count, already_sent) = shared_state.reserve_read_count(max_count, wanted);
+        packets.restore();
+        packets.set_length(count as usize);
+        if packets.is_empty() {
+            // Send empty packets to signal EOF
+            write_packets_to_channel(packets, data_tx, shared_state)?;
+            break;
+        }
+        for packet in packets.iter_mut() {
+            let now = SystemTime::now();
+            template.expand(seq, now, &mut rng, &mut payload);
+            seq += 1;
+            packet.ensure_capacity(payload.len());
+            packet.set_length(payload.len());
+            if let Some(data) = packet.data_mut().get_mut(..payload.len()) {
+                data.copy_from_slice(&payload);
+            }
+            packet.set_timestamp(Some(now));
+        }
+        write_packets_to_channel(packets, data_tx, shared_state)?;
 
-        let already_sent = shared_state.add_read_count(send_count as u64);
         if max_count > 0 && already_sent >= max_count {
             // Send empty packets to signal EOF
-            write_packets_to_channel(Packets::empty(), data_tx)?;
+            write_eof_to_channel(data_tx)?;
             break;
         }
     }
@@ -173,72 +721,243 @@ pub fn read_from_network(
 }
 
 fn read_from_file(
-    filename: &str,
-    channels: &(Sender<Packets>, Receiver<Packets>),
+    file: File,
+    framing: InputFraming,
+    channels: &mut (Box<dyn BatchSender>, Receiver<Packets>),
+    shared_state: &SharedState,
+    max_count: u64,
+    speed: f64,
+) -> Result<()> {
+    let metadata = file.metadata()?;
+    if metadata.is_file() {
+        shared_state.input_progress.set_size(metadata.len());
+    }
+
+    read_framed(
+        BufReader::new(file),
+        framing,
+        channels,
+        shared_state,
+        max_count,
+        speed,
+    )
+}
+
+// --loop: the file from the start, again and again. Each pass ends as if the
+// file had, and Passes decides whether that end goes on to the writer.
+fn read_from_file_repeatedly(
+    mut file: File,
+    framing: InputFraming,
+    repeat: &Repeat,
+    channels: &mut (Box<dyn BatchSender>, Receiver<Packets>),
     shared_state: &SharedState,
     max_count: u64,
+    speed: f64,
 ) -> Result<()> {
-    let file = File::open(filename)?;
+    let metadata = file.metadata()?;
+    // Going on for ever has no end to show progress towards
+    if metadata.is_file() && repeat.passes > 0 {
+        shared_state
+            .input_progress
+            .set_size(metadata.len().saturating_mul(repeat.passes));
+    }
+
+    let again = Arc::new(AtomicBool::new(false));
+    // Passes has the data channel for the rest of the run
+    let data_tx = std::mem::replace(&mut channels.0, Box::new(repeat.memory_return.clone()));
+    let mut passes = (
+        Box::new(Passes {
+            data_tx,
+            memory_return: repeat.memory_return.clone(),
+            shared_state: shared_state.clone(),
+            max_count,
+            remaining: repeat.passes.checked_sub(1),
+            read_by_last_end: shared_state.get_read_count(),
+            again: again.clone(),
+        }) as Box<dyn BatchSender>,
+        channels.1.clone(),
+    );
+    for pass in 1.. {
+        file.seek(SeekFrom::Start(0))
+            .map_err(|e| LibError::Critical(format!("--loop can't go back to the start: {e}")))?;
+        read_framed(
+            BufReader::new(file.try_clone()?),
+            framing,
+            &mut passes,
+            shared_state,
+            max_count,
+            speed,
+        )?;
+        if !again.swap(false, Ordering::Relaxed) {
+            break;
+        }
+        log::debug!("pass {pass} done, reading the file again");
+    }
+    Ok(())
+}
+
+/// The data channel for --loop, passing on everything but the end of a pass
+/// that isn't the last, which goes back to the memory pool instead.
+struct Passes {
+    data_tx: Box<dyn BatchSender>,
+    memory_return: Sender<Packets>,
+    shared_state: SharedState,
+    max_count: u64,
+    // Passes to go after this one, None to go on for ever
+    remaining: Option<u64>,
+    // The read count when the last pass ended; a pass that adds nothing
+    // would only spin
+    read_by_last_end: u64,
+    // Set when a pass ended and the file should be read again
+    again: Arc<AtomicBool>,
+}
 
-    match shared_state.packet_type {
-        PacketType::Text => read_text_mode(BufReader::new(file), channels, shared_state, max_count),
-        _ => read_binary_mode(BufReader::new(file), channels, shared_state, max_count),
+impl BatchSender for Passes {
+    fn try_push(&mut self, mut packets: Packets) -> std::result::Result<(), TrySendError<Packets>> {
+        let read = self.shared_state.get_read_count();
+        if !packets.is_empty()
+            || self.remaining == Some(0)
+            || (self.max_count > 0 && read >= self.max_count)
+            || read == self.read_by_last_end
+            || self.shared_state.should_exit()
+        {
+            return self.data_tx.try_push(packets);
+        }
+        self.read_by_last_end = read;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= 1;
+        }
+        self.again.store(true, Ordering::Relaxed);
+        // The pool has room for every batch, so this only fails once the
+        // writer is gone, and the next pass finds that out for itself
+        packets.restore();
+        let _ = self.memory_return.try_send(packets);
+        Ok(())
     }
 }
 
 fn read_from_stdin(
-    channels: &(Sender<Packets>, Receiver<Packets>),
+    framing: InputFraming,
+    channels: &mut (Box<dyn BatchSender>, Receiver<Packets>),
     shared_state: &SharedState,
     max_count: u64,
+    speed: f64,
 ) -> Result<()> {
-    let stdin = io::stdin();
+    // Buffered here, where more_within can see whether a line is waiting
+    read_framed(
+        BufReader::new(io::stdin().lock()),
+        framing,
+        channels,
+        shared_state,
+        max_count,
+        speed,
+    )
+}
 
-    match shared_state.packet_type {
-        PacketType::Text => read_text_mode(stdin.lock(), channels, shared_state, max_count),
-        _ => read_binary_mode(stdin.lock(), channels, shared_state, max_count),
+fn read_framed<R: Input>(
+    reader: R,
+    framing: InputFraming,
+    channels: &mut (Box<dyn BatchSender>, Receiver<Packets>),
+    shared_state: &SharedState,
+    max_count: u64,
+    speed: f64,
+) -> Result<()> {
+    match framing {
+        InputFraming::Lines(limit) => {
+            read_text_mode(reader, limit, channels, shared_state, max_count)
+        }
+        InputFraming::LengthPrefixed => read_binary_mode(reader, channels, shared_state, max_count),
+        InputFraming::Timed => read_timed_mode(reader, channels, shared_state, max_count, speed),
+        InputFraming::Chunks(chunk) => {
+            read_raw_mode(reader, chunk, channels, shared_state, max_count)
+        }
+        InputFraming::JsonLines => {
+            read_jsonl_mode(reader, channels, shared_state, max_count, speed)
+        }
+        InputFraming::Vrlp => read_frames_mode(
+            reader,
+            FrameScanner::new(VrlpSync),
+            channels,
+            shared_state,
+            max_count,
+        ),
+        InputFraming::Pcap => read_pcap_mode(reader, channels, shared_state, max_count),
     }
 }
 
-fn read_text_mode<R: BufRead>(
+/// One packet per line, or with limit.per_packet several lines to a packet.
+fn read_text_mode<R: Input>(
     mut reader: R,
-    (data_tx, memory_return_rx): &(Sender<Packets>, Receiver<Packets>),
+    limit: LineLimit,
+    (data_tx, memory_return_rx): &mut (Box<dyn BatchSender>, Receiver<Packets>),
     shared_state: &SharedState,
     max_count: u64,
 ) -> Result<()> {
-    let mut line = String::new();
+    let mut line = Vec::new();
+    let mut bundle = Vec::new();
+    // The line read last didn't fit in the bundle before and is still to go
+    let mut held = false;
+    let mut eof = false;
 
     loop {
         // Pull a recycled Packets from the memory pool (blocking)
         let mut packets = memory_return_rx.recv()?;
 
-        line.clear();
-        let bytes_read = reader.read_line(&mut line)?;
+        bundle.clear();
+        let mut lines = 0;
+        let started = Instant::now();
+        while lines < limit.per_packet.max(1) {
+            if !held {
+                // Send what there is rather than wait on a quiet stdin, or
+                // past --max-latency
+                let wait = bundle_wait(started, limit.max_latency, Instant::now());
+                if lines > 0 && (wait.is_zero() || !reader.more_within(wait)?) {
+                    break;
+                }
+                line.clear();
+                let bytes_read = read_line_capped(&mut reader, &mut line, limit)?;
+                if bytes_read == 0 {
+                    eof = true;
+                    break;
+                }
+                shared_state.input_progress.add_offset(bytes_read as u64);
+                held = true;
+            }
+            if lines > 0 && bundle.len() + line.len() > MAX_BUNDLE_BYTES {
+                break;
+            }
+            bundle.extend_from_slice(&line);
+            held = false;
+            lines += 1;
+        }
 
         if shared_state.should_exit() {
             break;
         }
 
-        if bytes_read == 0 {
+        if lines == 0 {
             // EOF - send empty packets sentinel
             packets.set_length(0);
-            write_packets_to_channel(packets, data_tx)?;
+            write_packets_to_channel(packets, data_tx, shared_state)?;
             break;
         }
 
-        let packet_data = line.as_bytes();
+        let packet_data = bundle.as_slice();
         #[allow(clippy::indexing_slicing)]
         {
+            packets.packets_mut()[0].ensure_capacity(packet_data.len());
             packets.packets_mut()[0].data_mut()[..packet_data.len()].copy_from_slice(packet_data);
             packets.packets_mut()[0].set_length(packet_data.len());
+            packets.packets_mut()[0].set_timestamp(Some(SystemTime::now()));
         }
         packets.set_length(1);
 
-        write_packets_to_channel(packets, data_tx)?;
+        write_packets_to_channel(packets, data_tx, shared_state)?;
 
         let already_sent = shared_state.add_read_count(1);
-        if max_count > 0 && already_sent >= max_count {
+        if eof || (max_count > 0 && already_sent >= max_count) {
             // Send empty packets to signal EOF
-            write_packets_to_channel(Packets::empty(), data_tx)?;
+            write_eof_to_channel(data_tx)?;
             break;
         }
     }
@@ -246,9 +965,40 @@ fn read_text_mode<R: BufRead>(
     Ok(())
 }
 
+/// Read one line, newline included, into line without ever holding more than
+/// limit.max bytes of it. A longer line fails, or with limit.split comes back
+/// max bytes at a time and the rest on the following calls.
+pub(crate) fn read_line_capped<R: BufRead>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+    limit: LineLimit,
+) -> Result<usize> {
+    let read = reader
+        .by_ref()
+        .take(limit.max as u64)
+        .read_until(b'\n', line)?;
+    if read < limit.max || line.ends_with(b"\n") {
+        return Ok(read);
+    }
+
+    // Exactly max bytes so far: fine if the newline is next
+    if reader.fill_buf()?.first() == Some(&b'\n') {
+        reader.consume(1);
+        line.push(b'\n');
+        return Ok(read + 1);
+    }
+    if reader.fill_buf()?.is_empty() || limit.split {
+        return Ok(read);
+    }
+    Err(LibError::Critical(format!(
+        "Line longer than {} bytes, see --max-line-length and --split-long-lines",
+        limit.max
+    )))
+}
+
 fn read_binary_mode<R: BufRead>(
     mut reader: R,
-    (data_tx, memory_return_rx): &(Sender<Packets>, Receiver<Packets>),
+    (data_tx, memory_return_rx): &mut (Box<dyn BatchSender>, Receiver<Packets>),
     shared_state: &SharedState,
     max_count: u64,
 ) -> Result<()> {
@@ -259,7 +1009,32 @@ fn read_binary_mode<R: BufRead>(
         // u64 for packet length is overkill, but I've learned the value of giving
         // myself some room for future things.
         let mut length_buf = [0u8; 4];
-        reader.read_exact(&mut length_buf)?;
+        // Markers --mark-gaps wrote where packets were missing have nothing to send
+        let read = loop {
+            if let Err(e) = reader.read_exact(&mut length_buf) {
+                break Err(e);
+            }
+            match gaps::marker_missing(u32::from_le_bytes(length_buf)) {
+                Some(missing) => {
+                    log::debug!("skipping a marker for {missing} missing packets");
+                    shared_state
+                        .input_progress
+                        .add_offset(length_buf.len() as u64);
+                }
+                None => break Ok(()),
+            }
+        };
+        match read {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                // EOF between packets - send empty packets sentinel.
+                // A short read inside a packet is still an error below.
+                packets.set_length(0);
+                write_packets_to_channel(packets, data_tx, shared_state)?;
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        }
 
         if shared_state.should_exit() {
             break;
@@ -276,21 +1051,26 @@ fn read_binary_mode<R: BufRead>(
         // Read into the first packet
         #[allow(clippy::indexing_slicing)]
         {
+            packets.packets_mut()[0].ensure_capacity(length);
             reader.read_exact(&mut packets.packets_mut()[0].data_mut()[..length])?;
             packets.packets_mut()[0].set_length(length);
+            packets.packets_mut()[0].set_timestamp(Some(SystemTime::now()));
         }
         packets.set_length(1);
+        shared_state
+            .input_progress
+            .add_offset((length_buf.len() + length) as u64);
 
         if shared_state.should_exit() {
             break;
         }
 
-        write_packets_to_channel(packets, data_tx)?;
+        write_packets_to_channel(packets, data_tx, shared_state)?;
 
         let already_sent = shared_state.add_read_count(1);
         if max_count > 0 && already_sent >= max_count {
             // Send empty packets to signal EOF
-            write_packets_to_channel(Packets::empty(), data_tx)?;
+            write_eof_to_channel(data_tx)?;
             break;
         }
     }
@@ -298,18 +1078,1705 @@ fn read_binary_mode<R: BufRead>(
     Ok(())
 }
 
-/// Write packets to channel. Drop packets if channel is full.
-fn write_packets_to_channel(packets: Packets, tx: &Sender<Packets>) -> Result<()> {
-    // This might get a bit spammy having this at warning level.
-    match tx.try_send(packets) {
-        Ok(()) => {}
-        Err(crossbeam_channel::TrySendError::Full(_)) => {
-            log::warn!("dropping packets");
+/// The arrival time in nanoseconds and length of the next --output-format
+/// timed record, None if the input ends before it.
+pub(crate) fn read_timed_header<R: Read>(reader: &mut R) -> Result<Option<(u64, usize)>> {
+    let mut header = [0u8; 12];
+    match read_full(reader, &mut header)? {
+        0 => return Ok(None),
+        12 => {}
+        read => {
+            return Err(LibError::Critical(format!(
+                "timed record header cut short at {read} bytes"
+            )));
         }
-        Err(crossbeam_channel::TrySendError::Disconnected(_)) => {
-            return Err(LibError::Critical("channel disconnected".to_string()));
+    }
+    let (nanos, length) = header.split_at(8);
+    let nanos = u64::from_le_bytes(nanos.try_into().unwrap_or_default());
+    let length = u32::from_le_bytes(length.try_into().unwrap_or_default()) as usize;
+    if length > MAX_PACKET_BYTES {
+        return Err(LibError::Critical(format!(
+            "Packet too large: {length} bytes"
+        )));
+    }
+    Ok(Some((nanos, length)))
+}
+
+// Length prefixed records, each sent as long after the one before as it
+// arrived, divided by speed.
+fn read_timed_mode<R: BufRead>(
+    mut reader: R,
+    (data_tx, memory_return_rx): &mut (Box<dyn BatchSender>, Receiver<Packets>),
+    shared_state: &SharedState,
+    max_count: u64,
+    speed: f64,
+) -> Result<()> {
+    let mut pacer = Pacer::new(speed);
+
+    loop {
+        // Pull a recycled Packets from the memory pool (blocking)
+        let mut packets = memory_return_rx.recv()?;
+
+        let Some((nanos, length)) = read_timed_header(&mut reader)? else {
+            // EOF between packets - send empty packets sentinel
+            packets.set_length(0);
+            write_packets_to_channel(packets, data_tx, shared_state)?;
+            break;
+        };
+
+        #[allow(clippy::indexing_slicing)]
+        {
+            packets.packets_mut()[0].ensure_capacity(length);
+            reader.read_exact(&mut packets.packets_mut()[0].data_mut()[..length])?;
+            packets.packets_mut()[0].set_length(length);
+        }
+        packets.set_length(1);
+        shared_state.input_progress.add_offset(12 + length as u64);
+
+        pacer.shift(shared_state.control.paused_for());
+        if let Some(deadline) = pacer.due(nanos)
+            && !sleep_until(deadline, shared_state)
+        {
+            break;
+        }
+        // Paused, the replay keeps its place rather than filling the queue
+        if !hold_while_paused(shared_state) {
+            break;
+        }
+
+        #[allow(clippy::indexing_slicing)]
+        packets.packets_mut()[0].set_timestamp(Some(SystemTime::now()));
+        write_packets_to_channel(packets, data_tx, shared_state)?;
+
+        let already_sent = shared_state.add_read_count(1);
+        if max_count > 0 && already_sent >= max_count {
+            // Send empty packets to signal EOF
+            write_eof_to_channel(data_tx)?;
+            break;
         }
     }
 
     Ok(())
 }
+
+// Fill buf unless the input ends first; returns how much was read.
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while let Some(rest) = buf.get_mut(filled..)
+        && !rest.is_empty()
+    {
+        match reader.read(rest) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn read_raw_mode<R: BufRead>(
+    mut reader: R,
+    chunk: usize,
+    (data_tx, memory_return_rx): &mut (Box<dyn BatchSender>, Receiver<Packets>),
+    shared_state: &SharedState,
+    max_count: u64,
+) -> Result<()> {
+    loop {
+        // Pull a recycled Packets from the memory pool (blocking)
+        let mut packets = memory_return_rx.recv()?;
+
+        #[allow(clippy::indexing_slicing)]
+        let length = {
+            packets.packets_mut()[0].ensure_capacity(chunk);
+            read_full(
+                &mut reader,
+                &mut packets.packets_mut()[0].data_mut()[..chunk],
+            )?
+        };
+
+        if shared_state.should_exit() {
+            break;
+        }
+
+        if length == 0 {
+            // EOF - send empty packets sentinel
+            packets.set_length(0);
+            write_packets_to_channel(packets, data_tx, shared_state)?;
+            break;
+        }
+
+        #[allow(clippy::indexing_slicing)]
+        {
+            packets.packets_mut()[0].set_length(length);
+            packets.packets_mut()[0].set_timestamp(Some(SystemTime::now()));
+        }
+        packets.set_length(1);
+        shared_state.input_progress.add_offset(length as u64);
+
+        write_packets_to_channel(packets, data_tx, shared_state)?;
+
+        let already_sent = shared_state.add_read_count(1);
+        if max_count > 0 && already_sent >= max_count {
+            // Send empty packets to signal EOF
+            write_eof_to_channel(data_tx)?;
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// One packet per frame scanner finds, logging how much it had to skip.
+fn read_frames_mode<R: BufRead, S: FrameSync>(
+    mut reader: R,
+    mut scanner: FrameScanner<S>,
+    (data_tx, memory_return_rx): &mut (Box<dyn BatchSender>, Receiver<Packets>),
+    shared_state: &SharedState,
+    max_count: u64,
+) -> Result<()> {
+    let result = loop {
+        // Pull a recycled Packets from the memory pool (blocking)
+        let mut packets = memory_return_rx.recv()?;
+
+        let skipped = scanner.skipped();
+        let frame = match scanner.next_frame(&mut reader) {
+            Ok(frame) => frame,
+            Err(e) => break Err(e.into()),
+        };
+
+        if shared_state.should_exit() {
+            break Ok(());
+        }
+
+        let length = frame.map(|frame| {
+            #[allow(clippy::indexing_slicing)]
+            {
+                packets.packets_mut()[0].ensure_capacity(frame.len());
+                packets.packets_mut()[0].data_mut()[..frame.len()].copy_from_slice(frame);
+                packets.packets_mut()[0].set_length(frame.len());
+                packets.packets_mut()[0].set_timestamp(Some(SystemTime::now()));
+            }
+            frame.len() as u64
+        });
+        let skipped = scanner.skipped() - skipped;
+        shared_state
+            .input_progress
+            .add_offset(skipped + length.unwrap_or(0));
+
+        if length.is_none() {
+            // EOF - send empty packets sentinel
+            packets.set_length(0);
+            break write_packets_to_channel(packets, data_tx, shared_state);
+        }
+        if skipped > 0 {
+            log::debug!("skipped {skipped} bytes to the next frame");
+        }
+        packets.set_length(1);
+
+        write_packets_to_channel(packets, data_tx, shared_state)?;
+
+        let already_sent = shared_state.add_read_count(1);
+        if max_count > 0 && already_sent >= max_count {
+            // Send empty packets to signal EOF
+            break write_eof_to_channel(data_tx);
+        }
+    };
+
+    if scanner.skipped() > 0 {
+        log::warn!(
+            "skipped {} bytes of input that weren't whole frames",
+            scanner.skipped()
+        );
+    }
+    result
+}
+
+/// One packet per UDP datagram in a pcap capture, sent on to the group
+/// given rather than the one it was captured from.
+fn read_pcap_mode<R: BufRead>(
+    mut reader: R,
+    (data_tx, memory_return_rx): &mut (Box<dyn BatchSender>, Receiver<Packets>),
+    shared_state: &SharedState,
+    max_count: u64,
+) -> Result<()> {
+    let mut input = PcapInput::open(&mut reader)?;
+    shared_state.input_progress.add_offset(24);
+    let mut records = 0u64;
+    let mut skipped = 0u64;
+
+    let result = loop {
+        // Pull a recycled Packets from the memory pool (blocking)
+        let mut packets = memory_return_rx.recv()?;
+
+        // Records without a datagram don't use up the batch
+        let length = loop {
+            let Some((bytes, record)) = input.next_record(&mut reader)? else {
+                break None;
+            };
+            shared_state.input_progress.add_offset(bytes);
+            records += 1;
+            match record {
+                Record::Udp(payload) => {
+                    #[allow(clippy::indexing_slicing)]
+                    {
+                        packets.packets_mut()[0].ensure_capacity(payload.len());
+                        packets.packets_mut()[0].data_mut()[..payload.len()]
+                            .copy_from_slice(payload);
+                        packets.packets_mut()[0].set_length(payload.len());
+                        packets.packets_mut()[0].set_timestamp(Some(SystemTime::now()));
+                    }
+                    break Some(payload.len());
+                }
+                Record::Skipped(why) => {
+                    skipped += 1;
+                    log::debug!("skipping pcap record {records}, {why}");
+                }
+            }
+        };
+
+        if shared_state.should_exit() {
+            break Ok(());
+        }
+
+        if length.is_none() {
+            // EOF - send empty packets sentinel
+            packets.set_length(0);
+            break write_packets_to_channel(packets, data_tx, shared_state);
+        }
+        packets.set_length(1);
+
+        write_packets_to_channel(packets, data_tx, shared_state)?;
+
+        let already_sent = shared_state.add_read_count(1);
+        if max_count > 0 && already_sent >= max_count {
+            // Send empty packets to signal EOF
+            break write_eof_to_channel(data_tx);
+        }
+    };
+
+    if skipped > 0 {
+        log::info!("skipped {skipped} pcap records without a whole UDP datagram");
+    }
+    result
+}
+
+/// One line of --input-format jsonl. Fields mnc doesn't use are ignored.
+#[derive(Debug, serde::Deserialize)]
+pub(crate) struct JsonRecord {
+    payload_b64: Option<String>,
+    /// Microseconds to wait before sending this packet
+    delay_us: Option<u64>,
+    /// Microseconds since the unix epoch; packets keep their spacing
+    /// relative to the first record's
+    timestamp_us: Option<u64>,
+    /// Where the packet was sent, like "239.1.1.1:5000"
+    dst: Option<SocketAddrV4>,
+}
+
+// Longest wait between two timestamped records, after scaling by the speed.
+// A capture that sat idle for an hour replays the packets either side of it
+// back to back rather than stalling.
+const MAX_REPLAY_GAP: Duration = Duration::from_secs(60);
+
+/// When the next record should go out, from its delay or timestamp, with
+/// every gap divided by the replay speed. A speed of 0 sends them all as
+/// soon as they're read.
+#[derive(Debug)]
+struct Pacer {
+    speed: f64,
+    // The latest timestamp seen, in nanoseconds, and when its packet was due
+    last: Option<(u64, Instant)>,
+    // How long --control had paused the writer, as of the last record
+    paused: Duration,
+}
+
+impl Pacer {
+    fn new(speed: f64) -> Self {
+        Self {
+            speed,
+            last: None,
+            paused: Duration::ZERO,
+        }
+    }
+
+    /// Push the schedule back by however much of paused is new, so a
+    /// replay picks up where it was paused rather than rushing to catch up.
+    fn shift(&mut self, paused: Duration) {
+        let new = paused.saturating_sub(self.paused);
+        self.paused = paused;
+        if let Some((_, due)) = self.last.as_mut() {
+            *due += new;
+        }
+    }
+
+    fn scale(&self, nanos: u64) -> Duration {
+        if self.speed <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::try_from_secs_f64(nanos as f64 / 1e9 / self.speed).unwrap_or(Duration::MAX)
+    }
+
+    fn deadline(&mut self, record: &JsonRecord) -> Option<Instant> {
+        if let Some(delay) = record.delay_us {
+            return Instant::now().checked_add(self.scale(delay.saturating_mul(1000)));
+        }
+        self.due(record.timestamp_us?.saturating_mul(1000))
+    }
+
+    /// When the packet recorded at timestamp, in nanoseconds, is due; None
+    /// for the first, which goes at once. One recorded before the last is
+    /// due with it.
+    fn due(&mut self, timestamp: u64) -> Option<Instant> {
+        match self.last {
+            Some((last, due)) => {
+                let gap = self.scale(timestamp.saturating_sub(last));
+                if gap > MAX_REPLAY_GAP {
+                    log::info!(
+                        "{:.1}s gap in the recording shortened to {}s",
+                        gap.as_secs_f64(),
+                        MAX_REPLAY_GAP.as_secs()
+                    );
+                }
+                let due = due + gap.min(MAX_REPLAY_GAP);
+                self.last = Some((last.max(timestamp), due));
+                Some(due)
+            }
+            None => {
+                self.last = Some((timestamp, Instant::now()));
+                None
+            }
+        }
+    }
+}
+
+pub(crate) fn decode_payload(line: &str, line_number: u64) -> Result<(JsonRecord, Vec<u8>)> {
+    let record: JsonRecord = serde_json::from_str(line)
+        .map_err(|e| LibError::Critical(format!("line {line_number}: {e}")))?;
+    let payload = record
+        .payload_b64
+        .as_deref()
+        .ok_or_else(|| LibError::Critical(format!("line {line_number}: no payload_b64")))?;
+    let payload = BASE64
+        .decode(payload)
+        .map_err(|e| LibError::Critical(format!("line {line_number}: payload_b64: {e}")))?;
+    Ok((record, payload))
+}
+
+// Sleep until deadline, waking now and then to see if it's time to exit.
+// Returns false if exit was signaled first.
+fn sleep_until(deadline: Instant, shared_state: &SharedState) -> bool {
+    loop {
+        if shared_state.should_exit() {
+            return false;
+        }
+        let wait = deadline.saturating_duration_since(Instant::now());
+        if wait.is_zero() {
+            return true;
+        }
+        thread::sleep(wait.min(Duration::from_millis(100)));
+    }
+}
+
+// Wait for --control to resume. Returns false if exit was signaled first.
+fn hold_while_paused(shared_state: &SharedState) -> bool {
+    while shared_state.control.is_paused() {
+        if shared_state.should_exit() {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    !shared_state.should_exit()
+}
+
+fn read_jsonl_mode<R: BufRead>(
+    mut reader: R,
+    (data_tx, memory_return_rx): &mut (Box<dyn BatchSender>, Receiver<Packets>),
+    shared_state: &SharedState,
+    max_count: u64,
+    speed: f64,
+) -> Result<()> {
+    let mut line = String::new();
+    let mut line_number = 0;
+    let mut pacer = Pacer::new(speed);
+
+    loop {
+        // Pull a recycled Packets from the memory pool (blocking)
+        let mut packets = memory_return_rx.recv()?;
+
+        // Blank lines carry no record
+        let bytes_read = loop {
+            line.clear();
+            let bytes_read = reader.read_line(&mut line)?;
+            line_number += 1;
+            shared_state.input_progress.add_offset(bytes_read as u64);
+            if bytes_read == 0 || !line.trim().is_empty() {
+                break bytes_read;
+            }
+        };
+
+        if shared_state.should_exit() {
+            break;
+        }
+
+        if bytes_read == 0 {
+            // EOF - send empty packets sentinel
+            packets.set_length(0);
+            write_packets_to_channel(packets, data_tx, shared_state)?;
+            break;
+        }
+
+        let (record, payload) = decode_payload(&line, line_number)?;
+        if payload.len() > MAX_PACKET_BYTES {
+            return Err(LibError::Critical(format!(
+                "line {line_number}: packet too large: {} bytes",
+                payload.len()
+            )));
+        }
+
+        pacer.shift(shared_state.control.paused_for());
+        if let Some(deadline) = pacer.deadline(&record)
+            && !sleep_until(deadline, shared_state)
+        {
+            break;
+        }
+        // Paused, the replay keeps its place rather than filling the queue
+        if !hold_while_paused(shared_state) {
+            break;
+        }
+
+        #[allow(clippy::indexing_slicing)]
+        {
+            packets.packets_mut()[0].ensure_capacity(payload.len());
+            packets.packets_mut()[0].data_mut()[..payload.len()].copy_from_slice(&payload);
+            packets.packets_mut()[0].set_length(payload.len());
+            packets.packets_mut()[0].set_timestamp(Some(SystemTime::now()));
+            packets.packets_mut()[0].set_destination(record.dst);
+        }
+        packets.set_length(1);
+
+        write_packets_to_channel(packets, data_tx, shared_state)?;
+
+        let already_sent = shared_state.add_read_count(1);
+        if max_count > 0 && already_sent >= max_count {
+            // Send empty packets to signal EOF
+            write_eof_to_channel(data_tx)?;
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// Adaptive receive buffers start here and grow with the traffic.
+const INITIAL_BATCH: usize = 64;
+const INITIAL_PACKET_BYTES: usize = 2048;
+
+// Grow the batch once this many recvmmsg calls in a row fill it, and
+// shrink it back once this many in a row use under a quarter of it.
+const GROW_AFTER: u32 = 4;
+const SHRINK_AFTER: u32 = 1000;
+
+/// How much of each pooled batch recvmmsg gets to fill, and how large each
+/// packet buffer must be. Fixed at the maximum unless adaptive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BufferSizing {
+    adaptive: bool,
+    batch: usize,
+    min_batch: usize,
+    max_batch: usize,
+    packet_bytes: usize,
+    max_packet_bytes: usize,
+    full_streak: u32,
+    sparse_streak: u32,
+}
+
+impl BufferSizing {
+    fn new(adaptive: bool, batch_size: usize, max_packet_bytes: usize) -> Self {
+        let batch_size = batch_size.max(1);
+        let max_packet_bytes = max_packet_bytes.clamp(1, MAX_PACKET_BYTES);
+        let (batch, packet_bytes) = if adaptive {
+            (
+                INITIAL_BATCH.min(batch_size),
+                INITIAL_PACKET_BYTES.min(max_packet_bytes),
+            )
+        } else {
+            (batch_size, max_packet_bytes)
+        };
+
+        Self {
+            adaptive,
+            batch,
+            min_batch: batch,
+            max_batch: batch_size,
+            packet_bytes,
+            max_packet_bytes,
+            full_streak: 0,
+            sparse_streak: 0,
+        }
+    }
+
+    /// Account for one recvmmsg call that returned received messages.
+    fn observe(&mut self, received: usize) {
+        if !self.adaptive {
+            return;
+        }
+
+        if received >= self.batch {
+            self.sparse_streak = 0;
+            self.full_streak += 1;
+            if self.full_streak >= GROW_AFTER && self.batch < self.max_batch {
+                self.batch = (self.batch * 2).min(self.max_batch);
+                self.full_streak = 0;
+                log::debug!("receive batch grown to {}", self.batch);
+            }
+        } else if received < self.batch / 4 {
+            self.full_streak = 0;
+            self.sparse_streak += 1;
+            if self.sparse_streak >= SHRINK_AFTER && self.batch > self.min_batch {
+                self.batch = (self.batch / 2).max(self.min_batch);
+                self.sparse_streak = 0;
+                log::debug!("receive batch shrunk to {}", self.batch);
+            }
+        } else {
+            self.full_streak = 0;
+            self.sparse_streak = 0;
+        }
+    }
+
+    /// A datagram of bytes didn't fit. Returns whether the buffers grew.
+    fn grow_for(&mut self, bytes: usize) -> bool {
+        let wanted = bytes.next_power_of_two().min(self.max_packet_bytes);
+        if wanted <= self.packet_bytes {
+            return false;
+        }
+        self.packet_bytes = wanted;
+        true
+    }
+}
+
+/// How many messages each recvmmsg call returned, in buckets of 1, 2-9,
+/// 10-99 and 100 or more, for --batch-stats. Mostly single messages means
+/// the reader is bound by system calls, mostly full batches that the batch
+/// size is the limit.
+#[derive(Debug, Default)]
+pub struct BatchFillCounters {
+    buckets: [AtomicU64; 4],
+}
+
+const BATCH_FILL_LABELS: [&str; 4] = ["1msg", "2-9", "10-99", "100+"];
+
+impl BatchFillCounters {
+    /// Count one call that returned messages.
+    fn record(&self, messages: usize) {
+        let bucket = match messages {
+            0 => return,
+            1 => 0,
+            2..=9 => 1,
+            10..=99 => 2,
+            _ => 3,
+        };
+        if let Some(counter) = self.buckets.get(bucket) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Calls so far in each bucket.
+    pub fn get(&self) -> [u64; 4] {
+        self.buckets
+            .each_ref()
+            .map(|counter| counter.load(Ordering::Relaxed))
+    }
+}
+
+/// "batches: 1msg 62% 2-9 25% 10-99 11% 100+ 2%", each bucket's share of
+/// the calls counted. Empty without any.
+pub fn format_batch_fill(calls: &[u64; 4]) -> String {
+    let total: u64 = calls.iter().sum();
+    if total == 0 {
+        return String::new();
+    }
+    let shares: Vec<String> = BATCH_FILL_LABELS
+        .iter()
+        .zip(calls)
+        .map(|(label, count)| format!("{label} {:.0}%", *count as f64 * 100.0 / total as f64))
+        .collect();
+    format!("batches: {}", shares.join(" "))
+}
+
+/// --stall-threshold when not given.
+pub const DEFAULT_STALL_THRESHOLD: Duration = Duration::from_millis(10);
+
+/// Times the reader was kept from its sockets longer than the threshold
+/// while datagrams waited for it, the throttled or descheduled thread that
+/// drops follow from rather than loss on the network.
+#[derive(Debug)]
+pub struct StallCounters {
+    threshold_us: AtomicU64,
+    count: AtomicU64,
+    worst_us: AtomicU64,
+    // Since the statistics thread last took it
+    interval_worst_us: AtomicU64,
+}
+
+impl Default for StallCounters {
+    fn default() -> Self {
+        Self {
+            threshold_us: AtomicU64::new(DEFAULT_STALL_THRESHOLD.as_micros() as u64),
+            count: AtomicU64::new(0),
+            worst_us: AtomicU64::new(0),
+            interval_worst_us: AtomicU64::new(0),
+        }
+    }
+}
+
+impl StallCounters {
+    fn set_threshold(&self, threshold: Duration) {
+        self.threshold_us
+            .store(threshold.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn threshold(&self) -> Duration {
+        Duration::from_micros(self.threshold_us.load(Ordering::Relaxed))
+    }
+
+    /// Count the gap when it's past the threshold.
+    fn record(&self, gap: Duration) {
+        let gap_us = gap.as_micros() as u64;
+        if gap_us <= self.threshold_us.load(Ordering::Relaxed) {
+            return;
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.worst_us.fetch_max(gap_us, Ordering::Relaxed);
+        self.interval_worst_us.fetch_max(gap_us, Ordering::Relaxed);
+    }
+
+    /// Stalls so far and the longest of them.
+    pub fn get(&self) -> (u64, Duration) {
+        (
+            self.count.load(Ordering::Relaxed),
+            Duration::from_micros(self.worst_us.load(Ordering::Relaxed)),
+        )
+    }
+
+    /// The longest stall since the last call, starting the next interval.
+    pub fn take_interval_worst(&self) -> Duration {
+        Duration::from_micros(self.interval_worst_us.swap(0, Ordering::Relaxed))
+    }
+}
+
+/// "stalls >10ms: 42, worst 180ms"
+pub fn format_stalls(count: u64, worst: Duration, threshold: Duration) -> String {
+    format!(
+        "stalls >{}ms: {count}, worst {}ms",
+        threshold.as_millis(),
+        worst.as_millis()
+    )
+}
+
+// How long the reader waits for traffic before looking at should_exit again.
+const POLL_INTERVAL_MS: u8 = 10;
+
+/// Queue up the sockets that became readable within POLL_INTERVAL_MS.
+fn wait_readable(sockets: &[impl AsFd], ready: &mut VecDeque<usize>) -> Result<()> {
+    let mut fds: Vec<PollFd> = sockets
+        .iter()
+        .map(|socket| PollFd::new(socket.as_fd(), PollFlags::POLLIN))
+        .collect();
+    match poll(&mut fds, PollTimeout::from(POLL_INTERVAL_MS)) {
+        Ok(_) => {}
+        // A signal (Ctrl-C) landed; let the caller check should_exit
+        Err(Errno::EINTR) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    ready.extend(fds.iter().enumerate().filter_map(|(index, fd)| {
+        fd.revents()
+            .is_some_and(|events| events.intersects(PollFlags::POLLIN | PollFlags::POLLERR))
+            .then_some(index)
+    }));
+    Ok(())
+}
+
+// poll said there is data, so receiving never needs to block. On Linux
+// MSG_TRUNC reports the real length of a datagram that didn't fit.
+#[cfg(target_os = "linux")]
+const RECV_FLAGS: MsgFlags = MsgFlags::MSG_DONTWAIT.union(MsgFlags::MSG_TRUNC);
+#[cfg(not(target_os = "linux"))]
+const RECV_FLAGS: MsgFlags = MsgFlags::MSG_DONTWAIT;
+
+/// Fills a batch with one recvmmsg call, along with the kernel's arrival
+/// time, TTL and destination group for each datagram.
+#[cfg(mmsg)]
+struct DatagramReceiver {
+    headers: MultiHeaders<SockaddrStorage>,
+}
+
+#[cfg(mmsg)]
+impl DatagramReceiver {
+    fn new(socket: &Socket, max_batch: usize) -> Result<Self> {
+        // Kernel receive timestamps, so arrival times don't include our own queueing
+        setsockopt(socket, sockopt::ReceiveTimestampns, &true)?;
+        // The TTL left on arrival shows how many routers the stream crossed
+        setsockopt(socket, sockopt::Ipv4RecvTtl, &true)?;
+        // Bound to INADDR_ANY, the socket also gets other groups joined on this
+        // host for the same port; IP_PKTINFO says which group each was sent to
+        setsockopt(socket, sockopt::Ipv4PacketInfo, &true)?;
+
+        Ok(Self {
+            headers: MultiHeaders::preallocate(
+                max_batch,
+                Some(nix::cmsg_space!(TimeSpec, libc::c_int, libc::in_pktinfo)),
+            ),
+        })
+    }
+
+    fn receive(
+        &mut self,
+        socket: &Socket,
+        packets: &mut Packets,
+        received: &mut Vec<(usize, Ancillary, bool)>,
+    ) -> Result<()> {
+        // Create iovecs pointing to our persistent buffers. Unlike the
+        // headers they can't be kept: recvmmsg borrows the Vec for as long as
+        // the buffers it points into, so it can't be taken back afterwards
+        // without unsafe code.
+        let mut iovecs: Vec<[IoSliceMut; 1]> = packets
+            .iter_mut()
+            .map(|packet| {
+                let length = packet.len();
+                [IoSliceMut::new(
+                    packet.data_mut().get_mut(..length).unwrap_or_default(),
+                )]
+            })
+            .collect();
+
+        match recvmmsg(
+            socket_to_raw_fd(socket),
+            &mut self.headers,
+            &mut iovecs,
+            RECV_FLAGS,
+            None,
+        ) {
+            Ok(msgs) => {
+                received.extend(msgs.into_iter().map(|msg| {
+                    let truncated = msg.flags.contains(MsgFlags::MSG_TRUNC);
+                    (msg.bytes, Ancillary::of(&msg), truncated)
+                }));
+            }
+            Err(Errno::EAGAIN) => {
+                // Retry on EAGAIN
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+}
+
+/// Fills a batch one recvmsg at a time until it is full or the socket runs
+/// dry, for systems without recvmmsg. The control messages it would need are
+/// Linux specific, so arrival time is when the datagram was read, the TTL is
+/// unknown and the destination is taken to be the socket's own.
+#[cfg(not(mmsg))]
+struct DatagramReceiver;
+
+#[cfg(not(mmsg))]
+impl DatagramReceiver {
+    fn new(_socket: &Socket, _max_batch: usize) -> Result<Self> {
+        log::debug!(
+            "receiving a datagram per call: no kernel timestamps, TTL or destination group"
+        );
+        Ok(Self)
+    }
+
+    fn receive(
+        &mut self,
+        socket: &Socket,
+        packets: &mut Packets,
+        received: &mut Vec<(usize, Ancillary, bool)>,
+    ) -> Result<()> {
+        let fd = socket_to_raw_fd(socket);
+        for packet in packets.iter_mut() {
+            let capacity = packet.len();
+            let mut iov = [IoSliceMut::new(
+                packet.data_mut().get_mut(..capacity).unwrap_or_default(),
+            )];
+            match recvmsg::<SockaddrStorage>(fd, &mut iov, None, RECV_FLAGS) {
+                Ok(msg) => {
+                    let truncated = msg.flags.contains(MsgFlags::MSG_TRUNC);
+                    // Without MSG_TRUNC the real length is unknown; one more
+                    // byte than fit is enough to grow the buffers
+                    let bytes = if truncated && msg.bytes <= capacity {
+                        capacity + 1
+                    } else {
+                        msg.bytes
+                    };
+                    received.push((bytes, Ancillary::now(msg.address.as_ref()), truncated));
+                }
+                Err(Errno::EAGAIN) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the kernel attached to a datagram as control messages, and who sent it.
+#[derive(Debug, Clone, Copy)]
+struct Ancillary {
+    /// Kernel receive time from SO_TIMESTAMPNS, or now if the kernel didn't attach one
+    arrival: SystemTime,
+    /// From IP_RECVTTL
+    ttl: Option<u8>,
+    /// Header destination address from IP_PKTINFO
+    destination: Option<Ipv4Addr>,
+    /// Sender's address and port
+    source: Option<SocketAddrV4>,
+}
+
+impl Ancillary {
+    #[cfg(not(mmsg))]
+    fn now(source: Option<&SockaddrStorage>) -> Self {
+        Self {
+            arrival: SystemTime::now(),
+            ttl: None,
+            destination: None,
+            source: source_of(source),
+        }
+    }
+
+    #[cfg(mmsg)]
+    fn of(msg: &RecvMsg<'_, '_, SockaddrStorage>) -> Self {
+        let mut arrival = None;
+        let mut ttl = None;
+        let mut destination = None;
+
+        // A control buffer too short to parse just leaves the fields unset
+        for cmsg in msg.cmsgs().into_iter().flatten() {
+            match cmsg {
+                ControlMessageOwned::ScmTimestampns(ts) => {
+                    arrival = Some(
+                        SystemTime::UNIX_EPOCH
+                            + Duration::new(ts.tv_sec() as u64, ts.tv_nsec() as u32),
+                    );
+                }
+                ControlMessageOwned::Ipv4Ttl(value) => ttl = u8::try_from(value).ok(),
+                ControlMessageOwned::Ipv4PacketInfo(info) => {
+                    destination = Some(Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr)));
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            arrival: arrival.unwrap_or_else(SystemTime::now),
+            ttl,
+            destination,
+            source: source_of(msg.address.as_ref()),
+        }
+    }
+}
+
+fn source_of(address: Option<&SockaddrStorage>) -> Option<SocketAddrV4> {
+    address
+        .and_then(SockaddrStorage::as_sockaddr_in)
+        .map(|sin| SocketAddrV4::from(*sin))
+}
+
+/// Drop datagrams sent to some other group, moving the rest to the front of
+/// the batch. Each foreign group is warned about once.
+fn keep_group(
+    packets: &mut Packets,
+    received: &mut Vec<(usize, Ancillary, bool)>,
+    group: Ipv4Addr,
+    foreign: &mut HashSet<Ipv4Addr>,
+) {
+    let mut kept = 0;
+    for i in 0..received.len() {
+        match received
+            .get(i)
+            .and_then(|(_, ancillary, _)| ancillary.destination)
+        {
+            Some(destination) if destination != group => {
+                if foreign.insert(destination) {
+                    log::warn!(
+                        "ignoring datagrams for {destination}, another socket on this host joined it on the same port"
+                    );
+                }
+            }
+            _ => {
+                packets.packets_mut().swap(kept, i);
+                received.swap(kept, i);
+                kept += 1;
+            }
+        }
+    }
+    received.truncate(kept);
+}
+
+// Truncated datagrams logged one by one before they are only counted.
+const LOGGED_TRUNCATIONS: u64 = 5;
+
+/// Count datagrams that didn't fit their buffer, growing the buffers for the
+/// next batch if they may, and with --drop-truncated take them out of this
+/// one. The first few are logged with the length the kernel gave for them.
+fn check_truncated(
+    packets: &mut Packets,
+    received: &mut Vec<(usize, Ancillary, bool)>,
+    sizing: &mut BufferSizing,
+    drop_truncated: bool,
+    shared_state: &SharedState,
+) {
+    let mut kept = 0;
+    for i in 0..received.len() {
+        let Some(&(bytes, ancillary, truncated)) = received.get(i) else {
+            continue;
+        };
+        if truncated {
+            let seen = shared_state.add_truncated();
+            let fit = packets.packets().get(i).map_or(0, |packet| packet.len());
+            let from = ancillary
+                .source
+                .map_or_else(String::new, |source| format!(" from {source}"));
+            if sizing.grow_for(bytes) {
+                log::warn!(
+                    "{bytes} byte datagram{from} truncated to {fit}, growing receive buffers to {}",
+                    sizing.packet_bytes
+                );
+            } else if seen < LOGGED_TRUNCATIONS {
+                log::warn!(
+                    "{bytes} byte datagram{from} truncated to {fit}{}",
+                    if drop_truncated { ", dropped" } else { "" }
+                );
+                if seen + 1 == LOGGED_TRUNCATIONS {
+                    log::warn!("further truncated datagrams are only counted");
+                }
+            }
+            if drop_truncated {
+                continue;
+            }
+        }
+        packets.packets_mut().swap(kept, i);
+        received.swap(kept, i);
+        kept += 1;
+    }
+    received.truncate(kept);
+}
+
+/// Drop the packets this mnc sent itself that came back to it, moving the
+/// rest to the front of the batch. The first is warned about, as it means
+/// the output loops back to the input.
+fn drop_own(
+    packets: &mut Packets,
+    received: &mut Vec<(usize, Ancillary, bool)>,
+    marker: Option<&[u8]>,
+    shared_state: &SharedState,
+) {
+    let sender = shared_state.own_traffic.sender();
+    if sender.is_none() && marker.is_none() {
+        return;
+    }
+    let mut kept = 0;
+    let mut dropped = 0;
+    for i in 0..received.len() {
+        let own = match (received.get(i), packets.packets().get(i)) {
+            (Some((bytes, ancillary, _)), Some(packet)) => {
+                let payload: &[u8] = packet;
+                let payload = payload.get(..*bytes).unwrap_or(payload);
+                loop_guard::own_packet(ancillary.source, payload, sender, marker)
+            }
+            _ => None,
+        };
+        if let Some(reason) = own {
+            if dropped == 0 && shared_state.own_traffic.dropped() == 0 {
+                log::warn!(
+                    "dropping packets {reason}: the output loops back to the input (further ones are counted as self)"
+                );
+            }
+            dropped += 1;
+            continue;
+        }
+        packets.packets_mut().swap(kept, i);
+        received.swap(kept, i);
+        kept += 1;
+    }
+    received.truncate(kept);
+    if dropped > 0 {
+        shared_state.own_traffic.add_dropped(dropped);
+    }
+}
+
+/// Write packets to channel. Drop packets if channel is full.
+fn write_packets_to_channel(
+    packets: Packets,
+    tx: &mut dyn BatchSender,
+    shared_state: &SharedState,
+) -> Result<()> {
+    shared_state.add_read_bytes(packets.iter().map(|packet| packet.len() as u64).sum());
+    // This might get a bit spammy having this at warning level.
+    match tx.try_push(packets) {
+        Ok(()) => {}
+        Err(crossbeam_channel::TrySendError::Full(_)) => {
+            shared_state.add_dropped_batch();
+            log::warn!("dropping packets");
+        }
+        Err(crossbeam_channel::TrySendError::Disconnected(_)) => {
+            return Err(LibError::Critical("channel disconnected".to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Send the empty batch that marks EOF. Downstream stops by itself once it
+/// has seen -c packets, so finding it already gone is not an error.
+fn write_eof_to_channel(tx: &mut dyn BatchSender) -> Result<()> {
+    match tx.try_push(Packets::empty()) {
+        Ok(()) | Err(crossbeam_channel::TrySendError::Disconnected(_)) => Ok(()),
+        Err(crossbeam_channel::TrySendError::Full(_)) => {
+            log::warn!("dropping packets");
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use std::time::Instant;
+
+    use crossbeam_channel::bounded;
+
+    use super::*;
+    use crate::transport::{self, TransportKind};
+
+    #[test]
+    fn test_input_framing() {
+        assert_eq!(
+            InputFraming::new(None, PacketType::Sdds, None),
+            Ok(InputFraming::LengthPrefixed)
+        );
+        assert_eq!(
+            InputFraming::new(Some(InputFormat::Text), PacketType::Binary, None),
+            Ok(InputFraming::Lines(LineLimit::default()))
+        );
+        assert_eq!(
+            InputFraming::new(Some(InputFormat::Raw), PacketType::Binary, Some(1024)),
+            Ok(InputFraming::Chunks(1024))
+        );
+        assert_eq!(
+            InputFraming::new(Some(InputFormat::Jsonl), PacketType::Sdds, None),
+            Ok(InputFraming::JsonLines)
+        );
+        assert!(InputFraming::new(Some(InputFormat::Raw), PacketType::Binary, None).is_err());
+        assert!(InputFraming::new(Some(InputFormat::Raw), PacketType::Binary, Some(0)).is_err());
+        assert!(InputFraming::new(None, PacketType::Binary, Some(1024)).is_err());
+    }
+
+    #[test]
+    fn test_raw_input_is_chunked() {
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let (data_tx, mut data_rx) = transport::bounded(TransportKind::Channel, 8);
+        let (memory_tx, memory_rx) = bounded(8);
+        for _ in 0..4 {
+            memory_tx.send(Packets::new(1, 0)).expect("pool");
+        }
+
+        let input: &[u8] = b"abcdefghij";
+        read_framed(
+            input,
+            InputFraming::Chunks(4),
+            &mut (data_tx, memory_rx),
+            &shared_state,
+            0,
+            1.0,
+        )
+        .expect("read");
+
+        let mut chunks = Vec::new();
+        while let Ok(packets) = data_rx.pop_timeout(Duration::from_millis(100)) {
+            if packets.is_empty() {
+                break;
+            }
+            chunks.extend(packets.iter().map(|packet| packet.to_vec()));
+        }
+        assert_eq!(
+            chunks,
+            vec![b"abcd".to_vec(), b"efgh".to_vec(), b"ij".to_vec()]
+        );
+    }
+
+    fn read_lines(input: &[u8], max: usize, split: bool) -> (Result<()>, Vec<Vec<u8>>) {
+        read_bundles(
+            input,
+            LineLimit {
+                max,
+                split,
+                ..LineLimit::default()
+            },
+        )
+    }
+
+    fn read_bundles(input: &[u8], limit: LineLimit) -> (Result<()>, Vec<Vec<u8>>) {
+        let shared_state = SharedState::new(PacketType::Text, false);
+        let (data_tx, mut data_rx) = transport::bounded(TransportKind::Channel, 8);
+        let (memory_tx, memory_rx) = bounded(8);
+        for _ in 0..8 {
+            memory_tx.send(Packets::new(1, 0)).expect("pool");
+        }
+
+        let result = read_framed(
+            input,
+            InputFraming::Lines(limit),
+            &mut (data_tx, memory_rx),
+            &shared_state,
+            0,
+            1.0,
+        );
+        let mut lines = Vec::new();
+        while let Ok(packets) = data_rx.pop_timeout(Duration::from_millis(100)) {
+            if packets.is_empty() {
+                break;
+            }
+            lines.extend(packets.iter().map(|packet| packet.to_vec()));
+        }
+        (result, lines)
+    }
+
+    #[test]
+    fn test_long_lines() {
+        // Exactly max bytes before the newline is still one line
+        let (result, lines) = read_lines(b"abcd\nefgh", 4, false);
+        assert!(result.is_ok());
+        assert_eq!(lines, vec![b"abcd\n".to_vec(), b"efgh".to_vec()]);
+
+        let (result, lines) = read_lines(b"ab\nabcdefghij\n", 4, false);
+        assert!(result.is_err());
+        assert_eq!(lines, vec![b"ab\n".to_vec()]);
+
+        let (result, lines) = read_lines(b"abcdefghij\n", 4, true);
+        assert!(result.is_ok());
+        assert_eq!(
+            lines,
+            vec![b"abcd".to_vec(), b"efgh".to_vec(), b"ij\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_lines_per_packet() {
+        let limit = LineLimit {
+            per_packet: 3,
+            ..LineLimit::default()
+        };
+        // The last bundle is whatever is left at EOF
+        let (result, bundles) = read_bundles(b"a\nb\nc\nd\ne", limit);
+        assert!(result.is_ok());
+        assert_eq!(bundles, vec![b"a\nb\nc\n".to_vec(), b"d\ne".to_vec()]);
+
+        // A line that would take the bundle past MAX_BUNDLE_BYTES starts the next
+        let long = [vec![b'x'; MAX_BUNDLE_BYTES - 3], b"\n".to_vec()].concat();
+        let input = [b"ab\n".to_vec(), long.clone(), b"c\n".to_vec()].concat();
+        let (result, bundles) = read_bundles(&input, limit);
+        assert!(result.is_ok());
+        assert_eq!(
+            bundles,
+            vec![b"ab\n".to_vec(), [long, b"c\n".to_vec()].concat()]
+        );
+    }
+
+    #[test]
+    fn test_bundle_wait() {
+        let started = Instant::now();
+        let ms = Duration::from_millis;
+        assert_eq!(bundle_wait(started, None, started + ms(500)), BUNDLE_WAIT);
+        // --max-latency only ever shortens the wait, down to nothing once due
+        assert_eq!(bundle_wait(started, Some(ms(50)), started), BUNDLE_WAIT);
+        assert_eq!(bundle_wait(started, Some(ms(50)), started + ms(45)), ms(5));
+        assert_eq!(
+            bundle_wait(started, Some(ms(50)), started + ms(60)),
+            Duration::ZERO
+        );
+    }
+
+    fn read_jsonl(input: &[u8]) -> (Result<()>, Vec<Vec<u8>>) {
+        let (result, packets) = read_jsonl_packets(input);
+        let payloads = packets.iter().map(|packet| packet.to_vec()).collect();
+        (result, payloads)
+    }
+
+    fn read_jsonl_packets(input: &[u8]) -> (Result<()>, Vec<crate::packet::Packet>) {
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let (data_tx, mut data_rx) = transport::bounded(TransportKind::Channel, 8);
+        let (memory_tx, memory_rx) = bounded(8);
+        for _ in 0..4 {
+            memory_tx.send(Packets::new(1, 0)).expect("pool");
+        }
+
+        let result = read_framed(
+            input,
+            InputFraming::JsonLines,
+            &mut (data_tx, memory_rx),
+            &shared_state,
+            0,
+            1.0,
+        );
+
+        let mut received = Vec::new();
+        while let Ok(packets) = data_rx.pop_timeout(Duration::from_millis(100)) {
+            if packets.is_empty() {
+                break;
+            }
+            received.extend(packets.iter().cloned());
+        }
+        (result, received)
+    }
+
+    #[test]
+    fn test_jsonl_input() {
+        let input = concat!(
+            "{\"payload_b64\": \"aGk=\", \"delay_us\": 0, \"source\": \"log\"}\n",
+            "\n",
+            "{\"timestamp_us\": 1000000, \"payload_b64\": \"AP8K\"}\n",
+            "{\"timestamp_us\": 1020000, \"payload_b64\": \"\"}\n",
+        );
+        let start = Instant::now();
+        let (result, payloads) = read_jsonl(input.as_bytes());
+        result.expect("read");
+        assert_eq!(
+            payloads,
+            vec![b"hi".to_vec(), b"\x00\xff\n".to_vec(), Vec::new()]
+        );
+        // The last record is 20ms after the one before it
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_pacer_speed() {
+        let record = |timestamp_us| JsonRecord {
+            payload_b64: None,
+            delay_us: None,
+            timestamp_us: Some(timestamp_us),
+            dst: None,
+        };
+        let mut pacer = Pacer::new(2.0);
+        assert_eq!(pacer.deadline(&record(1_000_000)), None);
+        let (_, start) = pacer.last.expect("origin");
+
+        // Half the recorded gaps at 2x
+        assert_eq!(
+            pacer.deadline(&record(2_000_000)),
+            Some(start + Duration::from_millis(500))
+        );
+        // An hour's silence is cut short after scaling
+        assert_eq!(
+            pacer.deadline(&record(3_602_000_000)),
+            Some(start + Duration::from_millis(500) + MAX_REPLAY_GAP)
+        );
+        // Going backwards doesn't move the schedule
+        assert_eq!(
+            pacer.deadline(&record(5_000)),
+            Some(start + Duration::from_millis(500) + MAX_REPLAY_GAP)
+        );
+        // A pause moves it on by as long, once
+        pacer.shift(Duration::from_secs(3));
+        pacer.shift(Duration::from_secs(3));
+        assert_eq!(
+            pacer.deadline(&record(3_602_000_000)),
+            Some(start + Duration::from_millis(3500) + MAX_REPLAY_GAP)
+        );
+
+        let delay = JsonRecord {
+            delay_us: Some(1_000_000),
+            ..record(0)
+        };
+        let now = Instant::now();
+        let due = Pacer::new(0.5).deadline(&delay).expect("delay");
+        assert!(due >= now + Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_pacer_timed() {
+        let mut pacer = Pacer::new(1.0);
+        assert_eq!(pacer.due(5_000_000_000), None);
+        let (_, start) = pacer.last.expect("origin");
+        assert_eq!(
+            pacer.due(5_000_250_000),
+            Some(start + Duration::from_micros(250))
+        );
+
+        // At speed 0 everything is due from the start
+        let mut pacer = Pacer::new(0.0);
+        assert_eq!(pacer.due(1_000_000_000), None);
+        let (_, start) = pacer.last.expect("origin");
+        assert_eq!(pacer.due(9_000_000_000), Some(start));
+    }
+
+    #[test]
+    fn test_read_timed_header() {
+        let mut input = 7_u64.to_le_bytes().to_vec();
+        input.extend(3_u32.to_le_bytes());
+        assert_eq!(
+            read_timed_header(&mut input.as_slice()).expect("read"),
+            Some((7, 3))
+        );
+        assert_eq!(read_timed_header(&mut &[][..]).expect("read"), None);
+        assert!(read_timed_header(&mut input.get(..10).unwrap_or_default()).is_err());
+
+        let mut large = 0_u64.to_le_bytes().to_vec();
+        large.extend(u32::MAX.to_le_bytes());
+        assert!(read_timed_header(&mut large.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_jsonl_input_destinations() {
+        let input = concat!(
+            "{\"payload_b64\": \"aGk=\", \"dst\": \"239.1.2.3:5000\"}\n",
+            "{\"payload_b64\": \"aGk=\"}\n",
+        );
+        let (result, packets) = read_jsonl_packets(input.as_bytes());
+        result.expect("read");
+        let destinations: Vec<_> = packets.iter().map(|packet| packet.destination()).collect();
+        assert_eq!(
+            destinations,
+            vec![
+                Some(SocketAddrV4::new(Ipv4Addr::new(239, 1, 2, 3), 5000)),
+                None
+            ]
+        );
+
+        let (result, _) =
+            read_jsonl_packets(b"{\"payload_b64\": \"aGk=\", \"dst\": \"239.1.2.3\"}\n");
+        let error = result.expect_err("no port").to_string();
+        assert!(error.contains("line 1"), "{error}");
+    }
+
+    #[test]
+    fn test_jsonl_input_errors_name_the_line() {
+        let input = b"{\"payload_b64\": \"aGk=\"}\n\n{\"delay_us\": 5}\n";
+        let (result, payloads) = read_jsonl(input);
+        assert_eq!(payloads, vec![b"hi".to_vec()]);
+        let error = result.expect_err("no payload").to_string();
+        assert!(error.contains("line 3"), "{error}");
+
+        let (result, _) = read_jsonl(b"{\"payload_b64\": \"not base64!\"}\n");
+        let error = result.expect_err("bad base64").to_string();
+        assert!(error.contains("line 1"), "{error}");
+    }
+
+    // Runs against recvmmsg normally and the fallback with --features portable
+    #[test]
+    fn test_datagram_receiver() {
+        let socket =
+            Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, None).expect("socket");
+        socket
+            .bind(&std::net::SocketAddr::from((Ipv4Addr::LOCALHOST, 0)).into())
+            .expect("bind");
+        let address = socket
+            .local_addr()
+            .expect("address")
+            .as_socket()
+            .expect("inet");
+        let mut receiver = DatagramReceiver::new(&socket, 4).expect("receiver");
+
+        let sender = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("sender");
+        for payload in [&b"one"[..], b"", &[7; 100]] {
+            sender.send_to(payload, address).expect("send");
+        }
+        let mut ready = VecDeque::new();
+        wait_readable(std::slice::from_ref(&socket), &mut ready).expect("poll");
+        assert_eq!(ready, [0]);
+
+        let mut packets = Packets::new(4, 64);
+        for packet in packets.iter_mut() {
+            packet.set_length(packet.capacity());
+        }
+        let mut received = Vec::new();
+        receiver
+            .receive(&socket, &mut packets, &mut received)
+            .expect("receive");
+
+        let summary: Vec<(bool, bool)> = received
+            .iter()
+            .map(|(bytes, _, truncated)| (*bytes > 64, *truncated))
+            .collect();
+        assert_eq!(summary, vec![(false, false), (false, false), (true, true)]);
+        assert_eq!(received.first().map(|(bytes, _, _)| *bytes), Some(3));
+        assert_eq!(
+            packets.packets().first().and_then(|packet| packet.get(..3)),
+            Some(&b"one"[..])
+        );
+    }
+
+    #[test]
+    fn test_idle_reader_exits_promptly() {
+        let shared_state = SharedState::new(PacketType::Text, false);
+        let (data_tx, _data_rx) = transport::bounded(TransportKind::Channel, 4);
+        let (memory_tx, memory_rx) = bounded(4);
+        for _ in 0..2 {
+            memory_tx.send(Packets::new(4, 0)).expect("pool");
+        }
+
+        let handle = spawn(ReaderConfig {
+            input: None,
+            template: None,
+            pps: None,
+            joins: vec![Join {
+                iface: None,
+                mgroup: "239.255.77.14".to_string(),
+                ports: vec![39514],
+            }],
+            batch_size: 4,
+            channels: (data_tx, memory_rx),
+            shared_state: shared_state.clone(),
+            max_count: 0,
+            input_framing: None,
+            adaptive_buffers: true,
+            speed: 1.0,
+            placement: ThreadPlacement::default(),
+            sandbox: false,
+            max_restarts: None,
+            max_packet: MAX_PACKET_BYTES,
+            drop_truncated: false,
+            loop_guard: None,
+            stall_threshold: DEFAULT_STALL_THRESHOLD,
+            repeat: None,
+        });
+
+        // Long enough to be parked waiting on an idle socket
+        thread::sleep(Duration::from_millis(200));
+        let signaled = Instant::now();
+        shared_state.signal_exit();
+
+        handle.join().expect("join").expect("reader");
+        let elapsed = signaled.elapsed();
+        assert!(elapsed < Duration::from_millis(50), "took {elapsed:?}");
+    }
+
+    #[test]
+    fn test_fixed_sizing_never_changes() {
+        let mut sizing = BufferSizing::new(false, 100, MAX_PACKET_BYTES);
+        assert_eq!((sizing.batch, sizing.packet_bytes), (100, MAX_PACKET_BYTES));
+        for _ in 0..10 {
+            sizing.observe(100);
+        }
+        assert_eq!(sizing.batch, 100);
+    }
+
+    #[test]
+    fn test_batch_fill_buckets() {
+        let counters = BatchFillCounters::default();
+        assert_eq!(format_batch_fill(&counters.get()), "");
+        for messages in [0, 1, 1, 1, 5, 10, 99, 100, 1024] {
+            counters.record(messages);
+        }
+        // A call that found nothing isn't counted
+        assert_eq!(counters.get(), [3, 1, 2, 2]);
+        assert_eq!(
+            format_batch_fill(&counters.get()),
+            "batches: 1msg 38% 2-9 12% 10-99 25% 100+ 25%"
+        );
+    }
+
+    #[test]
+    fn test_stalls_past_the_threshold() {
+        let stalls = StallCounters::default();
+        for ms in [1, 10, 25, 180, 40] {
+            stalls.record(Duration::from_millis(ms));
+        }
+        assert_eq!(stalls.get(), (3, Duration::from_millis(180)));
+        assert_eq!(stalls.take_interval_worst(), Duration::from_millis(180));
+        assert_eq!(stalls.take_interval_worst(), Duration::ZERO);
+
+        stalls.set_threshold(Duration::from_millis(50));
+        stalls.record(Duration::from_millis(40));
+        assert_eq!(stalls.get().0, 3);
+        assert_eq!(
+            format_stalls(42, Duration::from_millis(180), stalls.threshold()),
+            "stalls >50ms: 42, worst 180ms"
+        );
+    }
+
+    #[test]
+    fn test_batch_grows_when_full() {
+        let mut sizing = BufferSizing::new(true, 200, MAX_PACKET_BYTES);
+        assert_eq!((sizing.batch, sizing.packet_bytes), (64, 2048));
+
+        // A single full call isn't a trend
+        sizing.observe(64);
+        assert_eq!(sizing.batch, 64);
+
+        for _ in 1..GROW_AFTER {
+            sizing.observe(64);
+        }
+        assert_eq!(sizing.batch, 128);
+
+        for _ in 0..GROW_AFTER {
+            sizing.observe(128);
+        }
+        assert_eq!(sizing.batch, 200);
+    }
+
+    #[test]
+    fn test_batch_shrinks_when_sparse() {
+        let mut sizing = BufferSizing::new(true, 256, MAX_PACKET_BYTES);
+        for batch in [64, 128] {
+            for _ in 0..GROW_AFTER {
+                sizing.observe(batch);
+            }
+        }
+        assert_eq!(sizing.batch, 256);
+
+        for _ in 0..SHRINK_AFTER {
+            sizing.observe(1);
+        }
+        assert_eq!(sizing.batch, 128);
+
+        // Never below where it started
+        for _ in 0..SHRINK_AFTER * 4 {
+            sizing.observe(1);
+        }
+        assert_eq!(sizing.batch, 64);
+    }
+
+    #[test]
+    fn test_packet_bytes_grow_on_truncation() {
+        let mut sizing = BufferSizing::new(true, 64, MAX_PACKET_BYTES);
+        assert!(sizing.grow_for(3000));
+        assert_eq!(sizing.packet_bytes, 4096);
+        assert!(!sizing.grow_for(4000));
+        assert!(sizing.grow_for(65507));
+        assert_eq!(sizing.packet_bytes, MAX_PACKET_BYTES);
+
+        // Never past --max-packet
+        let mut sizing = BufferSizing::new(true, 64, 1500);
+        assert_eq!(sizing.packet_bytes, 1500);
+        assert!(!sizing.grow_for(3000));
+        assert_eq!(sizing.packet_bytes, 1500);
+    }
+
+    #[test]
+    fn test_other_groups_are_dropped() {
+        let group = Ipv4Addr::new(239, 1, 1, 1);
+        let other = Ipv4Addr::new(239, 1, 1, 2);
+        let to = |bytes: usize, destination: Option<Ipv4Addr>| {
+            let ancillary = Ancillary {
+                arrival: SystemTime::UNIX_EPOCH,
+                ttl: None,
+                destination,
+                source: None,
+            };
+            (bytes, ancillary, false)
+        };
+
+        let mut packets = Packets::new(4, 0);
+        for (i, packet) in packets.iter_mut().enumerate() {
+            packet.ensure_capacity(1);
+            packet.set_length(1);
+            if let Some(byte) = packet.data_mut().first_mut() {
+                *byte = i as u8;
+            }
+        }
+        let mut received = vec![
+            to(1, Some(other)),
+            to(2, Some(group)),
+            to(3, Some(other)),
+            to(4, None),
+        ];
+        let mut foreign = HashSet::new();
+
+        keep_group(&mut packets, &mut received, group, &mut foreign);
+
+        let bytes: Vec<usize> = received.iter().map(|(bytes, _, _)| *bytes).collect();
+        assert_eq!(bytes, vec![2, 4]);
+        // The buffers holding the kept datagrams moved with them
+        let first: Vec<u8> = packets
+            .iter()
+            .take(2)
+            .map(|packet| packet.first().copied().unwrap_or_default())
+            .collect();
+        assert_eq!(first, vec![1, 3]);
+        assert_eq!(foreign, HashSet::from([other]));
+    }
+
+    #[test]
+    fn test_truncated_are_counted_and_dropped() {
+        let datagram = |bytes: usize, truncated: bool| {
+            let ancillary = Ancillary {
+                arrival: SystemTime::UNIX_EPOCH,
+                ttl: None,
+                destination: None,
+                source: None,
+            };
+            (bytes, ancillary, truncated)
+        };
+        let batch = || {
+            let mut packets = Packets::new(3, 0);
+            for packet in packets.iter_mut() {
+                packet.ensure_capacity(100);
+                packet.set_length(100);
+            }
+            (
+                packets,
+                vec![
+                    datagram(40, false),
+                    datagram(3000, true),
+                    datagram(90, false),
+                ],
+            )
+        };
+        let shared_state = SharedState::new(PacketType::Text, false);
+
+        // Kept cut short, and the buffers stay at --max-packet
+        let mut sizing = BufferSizing::new(false, 3, 100);
+        let (mut packets, mut received) = batch();
+        check_truncated(
+            &mut packets,
+            &mut received,
+            &mut sizing,
+            false,
+            &shared_state,
+        );
+        assert_eq!(received.len(), 3);
+        assert_eq!(sizing.packet_bytes, 100);
+        assert_eq!(shared_state.get_truncated(), 1);
+
+        let (mut packets, mut received) = batch();
+        check_truncated(
+            &mut packets,
+            &mut received,
+            &mut sizing,
+            true,
+            &shared_state,
+        );
+        let bytes: Vec<usize> = received.iter().map(|(bytes, _, _)| *bytes).collect();
+        assert_eq!(bytes, vec![40, 90]);
+        assert_eq!(shared_state.get_truncated(), 2);
+    }
+
+    #[test]
+    fn test_restart_budget_is_per_hour() {
+        let start = Instant::now();
+        let error = LibError::Nix(Errno::ENOBUFS);
+        let mut budget = RestartBudget::new(2);
+        assert!(budget.spend(&error, start).is_ok());
+        assert!(
+            budget
+                .spend(&error, start + Duration::from_secs(60))
+                .is_ok()
+        );
+        assert!(
+            budget
+                .spend(&error, start + Duration::from_secs(120))
+                .is_err()
+        );
+        // An hour after the first, it no longer counts
+        assert!(budget.spend(&error, start + RESTART_WINDOW).is_ok());
+        assert!(
+            budget
+                .spend(&error, start + RESTART_WINDOW + Duration::from_secs(1))
+                .is_err()
+        );
+
+        assert!(is_transient(&error));
+        assert!(!is_transient(&LibError::Critical("x".to_string())));
+    }
+}
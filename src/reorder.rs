@@ -0,0 +1,273 @@
+/// --reorder: puts packets back in sequence order between the reader and the
+/// writer, for paths that reorder (e.g. across LAG members). Packets ahead of
+/// the next expected number wait in a buffer of up to depth packets for the
+/// ones before them. When the buffer overflows the oldest gap is given up on,
+/// and a packet that turns up after its place has passed goes out at once,
+/// counted as too late.
+///
+/// Held packets are swapped out of their batch for spare buffers rather than
+/// copied, so batches go back to the pool as usual.
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    packet::{Packet, PacketType, Packets},
+    sdds, vita49,
+};
+
+/// Where a packet's sequence number comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeqSource {
+    /// The SDDS frame sequence number, 16 bits
+    Sdds,
+    /// The VRLP frame sequence number, 12 bits
+    Vita49,
+    /// The --stamp-seq number read by --check-seq, 32 bits
+    Stamped,
+}
+
+impl SeqSource {
+    /// --check-seq wins, otherwise the packet type's own numbering.
+    pub fn new(packet_type: PacketType, check_seq: bool) -> Option<Self> {
+        match packet_type {
+            _ if check_seq => Some(SeqSource::Stamped),
+            PacketType::Sdds => Some(SeqSource::Sdds),
+            PacketType::Vita49 => Some(SeqSource::Vita49),
+            _ => None,
+        }
+    }
+
+    /// How many numbers there are before the sequence wraps.
+    fn modulus(self) -> u64 {
+        match self {
+            SeqSource::Sdds => 1 << 16,
+            SeqSource::Vita49 => 1 << 12,
+            SeqSource::Stamped => 1 << 32,
+        }
+    }
+
+    /// The most packets that can be held, half the sequence space so ahead
+    /// and behind stay distinguishable.
+    pub fn max_depth(self) -> usize {
+        (self.modulus() / 2 - 1) as usize
+    }
+
+    /// How far seq is ahead of next, modulo the sequence; behind is more
+    /// than half way round.
+    pub fn ahead(self, next: u64, seq: u64) -> u64 {
+        let modulus = self.modulus();
+        (seq % modulus + modulus - next % modulus) % modulus
+    }
+
+    /// Whether ahead() of this much really means behind.
+    pub fn is_behind(self, ahead: u64) -> bool {
+        ahead >= self.modulus() / 2
+    }
+
+    fn read(self, packet: &Packet) -> Option<u64> {
+        match self {
+            SeqSource::Stamped => packet.seq().map(u64::from),
+            _ => self.read_frame(packet),
+        }
+    }
+
+    /// The frame's own sequence number; stamped numbers aren't in the payload
+    /// once --check-seq has read them.
+    pub fn read_frame(self, data: &[u8]) -> Option<u64> {
+        match self {
+            SeqSource::Sdds if data.len() >= sdds::HEADER_BYTES => {
+                Some(sdds::parse_frame_header(data).frame_sequence_number.into())
+            }
+            SeqSource::Vita49 if data.get(..4) == Some(b"VRLP") => {
+                Some(vita49::parse_header(data).frame_sequence_number.into())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Packets put back in order and given up on, shared with the stats line.
+#[derive(Debug, Default)]
+pub struct ReorderCounters {
+    reordered: AtomicU64,
+    too_late: AtomicU64,
+}
+
+impl ReorderCounters {
+    /// (reordered, too late) so far.
+    pub fn get(&self) -> (u64, u64) {
+        (
+            self.reordered.load(Ordering::Relaxed),
+            self.too_late.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Reorder {
+    source: SeqSource,
+    depth: usize,
+    // The next number to go out, unwrapped so it never goes backwards
+    next: Option<u64>,
+    held: BTreeMap<u64, Packet>,
+    spare: Vec<Packet>,
+}
+
+impl Reorder {
+    pub fn new(source: SeqSource, depth: usize) -> Self {
+        Self {
+            source,
+            depth: depth.min(source.max_depth()),
+            next: None,
+            held: BTreeMap::new(),
+            spare: Vec::new(),
+        }
+    }
+
+    /// Take the batch's packets and append those ready to go out to ready,
+    /// in order. Packets without a sequence number go straight through.
+    pub fn push(
+        &mut self,
+        packets: &mut Packets,
+        ready: &mut Vec<Packet>,
+        counters: &ReorderCounters,
+    ) {
+        for slot in packets.iter_mut() {
+            let spare = self
+                .spare
+                .pop()
+                .unwrap_or_else(|| Packet::with_capacity(slot.capacity()));
+            let packet = std::mem::replace(slot, spare);
+
+            let Some(seq) = self.source.read(&packet) else {
+                ready.push(packet);
+                continue;
+            };
+            let next = *self.next.get_or_insert(seq);
+
+            // Ahead or behind the next expected number, whichever is closer
+            let ahead = self.source.ahead(next, seq);
+            if self.source.is_behind(ahead) || self.held.contains_key(&(next + ahead)) {
+                counters.too_late.fetch_add(1, Ordering::Relaxed);
+                ready.push(packet);
+                continue;
+            }
+
+            if ahead == 0 {
+                if !self.held.is_empty() {
+                    counters.reordered.fetch_add(1, Ordering::Relaxed);
+                }
+                ready.push(packet);
+                self.next = Some(next + 1);
+            } else {
+                self.held.insert(next + ahead, packet);
+                // Full: give up on the oldest gap
+                if self.held.len() > self.depth
+                    && let Some((seq, packet)) = self.held.pop_first()
+                {
+                    ready.push(packet);
+                    self.next = Some(seq + 1);
+                }
+            }
+            self.release(ready);
+        }
+    }
+
+    /// Everything still held, in order, at the end of the stream.
+    pub fn flush(&mut self, ready: &mut Vec<Packet>) {
+        while let Some((seq, packet)) = self.held.pop_first() {
+            ready.push(packet);
+            self.next = Some(seq + 1);
+        }
+    }
+
+    /// Take written packets back as spare buffers.
+    pub fn reuse(&mut self, written: &mut Vec<Packet>) {
+        self.spare.append(written);
+    }
+
+    // Held packets the next number has caught up with
+    fn release(&mut self, ready: &mut Vec<Packet>) {
+        while let Some(next) = self.next
+            && let Some(packet) = self.held.remove(&next)
+        {
+            ready.push(packet);
+            self.next = Some(next + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    // Run a stream of --stamp-seq numbers through, one per batch, and return
+    // the order they came out in
+    fn reorder(depth: usize, seqs: &[u32]) -> (Vec<u32>, (u64, u64)) {
+        let mut reorder = Reorder::new(SeqSource::Stamped, depth);
+        let counters = ReorderCounters::default();
+        let mut ready = Vec::new();
+        let mut order = Vec::new();
+
+        for seq in seqs {
+            let mut packets = Packets::new(1, 4);
+            for packet in packets.iter_mut() {
+                packet.set_seq(Some(*seq));
+            }
+            reorder.push(&mut packets, &mut ready, &counters);
+            order.extend(ready.iter().filter_map(|packet| packet.seq()));
+            reorder.reuse(&mut ready);
+        }
+        reorder.flush(&mut ready);
+        order.extend(ready.iter().filter_map(|packet| packet.seq()));
+        (order, counters.get())
+    }
+
+    #[test]
+    fn test_in_order_passes_through() {
+        assert_eq!(reorder(4, &[7, 8, 9]), (vec![7, 8, 9], (0, 0)));
+    }
+
+    #[test]
+    fn test_swapped_packets_are_put_back() {
+        assert_eq!(
+            reorder(4, &[1, 3, 2, 5, 4, 6]),
+            (vec![1, 2, 3, 4, 5, 6], (2, 0))
+        );
+    }
+
+    #[test]
+    fn test_overflow_gives_up_on_the_gap() {
+        // 2 never comes in time: with room for two, 5 pushes 3 out
+        assert_eq!(
+            reorder(2, &[1, 3, 4, 5, 2, 6]),
+            (vec![1, 3, 4, 5, 2, 6], (0, 1))
+        );
+    }
+
+    #[test]
+    fn test_flush_empties_the_buffer_in_order() {
+        assert_eq!(reorder(8, &[1, 4, 3]), (vec![1, 3, 4], (0, 0)));
+    }
+
+    #[test]
+    fn test_wraps() {
+        assert_eq!(
+            reorder(4, &[u32::MAX - 1, 0, u32::MAX, 1]),
+            (vec![u32::MAX - 1, u32::MAX, 0, 1], (1, 0))
+        );
+    }
+
+    #[test]
+    fn test_sdds_sequence() {
+        let mut packet = Packet::with_capacity(sdds::HEADER_BYTES);
+        if let Some(seq) = packet.data_mut().get_mut(2..4) {
+            seq.copy_from_slice(&513u16.to_be_bytes());
+        }
+        assert_eq!(SeqSource::Sdds.read(&packet), Some(513));
+        packet.set_length(10);
+        assert_eq!(SeqSource::Sdds.read(&packet), None);
+        assert_eq!(SeqSource::Vita49.max_depth(), 2047);
+    }
+}
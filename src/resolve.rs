@@ -0,0 +1,344 @@
+/// Host names for source addresses, for --resolve. Lookups happen on a
+/// thread of their own: whoever asks gets the name if it's known and the
+/// address until then, so a slow or dead DNS server never holds up packets.
+/// Names are looked up in /etc/hosts, then with a PTR query to the
+/// nameservers in /etc/resolv.conf. Misses are cached as well, so each
+/// address costs at most one timeout a minute.
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+
+use crate::mdns::{self, RecordData};
+
+/// How long a name is used before it's looked up again.
+const NAME_TTL: Duration = Duration::from_secs(600);
+
+/// How long an address without a name is left before trying again.
+const MISS_TTL: Duration = Duration::from_secs(60);
+
+/// Wait for each nameserver's answer.
+const LOOKUP_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Addresses remembered; past this, expired ones are dropped and new ones
+/// are left as addresses.
+const MAX_NAMES: usize = 4096;
+
+// Lookups queued for the resolver thread; more are asked for again later
+const QUEUE_DEPTH: usize = 256;
+
+const HOSTS: &str = "/etc/hosts";
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+#[derive(Debug, Clone)]
+enum Entry {
+    /// Queued for the resolver thread, with the name from before if any
+    Pending(Option<String>),
+    Known {
+        name: Option<String>,
+        expires: Instant,
+    },
+}
+
+/// The cache of names, shared by everything that shows a source. Does
+/// nothing until [`HostNames::start`].
+#[derive(Debug, Default)]
+pub struct HostNames {
+    requests: OnceLock<Sender<Ipv4Addr>>,
+    cache: Mutex<HashMap<Ipv4Addr, Entry>>,
+}
+
+impl HostNames {
+    /// Start the resolver thread. Only the first call does anything.
+    pub fn start(self: &std::sync::Arc<Self>) {
+        let (sender, receiver) = crossbeam_channel::bounded(QUEUE_DEPTH);
+        if self.requests.set(sender).is_err() {
+            return;
+        }
+        let names = self.clone();
+        let spawned = std::thread::Builder::new()
+            .name("resolver".to_string())
+            .spawn(move || names.run(receiver, lookup));
+        if let Err(e) = spawned {
+            log::warn!("--resolve: no resolver thread, sources stay numeric: {e}");
+        }
+    }
+
+    /// Whether names are being looked up at all.
+    pub fn is_started(&self) -> bool {
+        self.requests.get().is_some()
+    }
+
+    /// The name of ip if it's known, asking for it otherwise.
+    pub fn name(&self, ip: Ipv4Addr) -> Option<String> {
+        self.name_at(ip, Instant::now())
+    }
+
+    /// "host:port" once the host's name is known, the address before.
+    pub fn format(&self, source: SocketAddrV4) -> String {
+        match self.name(*source.ip()) {
+            Some(name) => format!("{name}:{}", source.port()),
+            None => source.to_string(),
+        }
+    }
+
+    fn name_at(&self, ip: Ipv4Addr, now: Instant) -> Option<String> {
+        let requests = self.requests.get()?;
+        let mut cache = self.cache.lock().ok()?;
+        let stale = match cache.get(&ip) {
+            Some(Entry::Pending(name)) => return name.clone(),
+            Some(Entry::Known { name, expires }) if now < *expires => return name.clone(),
+            Some(Entry::Known { name, .. }) => name.clone(),
+            None => None,
+        };
+
+        if !cache.contains_key(&ip) && cache.len() >= MAX_NAMES {
+            cache
+                .retain(|_, entry| matches!(entry, Entry::Known { expires, .. } if now < *expires));
+            if cache.len() >= MAX_NAMES {
+                return None;
+            }
+        }
+        match requests.try_send(ip) {
+            Ok(()) => {
+                cache.insert(ip, Entry::Pending(stale.clone()));
+            }
+            // Asked for again next time
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {}
+        }
+        stale
+    }
+
+    fn store(&self, ip: Ipv4Addr, name: Option<String>, now: Instant) {
+        let ttl = if name.is_some() { NAME_TTL } else { MISS_TTL };
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.insert(
+                ip,
+                Entry::Known {
+                    name,
+                    expires: now + ttl,
+                },
+            );
+        }
+    }
+
+    /// Started, knowing these names, without a resolver thread.
+    #[cfg(test)]
+    pub(crate) fn with_known(known: &[(Ipv4Addr, &str)]) -> std::sync::Arc<Self> {
+        let names = Self::default();
+        let (sender, _) = crossbeam_channel::bounded(1);
+        let _ = names.requests.set(sender);
+        for (ip, name) in known {
+            names.store(*ip, Some(name.to_string()), Instant::now());
+        }
+        std::sync::Arc::new(names)
+    }
+
+    fn run(&self, receiver: Receiver<Ipv4Addr>, lookup: fn(Ipv4Addr) -> Option<String>) {
+        for ip in receiver {
+            let name = lookup(ip);
+            log::debug!("resolved {ip} to {}", name.as_deref().unwrap_or("nothing"));
+            self.store(ip, name, Instant::now());
+        }
+    }
+}
+
+/// The name of ip from /etc/hosts, or else from DNS.
+fn lookup(ip: Ipv4Addr) -> Option<String> {
+    if let Some(name) = std::fs::read_to_string(HOSTS)
+        .ok()
+        .and_then(|hosts| hosts_name(&hosts, ip))
+    {
+        return Some(name);
+    }
+    let conf = std::fs::read_to_string(RESOLV_CONF).ok()?;
+    nameservers(&conf)
+        .into_iter()
+        .find_map(|server| query_ptr(server, ip).ok().flatten())
+}
+
+/// The first name given for ip in a hosts file.
+fn hosts_name(hosts: &str, ip: Ipv4Addr) -> Option<String> {
+    hosts.lines().find_map(|line| {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let address: Ipv4Addr = fields.next()?.parse().ok()?;
+        (address == ip).then(|| fields.next().map(str::to_string))?
+    })
+}
+
+/// The IPv4 nameservers in a resolv.conf.
+fn nameservers(conf: &str) -> Vec<Ipv4Addr> {
+    conf.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("nameserver") => fields.next()?.parse().ok(),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Ask server for ip's PTR record. Ok(None) is an answer without one.
+fn query_ptr(server: Ipv4Addr, ip: Ipv4Addr) -> std::io::Result<Option<String>> {
+    let id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as u16;
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect((server, 53))?;
+    socket.set_read_timeout(Some(LOOKUP_TIMEOUT))?;
+    socket.send(&ptr_query(id, ip))?;
+
+    let deadline = Instant::now() + LOOKUP_TIMEOUT;
+    let mut buffer = [0u8; 1500];
+    loop {
+        let received = socket.recv(&mut buffer)?;
+        if let Some(answer) = ptr_answer(buffer.get(..received).unwrap_or_default(), id) {
+            return Ok(answer);
+        }
+        // Someone else's reply; wait out what's left for ours
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        socket.set_read_timeout(Some(left))?;
+    }
+}
+
+/// A recursive query for ip's name under in-addr.arpa.
+fn ptr_query(id: u16, ip: Ipv4Addr) -> Vec<u8> {
+    let mut query = Vec::with_capacity(mdns::HEADER_SIZE + 32);
+    query.extend(id.to_be_bytes());
+    // Recursion desired
+    query.extend(0x0100u16.to_be_bytes());
+    // One question, nothing else
+    query.extend([0, 1, 0, 0, 0, 0, 0, 0]);
+    let octets = ip.octets();
+    for octet in octets.iter().rev() {
+        let label = octet.to_string();
+        query.push(label.len() as u8);
+        query.extend(label.as_bytes());
+    }
+    for label in ["in-addr", "arpa"] {
+        query.push(label.len() as u8);
+        query.extend(label.as_bytes());
+    }
+    query.push(0);
+    query.extend(mdns::TYPE_PTR.to_be_bytes());
+    // Class IN
+    query.extend(1u16.to_be_bytes());
+    query
+}
+
+/// The name in a reply to query id: None when packet isn't that reply,
+/// Some(None) when it is but has no PTR record.
+fn ptr_answer(packet: &[u8], id: u16) -> Option<Option<String>> {
+    let message = mdns::parse_message(packet)?;
+    if message.header.id != id || !message.header.is_response() {
+        return None;
+    }
+    Some(
+        message
+            .records
+            .into_iter()
+            .find_map(|record| match record.data {
+                RecordData::Ptr(name) => Some(name),
+                _ => None,
+            }),
+    )
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    const IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    #[test]
+    fn test_hosts_name() {
+        let hosts =
+            "127.0.0.1 localhost\n# 10.0.0.1 commented\n10.0.0.1\trecv1.lab recv1 # a comment\n";
+        assert_eq!(hosts_name(hosts, IP), Some("recv1.lab".to_string()));
+        assert_eq!(hosts_name(hosts, Ipv4Addr::new(10, 0, 0, 2)), None);
+    }
+
+    #[test]
+    fn test_nameservers() {
+        let conf =
+            "# generated\nsearch lab\nnameserver 10.0.0.53\nnameserver ::1\nnameserver 10.0.1.53\n";
+        assert_eq!(
+            nameservers(conf),
+            vec![Ipv4Addr::new(10, 0, 0, 53), Ipv4Addr::new(10, 0, 1, 53)]
+        );
+    }
+
+    #[test]
+    fn test_ptr_query_and_answer() {
+        let query = ptr_query(0x1234, IP);
+        let message = mdns::parse_message(&query).expect("query parses");
+        assert_eq!(message.header.id, 0x1234);
+        assert_eq!(
+            message.records.first().map(|record| record.name.as_str()),
+            Some("1.0.0.10.in-addr.arpa")
+        );
+
+        // The reply: the question again, then its answer pointing back at it
+        let mut reply = query.clone();
+        reply.splice(2..4, 0x8180u16.to_be_bytes());
+        reply.splice(6..8, 1u16.to_be_bytes());
+        reply.extend([0xC0, 12]);
+        reply.extend(mdns::TYPE_PTR.to_be_bytes());
+        reply.extend(1u16.to_be_bytes());
+        reply.extend(300u32.to_be_bytes());
+        reply.extend(11u16.to_be_bytes());
+        reply.extend(b"\x05recv1\x03lab\x00");
+        assert_eq!(
+            ptr_answer(&reply, 0x1234),
+            Some(Some("recv1.lab".to_string()))
+        );
+        assert_eq!(ptr_answer(&reply, 0x4321), None);
+        assert_eq!(ptr_answer(&query, 0x1234), None);
+
+        // NXDOMAIN, no answers
+        let mut missing = query;
+        missing.splice(2..4, 0x8183u16.to_be_bytes());
+        assert_eq!(ptr_answer(&missing, 0x1234), Some(None));
+    }
+
+    #[test]
+    fn test_names_are_cached_and_expire() {
+        let names = Arc::new(HostNames::default());
+        let start = Instant::now();
+        // Not started: always numeric, nothing asked for
+        assert_eq!(names.name_at(IP, start), None);
+        assert_eq!(names.format(SocketAddrV4::new(IP, 5000)), "10.0.0.1:5000");
+
+        let (sender, receiver) = crossbeam_channel::bounded(QUEUE_DEPTH);
+        names.requests.set(sender).expect("unset");
+        assert_eq!(names.name_at(IP, start), None);
+        // Asked for once while pending
+        assert_eq!(names.name_at(IP, start), None);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![IP]);
+
+        names.store(IP, Some("recv1.lab".to_string()), start);
+        assert_eq!(names.name_at(IP, start), Some("recv1.lab".to_string()));
+        // Expired, the old name is used while it's looked up again
+        let later = start + NAME_TTL;
+        assert_eq!(names.name_at(IP, later), Some("recv1.lab".to_string()));
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![IP]);
+
+        // A miss is cached for less long
+        names.store(IP, None, later);
+        assert_eq!(names.name_at(IP, later + MISS_TTL / 2), None);
+        assert!(receiver.try_iter().next().is_none());
+        assert_eq!(names.name_at(IP, later + MISS_TTL), None);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![IP]);
+    }
+}
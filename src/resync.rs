@@ -0,0 +1,172 @@
+/// Finding frames again in a byte stream that has no framing of its own,
+/// like VRLP frames recorded back to back with nothing between them. A
+/// [`FrameSync`] says where a frame starts, how long it is and whether it
+/// checks out; [`FrameScanner`] walks the stream with it, stepping a byte
+/// at a time past anything that doesn't, and counts what it stepped over.
+use std::io::{self, Read};
+
+// Read from the input this much at a time.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// How to recognize one kind of frame.
+pub trait FrameSync {
+    /// Bytes needed from the start of a frame to tell its length.
+    const PREFIX: usize;
+
+    /// The length of a frame starting with prefix, or None if a frame
+    /// can't start there. prefix holds at least PREFIX bytes.
+    fn frame_len(&self, prefix: &[u8]) -> Option<usize>;
+
+    /// Whether a whole frame of the length frame_len gave checks out.
+    fn is_valid(&self, frame: &[u8]) -> bool;
+}
+
+/// The frames of a stream, with what lies between them skipped.
+#[derive(Debug)]
+pub struct FrameScanner<S> {
+    sync: S,
+    buffer: Vec<u8>,
+    /// Where the bytes not yet scanned start in buffer
+    start: usize,
+    eof: bool,
+    skipped: u64,
+}
+
+impl<S: FrameSync> FrameScanner<S> {
+    pub fn new(sync: S) -> Self {
+        Self {
+            sync,
+            buffer: Vec::new(),
+            start: 0,
+            eof: false,
+            skipped: 0,
+        }
+    }
+
+    /// Bytes stepped over so far, between frames or after the last.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// The next frame that checks out, read from reader as needed. None
+    /// once the stream ends; bytes left over then count as skipped.
+    pub fn next_frame<R: Read>(&mut self, reader: &mut R) -> io::Result<Option<&[u8]>> {
+        loop {
+            if !self.fill(reader, S::PREFIX)? {
+                self.skipped += self.unscanned().len() as u64;
+                self.start = self.buffer.len();
+                return Ok(None);
+            }
+            if let Some(len) = self.sync.frame_len(self.unscanned())
+                && self.fill(reader, len)?
+                && self
+                    .unscanned()
+                    .get(..len)
+                    .is_some_and(|frame| self.sync.is_valid(frame))
+            {
+                let frame = self.start..self.start + len;
+                self.start += len;
+                return Ok(self.buffer.get(frame));
+            }
+            // No frame here: try again a byte on
+            self.start += 1;
+            self.skipped += 1;
+        }
+    }
+
+    fn unscanned(&self) -> &[u8] {
+        self.buffer.get(self.start..).unwrap_or_default()
+    }
+
+    /// Read until wanted bytes are waiting to be scanned. False if the
+    /// stream ends first.
+    fn fill<R: Read>(&mut self, reader: &mut R, wanted: usize) -> io::Result<bool> {
+        while self.unscanned().len() < wanted {
+            if self.eof {
+                return Ok(false);
+            }
+            // What was scanned already is never looked at again
+            self.buffer.drain(..self.start);
+            self.start = 0;
+            let filled = self.buffer.len();
+            self.buffer
+                .resize(filled + READ_CHUNK.max(wanted - filled), 0);
+            let read = loop {
+                match reader.read(self.buffer.get_mut(filled..).unwrap_or_default()) {
+                    Ok(read) => break read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        self.buffer.truncate(filled);
+                        return Err(e);
+                    }
+                }
+            };
+            self.buffer.truncate(filled + read);
+            self.eof = read == 0;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+
+    /// "<" then a length byte, that many bytes, then ">".
+    struct Angle;
+
+    impl FrameSync for Angle {
+        const PREFIX: usize = 2;
+
+        fn frame_len(&self, prefix: &[u8]) -> Option<usize> {
+            match prefix {
+                [b'<', len, ..] => Some(*len as usize + 3),
+                _ => None,
+            }
+        }
+
+        fn is_valid(&self, frame: &[u8]) -> bool {
+            frame.last() == Some(&b'>')
+        }
+    }
+
+    fn frames(input: &[u8]) -> (Vec<Vec<u8>>, u64) {
+        let mut scanner = FrameScanner::new(Angle);
+        let mut reader = input;
+        let mut frames = Vec::new();
+        while let Some(frame) = scanner.next_frame(&mut reader).expect("read") {
+            frames.push(frame.to_vec());
+        }
+        (frames, scanner.skipped())
+    }
+
+    #[test]
+    fn test_back_to_back() {
+        let (frames, skipped) = frames(b"<\x02ab><\x01c>");
+        assert_eq!(frames, vec![b"<\x02ab>".to_vec(), b"<\x01c>".to_vec()]);
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn test_resync_after_garbage() {
+        // Junk up front, a frame whose trailer is wrong, and a torn one at the end
+        let (frames, skipped) = frames(b"xy<\x02ab><\x01cX<\x01d><\x05e");
+        assert_eq!(frames, vec![b"<\x02ab>".to_vec(), b"<\x01d>".to_vec()]);
+        assert_eq!(skipped, 2 + 4 + 3);
+    }
+
+    #[test]
+    fn test_frames_across_reads() {
+        let frame = [b"<\xff".to_vec(), vec![b'z'; 255], b">".to_vec()].concat();
+        let input: Vec<u8> = frame
+            .iter()
+            .copied()
+            .cycle()
+            .take(frame.len() * 300)
+            .collect();
+        let (frames, skipped) = frames(&input);
+        assert_eq!(frames.len(), 300);
+        assert_eq!(skipped, 0);
+    }
+}
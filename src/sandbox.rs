@@ -0,0 +1,211 @@
+/// --sandbox: once a pipeline thread has opened its sockets and files it
+/// gives up opening any more. Landlock, where the kernel has it, takes away
+/// the filesystem, and a seccomp filter allows only the system calls moving
+/// packets needs. Opening a file fails, any other call kills the whole
+/// process with SIGSYS, so a violation shows up as "Bad system call" rather
+/// than as an I/O error that might be retried or logged and forgotten.
+/// Filters are per thread: the ctrl-c and signal threads, and the memory
+/// pool filler, start before anything is locked down and stay as they are.
+#[cfg(target_os = "linux")]
+use std::collections::BTreeMap;
+
+#[cfg(target_os = "linux")]
+use landlock::{ABI, Access, AccessFs, Ruleset, RulesetAttr, RulesetStatus};
+#[cfg(target_os = "linux")]
+use nix::libc;
+#[cfg(target_os = "linux")]
+use seccompiler::{
+    BpfProgram, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition, SeccompFilter,
+    SeccompRule, TargetArch,
+};
+
+#[cfg(target_os = "linux")]
+use crate::error::LibError;
+use crate::error::Result;
+
+/// Whether the kernel enforced the filesystem rules as well as the filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enforced {
+    /// Seccomp and Landlock both
+    Full,
+    /// Seccomp, and Landlock only as far as this kernel's version goes
+    Partial,
+    /// Seccomp alone, this kernel has no Landlock
+    SeccompOnly,
+}
+
+/// Lock the calling thread down for good. Its open descriptors keep
+/// working, nothing new can be opened.
+#[cfg(target_os = "linux")]
+pub fn enter(thread: &str) -> Result<Enforced> {
+    let enforced = restrict_filesystem().map_err(|e| sandbox_error(thread, "landlock", e))?;
+    for filter in build_filters().map_err(|e| sandbox_error(thread, "seccomp", e))? {
+        seccompiler::apply_filter(&filter).map_err(|e| sandbox_error(thread, "seccomp", e))?;
+    }
+    log::debug!("{thread} sandboxed, {enforced:?}");
+    Ok(enforced)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enter(_thread: &str) -> Result<Enforced> {
+    Err(crate::error::LibError::Critical(
+        "--sandbox needs Linux".to_string(),
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn sandbox_error(thread: &str, what: &str, e: impl std::fmt::Display) -> LibError {
+    LibError::Critical(format!("{thread}: {what}: {e}"))
+}
+
+/// Handle every filesystem access this kernel knows of and allow none.
+#[cfg(target_os = "linux")]
+fn restrict_filesystem() -> std::result::Result<Enforced, landlock::RulesetError> {
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(ABI::V5))?
+        .create()?
+        .restrict_self()?;
+    Ok(match status.ruleset {
+        RulesetStatus::FullyEnforced => Enforced::Full,
+        RulesetStatus::PartiallyEnforced => Enforced::Partial,
+        RulesetStatus::NotEnforced => Enforced::SeccompOnly,
+    })
+}
+
+/// Everything the reader, statistics and writer threads call once set up,
+/// and the main thread while it waits on them.
+#[cfg(target_os = "linux")]
+const ALLOWED: &[libc::c_long] = &[
+    // Packets, files and the standard streams
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_lseek,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_close,
+    libc::SYS_recvfrom,
+    libc::SYS_recvmsg,
+    libc::SYS_recvmmsg,
+    libc::SYS_sendto,
+    libc::SYS_sendmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_getsockopt,
+    libc::SYS_setsockopt,
+    libc::SYS_getsockname,
+    libc::SYS_ppoll,
+    libc::SYS_pselect6,
+    // Channels, sleeps and clocks
+    libc::SYS_futex,
+    libc::SYS_sched_yield,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_getres,
+    libc::SYS_gettimeofday,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_getrandom,
+    // The allocator
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    // Signals and threads ending
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_tgkill,
+    libc::SYS_restart_syscall,
+    libc::SYS_rseq,
+    libc::SYS_set_robust_list,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+];
+
+/// glibc opens files of its own now and then, like /proc/sys/vm/overcommit_memory
+/// the first time free hands memory back, and copes when it can't. Opening
+/// fails with EACCES, as Landlock would have it, rather than killing.
+#[cfg(target_os = "linux")]
+const REFUSED: &[libc::c_long] = &[libc::SYS_openat];
+
+/// Two filters, as the kernel takes the strictest answer of all of them:
+/// one kills the process on anything not allowed or refused, the other
+/// makes the refused calls fail. socket is allowed for AF_UNIX alone, for
+/// sd_notify.
+#[cfg(target_os = "linux")]
+fn build_filters() -> std::result::Result<[BpfProgram; 2], seccompiler::Error> {
+    let mut allowed: BTreeMap<i64, Vec<SeccompRule>> = ALLOWED
+        .iter()
+        .chain(REFUSED)
+        .map(|&syscall| (syscall, Vec::new()))
+        .collect();
+    allowed.insert(
+        libc::SYS_socket,
+        vec![SeccompRule::new(vec![SeccompCondition::new(
+            0,
+            SeccompCmpArgLen::Dword,
+            SeccompCmpOp::Eq,
+            libc::AF_UNIX as u64,
+        )?])?],
+    );
+    let refused = REFUSED
+        .iter()
+        .map(|&syscall| (syscall, Vec::new()))
+        .collect();
+    // The killing one last, as it takes away seccomp itself
+    Ok([
+        compile(
+            refused,
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::EACCES as u32),
+        )?,
+        compile(allowed, SeccompAction::KillProcess, SeccompAction::Allow)?,
+    ])
+}
+
+#[cfg(target_os = "linux")]
+fn compile(
+    rules: BTreeMap<i64, Vec<SeccompRule>>,
+    mismatch: SeccompAction,
+    matched: SeccompAction,
+) -> std::result::Result<BpfProgram, seccompiler::Error> {
+    let arch = TargetArch::try_from(std::env::consts::ARCH)?;
+    Ok(SeccompFilter::new(rules, mismatch, matched, arch)?.try_into()?)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filters_build() {
+        assert!(build_filters().is_ok_and(|filters| filters.iter().all(|f| !f.is_empty())));
+    }
+
+    #[test]
+    fn test_moving_packets_is_allowed() {
+        for syscall in [
+            libc::SYS_recvmmsg,
+            libc::SYS_sendmmsg,
+            libc::SYS_futex,
+            libc::SYS_write,
+        ] {
+            assert!(ALLOWED.contains(&syscall));
+        }
+        assert!(!ALLOWED.contains(&libc::SYS_openat));
+        assert!(!ALLOWED.contains(&libc::SYS_execve));
+        assert!(!REFUSED.contains(&libc::SYS_execve));
+    }
+}
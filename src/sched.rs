@@ -0,0 +1,193 @@
+/// CPU pinning and realtime priority for the pipeline threads.
+/// Each thread applies its own placement when it starts, so the settings
+/// only ever touch the thread they were meant for.
+/// Both need Linux: elsewhere --cpu is refused and --rt-priority warns.
+#[cfg(target_os = "linux")]
+use nix::errno::Errno;
+#[cfg(target_os = "linux")]
+use nix::libc;
+#[cfg(target_os = "linux")]
+use nix::sched::{CpuSet, sched_getaffinity, sched_getcpu, sched_setaffinity};
+#[cfg(target_os = "linux")]
+use nix::unistd::Pid;
+
+/// Per-thread core assignment from --cpu reader=N,writer=M,stats=K.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuAssignment {
+    pub reader: Option<usize>,
+    pub writer: Option<usize>,
+    pub stats: Option<usize>,
+}
+
+/// Where and how a single thread should run. Default leaves it alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ThreadPlacement {
+    pub cpu: Option<usize>,
+    pub rt_priority: Option<u8>,
+}
+
+/// Parse reader=N,writer=M,stats=K. Any subset of the threads may be given.
+/// Cores must be in the set this process is allowed to run on.
+#[cfg(target_os = "linux")]
+pub fn parse_cpu_assignment(s: &str) -> std::result::Result<CpuAssignment, String> {
+    let allowed = sched_getaffinity(Pid::from_raw(0))
+        .map_err(|e| format!("Unable to read the allowed CPU set: {e}"))?;
+
+    parse_cpu_assignment_with(s, |cpu| allowed.is_set(cpu).unwrap_or(false))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn parse_cpu_assignment(_s: &str) -> std::result::Result<CpuAssignment, String> {
+    Err("pinning threads to cores needs Linux".to_string())
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_cpu_assignment_with(
+    s: &str,
+    is_allowed: impl Fn(usize) -> bool,
+) -> std::result::Result<CpuAssignment, String> {
+    let mut assignment = CpuAssignment::default();
+
+    for entry in s.split(',') {
+        let (thread, cpu) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("Expected thread=core, got: {entry}"))?;
+
+        let cpu: usize = cpu
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid core index: {cpu}"))?;
+        if !is_allowed(cpu) {
+            return Err(format!("Core {cpu} is not available to this process"));
+        }
+
+        let slot = match thread.trim() {
+            "reader" => &mut assignment.reader,
+            "writer" => &mut assignment.writer,
+            "stats" => &mut assignment.stats,
+            other => {
+                return Err(format!(
+                    "Unknown thread {other}, expected reader, writer or stats"
+                ));
+            }
+        };
+        if slot.replace(cpu).is_some() {
+            return Err(format!("Core given twice for {}", thread.trim()));
+        }
+    }
+
+    Ok(assignment)
+}
+
+/// Apply placement to the calling thread and log where it ended up.
+/// Failures are warnings: a thread that can't be pinned still does its job.
+#[cfg(target_os = "linux")]
+pub fn apply(name: &str, placement: &ThreadPlacement) {
+    if let Some(cpu) = placement.cpu {
+        let mut cpuset = CpuSet::new();
+        // Pid 0 is the calling thread
+        match cpuset
+            .set(cpu)
+            .and_then(|_| sched_setaffinity(Pid::from_raw(0), &cpuset))
+        {
+            Ok(()) => {}
+            Err(e) => log::warn!("{name}: unable to pin to cpu {cpu}: {e}"),
+        }
+    }
+
+    if let Some(priority) = placement.rt_priority {
+        set_fifo_priority(name, priority);
+    }
+
+    if placement != &ThreadPlacement::default() {
+        match sched_getcpu() {
+            Ok(cpu) => log::info!("{name} thread running on cpu {cpu}"),
+            Err(e) => log::debug!("{name}: sched_getcpu failed: {e}"),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(name: &str, placement: &ThreadPlacement) {
+    if let Some(priority) = placement.rt_priority {
+        log::warn!("{name}: SCHED_FIFO priority {priority} needs Linux, ignored");
+    }
+}
+
+// nix has no wrapper for sched_setscheduler, and musl's always fails with
+// ENOSYS, so this is the system call itself. Pid 0 is the calling thread.
+#[cfg(target_os = "linux")]
+fn set_fifo_priority(name: &str, priority: u8) {
+    let param = libc::sched_param {
+        sched_priority: libc::c_int::from(priority),
+    };
+    // SAFETY: param is a valid sched_param that outlives the call, which only
+    // reads it, and pid 0 changes no thread but this one.
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_sched_setscheduler,
+            0,
+            libc::SCHED_FIFO,
+            &param as *const libc::sched_param,
+        )
+    };
+
+    match Errno::result(result) {
+        Ok(_) => log::info!("{name} thread using SCHED_FIFO priority {priority}"),
+        Err(Errno::EPERM) => log::warn!(
+            "{name}: SCHED_FIFO priority {priority} not permitted (EPERM), needs CAP_SYS_NICE or an rtprio limit"
+        ),
+        Err(e) => log::warn!("{name}: unable to set SCHED_FIFO priority {priority}: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> std::result::Result<CpuAssignment, String> {
+        parse_cpu_assignment_with(s, |cpu| cpu < 4)
+    }
+
+    #[test]
+    fn test_parse_all_threads() {
+        assert_eq!(
+            parse("reader=1,writer=2,stats=3"),
+            Ok(CpuAssignment {
+                reader: Some(1),
+                writer: Some(2),
+                stats: Some(3),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_subset() {
+        assert_eq!(
+            parse("writer=0"),
+            Ok(CpuAssignment {
+                writer: Some(0),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_input() {
+        assert!(parse("reader=9").is_err());
+        assert!(parse("reader=x").is_err());
+        assert!(parse("reader").is_err());
+        assert!(parse("decoder=1").is_err());
+        assert!(parse("reader=1,reader=2").is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_parse_against_process_affinity() {
+        let Ok(current) = sched_getcpu() else {
+            return;
+        };
+        assert!(parse_cpu_assignment(&format!("reader={current}")).is_ok());
+        assert!(parse_cpu_assignment(&format!("reader={}", CpuSet::count())).is_err());
+    }
+}
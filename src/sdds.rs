@@ -21,6 +21,86 @@
 //   20          reserved
 //   1024        [Data]
 
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+
+// Time tags count 250ps ticks.
+pub const TICKS_PER_SEC: u64 = 4_000_000_000;
+
+const DATA_BYTES: u64 = 1024;
+
+/// Everything before the sample data.
+pub const HEADER_BYTES: usize = 56;
+
+// Deviation from the expected time tag step that still counts as continuous,
+// in parts per million of the step.
+const TIME_TAG_TOLERANCE_PPM: u64 = 1000;
+
+// An interval's lowest time tag offset moving this far from the last one's
+// is a clock being set rather than jitter.
+const CLOCK_STEP_NANOS: u64 = 1_000_000;
+
+/// What the time tag counts from, for showing it as a UTC date and time.
+/// SDDS time tags count from the start of a year, but the stream doesn't say
+/// which.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SddsEpoch {
+    /// January 1st of the year, 00:00 UTC
+    Year(i32),
+    /// Seconds since the unix epoch
+    Unix(i64),
+}
+
+impl SddsEpoch {
+    /// Small numbers are years, anything from 10000 on is unix seconds.
+    pub fn from_number(n: i64) -> Result<Self, String> {
+        match n {
+            1970..=9999 => Ok(SddsEpoch::Year(n as i32)),
+            10000.. => Ok(SddsEpoch::Unix(n)),
+            _ => Err(format!(
+                "Expected a year from 1970 or unix seconds, got: {n}"
+            )),
+        }
+    }
+
+    fn start(self) -> Option<DateTime<Utc>> {
+        match self {
+            SddsEpoch::Year(year) => Utc.with_ymd_and_hms(year, 1, 1, 0, 0, 0).single(),
+            SddsEpoch::Unix(seconds) => DateTime::from_timestamp(seconds, 0),
+        }
+    }
+
+    /// The time tag as nanoseconds since the unix epoch.
+    pub fn unix_nanos(self, timetag: u64) -> Option<i128> {
+        let start = self.start()?.timestamp() as i128;
+        Some(start * 1_000_000_000 + timetag as i128 * 1_000_000_000 / TICKS_PER_SEC as i128)
+    }
+
+    /// "2024-05-02T14:31:22.12345678925Z", to the 250ps tick.
+    pub fn format(self, timetag: u64) -> String {
+        let seconds = (timetag / TICKS_PER_SEC) as i64;
+        let Some(time) = self
+            .start()
+            .and_then(|start| start.checked_add_signed(TimeDelta::try_seconds(seconds)?))
+        else {
+            return format_timestamp(timetag);
+        };
+        // 25 tens of picoseconds to the tick
+        let fraction = (timetag % TICKS_PER_SEC) * 25;
+        format!("{}.{fraction:011}Z", time.format("%Y-%m-%dT%H:%M:%S"))
+    }
+}
+
+/// The time tag as a UTC date and time when the epoch is known,
+/// otherwise day of year.
+pub fn format_time_tag(timetag: u64, epoch: Option<SddsEpoch>) -> String {
+    match epoch {
+        Some(epoch) => epoch.format(timetag),
+        None => format_timestamp(timetag),
+    }
+}
+
 pub struct SddsFrameHeader {
     pub frame_sequence_number: u16,
     pub time_tag: u64,
@@ -74,11 +154,266 @@ pub fn time_tag_ext(packet: &[u8]) -> u32 {
         .unwrap_or(0)
 }
 
+/// The samples after the header.
+pub fn data(packet: &[u8]) -> Option<&[u8]> {
+    packet.get(HEADER_BYTES..)
+}
+
+/// Time tag valid, the top bit of the time tag info.
+pub fn ttv(packet: &[u8]) -> bool {
+    packet.get(4).is_some_and(|b| (b & 0x80) != 0)
+}
+
+/// Parity packets carry no samples.
+pub fn is_parity(frame_sequence_number: u16) -> bool {
+    frame_sequence_number.is_multiple_of(32)
+}
+
+/// Ticks between consecutive data packets at sample_rate Hz: the 1024 byte
+/// payload holds 8192 / bits_per_sample samples, half as many when complex.
+pub fn time_tag_increment(sample_rate: f64, bits_per_sample: u8, cx: bool) -> Option<u64> {
+    let bits = bits_per_sample as u64 * if cx { 2 } else { 1 };
+    if bits == 0 || !sample_rate.is_finite() || sample_rate <= 0.0 {
+        return None;
+    }
+    let samples = (DATA_BYTES * 8) as f64 / bits as f64;
+    Some((samples * TICKS_PER_SEC as f64 / sample_rate).round() as u64)
+}
+
+/// Data packets from one sequence number to the next, skipping parity packets.
+/// The sequence wraps at a multiple of 32, so parity lines up across the wrap.
+fn data_packets_between(from: u16, to: u16) -> u64 {
+    let from = from as u64;
+    let to = from + to.wrapping_sub(from as u16) as u64;
+    (to - from) - (to / 32 - from / 32)
+}
+
+/// A time tag that moved by something other than the samples in between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Discontinuity {
+    pub frame_sequence_number: u16,
+    pub before: u64,
+    pub after: u64,
+    pub expected: u64,
+}
+
+impl Discontinuity {
+    pub fn describe(&self, epoch: Option<SddsEpoch>) -> String {
+        let actual = self.after as i128 - self.before as i128;
+        format!(
+            "time tag jumped from {} to {} at frame {} ({actual:+} ticks, expected +{})",
+            format_time_tag(self.before, epoch),
+            format_time_tag(self.after, epoch),
+            self.frame_sequence_number,
+            self.expected
+        )
+    }
+}
+
+/// Checks that time tags advance with the sample count. The step comes from
+/// the configured sample rate, or without one from the stream itself once
+/// two consecutive packets agree on it.
+#[derive(Debug, Default, Clone)]
+pub struct TimeTagCheck {
+    increment: Option<u64>,
+    // Last data packet with a valid time tag
+    last: Option<(u16, u64)>,
+    // Inferred step waiting for a second packet to confirm it
+    candidate: Option<u64>,
+}
+
+impl TimeTagCheck {
+    /// Ticks per data packet, once known.
+    pub fn increment(&self) -> Option<u64> {
+        self.increment
+    }
+
+    pub fn observe(&mut self, packet: &[u8], sample_rate: Option<f64>) -> Option<Discontinuity> {
+        let header = parse_frame_header(packet);
+        let seq = header.frame_sequence_number;
+        if is_parity(seq) {
+            return None;
+        }
+        if !ttv(packet) {
+            // Nothing to compare the next valid tag against
+            self.last = None;
+            return None;
+        }
+        if self.increment.is_none()
+            && let Some(rate) = sample_rate
+        {
+            self.increment = time_tag_increment(rate, bits_per_sample(packet), cx(packet));
+        }
+
+        let last = self.last.replace((seq, header.time_tag));
+        let (last_seq, last_tag) = last?;
+        let packets = data_packets_between(last_seq, seq);
+        if packets == 0 {
+            return None;
+        }
+        let actual = header.time_tag.wrapping_sub(last_tag);
+
+        let Some(increment) = self.increment else {
+            self.infer(actual, packets);
+            return None;
+        };
+        let expected = increment * packets;
+        let tolerance = expected * TIME_TAG_TOLERANCE_PPM / 1_000_000;
+        if actual.abs_diff(expected) <= tolerance {
+            return None;
+        }
+        Some(Discontinuity {
+            frame_sequence_number: seq,
+            before: last_tag,
+            after: header.time_tag,
+            expected,
+        })
+    }
+
+    fn infer(&mut self, actual: u64, packets: u64) {
+        if packets != 1 || actual == 0 || actual >= u64::MAX / 2 {
+            self.candidate = None;
+            return;
+        }
+        match self.candidate {
+            Some(candidate) if candidate == actual => self.increment = Some(actual),
+            _ => self.candidate = Some(actual),
+        }
+    }
+}
+
+/// How long after its time tag each packet arrived, by the kernel's receive
+/// time, for --timetag-offset. That's the latency plus however far apart the
+/// sender's clock and ours are, so it's the changes that tell: a slope is one
+/// clock running fast, a jump is one being set. Each interval has its own
+/// range, the drift is fitted over the whole run.
+#[derive(Debug, Default, Clone)]
+pub struct ClockOffset {
+    // This interval's offsets in nanoseconds: lowest, highest, sum and count
+    range: Option<(i64, i64, i128, u64)>,
+    unstamped: u64,
+    // Offset against receive time across the run, both from the first packet
+    origin: Option<(i128, i64)>,
+    fit: DriftFit,
+    // The last interval's lowest offset, the one least delayed on the way
+    last_min: Option<i64>,
+}
+
+/// Running sums for a least-squares line, x in seconds and y in nanoseconds.
+#[derive(Debug, Default, Clone, Copy)]
+struct DriftFit {
+    n: f64,
+    x: f64,
+    y: f64,
+    xx: f64,
+    xy: f64,
+}
+
+impl ClockOffset {
+    /// A packet and when the kernel received it. Parity packets and
+    /// invalid time tags say nothing about the clock.
+    pub fn observe(&mut self, packet: &[u8], epoch: SddsEpoch, arrival: Option<SystemTime>) {
+        if is_parity(frame_sequence_number(packet)) || !ttv(packet) {
+            return;
+        }
+        let Some(arrival) = arrival
+            .and_then(|arrival| arrival.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_nanos() as i128)
+        else {
+            self.unstamped += 1;
+            return;
+        };
+        let Some(offset) = epoch
+            .unix_nanos(time_tag(packet))
+            .and_then(|tag| i64::try_from(arrival - tag).ok())
+        else {
+            return;
+        };
+
+        self.range = Some(match self.range {
+            None => (offset, offset, offset as i128, 1),
+            Some((min, max, sum, count)) => (
+                min.min(offset),
+                max.max(offset),
+                sum + offset as i128,
+                count + 1,
+            ),
+        });
+        let (first_arrival, first_offset) = *self.origin.get_or_insert((arrival, offset));
+        let x = (arrival - first_arrival) as f64 / 1e9;
+        let y = offset.saturating_sub(first_offset) as f64;
+        let fit = &mut self.fit;
+        fit.n += 1.0;
+        fit.x += x;
+        fit.y += y;
+        fit.xx += x * x;
+        fit.xy += x * y;
+    }
+
+    /// The offset's slope since the first packet, in parts per million:
+    /// positive when our clock runs fast against the sender's.
+    pub fn drift_ppm(&self) -> Option<f64> {
+        let DriftFit { n, x, y, xx, xy } = self.fit;
+        let spread = n * xx - x * x;
+        if n < 2.0 || spread <= f64::EPSILON {
+            return None;
+        }
+        // Nanoseconds a second are parts per billion
+        Some((n * xy - x * y) / spread / 1000.0)
+    }
+
+    /// For the next interval, keeping the fit. A lowest offset that moved
+    /// further than jitter would take it since the last interval is logged
+    /// as a clock step.
+    pub fn next_interval(&mut self) -> Self {
+        let min = self.range.map(|(min, ..)| min);
+        if let (Some(last), Some(min)) = (self.last_min, min)
+            && min.abs_diff(last) > CLOCK_STEP_NANOS
+        {
+            log::warn!(
+                "time tag offset stepped by {} since the last interval: a clock was set",
+                micros(min as i128 - last as i128)
+            );
+        }
+        Self {
+            origin: self.origin,
+            fit: self.fit,
+            last_min: min.or(self.last_min),
+            ..Default::default()
+        }
+    }
+
+    /// "  offset: min 1.2us mean 1.5us max 3.0us  drift: +0.40ppm", for the
+    /// stats line.
+    pub fn format(&self) -> String {
+        let mut s = match self.range {
+            Some((min, max, sum, count)) => format!(
+                "  offset: min {} mean {} max {}",
+                micros(min as i128),
+                micros(sum / count.max(1) as i128),
+                micros(max as i128)
+            ),
+            None => "  offset: -".to_string(),
+        };
+        if self.unstamped > 0 {
+            s.push_str(&format!(" ({} unstamped)", self.unstamped));
+        }
+        if let Some(drift) = self.drift_ppm() {
+            s.push_str(&format!("  drift: {drift:+.2}ppm"));
+        }
+        s
+    }
+}
+
+fn micros(nanos: i128) -> String {
+    format!("{:.1}us", nanos as f64 / 1000.0)
+}
+
 pub fn sddstime(timetag: u64) -> (u32, u32, u32, u32, u64) {
     let mut tt = timetag;
 
-    let nsecs = (tt % 4_000_000_000) / 4;
-    tt /= 4_000_000_000;
+    let nsecs = (tt % TICKS_PER_SEC) / 4;
+    tt /= TICKS_PER_SEC;
 
     let secs = (tt % 60) as u32;
     tt /= 60;
@@ -141,11 +476,20 @@ pub fn bits_per_sample(packet: &[u8]) -> u8 {
 
 pub struct SddsHeader<'a> {
     packet: &'a [u8],
+    epoch: Option<SddsEpoch>,
 }
 
 impl<'a> SddsHeader<'a> {
     pub fn new(packet: &'a [u8]) -> Self {
-        Self { packet }
+        Self {
+            packet,
+            epoch: None,
+        }
+    }
+
+    /// Show the time tag as UTC counted from epoch.
+    pub fn with_epoch(self, epoch: Option<SddsEpoch>) -> Self {
+        Self { epoch, ..self }
     }
 }
 
@@ -161,7 +505,7 @@ impl std::fmt::Display for SddsHeader<'_> {
             f,
             "  {:24}: {:<25} {timetag:064b}",
             "Time Tag (64)",
-            format_timestamp(timetag),
+            format_time_tag(timetag, self.epoch),
         )?;
         writeln!(f, "  {:24}: {:<25} {tt_ext:032b}", "Time Tag Ext (32)", " ")?;
         writeln!(
@@ -239,6 +583,8 @@ impl std::fmt::Display for SddsHeader<'_> {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
 
     #[test]
@@ -266,7 +612,204 @@ mod tests {
         assert_eq!((days, hours, mins, secs, nsecs), (2, 0, 0, 0, 0));
     }
 
+    fn data_packet(seq: u16, time_tag: u64) -> Vec<u8> {
+        let mut packet = vec![0u8; 1080];
+        packet.splice(0..2, [0x80, 16]);
+        packet.splice(2..4, seq.to_be_bytes());
+        packet.splice(4..5, [0x80]);
+        packet.splice(8..16, time_tag.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_time_tag_increment() {
+        // 512 real 16 bit samples at 1 MHz take 512us
+        assert_eq!(time_tag_increment(1e6, 16, false), Some(2_048_000));
+        // 256 complex 16 bit samples
+        assert_eq!(time_tag_increment(1e6, 16, true), Some(1_024_000));
+        assert_eq!(time_tag_increment(1e6, 0, false), None);
+        assert_eq!(time_tag_increment(0.0, 16, false), None);
+    }
+
+    #[test]
+    fn test_data_packets_between() {
+        assert_eq!(data_packets_between(1, 2), 1);
+        assert_eq!(data_packets_between(31, 33), 1);
+        assert_eq!(data_packets_between(65535, 1), 1);
+        assert_eq!(data_packets_between(30, 35), 4);
+    }
+
+    #[test]
+    fn test_time_tag_check_configured() {
+        let step = 2_048_000;
+        let mut check = TimeTagCheck::default();
+        let rate = Some(1e6);
+        assert_eq!(check.observe(&data_packet(30, 1_000_000), rate), None);
+        assert_eq!(
+            check.observe(&data_packet(31, 1_000_000 + step), rate),
+            None
+        );
+        // Parity packets are skipped, and don't count as a step
+        let mut parity = data_packet(32, 0);
+        parity.splice(4..5, [0]);
+        assert_eq!(check.observe(&parity, rate), None);
+        assert_eq!(
+            check.observe(&data_packet(33, 1_000_000 + 2 * step), rate),
+            None
+        );
+        // Lost packets are allowed for
+        assert_eq!(
+            check.observe(&data_packet(36, 1_000_000 + 5 * step), rate),
+            None
+        );
+
+        // The packetizer restarted its clock
+        assert_eq!(
+            check.observe(&data_packet(37, 40), rate),
+            Some(Discontinuity {
+                frame_sequence_number: 37,
+                before: 1_000_000 + 5 * step,
+                after: 40,
+                expected: step,
+            })
+        );
+        assert_eq!(check.observe(&data_packet(38, 40 + step), rate), None);
+
+        // A tag marked invalid isn't checked, nor is the one after it
+        let mut invalid = data_packet(39, 0);
+        invalid.splice(4..5, [0]);
+        assert_eq!(check.observe(&invalid, rate), None);
+        assert_eq!(check.observe(&data_packet(40, 7), rate), None);
+    }
+
+    #[test]
+    fn test_time_tag_check_inferred() {
+        let mut check = TimeTagCheck::default();
+        let rate = None;
+        for (seq, tag) in [(1, 100), (2, 200), (3, 300)] {
+            assert_eq!(check.observe(&data_packet(seq, tag), rate), None);
+        }
+        assert_eq!(check.increment(), Some(100));
+        assert!(check.observe(&data_packet(4, 450), rate).is_some());
+    }
+
+    #[test]
+    fn test_discontinuity_display() {
+        let jump = Discontinuity {
+            frame_sequence_number: 7,
+            before: TICKS_PER_SEC,
+            after: 0,
+            expected: 400,
+        };
+        assert_eq!(
+            jump.describe(None),
+            "time tag jumped from 001:00:00:01:000000000 to 001:00:00:00:000000000 at frame 7 (-4000000000 ticks, expected +400)"
+        );
+    }
+
+    #[test]
+    fn test_epoch_from_number() {
+        assert_eq!(SddsEpoch::from_number(2024), Ok(SddsEpoch::Year(2024)));
+        assert_eq!(
+            SddsEpoch::from_number(1_704_067_200),
+            Ok(SddsEpoch::Unix(1_704_067_200))
+        );
+        assert!(SddsEpoch::from_number(99).is_err());
+        assert!(SddsEpoch::from_number(-1).is_err());
+    }
+
+    #[test]
+    fn test_epoch_format() {
+        // Day 123 14:31:22.123456789 and one 250ps tick
+        let tag = (122 * 86_400 + 14 * 3600 + 31 * 60 + 22) * TICKS_PER_SEC + 123_456_789 * 4 + 1;
+        assert_eq!(
+            SddsEpoch::Year(2024).format(tag),
+            "2024-05-02T14:31:22.12345678925Z"
+        );
+        // Not a leap year, so a day later
+        assert_eq!(
+            SddsEpoch::Year(2023).format(tag),
+            "2023-05-03T14:31:22.12345678925Z"
+        );
+        // 2024-01-01T00:00:00Z
+        assert_eq!(
+            SddsEpoch::Unix(1_704_067_200).format(tag),
+            SddsEpoch::Year(2024).format(tag)
+        );
+
+        // Day 366 of a leap year
+        let last_day = 365 * 86_400 * TICKS_PER_SEC + 3;
+        assert_eq!(
+            SddsEpoch::Year(2024).format(last_day),
+            "2024-12-31T00:00:00.00000000075Z"
+        );
+        assert_eq!(
+            SddsEpoch::Year(2023).format(last_day),
+            "2024-01-01T00:00:00.00000000075Z"
+        );
+
+        assert_eq!(format_time_tag(tag, None), "123:14:31:22:123456789");
+    }
+
+    #[test]
+    fn test_epoch_unix_nanos() {
+        let tag = 90 * TICKS_PER_SEC + 4 * 1_500 + 3;
+        assert_eq!(
+            SddsEpoch::Year(2024).unix_nanos(tag),
+            Some(1_704_067_290_000_001_500)
+        );
+        assert_eq!(
+            SddsEpoch::Unix(10_000).unix_nanos(tag),
+            Some(10_090_000_001_500)
+        );
+    }
+
+    #[test]
+    fn test_clock_offset() {
+        let epoch = SddsEpoch::Unix(1_700_000_000);
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut offset = ClockOffset::default();
+        assert_eq!(offset.format(), "  offset: -");
+
+        // A second apart, arriving a millisecond late and a microsecond
+        // later each second
+        for second in 0..3u64 {
+            let packet = data_packet(second as u16 + 1, second * TICKS_PER_SEC);
+            let late = Duration::from_micros(1000 + second);
+            offset.observe(
+                &packet,
+                epoch,
+                Some(start + Duration::from_secs(second) + late),
+            );
+        }
+        // Parity packets, invalid time tags and no receive time don't count
+        offset.observe(&data_packet(32, 0), epoch, Some(start));
+        let mut invalid = data_packet(4, 0);
+        invalid.splice(4..5, [0]);
+        offset.observe(&invalid, epoch, Some(start));
+        offset.observe(&data_packet(5, 0), epoch, None);
+        assert_eq!(
+            offset.format(),
+            "  offset: min 1000.0us mean 1001.0us max 1002.0us (1 unstamped)  drift: +1.00ppm"
+        );
+
+        // The drift is for the run, the range for the interval
+        let mut offset = offset.next_interval();
+        assert_eq!(offset.last_min, Some(1_000_000));
+        assert_eq!(offset.format(), "  offset: -  drift: +1.00ppm");
+        offset.observe(
+            &data_packet(6, 3 * TICKS_PER_SEC),
+            epoch,
+            Some(start + Duration::from_micros(3_001_003)),
+        );
+        assert_eq!(
+            offset.format(),
+            "  offset: min 1003.0us mean 1003.0us max 1003.0us  drift: +1.00ppm"
+        );
+    }
+
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_format_identifier() {
         let packet = vec![0b10110101, 0b11010111];
         assert_eq!(sf(&packet), true);
@@ -0,0 +1,265 @@
+/// Who is sending to the group, for watching a discovery group for a new
+/// device starting up. A sender gets an info line with its first packet and
+/// another once it goes quiet, and the stats line counts them. Only the
+/// address and when it was last heard are kept for each, so it's cheap
+/// enough to run whenever statistics are. With --resolve, senders are
+/// named in the log once their name is known.
+use std::collections::HashMap;
+use std::net::SocketAddrV4;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::resolve::HostNames;
+
+/// Senders remembered before the longest quiet one is forgotten for a new one.
+pub const MAX_SENDERS: usize = 4096;
+
+// Quiet senders are looked for this often, not with every batch
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy)]
+struct Heard {
+    last: Instant,
+    silent: bool,
+    // Its host name has been logged
+    named: bool,
+}
+
+/// The distinct source addresses seen since the start, up to a bound.
+#[derive(Debug)]
+pub struct Senders {
+    heard: HashMap<SocketAddrV4, Heard>,
+    capacity: usize,
+    /// Quiet this long and a sender is reported silent
+    timeout: Duration,
+    // The packet before's source, already recorded for this batch
+    last: Option<(SocketAddrV4, Instant)>,
+    last_check: Option<Instant>,
+    new_in_interval: u64,
+    forgotten: u64,
+    names: Option<Arc<HostNames>>,
+}
+
+impl Senders {
+    pub fn new(timeout: Duration, capacity: usize) -> Self {
+        Self {
+            heard: HashMap::new(),
+            capacity,
+            timeout,
+            last: None,
+            last_check: None,
+            new_in_interval: 0,
+            forgotten: 0,
+            names: None,
+        }
+    }
+
+    /// Name senders in the log from names, once it has looked them up.
+    pub fn with_names(mut self, names: Arc<HostNames>) -> Self {
+        self.names = names.is_started().then_some(names);
+        self
+    }
+
+    /// A packet from source, in a batch taken at now. Arrival is the
+    /// packet's receive time, for the first packet's log line.
+    pub fn observe(&mut self, source: SocketAddrV4, now: Instant, arrival: Option<SystemTime>) {
+        // A batch is mostly from the same sender
+        if self.last == Some((source, now)) {
+            return;
+        }
+        self.last = Some((source, now));
+
+        if let Some(heard) = self.heard.get_mut(&source) {
+            if heard.silent {
+                log::info!(
+                    "sender {} is back after {:.1}s",
+                    display(&self.names, source),
+                    now.duration_since(heard.last).as_secs_f64()
+                );
+                heard.silent = false;
+            }
+            heard.last = now;
+            return;
+        }
+
+        if self.heard.len() >= self.capacity {
+            self.forget_quietest();
+        }
+        // Asks for the name too, logged once it's known if it isn't yet
+        let name = self
+            .names
+            .as_ref()
+            .and_then(|names| names.name(*source.ip()));
+        let shown = match &name {
+            Some(name) => format!("{name}:{} ({})", source.port(), source.ip()),
+            None => source.to_string(),
+        };
+        let arrival: chrono::DateTime<chrono::Utc> = arrival.unwrap_or_else(SystemTime::now).into();
+        log::info!(
+            "new sender {shown}, first packet at {}",
+            arrival.format("%Y-%m-%dT%H:%M:%S%.6fZ")
+        );
+        let named = name.is_some() || self.names.is_none();
+        self.heard.insert(
+            source,
+            Heard {
+                last: now,
+                silent: false,
+                named,
+            },
+        );
+        self.new_in_interval += 1;
+    }
+
+    /// Log the senders that went quiet for the timeout since the last look,
+    /// once each until they're heard again.
+    pub fn check_silent(&mut self, now: Instant) {
+        if self
+            .last_check
+            .is_some_and(|last| now.duration_since(last) < CHECK_EVERY)
+        {
+            return;
+        }
+        self.last_check = Some(now);
+        for (source, heard) in self.heard.iter_mut() {
+            let name = match (&self.names, heard.named) {
+                (Some(names), false) => names.name(*source.ip()),
+                _ => None,
+            };
+            if let Some(name) = &name {
+                log::info!("sender {source} is {name}");
+                heard.named = true;
+            }
+            if !heard.silent && now.duration_since(heard.last) >= self.timeout {
+                heard.silent = true;
+                log::info!(
+                    "sender {} silent for {:.0}s",
+                    display(&self.names, *source),
+                    self.timeout.as_secs_f64()
+                );
+            }
+        }
+    }
+
+    /// "  sources: 3 (+2 new)", for the stats line. Empty until there's a
+    /// sender, as files and stdin have none.
+    pub fn format(&mut self) -> String {
+        if self.heard.is_empty() {
+            return String::new();
+        }
+        let mut s = format!("  sources: {}", self.heard.len());
+        match std::mem::take(&mut self.new_in_interval) {
+            0 => {}
+            new => s.push_str(&format!(" (+{new} new)")),
+        }
+        s
+    }
+
+    fn forget_quietest(&mut self) {
+        let quietest = self
+            .heard
+            .iter()
+            .min_by_key(|(_, heard)| heard.last)
+            .map(|(source, _)| *source);
+        if let Some(source) = quietest {
+            self.heard.remove(&source);
+        }
+        if self.forgotten == 0 {
+            log::warn!(
+                "more than {} senders, forgetting the longest quiet ones: one heard again is counted as new",
+                self.capacity
+            );
+        }
+        self.forgotten += 1;
+    }
+}
+
+/// source by name once names knows it.
+fn display(names: &Option<Arc<HostNames>>, source: SocketAddrV4) -> String {
+    match names {
+        Some(names) => names.format(source),
+        None => source.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn source(host: u8) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, host), 5000)
+    }
+
+    #[test]
+    fn test_counts_new_senders_each_interval() {
+        let start = Instant::now();
+        let mut senders = Senders::new(Duration::from_secs(10), MAX_SENDERS);
+        assert_eq!(senders.format(), "");
+
+        senders.observe(source(1), start, None);
+        senders.observe(source(1), start, None);
+        senders.observe(source(2), start, None);
+        assert_eq!(senders.format(), "  sources: 2 (+2 new)");
+
+        senders.observe(source(1), start + Duration::from_secs(1), None);
+        assert_eq!(senders.format(), "  sources: 2");
+        senders.observe(source(3), start + Duration::from_secs(2), None);
+        assert_eq!(senders.format(), "  sources: 3 (+1 new)");
+    }
+
+    #[test]
+    fn test_silent_then_back() {
+        let start = Instant::now();
+        let mut senders = Senders::new(Duration::from_secs(5), MAX_SENDERS);
+        senders.observe(source(1), start, None);
+        senders.observe(source(2), start, None);
+
+        senders.observe(source(2), start + Duration::from_millis(1500), None);
+        senders.check_silent(start + Duration::from_secs(6));
+        let silent = |senders: &Senders, host| senders.heard.get(&source(host)).map(|h| h.silent);
+        assert_eq!(silent(&senders, 1), Some(true));
+        assert_eq!(silent(&senders, 2), Some(false));
+
+        // Not looked at again within a second
+        senders.check_silent(start + Duration::from_millis(6800));
+        assert_eq!(silent(&senders, 2), Some(false));
+        senders.check_silent(start + Duration::from_secs(7));
+        assert_eq!(silent(&senders, 2), Some(true));
+
+        senders.observe(source(1), start + Duration::from_secs(11), None);
+        assert_eq!(silent(&senders, 1), Some(false));
+        // Back isn't new
+        assert_eq!(senders.format(), "  sources: 2 (+2 new)");
+    }
+
+    #[test]
+    fn test_named_once_known() {
+        let start = Instant::now();
+        let names = HostNames::with_known(&[(Ipv4Addr::new(10, 0, 0, 1), "recv1.lab")]);
+        let mut senders = Senders::new(Duration::from_secs(10), MAX_SENDERS).with_names(names);
+        senders.observe(source(1), start, None);
+        senders.observe(source(2), start, None);
+        senders.check_silent(start);
+        let named = |senders: &Senders, host| senders.heard.get(&source(host)).map(|h| h.named);
+        assert_eq!(named(&senders, 1), Some(true));
+        assert_eq!(named(&senders, 2), Some(false));
+    }
+
+    #[test]
+    fn test_forgets_the_quietest_when_full() {
+        let start = Instant::now();
+        let mut senders = Senders::new(Duration::from_secs(10), 2);
+        senders.observe(source(1), start, None);
+        senders.observe(source(2), start + Duration::from_secs(1), None);
+        senders.observe(source(1), start + Duration::from_secs(2), None);
+        senders.observe(source(3), start + Duration::from_secs(3), None);
+
+        assert!(senders.heard.contains_key(&source(1)));
+        assert!(!senders.heard.contains_key(&source(2)));
+        assert!(senders.heard.contains_key(&source(3)));
+        assert_eq!(senders.forgotten, 1);
+        assert_eq!(senders.format(), "  sources: 2 (+3 new)");
+    }
+}
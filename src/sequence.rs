@@ -0,0 +1,225 @@
+/// Sequence numbers for any payload, so two mnc instances can measure a link.
+/// The sender writes a u32 big endian counter into each packet (`--stamp-seq`),
+/// either prepended or over the bytes at an offset, and the receiver checks it
+/// (`--check-seq`) for loss, reordering and duplicates.
+use std::collections::BTreeSet;
+
+use crate::packet::Packet;
+
+pub const SEQ_BYTES: usize = 4;
+
+// Gaps still waiting for a late packet to fill them. Older ones are given up on.
+const MAX_MISSING: usize = 4096;
+
+/// Where the sequence number goes in the datagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeqField {
+    /// Added in front of the payload, and removed again by the receiver
+    Prepend,
+    /// Overwrites the payload bytes at this offset
+    At(usize),
+}
+
+impl SeqField {
+    /// No offset means prepend.
+    pub fn from_offset(offset: Option<usize>) -> Self {
+        offset.map_or(SeqField::Prepend, SeqField::At)
+    }
+
+    fn offset(self) -> usize {
+        match self {
+            SeqField::Prepend => 0,
+            SeqField::At(offset) => offset,
+        }
+    }
+}
+
+/// Copy payload into packet with seq written as field says.
+/// False when the payload is too short to hold it at the offset.
+pub fn stamp_into(packet: &mut Packet, payload: &[u8], field: SeqField, seq: u32) -> bool {
+    let prefix = match field {
+        SeqField::Prepend => SEQ_BYTES,
+        SeqField::At(_) => 0,
+    };
+    let length = prefix + payload.len();
+    packet.ensure_capacity(length);
+    packet.set_length(length);
+
+    let Some(data) = packet.data_mut().get_mut(..length) else {
+        return false;
+    };
+    if let Some(rest) = data.get_mut(prefix..) {
+        rest.copy_from_slice(payload);
+    }
+    let offset = field.offset();
+    match data.get_mut(offset..offset + SEQ_BYTES) {
+        Some(bytes) => {
+            bytes.copy_from_slice(&seq.to_be_bytes());
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn read(packet: &[u8], field: SeqField) -> Option<u32> {
+    let offset = field.offset();
+    let bytes: [u8; SEQ_BYTES] = packet.get(offset..offset + SEQ_BYTES)?.try_into().ok()?;
+    Some(u32::from_be_bytes(bytes))
+}
+
+/// What one stats interval saw.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SeqCounts {
+    pub lost: u64,
+    pub reordered: u64,
+    pub duplicates: u64,
+    pub unstamped: u64,
+}
+
+/// Follows the sequence across intervals; counts are taken per interval.
+#[derive(Debug, Default)]
+pub struct SeqTracker {
+    next: Option<u32>,
+    missing: BTreeSet<u32>,
+    counts: SeqCounts,
+}
+
+impl SeqTracker {
+    /// Returns the number expected and the one that came instead when seq
+    /// skipped ahead.
+    pub fn observe(&mut self, seq: Option<u32>) -> Option<(u32, u32)> {
+        let Some(seq) = seq else {
+            self.counts.unstamped += 1;
+            return None;
+        };
+        let Some(next) = self.next else {
+            self.next = Some(seq.wrapping_add(1));
+            return None;
+        };
+
+        // Signed distance, so the counter may wrap
+        let ahead = seq.wrapping_sub(next) as i32;
+        if ahead >= 0 {
+            for gap in 0..ahead as u32 {
+                self.missing.insert(next.wrapping_add(gap));
+            }
+            while self.missing.len() > MAX_MISSING {
+                self.missing.pop_first();
+            }
+            self.counts.lost += ahead as u64;
+            self.next = Some(seq.wrapping_add(1));
+            return (ahead > 0).then_some((next, seq));
+        } else if self.missing.remove(&seq) {
+            // Counted as lost when the gap opened, which may have been in an
+            // earlier interval, so only this interval's count can be undone
+            self.counts.reordered += 1;
+            self.counts.lost = self.counts.lost.saturating_sub(1);
+        } else {
+            self.counts.duplicates += 1;
+        }
+        None
+    }
+
+    /// Counts since the last call.
+    pub fn take_counts(&mut self) -> SeqCounts {
+        std::mem::take(&mut self.counts)
+    }
+}
+
+impl SeqCounts {
+    /// "  seq lost: N  reordered: R  duplicates: D", for the stats line.
+    pub fn format(&self) -> String {
+        let mut s = format!(
+            "  seq lost: {}  reordered: {}  duplicates: {}",
+            self.lost, self.reordered, self.duplicates
+        );
+        if self.unstamped > 0 {
+            s.push_str(&format!("  unstamped: {}", self.unstamped));
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(seqs: &[u32]) -> SeqCounts {
+        let mut tracker = SeqTracker::default();
+        for seq in seqs {
+            tracker.observe(Some(*seq));
+        }
+        tracker.take_counts()
+    }
+
+    #[test]
+    fn test_stamp_into() {
+        let mut packet = Packet::default();
+        assert!(stamp_into(&mut packet, b"payload", SeqField::Prepend, 258));
+        assert_eq!(&packet[..], b"\0\0\x01\x02payload");
+        assert_eq!(read(&packet, SeqField::Prepend), Some(258));
+
+        assert!(stamp_into(&mut packet, b"payload", SeqField::At(2), 7));
+        assert_eq!(&packet[..], b"pa\0\0\0\x07d");
+        assert_eq!(read(&packet, SeqField::At(2)), Some(7));
+
+        // Sent as is when it doesn't fit
+        assert!(!stamp_into(&mut packet, b"ab", SeqField::At(0), 7));
+        assert_eq!(&packet[..], b"ab");
+        assert_eq!(read(&packet, SeqField::At(0)), None);
+    }
+
+    #[test]
+    fn test_loss_reorder_duplicates() {
+        assert_eq!(track(&[1, 2, 3, 4]), SeqCounts::default());
+        assert_eq!(
+            track(&[1, 2, 5, 6]),
+            SeqCounts {
+                lost: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            track(&[1, 3, 2, 4]),
+            SeqCounts {
+                reordered: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            track(&[1, 2, 2, 3, 1]),
+            SeqCounts {
+                duplicates: 2,
+                ..Default::default()
+            }
+        );
+        // Wrapping is just the next number
+        assert_eq!(track(&[u32::MAX - 1, u32::MAX, 0, 1]), SeqCounts::default());
+    }
+
+    #[test]
+    fn test_observe_reports_gaps() {
+        let mut tracker = SeqTracker::default();
+        assert_eq!(tracker.observe(Some(1)), None);
+        assert_eq!(tracker.observe(Some(2)), None);
+        assert_eq!(tracker.observe(Some(5)), Some((3, 5)));
+        // Filling the gap late isn't a new one
+        assert_eq!(tracker.observe(Some(3)), None);
+        assert_eq!(tracker.observe(None), None);
+        assert_eq!(tracker.observe(Some(u32::MAX)), None);
+    }
+
+    #[test]
+    fn test_format() {
+        let counts = SeqCounts {
+            lost: 3,
+            reordered: 1,
+            duplicates: 0,
+            unstamped: 2,
+        };
+        assert_eq!(
+            counts.format(),
+            "  seq lost: 3  reordered: 1  duplicates: 0  unstamped: 2"
+        );
+    }
+}
@@ -0,0 +1,1242 @@
+/// Checking the options against each other, and what they come to: the
+/// direction, groups, formats and rates the pipeline is built with.
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use clap::{CommandFactory, ValueEnum, error::ErrorKind};
+
+use crate::{
+    banner,
+    cli::{Args, GroupSpec, LogTarget, Ports},
+    error,
+    extension::{self, Chosen},
+    filter::PacketFilter,
+    multicast::{self, RECV_BUFFER_BYTES},
+    packet::PacketType,
+    preflight,
+    reader::{DEFAULT_MAX_LINE_BYTES, InputFormat, InputFraming, LineLimit},
+    reorder::SeqSource,
+    sink::{Framing, OutputFormat, SplitBy},
+    txtime,
+};
+
+/// What the options come to once checked, for the pipeline to be built from.
+pub struct Setup {
+    /// The first group's [eth:]mgroup
+    pub iface: Option<String>,
+    pub mgroup: String,
+    pub mode: Mode,
+    /// Every group received, with each one's packet type
+    pub groups: Vec<GroupSpec>,
+    pub group_types: Vec<PacketType>,
+    /// Each -o, with the layout given or picked by its extension
+    pub output_formats: Vec<(String, Chosen<OutputFormat>)>,
+    pub input_format: Option<Chosen<InputFormat>>,
+    pub input_framing: InputFraming,
+    pub writes_stdout: bool,
+    pub sigmf: bool,
+    pub filters: Vec<PacketFilter>,
+    /// --pps at --speed, for whichever of the qdisc, the template, the
+    /// reader or the writer paces the run
+    pub txtime: Option<u64>,
+    pub template_pps: Option<u64>,
+    pub input_pps: Option<u64>,
+    pub writer_pps: Option<u64>,
+    pub expect_resync: Option<SeqSource>,
+    pub reorder: Option<(SeqSource, usize)>,
+    /// -c, or what --legacy-verbose makes of -v
+    pub max_count: u64,
+}
+
+// A usage error, reported the way clap reports its own.
+fn usage(kind: ErrorKind, message: impl std::fmt::Display) -> clap::Error {
+    Args::command().error(kind, message)
+}
+
+/// Check the options against each other, after any config file has been
+/// applied, and work out the pipeline they ask for. A lone group's own port
+/// and type become -p's and -t's.
+pub fn validate(args: &mut Args) -> Result<Setup, clap::Error> {
+    let Some(first_group) = args.mgroup.clone() else {
+        return Err(usage(
+            ErrorKind::MissingRequiredArgument,
+            "no multicast group on the command line or in the config file",
+        ));
+    };
+    // A lone group's own port and type are just -p and -t
+    if args.more_groups.is_empty() {
+        if let Some(port) = first_group.port {
+            args.port = Ports(vec![port]);
+        }
+        if let Some(packet_type) = first_group.packet_type {
+            args.packet_type = packet_type;
+        }
+    }
+    let GroupSpec { iface, mgroup, .. } = first_group.clone();
+
+    let mode = resolve_mode(args, iface.clone(), mgroup.clone())
+        .map_err(|e| usage(ErrorKind::ArgumentConflict, e))?;
+    let groups =
+        all_groups(&first_group, args, &mode).map_err(|e| usage(ErrorKind::ArgumentConflict, e))?;
+    // Each group's packet type, -t's where it has none of its own
+    let group_types: Vec<PacketType> = groups
+        .iter()
+        .map(|group| group.packet_type.unwrap_or(args.packet_type))
+        .collect();
+    let several_groups = groups.len() > 1;
+    let mixed_types = group_types.iter().any(|&t| t != args.packet_type);
+
+    // A file's extension picks its layout unless --output-format or
+    // --input-format does
+    let output_formats: Vec<(String, Chosen<OutputFormat>)> = args
+        .output
+        .iter()
+        .map(|output| {
+            let chosen = extension::output_format(output, args.output_format, args.packet_type);
+            (output.clone(), chosen)
+        })
+        .collect();
+    let input_format = args
+        .input
+        .as_deref()
+        .map(|input| extension::input_format(input, args.input_format, args.chunk));
+    let input_framing = InputFraming::new(
+        input_format
+            .as_ref()
+            .map_or(args.input_format, Chosen::format),
+        args.packet_type,
+        args.chunk,
+    )
+    .map_err(|e| usage(ErrorKind::ArgumentConflict, e))?;
+    // Not clap's requires, as -i may come from a config file
+    if args.repeat.is_some() {
+        let problem = match args.input.as_deref() {
+            Some("-") => Some("--loop can't read stdin again, give -i a file"),
+            None => Some("--loop needs -i"),
+            Some(_) => None,
+        };
+        if let Some(problem) = problem {
+            return Err(usage(ErrorKind::ArgumentConflict, problem));
+        }
+    }
+    let text_output = std::iter::once(args.output_format)
+        .chain(output_formats.iter().map(|(_, chosen)| chosen.format()))
+        .any(|format| {
+            matches!(
+                Framing::new(format, args.packet_type, None),
+                Framing::Text { .. }
+            )
+        });
+    let text_input = matches!(input_framing, InputFraming::Lines(_));
+    if (args.max_line_length.is_some() || args.split_long_lines) && !text_input {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--max-line-length and --split-long-lines only apply to text input",
+        ));
+    }
+    if args.lines_per_packet.is_some() && !text_input {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--lines-per-packet only applies to text input",
+        ));
+    }
+    let input_framing = input_framing.with_line_limit(LineLimit {
+        max: args
+            .max_line_length
+            .map_or(DEFAULT_MAX_LINE_BYTES, |max| max as usize),
+        split: args.split_long_lines,
+        per_packet: args.lines_per_packet.map_or(1, |lines| lines as usize),
+        max_latency: args.max_latency.map(Duration::from_millis),
+    });
+    if args.no_append_newline && !text_output {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--no-append-newline only applies to text output",
+        ));
+    }
+    if args.timestamps.is_some() && !text_output {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--timestamps only applies to text output",
+        ));
+    }
+
+    if args.sample_rate.is_some()
+        && !group_types
+            .iter()
+            .any(|t| matches!(t, PacketType::Sdds | PacketType::Vita49))
+    {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--sample-rate only applies to -t sdds and -t vita49",
+        ));
+    }
+    if args.sdds_epoch.is_some() && !group_types.contains(&PacketType::Sdds) {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--sdds-epoch only applies to -t sdds",
+        ));
+    }
+    // A config file can set one without the other
+    if args.timetag_offset && args.sdds_epoch.is_none() {
+        return Err(usage(
+            ErrorKind::MissingRequiredArgument,
+            "--timetag-offset needs --sdds-epoch to put the time tags on the clock",
+        ));
+    }
+    let filters = packet_filters(args);
+    let sdds_only = args.drop_parity || args.only_parity || args.filter_sdds_mode.is_some();
+    if sdds_only && args.packet_type != PacketType::Sdds {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--drop-parity, --only-parity and --filter-sdds-mode only apply to -t sdds",
+        ));
+    }
+    if !args.filter_stream_id.is_empty() && args.packet_type != PacketType::Vita49 {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--filter-stream-id only applies to -t vita49",
+        ));
+    }
+    // Packets written to stdout get it to themselves
+    let writes_stdout = args.output.iter().any(|output| output == "-");
+    if writes_stdout && args.log == LogTarget::Stdout {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--log stdout would mix log lines into the packets written to -o -",
+        ));
+    }
+
+    let sigmf = args.output_format == Some(OutputFormat::Sigmf)
+        || output_formats
+            .iter()
+            .any(|(_, chosen)| chosen.format() == Some(OutputFormat::Sigmf));
+    if args.sandbox && sigmf {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--sandbox can't write --output-format sigmf, its metadata file is created on exit",
+        ));
+    }
+    if args.sandbox && !cfg!(target_os = "linux") {
+        return Err(usage(ErrorKind::InvalidValue, "--sandbox needs Linux"));
+    }
+    if sigmf && !matches!(args.packet_type, PacketType::Sdds | PacketType::Vita49) {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--output-format sigmf only applies to -t sdds and -t vita49",
+        ));
+    }
+    if args.output_format == Some(OutputFormat::Sigmf) && (writes_stdout || args.output.is_empty())
+    {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--output-format sigmf needs -o with a file name",
+        ));
+    }
+    if args.sigmf_datatype.is_some() && !sigmf {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--sigmf-datatype only applies to --output-format sigmf",
+        ));
+    }
+    if let Some(split_by) = args.split_by {
+        let problem = if split_by == SplitBy::Source && !mode.receive {
+            Some("--split-by source only applies when receiving from a group")
+        } else if split_by == SplitBy::StreamId && args.packet_type != PacketType::Vita49 {
+            Some("--split-by stream-id only applies to -t vita49")
+        } else if sigmf {
+            Some("--split-by can't split SigMF recordings")
+        } else if args.output.iter().all(|output| output == "-") {
+            Some("--split-by needs -o with a file name")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            return Err(usage(ErrorKind::ArgumentConflict, problem));
+        }
+    }
+    if let Some(conversion) = args.convert
+        && args.packet_type != conversion.input_type()
+    {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            format!(
+                "--convert {conversion} takes -t {}",
+                conversion.input_type()
+            ),
+        ));
+    }
+    if args.convert.is_some() && sigmf {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--convert can't write --output-format sigmf, the samples are no longer in packets it can read",
+        ));
+    }
+    if args.mark_gaps {
+        let unmarked = output_formats.iter().any(|(_, chosen)| {
+            let format = chosen.format();
+            format != Some(OutputFormat::Sigmf)
+                && Framing::new(format, args.packet_type, None) != Framing::LengthPrefixed
+        });
+        let problem = if !matches!(args.packet_type, PacketType::Sdds | PacketType::Vita49) {
+            Some("--mark-gaps only applies to -t sdds and -t vita49")
+        } else if args.output.is_empty() {
+            Some("--mark-gaps needs -o")
+        } else if unmarked {
+            Some("--mark-gaps needs binary or SigMF output")
+        } else if args.split_by.is_some() {
+            Some("--mark-gaps can't mark files split with --split-by")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            return Err(usage(ErrorKind::ArgumentConflict, problem));
+        }
+    }
+    if args.vita49_context_gap.is_some() && !group_types.contains(&PacketType::Vita49) {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--vita49-context-gap only applies to -t vita49",
+        ));
+    }
+    if several_groups {
+        let problem = if args.reorder.is_some()
+            || args.expect_resync
+            || args.mark_gaps
+            || args.check_seq.is_some()
+            || args.verify_template.is_some()
+        {
+            Some(
+                "--reorder, --expect-resync, --mark-gaps, --check-seq and --verify-template follow one stream, not several groups",
+            )
+        } else if args.convert.is_some() || sigmf {
+            Some("--convert and --output-format sigmf take one stream, not several groups")
+        } else if mixed_types
+            && (sdds_only
+                || !args.filter_stream_id.is_empty()
+                || args.split_by == Some(SplitBy::StreamId))
+        {
+            Some(
+                "--drop-parity, --only-parity, --filter-sdds-mode, --filter-stream-id and --split-by stream-id read every packet as -t, give the groups -t's type",
+            )
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            return Err(usage(ErrorKind::ArgumentConflict, problem));
+        }
+    }
+
+    let txtime = match (args.txtime, args.pps) {
+        (true, Some(_)) if mode.transmit.is_none() => {
+            return Err(usage(
+                ErrorKind::ArgumentConflict,
+                "--txtime only applies when sending to a group",
+            ));
+        }
+        (true, Some(_)) if args.rate.is_some() => {
+            return Err(usage(
+                ErrorKind::ArgumentConflict,
+                "--txtime paces by --pps and can't be combined with --rate",
+            ));
+        }
+        (true, Some(pps)) => Some(scaled_pps(pps, args.speed.unwrap_or(1.0))),
+        (true, None) => {
+            return Err(usage(
+                ErrorKind::MissingRequiredArgument,
+                "--txtime needs --pps",
+            ));
+        }
+        (false, _) => None,
+    };
+
+    // --template paces itself by --pps, unless --txtime has the qdisc do it
+    let template_pps = args
+        .pps
+        .filter(|_| args.template.is_some() && txtime.is_none())
+        .map(|pps| scaled_pps(pps, args.speed.unwrap_or(1.0)));
+    // Read from a file or stdin, the reader waits on --pps, so it isn't done
+    // until the last packet is due
+    let input_pps = args
+        .pps
+        .filter(|_| !mode.receive && args.template.is_none() && txtime.is_none())
+        .map(|pps| scaled_pps(pps, args.speed.unwrap_or(1.0)));
+    // Forwarded, --pps paces the writer, from where --control rate can change it
+    let writer_pps = args
+        .pps
+        .filter(|_| mode.receive && args.template.is_none() && txtime.is_none())
+        .map(|pps| scaled_pps(pps, args.speed.unwrap_or(1.0)));
+    if args.pps_burst.is_some() && input_pps.is_none() && writer_pps.is_none() {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--pps-burst only applies to --pps without --txtime or --template",
+        ));
+    }
+    let timed_input = matches!(input_framing, InputFraming::JsonLines | InputFraming::Timed);
+    if args.speed.is_some() && !timed_input && args.pps.is_none() {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--speed only applies to --input-format jsonl or timed and --pps",
+        ));
+    }
+    if args.speed == Some(0.0) && args.pps.is_some() {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--speed 0 only applies to timed input, --pps needs a speed above 0",
+        ));
+    }
+
+    if args.reply_group.is_some() && !args.ping && !args.echo {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--reply-group only applies to --ping and --echo",
+        ));
+    }
+    if args.stamp.is_some() && mode.transmit.is_none() {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--stamp only applies when sending to a group",
+        ));
+    }
+    if args.stamp_seq.is_some() && mode.transmit.is_none() {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--stamp-seq only applies when sending to a group",
+        ));
+    }
+    if args.check_seq.is_some() && !mode.receive {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--check-seq only applies when receiving from a group",
+        ));
+    }
+    if let Some(template) = &args.verify_template {
+        let problem = if !mode.receive {
+            Some("--verify-template only applies when receiving from a group")
+        } else if !template.has_seq() {
+            Some("--verify-template needs the {seq} the sender's --template numbers packets with")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            return Err(usage(ErrorKind::ArgumentConflict, problem));
+        }
+    }
+    if args.max_restarts.is_some() && !(mode.receive && args.input.is_none()) {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--max-restarts only applies when receiving from a group; file and stdin input stop at the first error",
+        ));
+    }
+    if (args.max_packet.is_some() || args.drop_truncated) && !(mode.receive && args.input.is_none())
+    {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--max-packet and --drop-truncated only apply when receiving from a group",
+        ));
+    }
+    if args.batch_stats && !(mode.receive && args.input.is_none()) {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--batch-stats counts recvmmsg calls, so it only applies when receiving from a group",
+        ));
+    }
+    if args.resolve && args.sandbox {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--resolve can't look names up under --sandbox",
+        ));
+    }
+    if args.max_restarts.is_some() && args.sandbox {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--max-restarts can't reopen sockets under --sandbox",
+        ));
+    }
+    if args.timetag_offset && !(mode.receive && args.input.is_none()) {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--timetag-offset needs the kernel's receive times, so only applies when receiving from a group",
+        ));
+    }
+    if args.measure_latency.is_some() && !mode.receive {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--measure-latency only applies when receiving from a group",
+        ));
+    }
+    if args.fec.is_some() && mode.transmit.is_none() {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--fec only applies when sending to a group",
+        ));
+    }
+    if !args.patch.is_empty() && mode.transmit.is_none() {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--patch only applies when sending to a group",
+        ));
+    }
+    if args.prepend.is_some() && mode.transmit.is_none() {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--prepend only applies when sending to a group",
+        ));
+    }
+    if args.loop_guard.is_some() && mode.transmit.is_none() {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--loop-guard marks the packets sent, it only applies when sending to a group",
+        ));
+    }
+    if !args.allow_loop
+        && let Some(problem) = groups
+            .iter()
+            .find_map(|group| self_loop(&mode, group.iface.as_deref(), &group.mgroup))
+    {
+        return Err(usage(ErrorKind::ArgumentConflict, problem));
+    }
+    if args.dejitter.is_some() && !mode.receive {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--dejitter only applies when receiving from a group",
+        ));
+    }
+    if args.fail_on_gap.is_some()
+        && group_types
+            .iter()
+            .all(|&t| SeqSource::new(t, args.check_seq.is_some()).is_none())
+        && args.verify_template.is_none()
+    {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            "--fail-on-gap needs sequence numbers: -t sdds, -t vita49, --check-seq or --verify-template",
+        ));
+    }
+    let expect_resync = match SeqSource::new(args.packet_type, false) {
+        _ if !args.expect_resync => None,
+        Some(source) => Some(source),
+        None => {
+            return Err(usage(
+                ErrorKind::ArgumentConflict,
+                "--expect-resync needs sequence numbers: -t sdds or -t vita49",
+            ));
+        }
+    };
+    let reorder = args
+        .reorder
+        .map(|depth| {
+            let Some(source) = SeqSource::new(args.packet_type, args.check_seq.is_some()) else {
+                return Err(usage(
+                    ErrorKind::ArgumentConflict,
+                    "--reorder needs sequence numbers: -t sdds, -t vita49 or --check-seq",
+                ));
+            };
+            if depth > source.max_depth() as u64 {
+                return Err(usage(
+                    ErrorKind::ValueValidation,
+                    format!(
+                        "--reorder {depth} is deeper than the sequence allows ({})",
+                        source.max_depth()
+                    ),
+                ));
+            }
+            Ok((source, depth as usize))
+        })
+        .transpose()?;
+    // Sends go to every port, ping probes and echo replies to a single one
+    if args.port.is_many() && (args.ping || args.echo) {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            format!(
+                "-p {} doesn't apply to --ping or --echo, which use a single port",
+                args.port
+            ),
+        ));
+    }
+    if args.port.is_many() && !mode.receive && mode.transmit.is_none() {
+        return Err(usage(
+            ErrorKind::ArgumentConflict,
+            format!("-p {} only applies when receiving or sending", args.port),
+        ));
+    }
+
+    // -v without -c used to mean -c 1 too, which --legacy-verbose keeps for now
+    let max_count = match (args.verbose && args.legacy_verbose, args.count) {
+        (true, None) => 1,
+        (_, Some(c)) => c,
+        (false, None) => 0,
+    };
+
+    Ok(Setup {
+        iface,
+        mgroup,
+        mode,
+        groups,
+        group_types,
+        output_formats,
+        input_format,
+        input_framing,
+        writes_stdout,
+        sigmf,
+        filters,
+        txtime,
+        template_pps,
+        input_pps,
+        writer_pps,
+        expect_resync,
+        reorder,
+        max_count,
+    })
+}
+
+/// The protocol filters asked for, in the order they're checked.
+pub fn packet_filters(args: &Args) -> Vec<PacketFilter> {
+    let mut filters = Vec::new();
+    if args.drop_parity {
+        filters.push(PacketFilter::DropParity);
+    }
+    if args.only_parity {
+        filters.push(PacketFilter::OnlyParity);
+    }
+    if let Some(mode) = args.filter_sdds_mode {
+        filters.push(PacketFilter::SddsMode(mode));
+    }
+    if !args.filter_stream_id.is_empty() {
+        filters.push(PacketFilter::StreamIds(args.filter_stream_id.clone()));
+    }
+    filters
+}
+
+/// Packets -v dumps, None for all of them: --dump's, or every packet up to
+/// -c, or the first.
+pub fn dump_count(args: &Args) -> Option<u64> {
+    match (args.dump, args.count) {
+        (Some(0), _) | (None, Some(_)) => None,
+        (Some(count), _) => Some(count),
+        (None, None) => Some(1),
+    }
+}
+
+/// -s, or something that reports through the stats line.
+/// -v was asked for explicitly, so --quiet only silences the periodic counts.
+pub fn wants_statistics(args: &Args) -> bool {
+    // A prepended sequence number or --strip's bytes have to come off even
+    // when quiet
+    (!args.quiet
+        && (args.stats
+            || args.stats_on_change.is_some()
+            || args.measure_latency.is_some()
+            || args.timetag_offset
+            || args.unique.is_some()))
+        || args.verbose
+        || args.check_seq.is_some()
+        || args.verify_template.is_some()
+        || args.fail_on_gap.is_some()
+        || args.strip.is_some()
+}
+
+/// Where packets come from and whether they go out to a group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mode {
+    /// Receive from the group rather than reading -i
+    pub receive: bool,
+    /// [eth:]mgroup to send to
+    pub transmit: Option<(Option<String>, String)>,
+}
+
+/// A relay sending straight back into the group it receives, on the same
+/// interface. Multicast loopback is on, so every packet it sends comes back
+/// to be sent again.
+pub fn self_loop(mode: &Mode, iface: Option<&str>, mgroup: &str) -> Option<String> {
+    let (tx_iface, tx_groups) = mode.transmit.as_ref()?;
+    let group: Ipv4Addr = mgroup.parse().ok()?;
+    if !mode.receive || !multicast::parse_groups(tx_groups).ok()?.contains(&group) {
+        return None;
+    }
+    let address = |iface: Option<&str>| match iface {
+        Some(iface) => multicast::get_interface_addr(iface).ok(),
+        None => multicast::get_default_interface_for_multicast(&group).ok(),
+    };
+    let same = tx_iface.as_deref() == iface
+        || address(tx_iface.as_deref()).is_some_and(|tx| address(iface) == Some(tx));
+    same.then(|| {
+        format!(
+            "--tx={tx_groups} sends back into {mgroup} on the interface it is received on, so every packet would be relayed again; add --allow-loop if that is intended"
+        )
+    })
+}
+
+/// The first group and any more on the command line. Only a receiver takes
+/// more than one, each a single group joined once.
+pub fn all_groups(
+    first: &GroupSpec,
+    args: &Args,
+    mode: &Mode,
+) -> std::result::Result<Vec<GroupSpec>, String> {
+    if !args.more_groups.is_empty() && !mode.receive {
+        return Err("more than one group only applies when receiving from the network".to_string());
+    }
+    let mut groups = vec![first.clone()];
+    let mut joined = vec![(first.mgroup.clone(), first.ports(&args.port))];
+    for group in &args.more_groups {
+        if group.mgroup.contains([',', '-']) {
+            return Err(
+                "receiving takes a single group, lists and ranges are for sending".to_string(),
+            );
+        }
+        for port in group.ports(&args.port) {
+            if joined
+                .iter()
+                .any(|(mgroup, ports)| *mgroup == group.mgroup && ports.contains(&port))
+            {
+                return Err(format!("{}:{port} is received twice", group.mgroup));
+            }
+        }
+        joined.push((group.mgroup.clone(), group.ports(&args.port)));
+        groups.push(group.clone());
+    }
+    Ok(groups)
+}
+
+// Without --rx/--tx/--local the direction follows -i, but -i with an
+// output has two reasonable meanings, so that needs to be spelled out.
+pub fn resolve_mode(
+    args: &Args,
+    iface: Option<String>,
+    mgroup: String,
+) -> std::result::Result<Mode, String> {
+    if args.rx && args.input.is_some() {
+        return Err("--rx receives from the group, it can't be combined with -i".to_string());
+    }
+    if args.local && (args.rx || args.tx.is_some()) {
+        return Err("--local never touches the network, drop --rx/--tx".to_string());
+    }
+    // --template is made up rather than read, but goes where -i would
+    let has_input = args.input.is_some() || args.template.is_some();
+    if args.local && !has_input {
+        return Err("--local copies -i to the outputs, it needs -i".to_string());
+    }
+
+    // A heartbeat on its own sends to the group rather than receiving from it
+    let heartbeat_only = args.heartbeat.is_some() && !has_input && !args.rx && args.tx.is_none();
+    let receive = !has_input && !heartbeat_only;
+    if receive && mgroup.contains([',', '-']) {
+        return Err("receiving takes a single group, lists and ranges are for sending".to_string());
+    }
+    let has_sink = !args.output.is_empty() || args.exec.is_some() || args.exec_per_packet.is_some();
+    let transmit = match &args.tx {
+        Some(Some(group)) => Some(group.clone()),
+        Some(None) if receive => {
+            return Err(
+                "--tx would send back into the group being received, relay with --tx=[eth:]mgroup"
+                    .to_string(),
+            );
+        }
+        Some(None) => Some((iface, mgroup)),
+        None if heartbeat_only => Some((iface, mgroup)),
+        None if receive || args.local => None,
+        None if has_sink => {
+            return Err(
+                "-i with an output is ambiguous: add --tx to also send to the group, or --local to only copy"
+                    .to_string(),
+            );
+        }
+        None => Some((iface, mgroup)),
+    };
+
+    if args.heartbeat.is_some() && transmit.is_none() {
+        return Err(
+            "--heartbeat sends to a group: drop --local, or relay with --tx=[eth:]mgroup"
+                .to_string(),
+        );
+    }
+
+    Ok(Mode { receive, transmit })
+}
+
+/// Which side of the pipeline -c counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountDirection {
+    Rx,
+    Tx,
+}
+
+// Sending -i to a group counts what went out; receiving, relaying and
+// --local count what came in, which is also what they write at most.
+pub fn count_direction(args: &Args, mode: &Mode) -> CountDirection {
+    if args.count_tx {
+        CountDirection::Tx
+    } else if args.count_rx || mode.receive || mode.transmit.is_none() {
+        CountDirection::Rx
+    } else {
+        CountDirection::Tx
+    }
+}
+
+// --drain-timeout 0 waits forever
+pub fn drain_timeout(args: &Args) -> Option<Duration> {
+    (args.drain_timeout > 0).then(|| Duration::from_secs(args.drain_timeout))
+}
+
+// --pps at --speed, never below one packet a second
+pub fn scaled_pps(pps: u64, speed: f64) -> u64 {
+    ((pps as f64 * speed).round() as u64).max(1)
+}
+
+/// What the startup banner and --print-config-json show. Nothing here
+/// joins: the interface and rcvbuf are looked up the way --dry-run does.
+pub fn startup_config(
+    args: &Args,
+    mode: &Mode,
+    groups: &[GroupSpec],
+    max_count: u64,
+) -> banner::StartupConfig {
+    let interface = mode
+        .receive
+        .then(|| {
+            let first = groups.first()?;
+            let group = preflight::check_groups(&first.mgroup, args.port.first()).ok()?;
+            preflight::resolve_interface(first.iface.as_deref(), &group).ok()
+        })
+        .flatten()
+        .map(|status| banner::Interface {
+            name: status.name,
+            addr: status.addr,
+        });
+    let rcvbuf = mode
+        .receive
+        .then(|| preflight::probe_recv_buffer(RECV_BUFFER_BYTES).ok())
+        .flatten()
+        .map(|granted| banner::RecvBuffer {
+            requested: RECV_BUFFER_BYTES,
+            granted,
+        });
+
+    let mut outputs: Vec<banner::Output> = args
+        .output
+        .iter()
+        .map(|output| banner::Output {
+            sink: match output.as_str() {
+                "-" => "stdout".to_string(),
+                output => output.to_string(),
+            },
+            format: Some(format_name(
+                extension::output_format(output, args.output_format, args.packet_type).format(),
+                args.packet_type,
+            )),
+        })
+        .collect();
+    if let Some(command) = &args.exec {
+        outputs.push(banner::Output {
+            sink: format!("exec `{command}`"),
+            format: Some(format_name(args.output_format, args.packet_type)),
+        });
+    }
+    if let Some(command) = &args.exec_per_packet {
+        outputs.push(banner::Output {
+            sink: format!("exec per packet `{command}`"),
+            format: None,
+        });
+    }
+    if let Some((tx_iface, tx_mgroup)) = &mode.transmit {
+        outputs.push(banner::Output {
+            sink: format!(
+                "{}{tx_mgroup}:{}",
+                tx_iface
+                    .as_deref()
+                    .map_or(String::new(), |i| format!("{i}:")),
+                args.port
+            ),
+            format: None,
+        });
+    }
+    if outputs.is_empty() {
+        outputs.push(banner::Output {
+            sink: "discard".to_string(),
+            format: None,
+        });
+    }
+
+    banner::StartupConfig {
+        interface,
+        group: mode.receive.then(|| match groups {
+            [group] => group.mgroup.clone(),
+            groups => groups
+                .iter()
+                .map(GroupSpec::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        }),
+        ports: args.port.0.clone(),
+        input: args.input.clone(),
+        rcvbuf,
+        packet_type: args.packet_type.to_string(),
+        outputs,
+        limits: banner::Limits {
+            count: (max_count > 0).then_some(max_count),
+            duration_secs: args.duration,
+            rate: args.rate,
+            pps: args.pps,
+        },
+    }
+}
+
+// The name of a layout, or of the packet type's when None.
+pub fn format_name<F: ValueEnum>(format: Option<F>, packet_type: PacketType) -> String {
+    format
+        .and_then(|format| format.to_possible_value())
+        .map_or_else(
+            || match packet_type {
+                PacketType::Text => "text".to_string(),
+                _ => "binary".to_string(),
+            },
+            |format| format.get_name().to_string(),
+        )
+}
+
+// Say what a file's extension chose, or why it was passed over.
+pub fn log_chosen<F: ValueEnum + Copy>(
+    verb: &str,
+    path: &str,
+    chosen: &Chosen<F>,
+    packet_type: PacketType,
+) {
+    match chosen {
+        Chosen::FromExtension(format) => log::info!(
+            "{verb} {path} as {}, from its extension",
+            format_name(Some(*format), packet_type)
+        ),
+        Chosen::Default(Some(why)) => log::warn!(
+            "{path}: {why}, {verb} it as {}",
+            format_name(None::<F>, packet_type)
+        ),
+        Chosen::Given(_) | Chosen::Default(None) => {}
+    }
+}
+
+// Validate everything a real run would touch, without spawning threads or joining.
+pub fn dry_run(args: &Args, mode: &Mode, groups: &[GroupSpec]) -> error::Result<()> {
+    for spec in groups {
+        let group = preflight::check_groups(&spec.mgroup, args.port.first())?;
+        let interface = preflight::resolve_interface(spec.iface.as_deref(), &group)?;
+        if !interface.up {
+            return Err(error::LibError::Critical(format!(
+                "interface {} is down",
+                interface.name
+            )));
+        }
+        if !group.is_multicast() {
+            log::info!(
+                "interface: {} ({}) up, unicast",
+                interface.name,
+                interface.addr
+            );
+            continue;
+        }
+        if !interface.multicast {
+            return Err(error::LibError::Critical(format!(
+                "interface {} is not multicast capable",
+                interface.name
+            )));
+        }
+        log::info!(
+            "interface: {} ({}) up, multicast",
+            interface.name,
+            interface.addr
+        );
+    }
+
+    match &args.input {
+        Some(input) => {
+            if input != "-" {
+                std::fs::File::open(input)?;
+            }
+            log::info!("source: {input}");
+        }
+        None if args.template.is_some() => log::info!("source: template"),
+        None => {
+            let granted = preflight::probe_recv_buffer(RECV_BUFFER_BYTES)?;
+            let sources: Vec<String> = groups
+                .iter()
+                .map(|group| format!("{}:{}", group.mgroup, Ports(group.ports(&args.port))))
+                .collect();
+            log::info!(
+                "source: {}, rcvbuf {} granted of {} requested",
+                sources.join(", "),
+                preflight::format_size(granted as u64),
+                preflight::format_size(RECV_BUFFER_BYTES as u64)
+            );
+            if granted < RECV_BUFFER_BYTES {
+                log::warn!("rcvbuf is capped by net.core.rmem_max, expect drops at high rates");
+            }
+        }
+    }
+
+    for output in &args.output {
+        if output == "-" {
+            log::info!("sink: stdout");
+        } else {
+            let free = preflight::check_output(output, args.min_free.unwrap_or(0))?;
+            log::info!("sink: {output} ({} free)", preflight::format_size(free));
+        }
+    }
+
+    match (&args.exec, &args.exec_per_packet) {
+        (Some(command), _) => log::info!("sink: exec `{command}`"),
+        (None, Some(command)) => log::info!("sink: exec per packet `{command}`"),
+        (None, None) => {}
+    }
+
+    match &mode.transmit {
+        Some((tx_iface, tx_mgroup)) => {
+            let tx_group = preflight::check_groups(tx_mgroup, args.port.first())?;
+            if tx_iface.is_some() || args.txtime {
+                let tx_interface = preflight::resolve_interface(tx_iface.as_deref(), &tx_group)?;
+                if args.txtime {
+                    txtime::qdisc_clock(&tx_interface.name)?;
+                    log::info!("sink: txtime through the {} qdisc", tx_interface.name);
+                }
+            }
+            log::info!(
+                "sink: {}{tx_mgroup}:{} ttl {}",
+                tx_iface
+                    .as_deref()
+                    .map_or(String::new(), |i| format!("{i}:")),
+                args.port,
+                args.ttl
+            );
+        }
+        None if args.output.is_empty() && args.exec.is_none() && args.exec_per_packet.is_none() => {
+            log::info!("sink: discard")
+        }
+        None => {}
+    }
+
+    let stats = wants_statistics(args);
+    log::info!(
+        "threads: reader, {}writer",
+        if stats { "statistics, " } else { "" }
+    );
+    log::info!("dry run ok");
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use clap::{FromArgMatches, Parser};
+
+    use super::*;
+    use crate::cli::EXAMPLES;
+
+    // Split an example command line like sh would, for the quoting used in EXAMPLES.
+    fn split_words(line: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        let mut word = String::new();
+        let mut quote = None;
+        let mut in_word = false;
+
+        for c in line.chars() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), c) => word.push(c),
+                (None, '\'' | '"') => {
+                    quote = Some(c);
+                    in_word = true;
+                }
+                (None, c) if c.is_whitespace() => {
+                    if in_word {
+                        words.push(std::mem::take(&mut word));
+                        in_word = false;
+                    }
+                }
+                (None, c) => {
+                    word.push(c);
+                    in_word = true;
+                }
+            }
+        }
+        if in_word {
+            words.push(word);
+        }
+        words
+    }
+
+    fn describe(args: &Args, mode: &Mode) -> String {
+        let source = match args.input {
+            _ if mode.receive => "rx",
+            Some(_) => "file",
+            None if args.template.is_some() => "template",
+            None => "heartbeat",
+        };
+        match &mode.transmit {
+            Some((Some(iface), mgroup)) => format!("{source} -> {iface}:{mgroup}"),
+            Some((None, mgroup)) => format!("{source} -> {mgroup}"),
+            None => source.to_string(),
+        }
+    }
+
+    fn mode_of(line: &str) -> std::result::Result<String, String> {
+        let mut words = split_words(line);
+        // Which cores exist depends on the host running the tests
+        if let Some(idx) = words.iter().position(|word| word == "--cpu") {
+            words.drain(idx..idx + 2);
+        }
+
+        let matches = Args::command()
+            .try_get_matches_from(words)
+            .map_err(|e| e.to_string())?;
+        let args = Args::from_arg_matches(&matches).map_err(|e| e.to_string())?;
+        match args.mgroup.clone() {
+            Some(GroupSpec { iface, mgroup, .. }) => {
+                resolve_mode(&args, iface, mgroup).map(|mode| describe(&args, &mode))
+            }
+            None => Ok("from config".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_examples_parse_into_expected_mode() {
+        let examples: Vec<&str> = EXAMPLES
+            .lines()
+            .filter_map(|line| {
+                line.find("mnc ")
+                    .map(|idx| line.get(idx..).unwrap_or_default())
+            })
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .collect();
+        let comments = EXAMPLES
+            .lines()
+            .filter(|line| line.trim_start().starts_with('#'));
+        assert_eq!(
+            examples.len(),
+            comments.count(),
+            "every example is one command"
+        );
+
+        let expected = [
+            "rx",
+            "file -> 239.1.1.1",
+            "rx",
+            "rx",
+            "rx",
+            "rx",
+            "rx",
+            "file -> 239.1.1.1",
+            "rx",
+            "rx",
+            "file -> 239.1.1.1",
+            "file",
+            "rx -> eth1:239.1.1.1",
+            "heartbeat -> 239.1.1.1",
+            "template -> 239.1.1.1",
+            "file -> 239.1.1.1-16",
+            "rx",
+            "rx",
+            "rx",
+            "rx",
+            "rx",
+            "rx",
+            "from config",
+            "rx",
+        ];
+        let actual: Vec<String> = examples
+            .iter()
+            .map(|example| mode_of(example).unwrap_or_else(|e| format!("{example}: {e}")))
+            .collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_direction_conflicts() {
+        assert!(mode_of("mnc 239.1.1.1 --rx -i ./capture.bin").is_err());
+        assert!(mode_of("mnc 239.1.1.1 -i ./capture.bin -o ./copy.bin").is_err());
+        assert!(mode_of("mnc 239.1.1.1 --tx").is_err());
+        assert!(mode_of("mnc 239.1.1.1 --local").is_err());
+        assert!(mode_of("mnc 239.1.1.1 -i - --local --tx").is_err());
+        assert_eq!(
+            mode_of("mnc 239.1.1.1 --rx --tx=239.2.2.2").as_deref(),
+            Ok("rx -> 239.2.2.2")
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_direction() {
+        assert!(mode_of("mnc 239.1.1.1 --rx --heartbeat 1s").is_err());
+        assert!(mode_of("mnc 239.1.1.1 -i - --local --heartbeat 1s").is_err());
+        assert_eq!(
+            mode_of("mnc 239.1.1.1 --heartbeat 1s --tx=239.2.2.2").as_deref(),
+            Ok("rx -> 239.2.2.2")
+        );
+        assert_eq!(
+            mode_of("mnc 239.1.1.1 -i - --heartbeat 1s").as_deref(),
+            Ok("file -> 239.1.1.1")
+        );
+    }
+
+    #[test]
+    fn test_group_lists_are_for_sending() {
+        assert!(mode_of("mnc 239.1.1.1-4").is_err());
+        assert!(mode_of("mnc 239.1.1.9-4 -i -").is_err());
+        assert_eq!(
+            mode_of("mnc 239.1.1.1 --tx=eth1:239.2.2.1,239.2.2.7").as_deref(),
+            Ok("rx -> eth1:239.2.2.1,239.2.2.7")
+        );
+    }
+
+    #[test]
+    fn test_more_groups_only_when_receiving() {
+        let mode_of = |line: &[&str]| {
+            let args = Args::try_parse_from(line).expect("args");
+            let first = args.mgroup.clone().expect("group");
+            let mode = resolve_mode(&args, first.iface.clone(), first.mgroup.clone())?;
+            all_groups(&first, &args, &mode).map(|groups| groups.len())
+        };
+        assert_eq!(
+            mode_of(&["mnc", "239.1.1.1:5000?type=sdds", "239.1.1.2?type=vita49"]),
+            Ok(2)
+        );
+        assert!(mode_of(&["mnc", "239.1.1.1", "239.1.1.2", "-i", "-"]).is_err());
+        assert!(mode_of(&["mnc", "239.1.1.1", "239.1.1.2-3"]).is_err());
+        assert!(mode_of(&["mnc", "239.1.1.1", "239.1.1.1:5000", "-p", "5000"]).is_err());
+        assert_eq!(
+            mode_of(&["mnc", "239.1.1.1", "239.1.1.1:5001", "-p", "5000"]),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words("mnc 239.1.1.1 --exec-per-packet 'curl -s -d @- http://x'"),
+            [
+                "mnc",
+                "239.1.1.1",
+                "--exec-per-packet",
+                "curl -s -d @- http://x"
+            ]
+        );
+        assert_eq!(split_words("echo \"\" x"), ["echo", "", "x"]);
+    }
+
+    #[test]
+    fn test_self_loop() {
+        let relay = |iface: Option<&str>, groups: &str| Mode {
+            receive: true,
+            transmit: Some((iface.map(str::to_string), groups.to_string())),
+        };
+        assert!(self_loop(&relay(None, "239.1.1.1"), None, "239.1.1.1").is_some());
+        assert!(self_loop(&relay(None, "239.1.1.1-3"), None, "239.1.1.2").is_some());
+        assert!(self_loop(&relay(Some("eth9"), "239.1.1.1"), Some("eth9"), "239.1.1.1").is_some());
+        assert!(self_loop(&relay(None, "239.2.2.2"), None, "239.1.1.1").is_none());
+
+        // Sending from a file isn't relaying
+        let send = Mode {
+            receive: false,
+            transmit: Some((None, "239.1.1.1".to_string())),
+        };
+        assert!(self_loop(&send, None, "239.1.1.1").is_none());
+    }
+}
@@ -0,0 +1,718 @@
+/// SigMF recordings of the samples in SDDS and VITA-49 streams.
+/// Payloads without their headers go to NAME.sigmf-data, and what the headers
+/// say about them to NAME.sigmf-meta when the stream ends. A new capture
+/// segment starts wherever the time stamps jump or the tuned frequency changes.
+/// With --mark-gaps, missing packets are filled with zero samples, as many
+/// as the packet before them held, and annotated.
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use chrono::DateTime;
+use serde_json::{Map, Value, json};
+
+use crate::{
+    error::{LibError, Result},
+    gaps::{Gap, GapMarker},
+    packet::{Packet, PacketType},
+    sdds::{self, SddsEpoch, TICKS_PER_SEC, TimeTagCheck},
+    sink::Sink,
+    vita49::{self, PayloadFormat, VrtKind},
+};
+
+const SIGMF_VERSION: &str = "1.0.0";
+
+const PICOS_PER_SEC: f64 = 1e12;
+
+// A VITA-49 timestamp further than this, as a fraction of the packet's
+// duration, from where the samples before it end starts a new capture.
+const TIMESTAMP_TOLERANCE: f64 = 0.001;
+
+// Zeros written at a time when filling a gap.
+const ZEROS: [u8; 8192] = [0; 8192];
+
+/// What the stream may not say about itself.
+#[derive(Debug, Clone)]
+pub struct SigmfConfig {
+    pub packet_type: PacketType,
+    /// Taken over what the stream says or implies
+    pub sample_rate: Option<f64>,
+    /// SigMF datatype like ci16_be, for VITA-49 without a payload format
+    pub datatype: Option<String>,
+    /// What SDDS time tags count from; without it captures have no datetime
+    pub sdds_epoch: Option<SddsEpoch>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Capture {
+    sample_start: u64,
+    datetime: Option<String>,
+    frequency: Option<f64>,
+}
+
+/// Zero samples written for missing packets.
+#[derive(Debug, Clone, PartialEq)]
+struct Filled {
+    sample_start: u64,
+    sample_count: u64,
+    packets: u64,
+}
+
+/// SigMF datatype for a sample format, like ci16_be. None when SigMF has none.
+fn datatype(complex: bool, kind: char, bits: u8) -> Option<String> {
+    let valid = match kind {
+        'i' | 'u' => matches!(bits, 8 | 16 | 32),
+        'f' => matches!(bits, 32 | 64),
+        _ => false,
+    };
+    if !valid {
+        return None;
+    }
+    let endian = if bits == 8 { "" } else { "_be" };
+    let class = if complex { 'c' } else { 'r' };
+    Some(format!("{class}{kind}{bits}{endian}"))
+}
+
+fn vita49_datatype(format: PayloadFormat) -> Option<String> {
+    if format.item_bits != format.packing_bits {
+        return None;
+    }
+    let kind = match format.item_format {
+        0x00 => 'i',
+        0x10 => 'u',
+        0x0E | 0x0F => 'f',
+        _ => return None,
+    };
+    datatype(format.complex, kind, format.item_bits)
+}
+
+/// Bytes per sample of a SigMF datatype, both halves of a complex one.
+pub fn sample_bytes(name: &str) -> Option<usize> {
+    let mut chars = name.chars();
+    let complex = match chars.next()? {
+        'c' => true,
+        'r' => false,
+        _ => return None,
+    };
+    let kind = chars.next()?;
+    let rest = chars.as_str();
+    let bits = rest
+        .strip_suffix("_be")
+        .or_else(|| rest.strip_suffix("_le"))
+        .unwrap_or(rest);
+    let bits: u8 = bits.parse().ok()?;
+    let canonical = datatype(complex, kind, bits)?;
+    // 8 bit types have no byte order, the others must say
+    if canonical.len() != name.len() {
+        return None;
+    }
+    Some(bits as usize / 8 * if complex { 2 } else { 1 })
+}
+
+fn vita49_time(packet: &vita49::VrtPacket) -> Option<i128> {
+    Some(packet.utc_seconds? as i128 * PICOS_PER_SEC as i128 + packet.picoseconds? as i128)
+}
+
+fn format_picos(picos: i128) -> Option<String> {
+    let seconds = picos.div_euclid(PICOS_PER_SEC as i128);
+    let fraction = picos.rem_euclid(PICOS_PER_SEC as i128);
+    let time = DateTime::from_timestamp(i64::try_from(seconds).ok()?, 0)?;
+    Some(format!(
+        "{}.{fraction:012}Z",
+        time.format("%Y-%m-%dT%H:%M:%S")
+    ))
+}
+
+pub struct SigmfSink {
+    name: String,
+    data: BufWriter<File>,
+    meta_path: PathBuf,
+    config: SigmfConfig,
+    datatype: Option<String>,
+    sample_bytes: usize,
+    sample_rate: Option<f64>,
+    samples: u64,
+    captures: Vec<Capture>,
+    new_capture: bool,
+    frequency: Option<f64>,
+    sdds_time: TimeTagCheck,
+    sdds_packet_samples: u64,
+    // Where the next VITA-49 data packet should start, picoseconds since the epoch
+    vita49_next: Option<i128>,
+    waiting: u64,
+    // Samples in the last data packet, what a missing one is filled with
+    packet_samples: u64,
+    gap_marker: Option<GapMarker>,
+    filled: Vec<Filled>,
+}
+
+impl SigmfSink {
+    /// Create output.sigmf-data and, once flushed, output.sigmf-meta.
+    /// A .sigmf-data, .sigmf-meta or .sigmf extension on output is replaced.
+    pub fn create(output: &str, config: SigmfConfig) -> Result<Self> {
+        let base = [".sigmf-data", ".sigmf-meta", ".sigmf"]
+            .iter()
+            .find_map(|extension| output.strip_suffix(extension))
+            .unwrap_or(output);
+        let name = format!("{base}.sigmf-data");
+        let data = BufWriter::new(File::create(&name)?);
+
+        let datatype = config.datatype.clone();
+        let sample_bytes = match datatype.as_deref() {
+            Some(name) => sample_bytes(name)
+                .ok_or_else(|| LibError::Critical(format!("not a SigMF datatype: {name}")))?,
+            None => 0,
+        };
+        Ok(Self {
+            name,
+            data,
+            meta_path: PathBuf::from(format!("{base}.sigmf-meta")),
+            sample_rate: config.sample_rate,
+            config,
+            datatype,
+            sample_bytes,
+            samples: 0,
+            captures: Vec::new(),
+            new_capture: true,
+            frequency: None,
+            sdds_time: TimeTagCheck::default(),
+            sdds_packet_samples: 0,
+            vita49_next: None,
+            waiting: 0,
+            packet_samples: 0,
+            gap_marker: None,
+            filled: Vec::new(),
+        })
+    }
+
+    /// Fill missing packets with zero samples, for --mark-gaps.
+    pub fn with_gap_marker(mut self, marker: Option<GapMarker>) -> Self {
+        self.gap_marker = marker;
+        self
+    }
+
+    fn set_datatype(&mut self, datatype: String) -> Result<()> {
+        match &self.datatype {
+            None => {
+                self.sample_bytes = sample_bytes(&datatype).unwrap_or(0);
+                self.datatype = Some(datatype);
+                Ok(())
+            }
+            Some(current) if *current == datatype => Ok(()),
+            Some(current) => Err(LibError::Critical(format!(
+                "samples changed from {current} to {datatype}, which one SigMF recording can't hold"
+            ))),
+        }
+    }
+
+    fn start_capture(&mut self, datetime: Option<String>) {
+        self.captures.push(Capture {
+            sample_start: self.samples,
+            datetime,
+            frequency: self.frequency,
+        });
+        self.new_capture = false;
+    }
+
+    fn write_samples(&mut self, data: &[u8]) -> Result<()> {
+        self.data.write_all(data)?;
+        self.packet_samples = (data.len() / self.sample_bytes.max(1)) as u64;
+        self.samples += self.packet_samples;
+        Ok(())
+    }
+
+    /// Zero samples for the data packets gap left out. Before any sample
+    /// has been written there is nothing to size them by.
+    fn fill_gap(&mut self, gap: Gap) -> Result<()> {
+        // VITA-49 numbers its context packets in the same sequence
+        let packets = match self.config.packet_type {
+            PacketType::Sdds => gap.sdds_data_packets(),
+            _ => gap.skipped,
+        };
+        let sample_count = packets * self.packet_samples;
+        if sample_count == 0 {
+            return Ok(());
+        }
+        let mut bytes = sample_count * self.sample_bytes as u64;
+        while bytes > 0 {
+            let chunk = bytes.min(ZEROS.len() as u64);
+            self.data
+                .write_all(ZEROS.get(..chunk as usize).unwrap_or_default())?;
+            bytes -= chunk;
+        }
+        self.filled.push(Filled {
+            sample_start: self.samples,
+            sample_count,
+            packets,
+        });
+        if let Some(marker) = &self.gap_marker {
+            marker.mark(format!("{} sample {}", self.name, self.samples), packets);
+        }
+        self.samples += sample_count;
+        Ok(())
+    }
+
+    fn record_sdds(&mut self, packet: &[u8]) -> Result<()> {
+        if sdds::is_parity(sdds::frame_sequence_number(packet)) {
+            return Ok(());
+        }
+        let Some(data) = sdds::data(packet) else {
+            return Ok(());
+        };
+        let bits = sdds::bits_per_sample(packet);
+        let datatype = datatype(sdds::cx(packet), 'i', bits).ok_or_else(|| {
+            LibError::Critical(format!("SigMF has no datatype for {bits} bit SDDS samples"))
+        })?;
+        self.set_datatype(datatype)?;
+        self.sdds_packet_samples = (data.len() / self.sample_bytes.max(1)) as u64;
+
+        if self
+            .sdds_time
+            .observe(packet, self.config.sample_rate)
+            .is_some()
+        {
+            self.new_capture = true;
+        }
+        if self.new_capture {
+            let datetime = match self.config.sdds_epoch {
+                Some(epoch) if sdds::ttv(packet) => Some(epoch.format(sdds::time_tag(packet))),
+                _ => None,
+            };
+            self.start_capture(datetime);
+        }
+        self.write_samples(data)
+    }
+
+    fn record_vita49(&mut self, packet: &[u8]) -> Result<()> {
+        let Some(vrt) = vita49::parse_vrt(packet) else {
+            return Ok(());
+        };
+        match vrt.kind() {
+            VrtKind::Context => self.vita49_context(vita49::parse_context(vrt.payload)),
+            VrtKind::Data => self.vita49_data(&vrt),
+            VrtKind::Other => Ok(()),
+        }
+    }
+
+    fn vita49_context(&mut self, context: vita49::Context) -> Result<()> {
+        if self.config.sample_rate.is_none()
+            && let Some(rate) = context.sample_rate
+        {
+            if self.sample_rate.is_some_and(|current| current != rate) {
+                log::warn!("VITA-49 sample rate changed to {rate} Hz, SigMF keeps the first");
+            } else {
+                self.sample_rate = Some(rate);
+            }
+        }
+        if let Some(frequency) = context.rf_frequency
+            && self.frequency != Some(frequency)
+        {
+            self.frequency = Some(frequency);
+            self.new_capture = true;
+        }
+        if self.config.datatype.is_none()
+            && let Some(format) = context.payload_format
+        {
+            let datatype = vita49_datatype(format).ok_or_else(|| {
+                LibError::Critical(format!("SigMF has no datatype for VITA-49 {format:?}"))
+            })?;
+            self.set_datatype(datatype)?;
+        }
+        Ok(())
+    }
+
+    fn vita49_data(&mut self, vrt: &vita49::VrtPacket) -> Result<()> {
+        if self.datatype.is_none() {
+            if self.waiting == 0 {
+                log::info!(
+                    "{}: waiting for a VITA-49 context packet with the payload format, or give --sigmf-datatype",
+                    self.name
+                );
+            }
+            self.waiting += 1;
+            return Ok(());
+        }
+
+        let time = vita49_time(vrt);
+        let samples = vrt.payload.len() / self.sample_bytes.max(1);
+        let duration = self
+            .sample_rate
+            .map(|rate| samples as f64 / rate * PICOS_PER_SEC);
+        if let (Some(time), Some(expected), Some(duration)) = (time, self.vita49_next, duration)
+            && (time - expected).abs() as f64 > duration * TIMESTAMP_TOLERANCE
+        {
+            self.new_capture = true;
+        }
+        self.vita49_next = time
+            .zip(duration)
+            .map(|(time, duration)| time + duration as i128);
+
+        if self.new_capture {
+            self.start_capture(time.and_then(format_picos));
+        }
+        self.write_samples(vrt.payload)
+    }
+
+    /// The sample rate given, from the stream, or implied by SDDS time tags.
+    fn sample_rate(&self) -> Option<f64> {
+        self.sample_rate.or_else(|| {
+            let increment = self.sdds_time.increment()?;
+            Some(self.sdds_packet_samples as f64 * TICKS_PER_SEC as f64 / increment as f64)
+        })
+    }
+
+    fn meta(&self) -> Option<Value> {
+        let mut global = Map::new();
+        global.insert("core:datatype".into(), json!(self.datatype.as_ref()?));
+        if let Some(rate) = self.sample_rate() {
+            global.insert("core:sample_rate".into(), json!(rate));
+        }
+        global.insert("core:version".into(), json!(SIGMF_VERSION));
+        global.insert(
+            "core:recorder".into(),
+            json!(format!("mnc {}", env!("CARGO_PKG_VERSION"))),
+        );
+
+        let mut captures: Vec<Value> = self
+            .captures
+            .iter()
+            .map(|capture| {
+                let mut segment = Map::new();
+                segment.insert("core:sample_start".into(), json!(capture.sample_start));
+                if let Some(datetime) = &capture.datetime {
+                    segment.insert("core:datetime".into(), json!(datetime));
+                }
+                if let Some(frequency) = capture.frequency {
+                    segment.insert("core:frequency".into(), json!(frequency));
+                }
+                Value::Object(segment)
+            })
+            .collect();
+        if captures.is_empty() {
+            captures.push(json!({ "core:sample_start": 0 }));
+        }
+
+        let annotations: Vec<Value> = self
+            .filled
+            .iter()
+            .map(|filled| {
+                let missing = match filled.packets {
+                    1 => "1 packet".to_string(),
+                    packets => format!("{packets} packets"),
+                };
+                json!({
+                    "core:sample_start": filled.sample_start,
+                    "core:sample_count": filled.sample_count,
+                    "core:comment": format!("{missing} missing, filled with zeros"),
+                })
+            })
+            .collect();
+
+        Some(json!({
+            "global": global,
+            "captures": captures,
+            "annotations": annotations,
+        }))
+    }
+}
+
+impl Sink for SigmfSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn write_packets(&mut self, packets: &[Packet]) -> Result<()> {
+        for packet in packets {
+            // Keepalives carry nothing
+            if packet.is_empty() {
+                continue;
+            }
+            if let Some(gap) = self
+                .gap_marker
+                .as_mut()
+                .and_then(|marker| marker.observe(packet))
+            {
+                self.fill_gap(gap)?;
+            }
+            match self.config.packet_type {
+                PacketType::Sdds => self.record_sdds(packet)?,
+                PacketType::Vita49 => self.record_vita49(packet)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.data.flush()?;
+        if self.waiting > 0 {
+            log::warn!(
+                "{}: {} VITA-49 data packets arrived before their format was known and were left out",
+                self.name,
+                self.waiting
+            );
+        }
+        match self.meta() {
+            Some(meta) => {
+                let meta = serde_json::to_string_pretty(&meta)
+                    .map_err(|e| LibError::Critical(e.to_string()))?;
+                fs::write(&self.meta_path, meta + "\n")?;
+            }
+            None => log::warn!(
+                "{}: no samples recorded, so no {}",
+                self.name,
+                self.meta_path.display()
+            ),
+        }
+        Ok(())
+    }
+
+    fn flush_buffered(&mut self) -> Result<()> {
+        Ok(self.data.flush()?)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{gaps::GapMarks, packet::Packets};
+
+    fn batch(payloads: &[Vec<u8>]) -> Packets {
+        let mut packets = Packets::new(payloads.len(), 0);
+        for (packet, payload) in packets.iter_mut().zip(payloads) {
+            packet.ensure_capacity(payload.len());
+            if let Some(data) = packet.data_mut().get_mut(..payload.len()) {
+                data.copy_from_slice(payload);
+            }
+            packet.set_length(payload.len());
+        }
+        packets
+    }
+
+    fn record(name: &str, config: SigmfConfig, payloads: &[Vec<u8>]) -> (Value, Vec<u8>) {
+        record_marking(name, config, None, payloads)
+    }
+
+    fn record_marking(
+        name: &str,
+        config: SigmfConfig,
+        marker: Option<GapMarker>,
+        payloads: &[Vec<u8>],
+    ) -> (Value, Vec<u8>) {
+        let base = std::env::temp_dir().join(format!("mnc-sigmf-{}-{name}", std::process::id()));
+        let base = base.to_str().expect("path").to_string();
+        let mut sink = SigmfSink::create(&format!("{base}.sigmf"), config)
+            .expect("create")
+            .with_gap_marker(marker);
+        sink.write_packets(batch(payloads).packets())
+            .expect("write");
+        sink.flush().expect("flush");
+
+        let meta = fs::read_to_string(format!("{base}.sigmf-meta")).expect("meta");
+        let data = fs::read(format!("{base}.sigmf-data")).expect("data");
+        let _ = fs::remove_file(format!("{base}.sigmf-meta"));
+        let _ = fs::remove_file(format!("{base}.sigmf-data"));
+        (serde_json::from_str(&meta).expect("meta is JSON"), data)
+    }
+
+    fn sdds_packet(seq: u16, time_tag: u64, fill: u8) -> Vec<u8> {
+        let mut packet = vec![fill; sdds::HEADER_BYTES + 1024];
+        packet.splice(0..2, [0x80, 0x80 | 16]);
+        packet.splice(2..4, seq.to_be_bytes());
+        packet.splice(4..5, [0x80]);
+        packet.splice(8..16, time_tag.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_sample_bytes() {
+        assert_eq!(sample_bytes("ci16_be"), Some(4));
+        assert_eq!(sample_bytes("ri8"), Some(1));
+        assert_eq!(sample_bytes("cf32_le"), Some(8));
+        assert_eq!(sample_bytes("ci16"), None);
+        assert_eq!(sample_bytes("ri12_be"), None);
+        assert_eq!(sample_bytes("x"), None);
+    }
+
+    #[test]
+    fn test_sdds_recording() {
+        // Complex 16 bit samples at 1 MHz are 256 to a packet, 256us
+        let step = 1_024_000;
+        let config = SigmfConfig {
+            packet_type: PacketType::Sdds,
+            sample_rate: Some(1e6),
+            datatype: None,
+            sdds_epoch: Some(SddsEpoch::Year(2024)),
+        };
+        let packets = vec![
+            sdds_packet(1, 0, 1),
+            sdds_packet(2, step, 2),
+            // Parity is left out
+            sdds_packet(32, 0, 9),
+            // The packetizer restarted
+            sdds_packet(3, TICKS_PER_SEC, 3),
+        ];
+        let (meta, data) = record("sdds", config, &packets);
+
+        assert_eq!(data.len(), 3 * 1024);
+        assert_eq!(data.get(2048), Some(&3));
+        assert_eq!(
+            meta.pointer("/global/core:datatype"),
+            Some(&json!("ci16_be"))
+        );
+        assert_eq!(meta.pointer("/global/core:sample_rate"), Some(&json!(1e6)));
+        assert_eq!(
+            meta.pointer("/global/core:version"),
+            Some(&json!(SIGMF_VERSION))
+        );
+        assert_eq!(
+            meta.pointer("/captures").cloned(),
+            Some(json!([
+                { "core:sample_start": 0, "core:datetime": "2024-01-01T00:00:00.00000000000Z" },
+                { "core:sample_start": 512, "core:datetime": "2024-01-01T00:00:01.00000000000Z" },
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_gaps_filled_with_zeros() {
+        let step = 1_024_000;
+        let config = SigmfConfig {
+            packet_type: PacketType::Sdds,
+            sample_rate: Some(1e6),
+            datatype: None,
+            sdds_epoch: None,
+        };
+        let marks = Arc::new(GapMarks::default());
+        let marker = GapMarker::new(PacketType::Sdds, marks.clone());
+        // 31 and 32 are missing, but 32 is a parity packet; then 34 and 35
+        let packets = vec![
+            sdds_packet(29, 0, 1),
+            sdds_packet(30, step, 2),
+            sdds_packet(33, 3 * step, 3),
+            sdds_packet(36, 6 * step, 4),
+        ];
+        let (meta, data) = record_marking("gaps", config, Some(marker), &packets);
+
+        assert_eq!(data.len(), 7 * 1024);
+        assert_eq!(data.get(2 * 1024..3 * 1024), Some(&[0; 1024][..]));
+        assert_eq!(data.get(3 * 1024), Some(&3));
+        assert_eq!(data.get(4 * 1024..6 * 1024), Some(&[0; 2048][..]));
+        assert_eq!(data.get(6 * 1024), Some(&4));
+        assert_eq!(
+            meta.pointer("/annotations").cloned(),
+            Some(json!([
+                {
+                    "core:sample_start": 512,
+                    "core:sample_count": 256,
+                    "core:comment": "1 packet missing, filled with zeros",
+                },
+                {
+                    "core:sample_start": 1024,
+                    "core:sample_count": 512,
+                    "core:comment": "2 packets missing, filled with zeros",
+                },
+            ]))
+        );
+        assert_eq!(marks.get().missing, 3);
+    }
+
+    #[test]
+    fn test_vita49_recording() {
+        let fixed = |hz: u64| {
+            let value = hz << 20;
+            [(value >> 32) as u32, value as u32]
+        };
+        let frame = |words: &[u32]| -> Vec<u8> {
+            let mut packet = b"VRLP\x00\x00\x00\x00".to_vec();
+            packet.extend(words.iter().flat_map(|word| word.to_be_bytes()));
+            packet.extend(b"VEND");
+            packet
+        };
+        let context = |frequency: u64| {
+            let [rf_hi, rf_lo] = fixed(frequency);
+            let [rate_hi, rate_lo] = fixed(1_000);
+            let cif0 = (1 << 27) | (1 << 21) | (1 << 15);
+            let format = (1 << 29) | (15 << 6) | 15;
+            frame(&[
+                (4 << 28) | 10,
+                7,
+                cif0,
+                rf_hi,
+                rf_lo,
+                rate_hi,
+                rate_lo,
+                format,
+                0,
+                0,
+            ])
+        };
+        // Two complex 16 bit samples, 2ms at 1 kHz, stamped UTC + picoseconds
+        let data = |seconds: u32, picos: u64, sample: u32| {
+            let header = (1 << 28) | (1 << 22) | (2 << 20) | 7;
+            frame(&[
+                header,
+                7,
+                seconds,
+                (picos >> 32) as u32,
+                picos as u32,
+                sample,
+                sample,
+            ])
+        };
+
+        let config = SigmfConfig {
+            packet_type: PacketType::Vita49,
+            sample_rate: None,
+            datatype: None,
+            sdds_epoch: None,
+        };
+        let packets = vec![
+            // No format yet
+            data(1_700_000_000, 0, 0),
+            context(100_000_000),
+            data(1_700_000_000, 0, 1),
+            data(1_700_000_000, 2_000_000_000, 2),
+            // Retuned
+            context(200_000_000),
+            data(1_700_000_000, 4_000_000_000, 3),
+            // Timestamps jumped
+            data(1_700_000_001, 0, 4),
+        ];
+        let (meta, data) = record("vita49", config, &packets);
+
+        assert_eq!(data.len(), 4 * 8);
+        assert_eq!(data.get(..4), Some(&1u32.to_be_bytes()[..]));
+        assert_eq!(
+            meta.pointer("/global/core:datatype"),
+            Some(&json!("ci16_be"))
+        );
+        assert_eq!(
+            meta.pointer("/global/core:sample_rate"),
+            Some(&json!(1000.0))
+        );
+        assert_eq!(
+            meta.pointer("/captures").cloned(),
+            Some(json!([
+                {
+                    "core:sample_start": 0,
+                    "core:datetime": "2023-11-14T22:13:20.000000000000Z",
+                    "core:frequency": 1e8,
+                },
+                {
+                    "core:sample_start": 4,
+                    "core:datetime": "2023-11-14T22:13:20.004000000000Z",
+                    "core:frequency": 2e8,
+                },
+                {
+                    "core:sample_start": 6,
+                    "core:datetime": "2023-11-14T22:13:21.000000000000Z",
+                    "core:frequency": 2e8,
+                },
+            ]))
+        );
+    }
+}
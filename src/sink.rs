@@ -0,0 +1,2538 @@
+/// Destinations for the writer thread.
+/// The writer fans every batch out to each configured sink; a sink only has to
+/// know how to frame and deliver packets, not how to pace or count them.
+#[cfg(mmsg)]
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, IoSlice, Stdout, Write};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use nix::errno::Errno;
+use nix::sys::socket::{MsgFlags, SockaddrStorage, sendmsg};
+#[cfg(mmsg)]
+use nix::sys::socket::{MultiHeaders, sendmmsg};
+use socket2::Socket;
+
+use crate::{
+    error::{LibError, Result},
+    exec::{self, ExecWriter},
+    fec::{FecCounters, FecEncoder},
+    gaps::{self, GapMarker},
+    latency,
+    multicast::{
+        create_send_socket, create_unconnected_send_socket, parse_groups, socket_to_raw_fd,
+    },
+    packet::{Packet, PacketType},
+    patch::{PatchCounters, Patcher},
+    pcap, preflight,
+    resolve::HostNames,
+    sequence::{self, SeqField},
+    txtime, vita49,
+};
+
+// Per-packet commands that fail this many times in a row stop the sink.
+const MAX_EXEC_FAILURES: u32 = 10;
+
+// A packet the kernel has no room for is retried this many times, then dropped.
+const MAX_SEND_RETRIES: u32 = 3;
+
+pub trait Sink: Send {
+    /// Where the packets go, for log messages.
+    fn name(&self) -> &str;
+
+    fn write_packets(&mut self, packets: &[Packet]) -> Result<()>;
+
+    /// Push out anything buffered. Called once the stream ends.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Push out what's buffered while the stream carries on, for
+    /// --max-latency. Only outputs that buffer have anything to do.
+    fn flush_buffered(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Carry on in a fresh file at the same path, after logrotate has moved
+    /// the old one away. Only files have anything to do.
+    fn reopen(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Arrival time prefix for text output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimestampFormat {
+    /// RFC 3339 UTC with microseconds
+    Iso,
+    /// seconds since the unix epoch
+    Epoch,
+    /// microseconds since the previous packet
+    Delta,
+}
+
+/// Output layout chosen with --output-format, instead of the packet type's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// One packet per line
+    Text,
+    /// Each payload after its u32 little endian length
+    Binary,
+    /// Each payload after its u64 little endian arrival time in nanoseconds
+    /// since the unix epoch and u32 little endian length, for replays with
+    /// the gaps between packets kept
+    Timed,
+    /// Payloads back to back with nothing added, for data split across datagrams
+    Raw,
+    /// One JSON object per line, the payload base64 encoded, for --input-format jsonl
+    Jsonl,
+    /// SDDS or VITA-49 samples as a SigMF recording, NAME.sigmf-data and NAME.sigmf-meta
+    Sigmf,
+    /// A pcap capture for Wireshark, with UDP/IP headers made up around each payload
+    Pcap,
+}
+
+/// How packets are laid out in a byte stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// One packet per line, optionally prefixed with its arrival time. Packets
+    /// without a trailing newline get one unless append_newline is false.
+    Text {
+        timestamps: Option<TimestampFormat>,
+        append_newline: bool,
+    },
+    /// u32 little endian length then the payload
+    LengthPrefixed,
+    /// u64 little endian arrival time in nanoseconds, u32 little endian
+    /// length, then the payload
+    Timed,
+    /// The payload alone; packet boundaries are lost
+    Raw,
+    /// {"payload_b64": ..., "timestamp_us": ...} per line
+    JsonLines,
+    /// A pcap file header, then a record per packet, see [`crate::pcap`].
+    /// Packets without a destination are put down as sent to fallback.
+    Pcap { fallback: Option<SocketAddrV4> },
+}
+
+impl Framing {
+    /// Text lines, a newline added to packets without one
+    pub fn text(timestamps: Option<TimestampFormat>) -> Self {
+        Framing::Text {
+            timestamps,
+            append_newline: true,
+        }
+    }
+
+    /// The same framing, but text output writes packets exactly as they are
+    pub fn without_newline(self) -> Self {
+        match self {
+            Framing::Text { timestamps, .. } => Framing::Text {
+                timestamps,
+                append_newline: false,
+            },
+            framing => framing,
+        }
+    }
+
+    /// The same framing, but a capture puts packets without a destination
+    /// down as sent to fallback rather than to 0.0.0.0:0
+    pub fn with_fallback(self, fallback: Option<SocketAddrV4>) -> Self {
+        match self {
+            Framing::Pcap { .. } => Framing::Pcap { fallback },
+            framing => framing,
+        }
+    }
+
+    pub fn for_packet_type(packet_type: PacketType, timestamps: Option<TimestampFormat>) -> Self {
+        match packet_type {
+            PacketType::Text => Framing::text(timestamps),
+            _ => Framing::LengthPrefixed,
+        }
+    }
+
+    /// The packet type's framing unless format says otherwise.
+    pub fn new(
+        format: Option<OutputFormat>,
+        packet_type: PacketType,
+        timestamps: Option<TimestampFormat>,
+    ) -> Self {
+        match format {
+            None => Framing::for_packet_type(packet_type, timestamps),
+            Some(OutputFormat::Text) => Framing::text(timestamps),
+            Some(OutputFormat::Binary) => Framing::LengthPrefixed,
+            Some(OutputFormat::Timed) => Framing::Timed,
+            Some(OutputFormat::Raw) | Some(OutputFormat::Sigmf) => Framing::Raw,
+            Some(OutputFormat::Jsonl) => Framing::JsonLines,
+            Some(OutputFormat::Pcap) => Framing::Pcap { fallback: None },
+        }
+    }
+}
+
+/// What --label puts in front of each line of text output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Label {
+    /// The same on every line
+    Fixed(String),
+    /// Each packet's group:port, or this for packets without one
+    Destination(String),
+}
+
+impl Label {
+    /// The label given, or the one for packets without a destination.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Label::Fixed(label) | Label::Destination(label) => label,
+        }
+    }
+
+    /// The prefix for a line of packet.
+    pub fn prefix(&self, packet: &Packet) -> String {
+        match (self, packet.destination()) {
+            (Label::Destination(_), Some(destination)) => format!("[{destination}] "),
+            _ => format!("[{}] ", self.as_str()),
+        }
+    }
+}
+
+/// Any byte stream: files, stdout, a child's stdin.
+pub struct StreamSink<W: Write + Send> {
+    name: String,
+    writer: W,
+    framing: Framing,
+    timestamper: Option<Timestamper>,
+    label: Option<Label>,
+    names: Option<Arc<HostNames>>,
+    // The last packet ended its line, so the next one starts a new line
+    at_line_start: bool,
+    gap_marker: Option<GapMarker>,
+    // pcap output starts with a file header, written with the first packet
+    header_due: bool,
+}
+
+impl<W: Write + Send> StreamSink<W> {
+    pub fn new(name: impl Into<String>, writer: W, framing: Framing) -> Self {
+        let timestamper = match framing {
+            Framing::Text {
+                timestamps: Some(format),
+                ..
+            } => Some(Timestamper::new(format)),
+            _ => None,
+        };
+
+        Self {
+            name: name.into(),
+            writer,
+            framing,
+            timestamper,
+            label: None,
+            names: None,
+            at_line_start: true,
+            gap_marker: None,
+            header_due: matches!(framing, Framing::Pcap { .. }),
+        }
+    }
+
+    /// Start every line of text output with label. Other framings ignore it.
+    pub fn with_label(mut self, label: Option<Label>) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Record each packet's source in JSON Lines output, with its host name
+    /// once names has it. Nothing changes unless names was started.
+    pub fn with_names(mut self, names: Arc<HostNames>) -> Self {
+        self.names = names.is_started().then_some(names);
+        self
+    }
+
+    /// Write a marker record where packets are missing, for --mark-gaps.
+    /// Only length prefixed output has anywhere to put one.
+    pub fn with_gap_marker(mut self, marker: Option<GapMarker>) -> Self {
+        self.gap_marker = marker.filter(|_| self.framing == Framing::LengthPrefixed);
+        self
+    }
+
+    /// Carry on after what's already written, file header and all.
+    fn continuing(mut self) -> Self {
+        self.header_due = false;
+        self
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.header_due {
+            pcap::write_header(&mut self.writer)?;
+            self.header_due = false;
+        }
+        Ok(())
+    }
+}
+
+const FILE_BUFFER_BYTES: usize = 1024 * 1024;
+
+impl StreamSink<Stdout> {
+    pub fn stdout(framing: Framing) -> Self {
+        Self::new("stdout", io::stdout(), framing)
+    }
+}
+
+impl StreamSink<ExecWriter> {
+    pub fn exec(command: &str, framing: Framing) -> Result<Self> {
+        Ok(Self::new(
+            format!("`{command}`"),
+            ExecWriter::new(command)?,
+            framing,
+        ))
+    }
+}
+
+impl<W: Write + Send> Sink for StreamSink<W> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn write_packets(&mut self, packets: &[Packet]) -> Result<()> {
+        self.write_header()?;
+        for packet in packets {
+            match self.framing {
+                Framing::Text { append_newline, .. } => {
+                    let label = self.label.as_ref().map(|label| label.prefix(packet));
+                    // A packet carrying on a line without a newline isn't a new line
+                    if let Some(label) = &label
+                        && self.at_line_start
+                    {
+                        self.writer.write_all(label.as_bytes())?;
+                    }
+                    if let Some(timestamper) = self.timestamper.as_mut() {
+                        let arrival = packet.timestamp().unwrap_or_else(SystemTime::now);
+                        self.writer
+                            .write_all(timestamper.prefix(arrival).as_bytes())?;
+                    }
+
+                    match &label {
+                        Some(label) => {
+                            for (i, line) in packet.split_inclusive(|b| *b == b'\n').enumerate() {
+                                if i > 0 {
+                                    self.writer.write_all(label.as_bytes())?;
+                                }
+                                self.writer.write_all(line)?;
+                            }
+                        }
+                        None => self.writer.write_all(packet)?,
+                    }
+
+                    if append_newline && !packet.ends_with(b"\n") {
+                        self.writer.write_all(b"\n")?;
+                        self.at_line_start = true;
+                    } else if !packet.is_empty() {
+                        self.at_line_start = packet.ends_with(b"\n");
+                    }
+                }
+                Framing::LengthPrefixed => {
+                    if let Some(marker) = self.gap_marker.as_mut() {
+                        if let Some(gap) = marker.observe(packet) {
+                            let word = gaps::marker_length_word(gap.skipped);
+                            self.writer.write_all(&word.to_le_bytes())?;
+                            let record = marker.add_record();
+                            marker.mark(format!("{} record {record}", self.name), gap.skipped);
+                        }
+                        marker.add_record();
+                    }
+                    let length = packet.len() as u32;
+                    self.writer.write_all(&length.to_le_bytes())?;
+                    self.writer.write_all(packet)?;
+                }
+                Framing::Timed => {
+                    let arrival = packet.timestamp().unwrap_or_else(SystemTime::now);
+                    let nanos = arrival
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_nanos();
+                    let nanos = u64::try_from(nanos).unwrap_or(u64::MAX);
+                    self.writer.write_all(&nanos.to_le_bytes())?;
+                    self.writer
+                        .write_all(&(packet.len() as u32).to_le_bytes())?;
+                    self.writer.write_all(packet)?;
+                }
+                Framing::Raw => self.writer.write_all(packet)?,
+                Framing::JsonLines => {
+                    let arrival = packet.timestamp().unwrap_or_else(SystemTime::now);
+                    let micros = arrival
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_micros();
+                    write!(
+                        self.writer,
+                        r#"{{"payload_b64":"{}","timestamp_us":{micros}"#,
+                        BASE64.encode(&packet[..])
+                    )?;
+                    // Replays send it back there
+                    if let Some(destination) = packet.destination() {
+                        write!(self.writer, r#","dst":"{destination}""#)?;
+                    }
+                    if let (Some(names), Some(source)) = (&self.names, packet.source()) {
+                        write!(self.writer, r#","src":"{source}""#)?;
+                        if let Some(name) = names.name(*source.ip()) {
+                            write!(self.writer, r#","src_name":{}"#, serde_json::json!(name))?;
+                        }
+                    }
+                    writeln!(self.writer, "}}")?;
+                }
+                Framing::Pcap { fallback } => {
+                    pcap::write_record(&mut self.writer, packet, fallback)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // BufWriter swallows errors on drop, so a full disk has to surface here.
+    // A pcap capture with no packets still gets its header.
+    fn flush(&mut self) -> Result<()> {
+        self.write_header()?;
+        Ok(self.writer.flush()?)
+    }
+
+    // Stdout holds what comes after the last newline
+    fn flush_buffered(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+// A file on a full disk tries its held record again this often.
+const DISK_FULL_RETRY: Duration = Duration::from_secs(1);
+
+/// An output file that SIGHUP swaps for a new one at the same path.
+pub struct FileSink {
+    // Frames each packet on its own, so that a full disk can't tear a record
+    records: StreamSink<Vec<u8>>,
+    writer: BufWriter<File>,
+    // Records in writer's buffer, lost with it if the disk never has room
+    buffered: u64,
+    // Where packets dropped on a full disk are counted, None to fail instead
+    disk_full: Option<Arc<AtomicU64>>,
+    paused: Option<DiskFull>,
+}
+
+/// A file waiting for room on its disk.
+struct DiskFull {
+    since: Instant,
+    tried: Instant,
+    // The record that didn't fit, written first once one does
+    record: Vec<u8>,
+    dropped: u64,
+}
+
+fn is_disk_full(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+    )
+}
+
+impl FileSink {
+    pub fn create(filename: &str, framing: Framing) -> Result<Self> {
+        Ok(Self::new(filename, File::create(filename)?, framing))
+    }
+
+    /// Carry on at the end of filename, creating it if it isn't there.
+    pub fn append(filename: &str, framing: Framing) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(filename)?;
+        let written = file.metadata()?.len() > 0;
+        let mut sink = Self::new(filename, file, framing);
+        if written {
+            sink.records = sink.records.continuing();
+        }
+        Ok(sink)
+    }
+
+    fn new(filename: &str, file: File, framing: Framing) -> Self {
+        Self {
+            records: StreamSink::new(filename, Vec::new(), framing),
+            writer: BufWriter::with_capacity(FILE_BUFFER_BYTES, file),
+            buffered: 0,
+            disk_full: None,
+            paused: None,
+        }
+    }
+}
+
+impl FileSink {
+    /// Start every line of text output with label.
+    pub fn with_label(self, label: Option<Label>) -> Self {
+        Self {
+            records: self.records.with_label(label),
+            ..self
+        }
+    }
+
+    /// Record each packet's source in JSON Lines output.
+    pub fn with_names(self, names: Arc<HostNames>) -> Self {
+        Self {
+            records: self.records.with_names(names),
+            ..self
+        }
+    }
+
+    /// Write a marker record where packets are missing, for --mark-gaps.
+    pub fn with_gap_marker(self, marker: Option<GapMarker>) -> Self {
+        Self {
+            records: self.records.with_gap_marker(marker),
+            ..self
+        }
+    }
+
+    /// On a full disk, drop packets and count them in dropped until there
+    /// is room again, rather than fail. None fails.
+    pub fn with_disk_full(mut self, dropped: Option<Arc<AtomicU64>>) -> Self {
+        self.disk_full = dropped;
+        self
+    }
+
+    /// Add record to the buffer, flushing the buffer first if it has no room.
+    fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        if self.writer.buffer().len() + record.len() > self.writer.capacity() {
+            self.writer.flush()?;
+            self.buffered = 0;
+        }
+        self.writer.write_all(record)?;
+        self.buffered += 1;
+        Ok(())
+    }
+
+    // Fail unless e is a full disk and those are waited out
+    fn pause(&mut self, e: io::Error, record: Vec<u8>) -> Result<()> {
+        if self.disk_full.is_none() || !is_disk_full(&e) {
+            return Err(e.into());
+        }
+        log::warn!(
+            "{}: {e}, dropping packets until there is room",
+            self.records.name
+        );
+        let now = Instant::now();
+        self.paused = Some(DiskFull {
+            since: now,
+            tried: now,
+            record,
+            dropped: 0,
+        });
+        Ok(())
+    }
+
+    /// Whether the disk has room again, trying once every DISK_FULL_RETRY
+    /// unless now.
+    fn resume(&mut self, now: bool) -> Result<bool> {
+        let Some(paused) = self.paused.as_mut() else {
+            return Ok(true);
+        };
+        if !now && paused.tried.elapsed() < DISK_FULL_RETRY {
+            return Ok(false);
+        }
+        paused.tried = Instant::now();
+        let record = std::mem::take(&mut paused.record);
+        // What the buffer held on to goes first, then the record that didn't fit
+        match self.writer.flush().and_then(|()| {
+            self.buffered = 0;
+            self.write_record(&record)
+        }) {
+            Ok(()) => {}
+            Err(e) if is_disk_full(&e) => {
+                if let Some(paused) = self.paused.as_mut() {
+                    paused.record = record;
+                }
+                return Ok(false);
+            }
+            Err(e) => return Err(e.into()),
+        }
+        if let Some(paused) = self.paused.take() {
+            log::info!(
+                "{}: room again after {:.1}s, {} packets dropped",
+                self.records.name,
+                paused.since.elapsed().as_secs_f64(),
+                paused.dropped
+            );
+        }
+        Ok(true)
+    }
+
+    fn drop_packets(&mut self, count: u64) {
+        if let Some(paused) = self.paused.as_mut() {
+            paused.dropped += count;
+        }
+        if let Some(dropped) = &self.disk_full {
+            dropped.fetch_add(count, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Sink for FileSink {
+    fn name(&self) -> &str {
+        self.records.name()
+    }
+
+    fn write_packets(&mut self, packets: &[Packet]) -> Result<()> {
+        for packet in packets {
+            if !self.resume(false)? {
+                self.drop_packets(1);
+                continue;
+            }
+            self.records.writer.clear();
+            self.records.write_packets(std::slice::from_ref(packet))?;
+            let record = std::mem::take(&mut self.records.writer);
+            match self.write_record(&record) {
+                Ok(()) => self.records.writer = record,
+                Err(e) => self.pause(e, record)?,
+            }
+        }
+        Ok(())
+    }
+
+    // BufWriter swallows errors on drop, so a full disk has to surface here.
+    fn flush(&mut self) -> Result<()> {
+        if self.records.header_due && self.paused.is_none() {
+            self.records.writer.clear();
+            self.records.write_header()?;
+            let header = std::mem::take(&mut self.records.writer);
+            match self.writer.write_all(&header) {
+                Ok(()) => self.records.writer = header,
+                Err(e) => self.pause(e, header)?,
+            }
+        }
+        let flushed = if self.resume(true)? {
+            self.writer.flush()
+        } else {
+            Err(io::ErrorKind::StorageFull.into())
+        };
+        match flushed {
+            Ok(()) => {
+                self.buffered = 0;
+                Ok(())
+            }
+            Err(e) if self.disk_full.is_some() && is_disk_full(&e) => {
+                // Nothing gets another try: the buffer and any held record are lost
+                let held = self.paused.as_mut().map(|paused| {
+                    paused.record.clear();
+                    1
+                });
+                let lost = self.buffered + held.unwrap_or(0);
+                self.drop_packets(lost);
+                self.buffered = 0;
+                log::warn!(
+                    "{}: still no room, the last {lost} packets were never written",
+                    self.records.name
+                );
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // A full disk is left for the next write to wait out
+    fn flush_buffered(&mut self) -> Result<()> {
+        if self.paused.is_some() {
+            return Ok(());
+        }
+        match self.writer.flush() {
+            Ok(()) => {
+                self.buffered = 0;
+                Ok(())
+            }
+            Err(e) if self.disk_full.is_some() && is_disk_full(&e) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Appends, in case the file is still there because nothing moved it
+    fn reopen(&mut self) -> Result<()> {
+        self.flush()?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.records.name)?;
+        // A new pcap file needs its own header
+        self.records.header_due =
+            matches!(self.records.framing, Framing::Pcap { .. }) && file.metadata()?.len() == 0;
+        self.writer = BufWriter::with_capacity(FILE_BUFFER_BYTES, file);
+        if let Some(marker) = self.records.gap_marker.as_mut() {
+            marker.new_file();
+        }
+        log::info!("reopened {}", self.records.name);
+        Ok(())
+    }
+}
+
+/// How --split-by divides an output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SplitBy {
+    /// One file per sender address and port
+    Source,
+    /// One file per VITA-49 stream id, of each frame's first VRT packet
+    StreamId,
+}
+
+impl SplitBy {
+    /// Which file a packet belongs in.
+    pub fn key(self, packet: &Packet) -> SplitKey {
+        let key = match self {
+            SplitBy::Source => packet.source().map(SplitKey::Source),
+            SplitBy::StreamId => vita49::parse_vrt(packet)
+                .and_then(|vrt| vrt.stream_id)
+                .map(SplitKey::Stream),
+        };
+        key.unwrap_or(SplitKey::Unknown)
+    }
+
+    /// What the split files hold, for the summary.
+    pub fn noun(self) -> &'static str {
+        match self {
+            SplitBy::Source => "sources",
+            SplitBy::StreamId => "streams",
+        }
+    }
+}
+
+/// A split file's share of the packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SplitKey {
+    Source(SocketAddrV4),
+    Stream(u32),
+    /// No source, or no stream id that could be parsed
+    Unknown,
+}
+
+impl std::fmt::Display for SplitKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SplitKey::Source(source) => write!(f, "{source}"),
+            SplitKey::Stream(stream_id) => write!(f, "{stream_id:#010x}"),
+            SplitKey::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Bytes written to each split file, shared with the final summary.
+#[derive(Debug, Default)]
+pub struct SplitCounters {
+    bytes: Mutex<BTreeMap<SplitKey, u64>>,
+}
+
+impl SplitCounters {
+    fn add(&self, key: SplitKey, bytes: u64) {
+        if let Ok(mut totals) = self.bytes.lock() {
+            *totals.entry(key).or_default() += bytes;
+        }
+    }
+
+    /// Bytes so far per key, in key order.
+    pub fn get(&self) -> Vec<(SplitKey, u64)> {
+        self.bytes
+            .lock()
+            .map(|totals| totals.iter().map(|(key, bytes)| (*key, *bytes)).collect())
+            .unwrap_or_default()
+    }
+}
+
+// A split file left unwritten this long is closed, so senders that come and
+// go don't use up file descriptors. It is appended to if they come back.
+const SPLIT_IDLE_CLOSE: Duration = Duration::from_secs(30);
+
+/// An output file per source or stream, named from the output's: {ip},
+/// {port} and {stream} are filled in, and without any of them the key goes
+/// in before the extension. Each file has its own framing state, and SIGHUP
+/// reopens them all.
+pub struct SplitSink {
+    name: String,
+    template: String,
+    framing: Framing,
+    label: Option<Label>,
+    names: Option<Arc<HostNames>>,
+    disk_full: Option<Arc<AtomicU64>>,
+    split_by: SplitBy,
+    counters: Arc<SplitCounters>,
+    idle_close: Duration,
+    open: HashMap<SplitKey, (FileSink, Instant)>,
+    // Created this run, so appended to rather than truncated when reopened
+    created: HashSet<SplitKey>,
+}
+
+impl SplitSink {
+    pub fn new(
+        template: &str,
+        framing: Framing,
+        split_by: SplitBy,
+        counters: Arc<SplitCounters>,
+    ) -> Self {
+        Self {
+            name: format!("{template} per {}", split_by.noun().trim_end_matches('s')),
+            template: template.to_string(),
+            framing,
+            label: None,
+            names: None,
+            disk_full: None,
+            split_by,
+            counters,
+            idle_close: SPLIT_IDLE_CLOSE,
+            open: HashMap::new(),
+            created: HashSet::new(),
+        }
+    }
+
+    /// Start every line of each file's text output with label.
+    pub fn with_label(mut self, label: Option<Label>) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Record each packet's source in each file's JSON Lines output.
+    pub fn with_names(mut self, names: Arc<HostNames>) -> Self {
+        self.names = Some(names);
+        self
+    }
+
+    /// Wait out a full disk in each file, as FileSink::with_disk_full.
+    pub fn with_disk_full(mut self, dropped: Option<Arc<AtomicU64>>) -> Self {
+        self.disk_full = dropped;
+        self
+    }
+
+    fn file_for(&mut self, key: SplitKey) -> Result<&mut (FileSink, Instant)> {
+        let file = match self.open.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let filename = split_filename(&self.template, key);
+                let sink = if self.created.insert(key) {
+                    log::info!("writing {key} to {filename}");
+                    FileSink::create(&filename, self.framing)?
+                } else {
+                    log::debug!("reopening {filename}");
+                    FileSink::append(&filename, self.framing)?
+                };
+                let sink = match &self.names {
+                    Some(names) => sink.with_names(names.clone()),
+                    None => sink,
+                };
+                let sink = sink
+                    .with_label(self.label.clone())
+                    .with_disk_full(self.disk_full.clone());
+                entry.insert((sink, Instant::now()))
+            }
+        };
+        Ok(file)
+    }
+
+    // Flush and close the files nothing has been written to for a while
+    fn close_idle(&mut self, now: Instant) -> Result<()> {
+        let idle_close = self.idle_close;
+        let mut result = Ok(());
+        self.open.retain(|_, (sink, last)| {
+            if now.duration_since(*last) < idle_close {
+                return true;
+            }
+            log::debug!("closing idle {}", sink.name());
+            if let Err(e) = sink.flush() {
+                result = Err(e);
+            }
+            false
+        });
+        result
+    }
+}
+
+impl Sink for SplitSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn write_packets(&mut self, packets: &[Packet]) -> Result<()> {
+        let now = Instant::now();
+        let split_by = self.split_by;
+        for run in packets.chunk_by(|a, b| split_by.key(a) == split_by.key(b)) {
+            let Some(first) = run.first() else {
+                continue;
+            };
+            let key = split_by.key(first);
+            let (sink, last) = self.file_for(key)?;
+            sink.write_packets(run)?;
+            *last = now;
+            self.counters
+                .add(key, run.iter().map(|packet| packet.len() as u64).sum());
+        }
+        self.close_idle(now)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for (sink, _) in self.open.values_mut() {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush_buffered(&mut self) -> Result<()> {
+        for (sink, _) in self.open.values_mut() {
+            sink.flush_buffered()?;
+        }
+        Ok(())
+    }
+
+    // Closed now and reopened, appending, by the next packet for each file
+    fn reopen(&mut self) -> Result<()> {
+        self.flush()?;
+        self.open.clear();
+        log::info!("reopened {}", self.name);
+        Ok(())
+    }
+}
+
+/// The file a key's packets go to: template with {ip}, {port} and {stream}
+/// filled in, or with the key added before the extension,
+/// capture.bin -> capture-10.0.0.1-5000.bin or capture-0000002a.bin.
+pub fn split_filename(template: &str, key: SplitKey) -> String {
+    let unknown = || "unknown".to_string();
+    let (ip, port, stream, tag) = match key {
+        SplitKey::Source(source) => (
+            source.ip().to_string(),
+            source.port().to_string(),
+            unknown(),
+            format!("{}-{}", source.ip(), source.port()),
+        ),
+        SplitKey::Stream(stream_id) => {
+            let stream = format!("{stream_id:08x}");
+            (unknown(), unknown(), stream.clone(), stream)
+        }
+        SplitKey::Unknown => (unknown(), unknown(), unknown(), unknown()),
+    };
+    if ["{ip}", "{port}", "{stream}"]
+        .iter()
+        .any(|field| template.contains(field))
+    {
+        return template
+            .replace("{ip}", &ip)
+            .replace("{port}", &port)
+            .replace("{stream}", &stream);
+    }
+
+    // An extension is a dot in the last path component, past its first byte
+    let basename = template.rfind('/').map_or(0, |slash| slash + 1);
+    match template.rfind('.').filter(|dot| *dot > basename) {
+        Some(dot) => format!(
+            "{}-{tag}{}",
+            template.get(..dot).unwrap_or_default(),
+            template.get(dot..).unwrap_or_default()
+        ),
+        None => format!("{template}-{tag}"),
+    }
+}
+
+/// Runs a command per packet with the payload on its stdin.
+pub struct ExecPerPacketSink {
+    name: String,
+    command: String,
+    failures: u32,
+}
+
+impl ExecPerPacketSink {
+    pub fn new(command: &str) -> Self {
+        Self {
+            name: format!("`{command}` per packet"),
+            command: command.to_string(),
+            failures: 0,
+        }
+    }
+}
+
+impl Sink for ExecPerPacketSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn write_packets(&mut self, packets: &[Packet]) -> Result<()> {
+        for packet in packets {
+            if exec::run_once(&self.command, packet)? {
+                self.failures = 0;
+                continue;
+            }
+
+            self.failures += 1;
+            log::warn!("exec: `{}` failed", self.command);
+            if self.failures >= MAX_EXEC_FAILURES {
+                return Err(LibError::Critical(format!(
+                    "exec: `{}` failed {} times in a row",
+                    self.command, self.failures
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends to the multicast group, batched with sendmmsg unless rate limited.
+/// Why a send failed, as far as someone watching the stats cares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendErrorClass {
+    /// The kernel had no room: ENOBUFS, EAGAIN, ENOMEM, EINTR. Retried.
+    Congestion,
+    /// No route to the group: ENETUNREACH, EHOSTUNREACH, ENETDOWN
+    Unreachable,
+    /// Refused by a firewall or an ICMP error: EPERM, EACCES, ECONNREFUSED
+    Refused,
+    /// The datagram itself was rejected, e.g. EMSGSIZE
+    Packet,
+}
+
+impl SendErrorClass {
+    pub const ALL: [SendErrorClass; 4] = [
+        SendErrorClass::Congestion,
+        SendErrorClass::Unreachable,
+        SendErrorClass::Refused,
+        SendErrorClass::Packet,
+    ];
+
+    /// None for errors that mean the socket itself is unusable.
+    pub fn of(errno: Errno) -> Option<Self> {
+        match errno {
+            Errno::ENOBUFS | Errno::EAGAIN | Errno::ENOMEM | Errno::EINTR => Some(Self::Congestion),
+            Errno::ENETUNREACH | Errno::EHOSTUNREACH | Errno::ENETDOWN => Some(Self::Unreachable),
+            Errno::EPERM | Errno::EACCES | Errno::ECONNREFUSED => Some(Self::Refused),
+            Errno::EMSGSIZE | Errno::EINVAL => Some(Self::Packet),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Congestion => "congestion",
+            Self::Unreachable => "unreachable",
+            Self::Refused => "refused",
+            Self::Packet => "rejected",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Running totals for the network sink, read by the statistics thread.
+#[derive(Debug, Default)]
+pub struct TransmitCounters {
+    sent: AtomicU64,
+    retries: AtomicU64,
+    errors: [AtomicU64; SendErrorClass::ALL.len()],
+    skipped: AtomicU64,
+    // Set once by the sink when it sends to more than one group or port
+    destinations: OnceLock<Vec<(SocketAddrV4, AtomicU64)>>,
+}
+
+/// A snapshot of [`TransmitCounters`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TransmitTotals {
+    pub sent: u64,
+    pub retries: u64,
+    pub errors: [u64; SendErrorClass::ALL.len()],
+    /// Replayed packets not sent because their destination isn't multicast
+    pub skipped: u64,
+    /// Sent per group and port, empty with a single destination
+    pub per_destination: Vec<(SocketAddrV4, u64)>,
+}
+
+impl TransmitCounters {
+    fn set_destinations(&self, destinations: &[SocketAddrV4]) {
+        let _ = self.destinations.set(
+            destinations
+                .iter()
+                .map(|destination| (*destination, AtomicU64::new(0)))
+                .collect(),
+        );
+    }
+    fn add_sent(&self, count: u64) {
+        self.sent.fetch_add(count, Ordering::Relaxed);
+    }
+    fn add_sent_to(&self, destination: usize) {
+        if let Some((_, sent)) = self
+            .destinations
+            .get()
+            .and_then(|destinations| destinations.get(destination))
+        {
+            sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    fn add_skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+    fn add_retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+    fn add_error(&self, class: SendErrorClass) {
+        if let Some(errors) = self.errors.get(class.index()) {
+            errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    pub fn get(&self) -> TransmitTotals {
+        TransmitTotals {
+            sent: self.sent.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            errors: self
+                .errors
+                .each_ref()
+                .map(|errors| errors.load(Ordering::Relaxed)),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            per_destination: self
+                .destinations
+                .get()
+                .map(|destinations| {
+                    destinations
+                        .iter()
+                        .map(|(group, sent)| (*group, sent.load(Ordering::Relaxed)))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl TransmitTotals {
+    /// What happened between earlier and self.
+    pub fn since(&self, earlier: &TransmitTotals) -> TransmitTotals {
+        let mut errors = self.errors;
+        for (errors, earlier) in errors.iter_mut().zip(earlier.errors) {
+            *errors = errors.saturating_sub(earlier);
+        }
+        let per_destination = self
+            .per_destination
+            .iter()
+            .map(|&(group, sent)| {
+                let before = earlier
+                    .per_destination
+                    .iter()
+                    .find(|(earlier_group, _)| *earlier_group == group)
+                    .map_or(0, |(_, sent)| *sent);
+                (group, sent.saturating_sub(before))
+            })
+            .collect();
+        TransmitTotals {
+            sent: self.sent.saturating_sub(earlier.sent),
+            retries: self.retries.saturating_sub(earlier.retries),
+            errors,
+            skipped: self.skipped.saturating_sub(earlier.skipped),
+            per_destination,
+        }
+    }
+
+    pub fn total_errors(&self) -> u64 {
+        self.errors.iter().sum()
+    }
+}
+
+/// How packets are spread over several destination groups. Each packet
+/// goes to every port of the groups it's sent to either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Fanout {
+    /// each packet goes to the next group in turn
+    #[default]
+    #[value(name = "rr")]
+    RoundRobin,
+    /// every packet goes to every group
+    #[value(name = "dup")]
+    Duplicate,
+}
+
+/// Sends to the multicast group, counting what actually went out.
+/// Send errors go to the counters rather than the log: congestion is retried
+/// a few times, anything else costs the packet but not the sink.
+///
+/// A single group and port uses a connected socket. With a list or range of
+/// groups, or several ports, each message carries its own destination: the
+/// groups are spread according to fanout, and every port gets a copy.
+/// Replays can instead send each packet where it was recorded going, see
+/// [`NetworkSink::with_packet_destinations`].
+///
+/// With a txtime schedule every packet goes out on its own with a launch time
+/// for the qdisc, see [`NetworkSink::with_txtime`].
+pub struct NetworkSink {
+    name: String,
+    socket: Socket,
+    // First group, for finding the outgoing interface
+    group: Ipv4Addr,
+    rate: Option<u64>,
+    schedule: Option<txtime::Schedule>,
+    // Offset to write the send time at, see [`crate::latency`]
+    stamp: Option<usize>,
+    // Where to write a sequence number, see [`crate::sequence`]
+    stamp_seq: Option<SeqField>,
+    // Next sequence number for each destination, so every group sees a run
+    sequence: Vec<u32>,
+    // Stamped copies, since the batch itself is shared with the other sinks
+    stamped: Vec<Packet>,
+    // FEC framing for each destination, see [`crate::fec`]
+    fec: Option<(u8, Arc<FecCounters>)>,
+    encoders: Vec<FecEncoder>,
+    // Rewrites the copies' bytes after stamping and framing, see [`crate::patch`]
+    patcher: Option<(Patcher, Arc<PatchCounters>)>,
+    // A fixed header put in front of each copy last of all, for --prepend
+    prepend: Vec<u8>,
+    // A marker put after each copy, for --loop-guard
+    trailer: Vec<u8>,
+    // Empty when the socket is connected to the only group. The configured
+    // groups come first, then packets' own destinations as they turn up.
+    destinations: Vec<SockaddrStorage>,
+    // How many of destinations were configured, at least 1
+    groups: usize,
+    // Ports per group: each group's come one after the other in destinations
+    ports: usize,
+    // First port, for a packet destination on the connected socket
+    port: u16,
+    // Index into destinations of each packet destination seen so far
+    packet_destinations: Option<HashMap<SocketAddrV4, usize>>,
+    warned_skip: bool,
+    fanout: Fanout,
+    // Round robin position, carried across batches
+    next: usize,
+    counters: Arc<TransmitCounters>,
+    // Each class of error is logged as a warning once, then only at debug
+    warned: [bool; SendErrorClass::ALL.len()],
+}
+
+impl NetworkSink {
+    /// mgroup may be a list or range of groups, see [`parse_groups`], and
+    /// every packet sent to a group goes to each of ports.
+    pub fn new(
+        iface: Option<&str>,
+        mgroup: &str,
+        ports: &[u16],
+        ttl: u8,
+        rate: Option<u64>,
+        fanout: Fanout,
+        counters: Arc<TransmitCounters>,
+    ) -> Result<Self> {
+        let iface_str = match iface {
+            Some(iface_str) => format!("{iface_str}:"),
+            None => "".to_string(),
+        };
+
+        let groups = parse_groups(mgroup)?;
+        let Some(&port) = ports.first() else {
+            return Err(LibError::Critical(format!("{mgroup}: no ports")));
+        };
+        let addresses: Vec<SocketAddrV4> = groups
+            .iter()
+            .flat_map(|group| ports.iter().map(|port| SocketAddrV4::new(*group, *port)))
+            .collect();
+        let (socket, group, destinations) = match (groups.as_slice(), addresses.as_slice()) {
+            ([group], [_]) => (
+                create_send_socket(iface, &group.to_string(), port, ttl)?,
+                *group,
+                Vec::new(),
+            ),
+            ([first, ..], [_, _, ..]) => {
+                counters.set_destinations(&addresses);
+                (
+                    create_unconnected_send_socket(iface, first, ttl)?,
+                    *first,
+                    addresses.iter().map(|address| (*address).into()).collect(),
+                )
+            }
+            _ => return Err(LibError::Critical(format!("{mgroup}: no groups"))),
+        };
+
+        Ok(Self {
+            name: format!("{iface_str}{mgroup}"),
+            socket,
+            group,
+            rate,
+            schedule: None,
+            stamp: None,
+            stamp_seq: None,
+            sequence: Vec::new(),
+            stamped: Vec::new(),
+            fec: None,
+            encoders: Vec::new(),
+            patcher: None,
+            prepend: Vec::new(),
+            trailer: Vec::new(),
+            groups: destinations.len().max(1),
+            destinations,
+            ports: ports.len(),
+            port,
+            packet_destinations: None,
+            warned_skip: false,
+            fanout,
+            next: 0,
+            counters,
+            warned: [false; SendErrorClass::ALL.len()],
+        })
+    }
+
+    /// Launch pps packets a second with SO_TXTIME. Fails when the outgoing
+    /// interface has no etf or fq qdisc to honour the launch times.
+    pub fn with_txtime(mut self, iface: Option<&str>, pps: u64) -> Result<Self> {
+        let interface = preflight::resolve_interface(iface, &self.group)?;
+        let clock = txtime::qdisc_clock(&interface.name)?;
+        txtime::enable(&self.socket, clock)?;
+        self.schedule = Some(txtime::Schedule::new(clock, pps));
+        Ok(self)
+    }
+
+    /// Write the wall clock time into each packet at offset as it is sent.
+    pub fn with_stamp(mut self, offset: usize) -> Self {
+        self.stamp = Some(offset);
+        self
+    }
+
+    /// Send packets that know their destination, like a replayed capture,
+    /// back there instead of to the configured groups. Those that aren't
+    /// for a multicast group are counted as skipped.
+    pub fn with_packet_destinations(mut self, iface: Option<&str>, ttl: u8) -> Result<Self> {
+        if self.destinations.is_empty() {
+            self.socket = create_unconnected_send_socket(iface, &self.group, ttl)?;
+            self.destinations
+                .push(SocketAddrV4::new(self.group, self.port).into());
+        }
+        let known = self
+            .destinations
+            .iter()
+            .enumerate()
+            .filter_map(|(index, address)| {
+                let address = address.as_sockaddr_in()?;
+                Some((SocketAddrV4::new(address.ip(), address.port()), index))
+            })
+            .collect();
+        self.packet_destinations = Some(known);
+        Ok(self)
+    }
+
+    /// Number each packet as it is sent, counting separately per group.
+    pub fn with_stamp_seq(mut self, field: SeqField) -> Self {
+        self.stamp_seq = Some(field);
+        self.sequence = vec![0; self.destinations.len().max(1)];
+        self
+    }
+
+    /// Send a parity packet after every k packets, counting separately per
+    /// group. Each packet gets a small header for the receiver's decoder.
+    pub fn with_fec(mut self, k: u8, counters: Arc<FecCounters>) -> Self {
+        self.encoders = (0..self.destinations.len().max(1))
+            .map(|_| FecEncoder::new(k))
+            .collect();
+        self.fec = Some((k, counters));
+        self
+    }
+
+    /// Rewrite bytes of each packet sent, after any stamping or framing.
+    pub fn with_patcher(mut self, patcher: Patcher, counters: Arc<PatchCounters>) -> Self {
+        self.patcher = Some((patcher, counters));
+        self
+    }
+
+    /// Put a fixed header in front of each packet sent, outside any
+    /// stamping, framing or patches.
+    pub fn with_prepend(mut self, header: Vec<u8>) -> Self {
+        self.prepend = header;
+        self
+    }
+
+    /// Put a fixed marker after each packet sent, outside everything else,
+    /// for a receiver to know them by.
+    pub fn with_trailer(mut self, trailer: Vec<u8>) -> Self {
+        self.trailer = trailer;
+        self
+    }
+
+    /// Where the packets sent come from, as a receiver sees them: the
+    /// outgoing interface's address and the socket's port.
+    pub fn source(&self) -> Option<SocketAddrV4> {
+        let address = self.socket.multicast_if_v4().ok()?;
+        let port = self.socket.local_addr().ok()?.as_socket_ipv4()?.port();
+        Some(SocketAddrV4::new(address, port))
+    }
+
+    /// Frame each message into stamped for its destination's FEC group,
+    /// followed by the group's parity when it completes one, or by the
+    /// parity of every partial group at the end of the stream.
+    /// Returns the destination of each copy.
+    fn fec_copies(&mut self, messages: &[(&Packet, usize)], end: bool) -> Vec<usize> {
+        let mut destinations = Vec::with_capacity(messages.len());
+        let mut parity = 0;
+        // Grown as needed and never shrunk, zip stops at destinations
+        fn next_copy(copies: &mut Vec<Packet>, used: usize) -> Option<&mut Packet> {
+            if copies.len() <= used {
+                copies.resize_with(used + 1, Packet::default);
+            }
+            copies.get_mut(used)
+        }
+
+        for (packet, destination) in messages {
+            let Some(encoder) = self.encoders.get_mut(*destination) else {
+                continue;
+            };
+            let Some(copy) = next_copy(&mut self.stamped, destinations.len()) else {
+                continue;
+            };
+            let full = encoder.encode(copy, packet);
+            destinations.push(*destination);
+            if full && let Some(copy) = next_copy(&mut self.stamped, destinations.len()) {
+                encoder.parity(copy);
+                destinations.push(*destination);
+                parity += 1;
+            }
+        }
+        if end {
+            for (destination, encoder) in self.encoders.iter_mut().enumerate() {
+                if let Some(copy) = next_copy(&mut self.stamped, destinations.len())
+                    && encoder.parity(copy)
+                {
+                    destinations.push(destination);
+                    parity += 1;
+                }
+            }
+        }
+
+        if let Some((_, counters)) = &self.fec {
+            counters.add_sent((destinations.len() - parity) as u64, parity as u64);
+        }
+        destinations
+    }
+
+    /// Copy each message into stamped with its sequence number and the
+    /// current time. Returns the destination of each copy.
+    fn stamp_copies(&mut self, messages: &[(&Packet, usize)]) -> Vec<usize> {
+        self.stamped.resize_with(messages.len(), Packet::default);
+        let now = SystemTime::now();
+        let mut short = 0;
+
+        for (copy, (packet, destination)) in self.stamped.iter_mut().zip(messages) {
+            let stamped = match self.stamp_seq {
+                Some(field) => {
+                    let seq = self.sequence.get_mut(*destination).map_or(0, |next| {
+                        let seq = *next;
+                        *next = seq.wrapping_add(1);
+                        seq
+                    });
+                    sequence::stamp_into(copy, packet, field, seq)
+                }
+                None => {
+                    copy.ensure_capacity(packet.len());
+                    copy.set_length(packet.len());
+                    if let Some(data) = copy.data_mut().get_mut(..packet.len()) {
+                        data.copy_from_slice(packet);
+                    }
+                    true
+                }
+            };
+
+            let timed = match self.stamp {
+                Some(offset) => {
+                    let length = copy.len();
+                    copy.data_mut()
+                        .get_mut(..length)
+                        .is_some_and(|data| latency::stamp(data, offset, now))
+                }
+                None => true,
+            };
+            if !stamped || !timed {
+                short += 1;
+            }
+        }
+
+        if short > 0 {
+            log::debug!("{short} packets too short to stamp");
+        }
+        messages
+            .iter()
+            .map(|(_, destination)| *destination)
+            .collect()
+    }
+
+    /// Pair each packet with the index of the destination it goes to.
+    fn plan<'a>(&mut self, packets: &'a [Packet]) -> Vec<(&'a Packet, usize)> {
+        let mut messages = Vec::with_capacity(packets.len());
+        for packet in packets {
+            if self.packet_destinations.is_some()
+                && let Some(address) = packet.destination()
+            {
+                if let Some(destination) = self.destination_of(address) {
+                    messages.push((packet, destination));
+                }
+                continue;
+            }
+            match self.fanout {
+                Fanout::Duplicate => {
+                    messages.extend((0..self.groups).map(|destination| (packet, destination)))
+                }
+                Fanout::RoundRobin => {
+                    let first = self.next * self.ports;
+                    messages.extend(
+                        (first..first + self.ports).map(|destination| (packet, destination)),
+                    );
+                    self.next = (self.next + 1) % (self.groups / self.ports).max(1);
+                }
+            }
+        }
+        messages
+    }
+
+    /// Index of a packet's own destination, added the first time it's seen.
+    /// None, and counted, when it isn't a multicast group.
+    fn destination_of(&mut self, address: SocketAddrV4) -> Option<usize> {
+        if !address.ip().is_multicast() {
+            self.counters.add_skipped();
+            if !self.warned_skip {
+                self.warned_skip = true;
+                log::warn!(
+                    "not sending to {address}, which isn't a multicast group (further ones are counted as skipped)"
+                );
+            }
+            return None;
+        }
+        let known = self.packet_destinations.as_mut()?;
+        if let Some(&destination) = known.get(&address) {
+            return Some(destination);
+        }
+        let destination = self.destinations.len();
+        known.insert(address, destination);
+        self.destinations.push(address.into());
+        if self.stamp_seq.is_some() {
+            self.sequence.push(0);
+        }
+        if let Some((k, _)) = self.fec {
+            self.encoders.push(FecEncoder::new(k));
+        }
+        Some(destination)
+    }
+
+    /// Send from the front of messages, returning how many went out.
+    fn send(&mut self, messages: &[(&Packet, usize)]) -> nix::Result<usize> {
+        let fd = socket_to_raw_fd(&self.socket);
+        // None on the connected socket
+        let destinations = &self.destinations;
+        let address = |destination: usize| destinations.get(destination).copied();
+
+        if let Some(schedule) = self.schedule.as_mut() {
+            let Some((packet, destination)) = messages.first() else {
+                return Ok(0);
+            };
+            let launch = schedule.next_launch()?;
+            txtime::send_at(fd, packet, launch, address(*destination).as_ref())?;
+            return Ok(1);
+        }
+
+        match self.rate {
+            Some(rate) => {
+                let Some((packet, destination)) = messages.first() else {
+                    return Ok(0);
+                };
+                let iov = [IoSlice::new(packet)];
+                sendmsg(
+                    fd,
+                    &iov,
+                    &[],
+                    MsgFlags::empty(),
+                    address(*destination).as_ref(),
+                )?;
+
+                for _ in 0..rate {
+                    std::hint::spin_loop();
+                }
+                Ok(1)
+            }
+            None => send_batch(fd, messages, destinations),
+        }
+    }
+
+    /// Send messages, counting what went out and what failed.
+    fn transmit(&mut self, messages: &[(&Packet, usize)]) -> Result<()> {
+        let mut remaining = messages;
+        let mut retries = 0;
+
+        while !remaining.is_empty() {
+            match self.send(remaining) {
+                Ok(sent) => {
+                    self.counters.add_sent(sent as u64);
+                    for (_, destination) in remaining.iter().take(sent) {
+                        self.counters.add_sent_to(*destination);
+                    }
+                    remaining = remaining.get(sent.max(1)..).unwrap_or_default();
+                    retries = 0;
+                }
+                Err(errno) => {
+                    let Some(class) = SendErrorClass::of(errno) else {
+                        return Err(errno.into());
+                    };
+                    self.counters.add_error(class);
+                    self.report(errno, class);
+
+                    if class == SendErrorClass::Congestion && retries < MAX_SEND_RETRIES {
+                        retries += 1;
+                        self.counters.add_retry();
+                        std::thread::yield_now();
+                    } else {
+                        // Give up on this packet and carry on with the rest
+                        remaining = remaining.get(1..).unwrap_or_default();
+                        retries = 0;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send the copies in stamped, each to its destination.
+    fn transmit_stamped(&mut self, destinations: Vec<usize>) -> Result<()> {
+        if let Some((patcher, counters)) = self.patcher.as_mut() {
+            for copy in self.stamped.iter_mut().take(destinations.len()) {
+                patcher.apply(copy, counters);
+            }
+        }
+        if !self.prepend.is_empty() {
+            for copy in self.stamped.iter_mut().take(destinations.len()) {
+                copy.prepend(&self.prepend);
+            }
+        }
+        if !self.trailer.is_empty() {
+            for copy in self.stamped.iter_mut().take(destinations.len()) {
+                copy.append(&self.trailer);
+            }
+        }
+        let stamped = std::mem::take(&mut self.stamped);
+        let result = self.transmit(&stamped.iter().zip(destinations).collect::<Vec<_>>());
+        self.stamped = stamped;
+        result
+    }
+
+    fn report(&mut self, errno: Errno, class: SendErrorClass) {
+        let warned = self.warned.get_mut(class.index());
+        match warned {
+            Some(warned) if !*warned => {
+                *warned = true;
+                log::warn!(
+                    "sending to {} failed: {errno} (further {} errors are counted, not logged)",
+                    self.name,
+                    class.name()
+                );
+            }
+            _ => log::debug!("sending to {} failed: {errno}", self.name),
+        }
+    }
+}
+
+impl Sink for NetworkSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn write_packets(&mut self, packets: &[Packet]) -> Result<()> {
+        let messages = self.plan(packets);
+        if self.fec.is_none()
+            && self.stamp.is_none()
+            && self.stamp_seq.is_none()
+            && self.patcher.is_none()
+            && self.prepend.is_empty()
+            && self.trailer.is_empty()
+        {
+            return self.transmit(&messages);
+        }
+
+        let destinations = match self.fec {
+            Some(_) => self.fec_copies(&messages, false),
+            None => self.stamp_copies(&messages),
+        };
+        self.transmit_stamped(destinations)
+    }
+
+    /// The parity of the groups the stream ended part way through.
+    fn flush(&mut self) -> Result<()> {
+        if self.fec.is_none() {
+            return Ok(());
+        }
+        let destinations = self.fec_copies(&[], true);
+        self.transmit_stamped(destinations)
+    }
+}
+
+/// What sendmmsg needs besides the packets, kept from batch to batch.
+/// MultiHeaders holds raw pointers and is not Send, so it can't live in the
+/// sink; every sink on a thread sends in turn, so they share one.
+#[cfg(mmsg)]
+struct SendHeaders {
+    headers: MultiHeaders<SockaddrStorage>,
+    capacity: usize,
+    // Empty between calls, kept for its allocation
+    iovecs: Vec<[IoSlice<'static>; 1]>,
+    addrs: Vec<Option<SockaddrStorage>>,
+}
+
+#[cfg(mmsg)]
+thread_local! {
+    static SEND_HEADERS: RefCell<SendHeaders> = RefCell::new(SendHeaders {
+        headers: MultiHeaders::preallocate(0, None),
+        capacity: 0,
+        iovecs: Vec::new(),
+        addrs: Vec::new(),
+    });
+}
+
+/// An empty Vec in v's allocation. The iovecs borrow each batch's packets, so
+/// they can't be kept as they are, but their memory can; same sized elements
+/// are collected in place.
+#[cfg(mmsg)]
+fn reuse_vec<T, U>(mut v: Vec<T>) -> Vec<U> {
+    v.clear();
+    v.into_iter().filter_map(|_| None).collect()
+}
+
+/// Send messages with one sendmmsg, returning how many went out.
+/// A short count means the next message failed; the retry reports why.
+#[cfg(mmsg)]
+fn send_batch(
+    fd: RawFd,
+    messages: &[(&Packet, usize)],
+    destinations: &[SockaddrStorage],
+) -> nix::Result<usize> {
+    SEND_HEADERS.with_borrow_mut(|scratch| {
+        // sendmmsg sends no more messages than there are headers
+        if scratch.capacity < messages.len() {
+            scratch.headers = MultiHeaders::preallocate(messages.len(), None);
+            scratch.capacity = messages.len();
+        }
+
+        let mut iovecs = reuse_vec(std::mem::take(&mut scratch.iovecs));
+        iovecs.extend(messages.iter().map(|(packet, _)| [IoSlice::new(packet)]));
+
+        // sendmmsg zips slices with addrs — must be same length.
+        scratch.addrs.clear();
+        scratch.addrs.extend(
+            messages
+                .iter()
+                .map(|(_, destination)| destinations.get(*destination).copied()),
+        );
+        let sent = sendmmsg(
+            fd,
+            &mut scratch.headers,
+            &iovecs,
+            &scratch.addrs,
+            [],
+            MsgFlags::empty(),
+        )
+        .map(|sent| sent.count());
+        scratch.iovecs = reuse_vec(iovecs);
+        sent
+    })
+}
+
+/// Send messages one sendmsg at a time, for systems without sendmmsg.
+/// Stops at the first failure like sendmmsg does, so the caller sees the
+/// same short counts and errors either way.
+#[cfg(not(mmsg))]
+fn send_batch(
+    fd: RawFd,
+    messages: &[(&Packet, usize)],
+    destinations: &[SockaddrStorage],
+) -> nix::Result<usize> {
+    for (sent, (packet, destination)) in messages.iter().enumerate() {
+        let iov = [IoSlice::new(packet)];
+        let address = destinations.get(*destination);
+        match sendmsg(fd, &iov, &[], MsgFlags::empty(), address) {
+            Ok(_) => {}
+            Err(_) if sent > 0 => return Ok(sent),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(messages.len())
+}
+
+/// Counts and drops. Used when there is nowhere to write.
+pub struct DiscardSink;
+
+impl Sink for DiscardSink {
+    fn name(&self) -> &str {
+        "discard"
+    }
+
+    fn write_packets(&mut self, _packets: &[Packet]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Formats the arrival time prefix, remembering the previous packet for delta.
+struct Timestamper {
+    format: TimestampFormat,
+    previous: Option<SystemTime>,
+}
+
+impl Timestamper {
+    fn new(format: TimestampFormat) -> Self {
+        Self {
+            format,
+            previous: None,
+        }
+    }
+
+    fn prefix(&mut self, arrival: SystemTime) -> String {
+        let previous = self.previous.replace(arrival);
+
+        match self.format {
+            TimestampFormat::Iso => {
+                let time: chrono::DateTime<chrono::Utc> = arrival.into();
+                format!("[{}] ", time.format("%Y-%m-%dT%H:%M:%S%.6fZ"))
+            }
+            TimestampFormat::Epoch => {
+                let since_epoch = arrival
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default();
+                format!(
+                    "[{}.{:06}] ",
+                    since_epoch.as_secs(),
+                    since_epoch.subsec_micros()
+                )
+            }
+            TimestampFormat::Delta => {
+                // Kernel timestamps can be a hair out of order across batches
+                let delta = previous
+                    .and_then(|previous| arrival.duration_since(previous).ok())
+                    .unwrap_or_default();
+                format!("[+{}us] ", delta.as_micros())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use std::os::fd::AsRawFd;
+    use std::time::Duration;
+
+    #[cfg(target_os = "linux")]
+    use nix::time::ClockId;
+
+    use super::*;
+    use crate::packet::Packets;
+
+    fn at(secs: u64, micros: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_micros(micros)
+    }
+
+    fn batch(payloads: &[&[u8]]) -> Packets {
+        let mut packets = Packets::new(payloads.len(), 64);
+        for (packet, payload) in packets.iter_mut().zip(payloads) {
+            if let Some(data) = packet.data_mut().get_mut(..payload.len()) {
+                data.copy_from_slice(payload);
+            }
+            packet.set_length(payload.len());
+            packet.set_timestamp(Some(at(100, 0)));
+        }
+        packets
+    }
+
+    #[test]
+    fn test_text_framing() {
+        let mut sink = StreamSink::new("buffer", Vec::new(), Framing::text(None));
+        sink.write_packets(batch(&[b"one", b"", b"two\n"]).packets())
+            .expect("write");
+        // A zero-length datagram is an empty line
+        assert_eq!(sink.writer, b"one\n\ntwo\n");
+
+        // Binary-ish payloads come out untouched
+        let framing = Framing::text(None).without_newline();
+        let mut sink = StreamSink::new("buffer", Vec::new(), framing);
+        sink.write_packets(batch(&[b"one", b"\x00two\n"]).packets())
+            .expect("write");
+        assert_eq!(sink.writer, b"one\x00two\n");
+    }
+
+    #[test]
+    fn test_text_framing_with_timestamps() {
+        let framing = Framing::text(Some(TimestampFormat::Epoch));
+        let mut sink = StreamSink::new("buffer", Vec::new(), framing);
+        sink.write_packets(batch(&[b"one"]).packets())
+            .expect("write");
+        assert_eq!(sink.writer, b"[100.000000] one\n");
+    }
+
+    #[test]
+    fn test_text_framing_with_label() {
+        let label = Some(Label::Fixed("a".to_string()));
+        let framing = Framing::text(Some(TimestampFormat::Epoch));
+        let mut sink = StreamSink::new("buffer", Vec::new(), framing).with_label(label.clone());
+        sink.write_packets(batch(&[b"one", b"two\nthree\n"]).packets())
+            .expect("write");
+        assert_eq!(
+            String::from_utf8_lossy(&sink.writer),
+            "[a] [100.000000] one\n[a] [100.000000] two\n[a] three\n"
+        );
+
+        // The rest of a line isn't labeled again, the newline is
+        let framing = Framing::text(None).without_newline();
+        let mut sink = StreamSink::new("buffer", Vec::new(), framing).with_label(label);
+        sink.write_packets(batch(&[b"par", b"tial\nnext"]).packets())
+            .expect("write");
+        assert_eq!(
+            String::from_utf8_lossy(&sink.writer),
+            "[a] partial\n[a] next"
+        );
+
+        let mut packets = batch(&[b"one", b"two"]);
+        if let Some(packet) = packets.packets_mut().get_mut(1) {
+            packet.set_destination(Some(SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 2), 5000)));
+        }
+        let label = Some(Label::Destination("239.1.1.1:5000".to_string()));
+        let mut sink = StreamSink::new("buffer", Vec::new(), Framing::text(None)).with_label(label);
+        sink.write_packets(packets.packets()).expect("write");
+        assert_eq!(
+            String::from_utf8_lossy(&sink.writer),
+            "[239.1.1.1:5000] one\n[239.1.1.2:5000] two\n"
+        );
+
+        // Binary output is left alone
+        let label = Some(Label::Fixed("a".to_string()));
+        let mut sink =
+            StreamSink::new("buffer", Vec::new(), Framing::LengthPrefixed).with_label(label);
+        sink.write_packets(batch(&[b"one"]).packets())
+            .expect("write");
+        assert_eq!(sink.writer, b"\x03\x00\x00\x00one");
+    }
+
+    #[test]
+    fn test_raw_framing() {
+        let framing = Framing::new(Some(OutputFormat::Raw), PacketType::Text, None);
+        let mut sink = StreamSink::new("buffer", Vec::new(), framing);
+        sink.write_packets(batch(&[b"ab", b"", b"c\n", b"d"]).packets())
+            .expect("write");
+        assert_eq!(sink.writer, b"abc\nd");
+    }
+
+    #[test]
+    fn test_jsonl_framing() {
+        let framing = Framing::new(Some(OutputFormat::Jsonl), PacketType::Binary, None);
+        let mut sink = StreamSink::new("buffer", Vec::new(), framing);
+        let mut packets = batch(&[b"hi", b"\x00\xff"]);
+        if let Some(packet) = packets.packets_mut().get_mut(1) {
+            packet.set_destination(Some(SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 1), 5000)));
+        }
+        sink.write_packets(packets.packets()).expect("write");
+        assert_eq!(
+            String::from_utf8_lossy(&sink.writer),
+            concat!(
+                "{\"payload_b64\":\"aGk=\",\"timestamp_us\":100000000}\n",
+                "{\"payload_b64\":\"AP8=\",\"timestamp_us\":100000000,\"dst\":\"239.1.1.1:5000\"}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_jsonl_framing_with_names() {
+        let framing = Framing::new(Some(OutputFormat::Jsonl), PacketType::Binary, None);
+        let names = HostNames::with_known(&[(Ipv4Addr::new(10, 0, 0, 1), "recv1.lab")]);
+        let mut sink = StreamSink::new("buffer", Vec::new(), framing).with_names(names);
+        let mut packets = batch(&[b"hi", b"hi", b"hi"]);
+        for (packet, host) in packets.packets_mut().iter_mut().zip([1, 2]) {
+            packet.set_source(Some(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, host), 5000)));
+        }
+        sink.write_packets(packets.packets()).expect("write");
+        assert_eq!(
+            String::from_utf8_lossy(&sink.writer),
+            concat!(
+                "{\"payload_b64\":\"aGk=\",\"timestamp_us\":100000000,\"src\":\"10.0.0.1:5000\",\"src_name\":\"recv1.lab\"}\n",
+                "{\"payload_b64\":\"aGk=\",\"timestamp_us\":100000000,\"src\":\"10.0.0.2:5000\"}\n",
+                "{\"payload_b64\":\"aGk=\",\"timestamp_us\":100000000}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_length_prefixed_framing() {
+        let mut sink = StreamSink::new("buffer", Vec::new(), Framing::LengthPrefixed);
+        sink.write_packets(batch(&[b"ab", b"", b"c"]).packets())
+            .expect("write");
+        assert_eq!(
+            sink.writer,
+            b"\x02\x00\x00\x00ab\x00\x00\x00\x00\x01\x00\x00\x00c"
+        );
+    }
+
+    #[test]
+    fn test_timed_framing() {
+        let framing = Framing::new(Some(OutputFormat::Timed), PacketType::Sdds, None);
+        let mut sink = StreamSink::new("buffer", Vec::new(), framing);
+        let mut packets = batch(&[b"ab", b"c"]);
+        if let Some(packet) = packets.packets_mut().get_mut(1) {
+            packet.set_timestamp(Some(at(100, 5)));
+        }
+        sink.write_packets(packets.packets()).expect("write");
+        let mut expected = 100_000_000_000_u64.to_le_bytes().to_vec();
+        expected.extend(b"\x02\x00\x00\x00ab");
+        expected.extend(100_000_005_000_u64.to_le_bytes());
+        expected.extend(b"\x01\x00\x00\x00c");
+        assert_eq!(sink.writer, expected);
+    }
+
+    #[test]
+    fn test_gap_marker_records() {
+        let marks = Arc::new(gaps::GapMarks::default());
+        let marker = GapMarker::new(PacketType::Sdds, marks.clone());
+        let mut sink = StreamSink::new("buffer", Vec::new(), Framing::LengthPrefixed)
+            .with_gap_marker(Some(marker));
+        // Just the SDDS sequence numbers, 3 and 4 missing
+        sink.write_packets(
+            batch(&[
+                b"\x00\x00\x00\x01",
+                b"\x00\x00\x00\x02",
+                b"\x00\x00\x00\x05",
+            ])
+            .packets(),
+        )
+        .expect("write");
+        assert_eq!(
+            sink.writer,
+            b"\x04\x00\x00\x00\x00\x00\x00\x01\x04\x00\x00\x00\x00\x00\x00\x02\
+              \x02\x00\x00\x80\x04\x00\x00\x00\x00\x00\x00\x05"
+        );
+        let totals = marks.get();
+        assert_eq!((totals.markers, totals.missing), (1, 2));
+        assert_eq!(totals.locations, ["buffer record 3"]);
+
+        // Text output has nowhere to put one
+        let marker = GapMarker::new(PacketType::Sdds, marks);
+        let sink = StreamSink::new("buffer", Vec::new(), Framing::text(None))
+            .with_gap_marker(Some(marker));
+        assert!(sink.gap_marker.is_none());
+    }
+
+    #[test]
+    fn test_file_sink_reopen() {
+        let path = std::env::temp_dir().join(format!("mnc-reopen-{}", std::process::id()));
+        let rotated = path.with_extension("1");
+        let name = path.to_str().expect("path");
+
+        let mut sink = FileSink::create(name, Framing::Raw).expect("create");
+        sink.write_packets(batch(&[b"old"]).packets())
+            .expect("write");
+        std::fs::rename(&path, &rotated).expect("rename");
+        sink.reopen().expect("reopen");
+        sink.write_packets(batch(&[b"new"]).packets())
+            .expect("write");
+        sink.flush().expect("flush");
+
+        assert_eq!(std::fs::read(&rotated).expect("rotated"), b"old");
+        assert_eq!(std::fs::read(&path).expect("reopened"), b"new");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&rotated);
+    }
+
+    #[test]
+    fn test_file_sink_waits_out_full_disk() {
+        let path = std::env::temp_dir().join(format!("mnc-disk-full-{}", std::process::id()));
+        let name = path.to_str().expect("path");
+        let full = || {
+            let file = OpenOptions::new()
+                .append(true)
+                .open("/dev/full")
+                .expect("/dev/full");
+            BufWriter::with_capacity(8, file)
+        };
+
+        // Without a counter a full disk fails as it always did
+        let mut strict = FileSink::create(name, Framing::LengthPrefixed).expect("create");
+        strict.writer = full();
+        assert!(
+            strict
+                .write_packets(batch(&[b"ab", b"cd"]).packets())
+                .is_err()
+        );
+
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut sink = FileSink::create(name, Framing::LengthPrefixed)
+            .expect("create")
+            .with_disk_full(Some(dropped.clone()));
+        let room = std::mem::replace(&mut sink.writer, full());
+        // The second record doesn't fit, the two after it are dropped. The
+        // first, in the buffer of the full file, goes with it below
+        sink.write_packets(batch(&[b"ab", b"cd", b"ef", b"gh"]).packets())
+            .expect("write");
+        assert_eq!(dropped.load(Ordering::Relaxed), 2);
+        assert!(!sink.resume(false).expect("resume"));
+
+        // Room again: what was held is written, then what comes next
+        sink.writer = room;
+        sink.paused.as_mut().expect("paused").tried -= DISK_FULL_RETRY;
+        sink.write_packets(batch(&[b"ij"]).packets())
+            .expect("write");
+        sink.flush().expect("flush");
+        assert!(sink.paused.is_none());
+        assert_eq!(
+            std::fs::read(&path).expect("read"),
+            b"\x02\x00\x00\x00cd\x02\x00\x00\x00ij"
+        );
+
+        // Still full at the end: what the buffer holds is lost too
+        sink.writer = full();
+        sink.write_packets(batch(&[b"kl"]).packets())
+            .expect("write");
+        sink.flush().expect("flush");
+        assert_eq!(dropped.load(Ordering::Relaxed), 3);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_split_filename() {
+        let source = SplitKey::Source(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 5000));
+        assert_eq!(
+            split_filename("capture.bin", source),
+            "capture-10.0.0.1-5000.bin"
+        );
+        assert_eq!(
+            split_filename("/data.d/capture", source),
+            "/data.d/capture-10.0.0.1-5000"
+        );
+        assert_eq!(split_filename(".hidden", source), ".hidden-10.0.0.1-5000");
+        assert_eq!(
+            split_filename("{ip}/{port}.bin", source),
+            "10.0.0.1/5000.bin"
+        );
+        assert_eq!(
+            split_filename("capture.bin", SplitKey::Stream(42)),
+            "capture-0000002a.bin"
+        );
+        assert_eq!(
+            split_filename("ch-{stream}.bin", SplitKey::Stream(42)),
+            "ch-0000002a.bin"
+        );
+        assert_eq!(
+            split_filename("capture", SplitKey::Unknown),
+            "capture-unknown"
+        );
+    }
+
+    #[test]
+    fn test_split_sink() {
+        let dir = std::env::temp_dir().join(format!("mnc-split-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("dir");
+        let template = dir.join("{ip}-{port}.txt");
+        let counters = Arc::new(SplitCounters::default());
+        let mut sink = SplitSink::new(
+            template.to_str().expect("path"),
+            Framing::text(None),
+            SplitBy::Source,
+            counters.clone(),
+        );
+        let first = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 5000);
+        let second = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 5000);
+
+        let mut packets = batch(&[b"a", b"b", b"c"]);
+        for (packet, source) in packets.iter_mut().zip([first, second, first]) {
+            packet.set_source(Some(source));
+        }
+        sink.write_packets(packets.packets()).expect("write");
+        assert_eq!(sink.open.len(), 2);
+
+        // Idle files are closed, and appended to when their source returns
+        sink.idle_close = Duration::ZERO;
+        sink.write_packets(packets.packets().get(..1).expect("first"))
+            .expect("write");
+        assert!(sink.open.is_empty());
+        sink.flush().expect("flush");
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).expect("read");
+        assert_eq!(read("10.0.0.1-5000.txt"), "a\nc\na\n");
+        assert_eq!(read("10.0.0.2-5000.txt"), "b\n");
+        assert_eq!(
+            counters.get(),
+            [(SplitKey::Source(first), 3), (SplitKey::Source(second), 1)]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_split_by_stream_id() {
+        let frame = |vrt: &[u32]| {
+            let mut bytes = b"VRLP\x00\x10\x00\x00".to_vec();
+            bytes.extend(vrt.iter().flat_map(|word| word.to_be_bytes()));
+            let mut packet = Packet::with_capacity(bytes.len());
+            packet.data_mut().copy_from_slice(&bytes);
+            packet
+        };
+
+        // Signal data with a stream id, then without one, then not VRLP
+        let with_id = frame(&[(1 << 28) | 3, 42, 0]);
+        assert_eq!(SplitBy::StreamId.key(&with_id), SplitKey::Stream(42));
+        assert_eq!(SplitBy::StreamId.key(&frame(&[2, 0])), SplitKey::Unknown);
+        assert_eq!(
+            SplitBy::StreamId.key(&Packet::with_capacity(4)),
+            SplitKey::Unknown
+        );
+        assert_eq!(SplitKey::Stream(42).to_string(), "0x0000002a");
+    }
+
+    #[test]
+    fn test_send_error_class() {
+        assert_eq!(
+            SendErrorClass::of(Errno::ENOBUFS),
+            Some(SendErrorClass::Congestion)
+        );
+        assert_eq!(
+            SendErrorClass::of(Errno::ENETUNREACH),
+            Some(SendErrorClass::Unreachable)
+        );
+        assert_eq!(
+            SendErrorClass::of(Errno::EPERM),
+            Some(SendErrorClass::Refused)
+        );
+        assert_eq!(SendErrorClass::of(Errno::EBADF), None);
+    }
+
+    #[test]
+    fn test_transmit_totals_since() {
+        let counters = TransmitCounters::default();
+        counters.add_sent(10);
+        counters.add_error(SendErrorClass::Congestion);
+        let earlier = counters.get();
+
+        counters.add_sent(5);
+        counters.add_retry();
+        counters.add_error(SendErrorClass::Congestion);
+        counters.add_error(SendErrorClass::Packet);
+
+        let interval = counters.get().since(&earlier);
+        assert_eq!(interval.sent, 5);
+        assert_eq!(interval.retries, 1);
+        assert_eq!(interval.errors, [1, 0, 0, 1]);
+        assert_eq!(interval.total_errors(), 2);
+    }
+
+    // Runs against sendmmsg normally and the fallback with --features portable
+    #[test]
+    fn test_send_batch() {
+        let receiver = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("receiver");
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .expect("timeout");
+        let address = receiver.local_addr().expect("address");
+        let sender = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("sender");
+        let destinations = [SockaddrStorage::from(address)];
+
+        let packets = batch(&[b"one", b"two"]);
+        let messages: Vec<(&Packet, usize)> = packets.iter().map(|packet| (packet, 0)).collect();
+        let sent = send_batch(sender.as_raw_fd(), &messages, &destinations).expect("send");
+        assert_eq!(sent, 2);
+
+        let mut buffer = [0u8; 16];
+        for expected in [&b"one"[..], b"two"] {
+            let length = receiver.recv(&mut buffer).expect("recv");
+            assert_eq!(buffer.get(..length), Some(expected));
+        }
+    }
+
+    // The headers are kept between calls; a bigger batch must regrow them
+    // and a smaller one must not send stale messages
+    #[test]
+    fn test_send_batch_reuses_headers() {
+        let receiver = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("receiver");
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .expect("timeout");
+        let address = receiver.local_addr().expect("address");
+        let sender = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).expect("sender");
+        let destinations = [SockaddrStorage::from(address)];
+
+        let mut buffer = [0u8; 16];
+        for payloads in [&[&b"a"[..]][..], &[b"b", b"c", b"d"], &[b"e", b"f"]] {
+            let packets = batch(payloads);
+            let messages: Vec<(&Packet, usize)> =
+                packets.iter().map(|packet| (packet, 0)).collect();
+            let sent = send_batch(sender.as_raw_fd(), &messages, &destinations).expect("send");
+            assert_eq!(sent, payloads.len());
+
+            for expected in payloads {
+                let length = receiver.recv(&mut buffer).expect("recv");
+                assert_eq!(buffer.get(..length), Some(*expected));
+            }
+        }
+        receiver.set_nonblocking(true).expect("nonblocking");
+        assert!(receiver.recv(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_network_sink_counts_sent() {
+        let counters = Arc::new(TransmitCounters::default());
+        let mut sink = NetworkSink::new(
+            None,
+            "239.255.77.16",
+            &[39516],
+            0,
+            None,
+            Fanout::default(),
+            counters.clone(),
+        )
+        .expect("sink");
+        sink.write_packets(batch(&[b"one", b"two", b"three"]).packets())
+            .expect("send");
+        assert_eq!(counters.get().sent, 3);
+    }
+
+    #[test]
+    fn test_network_sink_fanout() {
+        let sent_per_group = |fanout, packets: &[&[u8]]| {
+            let counters = Arc::new(TransmitCounters::default());
+            let mut sink = NetworkSink::new(
+                None,
+                "239.255.77.16-18",
+                &[39516],
+                0,
+                None,
+                fanout,
+                counters.clone(),
+            )
+            .expect("sink");
+            sink.write_packets(batch(packets).packets()).expect("send");
+            sink.write_packets(batch(packets.get(..1).unwrap_or_default()).packets())
+                .expect("send");
+            let totals = counters.get();
+            let sent: Vec<u64> = totals
+                .per_destination
+                .iter()
+                .map(|(_, sent)| *sent)
+                .collect();
+            (totals.sent, sent)
+        };
+
+        // Round robin carries on where the previous batch stopped
+        assert_eq!(
+            sent_per_group(Fanout::RoundRobin, &[b"1", b"2", b"3", b"4"]),
+            (5, vec![2, 2, 1])
+        );
+        assert_eq!(
+            sent_per_group(Fanout::Duplicate, &[b"1", b"2"]),
+            (9, vec![3, 3, 3])
+        );
+    }
+
+    #[test]
+    fn test_network_sink_every_port() {
+        let sent_per_destination = |fanout| {
+            let counters = Arc::new(TransmitCounters::default());
+            let mut sink = NetworkSink::new(
+                None,
+                "239.255.77.16-17",
+                &[39516, 39517],
+                0,
+                None,
+                fanout,
+                counters.clone(),
+            )
+            .expect("sink");
+            sink.write_packets(batch(&[b"1", b"2", b"3"]).packets())
+                .expect("send");
+            let totals = counters.get();
+            let sent: Vec<String> = totals
+                .per_destination
+                .iter()
+                .map(|(destination, sent)| format!("{destination} {sent}"))
+                .collect();
+            (totals.sent, sent)
+        };
+
+        // Round robin picks the group; both its ports get the packet
+        assert_eq!(
+            sent_per_destination(Fanout::RoundRobin),
+            (
+                6,
+                vec![
+                    "239.255.77.16:39516 2".to_string(),
+                    "239.255.77.16:39517 2".to_string(),
+                    "239.255.77.17:39516 1".to_string(),
+                    "239.255.77.17:39517 1".to_string(),
+                ]
+            )
+        );
+        assert_eq!(sent_per_destination(Fanout::Duplicate).0, 12);
+    }
+
+    #[test]
+    fn test_network_sink_packet_destinations() {
+        let counters = Arc::new(TransmitCounters::default());
+        let mut sink = NetworkSink::new(
+            None,
+            "239.255.77.16-17",
+            &[39516],
+            0,
+            None,
+            Fanout::default(),
+            counters.clone(),
+        )
+        .expect("sink")
+        .with_packet_destinations(None, 0)
+        .expect("unconnected");
+
+        let to = |a, b, c, d| Some(SocketAddrV4::new(Ipv4Addr::new(a, b, c, d), 39516));
+        let mut packets = batch(&[b"1", b"2", b"3", b"4"]);
+        let destinations = [
+            to(239, 255, 77, 17),
+            to(239, 255, 77, 20),
+            to(10, 0, 0, 1),
+            None,
+        ];
+        for (packet, destination) in packets.packets_mut().iter_mut().zip(destinations) {
+            packet.set_destination(destination);
+        }
+        sink.write_packets(packets.packets()).expect("send");
+
+        // Only the configured groups are counted per group; the packet
+        // without a destination of its own goes to the first in turn
+        let totals = counters.get();
+        assert_eq!(totals.sent, 3);
+        assert_eq!(totals.skipped, 1);
+        let sent: Vec<u64> = totals
+            .per_destination
+            .iter()
+            .map(|(_, sent)| *sent)
+            .collect();
+        assert_eq!(sent, vec![1, 1]);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_network_sink_txtime() {
+        // Without an fq or etf qdisc the launch time is ignored, but the
+        // socket option and control message still have to be accepted
+        let counters = Arc::new(TransmitCounters::default());
+        let mut sink = NetworkSink::new(
+            None,
+            "239.255.77.21",
+            &[39523],
+            0,
+            None,
+            Fanout::RoundRobin,
+            counters.clone(),
+        )
+        .expect("sink");
+        txtime::enable(&sink.socket, ClockId::CLOCK_MONOTONIC).expect("SO_TXTIME");
+        sink.schedule = Some(txtime::Schedule::new(ClockId::CLOCK_MONOTONIC, 10_000));
+
+        sink.write_packets(batch(&[b"1", b"2", b"3"]).packets())
+            .expect("send");
+        assert_eq!(counters.get().sent, 3);
+        assert_eq!(counters.get().total_errors(), 0);
+    }
+
+    #[test]
+    fn test_network_sink_stamp() {
+        let receiver = std::net::UdpSocket::bind("0.0.0.0:39528").expect("bind");
+        receiver
+            .join_multicast_v4(&Ipv4Addr::new(239, 255, 77, 26), &Ipv4Addr::UNSPECIFIED)
+            .expect("join");
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .expect("timeout");
+
+        let mut sink = NetworkSink::new(
+            None,
+            "239.255.77.26",
+            &[39528],
+            0,
+            None,
+            Fanout::RoundRobin,
+            Arc::new(TransmitCounters::default()),
+        )
+        .expect("sink")
+        .with_stamp(2);
+
+        let before = SystemTime::now();
+        let packets = batch(&[b"ab--------cd", b"short"]);
+        sink.write_packets(packets.packets()).expect("send");
+
+        let mut buffer = [0u8; 64];
+        let received = receiver.recv(&mut buffer).expect("recv");
+        let stamped = buffer.get(..received).unwrap_or_default();
+        assert_eq!(stamped.len(), 12);
+        assert_eq!(stamped.get(..2), Some(&b"ab"[..]));
+        assert_eq!(stamped.get(10..), Some(&b"cd"[..]));
+        let stamp = latency::read_stamp(stamped, 2).expect("stamp");
+        let since = before
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("epoch");
+        assert!(stamp >= since.as_nanos() as u64);
+
+        // Too short to stamp, sent as it was
+        let received = receiver.recv(&mut buffer).expect("recv");
+        assert_eq!(buffer.get(..received), Some(&b"short"[..]));
+
+        // The batch is shared with the other sinks and mustn't change
+        let original: Vec<&[u8]> = packets.iter().map(|packet| &packet[..]).collect();
+        assert_eq!(original, vec![&b"ab--------cd"[..], &b"short"[..]]);
+    }
+
+    #[test]
+    fn test_network_sink_stamp_seq() {
+        let receiver = std::net::UdpSocket::bind("0.0.0.0:39531").expect("bind");
+        receiver
+            .join_multicast_v4(&Ipv4Addr::new(239, 255, 77, 31), &Ipv4Addr::UNSPECIFIED)
+            .expect("join");
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .expect("timeout");
+
+        let mut sink = NetworkSink::new(
+            None,
+            "239.255.77.31",
+            &[39531],
+            0,
+            None,
+            Fanout::RoundRobin,
+            Arc::new(TransmitCounters::default()),
+        )
+        .expect("sink")
+        .with_stamp_seq(SeqField::Prepend);
+
+        let packets = batch(&[b"one", b"two"]);
+        sink.write_packets(packets.packets()).expect("send");
+        sink.write_packets(packets.packets()).expect("send");
+
+        let mut buffer = [0u8; 64];
+        for (seq, payload) in [(0u32, b"one"), (1, b"two"), (2, b"one"), (3, b"two")] {
+            let received = receiver.recv(&mut buffer).expect("recv");
+            let numbered = buffer.get(..received).unwrap_or_default();
+            assert_eq!(sequence::read(numbered, SeqField::Prepend), Some(seq));
+            assert_eq!(numbered.get(sequence::SEQ_BYTES..), Some(&payload[..]));
+        }
+    }
+
+    #[test]
+    fn test_iso_prefix() {
+        let mut timestamper = Timestamper::new(TimestampFormat::Iso);
+        assert_eq!(
+            timestamper.prefix(at(1714660282, 123456)),
+            "[2024-05-02T14:31:22.123456Z] "
+        );
+    }
+
+    #[test]
+    fn test_epoch_prefix() {
+        let mut timestamper = Timestamper::new(TimestampFormat::Epoch);
+        assert_eq!(
+            timestamper.prefix(at(1714660282, 42)),
+            "[1714660282.000042] "
+        );
+    }
+
+    #[test]
+    fn test_delta_prefix() {
+        let mut timestamper = Timestamper::new(TimestampFormat::Delta);
+        assert_eq!(timestamper.prefix(at(100, 0)), "[+0us] ");
+        assert_eq!(timestamper.prefix(at(100, 1500)), "[+1500us] ");
+        assert_eq!(timestamper.prefix(at(101, 1500)), "[+1000000us] ");
+        // Out of order arrival doesn't go negative
+        assert_eq!(timestamper.prefix(at(101, 0)), "[+0us] ");
+    }
+}
@@ -1,26 +1,137 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::net::SocketAddrV4;
 use std::thread::{self, JoinHandle};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::Sender;
 
 use crate::{
     SharedState,
-    error::Result,
-    packet::{PacketType, Packets},
-    sdds, vita49,
+    cpu::CpuUsage,
+    drain::ExitDrain,
+    dump::{DumpOutput, HexDump},
+    error::{LibError, Result},
+    fec::FecTotals,
+    gaps::{FrameSeq, Gap},
+    latency::Latency,
+    mdns,
+    nic::NicCounters,
+    packet::{Packet, PacketType, Packets},
+    preflight::format_size,
+    reader, sandbox,
+    sched::{self, ThreadPlacement},
+    sdds,
+    senders::{MAX_SENDERS, Senders},
+    sequence::{self, SeqField, SeqTracker},
+    sink::{SendErrorClass, TransmitTotals},
+    template::Template,
+    transport::{BatchReceiver, BatchSender},
+    unique::UniqueCount,
+    vita49, writer,
 };
 
-// Print every second.
-const STATISTICS_DELAY_SECS: u64 = 1;
+// Print every second, on the second.
+const STATISTICS_INTERVAL: Duration = Duration::from_secs(1);
 
+// Longest wait for a batch, so signals and idle senders are seen.
+const POLL: Duration = Duration::from_millis(100);
+
+/// --stats-on-change without a value: the rate moving by more than this
+/// many percent is a change.
+pub const DEFAULT_CHANGE_PERCENT: f64 = 10.0;
+
+// Time constant of the smoothed rates.
+const RATE_TAU: Duration = Duration::from_secs(5);
+
+// Bursts are measured over windows this long, within each interval.
+const PEAK_WINDOW: Duration = Duration::from_millis(100);
+
+// Time tag jumps logged in full, after which they are only counted.
+const LOGGED_DISCONTINUITIES: u64 = 5;
+
+// A stream that arrives with this TTL or less dies at the next router.
+const LOW_TTL: u8 = 1;
+
+/// Statistics thread configuration.
+/// Packets are passed through from the first channel to the second.
 pub struct StatisticsConfig {
-    pub channels: (Receiver<Packets>, Sender<Packets>),
+    pub channels: (Box<dyn BatchReceiver>, Box<dyn BatchSender>),
     pub shared_state: SharedState,
+    /// Where -v hex dumps go, also when SIGUSR2 turns them on
+    pub dump_output: DumpOutput,
+    /// Mark what changed since the previous packet in each hex dump
+    pub dump_diff: bool,
+    /// Packets -v dumps before it stops, None for all of them
+    pub dump_count: Option<u64>,
+    /// Add the network sink's sent, error and retry counts to each line
+    pub transmit: bool,
+    /// Offset of the sender's --stamp to measure one-way latency from
+    pub measure_latency: Option<usize>,
+    /// Where the sender's --stamp-seq number is, to count loss and reordering
+    pub check_seq: Option<SeqField>,
+    /// The sender's --template, to read {seq} back out of for the same counts
+    pub verify_template: Option<Template>,
+    /// SDDS sample rate in Hz for the time tag check, inferred when None
+    pub sdds_rate: Option<f64>,
+    /// What SDDS time tags count from, to show them as UTC
+    pub sdds_epoch: Option<sdds::SddsEpoch>,
+    /// Report each SDDS time tag's offset from the kernel's receive time
+    pub timetag_offset: bool,
+    /// Warn after this many VITA-49 packets without a context packet
+    pub vita49_context_gap: Option<u64>,
+    /// Exit once more than this many sequence numbers were skipped in all
+    pub fail_on_gap: Option<u64>,
+    /// Each group's destination and packet type when receiving several,
+    /// which are then parsed and counted apart. Empty for one group.
+    pub groups: Vec<(SocketAddrV4, PacketType)>,
+    /// Count packets by the port they arrived on, when receiving on several
+    pub per_port: bool,
+    /// Add the packets the --filter-* and parity filters left out to each line
+    pub filters: bool,
+    /// Add the packets --police dropped to each line
+    pub police: bool,
+    /// Add what --reorder put back in order and gave up on to each line
+    pub reorder: bool,
+    /// Add the --dejitter buffer's depth, late releases and overflow to each line
+    pub dejitter: bool,
+    /// Add the --fec or --fec-decode data, parity and rebuilt counts to each line
+    pub fec: bool,
+    /// Add the --patch patches applied and skipped to each line
+    pub patch: bool,
+    /// Start each line with this, to tell instances apart in one log
+    pub label: Option<String>,
+    /// Only print a line when the rate moved more than this many percent,
+    /// or something else changed, since the interval before
+    pub on_change: Option<f64>,
+    /// Take this many bytes off the front of every packet, before the
+    /// packet type's parser sees it
+    pub strip: Option<usize>,
+    /// Where a batch --strip dropped every packet of goes back to the reader
+    pub memory_return: Sender<Packets>,
+    /// Count the senders and log new ones, and those quiet for this long
+    pub senders: Option<Duration>,
+    /// Add the drop counters of the interface received on to each line
+    pub nic: Option<String>,
+    /// Add how many packets each recvmmsg call returned to each line
+    pub batch_fill: bool,
+    /// Count distinct payloads, exactly up to this many
+    pub unique: Option<u64>,
+    /// Once exit is signaled, pass queued batches on for this long at most
+    pub drain_timeout: Option<Duration>,
+    pub placement: ThreadPlacement,
+    /// Lock the thread down once the dump output is open
+    pub sandbox: bool,
 }
 
+/// Spawn the statistics thread. Any error also signals exit to the other threads.
 pub fn spawn(config: StatisticsConfig) -> JoinHandle<Result<()>> {
     thread::spawn(move || {
-        run_statistics(&config)
+        let mut config = config;
+        sched::apply("statistics", &config.placement);
+        config.shared_state.cpu.register("statistics");
+        run_statistics(&mut config)
             .inspect(|_| log::debug!("statistics exited"))
             .inspect_err(|e| {
                 log::debug!("{e:?}");
@@ -29,196 +140,1797 @@ pub fn spawn(config: StatisticsConfig) -> JoinHandle<Result<()>> {
     })
 }
 
+/// Columns on every stats line whatever the packet type.
+#[derive(Debug)]
+struct Extras {
+    label: Option<String>,
+    transmit: bool,
+    latency_offset: Option<usize>,
+    check_seq: Option<SeqField>,
+    verify_template: Option<Template>,
+    /// --fail-on-gap on the --check-seq or --verify-template numbers
+    seq_gap_allowance: Option<u64>,
+    per_port: bool,
+    filters: bool,
+    police: bool,
+    reorder: bool,
+    dejitter: bool,
+    fec: bool,
+    patch: bool,
+    strip: Option<Strip>,
+    on_change: Option<f64>,
+    senders: Option<Duration>,
+    nic: Option<NicCounters>,
+    batch_fill: bool,
+    unique: Option<u64>,
+    drain_timeout: Option<Duration>,
+}
+
+/// --strip's byte count, and the pool a batch it empties goes back to.
+#[derive(Debug, Clone)]
+struct Strip {
+    bytes: usize,
+    memory_return: Sender<Packets>,
+}
+
 #[derive(Default)]
 struct SddsState {
-    last_seq: Option<u16>,
+    seq: FrameSeq,
     skipped_in_period: u64,
     latest_timestamp: String,
+    time_check: sdds::TimeTagCheck,
+    discontinuities_in_period: u64,
+    discontinuities_total: u64,
+    // The data format bytes, across intervals
+    format: Option<u16>,
+    format_changes: u64,
+    // --timetag-offset, its drift across intervals
+    offset: sdds::ClockOffset,
+}
+
+/// Per-type state, started afresh each interval.
+trait IntervalState: Default {
+    /// State for the next interval; anything that spans intervals carries over.
+    fn next_interval(&mut self) -> Self {
+        Self::default()
+    }
+
+    /// Whether the packet type's own sequence numbers skipped this interval.
+    fn gaps(&self) -> bool {
+        false
+    }
+
+    /// Whether a header field that should hold still changed this interval.
+    fn header_changed(&self) -> bool {
+        false
+    }
+}
+
+impl IntervalState for () {}
+impl IntervalState for mdns::MdnsState {}
+
+impl IntervalState for Vita49State {
+    fn next_interval(&mut self) -> Self {
+        Self {
+            since_context: self.since_context,
+            data_size: self.data_size,
+            ..Default::default()
+        }
+    }
+
+    fn gaps(&self) -> bool {
+        self.skipped_in_period > 0
+    }
+
+    fn header_changed(&self) -> bool {
+        self.size_changes > 0
+    }
+}
+
+impl IntervalState for SddsState {
+    fn next_interval(&mut self) -> Self {
+        Self {
+            time_check: std::mem::take(&mut self.time_check),
+            discontinuities_total: self.discontinuities_total,
+            format: self.format,
+            offset: self.offset.next_interval(),
+            ..Default::default()
+        }
+    }
+
+    fn gaps(&self) -> bool {
+        self.skipped_in_period > 0 || self.discontinuities_in_period > 0
+    }
+
+    fn header_changed(&self) -> bool {
+        self.format_changes > 0
+    }
+}
+
+/// With several groups, the packets sent to each under their own type's
+/// state.
+#[derive(Default)]
+struct GroupsState {
+    groups: BTreeMap<SocketAddrV4, GroupState>,
+}
+
+struct GroupState {
+    count: u64,
+    kind: KindState,
+}
+
+/// One packet type's interval state.
+enum KindState {
+    Plain,
+    Sdds(Box<SddsState>),
+    Vita49(Vita49State),
+    Mdns(mdns::MdnsState),
+}
+
+impl GroupState {
+    fn new(packet_type: PacketType) -> Self {
+        let kind = match packet_type {
+            PacketType::Text | PacketType::Binary => KindState::Plain,
+            PacketType::Sdds => KindState::Sdds(Box::default()),
+            PacketType::Vita49 => KindState::Vita49(Vita49State::default()),
+            PacketType::Mdns => KindState::Mdns(mdns::MdnsState::default()),
+        };
+        Self { count: 0, kind }
+    }
+}
+
+impl KindState {
+    fn next_interval(&mut self) -> Self {
+        match self {
+            KindState::Plain => KindState::Plain,
+            KindState::Sdds(state) => KindState::Sdds(Box::new(state.next_interval())),
+            KindState::Vita49(state) => KindState::Vita49(state.next_interval()),
+            KindState::Mdns(state) => KindState::Mdns(state.next_interval()),
+        }
+    }
+
+    fn gaps(&self) -> bool {
+        match self {
+            KindState::Sdds(state) => state.gaps(),
+            KindState::Vita49(state) => state.gaps(),
+            KindState::Plain | KindState::Mdns(_) => false,
+        }
+    }
+
+    fn header_changed(&self) -> bool {
+        match self {
+            KindState::Sdds(state) => state.header_changed(),
+            KindState::Vita49(state) => state.header_changed(),
+            KindState::Plain | KindState::Mdns(_) => false,
+        }
+    }
+}
+
+impl IntervalState for GroupsState {
+    fn next_interval(&mut self) -> Self {
+        Self {
+            groups: self
+                .groups
+                .iter_mut()
+                .map(|(&destination, group)| {
+                    let kind = group.kind.next_interval();
+                    (destination, GroupState { count: 0, kind })
+                })
+                .collect(),
+        }
+    }
+
+    fn gaps(&self) -> bool {
+        self.groups.values().any(|group| group.kind.gaps())
+    }
+
+    fn header_changed(&self) -> bool {
+        self.groups
+            .values()
+            .any(|group| group.kind.header_changed())
+    }
 }
 
 #[derive(Default)]
 struct Vita49State {
-    last_seq: Option<u16>,
+    seq: FrameSeq,
     skipped_in_period: u64,
+    data: u64,
+    context: u64,
+    other: u64,
+    // Packets since the last context packet, across intervals
+    since_context: u64,
+    // Frame size of the data packets, across intervals
+    data_size: Option<u32>,
+    size_changes: u64,
 }
 
 fn run_statistics(
     StatisticsConfig {
         channels,
         shared_state,
-    }: &StatisticsConfig,
+        dump_output,
+        dump_diff,
+        dump_count,
+        transmit,
+        measure_latency,
+        check_seq,
+        verify_template,
+        sdds_rate,
+        sdds_epoch,
+        timetag_offset,
+        vita49_context_gap,
+        fail_on_gap,
+        groups,
+        per_port,
+        filters,
+        police,
+        reorder,
+        dejitter,
+        fec,
+        patch,
+        label,
+        on_change,
+        strip,
+        memory_return,
+        senders,
+        nic,
+        batch_fill,
+        unique,
+        drain_timeout,
+        placement: _,
+        sandbox,
+    }: &mut StatisticsConfig,
 ) -> Result<()> {
     log::debug!("statistics for {}", &shared_state.packet_type);
 
-    match shared_state.packet_type {
-        PacketType::Text => produce_stats(
-            channels,
+    // Opened before the sandbox, which would stop it
+    let nic = nic.as_deref().and_then(NicCounters::open);
+
+    let mut dump = Dumper::new(
+        dump_output.clone(),
+        shared_state.verbose,
+        *dump_diff,
+        *dump_count,
+    )?;
+    if *sandbox {
+        // SIGUSR2 can't open the dump file later
+        dump.open()?;
+        sandbox::enter("statistics")?;
+    }
+    let dump = &mut dump;
+    let parsers = Parsers {
+        sdds_rate: *sdds_rate,
+        sdds_epoch: *sdds_epoch,
+        timetag_offset: sdds_epoch.filter(|_| *timetag_offset),
+        vita49_context_gap: *vita49_context_gap,
+        // --check-seq's numbers win over the packet type's own
+        gate: GapGate::new(
+            fail_on_gap.filter(|_| check_seq.is_none() && verify_template.is_none()),
             shared_state,
-            print_hex_dump,
-            |_packet, _state: &mut ()| {},
-            |count, rate, _state: &()| format!("packets: {count}  rate: {rate:.2} pkt/s"),
         ),
-        PacketType::Binary => produce_stats(
+    };
+    let parsers = &parsers;
+    let extras = Extras {
+        label: label.clone(),
+        transmit: *transmit,
+        latency_offset: *measure_latency,
+        check_seq: *check_seq,
+        verify_template: verify_template.clone(),
+        seq_gap_allowance: fail_on_gap.filter(|_| check_seq.is_some() || verify_template.is_some()),
+        per_port: *per_port,
+        filters: *filters,
+        police: *police,
+        reorder: *reorder,
+        dejitter: *dejitter,
+        fec: *fec,
+        patch: *patch,
+        on_change: *on_change,
+        senders: *senders,
+        nic,
+        batch_fill: *batch_fill,
+        unique: *unique,
+        drain_timeout: *drain_timeout,
+        strip: strip.map(|bytes| Strip {
+            bytes,
+            memory_return: memory_return.clone(),
+        }),
+    };
+
+    // Several groups are each parsed as their own type and counted apart
+    if !groups.is_empty() {
+        let groups = groups.as_slice();
+        return produce_stats(
+            channels,
+            shared_state,
+            dump,
+            extras,
+            |packet| {
+                let (_, packet_type) = group_of(groups, packet)?;
+                parsers.describe(packet_type, packet)
+            },
+            |packet, state: &mut GroupsState| parsers.group(packet, state, groups),
+            |count, rate, state: &GroupsState| parsers.format_groups(count, rate, state, groups),
+        );
+    }
+
+    let packet_type = shared_state.packet_type;
+    match packet_type {
+        PacketType::Text | PacketType::Binary => produce_stats(
             channels,
             shared_state,
-            print_hex_dump,
+            dump,
+            extras,
+            |_packet| None,
             |_packet, _state: &mut ()| {},
-            |count, rate, _state: &()| format!("packets: {count}  rate: {rate:.2} pkt/s"),
+            |count, rate, _state: &()| format_plain(count, rate),
         ),
         PacketType::Sdds => produce_stats(
             channels,
             shared_state,
-            |packet| {
-                log::info!("{}", sdds::SddsHeader::new(packet));
-                print_hex_dump(packet);
-            },
-            |packet, state: &mut SddsState| {
-                let header = sdds::parse_frame_header(packet);
-                let seq = header.frame_sequence_number;
-                if seq.is_multiple_of(32) {
-                    state.last_seq = Some(seq);
-                    return; // Every 32 packet is a parity packet
-                }
-                if let Some(prev_seq) = state.last_seq {
-                    let expected = prev_seq.wrapping_add(1);
-                    if seq != expected {
-                        let skipped = if seq > expected {
-                            (seq - expected) as u64
-                        } else {
-                            (u16::MAX - expected + seq + 1) as u64
-                        };
-                        state.skipped_in_period += skipped;
-                    }
-                }
-                state.last_seq = Some(seq);
-                state.latest_timestamp = sdds::format_timestamp(header.time_tag);
-            },
-            |count, rate, state: &SddsState| {
-                let mut s = format!(
-                    "packets: {count}  rate: {rate:.2} pkt/s  skipped: {}",
-                    state.skipped_in_period
-                );
-                if !state.latest_timestamp.is_empty() {
-                    s.push_str(&format!("  time: {}", state.latest_timestamp));
-                }
-                s
-            },
+            dump,
+            extras,
+            |packet| parsers.describe(packet_type, packet),
+            |packet, state: &mut SddsState| parsers.sdds(packet, state),
+            |count, rate, state: &SddsState| parsers.format_sdds(count, rate, state),
         ),
         PacketType::Vita49 => produce_stats(
             channels,
             shared_state,
-            |packet| {
-                log::info!("{}", vita49::parse_header(packet));
-                print_hex_dump(packet);
-            },
-            |packet, state: &mut Vita49State| {
-                let header = vita49::parse_header(packet);
-                let seq = header.frame_sequence_number;
-                if let Some(prev_seq) = state.last_seq {
-                    let expected = (prev_seq + 1) & 0xFFF;
-                    if seq != expected {
-                        let skipped = if seq > expected {
-                            (seq - expected) as u64
-                        } else {
-                            0x1000 - expected as u64 + seq as u64
-                        };
-                        state.skipped_in_period += skipped;
-                    }
-                }
-                state.last_seq = Some(seq);
-            },
-            |count, rate, state: &Vita49State| {
-                format!(
-                    "packets: {count}  rate: {rate:.2} pkt/s  skipped: {}",
-                    state.skipped_in_period
-                )
-            },
+            dump,
+            extras,
+            |packet| parsers.describe(packet_type, packet),
+            |packet, state: &mut Vita49State| parsers.vita49(packet, state),
+            |count, rate, state: &Vita49State| format_vita49(count, rate, state),
+        ),
+        PacketType::Mdns => produce_stats(
+            channels,
+            shared_state,
+            dump,
+            extras,
+            |packet| parsers.describe(packet_type, packet),
+            |packet, state: &mut mdns::MdnsState| state.process(packet),
+            |count, rate, state: &mdns::MdnsState| format_mdns(count, rate, state),
         ),
     }
 }
 
-fn produce_stats<S: Default>(
-    (data_rx, data_tx): &(Receiver<Packets>, Sender<Packets>),
+/// The packet types' parsers, with the options that shape what they report.
+struct Parsers {
+    sdds_rate: Option<f64>,
+    sdds_epoch: Option<sdds::SddsEpoch>,
+    timetag_offset: Option<sdds::SddsEpoch>,
+    vita49_context_gap: Option<u64>,
+    gate: GapGate,
+}
+
+impl Parsers {
+    /// The decoded header a hex dump of the packet is headed with.
+    fn describe(&self, packet_type: PacketType, packet: &[u8]) -> Option<String> {
+        match packet_type {
+            PacketType::Text | PacketType::Binary => None,
+            PacketType::Sdds => Some(
+                sdds::SddsHeader::new(packet)
+                    .with_epoch(self.sdds_epoch)
+                    .to_string(),
+            ),
+            PacketType::Vita49 => Some(vita49::parse_header(packet).to_string()),
+            PacketType::Mdns => Some(match mdns::parse_message(packet) {
+                Some(message) => message.to_string(),
+                None => format!("mDNS: short packet ({} bytes)", packet.len()),
+            }),
+        }
+    }
+
+    fn sdds(&self, packet: &Packet, state: &mut SddsState) {
+        let sdds_epoch = self.sdds_epoch;
+        if let Some(jump) = state.time_check.observe(packet, self.sdds_rate) {
+            state.discontinuities_in_period += 1;
+            state.discontinuities_total += 1;
+            match state.discontinuities_total {
+                n if n < LOGGED_DISCONTINUITIES => {
+                    log::warn!("{}", jump.describe(sdds_epoch))
+                }
+                LOGGED_DISCONTINUITIES => log::warn!(
+                    "{}; further time discontinuities are only counted",
+                    jump.describe(sdds_epoch)
+                ),
+                _ => {}
+            }
+        }
+
+        let format = packet
+            .get(..2)
+            .and_then(|b| b.try_into().ok())
+            .map(u16::from_be_bytes);
+        if state.format.is_some() && format != state.format {
+            state.format_changes += 1;
+        }
+        state.format = format;
+
+        if let Some(epoch) = self.timetag_offset {
+            state.offset.observe(packet, epoch, packet.timestamp());
+        }
+
+        let header = sdds::parse_frame_header(packet);
+        let seq = header.frame_sequence_number;
+        let gap = state.seq.sdds(seq);
+        if sdds::is_parity(seq) {
+            return; // Every 32 packet is a parity packet
+        }
+        if let Some(Gap {
+            expected,
+            seq,
+            skipped,
+        }) = gap
+        {
+            state.skipped_in_period += skipped;
+            self.gate.observe(expected, seq, skipped);
+        }
+        state.latest_timestamp = sdds::format_time_tag(header.time_tag, sdds_epoch);
+    }
+
+    fn format_sdds(&self, count: u64, rate: f64, state: &SddsState) -> String {
+        let mut s = format!(
+            "packets: {count}  rate: {rate:.2} pkt/s  skipped: {}",
+            state.skipped_in_period
+        );
+        if state.time_check.increment().is_some() {
+            s.push_str(&format!(
+                "  time discontinuities: {}",
+                state.discontinuities_in_period
+            ));
+        }
+        if !state.latest_timestamp.is_empty() {
+            s.push_str(&format!("  time: {}", state.latest_timestamp));
+        }
+        if self.timetag_offset.is_some() {
+            s.push_str(&state.offset.format());
+        }
+        s
+    }
+
+    fn vita49(&self, packet: &Packet, state: &mut Vita49State) {
+        let header = vita49::parse_header(packet);
+        if let Some(Gap {
+            expected,
+            seq,
+            skipped,
+        }) = state.seq.vita49(header.frame_sequence_number)
+        {
+            state.skipped_in_period += skipped;
+            self.gate.observe(expected, seq, skipped);
+        }
+
+        match header.kind() {
+            vita49::VrtKind::Data => {
+                state.data += 1;
+                if state
+                    .data_size
+                    .is_some_and(|size| size != header.frame_size)
+                {
+                    state.size_changes += 1;
+                }
+                state.data_size = Some(header.frame_size);
+            }
+            vita49::VrtKind::Context => {
+                state.context += 1;
+                state.since_context = 0;
+                return;
+            }
+            vita49::VrtKind::Other => state.other += 1,
+        }
+        state.since_context += 1;
+        if let Some(gap) = self.vita49_context_gap
+            && state.since_context == gap + 1
+        {
+            log::warn!("more than {gap} packets since the last VITA-49 context packet");
+        }
+    }
+
+    /// Parse a packet as the type of the group it was sent to, counting it
+    /// for that group. Packets for no group of ours are left out.
+    fn group(
+        &self,
+        packet: &Packet,
+        state: &mut GroupsState,
+        groups: &[(SocketAddrV4, PacketType)],
+    ) {
+        let Some((destination, packet_type)) = group_of(groups, packet) else {
+            return;
+        };
+        let group = state
+            .groups
+            .entry(destination)
+            .or_insert_with(|| GroupState::new(packet_type));
+        group.count += 1;
+        match &mut group.kind {
+            KindState::Plain => {}
+            KindState::Sdds(state) => self.sdds(packet, state),
+            KindState::Vita49(state) => self.vita49(packet, state),
+            KindState::Mdns(state) => state.process(packet),
+        }
+    }
+
+    /// The totals, then each group's packet type's columns, in the order the
+    /// groups were given.
+    fn format_groups(
+        &self,
+        count: u64,
+        rate: f64,
+        state: &GroupsState,
+        groups: &[(SocketAddrV4, PacketType)],
+    ) -> String {
+        let mut s = format_plain(count, rate);
+        for &(destination, packet_type) in groups {
+            let quiet;
+            let group = match state.groups.get(&destination) {
+                Some(group) => group,
+                None => {
+                    quiet = GroupState::new(packet_type);
+                    &quiet
+                }
+            };
+            let share = match count {
+                0 => 0.0,
+                count => rate * group.count as f64 / count as f64,
+            };
+            let columns = match &group.kind {
+                KindState::Plain => format_plain(group.count, share),
+                KindState::Sdds(state) => self.format_sdds(group.count, share, state),
+                KindState::Vita49(state) => format_vita49(group.count, share, state),
+                KindState::Mdns(state) => format_mdns(group.count, share, state),
+            };
+            s.push_str(&format!("  | {destination} {packet_type} {columns}"));
+        }
+        s
+    }
+}
+
+fn format_plain(count: u64, rate: f64) -> String {
+    format!("packets: {count}  rate: {rate:.2} pkt/s")
+}
+
+fn format_vita49(count: u64, rate: f64, state: &Vita49State) -> String {
+    format!(
+        "packets: {count}  rate: {rate:.2} pkt/s  skipped: {}  data: {}  ctx: {}  other: {}",
+        state.skipped_in_period, state.data, state.context, state.other
+    )
+}
+
+fn format_mdns(count: u64, rate: f64, state: &mdns::MdnsState) -> String {
+    let mut s = format!(
+        "packets: {count}  rate: {rate:.2} pkt/s  queries: {}  responses: {}  services: {}",
+        state.queries,
+        state.responses,
+        state.services.len()
+    );
+    if state.malformed > 0 {
+        s.push_str(&format!("  malformed: {}", state.malformed));
+    }
+    s
+}
+
+/// The group a packet was sent to, and its packet type.
+fn group_of(
+    groups: &[(SocketAddrV4, PacketType)],
+    packet: &Packet,
+) -> Option<(SocketAddrV4, PacketType)> {
+    let destination = packet.destination()?;
+    groups
+        .iter()
+        .find(|(group, _)| *group == destination)
+        .copied()
+}
+
+fn produce_stats<S: IntervalState>(
+    (data_rx, data_tx): &mut (Box<dyn BatchReceiver>, Box<dyn BatchSender>),
     shared_state: &SharedState,
-    hex_print: impl Fn(&[u8]),
-    process_packet: impl Fn(&[u8], &mut S),
+    dump: &mut Dumper,
+    extras: Extras,
+    describe: impl Fn(&Packet) -> Option<String>,
+    process_packet: impl Fn(&Packet, &mut S),
     format_stats: impl Fn(u64, f64, &S) -> String,
 ) -> Result<()> {
-    let mut last_time = Instant::now();
+    let started = Instant::now();
+    let mut clock = IntervalClock::new(STATISTICS_INTERVAL, SystemTime::now(), started);
     let mut packet_count = 0u64;
+    let mut total_count = 0u64;
+    let mut total_bytes = 0u64;
     let mut state = S::default();
+    let mut last_transmit = TransmitTotals::default();
+    let mut last_filters = (0, 0);
+    let mut last_policed = 0;
+    let mut last_reorder = (0, 0);
+    let mut last_dejitter = (0, 0);
+    let mut last_fec = FecTotals::default();
+    let mut last_patch = (0, 0);
+    let mut last_too_short = 0;
+    let mut last_truncated = 0;
+    let mut last_stalls = 0;
+    let mut last_own = 0;
+    let mut ttl: Option<(u8, u8)> = None;
+    let mut last_ttl: Option<(u8, u8)> = None;
+    let mut latency = Latency::default();
+    let mut warned_skew = false;
+    let mut sequence = SeqTracker::default();
+    let gate = GapGate::new(extras.seq_gap_allowance, shared_state);
+    let mut rates = Rates::new(Instant::now());
+    // Deepest the input queue got this interval, in batches
+    let mut queue_peak = 0usize;
+    let mut ports = BTreeMap::new();
+    let mut changes = extras.on_change.map(ChangeWatch::new);
+    let mut senders = extras
+        .senders
+        .map(|timeout| Senders::new(timeout, MAX_SENDERS).with_names(shared_state.names.clone()));
+    let mut cpu = CpuUsage::new(&shared_state.cpu, started);
+    let mut last_batch_fill = [0u64; 4];
+    // This interval's payloads, and the whole run's
+    let mut unique = extras
+        .unique
+        .map(|limit| (UniqueCount::new(limit), UniqueCount::new(limit)));
+    let mut nic = extras.nic.map(|mut nic| {
+        let sample = nic.sample();
+        (nic, sample)
+    });
+    let mut drain = ExitDrain::new(extras.drain_timeout);
+    let mut gave_up = false;
 
     loop {
-        let packets = match data_rx.recv_timeout(Duration::from_millis(100)) {
-            Ok(packets) => packets,
-            Err(crossbeam_channel::RecvTimeoutError::Timeout) => continue,
+        // The writer gives up on what's still queued for it at about the
+        // same time
+        if drain.spent(shared_state) {
+            drain.abandon("statistics", &mut **data_rx, shared_state);
+            gave_up = true;
+            break;
+        }
+
+        // Signals are checked between batches, and every timeout while idle.
+        // Waits end on the next line's boundary, so it isn't printed late.
+        let wait = clock.wait(SystemTime::now(), POLL);
+        let batch = match data_rx.pop_timeout(wait) {
+            Ok(packets) => Some(packets),
+            // Once exit is signaled, keep passing batches on until the queue is empty
+            Err(_) if shared_state.should_exit() => break,
+            Err(crossbeam_channel::RecvTimeoutError::Timeout) => None,
             Err(e) => return Err(e.into()),
         };
 
-        let is_eof = packets.is_empty();
+        if shared_state.take_dump_toggle() {
+            dump.toggle()?;
+        }
+        let snapshot = shared_state.take_snapshot();
+        let received = batch.is_some();
+
+        // Sampled here so the reader doesn't pay for it. The batch just
+        // taken was queued too.
+        let (queued, _) = data_rx.occupancy();
+        queue_peak = queue_peak.max(queued + usize::from(received));
+
+        // Stripped first, so everything after sees the packet as sent
+        let batch = match (batch, &extras.strip) {
+            (Some(mut packets), Some(strip)) if !packets.is_empty() => {
+                strip_packets(&mut packets, strip.bytes, shared_state);
+                // Emptied by --strip, which isn't EOF
+                if packets.is_empty() {
+                    writer::recycle(&strip.memory_return, packets)?;
+                    None
+                } else {
+                    Some(packets)
+                }
+            }
+            (batch, _) => batch,
+        };
+
+        let mut is_eof = false;
+        if let Some(mut packets) = batch {
+            is_eof = packets.is_empty();
+            // How long the stream took to show up is worth knowing by itself
+            if total_count == 0 && !is_eof {
+                log::info!("first packet after {:.2}s", started.elapsed().as_secs_f64());
+                clock.restart(Instant::now());
+            }
+            let mut batch_bytes = 0u64;
+            let taken = Instant::now();
+
+            for packet in packets.iter_mut() {
+                packet_count += 1;
+                total_count += 1;
+                batch_bytes += packet.len() as u64;
 
-        for packet in packets.iter() {
-            packet_count += 1;
+                if let Some(received) = packet.ttl() {
+                    ttl = widen_ttl(ttl, received);
+                }
+                if extras.per_port
+                    && let Some(destination) = packet.destination()
+                {
+                    *ports.entry(destination.port()).or_insert(0u64) += 1;
+                }
+                if let Some(changes) = changes.as_mut() {
+                    changes.observe(packet.source());
+                }
+                if let (Some(senders), Some(source)) = (senders.as_mut(), packet.source()) {
+                    senders.observe(source, taken, packet.timestamp());
+                }
+                if let Some(offset) = extras.latency_offset {
+                    latency.observe(packet, offset, packet.timestamp());
+                }
+                // Offsets count from the start of the datagram, so a prepended
+                // number only comes off once everything has been read
+                if let Some(field) = extras.check_seq {
+                    let seq = sequence::read(packet, field);
+                    if let Some((expected, seq)) = sequence.observe(seq) {
+                        gate.observe(expected, seq, seq.wrapping_sub(expected) as u64);
+                    }
+                    packet.set_seq(seq);
+                    if field == SeqField::Prepend {
+                        packet.remove_prefix(sequence::SEQ_BYTES);
+                    }
+                }
+                if let Some(template) = &extras.verify_template {
+                    // The tracker wraps at 32 bits, as --stamp-seq does
+                    let seq = template.seq_of(packet).map(|seq| seq as u32);
+                    if let Some((expected, seq)) = sequence.observe(seq) {
+                        gate.observe(expected, seq, seq.wrapping_sub(expected) as u64);
+                    }
+                    packet.set_seq(seq);
+                }
+                if let Some((interval, total)) = unique.as_mut() {
+                    interval.insert(packet);
+                    total.insert(packet);
+                }
 
-            process_packet(packet, &mut state);
+                // Keepalives carry no header to decode
+                if !packet.is_empty() {
+                    process_packet(packet, &mut state);
+                }
 
-            if shared_state.verbose {
-                hex_print(packet);
+                if let Some(dump) = dump.active() {
+                    dump.dump(total_count, packet, describe(packet).as_deref())?;
+                }
+            }
+
+            total_bytes += batch_bytes;
+            rates.observe(Instant::now(), packets.len() as u64, batch_bytes);
+
+            // Hand off the packets to the next thread, including the eof sentinel.
+            // The writer may already be done if it reached its count.
+            match data_tx.try_push(packets) {
+                Ok(()) => {}
+                Err(crossbeam_channel::TrySendError::Disconnected(_)) => break,
+                Err(e) => return Err(e.into()),
             }
         }
 
-        // Hand off the packets to the next thread, including the eof sentinel
-        data_tx.try_send(packets)?;
+        // Quiet senders are noticed while idle too
+        if let Some(senders) = senders.as_mut() {
+            senders.check_silent(Instant::now());
+        }
 
-        let elapsed = last_time.elapsed();
-        if elapsed >= Duration::from_secs(STATISTICS_DELAY_SECS) {
+        // Lines wait for traffic, except a snapshot asked for with SIGUSR1
+        let wall = SystemTime::now();
+        if snapshot || ((received || packet_count > 0) && clock.is_due(wall)) {
+            let (at, elapsed) = clock.end(wall, Instant::now());
             let rate = packet_count as f64 / elapsed.as_secs_f64();
-            log::info!("{}", format_stats(packet_count, rate, &state));
+            let label = extras
+                .label
+                .as_ref()
+                .map_or_else(String::new, |label| format!("[{label}] "));
+            let mut line = label.clone();
+            line.push_str(&format_interval_time(at));
+            line.push_str(&format_stats(packet_count, rate, &state));
+            line.push_str(&rates.format());
+            if let Some(range) = ttl {
+                line.push_str(&format!("  ttl: {}", format_ttl(range)));
+            }
+            if let Some(senders) = senders.as_mut() {
+                line.push_str(&senders.format());
+            }
+            if let Some((interval, total)) = unique.as_mut() {
+                line.push_str(&interval.format(packet_count));
+                shared_state.unique.publish(total);
+                interval.clear();
+            }
+            if extras.per_port {
+                line.push_str(&format_ports(&std::mem::take(&mut ports)));
+            }
+            if let Some(warning) = ttl_warning(last_ttl, ttl) {
+                log::warn!("{warning}");
+            }
+            if extras.latency_offset.is_some() {
+                if latency.negative() > 0 && !warned_skew {
+                    warned_skew = true;
+                    log::warn!(
+                        "packets arriving before they were stamped: the sender's clock is ahead of ours"
+                    );
+                }
+                line.push_str(&latency.format());
+                latency = Latency::default();
+            }
+            let mut seq_lost = 0;
+            if extras.check_seq.is_some() || extras.verify_template.is_some() {
+                let counts = sequence.take_counts();
+                seq_lost = counts.lost;
+                line.push_str(&counts.format());
+            }
+            if extras.transmit {
+                let totals = shared_state.transmit.get();
+                line.push_str(&format_transmit(&totals.since(&last_transmit)));
+                last_transmit = totals;
+            }
+            if extras.filters {
+                let (filtered, unparsed) = shared_state.filters.get();
+                line.push_str(&format!(
+                    "  filtered: {}  unparsed: {}",
+                    filtered - last_filters.0,
+                    unparsed - last_filters.1
+                ));
+                last_filters = (filtered, unparsed);
+            }
+            if extras.police {
+                let policed = shared_state.get_policed();
+                line.push_str(&format!("  policed: {}", policed - last_policed));
+                last_policed = policed;
+            }
+            if extras.reorder {
+                let (reordered, too_late) = shared_state.reorder.get();
+                line.push_str(&format!(
+                    "  reordered: {}  too late: {}",
+                    reordered - last_reorder.0,
+                    too_late - last_reorder.1
+                ));
+                last_reorder = (reordered, too_late);
+            }
+            if extras.dejitter {
+                let (held, late, overflowed) = shared_state.dejitter.get();
+                line.push_str(&format!(
+                    "  held: {held}  late: {}  overflow: {}",
+                    late - last_dejitter.0,
+                    overflowed - last_dejitter.1
+                ));
+                last_dejitter = (late, overflowed);
+            }
+            if extras.fec {
+                let totals = shared_state.fec.get();
+                line.push_str(&format_fec(&totals.since(&last_fec)));
+                last_fec = totals;
+            }
+            if extras.patch {
+                let (applied, skipped) = shared_state.patch.get();
+                line.push_str(&format!(
+                    "  patched: {}  too short: {}",
+                    applied - last_patch.0,
+                    skipped - last_patch.1
+                ));
+                last_patch = (applied, skipped);
+            }
+            if extras.strip.is_some() {
+                let too_short = shared_state.get_too_short();
+                line.push_str(&format!(
+                    "  too short to strip: {}",
+                    too_short - last_too_short
+                ));
+                last_too_short = too_short;
+            }
+            // Shown from the first datagram that didn't fit its buffer on
+            let truncated = shared_state.get_truncated();
+            if truncated > 0 {
+                line.push_str(&format!("  truncated: {}", truncated - last_truncated));
+                last_truncated = truncated;
+            }
+            // Likewise from the reader's first stall
+            let (stalls, _) = shared_state.stalls.get();
+            if stalls > 0 {
+                line.push_str(&format!(
+                    "  {}",
+                    reader::format_stalls(
+                        stalls - last_stalls,
+                        shared_state.stalls.take_interval_worst(),
+                        shared_state.stalls.threshold()
+                    )
+                ));
+                last_stalls = stalls;
+            }
+            // Likewise from the first of our own packets to come back
+            let own = shared_state.own_traffic.dropped();
+            if own > 0 {
+                line.push_str(&format!("  self: {}", own - last_own));
+                last_own = own;
+            }
+            if shared_state.control.is_paused() {
+                line.push_str("  paused");
+            }
+            line.push_str(&cpu.format(&shared_state.cpu, Instant::now()));
+            let (queued, capacity) = data_rx.occupancy();
+            line.push_str(&format_queue(
+                queued,
+                queue_peak,
+                capacity,
+                shared_state.get_dropped_batches(),
+            ));
+            queue_peak = queued;
+            if extras.batch_fill {
+                let calls = shared_state.batch_fill.get();
+                let mut since = calls;
+                for (count, last) in since.iter_mut().zip(last_batch_fill) {
+                    *count -= last;
+                }
+                let fill = reader::format_batch_fill(&since);
+                if !fill.is_empty() {
+                    line.push_str(&format!("  {fill}"));
+                }
+                last_batch_fill = calls;
+            }
+            // Next to the batches dropped, to tell the two kinds of loss apart
+            if let Some((nic, last)) = nic.as_mut() {
+                let sample = nic.sample();
+                line.push_str(&sample.format_since(last));
+                *last = sample;
+            }
+            let show = match changes.as_mut() {
+                Some(changes) => {
+                    let gaps = state.gaps() || seq_lost > 0;
+                    match changes.interval(rate, gaps, state.header_changed()) {
+                        Some(reasons) => {
+                            line.push_str(&reasons);
+                            true
+                        }
+                        None => snapshot,
+                    }
+                }
+                None => true,
+            };
+            if show {
+                log::info!("{line}");
+            }
+            if snapshot {
+                log::info!(
+                    "{label}{}",
+                    format_totals(
+                        total_count,
+                        total_bytes,
+                        started.elapsed(),
+                        Some((
+                            shared_state.get_write_count(),
+                            shared_state.get_write_bytes()
+                        ))
+                    )
+                );
+            }
 
-            last_time = Instant::now();
             packet_count = 0;
-            state = S::default();
+            state = state.next_interval();
+            // An interval with no traffic isn't a path change
+            if ttl.is_some() {
+                last_ttl = ttl.take();
+            }
+        } else if packet_count == 0 && clock.is_due(wall) {
+            // Nothing arrived, so the next line doesn't cover the idle time
+            clock.end(wall, Instant::now());
         }
 
-        if is_eof || shared_state.should_exit() {
+        if is_eof {
             break;
         }
     }
 
-    Ok(())
+    if let Some((_, total)) = &unique {
+        shared_state.unique.publish(total);
+    }
+
+    // Lines were only printed on change, so sum the run up
+    if changes.is_some() {
+        let label = extras
+            .label
+            .as_ref()
+            .map_or_else(String::new, |label| format!("[{label}] "));
+        log::info!(
+            "{label}{}",
+            format_totals(total_count, total_bytes, started.elapsed(), None)
+        );
+    }
+
+    if gave_up {
+        Err(LibError::DrainIncomplete)
+    } else {
+        Ok(())
+    }
 }
 
-// Look roughly like the output of od
-fn print_hex_dump(data: &[u8]) {
-    for (i, chunk) in data.chunks(16).enumerate() {
-        let mut line = format!("{:08x}  ", i * 16);
+/// --stats-on-change: whether an interval differs enough from the one before
+/// it to be worth a line. Holds on to what the last interval looked like,
+/// since the per-interval state is started afresh.
+struct ChangeWatch {
+    percent: f64,
+    // Nothing printed yet, so the next line is the baseline
+    first: bool,
+    last_rate: Option<f64>,
+    sources: BTreeSet<SocketAddrV4>,
+    last_sources: BTreeSet<SocketAddrV4>,
+}
 
-        for (j, byte) in chunk.iter().enumerate() {
-            line.push_str(&format!("{byte:02x} "));
-            if j == 7 {
-                line.push(' ');
-            }
+impl ChangeWatch {
+    fn new(percent: f64) -> Self {
+        Self {
+            percent,
+            first: true,
+            last_rate: None,
+            sources: BTreeSet::new(),
+            last_sources: BTreeSet::new(),
         }
+    }
 
-        if chunk.len() < 16 {
-            for j in chunk.len()..16 {
-                line.push_str("   ");
-                if j == 7 {
-                    line.push(' ');
-                }
-            }
+    fn observe(&mut self, source: Option<SocketAddrV4>) {
+        if let Some(source) = source {
+            self.sources.insert(source);
+        }
+    }
+
+    /// End the interval. None when nothing changed, otherwise what to add
+    /// to the line: nothing for the baseline, or "  changed: rate, gap".
+    fn interval(&mut self, rate: f64, gaps: bool, header: bool) -> Option<String> {
+        let mut reasons = Vec::new();
+        if self
+            .last_rate
+            .is_some_and(|last| (rate - last).abs() > last * self.percent / 100.0)
+        {
+            reasons.push("rate");
+        }
+        if gaps {
+            reasons.push("gap");
+        }
+        let sources = std::mem::take(&mut self.sources);
+        if !self.first && sources != self.last_sources {
+            reasons.push("sources");
+        }
+        if header {
+            reasons.push("header");
+        }
+        self.last_rate = Some(rate);
+        self.last_sources = sources;
+
+        if std::mem::take(&mut self.first) {
+            Some(String::new())
+        } else if reasons.is_empty() {
+            None
+        } else {
+            Some(format!("  changed: {}", reasons.join(", ")))
+        }
+    }
+}
+
+/// --fail-on-gap: sequence numbers skipped since the start, against the
+/// allowance. The first gap past it is logged and ends the run.
+struct GapGate {
+    allowed: Option<u64>,
+    skipped: Cell<u64>,
+    shared_state: SharedState,
+}
+
+impl GapGate {
+    fn new(allowed: Option<u64>, shared_state: &SharedState) -> Self {
+        Self {
+            allowed,
+            skipped: Cell::new(0),
+            shared_state: shared_state.clone(),
+        }
+    }
+
+    /// Count skipped numbers between the one expected and the one that came.
+    fn observe(&self, expected: impl Display, seq: impl Display, skipped: u64) {
+        let Some(allowed) = self.allowed else {
+            return;
+        };
+        let total = self.skipped.get() + skipped;
+        self.skipped.set(total);
+        if total > allowed && !self.shared_state.gap_failed() {
+            log::error!(
+                "sequence gap: expected {expected}, got {seq}; {total} skipped, {allowed} allowed"
+            );
+            self.shared_state.signal_gap_failed();
+        }
+    }
+}
+
+/// -v hex dumps, which SIGUSR2 turns on and off while running.
+struct Dumper {
+    output: DumpOutput,
+    diff: bool,
+    // Kept once created, so turning dumps back on appends to a file
+    dump: Option<HexDump>,
+    on: bool,
+    // Packets left for -v to dump, None for no limit
+    left: Option<u64>,
+}
+
+impl Dumper {
+    fn new(output: DumpOutput, on: bool, diff: bool, count: Option<u64>) -> Result<Self> {
+        let mut dumper = Self {
+            output,
+            diff,
+            dump: None,
+            on,
+            left: count,
+        };
+        if on {
+            dumper.dump = Some(dumper.create()?);
+        }
+        Ok(dumper)
+    }
+
+    fn create(&self) -> Result<HexDump> {
+        let dump = HexDump::create(&self.output)?;
+        Ok(if self.diff { dump.with_diff() } else { dump })
+    }
+
+    /// Open the output now, for dumps turned on later.
+    fn open(&mut self) -> Result<()> {
+        if self.dump.is_none() {
+            self.dump = Some(self.create()?);
+        }
+        Ok(())
+    }
+
+    /// SIGUSR2: dumps every packet once on, until turned off.
+    fn toggle(&mut self) -> Result<()> {
+        self.on = !self.on;
+        if self.on {
+            self.left = None;
+            self.open()?;
+        }
+        log::info!("hex dumps {}", if self.on { "on" } else { "off" });
+        Ok(())
+    }
+
+    /// Where the next packet is dumped, if it is, counting it against the
+    /// packets left to dump.
+    fn active(&mut self) -> Option<&mut HexDump> {
+        if !self.on || self.left == Some(0) {
+            return None;
+        }
+        if let Some(left) = self.left.as_mut() {
+            *left -= 1;
+        }
+        self.dump.as_mut()
+    }
+}
+
+/// Packet and byte rates, smoothed and at their peak. Updated with every
+/// batch, so the peak catches bursts that a once a second rate averages away.
+#[derive(Debug)]
+struct Rates {
+    last: Instant,
+    // Arrived since the smoothed rates were last updated
+    pending: (u64, u64),
+    // Smoothed pkt/s and B/s
+    seeded: bool,
+    packets: f64,
+    bytes: f64,
+    window_start: Instant,
+    window: (u64, u64),
+    // Busiest window this interval, pkt/s and B/s
+    peak: Option<(f64, f64)>,
+}
+
+impl Rates {
+    fn new(now: Instant) -> Self {
+        Self {
+            last: now,
+            pending: (0, 0),
+            seeded: false,
+            packets: 0.0,
+            bytes: 0.0,
+            window_start: now,
+            window: (0, 0),
+            peak: None,
         }
+    }
 
-        line.push_str(" |");
-        for byte in chunk {
-            let c = if byte.is_ascii_graphic() || *byte == b' ' {
-                *byte as char
+    /// A batch of packets and bytes that arrived by now.
+    fn observe(&mut self, now: Instant, packets: u64, bytes: u64) {
+        self.pending.0 += packets;
+        self.pending.1 += bytes;
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        if elapsed > 0.0 {
+            // Weighted by the time covered, as batches arrive unevenly.
+            // The first rate seen stands in for the history there isn't.
+            let alpha = if self.seeded {
+                1.0 - (-elapsed / RATE_TAU.as_secs_f64()).exp()
             } else {
-                '.'
+                1.0
             };
-            line.push(c);
+            self.seeded = true;
+            self.packets += alpha * (self.pending.0 as f64 / elapsed - self.packets);
+            self.bytes += alpha * (self.pending.1 as f64 / elapsed - self.bytes);
+            self.pending = (0, 0);
+            self.last = now;
+        }
+
+        self.window.0 += packets;
+        self.window.1 += bytes;
+        let window = now.saturating_duration_since(self.window_start);
+        if window >= PEAK_WINDOW {
+            let secs = window.as_secs_f64();
+            let (packets, bytes) = (self.window.0 as f64 / secs, self.window.1 as f64 / secs);
+            self.peak = Some(match self.peak {
+                Some((peak_packets, peak_bytes)) => {
+                    (peak_packets.max(packets), peak_bytes.max(bytes))
+                }
+                None => (packets, bytes),
+            });
+            self.window_start = now;
+            self.window = (0, 0);
+        }
+    }
+
+    /// "  avg: 980.2 pkt/s 1.3 MiB/s  peak: 2400.0 pkt/s 3.2 MiB/s", for the
+    /// stats line. The peak starts over for the next interval.
+    fn format(&mut self) -> String {
+        let mut s = format!(
+            "  avg: {:.1} pkt/s {}/s",
+            self.packets,
+            format_size(self.bytes as u64)
+        );
+        if let Some((packets, bytes)) = self.peak.take() {
+            s.push_str(&format!(
+                "  peak: {packets:.1} pkt/s {}/s",
+                format_size(bytes as u64)
+            ));
         }
-        line.push('|');
-        log::info!("{line}");
+        s
+    }
+}
+
+/// When stats lines are due: on whole intervals of the wall clock counted
+/// from the epoch, so lines from runs on different hosts fall on the same
+/// seconds and line up with their logs. The times are passed in, for tests.
+struct IntervalClock {
+    interval: Duration,
+    /// When the interval being counted started, for its true length
+    started: Instant,
+    /// Wall clock time the next line is due
+    due: SystemTime,
+}
+
+impl IntervalClock {
+    fn new(interval: Duration, wall: SystemTime, now: Instant) -> Self {
+        Self {
+            interval,
+            started: now,
+            due: next_boundary(wall, interval),
+        }
+    }
+
+    /// Whether the interval is over. A wall clock stepped back by more than
+    /// an interval is aligned afresh rather than waited out.
+    fn is_due(&mut self, wall: SystemTime) -> bool {
+        if self
+            .due
+            .duration_since(wall)
+            .is_ok_and(|ahead| ahead > self.interval)
+        {
+            self.due = next_boundary(wall, self.interval);
+        }
+        wall >= self.due
+    }
+
+    /// Time the interval from now, as when the stream starts partway into it.
+    fn restart(&mut self, now: Instant) {
+        self.started = now;
+    }
+
+    /// How long to wait for a batch: until the line is due, and no longer
+    /// than poll. A line already due waits on traffic, so poll as usual.
+    fn wait(&self, wall: SystemTime, poll: Duration) -> Duration {
+        match self.due.duration_since(wall) {
+            Ok(left) if !left.is_zero() => left.min(poll),
+            _ => poll,
+        }
+    }
+
+    /// End the interval: the time to stamp its line with, and how long it
+    /// really lasted. A line on time is stamped with its boundary, a snapshot
+    /// between boundaries with now. The next line is due on the first
+    /// boundary after now, so a stall skips the ones it missed rather than
+    /// printing them all at once, and its line's rate covers the stall.
+    fn end(&mut self, wall: SystemTime, now: Instant) -> (SystemTime, Duration) {
+        let at = if wall >= self.due {
+            last_boundary(wall, self.interval)
+        } else {
+            wall
+        };
+        let elapsed = now.saturating_duration_since(self.started);
+        self.started = now;
+        self.due = next_boundary(wall, self.interval);
+        (at, elapsed)
+    }
+}
+
+/// The latest whole interval since the epoch at or before wall.
+fn last_boundary(wall: SystemTime, interval: Duration) -> SystemTime {
+    let since = wall
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let interval = interval.as_nanos().max(1);
+    SystemTime::UNIX_EPOCH + Duration::from_nanos((since - since % interval) as u64)
+}
+
+/// The first whole interval since the epoch after wall.
+fn next_boundary(wall: SystemTime, interval: Duration) -> SystemTime {
+    last_boundary(wall, interval) + interval
+}
+
+/// "2026-10-17T03:33:10.000Z  ", the time at the front of a stats line.
+fn format_interval_time(at: SystemTime) -> String {
+    let at: chrono::DateTime<chrono::Utc> = at.into();
+    format!("{}  ", at.format("%Y-%m-%dT%H:%M:%S%.3fZ"))
+}
+
+/// "  queue: 2/14/65 batches  dropped: 0": batches queued now, the most this
+/// interval and how many fit, then batches dropped since the start.
+fn format_queue(queued: usize, peak: usize, capacity: usize, dropped: u64) -> String {
+    format!("  queue: {queued}/{peak}/{capacity} batches  dropped: {dropped}")
+}
+
+/// Everything since the start, after a SIGUSR1 snapshot's stats line or at
+/// the end with --stats-on-change. The writer is still catching up at the
+/// end, so only a snapshot has its packets and bytes written.
+fn format_totals(
+    packets: u64,
+    bytes: u64,
+    elapsed: Duration,
+    written: Option<(u64, u64)>,
+) -> String {
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 {
+        packets as f64 / secs
+    } else {
+        0.0
+    };
+    let mut s = format!(
+        "totals: {packets} packets  {}  in {secs:.1}s  avg: {rate:.1} pkt/s",
+        format_size(bytes)
+    );
+    if let Some((written, written_bytes)) = written {
+        s.push_str(&format!(
+            "  written: {written}  {}",
+            format_size(written_bytes)
+        ));
+    }
+    s
+}
+
+/// --strip: take bytes off the front of every packet in the batch, dropping
+/// those shorter than that.
+fn strip_packets(packets: &mut Packets, bytes: usize, shared_state: &SharedState) {
+    let arrived = packets.len();
+    packets.retain(|packet| packet.len() >= bytes);
+    shared_state.add_too_short((arrived - packets.len()) as u64);
+    for packet in packets.iter_mut() {
+        packet.remove_prefix(bytes);
+    }
+}
+
+/// Min and max received TTL, including ttl.
+fn widen_ttl(range: Option<(u8, u8)>, ttl: u8) -> Option<(u8, u8)> {
+    Some(match range {
+        Some((min, max)) => (min.min(ttl), max.max(ttl)),
+        None => (ttl, ttl),
+    })
+}
+
+/// "62", or "60-62" when it varied over the interval.
+fn format_ttl((min, max): (u8, u8)) -> String {
+    if min == max {
+        min.to_string()
+    } else {
+        format!("{min}-{max}")
+    }
+}
+
+/// A received TTL that moved since the last interval means the packets took
+/// a different number of hops, so the route to the sender changed.
+fn ttl_warning(previous: Option<(u8, u8)>, current: Option<(u8, u8)>) -> Option<String> {
+    let (min, max) = current?;
+    if previous == current {
+        return None;
+    }
+
+    if min <= LOW_TTL {
+        return Some(format!(
+            "packets arriving with TTL {min}, the next router would drop them"
+        ));
+    }
+
+    Some(format!(
+        "received TTL changed from {} to {}, the path to the sender may have changed",
+        format_ttl(previous?),
+        format_ttl((min, max))
+    ))
+}
+
+/// "  sent: N  errors: M  retries: K", with errors broken down by class
+/// and, with several destinations, what each group was sent.
+/// Packets by receiving port this interval.
+fn format_ports(ports: &BTreeMap<u16, u64>) -> String {
+    let ports: Vec<String> = ports
+        .iter()
+        .map(|(port, count)| format!("{port} {count}"))
+        .collect();
+    format!("  ports: {}", ports.join(", "))
+}
+
+fn format_fec(interval: &FecTotals) -> String {
+    let mut s = format!("  fec data: {}  parity: {}", interval.data, interval.parity);
+    // Only the receiver rebuilds, and only loss or damage gives it work
+    for (name, count) in [
+        ("recovered", interval.recovered),
+        ("unrecoverable", interval.unrecoverable),
+        ("corrupt", interval.corrupt),
+    ] {
+        if count > 0 {
+            s.push_str(&format!("  {name}: {count}"));
+        }
+    }
+    s
+}
+
+fn format_transmit(interval: &TransmitTotals) -> String {
+    let mut s = format!(
+        "  sent: {}  errors: {}",
+        interval.sent,
+        interval.total_errors()
+    );
+
+    let classes: Vec<String> = SendErrorClass::ALL
+        .iter()
+        .zip(interval.errors)
+        .filter(|(_, count)| *count > 0)
+        .map(|(class, count)| format!("{} {count}", class.name()))
+        .collect();
+    if !classes.is_empty() {
+        s.push_str(&format!(" ({})", classes.join(", ")));
+    }
+
+    s.push_str(&format!("  retries: {}", interval.retries));
+    if interval.skipped > 0 {
+        s.push_str(&format!("  skipped: {}", interval.skipped));
+    }
+
+    // Groups alone unless there's more than one port to tell apart
+    let one_port = interval
+        .per_destination
+        .windows(2)
+        .all(|pair| matches!(pair, [a, b] if a.0.port() == b.0.port()));
+    if !interval.per_destination.is_empty() {
+        let sent: Vec<String> = interval
+            .per_destination
+            .iter()
+            .map(|(destination, sent)| {
+                if one_port {
+                    format!("{} {sent}", destination.ip())
+                } else {
+                    format!("{destination} {sent}")
+                }
+            })
+            .collect();
+        let label = if one_port { "groups" } else { "destinations" };
+        s.push_str(&format!("  {label}: {}", sent.join(", ")));
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_rates() {
+        let start = Instant::now();
+        let mut rates = Rates::new(start);
+        let at = |millis: u64| start + Duration::from_millis(millis);
+
+        // A steady 1000 pkt/s of 1 KiB, in 10ms batches, for a long while
+        for tick in 1..=10_000 {
+            rates.observe(at(tick * 10), 10, 10 * 1024);
+        }
+        assert!((rates.packets - 1000.0).abs() < 1.0, "{}", rates.packets);
+        assert_eq!(
+            rates.format(),
+            "  avg: 1000.0 pkt/s 1000.0 KiB/s  peak: 1000.0 pkt/s 1000.0 KiB/s"
+        );
+
+        // A 100ms burst at ten times the rate barely moves the average
+        for tick in 1..=10 {
+            rates.observe(at(100_000 + tick * 10), 100, 100 * 1024);
+        }
+        assert!(rates.packets < 2000.0, "{}", rates.packets);
+        assert_eq!(
+            rates.peak.map(|(packets, _)| packets.round()),
+            Some(10000.0)
+        );
+
+        // Nothing closed a window since the peak was taken
+        rates.format();
+        assert!(!rates.format().contains("peak"));
+    }
+
+    #[test]
+    fn test_interval_clock() {
+        let second = Duration::from_secs(1);
+        let epoch = SystemTime::UNIX_EPOCH;
+        let wall = |millis: u64| epoch + Duration::from_millis(millis);
+        let start = Instant::now();
+        let now = |millis: u64| start + Duration::from_millis(millis);
+
+        // Started mid-second, the first line is due on the next second and
+        // timed from the first packet
+        let mut clock = IntervalClock::new(second, wall(1_000_400), now(0));
+        clock.restart(now(100));
+        assert!(!clock.is_due(wall(1_000_900)));
+        assert_eq!(clock.wait(wall(1_000_950), POLL), Duration::from_millis(50));
+        assert_eq!(clock.wait(wall(1_000_800), POLL), POLL);
+        assert!(clock.is_due(wall(1_001_000)));
+        // Stamped with the boundary, the rate taken over the true 520ms
+        assert_eq!(
+            clock.end(wall(1_001_020), now(620)),
+            (wall(1_001_000), Duration::from_millis(520))
+        );
+        assert!(!clock.is_due(wall(1_001_500)));
+
+        // Once due, the line waits on traffic without spinning
+        assert!(clock.is_due(wall(1_002_000)));
+        assert_eq!(clock.wait(wall(1_002_000), POLL), POLL);
+
+        // A stall past several boundaries gives one line covering all of it
+        assert_eq!(
+            clock.end(wall(1_004_700), now(4_300)),
+            (wall(1_004_000), Duration::from_millis(3_680))
+        );
+        assert!(!clock.is_due(wall(1_004_999)));
+        assert!(clock.is_due(wall(1_005_000)));
+
+        // A snapshot between boundaries is stamped with now, and the next
+        // line stays on the boundary
+        assert_eq!(
+            clock.end(wall(1_004_800), now(4_400)),
+            (wall(1_004_800), Duration::from_millis(100))
+        );
+        assert!(clock.is_due(wall(1_005_000)));
+
+        // A wall clock stepped back is aligned afresh
+        assert!(!clock.is_due(wall(900_300)));
+        assert_eq!(clock.wait(wall(900_950), POLL), Duration::from_millis(50));
+        assert!(clock.is_due(wall(901_000)));
+    }
+
+    #[test]
+    fn test_format_interval_time() {
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_792_208_590);
+        assert_eq!(format_interval_time(at), "2026-10-17T03:43:10.000Z  ");
+    }
+
+    #[test]
+    fn test_ttl_warning() {
+        let range = |ttl: &[u8]| ttl.iter().fold(None, |range, ttl| widen_ttl(range, *ttl));
+        assert_eq!(range(&[62, 60, 61]), Some((60, 62)));
+        assert_eq!(format_ttl((60, 62)), "60-62");
+        assert_eq!(format_ttl((62, 62)), "62");
+
+        // Steady, or the first interval seen
+        assert_eq!(ttl_warning(range(&[62]), range(&[62])), None);
+        assert_eq!(ttl_warning(None, range(&[62])), None);
+        assert_eq!(ttl_warning(range(&[62]), None), None);
+
+        assert_eq!(
+            ttl_warning(range(&[62]), range(&[60, 62])).as_deref(),
+            Some("received TTL changed from 62 to 60-62, the path to the sender may have changed")
+        );
+        assert_eq!(
+            ttl_warning(None, range(&[1])).as_deref(),
+            Some("packets arriving with TTL 1, the next router would drop them")
+        );
+    }
+
+    #[test]
+    fn test_format_queue() {
+        assert_eq!(
+            format_queue(2, 14, 65, 0),
+            "  queue: 2/14/65 batches  dropped: 0"
+        );
+    }
+
+    #[test]
+    fn test_format_ports() {
+        let ports = BTreeMap::from([(29496, 12), (29495, 10)]);
+        assert_eq!(format_ports(&ports), "  ports: 29495 10, 29496 12");
+    }
+
+    #[test]
+    fn test_gap_gate() {
+        let shared_state = SharedState::new(PacketType::Sdds, false);
+        let gate = GapGate::new(Some(3), &shared_state);
+        gate.observe(3, 5, 2);
+        assert!(!shared_state.should_exit());
+        gate.observe(9, 10, 1);
+        assert!(!shared_state.gap_failed());
+        gate.observe(12, 13, 1);
+        assert!(shared_state.gap_failed());
+        assert!(shared_state.should_exit());
+
+        // Without --fail-on-gap nothing ends the run
+        let shared_state = SharedState::new(PacketType::Sdds, false);
+        GapGate::new(None, &shared_state).observe(0, 1000, 1000);
+        assert!(!shared_state.should_exit());
+    }
+
+    #[test]
+    fn test_format_totals() {
+        assert_eq!(
+            format_totals(
+                5000,
+                3 << 20,
+                Duration::from_secs(10),
+                Some((4990, 2 << 20))
+            ),
+            "totals: 5000 packets  3.0 MiB  in 10.0s  avg: 500.0 pkt/s  written: 4990  2.0 MiB"
+        );
+        assert_eq!(
+            format_totals(0, 0, Duration::ZERO, Some((0, 0))),
+            "totals: 0 packets  0.0 B  in 0.0s  avg: 0.0 pkt/s  written: 0  0.0 B"
+        );
+        assert_eq!(
+            format_totals(30, 900, Duration::from_secs(3), None),
+            "totals: 30 packets  900.0 B  in 3.0s  avg: 10.0 pkt/s"
+        );
+    }
+
+    #[test]
+    fn test_format_transmit() {
+        let clean = TransmitTotals {
+            sent: 1000,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_transmit(&clean),
+            "  sent: 1000  errors: 0  retries: 0"
+        );
+
+        let congested = TransmitTotals {
+            sent: 990,
+            retries: 12,
+            errors: [12, 0, 0, 1],
+            ..Default::default()
+        };
+        assert_eq!(
+            format_transmit(&congested),
+            "  sent: 990  errors: 13 (congestion 12, rejected 1)  retries: 12"
+        );
+
+        let fanned_out = TransmitTotals {
+            sent: 4,
+            per_destination: vec![
+                (SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 1), 5000), 2),
+                (SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 2), 5000), 2),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            format_transmit(&fanned_out),
+            "  sent: 4  errors: 0  retries: 0  groups: 239.1.1.1 2, 239.1.1.2 2"
+        );
+
+        let two_ports = TransmitTotals {
+            sent: 4,
+            per_destination: vec![
+                (SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 1), 29495), 2),
+                (SocketAddrV4::new(Ipv4Addr::new(239, 1, 1, 1), 29496), 2),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            format_transmit(&two_ports),
+            "  sent: 4  errors: 0  retries: 0  destinations: 239.1.1.1:29495 2, 239.1.1.1:29496 2"
+        );
+    }
+
+    #[test]
+    fn test_change_watch() {
+        let mut changes = ChangeWatch::new(10.0);
+        let source = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 5000);
+        changes.observe(Some(source));
+        // The first line is printed whatever it shows
+        assert_eq!(changes.interval(1000.0, false, false), Some(String::new()));
+
+        changes.observe(Some(source));
+        assert_eq!(changes.interval(1090.0, false, false), None);
+        changes.observe(Some(source));
+        assert_eq!(
+            changes.interval(1250.0, true, false),
+            Some("  changed: rate, gap".to_string())
+        );
+
+        // A second sender turns up, then goes away again
+        changes.observe(Some(source));
+        changes.observe(Some(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 5000)));
+        assert_eq!(
+            changes.interval(1250.0, false, true),
+            Some("  changed: sources, header".to_string())
+        );
+        changes.observe(Some(source));
+        assert_eq!(
+            changes.interval(1250.0, false, false),
+            Some("  changed: sources".to_string())
+        );
+        changes.observe(Some(source));
+        assert_eq!(changes.interval(1250.0, false, false), None);
+    }
+
+    #[test]
+    #[allow(clippy::indexing_slicing)]
+    fn test_strip_packets() {
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let mut packets = Packets::new(3, 16);
+        for (packet, payload) in
+            packets
+                .iter_mut()
+                .zip([&b"shim0000VRLP"[..], b"short", b"shim0000"])
+        {
+            packet.data_mut()[..payload.len()].copy_from_slice(payload);
+            packet.set_length(payload.len());
+        }
+
+        strip_packets(&mut packets, 8, &shared_state);
+        let kept: Vec<&[u8]> = packets.iter().map(|packet| &packet[..]).collect();
+        // Nothing left but the shim is an empty packet, not a short one
+        assert_eq!(kept, vec![&b"VRLP"[..], b""]);
+        assert_eq!(shared_state.get_too_short(), 1);
+    }
+
+    #[test]
+    fn test_dump_count() -> Result<()> {
+        let mut dumper = Dumper::new(DumpOutput::Stderr, true, false, Some(2))?;
+        assert!(dumper.active().is_some());
+        assert!(dumper.active().is_some());
+        assert!(dumper.active().is_none());
+
+        // SIGUSR2 off and on again dumps everything
+        dumper.toggle()?;
+        assert!(dumper.active().is_none());
+        dumper.toggle()?;
+        for _ in 0..5 {
+            assert!(dumper.active().is_some());
+        }
+
+        let mut dumper = Dumper::new(DumpOutput::Stderr, true, false, None)?;
+        for _ in 0..5 {
+            assert!(dumper.active().is_some());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_fec() {
+        let sending = FecTotals {
+            data: 400,
+            parity: 100,
+            ..Default::default()
+        };
+        assert_eq!(format_fec(&sending), "  fec data: 400  parity: 100");
+
+        let lossy = FecTotals {
+            data: 398,
+            parity: 100,
+            recovered: 1,
+            unrecoverable: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            format_fec(&lossy),
+            "  fec data: 398  parity: 100  recovered: 1  unrecoverable: 2"
+        );
     }
 }
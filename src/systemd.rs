@@ -0,0 +1,158 @@
+/// Running as a systemd service. Socket activation hands the reader UDP
+/// sockets systemd bound already, so a restart doesn't churn the group
+/// membership, and sd_notify tells systemd once the group is joined and
+/// how fast packets arrive. Without LISTEN_FDS or NOTIFY_SOCKET in the
+/// environment all of it does nothing.
+use std::ffi::OsStr;
+use std::ops::Range;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::{Mutex, OnceLock};
+
+use nix::fcntl::{FcntlArg, FdFlag, fcntl};
+use socket2::{Socket, Type};
+
+use crate::error::Result;
+
+/// systemd passes sockets from this descriptor on, after stdio.
+const LISTEN_FDS_START: RawFd = 3;
+
+static ACTIVATED: OnceLock<Mutex<Vec<Socket>>> = OnceLock::new();
+
+/// The datagram socket systemd bound to port, if it passed one. Each is
+/// handed out once.
+pub fn take_activated_socket(port: u16) -> Option<Socket> {
+    let sockets = ACTIVATED.get_or_init(|| Mutex::new(listen_sockets()));
+    let mut sockets = sockets.lock().ok()?;
+    let index = sockets
+        .iter()
+        .position(|socket| local_port(socket) == Some(port))?;
+    Some(sockets.swap_remove(index))
+}
+
+/// Tell systemd something, like "READY=1" or "STATUS=...". Failing to is
+/// only logged, the service carries on regardless.
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send_notify(&path, state) {
+        log::debug!("sd_notify {state} to {}: {e}", path.display());
+    }
+}
+
+/// NOTIFY_SOCKET is a path, or on Linux an abstract socket name after an @.
+fn send_notify(path: &OsStr, state: &str) -> Result<()> {
+    let address = match path.as_encoded_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        _ => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+/// The sockets in LISTEN_FDS, when LISTEN_PID says they were meant for us
+/// rather than a parent that left them in the environment.
+fn listen_sockets() -> Vec<Socket> {
+    let count = listen_fd_count(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+    if count > 0 {
+        log::debug!("systemd passed {count} sockets");
+    }
+    adopt(LISTEN_FDS_START..LISTEN_FDS_START.saturating_add(count))
+}
+
+fn listen_fd_count(pid: Option<&str>, fds: Option<&str>, our_pid: u32) -> RawFd {
+    match (pid.map(str::parse::<u32>), fds.map(str::parse::<RawFd>)) {
+        (Some(Ok(pid)), Some(Ok(count))) if pid == our_pid && count > 0 => count,
+        _ => 0,
+    }
+}
+
+/// Own the datagram sockets among fds, closed across --exec. Anything else
+/// systemd passed is closed, nothing here reads from it.
+fn adopt(fds: Range<RawFd>) -> Vec<Socket> {
+    fds.filter_map(|fd| {
+        // SAFETY: LISTEN_PID names this process, so systemd passed these
+        // descriptors to us alone, and nothing else here takes them.
+        let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+        if let Err(e) = fcntl(&owned, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)) {
+            log::debug!("FD_CLOEXEC on socket {fd} from systemd: {e}");
+        }
+        let socket = Socket::from(owned);
+        match socket.r#type() {
+            Ok(Type::DGRAM) if local_port(&socket).is_some() => Some(socket),
+            _ => {
+                log::warn!("ignoring descriptor {fd} from systemd, not a UDP socket");
+                None
+            }
+        }
+    })
+    .collect()
+}
+
+fn local_port(socket: &Socket) -> Option<u16> {
+    socket
+        .local_addr()
+        .ok()?
+        .as_socket_ipv4()
+        .map(|address| address.port())
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::os::fd::IntoRawFd;
+    use std::time::Duration;
+
+    use socket2::{Domain, Protocol};
+
+    use super::*;
+
+    #[test]
+    fn test_listen_fd_count() {
+        assert_eq!(listen_fd_count(Some("42"), Some("2"), 42), 2);
+        // Meant for another process
+        assert_eq!(listen_fd_count(Some("41"), Some("2"), 42), 0);
+        assert_eq!(listen_fd_count(None, Some("2"), 42), 0);
+        assert_eq!(listen_fd_count(Some("42"), None, 42), 0);
+        assert_eq!(listen_fd_count(Some("42"), Some("x"), 42), 0);
+        assert_eq!(listen_fd_count(Some("42"), Some("-1"), 42), 0);
+    }
+
+    #[test]
+    fn test_adopt_keeps_udp_sockets() {
+        let udp = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP)).expect("socket");
+        udp.bind(&SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0).into())
+            .expect("bind");
+        let port = local_port(&udp).expect("port");
+        let fd = udp.into_raw_fd();
+
+        let sockets = adopt(fd..fd + 1);
+        assert_eq!(sockets.len(), 1);
+        assert_eq!(sockets.first().and_then(local_port), Some(port));
+    }
+
+    #[test]
+    fn test_send_notify() {
+        let path = std::env::temp_dir().join(format!("mnc-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixDatagram::bind(&path).expect("bind");
+        listener
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .expect("timeout");
+
+        send_notify(path.as_os_str(), "READY=1").expect("notify");
+        let mut buffer = [0u8; 64];
+        let length = listener.recv(&mut buffer).expect("recv");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(buffer.get(..length), Some(&b"READY=1"[..]));
+    }
+}
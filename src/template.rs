@@ -0,0 +1,263 @@
+/// --template: packets made up from a pattern instead of read from -i, for
+/// load tests that want numbered, timestamped payloads without a shell loop.
+/// The pattern is compiled into segments once at startup, and expanding it
+/// writes each token straight into a reused buffer, so a packet costs no
+/// allocations however fast they go out.
+///
+/// The receiver's --verify-template reads {seq} back out of each payload to
+/// count loss, reordering and duplicates as --check-seq does.
+use std::io::Write as _;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+
+use crate::MAX_PACKET_BYTES;
+
+/// The tokens a template may hold, for error messages.
+const TOKENS: &str = "{seq}, {time_iso}, {time_unix_us} and {rand:N}";
+
+// {time_iso} is always this long: 2026-10-17T04:26:45.682873Z
+const TIME_ISO_BYTES: usize = 27;
+
+// {rand:N} draws from these, so the payload stays readable as text
+const RAND_CHARS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(Vec<u8>),
+    /// Counts up by one for each packet, from 0
+    Seq,
+    /// UTC with microseconds
+    TimeIso,
+    /// Microseconds since the Unix epoch
+    TimeUnixUs,
+    /// This many random letters and digits
+    Rand(usize),
+}
+
+/// A compiled --template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+/// Compile a template. {{ and }} stand for literal braces.
+pub fn parse_template(s: &str) -> std::result::Result<Template, String> {
+    let mut segments = Vec::new();
+    let mut literal = Vec::new();
+    let mut rest = s;
+    while let Some(at) = rest.find(['{', '}']) {
+        let (before, from) = rest.split_at(at);
+        literal.extend_from_slice(before.as_bytes());
+        if let Some(after) = from.strip_prefix("{{").or_else(|| from.strip_prefix("}}")) {
+            literal.push(from.as_bytes().first().copied().unwrap_or(b'{'));
+            rest = after;
+            continue;
+        }
+        let Some(inner) = from.strip_prefix('{') else {
+            return Err(format!(
+                "Unmatched }} in template, write }}}} for a }}: {s}"
+            ));
+        };
+        let Some((token, after)) = inner.split_once('}') else {
+            return Err(format!("Unclosed {{ in template, write {{{{ for a {{: {s}"));
+        };
+        let segment = match token {
+            "seq" => Segment::Seq,
+            "time_iso" => Segment::TimeIso,
+            "time_unix_us" => Segment::TimeUnixUs,
+            _ => match token.strip_prefix("rand:").map(str::parse::<usize>) {
+                Some(Ok(n)) if (1..=MAX_PACKET_BYTES).contains(&n) => Segment::Rand(n),
+                Some(_) => {
+                    return Err(format!(
+                        "Expected {{rand:N}} with N from 1 to {MAX_PACKET_BYTES}, got: {{{token}}}"
+                    ));
+                }
+                None => {
+                    return Err(format!(
+                        "Unknown template token {{{token}}}, expected {TOKENS}"
+                    ));
+                }
+            },
+        };
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(std::mem::take(&mut literal)));
+        }
+        segments.push(segment);
+        rest = after;
+    }
+    literal.extend_from_slice(rest.as_bytes());
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(Template { segments })
+}
+
+impl Template {
+    /// Whether {seq} is in it, for --verify-template to find.
+    pub fn has_seq(&self) -> bool {
+        self.segments.contains(&Segment::Seq)
+    }
+
+    /// The payload for packet seq, made at now, in place of out's contents.
+    pub fn expand(&self, seq: u64, now: SystemTime, rng: &mut Rng, out: &mut Vec<u8>) {
+        out.clear();
+        for segment in &self.segments {
+            // Writing to a Vec can't fail
+            let _ = match segment {
+                Segment::Literal(literal) => {
+                    out.extend_from_slice(literal);
+                    Ok(())
+                }
+                Segment::Seq => write!(out, "{seq}"),
+                Segment::TimeIso => {
+                    let at = DateTime::<Utc>::from(now);
+                    write!(
+                        out,
+                        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+                        at.year(),
+                        at.month(),
+                        at.day(),
+                        at.hour(),
+                        at.minute(),
+                        at.second(),
+                        at.timestamp_subsec_micros().min(999_999)
+                    )
+                }
+                Segment::TimeUnixUs => write!(
+                    out,
+                    "{}",
+                    now.duration_since(SystemTime::UNIX_EPOCH)
+                        .map_or(0, |since| since.as_micros())
+                ),
+                Segment::Rand(n) => {
+                    out.extend((0..*n).map(|_| rng.pick(RAND_CHARS)));
+                    Ok(())
+                }
+            };
+        }
+        out.truncate(MAX_PACKET_BYTES);
+    }
+
+    /// {seq} read back out of a payload this template made, None when the
+    /// payload doesn't follow it that far.
+    pub fn seq_of(&self, payload: &[u8]) -> Option<u64> {
+        let mut rest = payload;
+        for segment in &self.segments {
+            rest = match segment {
+                Segment::Literal(literal) => rest.strip_prefix(literal.as_slice())?,
+                Segment::Seq => {
+                    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+                    return std::str::from_utf8(rest.get(..digits)?).ok()?.parse().ok();
+                }
+                Segment::TimeIso => rest.get(TIME_ISO_BYTES..)?,
+                Segment::TimeUnixUs => {
+                    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+                    rest.get(digits..)?
+                }
+                Segment::Rand(n) => rest.get(*n..)?,
+            };
+        }
+        None
+    }
+}
+
+/// xorshift64 for {rand:N}: fast, and plenty for padding.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed | 1)
+    }
+
+    /// Seeded from the clock.
+    pub fn from_time() -> Self {
+        Self::new(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since| since.as_nanos() as u64),
+        )
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn pick(&mut self, from: &[u8]) -> u8 {
+        let index = (self.next() % from.len().max(1) as u64) as usize;
+        from.get(index).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn expand(template: &str, seq: u64) -> String {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_micros(1_792_211_205_682_873);
+        let mut out = Vec::new();
+        parse_template(template)
+            .expect("parse")
+            .expand(seq, now, &mut Rng::new(7), &mut out);
+        String::from_utf8(out).expect("utf8")
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(expand("load {seq}", 42), "load 42");
+        assert_eq!(
+            expand("{time_iso} {time_unix_us}", 0),
+            "2026-10-17T04:26:45.682873Z 1792211205682873"
+        );
+        assert_eq!(expand("{{seq}} }}", 1), "{seq} }");
+
+        let padded = expand("{seq}:{rand:16}", 3);
+        let rand = padded.strip_prefix("3:").expect("prefix");
+        assert_eq!(rand.len(), 16);
+        assert!(rand.bytes().all(|b| RAND_CHARS.contains(&b)), "{padded}");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for bad in [
+            "{seq",
+            "seq}",
+            "{sequence}",
+            "{rand}",
+            "{rand:0}",
+            "{rand:x}",
+            "{rand:100000}",
+        ] {
+            assert!(parse_template(bad).is_err(), "{bad}");
+        }
+        assert!(parse_template("").is_ok());
+    }
+
+    #[test]
+    fn test_seq_read_back() {
+        let now = SystemTime::now();
+        let mut rng = Rng::from_time();
+        let mut out = Vec::new();
+        for template in [
+            "{seq}",
+            "load {time_iso} {rand:8} #{seq} end",
+            "{time_unix_us}-{seq}",
+        ] {
+            let template = parse_template(template).expect("parse");
+            assert!(template.has_seq());
+            template.expand(1234, now, &mut rng, &mut out);
+            assert_eq!(template.seq_of(&out), Some(1234));
+        }
+
+        let template = parse_template("load #{seq}").expect("parse");
+        assert_eq!(template.seq_of(b"other #5"), None);
+        assert_eq!(template.seq_of(b"load #"), None);
+        assert!(!parse_template("{time_iso}").expect("parse").has_seq());
+    }
+}
@@ -0,0 +1,228 @@
+/// Data channel between the pipeline threads.
+///
+/// Every data hop is strictly single-producer single-consumer, so besides the
+/// crossbeam channel we can use a lock-free SPSC ring of batch slots, which
+/// avoids the MPMC bookkeeping that shows up in profiles at very high rates.
+/// Both transports have the same semantics: try_push never blocks and hands the
+/// batch back when full, and the receiver reports Disconnected only after the
+/// sender is gone and everything queued has been popped.
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+
+use crate::packet::Packets;
+
+// Waiting on an empty ring: spin, then yield, and only sleep once the ring has
+// been idle for a while so a quiet stream doesn't burn a core.
+const SPIN_LIMIT: u32 = 64;
+const YIELD_LIMIT: Duration = Duration::from_millis(1);
+const IDLE_SLEEP: Duration = Duration::from_micros(50);
+
+pub trait BatchSender: Send {
+    fn try_push(&mut self, packets: Packets) -> Result<(), TrySendError<Packets>>;
+}
+
+pub trait BatchReceiver: Send {
+    fn pop_timeout(&mut self, timeout: Duration) -> Result<Packets, RecvTimeoutError>;
+
+    /// Batches queued right now and how many fit.
+    fn occupancy(&self) -> (usize, usize);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TransportKind {
+    /// crossbeam bounded channel
+    Channel,
+    /// lock-free SPSC ring
+    Ring,
+}
+
+/// Create a bounded data channel of the requested kind.
+pub fn bounded(
+    kind: TransportKind,
+    capacity: usize,
+) -> (Box<dyn BatchSender>, Box<dyn BatchReceiver>) {
+    match kind {
+        TransportKind::Channel => {
+            let (tx, rx) = crossbeam_channel::bounded(capacity);
+            (Box::new(tx), Box::new(rx))
+        }
+        TransportKind::Ring => {
+            let (tx, rx) = rtrb::RingBuffer::new(capacity);
+            (Box::new(tx), Box::new(rx))
+        }
+    }
+}
+
+impl<T: BatchSender + ?Sized> BatchSender for Box<T> {
+    fn try_push(&mut self, packets: Packets) -> Result<(), TrySendError<Packets>> {
+        (**self).try_push(packets)
+    }
+}
+
+impl<T: BatchReceiver + ?Sized> BatchReceiver for Box<T> {
+    fn pop_timeout(&mut self, timeout: Duration) -> Result<Packets, RecvTimeoutError> {
+        (**self).pop_timeout(timeout)
+    }
+
+    fn occupancy(&self) -> (usize, usize) {
+        (**self).occupancy()
+    }
+}
+
+impl BatchSender for Sender<Packets> {
+    fn try_push(&mut self, packets: Packets) -> Result<(), TrySendError<Packets>> {
+        self.try_send(packets)
+    }
+}
+
+impl BatchReceiver for Receiver<Packets> {
+    fn pop_timeout(&mut self, timeout: Duration) -> Result<Packets, RecvTimeoutError> {
+        self.recv_timeout(timeout)
+    }
+
+    // The data channels are all bounded
+    fn occupancy(&self) -> (usize, usize) {
+        (self.len(), self.capacity().unwrap_or_default())
+    }
+}
+
+impl BatchSender for rtrb::Producer<Packets> {
+    fn try_push(&mut self, packets: Packets) -> Result<(), TrySendError<Packets>> {
+        if self.is_abandoned() {
+            return Err(TrySendError::Disconnected(packets));
+        }
+        self.push(packets)
+            .map_err(|rtrb::PushError::Full(packets)| TrySendError::Full(packets))
+    }
+}
+
+impl BatchReceiver for rtrb::Consumer<Packets> {
+    fn pop_timeout(&mut self, timeout: Duration) -> Result<Packets, RecvTimeoutError> {
+        let start = Instant::now();
+        let deadline = start + timeout;
+        let mut spins = 0u32;
+
+        loop {
+            // Check abandonment before popping so nothing pushed right before
+            // the producer went away is lost.
+            let abandoned = self.is_abandoned();
+            if let Ok(packets) = self.pop() {
+                return Ok(packets);
+            }
+            if abandoned {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            if spins < SPIN_LIMIT {
+                spins += 1;
+                std::hint::spin_loop();
+            } else if now - start < YIELD_LIMIT {
+                std::thread::yield_now();
+            } else {
+                std::thread::sleep(IDLE_SLEEP);
+            }
+        }
+    }
+
+    fn occupancy(&self) -> (usize, usize) {
+        (self.slots(), self.buffer().capacity())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(length: usize) -> Packets {
+        Packets::new(length, 16)
+    }
+
+    fn check_transport(kind: TransportKind) {
+        let (mut tx, mut rx) = bounded(kind, 2);
+
+        assert!(tx.try_push(batch(1)).is_ok());
+        assert!(tx.try_push(batch(2)).is_ok());
+        assert_eq!(rx.occupancy(), (2, 2));
+        assert!(matches!(
+            tx.try_push(batch(3)),
+            Err(TrySendError::Full(p)) if p.len() == 3
+        ));
+
+        assert_eq!(
+            rx.pop_timeout(Duration::from_millis(10)).map(|p| p.len()),
+            Ok(1)
+        );
+        assert_eq!(
+            rx.pop_timeout(Duration::from_millis(10)).map(|p| p.len()),
+            Ok(2)
+        );
+        assert_eq!(
+            rx.pop_timeout(Duration::from_millis(10)).map(|p| p.len()),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        // Queued batches survive the sender going away
+        assert!(tx.try_push(batch(4)).is_ok());
+        drop(tx);
+        assert_eq!(
+            rx.pop_timeout(Duration::from_millis(10)).map(|p| p.len()),
+            Ok(4)
+        );
+        assert_eq!(
+            rx.pop_timeout(Duration::from_millis(10)).map(|p| p.len()),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_channel_transport() {
+        check_transport(TransportKind::Channel);
+    }
+
+    #[test]
+    fn test_ring_transport() {
+        check_transport(TransportKind::Ring);
+    }
+
+    #[test]
+    fn test_push_after_receiver_dropped() {
+        for kind in [TransportKind::Channel, TransportKind::Ring] {
+            let (mut tx, rx) = bounded(kind, 2);
+            drop(rx);
+            assert!(matches!(
+                tx.try_push(batch(1)),
+                Err(TrySendError::Disconnected(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_ring_across_threads() {
+        let (mut tx, mut rx) = bounded(TransportKind::Ring, 4);
+
+        let producer = std::thread::spawn(move || {
+            let mut length = 1;
+            while length <= 1000 {
+                match tx.try_push(batch(length % 8)) {
+                    Ok(()) => length += 1,
+                    Err(TrySendError::Full(_)) => std::thread::yield_now(),
+                    Err(TrySendError::Disconnected(_)) => return,
+                }
+            }
+        });
+
+        let mut received = 0;
+        while let Ok(packets) = rx.pop_timeout(Duration::from_secs(1)) {
+            received += 1;
+            assert_eq!(packets.len(), received % 8);
+        }
+
+        assert!(producer.join().is_ok());
+        assert_eq!(received, 1000);
+    }
+}
@@ -0,0 +1,380 @@
+/// Scheduled transmission with SO_TXTIME.
+/// Rather than sleeping between sends, each packet carries the time it should
+/// leave the NIC and the qdisc (etf or fq) launches it then. Launch times are
+/// on the qdisc's clock: etf runs on TAI, fq on the monotonic clock.
+#[cfg(target_os = "linux")]
+use std::io::IoSlice;
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::os::fd::RawFd;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use nix::errno::Errno;
+#[cfg(target_os = "linux")]
+use nix::libc;
+#[cfg(target_os = "linux")]
+use nix::net::if_::if_nametoindex;
+use nix::sys::socket::SockaddrStorage;
+#[cfg(target_os = "linux")]
+use nix::sys::socket::{
+    AddressFamily, ControlMessage, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType, recv,
+    sendmsg, sendto, setsockopt, socket, sockopt,
+};
+#[cfg(target_os = "linux")]
+use nix::sys::time::TimeVal;
+use nix::time::{ClockId, clock_gettime};
+use socket2::Socket;
+
+use crate::error::{LibError, Result};
+
+// The first packet is scheduled this far out so it reaches the qdisc in time.
+const LEAD_NS: u64 = 500_000;
+
+// Don't queue further ahead than this; the qdisc drops past its horizon and
+// the sleep keeps the writer from racing through a whole capture.
+const AHEAD_NS: u64 = 50_000_000;
+
+// Lengths of struct nlmsghdr and struct tcmsg, which rtnetlink messages
+// about qdiscs start with
+#[cfg(target_os = "linux")]
+const NLMSG_HEADER: usize = 16;
+#[cfg(target_os = "linux")]
+const TCMSG: usize = 20;
+
+/// The clock SO_TXTIME must use for the qdisc on iface, from the qdiscs the
+/// kernel lists for it, as `tc qdisc show` would.
+#[cfg(target_os = "linux")]
+pub fn qdisc_clock(iface: &str) -> Result<ClockId> {
+    let kinds = qdisc_kinds(iface).map_err(|e| {
+        LibError::Critical(format!(
+            "--txtime: unable to check the qdisc on {iface}: {e}"
+        ))
+    })?;
+
+    clock_for_qdiscs(&kinds).ok_or_else(|| {
+        LibError::Critical(format!(
+            "--txtime needs an etf or fq qdisc on {iface}, e.g. `tc qdisc replace dev {iface} root fq`"
+        ))
+    })
+}
+
+/// The kinds of the qdiscs on iface ("fq", "etf", "mq"...), from an
+/// RTM_GETQDISC dump over rtnetlink.
+#[cfg(target_os = "linux")]
+fn qdisc_kinds(iface: &str) -> nix::Result<Vec<String>> {
+    let ifindex = if_nametoindex(iface)? as i32;
+    let fd = socket(
+        AddressFamily::Netlink,
+        SockType::Raw,
+        SockFlag::SOCK_CLOEXEC,
+        SockProtocol::NetlinkRoute,
+    )?;
+    setsockopt(&fd, sockopt::ReceiveTimeout, &TimeVal::new(1, 0))?;
+    sendto(
+        fd.as_raw_fd(),
+        &dump_request(ifindex),
+        &NetlinkAddr::new(0, 0),
+        MsgFlags::empty(),
+    )?;
+
+    let mut kinds = Vec::new();
+    let mut buffer = vec![0u8; 32 * 1024];
+    loop {
+        match recv(fd.as_raw_fd(), &mut buffer, MsgFlags::empty()) {
+            Ok(bytes) => {
+                let replies = buffer.get(..bytes).unwrap_or_default();
+                if read_qdiscs(replies, ifindex, &mut kinds)? {
+                    return Ok(kinds);
+                }
+            }
+            Err(Errno::EINTR) => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// An RTM_GETQDISC request for every qdisc. The kernel dumps them all, each
+/// reply says which interface it belongs to.
+#[cfg(target_os = "linux")]
+fn dump_request(ifindex: i32) -> Vec<u8> {
+    let mut request = Vec::with_capacity(NLMSG_HEADER + TCMSG);
+    request.extend_from_slice(&((NLMSG_HEADER + TCMSG) as u32).to_ne_bytes());
+    request.extend_from_slice(&libc::RTM_GETQDISC.to_ne_bytes());
+    request.extend_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+    // Sequence number, and the port id the kernel fills in
+    request.extend_from_slice(&1u32.to_ne_bytes());
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    // tcmsg: family and padding, ifindex, then handle, parent and info left 0
+    request.extend_from_slice(&[libc::AF_UNSPEC as u8, 0, 0, 0]);
+    request.extend_from_slice(&ifindex.to_ne_bytes());
+    request.extend_from_slice(&[0; 12]);
+    request
+}
+
+/// Adds the kind of each of ifindex's qdiscs in a batch of replies to kinds.
+/// True once the batch ends the dump.
+#[cfg(target_os = "linux")]
+fn read_qdiscs(mut replies: &[u8], ifindex: i32, kinds: &mut Vec<String>) -> nix::Result<bool> {
+    while let (Some(len), Some(kind)) = (u32_at(replies, 0), u16_at(replies, 4)) {
+        let len = len as usize;
+        let Some(message) = replies.get(..len).filter(|_| len >= NLMSG_HEADER) else {
+            return Err(Errno::EBADMSG);
+        };
+
+        if kind == libc::NLMSG_DONE as u16 {
+            return Ok(true);
+        } else if kind == libc::NLMSG_ERROR as u16 {
+            // A negative errno, or 0 for an acknowledgement
+            let error = i32_at(message, NLMSG_HEADER).ok_or(Errno::EBADMSG)?;
+            return if error == 0 {
+                Ok(true)
+            } else {
+                Err(Errno::from_raw(-error))
+            };
+        } else if kind == libc::RTM_NEWQDISC && i32_at(message, NLMSG_HEADER + 4) == Some(ifindex) {
+            let attributes = message.get(NLMSG_HEADER + TCMSG..).unwrap_or_default();
+            kinds.extend(qdisc_kind(attributes));
+        }
+        replies = replies.get(aligned(len)..).unwrap_or_default();
+    }
+    Ok(false)
+}
+
+/// The TCA_KIND attribute among a qdisc's attributes.
+#[cfg(target_os = "linux")]
+fn qdisc_kind(mut attributes: &[u8]) -> Option<String> {
+    while let (Some(len), Some(kind)) = (u16_at(attributes, 0), u16_at(attributes, 2)) {
+        let len = usize::from(len);
+        if len < 4 {
+            return None;
+        }
+        if kind == libc::TCA_KIND {
+            let name = attributes.get(4..len)?;
+            return Some(
+                String::from_utf8_lossy(name)
+                    .trim_end_matches('\0')
+                    .to_string(),
+            );
+        }
+        attributes = attributes.get(aligned(len)..).unwrap_or_default();
+    }
+    None
+}
+
+// Netlink messages and their attributes are padded to 4 bytes
+#[cfg(target_os = "linux")]
+fn aligned(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+#[cfg(target_os = "linux")]
+fn u16_at(bytes: &[u8], at: usize) -> Option<u16> {
+    let field = bytes.get(at..at + 2)?;
+    field.try_into().ok().map(u16::from_ne_bytes)
+}
+
+#[cfg(target_os = "linux")]
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    let field = bytes.get(at..at + 4)?;
+    field.try_into().ok().map(u32::from_ne_bytes)
+}
+
+#[cfg(target_os = "linux")]
+fn i32_at(bytes: &[u8], at: usize) -> Option<i32> {
+    u32_at(bytes, at).map(|field| field as i32)
+}
+
+/// SO_TXTIME and the qdiscs that honour it are Linux only.
+#[cfg(not(target_os = "linux"))]
+pub fn qdisc_clock(_iface: &str) -> Result<ClockId> {
+    Err(LibError::Critical(
+        "--txtime needs Linux for SO_TXTIME".to_string(),
+    ))
+}
+
+/// etf wins over fq when both are configured, since it is the precise one.
+#[cfg(target_os = "linux")]
+fn clock_for_qdiscs(kinds: &[String]) -> Option<ClockId> {
+    if kinds.iter().any(|kind| kind == "etf") {
+        Some(ClockId::CLOCK_TAI)
+    } else if kinds.iter().any(|kind| kind == "fq") {
+        Some(ClockId::CLOCK_MONOTONIC)
+    } else {
+        None
+    }
+}
+
+/// Turn on SO_TXTIME for the socket with the qdisc's clock.
+#[cfg(target_os = "linux")]
+pub fn enable(socket: &Socket, clock: ClockId) -> Result<()> {
+    let config = libc::sock_txtime {
+        clockid: clock.as_raw(),
+        flags: 0,
+    };
+    setsockopt(socket, sockopt::TxTime, &config)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable(_socket: &Socket, _clock: ClockId) -> Result<()> {
+    qdisc_clock("").map(|_| ())
+}
+
+/// Send one datagram for the qdisc to launch at launch, see [`Schedule`].
+#[cfg(target_os = "linux")]
+pub fn send_at(
+    fd: RawFd,
+    data: &[u8],
+    launch: u64,
+    address: Option<&SockaddrStorage>,
+) -> nix::Result<()> {
+    let iov = [IoSlice::new(data)];
+    sendmsg(
+        fd,
+        &iov,
+        &[ControlMessage::TxTime(&launch)],
+        MsgFlags::empty(),
+        address,
+    )
+    .map(|_| ())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn send_at(
+    _fd: RawFd,
+    _data: &[u8],
+    _launch: u64,
+    _address: Option<&SockaddrStorage>,
+) -> nix::Result<()> {
+    Err(nix::errno::Errno::ENOTSUP)
+}
+
+/// Launch times at a fixed packet rate.
+#[derive(Debug)]
+pub struct Schedule {
+    clock: ClockId,
+    interval: u64,
+    next: u64,
+}
+
+impl Schedule {
+    pub fn new(clock: ClockId, pps: u64) -> Self {
+        Self {
+            clock,
+            interval: 1_000_000_000 / pps.max(1),
+            next: 0,
+        }
+    }
+
+    /// Launch time for the next packet, in nanoseconds on the schedule's clock.
+    /// Sleeps when the schedule has got too far ahead of now.
+    pub fn next_launch(&mut self) -> nix::Result<u64> {
+        let now = clock_gettime(self.clock)?;
+        let now = now.tv_sec() as u64 * 1_000_000_000 + now.tv_nsec() as u64;
+
+        let launch = self.launch_after(now);
+        let ahead = launch.saturating_sub(now);
+        if ahead > AHEAD_NS {
+            thread::sleep(Duration::from_nanos(ahead - AHEAD_NS));
+        }
+        Ok(launch)
+    }
+
+    /// A writer that fell behind restarts the schedule rather than handing
+    /// the qdisc launch times already in the past, which it would drop.
+    fn launch_after(&mut self, now: u64) -> u64 {
+        let earliest = now + LEAD_NS;
+        if self.next < earliest {
+            self.next = earliest;
+        }
+        let launch = self.next;
+        self.next += self.interval;
+        launch
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    /// An RTM_NEWQDISC reply for a qdisc of kind on ifindex.
+    fn qdisc_reply(ifindex: i32, kind: &str) -> Vec<u8> {
+        let attribute = 4 + kind.len() + 1;
+        let len = NLMSG_HEADER + TCMSG + aligned(attribute);
+        let mut reply = Vec::new();
+        reply.extend_from_slice(&(len as u32).to_ne_bytes());
+        reply.extend_from_slice(&libc::RTM_NEWQDISC.to_ne_bytes());
+        reply.extend_from_slice(&[0; 10]);
+        reply.extend_from_slice(&[0; 4]);
+        reply.extend_from_slice(&ifindex.to_ne_bytes());
+        reply.extend_from_slice(&[0; 12]);
+        reply.extend_from_slice(&(attribute as u16).to_ne_bytes());
+        reply.extend_from_slice(&libc::TCA_KIND.to_ne_bytes());
+        reply.extend_from_slice(kind.as_bytes());
+        reply.resize(len, 0);
+        reply
+    }
+
+    fn done() -> Vec<u8> {
+        let mut done = (NLMSG_HEADER as u32).to_ne_bytes().to_vec();
+        done.extend_from_slice(&(libc::NLMSG_DONE as u16).to_ne_bytes());
+        done.resize(NLMSG_HEADER, 0);
+        done
+    }
+
+    #[test]
+    fn test_read_qdiscs() {
+        let mut kinds = Vec::new();
+        let mut replies = qdisc_reply(2, "mqprio");
+        replies.extend(qdisc_reply(3, "etf"));
+        replies.extend(qdisc_reply(2, "fq"));
+        assert_eq!(read_qdiscs(&replies, 2, &mut kinds), Ok(false));
+        assert_eq!(read_qdiscs(&done(), 2, &mut kinds), Ok(true));
+        assert_eq!(kinds, ["mqprio", "fq"]);
+
+        let mut error = (NLMSG_HEADER as u32 + 4).to_ne_bytes().to_vec();
+        error.extend_from_slice(&(libc::NLMSG_ERROR as u16).to_ne_bytes());
+        error.resize(NLMSG_HEADER, 0);
+        error.extend_from_slice(&(-libc::EPERM).to_ne_bytes());
+        assert_eq!(read_qdiscs(&error, 2, &mut kinds), Err(Errno::EPERM));
+    }
+
+    #[test]
+    fn test_qdisc_kinds_of_loopback() {
+        assert!(qdisc_kinds("lo").is_ok());
+    }
+
+    #[test]
+    fn test_clock_for_qdiscs() {
+        let kinds = |kinds: &[&str]| {
+            kinds
+                .iter()
+                .map(|kind| kind.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(clock_for_qdiscs(&kinds(&["pfifo_fast"])), None);
+        assert_eq!(
+            clock_for_qdiscs(&kinds(&["fq"])),
+            Some(ClockId::CLOCK_MONOTONIC)
+        );
+        assert_eq!(
+            clock_for_qdiscs(&kinds(&["mqprio", "etf", "fq"])),
+            Some(ClockId::CLOCK_TAI)
+        );
+    }
+
+    #[test]
+    fn test_launch_times() {
+        let mut schedule = Schedule::new(ClockId::CLOCK_MONOTONIC, 1000);
+        assert_eq!(schedule.launch_after(0), LEAD_NS);
+        assert_eq!(schedule.launch_after(10), LEAD_NS + 1_000_000);
+        assert_eq!(schedule.launch_after(20), LEAD_NS + 2_000_000);
+
+        // Fell 10ms behind: start again from now rather than in the past
+        let now = 10_000_000 + LEAD_NS;
+        assert_eq!(schedule.launch_after(now), now + LEAD_NS);
+        assert_eq!(schedule.launch_after(now), now + LEAD_NS + 1_000_000);
+    }
+}
@@ -0,0 +1,280 @@
+/// How many different payloads went by, for protocols that send the same
+/// state packet over and over until the state changes. Each payload is
+/// hashed with XXH64 and the hashes are counted exactly up to a limit; past
+/// it they go into a HyperLogLog sketch instead, which takes 16 KiB however
+/// long the run and is off by about 1%.
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Distinct payloads counted exactly before the count is estimated.
+pub const DEFAULT_EXACT_LIMIT: u64 = 100_000;
+
+const PRIME64_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME64_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME64_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME64_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME64_5: u64 = 0x27D4_EB2F_1656_67C5;
+
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1)
+}
+
+fn merge(acc: u64, value: u64) -> u64 {
+    (acc ^ round(0, value))
+        .wrapping_mul(PRIME64_1)
+        .wrapping_add(PRIME64_4)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap_or_default())
+}
+
+fn read_u32(bytes: &[u8]) -> u64 {
+    u32::from_le_bytes(bytes.try_into().unwrap_or_default()) as u64
+}
+
+/// XXH64 of data with a seed of 0.
+pub fn xxh64(data: &[u8]) -> u64 {
+    let stripes = data.chunks_exact(32);
+    let tail = stripes.remainder();
+    let mut hash = if data.len() >= 32 {
+        let mut lanes = [
+            PRIME64_1.wrapping_add(PRIME64_2),
+            PRIME64_2,
+            0,
+            0u64.wrapping_sub(PRIME64_1),
+        ];
+        for stripe in stripes {
+            for (lane, word) in lanes.iter_mut().zip(stripe.chunks_exact(8)) {
+                *lane = round(*lane, read_u64(word));
+            }
+        }
+        let [v1, v2, v3, v4] = lanes;
+        let hash = v1
+            .rotate_left(1)
+            .wrapping_add(v2.rotate_left(7))
+            .wrapping_add(v3.rotate_left(12))
+            .wrapping_add(v4.rotate_left(18));
+        lanes.iter().fold(hash, |hash, lane| merge(hash, *lane))
+    } else {
+        PRIME64_5
+    };
+    hash = hash.wrapping_add(data.len() as u64);
+
+    let words = tail.chunks_exact(8);
+    let rest = words.remainder();
+    for word in words {
+        hash = (hash ^ round(0, read_u64(word)))
+            .rotate_left(27)
+            .wrapping_mul(PRIME64_1)
+            .wrapping_add(PRIME64_4);
+    }
+    let bytes = if rest.len() >= 4 {
+        let (word, bytes) = rest.split_at(4);
+        hash = (hash ^ read_u32(word).wrapping_mul(PRIME64_1))
+            .rotate_left(23)
+            .wrapping_mul(PRIME64_2)
+            .wrapping_add(PRIME64_3);
+        bytes
+    } else {
+        rest
+    };
+    for byte in bytes {
+        hash = (hash ^ (*byte as u64).wrapping_mul(PRIME64_5))
+            .rotate_left(11)
+            .wrapping_mul(PRIME64_1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME64_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME64_3);
+    hash ^ (hash >> 32)
+}
+
+// 2^14 registers, for a standard error of 1.04 / sqrt(2^14), about 0.8%
+const SKETCH_BITS: u32 = 14;
+const REGISTERS: usize = 1 << SKETCH_BITS;
+
+/// A HyperLogLog sketch of 64 bit hashes.
+#[derive(Debug, Clone)]
+struct Sketch {
+    registers: Vec<u8>,
+}
+
+impl Sketch {
+    fn new() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    fn insert(&mut self, hash: u64) {
+        let index = (hash >> (64 - SKETCH_BITS)) as usize;
+        // The rest of the hash, with a bit set to stop the count at the end
+        let rank = ((hash << SKETCH_BITS) | (1 << (SKETCH_BITS - 1))).leading_zeros() as u8 + 1;
+        if let Some(register) = self.registers.get_mut(index) {
+            *register = (*register).max(rank);
+        }
+    }
+
+    fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-(*register as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        // Small counts are better told by the registers still empty
+        if estimate <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            estimate.round() as u64
+        }
+    }
+}
+
+/// The distinct payload hashes seen, exactly up to a limit and estimated
+/// past it.
+#[derive(Debug, Clone)]
+pub struct UniqueCount {
+    limit: u64,
+    exact: HashSet<u64>,
+    sketch: Option<Sketch>,
+}
+
+impl UniqueCount {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            exact: HashSet::new(),
+            sketch: None,
+        }
+    }
+
+    pub fn insert(&mut self, payload: &[u8]) {
+        let hash = xxh64(payload);
+        if let Some(sketch) = self.sketch.as_mut() {
+            sketch.insert(hash);
+            return;
+        }
+        self.exact.insert(hash);
+        if self.exact.len() as u64 > self.limit {
+            let mut sketch = Sketch::new();
+            for hash in std::mem::take(&mut self.exact) {
+                sketch.insert(hash);
+            }
+            self.sketch = Some(sketch);
+        }
+    }
+
+    /// Start counting afresh, keeping the memory for it.
+    pub fn clear(&mut self) {
+        self.exact.clear();
+        self.sketch = None;
+    }
+
+    /// How many distinct payloads, and whether that's an estimate.
+    pub fn count(&self) -> (u64, bool) {
+        match &self.sketch {
+            Some(sketch) => (sketch.estimate(), true),
+            None => (self.exact.len() as u64, false),
+        }
+    }
+
+    /// "  unique: 12 / total: 3400" for the stats line, out of packets.
+    pub fn format(&self, packets: u64) -> String {
+        format!(
+            "  unique: {} / total: {packets}",
+            format_count(self.count())
+        )
+    }
+}
+
+/// A count, with ~ in front of an estimate.
+pub fn format_count((count, estimated): (u64, bool)) -> String {
+    if estimated {
+        format!("~{count}")
+    } else {
+        count.to_string()
+    }
+}
+
+/// The cumulative count, published by the statistics thread for the summary.
+#[derive(Debug, Default)]
+pub struct UniqueTotals {
+    distinct: AtomicU64,
+    estimated: AtomicBool,
+}
+
+impl UniqueTotals {
+    pub fn publish(&self, unique: &UniqueCount) {
+        let (distinct, estimated) = unique.count();
+        self.distinct.store(distinct, Ordering::Relaxed);
+        self.estimated.store(estimated, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> (u64, bool) {
+        (
+            self.distinct.load(Ordering::Relaxed),
+            self.estimated.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xxh64() {
+        assert_eq!(xxh64(b""), 0xEF46_DB37_51D8_E999);
+        assert_eq!(xxh64(b"a"), 0xD24E_C4F1_A98C_6E5B);
+        assert_eq!(xxh64(b"abc"), 0x44BC_2CF5_AD77_0999);
+        assert_eq!(
+            xxh64(b"Nobody inspects the spammish repetition"),
+            0xFBCE_A83C_8A37_8BF1
+        );
+    }
+
+    #[test]
+    fn test_exact_count() {
+        let mut unique = UniqueCount::new(DEFAULT_EXACT_LIMIT);
+        for i in 0..1000u32 {
+            unique.insert(&(i % 10).to_le_bytes());
+        }
+        assert_eq!(unique.count(), (10, false));
+        assert_eq!(unique.format(1000), "  unique: 10 / total: 1000");
+    }
+
+    #[test]
+    fn test_estimate_past_the_limit() {
+        let mut unique = UniqueCount::new(1000);
+        for i in 0..200_000u32 {
+            unique.insert(&i.to_le_bytes());
+            // Repeats don't count again
+            unique.insert(&i.to_le_bytes());
+        }
+        let (count, estimated) = unique.count();
+        assert!(estimated);
+        assert!(unique.exact.is_empty());
+        assert!((196_000..=204_000).contains(&count), "{count}");
+
+        // Just past the limit the empty registers count
+        let mut unique = UniqueCount::new(1000);
+        for i in 0..1500u32 {
+            unique.insert(&i.to_le_bytes());
+        }
+        let (count, _) = unique.count();
+        assert!((1470..=1530).contains(&count), "{count}");
+    }
+}
@@ -1,8 +1,39 @@
+use crate::{MAX_PACKET_BYTES, resync::FrameSync};
+
 pub const HEADER_SIZE: usize = 8;
 
+/// What the VRT packet inside the frame carries, from its packet type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VrtKind {
+    /// Signal or extension data, types 0 to 3
+    Data,
+    /// Signal or extension context, types 4 and 5
+    Context,
+    /// Commands, reserved types, or no VRT header at all
+    Other,
+}
+
 pub struct Vita49Header {
     pub frame_sequence_number: u16,
     pub frame_size: u32,
+    /// Packet type of the first VRT packet, the top 4 bits after the frame header
+    pub packet_type: Option<u8>,
+}
+
+impl VrtKind {
+    fn of(packet_type: Option<u8>) -> Self {
+        match packet_type {
+            Some(0..=3) => VrtKind::Data,
+            Some(4 | 5) => VrtKind::Context,
+            _ => VrtKind::Other,
+        }
+    }
+}
+
+impl Vita49Header {
+    pub fn kind(&self) -> VrtKind {
+        VrtKind::of(self.packet_type)
+    }
 }
 
 impl std::fmt::Display for Vita49Header {
@@ -18,7 +49,17 @@ impl std::fmt::Display for Vita49Header {
             f,
             "  {:<24}: {:<25} {:020b}",
             "Frame Size (20)", self.frame_size, self.frame_size
-        )
+        )?;
+        if let Some(packet_type) = self.packet_type {
+            writeln!(
+                f,
+                "  {:<24}: {:<25} {:04b}",
+                "Packet Type (4)",
+                format!("{packet_type} ({:?})", self.kind()),
+                packet_type
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -27,6 +68,7 @@ pub fn parse_header(packet: &[u8]) -> Vita49Header {
         return Vita49Header {
             frame_sequence_number: 0,
             frame_size: 0,
+            packet_type: None,
         };
     }
 
@@ -34,6 +76,7 @@ pub fn parse_header(packet: &[u8]) -> Vita49Header {
         return Vita49Header {
             frame_sequence_number: 0,
             frame_size: 0,
+            packet_type: None,
         };
     }
 
@@ -45,6 +88,7 @@ pub fn parse_header(packet: &[u8]) -> Vita49Header {
         return Vita49Header {
             frame_sequence_number: 0,
             frame_size: 0,
+            packet_type: None,
         };
     };
     let word = u32::from_be_bytes(chunk.try_into().unwrap_or([0u8; 4]));
@@ -52,9 +96,196 @@ pub fn parse_header(packet: &[u8]) -> Vita49Header {
     Vita49Header {
         frame_sequence_number: (word >> 20) as u16,
         frame_size: word & 0x000F_FFFF,
+        packet_type: packet.get(HEADER_SIZE).map(|b| b >> 4),
     }
 }
 
+/// VRLP frames in a stream with nothing between them, for
+/// --input-format vrlp: "VRLP", the frame size in 32-bit words counting the
+/// header and trailer, and "VEND" last.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VrlpSync;
+
+impl FrameSync for VrlpSync {
+    const PREFIX: usize = HEADER_SIZE;
+
+    fn frame_len(&self, prefix: &[u8]) -> Option<usize> {
+        if prefix.get(0..4) != Some(b"VRLP") {
+            return None;
+        }
+        let len = parse_header(prefix).frame_size as usize * 4;
+        (HEADER_SIZE + TRAILER.len()..=MAX_PACKET_BYTES)
+            .contains(&len)
+            .then_some(len)
+    }
+
+    fn is_valid(&self, frame: &[u8]) -> bool {
+        frame.ends_with(TRAILER)
+    }
+}
+
+const TRAILER: &[u8] = b"VEND";
+
+/// The first VRT packet in a frame, with its payload located.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VrtPacket<'a> {
+    pub packet_type: u8,
+    /// Absent from types 0 and 2, which have no stream id
+    pub stream_id: Option<u32>,
+    /// Integer seconds, when the timestamp is UTC (TSI 1)
+    pub utc_seconds: Option<u32>,
+    /// Fractional picoseconds, when the timestamp is real time (TSF 2)
+    pub picoseconds: Option<u64>,
+    pub payload: &'a [u8],
+}
+
+impl VrtPacket<'_> {
+    pub fn kind(&self) -> VrtKind {
+        VrtKind::of(Some(self.packet_type))
+    }
+}
+
+fn word(bytes: &[u8], index: usize) -> Option<u32> {
+    let bytes = bytes.get(index * 4..index * 4 + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn double_word(bytes: &[u8], index: usize) -> Option<u64> {
+    Some(((word(bytes, index)? as u64) << 32) | word(bytes, index + 1)? as u64)
+}
+
+/// Walk the VRT header after the frame header: stream and class ids,
+/// timestamps, and the trailer on data packets.
+pub fn parse_vrt(frame: &[u8]) -> Option<VrtPacket<'_>> {
+    if frame.get(0..4) != Some(b"VRLP") {
+        return None;
+    }
+    let vrt = frame.get(HEADER_SIZE..)?;
+    let header = word(vrt, 0)?;
+    let packet_type = (header >> 28) as u8;
+    let class_id = header & (1 << 27) != 0;
+    let trailer = packet_type <= 3 && header & (1 << 26) != 0;
+    let tsi = (header >> 22) & 0x3;
+    let tsf = (header >> 20) & 0x3;
+    let size = (header & 0xFFFF) as usize;
+
+    let mut index = 1;
+    let stream_id = if matches!(packet_type, 0 | 2) {
+        None
+    } else {
+        index += 1;
+        Some(word(vrt, 1)?)
+    };
+    if class_id {
+        index += 2;
+    }
+    let utc_seconds = match tsi {
+        0 => None,
+        1 => word(vrt, index),
+        _ => None,
+    };
+    if tsi != 0 {
+        index += 1;
+    }
+    let picoseconds = match tsf {
+        0 => None,
+        2 => double_word(vrt, index),
+        _ => None,
+    };
+    if tsf != 0 {
+        index += 2;
+    }
+
+    let end = size.checked_sub(trailer as usize)?;
+    Some(VrtPacket {
+        packet_type,
+        stream_id,
+        utc_seconds,
+        picoseconds,
+        payload: vrt.get(index * 4..end * 4)?,
+    })
+}
+
+/// What a context packet says about the data, as far as recording it goes.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Context {
+    pub sample_rate: Option<f64>,
+    pub rf_frequency: Option<f64>,
+    pub payload_format: Option<PayloadFormat>,
+}
+
+/// The data packet payload format field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadFormat {
+    pub complex: bool,
+    /// 0 signed fixed point, 0x10 unsigned fixed point, 0x0E IEEE float
+    pub item_format: u8,
+    pub item_bits: u8,
+    pub packing_bits: u8,
+}
+
+// Words taken by CIF0 fields 30 through 16, the ones before the payload format.
+const CIF0_FIELD_WORDS: [(u32, usize); 15] = [
+    (30, 1),
+    (29, 2),
+    (28, 2),
+    (27, 2),
+    (26, 2),
+    (25, 2),
+    (24, 1),
+    (23, 1),
+    (22, 1),
+    (21, 2),
+    (20, 2),
+    (19, 1),
+    (18, 1),
+    (17, 2),
+    (16, 1),
+];
+
+const RF_FREQUENCY_BIT: u32 = 27;
+const SAMPLE_RATE_BIT: u32 = 21;
+const PAYLOAD_FORMAT_BIT: u32 = 15;
+
+// Frequencies are 64 bit fixed point with 20 fraction bits.
+fn hertz(value: u64) -> f64 {
+    value as i64 as f64 / (1u64 << 20) as f64
+}
+
+/// Sample rate, RF reference frequency and payload format from a context
+/// packet's payload, the fields that come later are not needed.
+pub fn parse_context(payload: &[u8]) -> Context {
+    let mut context = Context::default();
+    let Some(cif0) = word(payload, 0) else {
+        return context;
+    };
+
+    let mut index = 1;
+    for (bit, words) in CIF0_FIELD_WORDS {
+        if cif0 & (1 << bit) == 0 {
+            continue;
+        }
+        match bit {
+            RF_FREQUENCY_BIT => context.rf_frequency = double_word(payload, index).map(hertz),
+            SAMPLE_RATE_BIT => context.sample_rate = double_word(payload, index).map(hertz),
+            _ => {}
+        }
+        index += words;
+    }
+
+    if cif0 & (1 << PAYLOAD_FORMAT_BIT) != 0
+        && let Some(format) = word(payload, index)
+    {
+        context.payload_format = Some(PayloadFormat {
+            complex: (format >> 29) & 0x3 == 1,
+            item_format: ((format >> 24) & 0x1F) as u8,
+            item_bits: ((format & 0x3F) + 1) as u8,
+            packing_bits: (((format >> 6) & 0x3F) + 1) as u8,
+        });
+    }
+    context
+}
+
 #[cfg(test)]
 #[allow(clippy::assertions_on_constants)]
 mod tests {
@@ -67,5 +298,102 @@ mod tests {
         let header = parse_header(&packet);
         assert_eq!(header.frame_sequence_number, 0x123);
         assert_eq!(header.frame_size, 0x45678);
+        assert_eq!(header.packet_type, None);
+        assert_eq!(header.kind(), VrtKind::Other);
+    }
+
+    fn frame(vrt: &[u32]) -> Vec<u8> {
+        let mut packet = b"VRLP\x00\x10\x00\x00".to_vec();
+        packet.extend(vrt.iter().flat_map(|word| word.to_be_bytes()));
+        packet.extend(b"VEND");
+        packet
+    }
+
+    #[test]
+    #[allow(clippy::expect_used)]
+    fn test_parse_vrt() {
+        // Data with stream id, UTC and real time stamps, and a trailer
+        let header = (1 << 28) | (1 << 26) | (1 << 22) | (2 << 20) | 7;
+        let packet = frame(&[header, 0x1234, 1_700_000_000, 0, 500, 0xAABBCCDD, 0xEE]);
+        let vrt = parse_vrt(&packet).expect("vrt");
+        assert_eq!(vrt.kind(), VrtKind::Data);
+        assert_eq!(vrt.stream_id, Some(0x1234));
+        assert_eq!(vrt.utc_seconds, Some(1_700_000_000));
+        assert_eq!(vrt.picoseconds, Some(500));
+        assert_eq!(vrt.payload, &[0xAA, 0xBB, 0xCC, 0xDD]);
+
+        // Without a stream id or timestamps
+        let packet = frame(&[2, 0x01020304]);
+        let vrt = parse_vrt(&packet).expect("vrt");
+        assert_eq!(vrt.stream_id, None);
+        assert_eq!(vrt.utc_seconds, None);
+        assert_eq!(vrt.payload, &[1, 2, 3, 4]);
+
+        assert_eq!(parse_vrt(b"VRLP\x00\x10\x00\x00"), None);
+    }
+
+    #[test]
+    fn test_parse_context() {
+        let fixed = |hz: u64| {
+            let value = hz << 20;
+            [(value >> 32) as u32, value as u32]
+        };
+        let [rf_hi, rf_lo] = fixed(2_400_000_000);
+        let [rate_hi, rate_lo] = fixed(1_000_000);
+        // Bandwidth, RF frequency, gain, sample rate and payload format
+        let cif0 = (1 << 29) | (1 << 27) | (1 << 23) | (1 << 21) | (1 << 15);
+        // Complex signed 16 bit items
+        let format = (1 << 29) | (15 << 6) | 15;
+        let payload: Vec<u8> = [cif0, 0, 0, rf_hi, rf_lo, 0, rate_hi, rate_lo, format, 0]
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+
+        assert_eq!(
+            parse_context(&payload),
+            Context {
+                sample_rate: Some(1e6),
+                rf_frequency: Some(2.4e9),
+                payload_format: Some(PayloadFormat {
+                    complex: true,
+                    item_format: 0,
+                    item_bits: 16,
+                    packing_bits: 16,
+                }),
+            }
+        );
+        assert_eq!(parse_context(&[]), Context::default());
+    }
+
+    #[test]
+    fn test_vrlp_sync() {
+        let mut packet = frame(&[2, 0x01020304]);
+        // Two header, two VRT and one trailer word
+        packet.splice(4..8, [0x00, 0x10, 0x00, 0x05]);
+        assert_eq!(VrlpSync.frame_len(&packet), Some(20));
+        assert!(VrlpSync.is_valid(&packet));
+
+        assert_eq!(VrlpSync.frame_len(b"VRLX\x00\x10\x00\x05"), None);
+        // Too short to hold the header and trailer, or longer than a datagram
+        assert_eq!(VrlpSync.frame_len(b"VRLP\x00\x10\x00\x02"), None);
+        assert_eq!(VrlpSync.frame_len(b"VRLP\x00\x0F\xFF\xFF"), None);
+        packet.truncate(16);
+        packet.extend(b"VENX");
+        assert!(!VrlpSync.is_valid(&packet));
+    }
+
+    #[test]
+    fn test_vrt_kind() {
+        let frame = |packet_type: u8| {
+            let mut packet = b"VRLP\x00\x10\x00\x04".to_vec();
+            packet.extend([packet_type << 4, 0, 0, 1]);
+            parse_header(&packet).kind()
+        };
+        assert_eq!(frame(1), VrtKind::Data);
+        assert_eq!(frame(3), VrtKind::Data);
+        assert_eq!(frame(4), VrtKind::Context);
+        assert_eq!(frame(5), VrtKind::Context);
+        assert_eq!(frame(6), VrtKind::Other);
+        assert_eq!(parse_header(b"not vita49 at all").kind(), VrtKind::Other);
     }
 }
@@ -1,36 +1,135 @@
 /// Important: Ensure we don't drop the Packets, it must recycle
 /// through the memory channel back to the reader thread.
-use std::fs::File;
-use std::io::{self, BufWriter, IoSlice, Write};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crossbeam_channel::{Receiver, Sender};
-use nix::sys::socket::{MsgFlags, MultiHeaders, SockaddrStorage, sendmmsg, sendmsg};
+use crossbeam_channel::{Sender, TrySendError};
 
 use crate::{
     SharedState,
-    error::Result,
-    multicast::{create_send_socket, socket_to_raw_fd},
-    packet::{PacketType, Packets},
+    convert::{Conversion, Transform},
+    dejitter::Dejitter,
+    drain::ExitDrain,
+    error::{LibError, Result},
+    expect::{ExpectConfig, ExpectSink},
+    fec::FecDecoder,
+    filter::{Filters, PacketFilter},
+    gaps::GapMarker,
+    heartbeat::{Heartbeat, HeartbeatConfig},
+    pace::TokenBucket,
+    packet::{Packet, Packets},
+    patch::{Patch, Patcher},
+    police::{PoliceRate, Policer},
+    reorder::{Reorder, SeqSource},
+    sandbox,
+    sched::{self, ThreadPlacement},
+    sequence::SeqField,
+    sigmf::{SigmfConfig, SigmfSink},
+    sink::{
+        DiscardSink, ExecPerPacketSink, Fanout, FileSink, Framing, Label, NetworkSink,
+        OutputFormat, Sink, SplitBy, SplitSink, StreamSink, TimestampFormat,
+    },
+    transport::BatchReceiver,
 };
 
+/// Writer thread configuration.
+/// Every output gets a copy of each packet, and so does iface:mgroup when
+/// to_network is set. With neither, packets are counted and discarded.
 pub struct WriterConfig {
-    pub output: Option<String>,
+    /// Each with its layout, output_format's when None
+    pub outputs: Vec<(String, Option<OutputFormat>)>,
     pub to_network: bool,
     pub iface: Option<String>,
     pub mgroup: String,
-    pub port: u16,
+    /// Every packet sent goes to each of them
+    pub ports: Vec<u16>,
     pub ttl: u8,
-    pub channels: (Receiver<Packets>, Sender<Packets>),
+    pub channels: (Box<dyn BatchReceiver>, Sender<Packets>),
     pub shared_state: SharedState,
     pub rate: Option<u64>,
+    /// Packets per second to launch with SO_TXTIME, instead of pacing by rate
+    pub txtime: Option<u64>,
+    /// Packets --pps or the --control rate may send at once, a millisecond's
+    /// worth when None
+    pub pace_burst: Option<u64>,
+    /// Longest a packet may sit in an output's buffer, for --max-latency
+    pub max_latency: Option<Duration>,
+    /// Byte offset to write the send time at, for --measure-latency on the receiver
+    pub stamp: Option<usize>,
+    /// Where to number each packet, for --check-seq on the receiver
+    pub stamp_seq: Option<SeqField>,
+    /// Send a parity packet after every this many, for --fec-decode on the receiver
+    pub fec: Option<u8>,
+    /// Bytes to overwrite in each packet sent
+    pub patches: Vec<Patch>,
+    /// Grow packets a patch runs past the end of, rather than skipping them
+    pub patch_extend: bool,
+    /// A header to put in front of each packet sent, empty for none
+    pub prepend: Vec<u8>,
+    /// A marker to put after each packet sent, for the reader to know them
+    /// by should they loop back, empty for none
+    pub loop_guard: Vec<u8>,
+    /// How packets are spread when mgroup is a list or range of groups
+    pub fanout: Fanout,
+    /// Send packets with a destination of their own there instead of mgroup
+    pub packet_destinations: bool,
+    /// Leave out the packets any of these don't keep
+    pub filters: Vec<PacketFilter>,
+    /// Drop the packets above this rate before they reach any output
+    pub police: Option<PoliceRate>,
+    /// Put packets back in sequence order, holding up to depth of them
+    pub reorder: Option<(SeqSource, usize)>,
+    /// Hold every packet this long after it arrived, to take out jitter
+    pub dejitter: Option<Duration>,
+    /// Take off the framing of a --fec sender and rebuild what it can
+    pub fec_decode: Option<u8>,
+    /// Turn packets into others before any output sees them
+    pub convert: Option<Conversion>,
     pub max_count: u64,
+    pub timestamps: Option<TimestampFormat>,
+    /// Add a newline to text output packets that don't end in one
+    pub append_newline: bool,
+    /// Start every line of text output with this
+    pub label: Option<Label>,
+    /// --exec and default output layout, the packet type's when None
+    pub output_format: Option<OutputFormat>,
+    /// How the outputs with the sigmf layout record their samples
+    pub sigmf: Option<SigmfConfig>,
+    /// Write each output file as one file per source instead
+    pub split_by: Option<SplitBy>,
+    /// Mark where packets are missing in binary and SigMF output
+    pub mark_gaps: bool,
+    /// Fail on a full disk rather than drop packets until there is room
+    pub strict_disk: bool,
+    /// Compare the packets with a golden file as well
+    pub expect: Option<ExpectConfig>,
+    /// Send a packet of our own every so often, between the reader's
+    pub heartbeat: Option<HeartbeatConfig>,
+    pub exec: Option<ExecCommand>,
+    /// How long to keep draining once exit is signaled, None for as long as it takes
+    pub drain_timeout: Option<Duration>,
+    pub placement: ThreadPlacement,
+    /// Lock the thread down once the outputs are open
+    pub sandbox: bool,
 }
 
+/// Shell command that receives packets on its stdin instead of output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecCommand {
+    pub command: String,
+    /// Run the command once per packet rather than streaming to one child
+    pub per_packet: bool,
+}
+
+/// Spawn the writer thread. Any error also signals exit to the other threads.
 pub fn spawn(config: WriterConfig) -> JoinHandle<Result<()>> {
     thread::spawn(move || {
-        run_writer(&config)
+        let mut config = config;
+        sched::apply("writer", &config.placement);
+        config.shared_state.cpu.register("writer");
+        run_writer(&mut config)
             .inspect(|_| log::debug!("writer exited"))
             .inspect_err(|e| {
                 log::debug!("{e:?}");
@@ -41,384 +140,1030 @@ pub fn spawn(config: WriterConfig) -> JoinHandle<Result<()>> {
 
 fn run_writer(
     WriterConfig {
-        output,
+        outputs,
         to_network,
         iface,
         mgroup,
-        port,
+        ports,
         ttl,
         channels,
         shared_state,
         rate,
+        txtime,
+        pace_burst,
+        max_latency,
+        stamp,
+        stamp_seq,
+        fec,
+        patches,
+        patch_extend,
+        prepend,
+        loop_guard,
+        fanout,
+        packet_destinations,
+        filters,
+        police,
+        reorder,
+        dejitter,
+        fec_decode,
+        convert,
         max_count,
-    }: &WriterConfig,
+        timestamps,
+        append_newline,
+        label,
+        output_format,
+        sigmf,
+        split_by,
+        mark_gaps,
+        strict_disk,
+        expect,
+        heartbeat,
+        exec,
+        drain_timeout,
+        placement: _,
+        sandbox,
+    }: &mut WriterConfig,
 ) -> Result<()> {
-    match &output {
-        Some(filename) if filename == "-" => {
-            log::info!("writing to stdout");
-            write_to_stdout(channels, shared_state, *max_count)
-        }
-        Some(filename) => {
-            log::info!("writing to {filename}");
-            write_to_file(filename, channels, shared_state, *max_count)
-        }
-        None if *to_network => {
-            let iface_str = match iface {
-                Some(iface_str) => format!("{iface_str}:"),
-                None => "".to_string(),
-            };
-            log::info!("writing to {iface_str}{mgroup}");
-            write_to_network(
-                iface.as_deref(),
-                mgroup,
-                *port,
-                *ttl,
-                channels,
-                shared_state,
-                *rate,
-                *max_count,
-            )
+    // Captures put packets that don't say where they went down as sent here
+    let fallback = mgroup
+        .parse::<Ipv4Addr>()
+        .ok()
+        .zip(ports.first())
+        .map(|(group, port)| SocketAddrV4::new(group, *port));
+    let framing_for = |format: Option<OutputFormat>| {
+        let framing =
+            Framing::new(format, shared_state.packet_type, *timestamps).with_fallback(fallback);
+        if *append_newline {
+            framing
+        } else {
+            framing.without_newline()
         }
-        None => {
-            log::debug!("discarding packets");
-            write_to_devnull(channels, shared_state, *max_count)
+    };
+    let framing = framing_for(*output_format);
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    let disk_full = (!*strict_disk).then(|| shared_state.disk_full.clone());
+    let gap_marker = || {
+        mark_gaps.then(|| GapMarker::new(shared_state.packet_type, shared_state.gap_marks.clone()))
+    };
+
+    for (output, format) in outputs.iter() {
+        let format = format.or(*output_format);
+        let framing = framing_for(format);
+        if output == "-" {
+            sinks.push(Box::new(
+                StreamSink::stdout(framing)
+                    .with_label(label.clone())
+                    .with_names(shared_state.names.clone())
+                    .with_gap_marker(gap_marker()),
+            ));
+        } else if let Some(config) = sigmf
+            && format == Some(OutputFormat::Sigmf)
+        {
+            sinks.push(Box::new(
+                SigmfSink::create(output, config.clone())?.with_gap_marker(gap_marker()),
+            ));
+        } else if let Some(split_by) = split_by {
+            sinks.push(Box::new(
+                SplitSink::new(output, framing, *split_by, shared_state.split.clone())
+                    .with_label(label.clone())
+                    .with_names(shared_state.names.clone())
+                    .with_disk_full(disk_full.clone()),
+            ));
+        } else {
+            sinks.push(Box::new(
+                FileSink::create(output, framing)?
+                    .with_label(label.clone())
+                    .with_names(shared_state.names.clone())
+                    .with_disk_full(disk_full.clone())
+                    .with_gap_marker(gap_marker()),
+            ));
         }
     }
-}
 
-fn write_to_devnull(
-    (data_rx, memory_return_tx): &(Receiver<Packets>, Sender<Packets>),
-    shared_state: &SharedState,
-    max_count: u64,
-) -> Result<()> {
-    loop {
-        if shared_state.should_exit() {
-            break;
-        }
+    match exec {
+        Some(ExecCommand {
+            command,
+            per_packet: true,
+        }) => sinks.push(Box::new(ExecPerPacketSink::new(command))),
+        Some(ExecCommand {
+            command,
+            per_packet: false,
+        }) => sinks.push(Box::new(
+            StreamSink::exec(command, framing)?
+                .with_label(label.clone())
+                .with_names(shared_state.names.clone()),
+        )),
+        None => {}
+    }
 
-        if max_count > 0 && shared_state.get_write_count() >= max_count {
-            break;
+    if *to_network {
+        let sink = NetworkSink::new(
+            iface.as_deref(),
+            mgroup,
+            ports,
+            *ttl,
+            *rate,
+            *fanout,
+            shared_state.transmit.clone(),
+        )?;
+        let sink = match txtime {
+            Some(pps) => sink.with_txtime(iface.as_deref(), *pps)?,
+            None => sink,
+        };
+        let sink = if *packet_destinations {
+            sink.with_packet_destinations(iface.as_deref(), *ttl)?
+        } else {
+            sink
+        };
+        let sink = match stamp {
+            Some(offset) => sink.with_stamp(*offset),
+            None => sink,
+        };
+        let sink = match stamp_seq {
+            Some(field) => sink.with_stamp_seq(*field),
+            None => sink,
+        };
+        let sink = match fec {
+            Some(k) => sink.with_fec(*k, shared_state.fec.clone()),
+            None => sink,
+        };
+        let sink = if patches.is_empty() {
+            sink
+        } else {
+            sink.with_patcher(
+                Patcher::new(patches.clone(), *patch_extend),
+                shared_state.patch.clone(),
+            )
+        };
+        let sink = if prepend.is_empty() {
+            sink
+        } else {
+            sink.with_prepend(prepend.clone())
+        };
+        let sink = if loop_guard.is_empty() {
+            sink
+        } else {
+            sink.with_trailer(loop_guard.clone())
+        };
+        // For the reader to drop, should they come back
+        if let Some(source) = sink.source() {
+            log::debug!("sending from {source}");
+            shared_state.own_traffic.set_sender(source);
         }
+        sinks.push(Box::new(sink));
+    }
 
-        match data_rx.recv_timeout(Duration::from_millis(100)) {
-            Ok(packets) => {
-                if packets.is_empty() {
-                    // EOF signal
-                    break;
-                }
+    if let Some(config) = expect {
+        sinks.push(Box::new(ExpectSink::open(
+            config,
+            shared_state.expect.clone(),
+        )?));
+    }
 
-                // Calculate how many packets to process
-                let mut process_count = packets.len();
-                if max_count > 0 {
-                    let remaining = max_count - shared_state.get_write_count();
-                    if process_count as u64 > remaining {
-                        process_count = remaining as usize;
-                    }
-                }
+    if sinks.is_empty() {
+        log::debug!("discarding packets");
+        sinks.push(Box::new(DiscardSink));
+    }
+
+    for sink in sinks.iter() {
+        log::info!("writing to {}", sink.name());
+    }
+
+    let stages = Stages {
+        filters: Filters::new(filters.clone()),
+        policer: police.map(Policer::new),
+        reorder: reorder.map(|(source, depth)| Reorder::new(source, depth)),
+        dejitter: dejitter.map(Dejitter::new),
+        fec: fec_decode.map(FecDecoder::new),
+        transform: convert.map(|conversion| conversion.transform(shared_state.convert.clone())),
+        pacer: Pacer::new(*pace_burst),
+        flush: max_latency.map(FlushBound::new),
+    };
+    let heartbeat = heartbeat.as_ref().map(Heartbeat::new).transpose()?;
+    if *sandbox {
+        sandbox::enter("writer")?;
+    }
+    write_to_sinks(
+        sinks,
+        channels,
+        shared_state,
+        stages,
+        heartbeat,
+        *max_count,
+        *drain_timeout,
+    )
+}
 
-                shared_state.add_write_count(process_count as u64);
+/// What happens to packets on their way from the reader to the sinks.
+#[derive(Debug, Default)]
+struct Stages {
+    filters: Filters,
+    policer: Option<Policer>,
+    reorder: Option<Reorder>,
+    dejitter: Option<Dejitter>,
+    fec: Option<FecDecoder>,
+    transform: Option<Box<dyn Transform>>,
+    pacer: Pacer,
+    flush: Option<FlushBound>,
+}
 
-                // Return packets back to memory pool
-                memory_return_tx.try_send(packets)?;
+impl Stages {
+    /// Take the batch's packets and append those that may go out now to
+    /// ready. Without a stage that holds packets back, the batch is written
+    /// as it is instead.
+    fn push(&mut self, packets: &mut Packets, ready: &mut Vec<Packet>, shared_state: &SharedState) {
+        let now = Instant::now();
+        match (self.reorder.as_mut(), self.dejitter.as_mut()) {
+            (Some(reorder), Some(dejitter)) => {
+                reorder.push(packets, ready, &shared_state.reorder);
+                for packet in ready.drain(..) {
+                    dejitter.hold(packet, now, &shared_state.dejitter);
+                }
+                dejitter.release(now, ready, &shared_state.dejitter);
             }
-            Err(crossbeam_channel::RecvTimeoutError::Timeout) => continue,
-            Err(e) => return Err(e.into()),
+            (Some(reorder), None) => reorder.push(packets, ready, &shared_state.reorder),
+            (None, Some(dejitter)) => {
+                dejitter.push(packets, now, &shared_state.dejitter);
+                dejitter.release(now, ready, &shared_state.dejitter);
+            }
+            (None, None) => {}
         }
     }
 
-    Ok(())
-}
+    fn holds(&self) -> bool {
+        self.reorder.is_some() || self.dejitter.is_some()
+    }
 
-#[allow(clippy::too_many_arguments)]
-fn write_to_network(
-    iface: Option<&str>,
-    mgroup: &str,
-    port: u16,
-    ttl: u8,
-    channels: &(Receiver<Packets>, Sender<Packets>),
-    shared_state: &SharedState,
-    rate: Option<u64>,
-    max_count: u64,
-) -> Result<()> {
-    let socket = create_send_socket(iface, mgroup, port, ttl)?;
-    let fd = socket_to_raw_fd(&socket);
+    /// When the de-jitter buffer next has a packet due.
+    fn next_due(&self) -> Option<Instant> {
+        self.dejitter.as_ref().and_then(Dejitter::next_due)
+    }
 
-    if let Some(rate_limit) = rate {
-        write_with_rate_limit(fd, channels, shared_state, rate_limit, max_count)
-    } else {
-        write_with_sendmmsg(fd, channels, shared_state, max_count)
+    /// Append the packets the de-jitter buffer has due by now to ready.
+    fn release(&mut self, ready: &mut Vec<Packet>, shared_state: &SharedState) {
+        if let Some(dejitter) = self.dejitter.as_mut() {
+            dejitter.release(Instant::now(), ready, &shared_state.dejitter);
+        }
+    }
+
+    /// Everything the reorder buffer still holds, which then waits out its
+    /// delay like the rest when de-jittering.
+    fn flush(&mut self, ready: &mut Vec<Packet>, shared_state: &SharedState) {
+        if let Some(reorder) = self.reorder.as_mut() {
+            reorder.flush(ready);
+            if let Some(dejitter) = self.dejitter.as_mut() {
+                let now = Instant::now();
+                for packet in ready.drain(..) {
+                    dejitter.hold(packet, now, &shared_state.dejitter);
+                }
+            }
+        }
+    }
+
+    /// Take written packets back as spare buffers, by whichever stage swaps
+    /// them out of batches.
+    fn reuse(&mut self, written: &mut Vec<Packet>) {
+        match (self.reorder.as_mut(), self.dejitter.as_mut()) {
+            (Some(reorder), _) => reorder.reuse(written),
+            (None, Some(dejitter)) => dejitter.reuse(written),
+            (None, None) => written.clear(),
+        }
     }
 }
 
-fn write_with_sendmmsg(
-    fd: i32,
-    (data_rx, memory_return_tx): &(Receiver<Packets>, Sender<Packets>),
+/// Fan each batch out to every sink.
+/// A sink that fails is dropped and the rest carry on, so a closed stdout
+/// doesn't stop a recording. The first real failure is still returned once
+/// the stream ends; a reader going away (broken pipe) is not a failure.
+///
+/// Once exit is signaled whatever is still queued is drained rather than
+/// dropped, but only for drain_timeout, less the [`ExitDrain`] margin: a
+/// producer that keeps the channel busy must not keep the writer from
+/// exiting, nor main from joining it before it has flushed.
+///
+/// Filters take out the packets they don't keep, after statistics has
+/// counted them. A policer drops the packets above its rate before any
+/// sink sees them, and
+/// reordering holds packets back until the ones before them have been written.
+/// De-jittering holds each packet for a fixed delay, so the writer wakes for
+/// the next one due rather than only for the next batch. Whatever is still
+/// held is written in order once the loop ends: on time after EOF, within
+/// drain_timeout, and at once when exit cut the stream short.
+///
+/// A heartbeat is written whenever it's due until exit is signaled, so the
+/// writer also wakes for that.
+///
+/// --control can pause the writer, which then leaves batches queued until
+/// resumed or exit is signaled, pace it to a rate, and turn the filters off.
+///
+/// FEC decoding comes first, so every later stage sees the packets as they
+/// were before --fec framed them. A packet rebuilt when a late packet of its
+/// group arrived is written after that packet's batch, and skips the
+/// filters and policer.
+///
+/// A --convert transform comes last, after the filters and policer, and
+/// the sinks see only what it makes. Whatever it still holds is written
+/// once the loop ends.
+fn write_to_sinks(
+    mut sinks: Vec<Box<dyn Sink>>,
+    (data_rx, memory_return_tx): &mut (Box<dyn BatchReceiver>, Sender<Packets>),
     shared_state: &SharedState,
+    mut stages: Stages,
+    mut heartbeat: Option<Heartbeat>,
     max_count: u64,
+    drain_timeout: Option<Duration>,
 ) -> Result<()> {
+    let mut first_error: Option<LibError> = None;
+    let mut drain = ExitDrain::new(drain_timeout);
+    // The stream ran out, rather than exit being signaled
+    let mut ended = false;
+    // Packets the reorder and de-jitter buffers let go of
+    let mut ready = Vec::new();
+    // Packets FEC decoding rebuilt
+    let mut recovered = Vec::new();
+    // Packets a transform made
+    let mut converted = Vec::new();
+
     loop {
-        let packets = match data_rx.recv_timeout(Duration::from_millis(100)) {
-            Ok(packets) => packets,
-            Err(crossbeam_channel::RecvTimeoutError::Timeout) => continue,
-            Err(e) => return Err(e.into()),
-        };
+        if shared_state.take_reopen() {
+            sinks.retain_mut(|sink| {
+                let result = sink.reopen();
+                keep_sink(sink.as_ref(), result, &mut first_error)
+            });
+        }
 
-        if shared_state.should_exit() {
-            break;
+        if let Some(flush) = stages.flush.as_mut() {
+            flush.poll(&mut sinks, shared_state, Instant::now(), &mut first_error);
         }
 
-        if packets.is_empty() {
-            // EOF signal
+        if drain.spent(shared_state) {
+            drain.abandon("writer", &mut **data_rx, shared_state);
+            first_error.get_or_insert(LibError::DrainIncomplete);
             break;
         }
 
-        if max_count > 0 && shared_state.get_write_count() >= max_count {
-            break;
+        // Queued packets wait; exit drains them all the same
+        if shared_state.control.is_paused() && !shared_state.should_exit() {
+            thread::sleep(PAUSE_POLL);
+            continue;
         }
 
-        // Calculate how many packets to send
-        let mut send_count = packets.len();
-        if max_count > 0 {
-            let remaining = max_count - shared_state.get_write_count();
-            if send_count as u64 > remaining {
-                send_count = remaining as usize;
+        if !shared_state.should_exit()
+            && let Some(beat) = heartbeat
+                .as_mut()
+                .and_then(|heartbeat| heartbeat.poll(Instant::now()))
+        {
+            write_batch(&mut sinks, beat, shared_state, max_count, &mut first_error);
+            if sinks.is_empty() {
+                break;
+            }
+            if max_count > 0 && shared_state.get_write_count() >= max_count {
+                shared_state.signal_exit();
+                break;
             }
         }
 
-        let mut headers = MultiHeaders::<SockaddrStorage>::preallocate(send_count, None);
+        let next_due = [
+            stages.next_due(),
+            heartbeat.as_ref().map(Heartbeat::due),
+            stages.flush.as_ref().and_then(FlushBound::due),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let timeout = next_due.map_or(Duration::from_millis(100), |due| {
+            due.saturating_duration_since(Instant::now())
+                .min(Duration::from_millis(100))
+        });
+        let mut packets = match data_rx.pop_timeout(timeout) {
+            Ok(packets) => packets,
+            // Drained
+            Err(_) if shared_state.should_exit() => break,
+            Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                stages.release(&mut ready, shared_state);
+                write_batch(
+                    &mut sinks,
+                    &ready,
+                    shared_state,
+                    max_count,
+                    &mut first_error,
+                );
+                stages.reuse(&mut ready);
+                if sinks.is_empty() {
+                    break;
+                }
+                if max_count > 0 && shared_state.get_write_count() >= max_count {
+                    shared_state.signal_exit();
+                    break;
+                }
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
 
-        let iovecs: Vec<[IoSlice; 1]> = packets
-            .iter()
-            .take(send_count)
-            .map(|pkt| [IoSlice::new(pkt)])
-            .collect();
+        // Check for EOF
+        if packets.is_empty() {
+            ended = true;
+            break;
+        }
 
-        // sendmmsg zips slices with addrs — must be same length.
-        let addrs: Vec<Option<SockaddrStorage>> = vec![None; send_count];
-        sendmmsg(fd, &mut headers, &iovecs, &addrs, [], MsgFlags::empty())?;
+        if let Some(fec) = stages.fec.as_mut() {
+            fec.decode(&mut packets, &shared_state.fec);
+            // Only parity, which isn't EOF
+            if packets.is_empty() {
+                recycle(memory_return_tx, packets)?;
+                continue;
+            }
+        }
 
-        shared_state.add_write_count(send_count as u64);
+        if !stages.filters.is_empty() && shared_state.control.filters_on() {
+            stages.filters.apply(&mut packets, &shared_state.filters);
+            // Emptied by the filters, which isn't EOF
+            if packets.is_empty() {
+                recycle(memory_return_tx, packets)?;
+                continue;
+            }
+        }
 
-        // Return packets to memory pool
-        memory_return_tx.try_send(packets)?;
+        // The batch arrived all at once, so it is policed as of now
+        if let Some(policer) = stages.policer.as_mut() {
+            let now = Instant::now();
+            let arrived = packets.len();
+            packets.retain(|_| policer.conform(now));
+            shared_state.add_policed((arrived - packets.len()) as u64);
+            // Emptied by the policer, which isn't EOF
+            if packets.is_empty() {
+                recycle(memory_return_tx, packets)?;
+                continue;
+            }
+        }
 
         if max_count > 0 && shared_state.get_write_count() >= max_count {
             break;
         }
-    }
 
-    Ok(())
-}
+        if let Some(transform) = stages.transform.as_mut() {
+            transform.push(&packets, &mut converted);
+            stages.pacer.write(
+                &mut sinks,
+                &converted,
+                shared_state,
+                max_count,
+                &mut first_error,
+            );
+            transform.reuse(&mut converted);
+        } else if stages.holds() {
+            stages.push(&mut packets, &mut ready, shared_state);
+            stages.pacer.write(
+                &mut sinks,
+                &ready,
+                shared_state,
+                max_count,
+                &mut first_error,
+            );
+            stages.reuse(&mut ready);
+        } else {
+            stages.pacer.write(
+                &mut sinks,
+                packets.packets(),
+                shared_state,
+                max_count,
+                &mut first_error,
+            );
+        }
 
-fn write_with_rate_limit(
-    fd: i32,
-    (data_rx, memory_return_tx): &(Receiver<Packets>, Sender<Packets>),
-    shared_state: &SharedState,
-    rate: u64,
-    max_count: u64,
-) -> Result<()> {
-    loop {
-        let packets = match data_rx.recv_timeout(Duration::from_millis(100)) {
-            Ok(packets) => packets,
-            Err(crossbeam_channel::RecvTimeoutError::Timeout) => continue,
-            Err(e) => return Err(e.into()),
-        };
+        // Return batch to memory pool
+        recycle(memory_return_tx, packets)?;
 
-        if shared_state.should_exit() {
-            break;
+        if let Some(fec) = stages.fec.as_mut() {
+            fec.take_recovered(&mut recovered);
+            write_batch(
+                &mut sinks,
+                &recovered,
+                shared_state,
+                max_count,
+                &mut first_error,
+            );
+            recovered.clear();
         }
 
-        // Check for EOF
-        if packets.is_empty() {
+        if sinks.is_empty() {
             break;
         }
 
         if max_count > 0 && shared_state.get_write_count() >= max_count {
+            shared_state.signal_exit();
             break;
         }
+    }
 
-        // Calculate how many packets to send
-        let mut send_limit = packets.len();
-        if max_count > 0 {
-            let remaining = max_count - shared_state.get_write_count();
-            if send_limit as u64 > remaining {
-                send_limit = remaining as usize;
-            }
+    if let Some(fec) = stages.fec.as_mut() {
+        fec.flush(&shared_state.fec);
+        fec.take_recovered(&mut recovered);
+        write_batch(
+            &mut sinks,
+            &recovered,
+            shared_state,
+            max_count,
+            &mut first_error,
+        );
+    }
+    if let Some(transform) = stages.transform.as_mut() {
+        transform.flush(&mut converted);
+        write_batch(
+            &mut sinks,
+            &converted,
+            shared_state,
+            max_count,
+            &mut first_error,
+        );
+    }
+    stages.flush(&mut ready, shared_state);
+    write_batch(
+        &mut sinks,
+        &ready,
+        shared_state,
+        max_count,
+        &mut first_error,
+    );
+    stages.reuse(&mut ready);
+    while let Some(due) = stages.next_due() {
+        if sinks.is_empty() || max_count > 0 && shared_state.get_write_count() >= max_count {
+            break;
+        }
+        // Exit follows EOF, since the reader is done, so only drain_timeout
+        // cuts the wait short
+        let waited_out = drain.spent(shared_state);
+        if ended && !waited_out {
+            // In slices, to notice the timeout
+            thread::sleep(
+                due.saturating_duration_since(Instant::now())
+                    .min(Duration::from_millis(100)),
+            );
+            stages.release(&mut ready, shared_state);
+        } else if let Some(dejitter) = stages.dejitter.as_mut() {
+            dejitter.flush(&mut ready, &shared_state.dejitter);
         }
+        write_batch(
+            &mut sinks,
+            &ready,
+            shared_state,
+            max_count,
+            &mut first_error,
+        );
+        stages.reuse(&mut ready);
+    }
 
-        let mut sent_count = 0u64;
-        for (idx, packet) in packets.iter().enumerate() {
-            if idx >= send_limit {
-                break;
-            }
+    sinks.retain_mut(|sink| {
+        let result = sink.flush();
+        keep_sink(sink.as_ref(), result, &mut first_error)
+    });
 
-            let iov = [IoSlice::new(packet)];
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
 
-            sendmsg::<()>(fd, &iov, &[], MsgFlags::empty(), None).inspect(|_| sent_count += 1)?;
+// How often a paused writer looks to see if it may go on.
+const PAUSE_POLL: Duration = Duration::from_millis(50);
 
-            for _ in 0..rate {
-                std::hint::spin_loop();
-            }
+/// Spaces the reader's batches out to the --control rate, or --pps when
+/// forwarding, with a token bucket.
+#[derive(Debug, Default)]
+struct Pacer {
+    bucket: TokenBucket,
+}
+
+impl Pacer {
+    fn new(burst: Option<u64>) -> Self {
+        Self {
+            bucket: TokenBucket::new(burst),
         }
+    }
 
-        shared_state.add_write_count(sent_count);
+    /// write_batch, at the rate if one is set. A pause part way through
+    /// holds the rest; exit sends it at once.
+    fn write(
+        &mut self,
+        sinks: &mut Vec<Box<dyn Sink>>,
+        batch: &[Packet],
+        shared_state: &SharedState,
+        max_count: u64,
+        first_error: &mut Option<LibError>,
+    ) {
+        let mut rest = batch;
+        while !rest.is_empty() && !sinks.is_empty() {
+            let control = &shared_state.control;
+            if control.is_paused() && !shared_state.should_exit() {
+                thread::sleep(PAUSE_POLL);
+                continue;
+            }
+            let slice = match control.rate() {
+                Some(pps) => match self.bucket.take(pps, rest.len(), shared_state) {
+                    0 => rest.len(),
+                    granted => granted,
+                },
+                None => {
+                    self.bucket.reset();
+                    rest.len()
+                }
+            };
+            let (now, later) = rest.split_at(slice.min(rest.len()));
+            write_batch(sinks, now, shared_state, max_count, first_error);
+            rest = later;
+        }
+    }
+}
 
-        // Return batch to memory pool
-        memory_return_tx.try_send(packets)?;
+/// --max-latency: pushes out what the sinks buffer once the oldest of it
+/// has waited that long, so a slow stream isn't held back until a buffer
+/// fills or the stream ends.
+#[derive(Debug)]
+struct FlushBound {
+    max: Duration,
+    // Packets written when last looked
+    written: u64,
+    // When packets were first seen written since the last push
+    since: Option<Instant>,
+}
 
-        if max_count > 0 && shared_state.get_write_count() >= max_count {
-            shared_state.signal_exit();
-            break;
+impl FlushBound {
+    fn new(max: Duration) -> Self {
+        Self {
+            max,
+            written: 0,
+            since: None,
         }
     }
 
-    Ok(())
+    /// When what's buffered is due out.
+    fn due(&self) -> Option<Instant> {
+        self.since.map(|since| since + self.max)
+    }
+
+    /// Note packets written since the last look, and push them out if due.
+    fn poll(
+        &mut self,
+        sinks: &mut Vec<Box<dyn Sink>>,
+        shared_state: &SharedState,
+        now: Instant,
+        first_error: &mut Option<LibError>,
+    ) {
+        let written = shared_state.get_write_count();
+        if written != self.written {
+            self.written = written;
+            self.since.get_or_insert(now);
+        }
+        if self.due().is_some_and(|due| due <= now) {
+            sinks.retain_mut(|sink| {
+                let result = sink.flush_buffered();
+                keep_sink(sink.as_ref(), result, first_error)
+            });
+            self.since = None;
+        }
+    }
 }
 
-fn write_to_file(
-    filename: &str,
-    channels: &(Receiver<Packets>, Sender<Packets>),
+/// Write as much of batch as -c has room for to every sink.
+fn write_batch(
+    sinks: &mut Vec<Box<dyn Sink>>,
+    batch: &[Packet],
     shared_state: &SharedState,
     max_count: u64,
-) -> Result<()> {
-    let file = File::create(filename)?;
-    let mut writer = BufWriter::with_capacity(1024 * 1024, file);
+    first_error: &mut Option<LibError>,
+) {
+    let mut write_limit = batch.len();
+    if max_count > 0 {
+        let remaining = max_count.saturating_sub(shared_state.get_write_count());
+        write_limit = write_limit.min(remaining as usize);
+    }
 
-    match shared_state.packet_type {
-        PacketType::Text => write_text_mode(&mut writer, channels, shared_state, max_count),
-        _ => write_binary_mode(&mut writer, channels, shared_state, max_count),
+    let batch = batch.get(..write_limit).unwrap_or_default();
+    if batch.is_empty() {
+        return;
     }
+    sinks.retain_mut(|sink| {
+        let result = sink.write_packets(batch);
+        keep_sink(sink.as_ref(), result, first_error)
+    });
+
+    shared_state.add_write_count(write_limit as u64);
+    shared_state.add_write_bytes(batch.iter().map(|packet| packet.len() as u64).sum());
 }
 
-fn write_to_stdout(
-    channels: &(Receiver<Packets>, Sender<Packets>),
-    shared_state: &SharedState,
-    max_count: u64,
-) -> Result<()> {
-    let mut stdout = io::stdout();
+fn keep_sink(sink: &dyn Sink, result: Result<()>, first_error: &mut Option<LibError>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(LibError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => {
+            log::warn!("{} closed, no longer writing to it", sink.name());
+            false
+        }
+        Err(e) => {
+            log::error!("{} failed: {e}", sink.name());
+            first_error.get_or_insert(e);
+            false
+        }
+    }
+}
 
-    match shared_state.packet_type {
-        PacketType::Text => write_text_mode(&mut stdout, channels, shared_state, max_count),
-        _ => write_binary_mode(&mut stdout, channels, shared_state, max_count),
+/// Return a batch to the memory pool.
+/// The reader stops pulling from the pool once it has sent EOF, so a
+/// disconnected pool at the end of the stream is not an error. Filters and
+/// the policer and --strip may have emptied the batch, the readers expect it whole.
+pub(crate) fn recycle(memory_return_tx: &Sender<Packets>, mut packets: Packets) -> Result<()> {
+    packets.restore();
+    match memory_return_tx.try_send(packets) {
+        Ok(()) | Err(TrySendError::Disconnected(_)) => Ok(()),
+        Err(e) => Err(e.into()),
     }
 }
 
-fn write_text_mode<W: Write>(
-    writer: &mut W,
-    (data_rx, memory_return_tx): &(Receiver<Packets>, Sender<Packets>),
-    shared_state: &SharedState,
-    max_count: u64,
-) -> Result<()> {
-    loop {
-        let packets = match data_rx.recv_timeout(Duration::from_millis(100)) {
-            Ok(packets) => packets,
-            Err(crossbeam_channel::RecvTimeoutError::Timeout) => continue,
-            Err(e) => return Err(e.into()),
-        };
+#[cfg(test)]
+#[allow(clippy::expect_used)]
+mod tests {
+    use std::sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    use super::*;
+    use crate::{
+        heartbeat::parse_heartbeat,
+        packet::{Packet, PacketType},
+        transport::{self, TransportKind},
+    };
+
+    // Records what it was given, optionally failing after a few packets.
+    struct TestSink {
+        written: Arc<Mutex<Vec<Vec<u8>>>>,
+        fail_after: Option<(usize, io::ErrorKind)>,
+    }
 
-        if shared_state.should_exit() {
-            break;
+    impl Sink for TestSink {
+        fn name(&self) -> &str {
+            "test"
         }
 
-        // Check for EOF
-        if packets.is_empty() {
-            break;
+        fn write_packets(&mut self, packets: &[Packet]) -> Result<()> {
+            let mut written = self
+                .written
+                .lock()
+                .map_err(|e| LibError::Critical(e.to_string()))?;
+            for packet in packets {
+                if let Some((limit, kind)) = self.fail_after
+                    && written.len() >= limit
+                {
+                    return Err(io::Error::from(kind).into());
+                }
+                written.push(packet.to_vec());
+            }
+            Ok(())
         }
+    }
 
-        if max_count > 0 && shared_state.get_write_count() >= max_count {
-            break;
-        }
+    fn run(sinks: Vec<Box<dyn Sink>>, batches: usize) -> Result<()> {
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let (mut data_tx, data_rx) = transport::bounded(TransportKind::Channel, batches + 1);
+        let (memory_return_tx, _memory_return_rx) = crossbeam_channel::bounded(batches + 1);
 
-        // Calculate how many packets to write
-        let mut write_limit = packets.len();
-        if max_count > 0 {
-            let remaining = max_count - shared_state.get_write_count();
-            if write_limit as u64 > remaining {
-                write_limit = remaining as usize;
+        for i in 0..batches {
+            let mut packets = Packets::new(1, 8);
+            for packet in packets.iter_mut() {
+                if let Some(byte) = packet.data_mut().first_mut() {
+                    *byte = i as u8;
+                }
+                packet.set_length(1);
             }
+            assert!(data_tx.try_push(packets).is_ok());
         }
+        assert!(data_tx.try_push(Packets::empty()).is_ok());
+
+        write_to_sinks(
+            sinks,
+            &mut (data_rx, memory_return_tx),
+            &shared_state,
+            Stages::default(),
+            None,
+            0,
+            None,
+        )
+    }
 
-        for (idx, packet) in packets.iter().enumerate() {
-            if idx >= write_limit {
-                break;
-            }
+    fn test_sink(
+        fail_after: Option<(usize, io::ErrorKind)>,
+    ) -> (TestSink, Arc<Mutex<Vec<Vec<u8>>>>) {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let sink = TestSink {
+            written: written.clone(),
+            fail_after,
+        };
+        (sink, written)
+    }
 
-            writer.write_all(packet)?;
+    #[test]
+    fn test_every_sink_gets_every_packet() {
+        let (first, first_written) = test_sink(None);
+        let (second, second_written) = test_sink(None);
+
+        assert!(run(vec![Box::new(first), Box::new(second)], 5).is_ok());
+        assert_eq!(first_written.lock().expect("lock").len(), 5);
+        assert_eq!(
+            *first_written.lock().expect("lock"),
+            *second_written.lock().expect("lock")
+        );
+    }
 
-            if !packet.ends_with(b"\n") {
-                writer.write_all(b"\n")?;
-            }
-        }
+    #[test]
+    fn test_closed_sink_does_not_stop_others() {
+        let (stdout, _) = test_sink(Some((2, io::ErrorKind::BrokenPipe)));
+        let (file, file_written) = test_sink(None);
 
-        shared_state.add_write_count(write_limit as u64);
+        assert!(run(vec![Box::new(stdout), Box::new(file)], 5).is_ok());
+        assert_eq!(file_written.lock().expect("lock").len(), 5);
+    }
 
-        // Return batch to memory pool
-        memory_return_tx.try_send(packets)?;
+    #[test]
+    fn test_failed_sink_is_reported_after_others_finish() {
+        let (disk, _) = test_sink(Some((2, io::ErrorKind::StorageFull)));
+        let (stdout, stdout_written) = test_sink(None);
 
-        if max_count > 0 && shared_state.get_write_count() >= max_count {
-            shared_state.signal_exit();
-            break;
-        }
+        assert!(run(vec![Box::new(disk), Box::new(stdout)], 5).is_err());
+        assert_eq!(stdout_written.lock().expect("lock").len(), 5);
     }
 
-    Ok(())
-}
-
-fn write_binary_mode<W: Write>(
-    writer: &mut W,
-    (data_rx, memory_return_tx): &(Receiver<Packets>, Sender<Packets>),
-    shared_state: &SharedState,
-    max_count: u64,
-) -> Result<()> {
-    loop {
-        let packets = match data_rx.recv_timeout(Duration::from_millis(100)) {
-            Ok(packets) => packets,
-            Err(crossbeam_channel::RecvTimeoutError::Timeout) => continue,
-            Err(e) => return Err(e.into()),
+    #[test]
+    fn test_exit_while_channel_stays_busy() {
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let (mut data_tx, data_rx) = transport::bounded(TransportKind::Channel, 4);
+        let (memory_return_tx, memory_return_rx) = crossbeam_channel::unbounded();
+
+        // A live relay: the producer never runs dry and never looks at should_exit
+        let feeding = Arc::new(AtomicBool::new(true));
+        let producer = {
+            let feeding = feeding.clone();
+            thread::spawn(move || {
+                while feeding.load(Ordering::Relaxed) {
+                    let packets = memory_return_rx
+                        .try_recv()
+                        .unwrap_or_else(|_| Packets::new(1, 8));
+                    let _ = data_tx.try_push(packets);
+                }
+            })
         };
 
-        if shared_state.should_exit() {
-            break;
-        }
+        let exit = shared_state.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            exit.signal_exit();
+        });
+
+        let (sink, _) = test_sink(None);
+        let start = Instant::now();
+        let result = write_to_sinks(
+            vec![Box::new(sink)],
+            &mut (data_rx, memory_return_tx),
+            &shared_state,
+            Stages::default(),
+            None,
+            0,
+            Some(Duration::from_millis(100)),
+        );
+        let elapsed = start.elapsed();
+        feeding.store(false, Ordering::Relaxed);
+        assert!(producer.join().is_ok());
+
+        // Returned promptly, and reported the incomplete drain
+        assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+        assert!(matches!(result, Err(LibError::DrainIncomplete)));
+    }
 
-        // Check for EOF
-        if packets.is_empty() {
-            break;
+    #[test]
+    fn test_queued_batches_drain_after_exit() {
+        let (sink, written) = test_sink(None);
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let (mut data_tx, data_rx) = transport::bounded(TransportKind::Channel, 4);
+        let (memory_return_tx, _memory_return_rx) = crossbeam_channel::bounded(4);
+
+        for _ in 0..3 {
+            let mut packets = Packets::new(1, 8);
+            packets.set_length(1);
+            assert!(data_tx.try_push(packets).is_ok());
         }
+        shared_state.signal_exit();
+
+        let result = write_to_sinks(
+            vec![Box::new(sink)],
+            &mut (data_rx, memory_return_tx),
+            &shared_state,
+            Stages::default(),
+            None,
+            0,
+            Some(Duration::from_secs(5)),
+        );
+        assert!(result.is_ok());
+        assert_eq!(written.lock().expect("lock").len(), 3);
+    }
 
-        if max_count > 0 && shared_state.get_write_count() >= max_count {
-            break;
-        }
+    #[test]
+    fn test_heartbeat_without_input() {
+        let (sink, written) = test_sink(None);
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        // Nothing is ever sent, but the channel stays open
+        let (_data_tx, data_rx) = transport::bounded(TransportKind::Channel, 4);
+        let (memory_return_tx, _memory_return_rx) = crossbeam_channel::bounded(4);
+
+        let config = parse_heartbeat("10ms:beat {seq}").expect("parse");
+        let result = write_to_sinks(
+            vec![Box::new(sink)],
+            &mut (data_rx, memory_return_tx),
+            &shared_state,
+            Stages::default(),
+            Some(Heartbeat::new(&config).expect("heartbeat")),
+            3,
+            None,
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            *written.lock().expect("lock"),
+            vec![b"beat 0".to_vec(), b"beat 1".to_vec(), b"beat 2".to_vec()]
+        );
+        assert!(shared_state.should_exit());
+    }
 
-        // Calculate how many packets to write
-        let mut write_limit = packets.len();
-        if max_count > 0 {
-            let remaining = max_count - shared_state.get_write_count();
-            if write_limit as u64 > remaining {
-                write_limit = remaining as usize;
+    #[test]
+    fn test_policer_drops_the_excess() {
+        let (sink, written) = test_sink(None);
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let (mut data_tx, data_rx) = transport::bounded(TransportKind::Channel, 4);
+        let (memory_return_tx, memory_return_rx) = crossbeam_channel::bounded(4);
+
+        // Two batches arriving faster than 1 pps can earn a token
+        for _ in 0..2 {
+            let mut packets = Packets::new(10, 8);
+            for packet in packets.iter_mut() {
+                packet.set_length(1);
             }
+            assert!(data_tx.try_push(packets).is_ok());
         }
+        assert!(data_tx.try_push(Packets::empty()).is_ok());
+
+        let policer = Policer::new(PoliceRate { pps: 1, burst: 4 });
+        let result = write_to_sinks(
+            vec![Box::new(sink)],
+            &mut (data_rx, memory_return_tx),
+            &shared_state,
+            Stages {
+                policer: Some(policer),
+                ..Default::default()
+            },
+            None,
+            0,
+            None,
+        );
+        assert!(result.is_ok());
+        assert_eq!(written.lock().expect("lock").len(), 4);
+        assert_eq!(shared_state.get_policed(), 16);
+        assert_eq!(shared_state.get_write_count(), 4);
+        // Both batches went back to the pool, the emptied one included
+        assert_eq!(memory_return_rx.len(), 2);
+    }
 
-        for (idx, packet) in packets.iter().enumerate() {
-            if idx >= write_limit {
-                break;
+    #[test]
+    fn test_dejitter_writes_held_packets_on_time() {
+        let (sink, written) = test_sink(None);
+        let shared_state = SharedState::new(PacketType::Binary, false);
+        let (mut data_tx, data_rx) = transport::bounded(TransportKind::Channel, 4);
+        let (memory_return_tx, memory_return_rx) = crossbeam_channel::bounded(4);
+
+        for _ in 0..3 {
+            let mut packets = Packets::new(2, 8);
+            for packet in packets.iter_mut() {
+                packet.set_length(1);
             }
-
-            let length = packet.len() as u32;
-            writer.write_all(&length.to_le_bytes())?;
-            writer.write_all(packet)?;
-        }
-
-        shared_state.add_write_count(write_limit as u64);
-
-        // Return packets to memory pool
-        memory_return_tx.try_send(packets)?;
-
-        if max_count > 0 && shared_state.get_write_count() >= max_count {
-            shared_state.signal_exit();
-            break;
+            assert!(data_tx.try_push(packets).is_ok());
         }
+        assert!(data_tx.try_push(Packets::empty()).is_ok());
+
+        // The stream ends long before the packets are due
+        let start = Instant::now();
+        let result = write_to_sinks(
+            vec![Box::new(sink)],
+            &mut (data_rx, memory_return_tx),
+            &shared_state,
+            Stages {
+                dejitter: Some(Dejitter::new(Duration::from_millis(50))),
+                ..Default::default()
+            },
+            None,
+            0,
+            None,
+        );
+        assert!(result.is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(written.lock().expect("lock").len(), 6);
+        assert_eq!(shared_state.dejitter.get().0, 0);
+        // Held packets were swapped out, so every batch still went back
+        assert_eq!(memory_return_rx.len(), 3);
     }
-
-    Ok(())
 }
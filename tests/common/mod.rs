@@ -0,0 +1,90 @@
+//! Helpers shared by the integration tests. Each test file is a crate of its
+//! own and takes them with `mod common;`, so not every one uses them all.
+#![allow(dead_code, clippy::expect_used)]
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+/// The mnc binary under test.
+pub fn mnc() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_mnc"))
+}
+
+/// mnc with args and its stdout and stderr captured, to spawn and collect
+/// with wait_with_output.
+pub fn piped(args: &[&str]) -> Command {
+    let mut command = mnc();
+    command
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    command
+}
+
+/// Runs command to the end and checks it exited with code.
+pub fn exits_with(command: &mut Command, code: i32) -> Output {
+    let output = command.output().expect("run mnc");
+    assert_eq!(output.status.code(), Some(code), "{output:?}");
+    output
+}
+
+/// Runs command with stdin on its standard input, to the end, and checks it
+/// exited with code.
+pub fn exits_with_input(command: &mut Command, stdin: &[u8], code: i32) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn mnc");
+    // Fed from a thread so a child writing out as it reads can't block us
+    let mut pipe = child.stdin.take().expect("stdin");
+    let stdin = stdin.to_vec();
+    let feeder = thread::spawn(move || {
+        // A child that exits before reading it all closes the pipe early
+        let _ = pipe.write_all(&stdin);
+    });
+    let output = child.wait_with_output().expect("wait for mnc");
+    feeder.join().expect("feeder");
+    assert_eq!(output.status.code(), Some(code), "{output:?}");
+    output
+}
+
+/// A path in the temporary directory, unique to this test binary.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mnc-{}-{name}", std::process::id()))
+}
+
+/// A file in the temporary directory holding contents.
+pub fn temp_file(name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+    let path = temp_path(name);
+    std::fs::write(&path, contents).expect("write");
+    path
+}
+
+/// An SDDS packet with seq for its frame sequence number. Every 32nd, from
+/// 0, is a parity packet.
+pub fn sdds_packet(seq: u16) -> Vec<u8> {
+    let mut packet = vec![0u8; 1080];
+    packet.splice(0..2, [0x80, 16]);
+    packet.splice(2..4, seq.to_be_bytes());
+    packet
+}
+
+/// packets as a binary recording, each after its length.
+pub fn recording(packets: &[Vec<u8>]) -> Vec<u8> {
+    let mut recording = Vec::new();
+    for packet in packets {
+        recording.extend((packet.len() as u32).to_le_bytes());
+        recording.extend(packet);
+    }
+    recording
+}
+
+/// A recording of SDDS packets numbered seqs.
+pub fn sdds_recording(seqs: impl IntoIterator<Item = u16>) -> Vec<u8> {
+    let packets: Vec<_> = seqs.into_iter().map(sdds_packet).collect();
+    recording(&packets)
+}
@@ -0,0 +1,70 @@
+//! --control pauses and paces a replay while it runs, and --ctl scripts it.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::{mnc, temp_path};
+
+fn ctl(socket: &std::path::Path, command: &str) -> (bool, String) {
+    let output = mnc()
+        .arg("--ctl")
+        .arg(socket)
+        .arg(command)
+        .output()
+        .expect("mnc --ctl");
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    )
+}
+
+#[test]
+fn test_pause_and_resume_a_replay() {
+    let input = temp_path("control.jsonl");
+    let socket = temp_path("control.sock");
+    let lines: String = (0..20)
+        .map(|_| "{\"payload_b64\": \"cGluZw==\", \"delay_us\": 20000}\n")
+        .collect();
+    std::fs::write(&input, lines).expect("write");
+
+    let sender = mnc()
+        .args(["239.255.77.65", "-p", "39566", "--input-format", "jsonl"])
+        .arg("-i")
+        .arg(&input)
+        .arg("--control")
+        .arg(&socket)
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(150));
+    assert_eq!(ctl(&socket, "pause"), (true, "paused".to_string()));
+    sleep(Duration::from_millis(100));
+    let (_, paused) = ctl(&socket, "stats");
+    sleep(Duration::from_millis(300));
+    let (_, still) = ctl(&socket, "stats");
+    assert!(paused.contains("; paused;"), "{paused}");
+    // Nothing more goes out while paused
+    assert_eq!(paused, still);
+
+    let (resumed, answer) = ctl(&socket, "resume");
+    assert!(resumed && answer.starts_with("resumed after "), "{answer}");
+    assert_eq!(
+        ctl(&socket, "rate 1000"),
+        (true, "rate 1000 pkt/s".to_string())
+    );
+    let (ok, answer) = ctl(&socket, "faster");
+    assert!(!ok && answer.starts_with("error:"), "{answer}");
+
+    let output = sender.wait_with_output().expect("sender");
+    let _ = std::fs::remove_file(&input);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("20 packets read, 20 written"), "{stderr}");
+    // Removed on the way out
+    assert!(!socket.exists());
+}
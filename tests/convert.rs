@@ -0,0 +1,107 @@
+//! --convert vita49:rawN cuts the samples of VITA-49 data packets into
+//! fixed size records.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::fs;
+use std::net::UdpSocket;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::{mnc, temp_path};
+
+// A VRLP frame holding a VITA-49 packet with a stream id and payload
+fn frame(packet_type: u32, payload: &[u8]) -> Vec<u8> {
+    let words = 2 + payload.len().div_ceil(4);
+    let mut frame = b"VRLP".to_vec();
+    frame.extend(((words + 3) as u32).to_be_bytes());
+    frame.extend(((packet_type << 28) | words as u32).to_be_bytes());
+    frame.extend(7u32.to_be_bytes());
+    frame.extend(payload);
+    frame.extend(b"VEND");
+    frame
+}
+
+#[test]
+fn test_vita49_to_raw_records() {
+    let output = temp_path("convert.raw");
+    let child = mnc()
+        .args([
+            "239.255.77.74",
+            "-p",
+            "39579",
+            "-t",
+            "vita49",
+            "--convert",
+            "vita49:raw16",
+            "--output-format",
+            "raw",
+            "-c",
+            "4",
+            "-o",
+            output.to_str().expect("path"),
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    sleep(Duration::from_millis(300));
+
+    // 36 bytes of samples around a context packet
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    for packet in [
+        frame(1, &[1; 12]),
+        frame(4, &[9; 8]),
+        frame(1, &[2; 12]),
+        frame(1, &[3; 12]),
+    ] {
+        sender
+            .send_to(&packet, "239.255.77.74:39579")
+            .expect("send");
+        sleep(Duration::from_millis(10));
+    }
+
+    let child = child.wait_with_output().expect("wait");
+    let stderr = String::from_utf8_lossy(&child.stderr);
+    assert!(child.status.success(), "{stderr}");
+    assert!(
+        stderr.contains(
+            "4 packets read, 3 written, 3 converted vita49:raw16 into 3 records, 1 with nothing to convert"
+        ),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("4 bytes left at the end of the stream"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("never written"), "{stderr}");
+
+    let written = fs::read(&output).expect("output");
+    let samples: Vec<u8> = [[1; 12], [2; 12], [3; 12]].concat();
+    assert_eq!(written, samples);
+    let _ = fs::remove_file(output);
+}
+
+#[test]
+fn test_convert_needs_its_packet_type() {
+    let output = mnc()
+        .args([
+            "239.255.77.74",
+            "-p",
+            "39579",
+            "-t",
+            "sdds",
+            "--convert",
+            "vita49:raw1024",
+        ])
+        .output()
+        .expect("mnc");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--convert vita49:raw1024 takes -t vita49"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,228 @@
+//! -c delivers exactly that many packets, however they are batched.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::{mnc, sdds_recording, temp_file, temp_path};
+
+#[test]
+fn test_count_is_exact_under_batching() {
+    let path = temp_path("count");
+    let output = path.to_str().expect("utf8 path");
+    let mut child = mnc()
+        .args([
+            "239.255.77.8",
+            "-p",
+            "39508",
+            "-c",
+            "7",
+            "-b",
+            "64",
+            "-o",
+            output,
+        ])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    // Bursts so recvmmsg returns many packets per call, straddling the count
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while child.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        for _ in 0..50 {
+            sender
+                .send_to(b"payload", "239.255.77.8:39508")
+                .expect("send");
+        }
+        sleep(Duration::from_millis(20));
+    }
+
+    let status = child.wait().expect("wait");
+    assert!(status.success());
+    let written = std::fs::read_to_string(&path).expect("read");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(written.lines().count(), 7, "{written:?}");
+}
+
+#[test]
+fn test_zero_length_datagrams_are_delivered() {
+    let path = temp_path("empty");
+    let output = path.to_str().expect("utf8 path");
+    let mut child = mnc()
+        .args(["239.255.77.8", "-p", "39510", "-c", "4", "-o", output])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    // Sent back to back so they land in one recvmmsg batch
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    for payload in [&b"a"[..], b"", b"b", b""] {
+        sender.send_to(payload, "239.255.77.8:39510").expect("send");
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while child.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        sleep(Duration::from_millis(20));
+    }
+    let _ = child.kill();
+
+    let written = std::fs::read_to_string(&path).expect("read");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(written, "a\n\nb\n\n");
+}
+
+#[test]
+fn test_count_spans_every_port() {
+    let path = temp_path("ports");
+    let output = path.to_str().expect("utf8 path");
+    let mut child = mnc()
+        .args([
+            "239.255.77.37",
+            "-p",
+            "39537-39538",
+            "-c",
+            "4",
+            "-o",
+            output,
+        ])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    for (payload, port) in [("a", 39537), ("b", 39538), ("c", 39538), ("d", 39537)] {
+        sender
+            .send_to(payload.as_bytes(), ("239.255.77.37", port))
+            .expect("send");
+        sleep(Duration::from_millis(20));
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while child.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        sleep(Duration::from_millis(20));
+    }
+    let _ = child.kill();
+    let status = child.wait().expect("wait");
+    assert!(status.success());
+    let written = std::fs::read_to_string(&path).expect("read");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(written, "a\nb\nc\nd\n");
+}
+
+#[test]
+fn test_sending_goes_to_every_port() {
+    let input = temp_path("every-port-in");
+    let path = temp_path("every-port-out");
+    std::fs::write(&input, "a\nb\nc\n").expect("write");
+    let mut child = mnc()
+        .args(["239.255.77.64", "-p", "39564-39565", "-c", "6", "--label"])
+        .arg("-o")
+        .arg(&path)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    let sent = mnc()
+        .args(["239.255.77.64", "-p", "39564,39565"])
+        .arg("-i")
+        .arg(&input)
+        .stderr(Stdio::null())
+        .status()
+        .expect("sender");
+    assert!(sent.success());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while child.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        sleep(Duration::from_millis(20));
+    }
+    let _ = child.kill();
+    let status = child.wait().expect("wait");
+    assert!(status.success());
+    let written = std::fs::read_to_string(&path).expect("read");
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&path);
+    let mut lines: Vec<&str> = written.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(
+        lines,
+        [
+            "[239.255.77.64:39564] a",
+            "[239.255.77.64:39564] b",
+            "[239.255.77.64:39564] c",
+            "[239.255.77.64:39565] a",
+            "[239.255.77.64:39565] b",
+            "[239.255.77.64:39565] c",
+        ]
+    );
+}
+
+#[test]
+fn test_ping_takes_one_port() {
+    mnc()
+        .args(["239.255.77.37", "-p", "39537-39538", "--ping"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| assert_eq!(status.code(), Some(2)))
+        .expect("run");
+}
+
+// Sends -c packets to the group with the parity packet dropped on the way
+fn send_dropping_parity(name: &str, direction: &[&str]) -> String {
+    // 8 SDDS packets numbered 0 to 7, the first one parity
+    let path = temp_file(name, sdds_recording(0..8));
+    let output = mnc()
+        .args(["239.255.77.46", "-p", "39546", "-L", "0", "-t", "sdds"])
+        .args(["--drop-parity", "-c", "5"])
+        .args(direction)
+        .arg("-i")
+        .arg(&path)
+        .output()
+        .expect("run");
+    let _ = std::fs::remove_file(&path);
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+#[test]
+fn test_count_sent_when_sending() {
+    let stderr = send_dropping_parity("count-tx", &[]);
+    assert!(stderr.contains(" 5 written"), "{stderr}");
+    assert!(!stderr.contains("never written"), "{stderr}");
+}
+
+#[test]
+fn test_count_rx_when_sending() {
+    let stderr = send_dropping_parity("count-rx", &["--count-rx"]);
+    assert!(stderr.contains("5 packets read, 4 written"), "{stderr}");
+}
+
+#[test]
+fn test_count_tx_when_copying() {
+    let path = temp_file("count-local", sdds_recording(0..8));
+    let output = mnc()
+        .args(["239.255.77.46", "-p", "39546", "-t", "sdds", "--local"])
+        .args(["--drop-parity", "-c", "5", "--count-tx", "-o", "-", "-i"])
+        .arg(&path)
+        .output()
+        .expect("run");
+    let _ = std::fs::remove_file(&path);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout.len(), 5 * (4 + 1080));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(" 5 written"), "{stderr}");
+    assert!(stderr.contains("5.3 KiB written"), "{stderr}");
+}
@@ -0,0 +1,62 @@
+//! --dejitter holding received packets, on one host.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::piped;
+
+#[test]
+fn test_packets_are_held_for_the_delay() {
+    let receiver = piped(&[
+        "239.255.77.41",
+        "-p",
+        "39542",
+        "--dejitter",
+        "400",
+        "-c",
+        "3",
+        "-o",
+        "-",
+    ])
+    .spawn()
+    .expect("spawn receiver");
+    sleep(Duration::from_millis(300));
+
+    let sender = std::net::UdpSocket::bind("0.0.0.0:0").expect("bind");
+    for payload in ["a", "b", "c"] {
+        sender
+            .send_to(payload.as_bytes(), "239.255.77.41:39542")
+            .expect("send");
+        sleep(Duration::from_millis(10));
+    }
+    let sent = Instant::now();
+
+    // The last packet can't be written, and -c met, until it's due
+    let received = receiver.wait_with_output().expect("wait");
+    assert!(received.status.success(), "{received:?}");
+    assert_eq!(String::from_utf8_lossy(&received.stdout), "a\nb\nc\n");
+    assert!(
+        sent.elapsed() >= Duration::from_millis(350),
+        "{:?}",
+        sent.elapsed()
+    );
+}
+
+#[test]
+fn test_dejitter_needs_receiving() {
+    let output = piped(&[
+        "-i",
+        "/dev/null",
+        "239.255.77.41",
+        "-p",
+        "39542",
+        "--dejitter",
+        "50",
+    ])
+    .output()
+    .expect("run");
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,35 @@
+//! --dry-run validates and exits without doing any work.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Output;
+
+use common::{mnc, temp_path};
+
+fn run(args: &[&str]) -> Output {
+    mnc().args(args).output().expect("run mnc")
+}
+
+#[test]
+fn test_dry_run_ok_leaves_no_output() {
+    let path = temp_path("dry-run");
+    let path = path.to_str().expect("utf8 path");
+
+    let output = run(&["239.255.77.5", "-o", path, "--dry-run"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("dry run ok"));
+    assert!(!std::path::Path::new(path).exists());
+}
+
+#[test]
+fn test_dry_run_rejects_bad_setup() {
+    let output = run(&["255.255.255.255", "--dry-run"]);
+    assert!(!output.status.success());
+
+    let output = run(&["239.255.77.5", "-o", "/nonexistent-dir/x", "--dry-run"]);
+    assert!(!output.status.success());
+
+    let output = run(&["no-such-iface0:239.255.77.5", "--dry-run"]);
+    assert!(!output.status.success());
+}
@@ -0,0 +1,203 @@
+//! The exit code contract scripts rely on:
+//! 0 clean finish, 2 usage error, 3 drops, 4 I/O error, 5 idle timeout,
+//! 6 sequence gap with --fail-on-gap, 7 a difference with --expect
+//! (tests/expect.rs).
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::{exits_with, mnc, sdds_packet, sdds_recording, temp_file};
+
+#[test]
+fn test_usage_error() {
+    exits_with(mnc().args(["239.1.1.1", "--no-such-flag"]), 2);
+    exits_with(mnc().args(["239.1.1.1", "-i", "./a", "-o", "./b"]), 2);
+    // A limit of nothing would end the run before it starts
+    for flag in ["--duration", "--idle-timeout"] {
+        exits_with(mnc().args(["239.1.1.1", flag, "0"]), 2);
+    }
+}
+
+#[test]
+fn test_count_reached() {
+    let input = temp_file("count", "one\ntwo\nthree\n");
+    let output = exits_with(
+        mnc()
+            .args(["239.1.1.1", "--local", "-o", "-", "-c", "2", "-i"])
+            .arg(&input),
+        0,
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "one\ntwo\n");
+    let _ = std::fs::remove_file(&input);
+}
+
+#[test]
+fn test_duration_elapsed() {
+    exits_with(
+        mnc().args(["239.255.77.11", "-p", "39511", "--duration", "1"]),
+        0,
+    );
+}
+
+#[test]
+fn test_io_error() {
+    let input = temp_file("io", "one\n");
+    exits_with(
+        mnc()
+            .args([
+                "239.1.1.1",
+                "--local",
+                "-o",
+                "/dev/full",
+                "--strict-disk",
+                "-i",
+            ])
+            .arg(&input),
+        4,
+    );
+    let _ = std::fs::remove_file(&input);
+}
+
+#[test]
+fn test_full_disk_drops() {
+    let input = temp_file("full", "one\n");
+    let output = exits_with(
+        mnc()
+            .args(["239.1.1.1", "--local", "-o", "/dev/full", "-i"])
+            .arg(&input),
+        3,
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 lost to a full disk"), "{stderr}");
+    let _ = std::fs::remove_file(&input);
+}
+
+#[test]
+fn test_idle_timeout() {
+    exits_with(
+        mnc().args(["239.255.77.12", "-p", "39512", "--idle-timeout", "1"]),
+        5,
+    );
+}
+
+#[test]
+fn test_drops() {
+    // The child never reads, so the writer wedges once the pipe is full and
+    // whatever is still queued at the drain timeout is lost.
+    let input = temp_file("drops", format!("{}\n", "x".repeat(999)).repeat(200));
+    exits_with(
+        mnc()
+            .args([
+                "239.1.1.1",
+                "--local",
+                "--exec",
+                "sleep 3",
+                "--duration",
+                "1",
+                "--drain-timeout",
+                "1",
+                "-i",
+            ])
+            .arg(&input),
+        3,
+    );
+    let _ = std::fs::remove_file(&input);
+}
+
+#[test]
+fn test_fail_on_gap() {
+    // 3 and 4 missing
+    let input = temp_file("gap", sdds_recording([1, 2, 5, 6]));
+    let run = |allowance: &str, code: i32| {
+        exits_with(
+            mnc()
+                .args(["239.1.1.1", "-t", "sdds", "--local", "-o", "/dev/null"])
+                .arg(allowance)
+                .arg("-i")
+                .arg(&input),
+            code,
+        )
+    };
+    let output = run("--fail-on-gap", 6);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("expected 3, got 5"), "{stderr}");
+    run("--fail-on-gap=2", 0);
+    let _ = std::fs::remove_file(&input);
+}
+
+#[test]
+fn test_fail_on_gap_needs_sequence_numbers() {
+    exits_with(
+        mnc().args(["239.255.77.47", "-p", "39547", "--fail-on-gap"]),
+        2,
+    );
+}
+
+#[test]
+fn test_max_restarts_needs_the_network() {
+    let input = temp_file("restarts", "one\n");
+    exits_with(
+        mnc()
+            .args(["239.1.1.1", "--local", "--max-restarts", "3", "-i"])
+            .arg(&input),
+        2,
+    );
+    let _ = std::fs::remove_file(&input);
+    exits_with(
+        mnc()
+            .args(["239.255.77.63", "-p", "39563", "--max-restarts", "3"])
+            .arg("--sandbox"),
+        2,
+    );
+    let output = exits_with(
+        mnc()
+            .args(["239.255.77.63", "-p", "39563", "--max-restarts", "3"])
+            .args(["--duration", "1"]),
+        0,
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(", 0 reader restarts"), "{stderr}");
+}
+
+// Gaps in live traffic end the run at once, and a clean window exits 0
+#[test]
+fn test_fail_on_gap_with_duration() {
+    let run = |port: u16, seqs: &[u16]| {
+        let mut child = mnc()
+            .args([
+                "239.255.77.47",
+                "-t",
+                "sdds",
+                "--fail-on-gap",
+                "--duration",
+                "3",
+            ])
+            .args(["-p", &port.to_string()])
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("spawn");
+        sleep(Duration::from_millis(300));
+
+        let started = Instant::now();
+        let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+        for seq in seqs {
+            sender
+                .send_to(&sdds_packet(*seq), ("239.255.77.47", port))
+                .expect("send");
+        }
+        let status = child.wait().expect("wait");
+        (status.code(), started.elapsed())
+    };
+
+    let (code, elapsed) = run(39547, &[1, 2, 3, 7, 8]);
+    assert_eq!(code, Some(6));
+    assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+
+    let (code, _) = run(39548, &[1, 2, 3, 4, 5]);
+    assert_eq!(code, Some(0));
+}
@@ -0,0 +1,86 @@
+//! --expect compares the stream with a golden file and exits 7 when they
+//! differ.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use common::{exits_with, mnc, sdds_recording, temp_file};
+
+// Send input to ourselves and compare it with golden, returning stderr
+fn compare(name: &str, input: &[u8], golden: &[u8], extra: &[&str], code: i32) -> String {
+    let input = temp_file(&format!("{name}-input"), input);
+    let golden = temp_file(&format!("{name}-golden"), golden);
+    let output = exits_with(
+        mnc()
+            .args(["239.1.1.1", "--local"])
+            .args(extra)
+            .arg("-i")
+            .arg(&input)
+            .arg("--expect")
+            .arg(&golden),
+        code,
+    );
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&golden);
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_match() {
+    let stderr = compare("match", b"one\ntwo\n", b"one\ntwo\n", &[], 0);
+    assert!(
+        stderr.contains("expect: 2 matched, 0 mismatched, 0 missing, 0 extra"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_mismatch() {
+    let stderr = compare("mismatch", b"one\ntwo\n", b"one\ntwx\n", &[], 7);
+    assert!(
+        stderr.contains("packet 2 differs from record 2 at byte 2"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("expected 74 77 78, got 74 77 6f"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_missing_and_extra() {
+    let stderr = compare("missing", b"one\n", b"one\ntwo\nthree\n", &[], 7);
+    assert!(stderr.contains("2 records never arrived"), "{stderr}");
+    let stderr = compare("extra", b"one\ntwo\n", b"one\n", &[], 7);
+    assert!(
+        stderr.contains("expect: 1 matched, 0 mismatched, 0 missing, 1 extra"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_resync() {
+    // 3 and 4 lost on the way
+    let input = sdds_recording([1, 2, 5, 6]);
+    let golden = sdds_recording([1, 2, 3, 4, 5, 6]);
+    compare("strict", &input, &golden, &["-t", "sdds"], 7);
+    let stderr = compare(
+        "resync",
+        &input,
+        &golden,
+        &["-t", "sdds", "--expect-resync"],
+        0,
+    );
+    assert!(
+        stderr.contains("expect: 4 matched, 0 mismatched, 2 missing, 0 extra"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_resync_needs_sequence_numbers() {
+    exits_with(
+        mnc().args(["239.1.1.1", "--expect", "./golden", "--expect-resync"]),
+        2,
+    );
+}
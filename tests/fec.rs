@@ -0,0 +1,126 @@
+//! --fec sends parity packets that --fec-decode rebuilds lost packets from.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::{exits_with, mnc, recording, temp_file, temp_path};
+
+// A --fec packet by hand: the header, then the payload
+fn framed(k: u8, index: u8, length: u16, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0xFE, 0xC0, k, index, 0, 0];
+    packet.extend(length.to_be_bytes());
+    packet.extend(payload);
+    packet
+}
+
+fn parity(k: u8, payloads: &[&[u8]]) -> Vec<u8> {
+    let longest = payloads.iter().map(|p| p.len()).max().unwrap_or(0);
+    let mut xor = vec![0u8; longest];
+    let mut length = 0u16;
+    for payload in payloads {
+        for (acc, byte) in xor.iter_mut().zip(*payload) {
+            *acc ^= byte;
+        }
+        length ^= payload.len() as u16;
+    }
+    framed(k, 0x80 | payloads.len() as u8, length, &xor)
+}
+
+#[test]
+fn test_decode_rebuilds_lost_packet() {
+    let payloads: [&[u8]; 4] = [b"one\n", b"two\n", b"three\n", b"four\n"];
+    // The third never arrived
+    let wire = vec![
+        framed(4, 0, 4, payloads[0]),
+        framed(4, 1, 4, payloads[1]),
+        framed(4, 3, 5, payloads[3]),
+        parity(4, &payloads),
+    ];
+    let input = temp_path("lost-input");
+    let output = temp_path("lost-output");
+    std::fs::write(&input, recording(&wire)).expect("write");
+
+    let run = exits_with(
+        mnc()
+            .args([
+                "239.1.1.1",
+                "--local",
+                "-t",
+                "binary",
+                "--output-format",
+                "raw",
+            ])
+            .args(["--fec-decode", "4", "-i"])
+            .arg(&input)
+            .arg("-o")
+            .arg(&output),
+        0,
+    );
+    let written = std::fs::read(&output).expect("read");
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+
+    assert_eq!(written, b"one\ntwo\nfour\nthree\n");
+    let stderr = String::from_utf8_lossy(&run.stderr);
+    assert!(
+        stderr.contains("fec: 3 data, 1 parity, 1 recovered, 0 unrecoverable, 0 corrupt"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("4 packets read, 4 written"), "{stderr}");
+}
+
+#[test]
+fn test_roundtrip() {
+    let output = temp_path("roundtrip-output");
+    let input = temp_file("roundtrip-input", "a\nbb\nccc\ndddd\neeeee\n");
+    let mut receiver = mnc()
+        .args([
+            "239.255.77.52",
+            "-p",
+            "39552",
+            "-c",
+            "5",
+            "--count-tx",
+            "--fec-decode",
+            "2",
+        ])
+        .arg("-o")
+        .arg(&output)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    let status = mnc()
+        .args(["239.255.77.52", "-p", "39552", "--fec", "2", "-i"])
+        .arg(&input)
+        .stderr(Stdio::null())
+        .status()
+        .expect("sender");
+    assert!(status.success());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while receiver.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        sleep(Duration::from_millis(20));
+    }
+    let _ = receiver.kill();
+
+    let written = std::fs::read_to_string(&output).expect("read");
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+    assert_eq!(written, "a\nbb\nccc\ndddd\neeeee\n");
+}
+
+#[test]
+fn test_fec_needs_a_group_to_send_to() {
+    exits_with(mnc().args(["239.1.1.1", "--fec", "4", "-o", "-"]), 2);
+    exits_with(
+        mnc().args(["239.1.1.1", "--fec-decode", "4", "--reorder", "8"]),
+        2,
+    );
+}
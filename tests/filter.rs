@@ -0,0 +1,79 @@
+//! The parity and --filter-* filters on recordings.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use common::{exits_with, mnc, recording, sdds_recording, temp_file};
+
+fn run(filter: &str) -> (usize, String) {
+    // 64 SDDS packets numbered 0 to 63: parity at 0 and 32
+    let path = temp_file(filter, sdds_recording(0..64));
+    let output = exits_with(
+        mnc()
+            .args(["239.255.77.44", "-p", "39545", "-t", "sdds", "--local"])
+            .args([filter, "-c", "40", "-o", "-", "-i"])
+            .arg(&path),
+        0,
+    );
+    let _ = std::fs::remove_file(&path);
+    (
+        output.stdout.len() / (4 + 1080),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[test]
+fn test_drop_parity_counts_before_the_filter() {
+    // -c 40 reads packets 0 to 39, two of them parity
+    let (written, stderr) = run("--drop-parity");
+    assert_eq!(written, 38);
+    assert!(
+        stderr.contains("40 packets read, 38 written, 2 filtered"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_only_parity() {
+    let (written, stderr) = run("--only-parity");
+    assert_eq!(written, 2);
+    assert!(!stderr.contains("never written"), "{stderr}");
+}
+
+#[test]
+fn test_parity_needs_sdds() {
+    exits_with(
+        mnc().args(["239.255.77.44", "-p", "39545", "--drop-parity"]),
+        2,
+    );
+}
+
+#[test]
+fn test_filter_stream_id() {
+    // Stream 1, stream 2, and a frame too short to have a VRT header
+    let input = recording(&[vrt_frame(1), vrt_frame(2), b"VRLP\x00\x10\x00\x00".to_vec()]);
+    let path = temp_file("stream-id", input);
+
+    let output = exits_with(
+        mnc()
+            .args(["239.255.77.44", "-p", "39545", "-t", "vita49", "--local"])
+            .args(["--filter-stream-id", "0x1", "-o", "-", "-i"])
+            .arg(&path),
+        0,
+    );
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(output.stdout.len(), 4 + 20);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("3 packets read, 1 written, 1 filtered, 1 unparsed"),
+        "{stderr}"
+    );
+}
+
+fn vrt_frame(stream_id: u32) -> Vec<u8> {
+    let mut frame = b"VRLP\x00\x10\x00\x00".to_vec();
+    for word in [(1 << 28) | 3, stream_id, 0] {
+        frame.extend(u32::to_be_bytes(word));
+    }
+    frame
+}
@@ -0,0 +1,57 @@
+//! --heartbeat sends on its own, with no input to read.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::{mnc, temp_path};
+
+#[test]
+fn test_heartbeat_without_input() {
+    let path = temp_path("heartbeat");
+    let output = path.to_str().expect("utf8 path");
+    let mut receiver = mnc()
+        .args(["239.255.77.50", "-p", "39550", "-c", "3", "-o", output])
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    // -c counts heartbeats sent, so this stops by itself
+    let start = Instant::now();
+    let status = mnc()
+        .args(["239.255.77.50", "-p", "39550", "-c", "3"])
+        .args(["--heartbeat", "200ms:beat {seq}"])
+        .stderr(Stdio::null())
+        .status()
+        .expect("heartbeat");
+    assert!(status.success());
+    assert!(start.elapsed() >= Duration::from_millis(400));
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while receiver.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        sleep(Duration::from_millis(20));
+    }
+    let _ = receiver.kill();
+
+    let written = std::fs::read_to_string(&path).expect("read");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(written, "beat 0\nbeat 1\nbeat 2\n");
+}
+
+#[test]
+fn test_heartbeat_stops_at_duration() {
+    let start = Instant::now();
+    let status = mnc()
+        .args(["239.255.77.51", "-p", "39551", "--duration", "1"])
+        .args(["--heartbeat", "100ms"])
+        .stderr(Stdio::null())
+        .status()
+        .expect("heartbeat");
+    assert!(status.success());
+    assert!(start.elapsed() < Duration::from_secs(4));
+}
@@ -0,0 +1,55 @@
+//! --loop reads the -i file again each time it ends.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::{Output, Stdio};
+
+use common::{mnc, temp_file};
+
+const GROUP: &str = "239.255.77.83";
+
+fn run(name: &str, contents: &str, args: &[&str]) -> Output {
+    let path = temp_file(&format!("loop-{name}"), contents);
+    let output = mnc()
+        .args([GROUP, "--local", "-t", "text", "-o", "-", "-i"])
+        .arg(&path)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .expect("mnc");
+    let _ = std::fs::remove_file(path);
+    output
+}
+
+#[test]
+fn test_loop_passes() {
+    let output = run("three", "a\nb\n", &["--loop=3"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "a\nb\na\nb\na\nb\n"
+    );
+
+    // -c stops it part way through a pass, forever included
+    let output = run("count", "a\nb\n", &["--loop", "-c", "5"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a\nb\na\nb\na\n");
+
+    let output = run("duration", "a\nb\n", &["--loop=0", "--duration", "1"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stdout.len() > 1000, "{}", output.stdout.len());
+
+    // A file with nothing in it ends rather than spinning
+    let output = run("empty", "", &["--loop"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_loop_needs_a_file() {
+    for args in [&[GROUP, "-i", "-", "--loop"][..], &[GROUP, "--loop=2"]] {
+        let output = mnc().args(args).stdin(Stdio::null()).output().expect("mnc");
+        assert_eq!(output.status.code(), Some(2), "{args:?}: {output:?}");
+    }
+}
@@ -0,0 +1,83 @@
+//! A relay doesn't forward its own packets when they come back to it.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::mnc;
+
+fn send(group: &str, port: u16, count: usize) {
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    for i in 0..count {
+        sender
+            .send_to(format!("packet {i}").as_bytes(), (group, port))
+            .expect("send");
+        sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn test_relay_into_own_group_refused() {
+    let output = mnc()
+        .args(["239.255.77.70", "-p", "39575", "--tx=239.255.77.70"])
+        .output()
+        .expect("mnc");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2), "{stderr}");
+    assert!(stderr.contains("--allow-loop"), "{stderr}");
+}
+
+#[test]
+fn test_own_packets_dropped() {
+    let relay = mnc()
+        .args(["239.255.77.70", "-p", "39575", "--tx=239.255.77.70"])
+        .args(["--allow-loop", "--duration", "2"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    sleep(Duration::from_millis(300));
+    send("239.255.77.70", 39575, 10);
+
+    let output = relay.wait_with_output().expect("wait");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("sent from our own socket"), "{stderr}");
+    assert!(
+        stderr.contains("10 packets read, 10 written, 10 of our own dropped"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_loop_through_another_relay() {
+    // Out one group with a marker, and back in through a relay that doesn't know
+    let guarded = mnc()
+        .args(["239.255.77.71", "-p", "39576", "--tx=239.255.77.72"])
+        .args(["--loop-guard", "0xfeed", "--duration", "2"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    let plain = mnc()
+        .args(["239.255.77.72", "-p", "39576", "--tx=239.255.77.71"])
+        .args(["--duration", "2"])
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("spawn");
+    sleep(Duration::from_millis(300));
+    send("239.255.77.71", 39576, 5);
+
+    let output = guarded.wait_with_output().expect("wait");
+    plain.wait_with_output().expect("wait");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("carrying our --loop-guard marker"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("5 packets read, 5 written, 5 of our own dropped"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,136 @@
+//! --mark-gaps writes a marker record where SDDS sequence numbers skip, and
+//! reading the recording back leaves the markers out.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::fs;
+use std::net::UdpSocket;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::{mnc, sdds_packet, temp_path};
+
+/// Records in a binary recording: the length word, then the payload for
+/// those that aren't markers.
+fn records(recording: &[u8]) -> Vec<(u32, usize)> {
+    let mut records = Vec::new();
+    let mut rest = recording;
+    while let Some((word, after)) = rest.split_first_chunk::<4>() {
+        let word = u32::from_le_bytes(*word);
+        let length = if word & 0x8000_0000 == 0 {
+            word as usize
+        } else {
+            0
+        };
+        records.push((word, length));
+        rest = after.get(length..).unwrap_or_default();
+    }
+    records
+}
+
+#[test]
+fn test_markers_written_and_skipped_on_replay() {
+    let capture = temp_path("capture.bin");
+    let capture_name = capture.to_str().expect("path");
+    let child = mnc()
+        .args([
+            "239.255.77.73",
+            "-p",
+            "39577",
+            "-t",
+            "sdds",
+            "--mark-gaps",
+            "-c",
+            "4",
+            "-o",
+            capture_name,
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    sleep(Duration::from_millis(300));
+
+    // 3 and 4 never sent
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    for seq in [1, 2, 5, 6] {
+        sender
+            .send_to(&sdds_packet(seq), "239.255.77.73:39577")
+            .expect("send");
+        sleep(Duration::from_millis(10));
+    }
+
+    let output = child.wait_with_output().expect("wait");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(
+        stderr.contains(&format!(
+            "gap markers: 1 for 2 missing packets, at {capture_name} record 3"
+        )),
+        "{stderr}"
+    );
+    let recording = fs::read(&capture).expect("recording");
+    assert_eq!(
+        records(&recording),
+        [
+            (1080, 1080),
+            (1080, 1080),
+            (0x8000_0002, 0),
+            (1080, 1080),
+            (1080, 1080)
+        ]
+    );
+
+    // Read back, only the packets come out
+    let copy = temp_path("copy.bin");
+    let output = mnc()
+        .args([
+            "239.255.77.73",
+            "-p",
+            "39578",
+            "-t",
+            "sdds",
+            "--local",
+            "-i",
+            capture_name,
+            "-o",
+            copy.to_str().expect("path"),
+        ])
+        .output()
+        .expect("mnc");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("4 packets read, 4 written"), "{stderr}");
+    let copied = fs::read(&copy).expect("copy");
+    assert_eq!(records(&copied), [(1080, 1080); 4]);
+
+    let _ = fs::remove_file(capture);
+    let _ = fs::remove_file(copy);
+}
+
+#[test]
+fn test_mark_gaps_needs_binary_output() {
+    let output = mnc()
+        .args([
+            "239.255.77.73",
+            "-p",
+            "39577",
+            "-t",
+            "sdds",
+            "--mark-gaps",
+            "-o",
+            "capture.jsonl",
+            "--output-format",
+            "jsonl",
+        ])
+        .output()
+        .expect("mnc");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("--mark-gaps needs binary or SigMF output"),
+        "{stderr}"
+    );
+}
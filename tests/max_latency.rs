@@ -0,0 +1,68 @@
+//! --max-latency pushes what an output file buffers out to it while the
+//! stream carries on, rather than when the buffer fills or the stream ends.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Child, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::{mnc, temp_path};
+
+fn start(output: &Path, args: &[&str]) -> Child {
+    let mut child = mnc()
+        .args(["239.255.77.86", "-p", "39593", "--local", "-i", "-", "-o"])
+        .arg(output)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("spawn");
+    child
+        .stdin
+        .as_mut()
+        .expect("stdin")
+        .write_all(b"hello\n")
+        .expect("write");
+    child
+}
+
+// What the file holds once it holds anything, or after a second
+fn written(output: &Path) -> String {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    loop {
+        let written = std::fs::read_to_string(output).unwrap_or_default();
+        if !written.is_empty() || Instant::now() > deadline {
+            return written;
+        }
+        sleep(Duration::from_millis(20));
+    }
+}
+
+fn finish(mut child: Child) {
+    drop(child.stdin.take());
+    assert!(child.wait().expect("wait").success());
+}
+
+#[test]
+fn test_max_latency_flushes_output_files() {
+    let bounded = temp_path("max-latency");
+    let child = start(&bounded, &["--max-latency", "50"]);
+    assert_eq!(written(&bounded), "hello\n");
+    finish(child);
+    let _ = std::fs::remove_file(&bounded);
+
+    // Otherwise the line waits for the buffer to fill or the stream to end
+    let unbounded = temp_path("max-latency-off");
+    let child = start(&unbounded, &[]);
+    assert_eq!(written(&unbounded), "");
+    finish(child);
+    assert_eq!(
+        std::fs::read_to_string(&unbounded).expect("read"),
+        "hello\n"
+    );
+    let _ = std::fs::remove_file(&unbounded);
+}
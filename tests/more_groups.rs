@@ -0,0 +1,97 @@
+//! Several groups received at once, each with its own port and packet type,
+//! parsed and counted apart on the stats line.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::{mnc, sdds_packet};
+
+const SDDS_GROUP: &str = "239.255.77.76:39582";
+const VITA49_GROUP: &str = "239.255.77.77:39583";
+
+fn vita49_packet(seq: u32) -> Vec<u8> {
+    let mut packet = b"VRLP".to_vec();
+    packet.extend((((seq % 4096) << 20) | 8).to_be_bytes());
+    packet.extend([0; 24]);
+    packet
+}
+
+#[test]
+fn test_each_group_parsed_as_its_own_type() {
+    let child = mnc()
+        .args([
+            &format!("{SDDS_GROUP}?type=sdds"),
+            &format!("{VITA49_GROUP}?type=vita49"),
+            "-s",
+            "-c",
+            "80",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    sleep(Duration::from_millis(300));
+
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut sent = 0;
+    while sent < 40 && Instant::now() < deadline {
+        sender
+            .send_to(&sdds_packet(sent as u16 + 1), SDDS_GROUP)
+            .expect("send");
+        sender
+            .send_to(&vita49_packet(sent), VITA49_GROUP)
+            .expect("send");
+        sent += 1;
+        sleep(Duration::from_millis(40));
+    }
+
+    let output = child.wait_with_output().expect("wait");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("80 packets read"), "{stderr}");
+    let line = stderr
+        .lines()
+        .find(|line| line.contains(&format!("| {SDDS_GROUP} sdds packets: ")))
+        .expect("stats line");
+    // Read as one stream, the VITA-49 packets would be SDDS sequence gaps
+    let sdds = line
+        .split("  | ")
+        .find(|group| group.starts_with(SDDS_GROUP))
+        .expect("sdds group");
+    assert!(sdds.contains("skipped: 0"), "{line}");
+    assert!(
+        line.contains(&format!("| {VITA49_GROUP} vita49 packets: ")),
+        "{line}"
+    );
+}
+
+#[test]
+fn test_several_groups_refuse_single_stream_options() {
+    for args in [
+        &[
+            "239.255.77.76",
+            "239.255.77.77",
+            "--reorder",
+            "8",
+            "-t",
+            "sdds",
+        ][..],
+        &["239.255.77.76", "239.255.77.77", "-i", "-"],
+        &[
+            "239.255.77.76?type=vita49",
+            "239.255.77.77",
+            "-t",
+            "sdds",
+            "--drop-parity",
+        ],
+    ] {
+        let output = mnc().args(args).stdin(Stdio::null()).output().expect("mnc");
+        assert_eq!(output.status.code(), Some(2), "{args:?}: {output:?}");
+    }
+}
@@ -0,0 +1,112 @@
+//! -o and -i files are laid out as their extensions say, unless
+//! --output-format or --input-format says otherwise.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Output;
+
+use common::{mnc, recording, temp_file, temp_path};
+
+fn replay(args: &[&str]) -> Output {
+    let output = mnc()
+        .args(["239.255.77.75", "-p", "39580", "--local"])
+        .args(args)
+        .output()
+        .expect("mnc");
+    assert!(output.status.success(), "{output:?}");
+    output
+}
+
+fn path(file: &Path) -> &str {
+    file.to_str().expect("path")
+}
+
+#[test]
+fn test_formats_from_extensions() {
+    // Text packets, but length prefixed as .bin says
+    let input = temp_file(
+        "input.bin",
+        recording(&[b"one\n".to_vec(), b"two\n".to_vec()]),
+    );
+    let jsonl = temp_path("copy.jsonl");
+    let text = temp_path("copy.TXT");
+
+    let output = replay(&["-i", path(&input), "-o", path(&jsonl), "-o", path(&text)]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in [
+        format!("reading {} as binary, from its extension", path(&input)),
+        format!("writing {} as jsonl, from its extension", path(&jsonl)),
+        format!("writing {} as text, from its extension", path(&text)),
+    ] {
+        assert!(stderr.contains(&line), "{stderr}");
+    }
+    assert_eq!(fs::read_to_string(&text).expect("text"), "one\ntwo\n");
+    let lines = fs::read_to_string(&jsonl).expect("jsonl");
+    assert_eq!(lines.lines().count(), 2, "{lines}");
+    assert!(
+        lines.lines().all(|line| line.contains("\"payload_b64\"")),
+        "{lines}"
+    );
+
+    for file in [input, jsonl, text] {
+        let _ = fs::remove_file(file);
+    }
+}
+
+#[test]
+fn test_output_format_overrides_extension() {
+    let input = temp_path("override-input");
+    fs::write(&input, "one\ntwo\n").expect("write");
+    let copy = temp_path("override.jsonl");
+
+    let output = replay(&[
+        "-i",
+        path(&input),
+        "-o",
+        path(&copy),
+        "--output-format",
+        "binary",
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("from its extension"), "{stderr}");
+    assert_eq!(
+        fs::read(&copy).expect("copy"),
+        [&[4, 0, 0, 0][..], b"one\n", &[4, 0, 0, 0], b"two\n"].concat()
+    );
+
+    let _ = fs::remove_file(input);
+    let _ = fs::remove_file(copy);
+}
+
+#[test]
+fn test_unknown_extensions_fall_back() {
+    let input = temp_path("fallback-input");
+    fs::write(&input, "one\ntwo\n").expect("write");
+    let dat = temp_path("fallback.dat");
+    let pcap = temp_path("fallback.pcapng");
+
+    let output = replay(&["-i", path(&input), "-o", path(&dat), "-o", path(&pcap)]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    for line in [
+        format!(
+            "{}: .dat isn't an extension mnc knows, writing it as text",
+            path(&dat)
+        ),
+        format!(
+            "{}: mnc doesn't write pcapng files, writing it as text",
+            path(&pcap)
+        ),
+    ] {
+        assert!(stderr.contains(&line), "{stderr}");
+    }
+    for file in [&dat, &pcap] {
+        assert_eq!(fs::read_to_string(file).expect("copy"), "one\ntwo\n");
+    }
+
+    for file in [input, dat, pcap] {
+        let _ = fs::remove_file(file);
+    }
+}
@@ -0,0 +1,82 @@
+//! --patch rewrites the packets sent, and only those.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::{mnc, temp_path};
+
+#[test]
+fn test_patch_every_other_packet() {
+    let input = temp_path("patch-input");
+    let output = temp_path("patch-output");
+    std::fs::write(&input, "aaaa\nbbbb\ncccc\ndddd\n").expect("write");
+    let mut receiver = mnc()
+        .args(["239.255.77.53", "-p", "39553", "-c", "4", "-o"])
+        .arg(&output)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    let sender = mnc()
+        .args(["239.255.77.53", "-p", "39553"])
+        .args(["--patch", "0:58:every=2", "--patch", "4:5a5a", "-i"])
+        .arg(&input)
+        .output()
+        .expect("sender");
+    assert!(sender.status.success());
+    // The last patch runs past the end of every packet
+    let stderr = String::from_utf8_lossy(&sender.stderr);
+    assert!(stderr.contains("patch: 2 applied, 4 skipped"), "{stderr}");
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while receiver.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        sleep(Duration::from_millis(20));
+    }
+    let _ = receiver.kill();
+
+    let written = std::fs::read_to_string(&output).expect("read");
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+    assert_eq!(written, "aaaa\nXbbb\ncccc\nXddd\n");
+}
+
+#[test]
+fn test_patch_extend() {
+    let input = temp_path("patch-extend-input");
+    let output = temp_path("patch-extend-output");
+    std::fs::write(&input, "ab\n").expect("write");
+    let mut receiver = mnc()
+        .args(["239.255.77.54", "-p", "39554", "-c", "1", "-o"])
+        .arg(&output)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    let status = mnc()
+        .args(["239.255.77.54", "-p", "39554"])
+        .args(["--patch", "4:21", "--patch-extend", "-i"])
+        .arg(&input)
+        .stderr(Stdio::null())
+        .status()
+        .expect("sender");
+    assert!(status.success());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while receiver.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        sleep(Duration::from_millis(20));
+    }
+    let _ = receiver.kill();
+
+    let written = std::fs::read(&output).expect("read");
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+    assert_eq!(written, b"ab\n\0!\n");
+}
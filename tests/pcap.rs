@@ -0,0 +1,215 @@
+//! --output-format pcap, or a .pcap -o, writes a capture Wireshark reads,
+//! each payload in made up headers with the group and port it was sent to.
+//! A .pcap -i, or --input-format pcap, replays the UDP payloads of one.
+#![allow(clippy::expect_used, clippy::indexing_slicing)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::{mnc, temp_file, temp_path};
+
+const GROUP: &str = "239.255.77.81";
+const PORT: u16 = 39588;
+
+/// (orig_len, frame) of each record, after checking the file header.
+fn records(capture: &[u8]) -> Vec<(u32, &[u8])> {
+    let u32_at = |at: usize| u32::from_le_bytes(capture[at..at + 4].try_into().expect("u32"));
+    assert_eq!(u32_at(0), 0xa1b2_c3d4);
+    assert_eq!(u32_at(20), 1, "Ethernet link type");
+    let mut records = Vec::new();
+    let mut at = 24;
+    while at < capture.len() {
+        let kept = u32_at(at + 8) as usize;
+        records.push((u32_at(at + 12), &capture[at + 16..at + 16 + kept]));
+        at += 16 + kept;
+    }
+    assert_eq!(at, capture.len());
+    records
+}
+
+#[test]
+fn test_capture_round_trip() {
+    let path = temp_path("pcap.pcap");
+    let receiver = mnc()
+        .arg(GROUP)
+        .args(["-p", &PORT.to_string(), "-c", "3", "--duration", "10", "-o"])
+        .arg(&path)
+        .spawn()
+        .expect("spawn");
+    sleep(Duration::from_millis(300));
+
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    for payload in [&b"one"[..], b"two", b"three"] {
+        sender.send_to(payload, (GROUP, PORT)).expect("send");
+        sleep(Duration::from_millis(20));
+    }
+    let output = receiver.wait_with_output().expect("wait");
+    assert!(output.status.success(), "{output:?}");
+
+    let capture = std::fs::read(&path).expect("capture");
+    let _ = std::fs::remove_file(&path);
+    let records = records(&capture);
+    assert_eq!(records.len(), 3);
+    for ((orig_len, frame), payload) in records.iter().zip([&b"one"[..], b"two", b"three"]) {
+        assert_eq!(*orig_len as usize, frame.len());
+        assert_eq!(&frame[12..14], &[0x08, 0x00], "IPv4");
+        assert_eq!(frame[23], 17, "UDP");
+        assert_eq!(&frame[30..34], &[239, 255, 77, 81]);
+        assert_eq!(u16::from_be_bytes([frame[36], frame[37]]), PORT);
+        assert_eq!(&frame[42..], payload);
+    }
+}
+
+#[test]
+fn test_truncated_datagram_keeps_original_length() {
+    let path = temp_path("pcap-cut.pcap");
+    let receiver = mnc()
+        .arg(GROUP)
+        .args([
+            "-p",
+            "39594",
+            "--max-packet",
+            "16",
+            "-c",
+            "1",
+            "--duration",
+            "10",
+            "-o",
+        ])
+        .arg(&path)
+        .spawn()
+        .expect("spawn");
+    sleep(Duration::from_millis(300));
+
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    sender.send_to(&[7; 100], (GROUP, 39594)).expect("send");
+    let output = receiver.wait_with_output().expect("wait");
+    assert!(output.status.success(), "{output:?}");
+
+    let capture = std::fs::read(&path).expect("capture");
+    let _ = std::fs::remove_file(&path);
+    let records = records(&capture);
+    assert_eq!(records.len(), 1);
+    let (orig_len, frame) = records[0];
+    // The frame holds what fit, its lengths are the datagram's
+    assert_eq!(frame.len(), 42 + 16);
+    assert_eq!(&frame[42..], &[7; 16]);
+    assert_eq!(orig_len, 42 + 100);
+    assert_eq!(u16::from_be_bytes([frame[16], frame[17]]), 20 + 8 + 100);
+    assert_eq!(u16::from_be_bytes([frame[38], frame[39]]), 8 + 100);
+}
+
+#[test]
+fn test_replay_to_pcap_has_header_without_packets() {
+    let input = temp_path("pcap-empty");
+    let capture = temp_path("pcap-empty.out");
+    std::fs::write(&input, "").expect("write");
+    let output = mnc()
+        .args([
+            GROUP,
+            "-p",
+            "39588",
+            "--local",
+            "--output-format",
+            "pcap",
+            "-i",
+        ])
+        .arg(&input)
+        .arg("-o")
+        .arg(&capture)
+        .output()
+        .expect("mnc");
+    assert!(output.status.success(), "{output:?}");
+
+    let written = std::fs::read(&capture).expect("capture");
+    let _ = std::fs::remove_file(input);
+    let _ = std::fs::remove_file(capture);
+    assert!(records(&written).is_empty());
+}
+
+/// A little-endian, microsecond pcap of Ethernet frames.
+fn capture_of(frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut capture = Vec::new();
+    for field in [0xa1b2_c3d4_u32, 0x0004_0002, 0, 0, 65535, 1] {
+        capture.extend_from_slice(&field.to_le_bytes());
+    }
+    for frame in frames {
+        let len = u32::try_from(frame.len()).expect("len");
+        for field in [1_u32, 0, len, len] {
+            capture.extend_from_slice(&field.to_le_bytes());
+        }
+        capture.extend_from_slice(frame);
+    }
+    capture
+}
+
+/// An Ethernet frame, VLAN tagged when vlan, of an IPv4 packet of protocol.
+fn frame(vlan: bool, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x01, 0x00, 0x5e, 0x7f, 0x4d, 0x51, 0, 0, 0, 0, 0, 1];
+    if vlan {
+        frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x07]);
+    }
+    frame.extend_from_slice(&[0x08, 0x00]);
+    let udp_len = u16::try_from(8 + payload.len()).expect("len");
+    let total = (20 + udp_len).to_be_bytes();
+    frame.extend_from_slice(&[0x45, 0, total[0], total[1], 0, 0, 0, 0, 1, protocol, 0, 0]);
+    frame.extend_from_slice(&[10, 0, 0, 1, 239, 255, 77, 81]);
+    let udp_len = udp_len.to_be_bytes();
+    frame.extend_from_slice(&[0x9c, 0x40, 0x9a, 0x94, udp_len[0], udp_len[1], 0, 0]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn replay(capture: &[u8], name: &str, extra: &[&str]) -> std::process::Output {
+    let path = temp_file(&format!("pcap-{name}"), capture);
+    let output = mnc()
+        .args([GROUP, "-p", "39588", "--local", "-o", "-", "-i"])
+        .arg(&path)
+        .args(extra)
+        .output()
+        .expect("mnc");
+    let _ = std::fs::remove_file(path);
+    output
+}
+
+#[test]
+fn test_replay_udp_payloads() {
+    let mut arp = vec![0xff; 6];
+    arp.extend_from_slice(&[0, 0, 0, 0, 0, 1, 0x08, 0x06]);
+    arp.extend_from_slice(&[0; 28]);
+    let capture = capture_of(&[
+        frame(false, 17, b"one\n"),
+        frame(true, 17, b"two\n"),
+        frame(false, 6, b"not udp\n"),
+        arp,
+        frame(false, 17, b"three\n"),
+    ]);
+
+    let output = replay(&capture, "replay.pcap", &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "one\ntwo\nthree\n");
+    assert!(stderr.contains("skipped 2 pcap records"), "{stderr}");
+
+    let output = replay(&capture, "replay.pcap", &["-c", "2"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "one\ntwo\n");
+
+    // Without the extension, only --input-format reads it as a capture
+    let output = replay(&capture, "replay", &["--input-format", "pcap"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "one\ntwo\nthree\n");
+}
+
+#[test]
+fn test_replay_refuses_pcapng() {
+    let output = replay(&[0x0a, 0x0d, 0x0d, 0x0a, 0, 0, 0, 0], "capture.pcap", &[]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("pcapng"),
+        "{output:?}"
+    );
+}
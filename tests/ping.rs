@@ -0,0 +1,66 @@
+//! --ping against --echo, on one host.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::thread::sleep;
+use std::time::Duration;
+
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
+
+use common::piped;
+
+#[test]
+fn test_ping_is_answered_on_a_reply_group() {
+    let echo = piped(&[
+        "239.255.77.27",
+        "-p",
+        "39529",
+        "--echo",
+        "--reply-group",
+        "239.255.77.28",
+    ])
+    .spawn()
+    .expect("spawn echo");
+    sleep(Duration::from_millis(300));
+
+    let ping = piped(&[
+        "239.255.77.27",
+        "-p",
+        "39529",
+        "--ping",
+        "-c",
+        "2",
+        "--reply-group",
+        "239.255.77.28",
+    ])
+    .output()
+    .expect("ping");
+
+    kill(Pid::from_raw(echo.id() as i32), Signal::SIGINT).expect("SIGINT");
+    let echo = echo.wait_with_output().expect("wait");
+
+    let log = String::from_utf8_lossy(&ping.stderr);
+    assert_eq!(ping.status.code(), Some(0), "{log}");
+    assert!(log.contains("seq=1 time="), "{log}");
+    assert!(
+        log.contains("2 probes sent, 2 answered, 0.0% loss"),
+        "{log}"
+    );
+    assert!(echo.status.success(), "{echo:?}");
+}
+
+#[test]
+fn test_unanswered_ping_exits_as_drops() {
+    let ping = piped(&["239.255.77.30", "-p", "39530", "--ping", "-c", "1"])
+        .output()
+        .expect("ping");
+
+    let log = String::from_utf8_lossy(&ping.stderr);
+    assert_eq!(ping.status.code(), Some(3), "{log}");
+    assert!(
+        log.contains("1 probes sent, 0 answered, 100.0% loss"),
+        "{log}"
+    );
+}
@@ -0,0 +1,83 @@
+//! --pps paces what's sent to a steady number of packets a second.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::net::{Ipv4Addr, UdpSocket};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use common::{mnc, temp_path};
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 77, 84);
+const PORT: u16 = 39591;
+
+/// The rate packets arrived at with mnc sending count of them at pps.
+fn measured_rate(pps: u64, count: u64) -> f64 {
+    let receiver = UdpSocket::bind(("0.0.0.0", PORT)).expect("bind");
+    receiver
+        .join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)
+        .expect("join");
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .expect("timeout");
+
+    let input = temp_path(&format!("pps-{pps}"));
+    let lines: String = (0..count).map(|n| format!("{n}\n")).collect();
+    std::fs::write(&input, lines).expect("write");
+    let mut sender = mnc()
+        .arg(GROUP.to_string())
+        .args(["-p", &PORT.to_string(), "--pps", &pps.to_string(), "-i"])
+        .arg(&input)
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("spawn");
+
+    let mut buf = [0u8; 64];
+    let mut arrivals = Vec::new();
+    while receiver.recv(&mut buf).is_ok() {
+        arrivals.push(Instant::now());
+        if arrivals.len() as u64 == count {
+            break;
+        }
+    }
+    assert!(sender.wait().expect("wait").success());
+    let _ = std::fs::remove_file(input);
+
+    assert!(arrivals.len() as u64 > count * 9 / 10, "{}", arrivals.len());
+    // The bucket starts full, so the first tenth goes out as a burst and
+    // the rate is measured over the rest
+    let steady = arrivals.get(arrivals.len() / 10..).unwrap_or_default();
+    let (Some(first), Some(last)) = (steady.first(), steady.last()) else {
+        return 0.0;
+    };
+    (steady.len() - 1) as f64 / last.duration_since(*first).as_secs_f64()
+}
+
+#[test]
+fn test_pps_holds_the_rate() {
+    // A second's worth at each, one after the other on the same port
+    for pps in [20, 5000] {
+        let rate = measured_rate(pps, pps + 1);
+        assert!(
+            (rate - pps as f64).abs() < pps as f64 * 0.05,
+            "{rate:.1} pkt/s at --pps {pps}"
+        );
+    }
+}
+
+#[test]
+fn test_pps_burst_needs_pps_pacing() {
+    for args in [
+        &["--pps-burst", "10", "-i", "/dev/null"][..],
+        &["--pps", "10", "--pps-burst", "0", "-i", "/dev/null"],
+        &["--pps", "10", "-r", "100", "-i", "/dev/null"],
+    ] {
+        let output = mnc()
+            .arg(GROUP.to_string())
+            .args(args)
+            .output()
+            .expect("mnc");
+        assert_eq!(output.status.code(), Some(2), "{args:?}: {output:?}");
+    }
+}
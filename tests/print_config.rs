@@ -0,0 +1,70 @@
+//! --print-config-json shows what the flags came to, without joining.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use serde_json::{Value, json};
+
+use common::mnc;
+
+fn print_config(args: &[&str]) -> Value {
+    let output = mnc()
+        .args(args)
+        .arg("--print-config-json")
+        .output()
+        .expect("mnc");
+    assert!(output.status.success(), "{output:?}");
+    serde_json::from_slice(&output.stdout).expect("json")
+}
+
+fn field<'a>(config: &'a Value, name: &str) -> &'a Value {
+    config.get(name).unwrap_or(&Value::Null)
+}
+
+#[test]
+fn test_receive_settings() {
+    let config = print_config(&[
+        "239.255.77.69",
+        "-p",
+        "5000-5001",
+        "-t",
+        "sdds",
+        "-o",
+        "capture.jsonl",
+        "--output-format",
+        "jsonl",
+        "-c",
+        "10",
+        "--duration",
+        "5",
+    ]);
+    assert_eq!(*field(&config, "group"), "239.255.77.69");
+    assert_eq!(*field(&config, "ports"), json!([5000, 5001]));
+    assert_eq!(*field(&config, "packet_type"), "sdds");
+    assert_eq!(
+        *field(&config, "outputs"),
+        json!([{"sink": "capture.jsonl", "format": "jsonl"}])
+    );
+    assert_eq!(
+        *field(&config, "limits"),
+        json!({"count": 10, "duration_secs": 5, "rate": null, "pps": null})
+    );
+    assert!(
+        field(field(&config, "rcvbuf"), "granted")
+            .as_u64()
+            .expect("granted")
+            > 0
+    );
+}
+
+#[test]
+fn test_send_settings() {
+    let config = print_config(&["239.255.77.69", "-p", "5000", "-i", "-", "--tx"]);
+    assert_eq!(*field(&config, "group"), Value::Null);
+    assert_eq!(*field(&config, "input"), "-");
+    assert_eq!(*field(&config, "rcvbuf"), Value::Null);
+    assert_eq!(
+        *field(&config, "outputs"),
+        json!([{"sink": "239.255.77.69:5000", "format": null}])
+    );
+}
@@ -0,0 +1,90 @@
+//! --speed scales the gaps of a timed replay.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::io::Write;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use common::mnc;
+
+// How long a capture takes to replay at speed. From stdin, as the progress
+// shown for files would add its own interval to the run.
+fn replay(capture: &str, speed: &str) -> Duration {
+    let started = Instant::now();
+    let mut child = mnc()
+        .args([
+            "239.255.77.38",
+            "-p",
+            "39539",
+            "-t",
+            "binary",
+            "--local",
+            "-o",
+            "/dev/null",
+            "--input-format",
+            "jsonl",
+            "--speed",
+            speed,
+            "-i",
+            "-",
+        ])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("spawn");
+    child
+        .stdin
+        .take()
+        .expect("stdin")
+        .write_all(capture.as_bytes())
+        .expect("write");
+    assert!(child.wait().expect("wait").success());
+    started.elapsed()
+}
+
+#[test]
+fn test_speed_halves_the_replay() {
+    // Two seconds of packets, 100ms apart
+    let capture: String = (0..=20)
+        .map(|n| {
+            format!(
+                "{{\"payload_b64\": \"aGk=\", \"timestamp_us\": {}}}\n",
+                n * 100_000
+            )
+        })
+        .collect();
+
+    let normal = replay(&capture, "1").as_secs_f64();
+    let double = replay(&capture, "2").as_secs_f64();
+    assert!(normal >= 2.0, "{normal}");
+    assert!(double >= 1.0, "{double}");
+    assert!((double / normal - 0.5).abs() < 0.1, "{double} / {normal}");
+}
+
+#[test]
+fn test_speed_zero_ignores_the_timing() {
+    let capture: String = (0..=20)
+        .map(|n| {
+            format!(
+                "{{\"payload_b64\": \"aGk=\", \"timestamp_us\": {}}}\n",
+                n * 100_000
+            )
+        })
+        .collect();
+    let replayed = replay(&capture, "0").as_secs_f64();
+    assert!(replayed < 1.0, "{replayed}");
+}
+
+#[test]
+fn test_speed_must_not_be_negative() {
+    for speed in ["-2", "inf"] {
+        let status = mnc()
+            .args(["239.255.77.38", "-p", "39539", "--speed", speed])
+            .stderr(Stdio::null())
+            .status()
+            .expect("run");
+        assert_eq!(status.code(), Some(2), "{speed}");
+    }
+}
@@ -0,0 +1,96 @@
+//! --sandbox runs a whole receive-to-file cycle without tripping its own
+//! seccomp filter, which would kill mnc with SIGSYS.
+#![allow(clippy::expect_used)]
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
+
+use common::{mnc, temp_path};
+
+#[test]
+fn test_receive_to_file_sandboxed() {
+    let input = temp_path("sandbox-input");
+    let output = temp_path("sandbox-output");
+    let dump = temp_path("sandbox-dump");
+    // 30 packets over a second and a half, so a stats line comes due
+    let lines: String = (0..30)
+        .map(|_| "{\"payload_b64\": \"cGluZw==\", \"delay_us\": 50000}\n")
+        .collect();
+    std::fs::write(&input, lines).expect("write");
+
+    // Statistics and hex dumps too, so all three pipeline threads are
+    // sandboxed
+    let mut receiver = mnc()
+        .args(["239.255.77.59", "-p", "39559", "-c", "30", "-s", "-v"])
+        .arg("--dump-output")
+        .arg(&dump)
+        .arg("-o")
+        .arg(&output)
+        .arg("--sandbox")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    let mut sender = mnc()
+        .args(["239.255.77.59", "-p", "39559", "--input-format", "jsonl"])
+        .arg("-i")
+        .arg(&input)
+        .arg("--sandbox")
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("sender");
+
+    // Signals are handled as usual, only SIGHUP no longer reopens -o
+    sleep(Duration::from_millis(300));
+    let pid = Pid::from_raw(receiver.id() as i32);
+    kill(pid, Signal::SIGUSR1).expect("SIGUSR1");
+    kill(pid, Signal::SIGHUP).expect("SIGHUP");
+
+    let sent = sender.wait().expect("sender");
+    assert_eq!(sent.signal(), None);
+    assert!(sent.success());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while receiver.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        sleep(Duration::from_millis(20));
+    }
+    let _ = receiver.kill();
+    let received = receiver.wait_with_output().expect("receiver");
+
+    let written = std::fs::read_to_string(&output).expect("read");
+    let dumped = std::fs::read_to_string(&dump).expect("read dump");
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+    let _ = std::fs::remove_file(&dump);
+    let stderr = String::from_utf8_lossy(&received.stderr);
+    assert_eq!(received.status.signal(), None, "{stderr}");
+    assert!(received.status.success(), "{stderr}");
+    assert_eq!(written, "ping\n".repeat(30));
+    assert!(dumped.contains("packet #30"), "{dumped}");
+    assert!(
+        stderr.contains("can't be reopened under --sandbox"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("30 packets read, 30 written"), "{stderr}");
+}
+
+#[test]
+fn test_sandbox_refuses_what_opens_files_later() {
+    let output = mnc()
+        .args(["239.255.77.60", "-p", "39560", "--sandbox"])
+        .args(["--exec", "cat"])
+        .output()
+        .expect("mnc");
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,60 @@
+//! -s counts the senders, logging new ones and those that go quiet.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::{mnc, temp_path};
+
+#[test]
+fn test_new_and_silent_senders() {
+    let short = temp_path("senders-short");
+    let long = temp_path("senders-long");
+    let line = "{\"payload_b64\": \"cGluZw==\", \"delay_us\": 50000}\n";
+    std::fs::write(&short, line.repeat(5)).expect("write");
+    std::fs::write(&long, line.repeat(60)).expect("write");
+    let receiver = mnc()
+        .args(["239.255.77.61", "-p", "39561", "-s"])
+        .args(["--sender-timeout", "1", "--duration", "4"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    // Each sender has a socket, and so a source port, of its own
+    let senders: Vec<_> = [&short, &long]
+        .iter()
+        .map(|input| {
+            mnc()
+                .args([
+                    "239.255.77.61",
+                    "-p",
+                    "39561",
+                    "--input-format",
+                    "jsonl",
+                    "-i",
+                ])
+                .arg(input)
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("sender")
+        })
+        .collect();
+    for mut sender in senders {
+        assert!(sender.wait().expect("sender").success());
+    }
+
+    let output = receiver.wait_with_output().expect("receiver");
+    let _ = std::fs::remove_file(&short);
+    let _ = std::fs::remove_file(&long);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("new sender ").count(), 2, "{stderr}");
+    assert!(stderr.contains("sources: 2 (+2 new)"), "{stderr}");
+    // The short one, while the long one carries on
+    assert!(stderr.contains("silent for 1s"), "{stderr}");
+}
@@ -0,0 +1,98 @@
+//! --stamp-seq into --check-seq, on one host.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::io::Write;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::piped;
+
+#[test]
+fn test_prepended_sequence_is_removed() {
+    let receiver = piped(&[
+        "239.255.77.32",
+        "-p",
+        "39532",
+        "--check-seq",
+        "-c",
+        "3",
+        "-o",
+        "-",
+    ])
+    .spawn()
+    .expect("spawn receiver");
+    sleep(Duration::from_millis(300));
+
+    let mut sender = piped(&["239.255.77.32", "-p", "39532", "-i", "-", "--stamp-seq"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("spawn sender");
+    sender
+        .stdin
+        .take()
+        .expect("stdin")
+        .write_all(b"one\ntwo\nthree\n")
+        .expect("write");
+    assert!(sender.wait().expect("wait").success());
+
+    let received = receiver.wait_with_output().expect("wait");
+    assert!(received.status.success(), "{received:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&received.stdout),
+        "one\ntwo\nthree\n"
+    );
+}
+
+#[test]
+fn test_stamp_seq_needs_transmit() {
+    let output = piped(&["239.255.77.32", "-p", "39532", "--stamp-seq=4"])
+        .output()
+        .expect("run");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--stamp-seq only applies"));
+}
+
+#[test]
+fn test_reorder_puts_swapped_packets_back() {
+    let receiver = piped(&[
+        "239.255.77.40",
+        "-p",
+        "39541",
+        "--check-seq",
+        "--reorder",
+        "4",
+        "-c",
+        "5",
+        "-o",
+        "-",
+    ])
+    .spawn()
+    .expect("spawn receiver");
+    sleep(Duration::from_millis(300));
+
+    // Numbered like --stamp-seq, sent as if two paths had swapped pairs
+    let sender = std::net::UdpSocket::bind("0.0.0.0:0").expect("bind");
+    for (seq, payload) in [(0u32, "a"), (2, "c"), (1, "b"), (4, "e"), (3, "d")] {
+        let mut datagram = seq.to_be_bytes().to_vec();
+        datagram.extend_from_slice(payload.as_bytes());
+        sender
+            .send_to(&datagram, "239.255.77.40:39541")
+            .expect("send");
+        sleep(Duration::from_millis(10));
+    }
+
+    let received = receiver.wait_with_output().expect("wait");
+    assert!(received.status.success(), "{received:?}");
+    assert_eq!(String::from_utf8_lossy(&received.stdout), "a\nb\nc\nd\ne\n");
+}
+
+#[test]
+fn test_reorder_needs_sequence_numbers() {
+    let output = piped(&["239.255.77.40", "-p", "39541", "--reorder", "4"])
+        .output()
+        .expect("run");
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,142 @@
+//! Exit codes for the ways a run can end.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::process::{Child, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
+
+use common::{piped, temp_path};
+
+fn interrupt(child: &Child) {
+    kill(Pid::from_raw(child.id() as i32), Signal::SIGINT).expect("SIGINT");
+}
+
+#[test]
+fn test_ctrl_c_exits_cleanly() {
+    let child = piped(&["239.255.77.2", "-p", "39502"])
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+    interrupt(&child);
+
+    let output = child.wait_with_output().expect("wait");
+    assert!(output.status.success(), "{output:?}");
+}
+
+#[test]
+fn test_stuck_reader_with_nothing_queued_exits_cleanly() {
+    // The reader blocks on a stdin that never delivers, so it can't see
+    // the exit signal and main has to give up on it. Nothing was lost,
+    // so that is still a clean Ctrl-C.
+    let mut child = piped(&["239.255.77.3", "-p", "39503", "-i", "-"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    let stdin = child.stdin.take();
+
+    sleep(Duration::from_millis(300));
+    interrupt(&child);
+
+    let output = child.wait_with_output().expect("wait");
+    drop(stdin);
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Timed out"));
+}
+
+#[test]
+fn test_writer_error_exits_nonzero() {
+    let mut child = piped(&[
+        "239.255.77.4",
+        "-p",
+        "39504",
+        "-o",
+        "/dev/full",
+        "--strict-disk",
+        "-c",
+        "3",
+    ])
+    .spawn()
+    .expect("spawn");
+
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while child.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        sender
+            .send_to(b"payload", "239.255.77.4:39504")
+            .expect("send");
+        sleep(Duration::from_millis(50));
+    }
+
+    let output = child.wait_with_output().expect("wait");
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("writer failed"));
+}
+
+#[test]
+fn test_queued_packets_drain_before_exit() {
+    // Reaching -c signals exit while the last packets may still be queued
+    // behind a slow sink; they must be written, not dropped.
+    let path = temp_path("drain");
+    let command = format!("sleep 0.2; cat > {}", path.display());
+    let child = piped(&[
+        "239.255.77.6",
+        "-p",
+        "39506",
+        "-c",
+        "3",
+        "--exec",
+        &command,
+        "--drain-timeout",
+        "5",
+    ])
+    .spawn()
+    .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    for message in ["one", "two", "three"] {
+        sender
+            .send_to(message.as_bytes(), "239.255.77.6:39506")
+            .expect("send");
+    }
+
+    let output = child.wait_with_output().expect("wait");
+    assert!(output.status.success(), "{output:?}");
+    let written = std::fs::read_to_string(&path).expect("read");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(written, "one\ntwo\nthree\n");
+}
+
+#[test]
+fn test_drain_timeout_is_configurable() {
+    let mut child = piped(&[
+        "239.255.77.7",
+        "-p",
+        "39507",
+        "-i",
+        "-",
+        "--drain-timeout",
+        "2",
+    ])
+    .stdin(Stdio::piped())
+    .spawn()
+    .expect("spawn");
+    let stdin = child.stdin.take();
+
+    sleep(Duration::from_millis(300));
+    let interrupted = Instant::now();
+    interrupt(&child);
+
+    let output = child.wait_with_output().expect("wait");
+    drop(stdin);
+    assert!(output.status.success(), "{output:?}");
+    assert!(interrupted.elapsed() >= Duration::from_secs(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Timed out waiting 2s"));
+}
@@ -0,0 +1,76 @@
+//! SIGUSR1, SIGUSR2 and SIGHUP poke a running pipeline without stopping it.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
+
+use common::{mnc, temp_path};
+
+#[test]
+fn test_usr1_snapshot_and_usr2_dump_toggle() {
+    let child = mnc()
+        .args(["239.255.77.35", "-p", "39535", "-s"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    let pid = Pid::from_raw(child.id() as i32);
+
+    // Idle, so no stats line is due on its own
+    sleep(Duration::from_millis(300));
+    kill(pid, Signal::SIGUSR1).expect("SIGUSR1");
+    sleep(Duration::from_millis(300));
+    kill(pid, Signal::SIGUSR2).expect("SIGUSR2");
+    sleep(Duration::from_millis(300));
+    kill(pid, Signal::SIGINT).expect("SIGINT");
+
+    let output = child.wait_with_output().expect("wait");
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("packets: 0"), "{stderr}");
+    assert!(stderr.contains("totals: 0 packets"), "{stderr}");
+    assert!(stderr.contains("hex dumps on"), "{stderr}");
+}
+
+#[test]
+fn test_hup_reopens_the_output_after_rotation() {
+    let path = temp_path("hup");
+    let rotated = path.with_extension("1");
+    let child = mnc()
+        .args(["239.255.77.36", "-p", "39536", "-t", "text", "-o"])
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    let pid = Pid::from_raw(child.id() as i32);
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    let send = |payload: &[u8]| {
+        sender
+            .send_to(payload, "239.255.77.36:39536")
+            .expect("send");
+        sleep(Duration::from_millis(300));
+    };
+
+    sleep(Duration::from_millis(300));
+    send(b"before");
+    std::fs::rename(&path, &rotated).expect("rename");
+    kill(pid, Signal::SIGHUP).expect("SIGHUP");
+    sleep(Duration::from_millis(300));
+    send(b"after");
+    kill(pid, Signal::SIGINT).expect("SIGINT");
+
+    let output = child.wait_with_output().expect("wait");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(&rotated).expect("rotated"), b"before\n");
+    assert_eq!(std::fs::read(&path).expect("reopened"), b"after\n");
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&rotated);
+}
@@ -0,0 +1,111 @@
+//! --split-by source writing each sender to its own file, on one host.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::{piped, recording, temp_path};
+
+#[test]
+fn test_each_sender_gets_a_file() {
+    let dir = temp_path("split-by");
+    std::fs::create_dir_all(&dir).expect("dir");
+    let template = dir.join("capture.txt");
+
+    let receiver = piped(&[
+        "239.255.77.42",
+        "-p",
+        "39543",
+        "--split-by",
+        "source",
+        "-c",
+        "4",
+        "-o",
+        template.to_str().expect("path"),
+    ])
+    .spawn()
+    .expect("spawn receiver");
+    sleep(Duration::from_millis(300));
+
+    let first = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    let second = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    for (sender, payload) in [(&first, "a"), (&second, "b"), (&first, "c"), (&second, "d")] {
+        sender
+            .send_to(payload.as_bytes(), "239.255.77.42:39543")
+            .expect("send");
+        sleep(Duration::from_millis(10));
+    }
+
+    let received = receiver.wait_with_output().expect("wait");
+    assert!(received.status.success(), "{received:?}");
+
+    let mut files: Vec<String> = std::fs::read_dir(&dir)
+        .expect("read dir")
+        .map(|entry| std::fs::read_to_string(entry.expect("entry").path()).expect("read"))
+        .collect();
+    files.sort();
+    assert_eq!(files, ["a\nc\n", "b\nd\n"]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_split_by_needs_a_file() {
+    let output = piped(&[
+        "239.255.77.42",
+        "-p",
+        "39543",
+        "--split-by",
+        "source",
+        "-o",
+        "-",
+    ])
+    .output()
+    .expect("run");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_each_stream_id_gets_a_file() {
+    let dir = temp_path("split-stream");
+    std::fs::create_dir_all(&dir).expect("dir");
+    let frame = |stream_id: u32| {
+        let mut frame = b"VRLP\x00\x10\x00\x00".to_vec();
+        for word in [(1 << 28) | 3, stream_id, 0] {
+            frame.extend(u32::to_be_bytes(word));
+        }
+        frame
+    };
+    let path = dir.join("input.bin");
+    std::fs::write(&path, recording(&[frame(1), frame(2), frame(1)])).expect("write");
+
+    let output = piped(&[
+        "239.255.77.43",
+        "-p",
+        "39544",
+        "-t",
+        "vita49",
+        "--local",
+        "--split-by",
+        "stream-id",
+        "-i",
+        path.to_str().expect("path"),
+        "-o",
+        dir.join("{stream}.bin").to_str().expect("path"),
+    ])
+    .output()
+    .expect("run");
+    assert!(output.status.success(), "{output:?}");
+
+    let size = |name: &str| std::fs::metadata(dir.join(name)).expect(name).len();
+    assert_eq!(size("00000001.bin"), 2 * (4 + 20));
+    assert_eq!(size("00000002.bin"), 4 + 20);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("2 streams: 0x00000001 40.0 B, 0x00000002 20.0 B"),
+        "{stderr}"
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,48 @@
+//! --stats-on-change keeps quiet while a stream holds steady.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::{mnc, temp_path};
+
+#[test]
+fn test_steady_stream_prints_baseline_and_totals() {
+    let input = temp_path("on-change");
+    // 20 packets a second for three seconds
+    let lines: String = (0..60)
+        .map(|_| "{\"payload_b64\": \"cGluZw==\", \"delay_us\": 50000}\n")
+        .collect();
+    std::fs::write(&input, lines).expect("write");
+    let receiver = mnc()
+        .args(["239.255.77.56", "-p", "39556", "-c", "60"])
+        .args(["--stats-on-change=100", "--idle-timeout", "10"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    let sender = mnc()
+        .args(["239.255.77.56", "-p", "39556", "-t", "binary"])
+        .args(["--input-format", "jsonl", "-i"])
+        .arg(&input)
+        .stderr(Stdio::null())
+        .status()
+        .expect("sender");
+    assert!(sender.success());
+
+    let output = receiver.wait_with_output().expect("receiver");
+    let _ = std::fs::remove_file(&input);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines = stderr
+        .lines()
+        .filter(|line| line.contains("  rate: "))
+        .count();
+    assert_eq!(lines, 1, "{stderr}");
+    assert!(stderr.contains("totals: 60 packets"), "{stderr}");
+}
@@ -0,0 +1,88 @@
+//! Packets written to stdout get it to themselves: logs, stats lines and hex
+//! dumps go to stderr.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use common::{exits_with, exits_with_input, mnc, recording, temp_file, temp_path};
+
+#[test]
+fn test_binary_stdout_holds_only_packets() {
+    let input = recording(&[
+        b"\x00\x01\x02\xff".to_vec(),
+        b"\npacket #1\n".to_vec(),
+        b"\x7fELF".to_vec(),
+    ]);
+    let path = temp_file("stdout", &input);
+
+    let output = exits_with(
+        mnc()
+            .args([
+                "239.255.77.34",
+                "-p",
+                "39534",
+                "-t",
+                "binary",
+                "--local",
+                "-s",
+                "-v",
+                "-c",
+                "3",
+                "-o",
+                "-",
+                "-i",
+            ])
+            .arg(&path),
+        0,
+    );
+    assert_eq!(output.stdout, input);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("reading from"), "{stderr}");
+    assert!(stderr.contains("packet #1"), "{stderr}");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_log_stdout_conflicts_with_stdout_output() {
+    exits_with(
+        mnc().args(["239.255.77.34", "-p", "39534", "-o", "-", "--log", "stdout"]),
+        2,
+    );
+}
+
+#[test]
+fn test_log_to_file() {
+    let path = temp_path("log");
+    let target = format!("file:{}", path.display());
+
+    let output = exits_with(
+        mnc().args([
+            "239.255.77.34",
+            "-p",
+            "39534",
+            "--dry-run",
+            "--log",
+            &target,
+        ]),
+        0,
+    );
+    assert!(output.stderr.is_empty());
+    let log = std::fs::read_to_string(&path).expect("log file");
+    assert!(log.contains("dry run ok"), "{log}");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_dump_diff() {
+    // Not a terminal, so changes are bracketed
+    let output = exits_with_input(
+        mnc()
+            .args(["239.255.77.34", "-p", "39534", "--local", "-i", "-"])
+            .args(["-v", "--dump-diff", "-c", "2"]),
+        b"abcd\nabXd\n",
+        0,
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(" 61  62 [58] 64 "), "{stdout}");
+    assert!(stdout.contains("1 byte differs at offset 0x2"), "{stdout}");
+}
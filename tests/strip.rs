@@ -0,0 +1,54 @@
+//! --prepend adds a header on the way out and --strip takes it off again.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::{mnc, temp_path};
+
+#[test]
+fn test_prepend_then_strip() {
+    let input = temp_path("strip-input");
+    let output = temp_path("strip-output");
+    std::fs::write(&input, "aaaa\nb\ncccc\n").expect("write");
+    let mut receiver = mnc()
+        .args(["239.255.77.55", "-p", "39555", "-c", "3", "-o"])
+        .arg(&output)
+        .args(["--strip", "5"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    // Every packet gets "hi" in front, then the receiver strips three more
+    // bytes than that, which "b\n" doesn't have
+    let sender = mnc()
+        .args(["239.255.77.55", "-p", "39555"])
+        .args(["--prepend", "6869", "-i"])
+        .arg(&input)
+        .status()
+        .expect("sender");
+    assert!(sender.success());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while receiver.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        sleep(Duration::from_millis(20));
+    }
+    let _ = receiver.kill();
+    let stderr = receiver.wait_with_output().expect("receiver").stderr;
+
+    let written = std::fs::read_to_string(&output).expect("read");
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+    assert_eq!(written, "a\nc\n");
+    let stderr = String::from_utf8_lossy(&stderr);
+    assert!(
+        stderr.contains("3 packets read, 2 written, 1 too short to strip"),
+        "{stderr}"
+    );
+}
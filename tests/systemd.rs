@@ -0,0 +1,64 @@
+//! Under systemd, mnc says when it is ready and when it is stopping.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::os::unix::net::UnixDatagram;
+use std::process::Stdio;
+use std::time::Duration;
+
+use common::{mnc, temp_path};
+
+#[test]
+fn test_notify_ready_and_stopping() {
+    let path = temp_path("notify-socket");
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixDatagram::bind(&path).expect("bind");
+    listener
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("timeout");
+
+    let status = mnc()
+        .args(["239.255.77.57", "-p", "39557", "--duration", "1"])
+        .env("NOTIFY_SOCKET", &path)
+        .stderr(Stdio::null())
+        .status()
+        .expect("mnc");
+    assert!(status.success());
+
+    let mut states = Vec::new();
+    let mut buffer = [0u8; 256];
+    while let Ok(length) = listener.recv(&mut buffer) {
+        let state = String::from_utf8_lossy(buffer.get(..length).unwrap_or_default());
+        let stopping = state == "STOPPING=1";
+        states.push(state.into_owned());
+        if stopping {
+            break;
+        }
+    }
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(
+        states.first().map(String::as_str),
+        Some("READY=1"),
+        "{states:?}"
+    );
+    assert_eq!(
+        states.last().map(String::as_str),
+        Some("STOPPING=1"),
+        "{states:?}"
+    );
+}
+
+#[test]
+fn test_no_notify_socket() {
+    // Nothing to tell, and nothing goes wrong for it
+    let status = mnc()
+        .args(["239.255.77.58", "-p", "39558", "--duration", "1"])
+        .env_remove("NOTIFY_SOCKET")
+        .env("LISTEN_FDS", "1")
+        .env("LISTEN_PID", "1")
+        .stderr(Stdio::null())
+        .status()
+        .expect("mnc");
+    assert!(status.success());
+}
@@ -0,0 +1,85 @@
+//! --template sends numbered packets made up from a pattern, and
+//! --verify-template counts them back in on the receiver.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::mnc;
+
+const GROUP: &str = "239.255.77.80";
+const PORT: &str = "39584";
+
+#[test]
+fn test_template_round_trip() {
+    let receiver = mnc()
+        .args([
+            GROUP,
+            "-p",
+            PORT,
+            "--verify-template",
+            "load {seq}",
+            "-s",
+            "-c",
+            "30",
+            "--duration",
+            "10",
+            "-o",
+            "-",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    sleep(Duration::from_millis(300));
+
+    let sender = mnc()
+        .args([
+            GROUP,
+            "-p",
+            PORT,
+            "--template",
+            "load {seq} {time_unix_us} {rand:8}",
+            "--pps",
+            "20",
+            "-c",
+            "30",
+        ])
+        .stdout(Stdio::null())
+        .output()
+        .expect("mnc");
+    let stderr = String::from_utf8_lossy(&sender.stderr);
+    assert!(sender.status.success(), "{stderr}");
+    assert!(stderr.contains("30 packets read"), "{stderr}");
+
+    let output = receiver.wait_with_output().expect("wait");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("30 packets read"), "{stderr}");
+    // The stats line each second shows the numbers counted in so far
+    assert!(stderr.contains("seq lost: 0"), "{stderr}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 30, "{stdout}");
+    assert!(stdout.starts_with("load 0 "), "{stdout}");
+    assert!(stdout.contains("\nload 29 "), "{stdout}");
+}
+
+#[test]
+fn test_bad_templates_refused() {
+    for args in [
+        &["--template", "load {sequence}"][..],
+        &["--template", "load {seq", "-i", "-"],
+        &["--verify-template", "no number here"],
+    ] {
+        let output = mnc()
+            .arg(GROUP)
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .expect("mnc");
+        assert_eq!(output.status.code(), Some(2), "{args:?}: {output:?}");
+    }
+}
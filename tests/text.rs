@@ -0,0 +1,110 @@
+//! Text mode with lines that are too long or don't end in a newline.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Output;
+
+use common::{exits_with_input, mnc};
+
+// Runs a text mode copy of stdin to stdout, checking it exits with code
+fn text(args: &[&str], stdin: &[u8], code: i32) -> Output {
+    exits_with_input(
+        mnc()
+            .args([
+                "239.255.77.45",
+                "-p",
+                "39546",
+                "--local",
+                "-o",
+                "-",
+                "-i",
+                "-",
+            ])
+            .args(args),
+        stdin,
+        code,
+    )
+}
+
+#[test]
+fn test_giant_line_fails() {
+    // Binary data with no newline in sight
+    let output = text(&[], &vec![0xa5; 1024 * 1024], 4);
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    assert!(stderr.contains("--max-line-length"), "{stderr}");
+}
+
+#[test]
+fn test_split_long_lines() {
+    let input = b"abcdefghij\nxy\n";
+    let output = text(
+        &[
+            "--max-line-length",
+            "4",
+            "--split-long-lines",
+            "--no-append-newline",
+        ],
+        input,
+        0,
+    );
+    assert_eq!(output.stdout, input);
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    assert!(stderr.contains("4 packets read, 4 written"), "{stderr}");
+}
+
+#[test]
+fn test_no_trailing_newline() {
+    let output = text(&[], b"one\ntwo", 0);
+    assert_eq!(output.stdout, b"one\ntwo\n");
+
+    let output = text(&["--no-append-newline"], b"one\ntwo", 0);
+    assert_eq!(output.stdout, b"one\ntwo");
+}
+
+#[test]
+fn test_line_options_need_text_input() {
+    text(&["-t", "binary", "--max-line-length", "4"], b"", 2);
+}
+
+#[test]
+fn test_label() {
+    let output = text(&["--label=east"], b"one\ntwo\n", 0);
+    assert_eq!(output.stdout, b"[east] one\n[east] two\n");
+
+    // Read from a file, the packets have no group of their own
+    let output = text(&["--label"], b"one\n", 0);
+    assert_eq!(output.stdout, b"[239.255.77.45:39546] one\n");
+
+    // The pieces of a long line carry on the first one's label
+    let args = [
+        "--label=east",
+        "--max-line-length",
+        "4",
+        "--split-long-lines",
+        "--no-append-newline",
+    ];
+    let output = text(&args, b"abcdefghij\nxy\n", 0);
+    assert_eq!(output.stdout, b"[east] abcdefghij\n[east] xy\n");
+}
+
+#[test]
+fn test_lines_per_packet_round_trip() {
+    let input: Vec<u8> = (0..1000)
+        .flat_map(|n| format!("reading {n} 21.5C\n").into_bytes())
+        .collect();
+    let output = text(
+        &["--lines-per-packet", "50", "--no-append-newline"],
+        &input,
+        0,
+    );
+    assert_eq!(output.stdout, input);
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    // 50 lines of about 20 bytes fit in a bundle
+    assert!(stderr.contains("20 packets read, 20 written"), "{stderr}");
+}
+
+#[test]
+fn test_lines_per_packet_needs_text_input() {
+    text(&["-t", "binary", "--lines-per-packet", "4"], b"", 2);
+}
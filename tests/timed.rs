@@ -0,0 +1,84 @@
+//! --output-format timed keeps each packet's arrival time, and
+//! --input-format timed replays the packets as far apart as they arrived.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use common::{mnc, temp_file};
+
+const GROUP: &str = "239.255.77.82";
+const PORT: &str = "39589";
+
+/// Payloads recorded at the given offsets in milliseconds.
+fn recording(packets: &[(u64, &[u8])]) -> Vec<u8> {
+    let mut recording = Vec::new();
+    for (millis, payload) in packets {
+        let nanos = 1_700_000_000_000_000_000 + millis * 1_000_000;
+        recording.extend(nanos.to_le_bytes());
+        recording.extend((payload.len() as u32).to_le_bytes());
+        recording.extend_from_slice(payload);
+    }
+    recording
+}
+
+fn replay(recording: &[u8], name: &str, extra: &[&str]) -> (std::process::Output, Duration) {
+    let path = temp_file(&format!("timed-{name}"), recording);
+    let started = Instant::now();
+    let output = mnc()
+        .args([GROUP, "-p", PORT, "--local", "--input-format", "timed"])
+        .args(["--output-format", "text", "-o", "-", "-i"])
+        .arg(&path)
+        .args(extra)
+        .stdin(Stdio::null())
+        .output()
+        .expect("mnc");
+    let elapsed = started.elapsed();
+    let _ = std::fs::remove_file(path);
+    (output, elapsed)
+}
+
+#[test]
+fn test_replay_keeps_the_gaps() {
+    // The last packet's clock went backwards, so it follows at once
+    let recording = recording(&[(0, b"one"), (400, b"two"), (800, b"three"), (100, b"four")]);
+
+    let (output, elapsed) = replay(&recording, "normal", &[]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "one\ntwo\nthree\nfour\n"
+    );
+    assert!(elapsed >= Duration::from_millis(800), "{elapsed:?}");
+    assert!(elapsed < Duration::from_secs(3), "{elapsed:?}");
+
+    let (output, doubled) = replay(&recording, "double", &["--speed", "2"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(doubled >= Duration::from_millis(400), "{doubled:?}");
+    assert!(doubled < elapsed, "{doubled:?} / {elapsed:?}");
+
+    let (output, _) = replay(&recording, "count", &["--speed", "0", "-c", "2"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "one\ntwo\n");
+}
+
+#[test]
+fn test_replay_cut_short_fails() {
+    let recording = recording(&[(0, b"one"), (10, b"two")]);
+    let cut = recording.get(..recording.len() - 1).expect("cut");
+    let (output, _) = replay(cut, "cut", &["--speed", "0"]);
+    assert!(!output.status.success(), "{output:?}");
+}
+
+#[test]
+fn test_speed_zero_needs_timed_input() {
+    let output = mnc()
+        .args([GROUP, "-p", PORT, "--template", "x {seq}", "--pps", "10"])
+        .args(["--speed", "0"])
+        .stdin(Stdio::null())
+        .output()
+        .expect("mnc");
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+}
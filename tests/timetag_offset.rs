@@ -0,0 +1,98 @@
+//! --timetag-offset compares SDDS time tags with the kernel's receive times.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+
+use common::{mnc, sdds_packet, temp_file};
+
+// Ticks of 250ps in a second, as SDDS time tags count them
+const TICKS_PER_SEC: u128 = 4_000_000_000;
+
+#[test]
+fn test_offset_and_drift_on_the_stats_line() {
+    // Tagged with when each packet is meant to go, counting from 1970
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock")
+        .as_nanos();
+    let lines: String = (1u16..=30)
+        .map(|seq| {
+            let tag = (now + seq as u128 * 50_000_000) * TICKS_PER_SEC / 1_000_000_000;
+            let mut packet = sdds_packet(seq);
+            packet.splice(4..5, [0x80]);
+            packet.splice(8..16, (tag as u64).to_be_bytes());
+            format!(
+                "{{\"payload_b64\": \"{}\", \"delay_us\": 50000}}\n",
+                base64::engine::general_purpose::STANDARD.encode(&packet)
+            )
+        })
+        .collect();
+    let input = temp_file("timetag-offset", lines);
+
+    let receiver = mnc()
+        .args(["239.255.77.62", "-p", "39562", "-t", "sdds", "-c", "30"])
+        .args([
+            "--timetag-offset",
+            "--sdds-epoch",
+            "1970",
+            "--duration",
+            "5",
+        ])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+
+    sleep(Duration::from_millis(300));
+
+    let sent = mnc()
+        .args(["239.255.77.62", "-p", "39562", "--input-format", "jsonl"])
+        .arg("-i")
+        .arg(&input)
+        .stderr(Stdio::null())
+        .status()
+        .expect("sender");
+    assert!(sent.success());
+
+    let output = receiver.wait_with_output().expect("receiver");
+    let _ = std::fs::remove_file(&input);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("  offset: min "), "{stderr}");
+    assert!(stderr.contains("ppm"), "{stderr}");
+}
+
+#[test]
+fn test_needs_an_epoch_and_the_network() {
+    let output = mnc()
+        .args([
+            "239.255.77.62",
+            "-p",
+            "39562",
+            "-t",
+            "sdds",
+            "--timetag-offset",
+        ])
+        .output()
+        .expect("mnc");
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = mnc()
+        .args(["239.255.77.62", "-p", "39562", "-t", "sdds", "--local"])
+        .args([
+            "--timetag-offset",
+            "--sdds-epoch",
+            "2024",
+            "-i",
+            "/dev/null",
+        ])
+        .output()
+        .expect("mnc");
+    assert_eq!(output.status.code(), Some(2));
+}
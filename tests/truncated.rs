@@ -0,0 +1,70 @@
+//! Datagrams longer than --max-packet are counted, and with --drop-truncated
+//! left out.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::process::{Output, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::mnc;
+
+/// Ten 100 byte datagrams and ten 8 byte ones, alternating, into a receiver
+/// taking 16 bytes of each.
+fn receive(port: u16, args: &[&str]) -> Output {
+    let child = mnc()
+        .args([
+            "239.255.77.70",
+            "-p",
+            &port.to_string(),
+            "--max-packet",
+            "16",
+        ])
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    sleep(Duration::from_millis(300));
+
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    for _ in 0..10 {
+        for payload in [&[b'x'; 100][..], &[b'y'; 8]] {
+            sender
+                .send_to(payload, ("239.255.77.70", port))
+                .expect("send");
+            sleep(Duration::from_millis(10));
+        }
+    }
+    child.wait_with_output().expect("wait")
+}
+
+#[test]
+fn test_truncated_are_kept_and_counted() {
+    let output = receive(39572, &["-c", "20"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("100 byte datagram from "), "{stderr}");
+    assert!(stderr.contains("truncated to 16"), "{stderr}");
+    assert!(
+        stderr.contains("further truncated datagrams are only counted"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("20 packets read, 20 written, 10 truncated;"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn test_drop_truncated() {
+    let output = receive(39573, &["-c", "10", "--drop-truncated"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(
+        stderr.contains("10 packets read, 10 written, 10 truncated and dropped;"),
+        "{stderr}"
+    );
+}
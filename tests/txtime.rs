@@ -0,0 +1,109 @@
+//! Scheduled transmission with --txtime.
+//! The spacing test needs lo to be multicast capable with an fq or etf qdisc,
+//! which the tests won't set up themselves:
+//!   ip link set lo multicast on && tc qdisc replace dev lo root fq
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::io::IoSliceMut;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::os::fd::AsRawFd;
+use std::process::Command;
+use std::time::Duration;
+
+use nix::sys::socket::{ControlMessageOwned, MsgFlags, recvmsg, setsockopt, sockopt};
+
+use common::{exits_with, mnc, temp_path};
+
+const PACKETS: usize = 50;
+const PPS: u64 = 1000;
+
+fn lo_is_ready() -> bool {
+    let qdiscs = Command::new("tc")
+        .args(["qdisc", "show", "dev", "lo"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+        .unwrap_or_default();
+    let link = Command::new("ip")
+        .args(["link", "show", "dev", "lo"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+        .unwrap_or_default();
+    (qdiscs.contains("qdisc fq ") || qdiscs.contains("qdisc etf ")) && link.contains("MULTICAST")
+}
+
+#[test]
+fn test_txtime_needs_pps() {
+    exits_with(
+        mnc().args([
+            "239.255.77.19",
+            "-p",
+            "39521",
+            "-i",
+            "/dev/null",
+            "--txtime",
+        ]),
+        2,
+    );
+}
+
+#[test]
+fn test_txtime_spacing_on_loopback() {
+    if !lo_is_ready() {
+        eprintln!("skipping: lo needs multicast and an fq or etf qdisc");
+        return;
+    }
+
+    let receiver = UdpSocket::bind("0.0.0.0:39522").expect("bind");
+    receiver
+        .join_multicast_v4(&Ipv4Addr::new(239, 255, 77, 20), &Ipv4Addr::LOCALHOST)
+        .expect("join");
+    receiver
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("timeout");
+    setsockopt(&receiver, sockopt::ReceiveTimestampns, &true).expect("SO_TIMESTAMPNS");
+
+    let input = temp_path("txtime");
+    let lines: Vec<String> = (0..PACKETS).map(|i| i.to_string()).collect();
+    std::fs::write(&input, lines.join("\n") + "\n").expect("write");
+
+    exits_with(
+        mnc()
+            .args(["lo:239.255.77.20", "-p", "39522", "--txtime", "--pps"])
+            .arg(PPS.to_string())
+            .arg("-i")
+            .arg(&input),
+        0,
+    );
+    let _ = std::fs::remove_file(&input);
+
+    let mut arrivals = Vec::new();
+    let mut buffer = [0u8; 64];
+    let mut space = nix::cmsg_space!(nix::sys::time::TimeSpec);
+    while arrivals.len() < PACKETS {
+        let mut iov = [IoSliceMut::new(&mut buffer)];
+        let message = recvmsg::<()>(
+            receiver.as_raw_fd(),
+            &mut iov,
+            Some(&mut space),
+            MsgFlags::empty(),
+        )
+        .expect("recvmsg");
+        for cmsg in message.cmsgs().expect("cmsgs") {
+            if let ControlMessageOwned::ScmTimestampns(at) = cmsg {
+                arrivals.push(at.tv_sec() as i128 * 1_000_000_000 + at.tv_nsec() as i128);
+            }
+        }
+    }
+
+    // Userspace pacing at this rate drifts by far more than 5%
+    let first = arrivals.first().copied().unwrap_or_default();
+    let last = arrivals.last().copied().unwrap_or_default();
+    let spacing = (last - first) / (PACKETS as i128 - 1);
+    let expected = 1_000_000_000 / PPS as i128;
+    assert!(
+        (spacing - expected).abs() < expected / 20,
+        "mean spacing {spacing}ns, expected {expected}ns"
+    );
+}
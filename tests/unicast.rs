@@ -0,0 +1,59 @@
+//! An address outside 224.0.0.0/4 is received by binding to it and sent to
+//! by connecting, with no group joined.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::io::Write;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+
+use common::mnc;
+
+const PORT: &str = "39587";
+
+#[test]
+fn test_unicast_round_trip() {
+    let receiver = mnc()
+        .args(["0.0.0.0", "-p", PORT, "-c", "3", "-o", "-"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn receiver");
+    sleep(Duration::from_millis(300));
+
+    let mut sender = mnc()
+        .args(["127.0.0.1", "-p", PORT, "-i", "-"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("spawn sender");
+    sender
+        .stdin
+        .take()
+        .expect("stdin")
+        .write_all(b"one\ntwo\nthree\n")
+        .expect("write");
+    assert!(sender.wait().expect("wait").success());
+
+    let received = receiver.wait_with_output().expect("wait");
+    assert!(received.status.success(), "{received:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&received.stdout),
+        "one\ntwo\nthree\n"
+    );
+}
+
+#[test]
+fn test_unicast_needs_an_address_of_ours() {
+    let output = mnc()
+        .args(["192.0.2.250", "-p", PORT, "-c", "1"])
+        .output()
+        .expect("mnc");
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("is not an address of this host"),
+        "{output:?}"
+    );
+}
@@ -0,0 +1,41 @@
+//! --unique counts distinct payloads on the stats line and in the summary.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::mnc;
+
+#[test]
+fn test_repeated_payloads_count_once() {
+    let child = mnc()
+        .args(["239.255.77.68", "-p", "39571", "-s", "--unique", "-c", "80"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn");
+    sleep(Duration::from_millis(300));
+
+    // A keepalive repeated until its state changes, three states in all
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut sent = 0;
+    while sent < 80 && Instant::now() < deadline {
+        let state = format!("state {}", sent * 3 / 80);
+        sender
+            .send_to(state.as_bytes(), "239.255.77.68:39571")
+            .expect("send");
+        sent += 1;
+        sleep(Duration::from_millis(20));
+    }
+
+    let output = child.wait_with_output().expect("wait");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("  unique: "), "{stderr}");
+    assert!(stderr.contains("unique: 3 / total: 80"), "{stderr}");
+}
@@ -0,0 +1,67 @@
+//! -v hex dumps the first packet, or --dump of them, without limiting the
+//! run; only -c does that, unless --legacy-verbose.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::process::Output;
+
+use common::{mnc, temp_file};
+
+const DEPRECATED_V: &str = "-v no longer stops after the first packet";
+const DEPRECATED_LEGACY: &str = "--legacy-verbose is deprecated";
+
+// Replays three lines with args, giving the run's output, its stderr and
+// the dumps written to stdout.
+fn replay(args: &[&str]) -> (Output, String, usize) {
+    let input = temp_file(&format!("verbose-{}", args.join("")), "one\ntwo\nthree\n");
+    let output = mnc()
+        .args(["239.255.77.75", "-p", "39581", "--local", "-i"])
+        .arg(&input)
+        .args(args)
+        .output()
+        .expect("mnc");
+    let _ = std::fs::remove_file(input);
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    let dumps = String::from_utf8_lossy(&output.stdout)
+        .matches("packet #")
+        .count();
+    (output, stderr, dumps)
+}
+
+#[test]
+fn test_verbose_count_matrix() {
+    // args, packets read, packets dumped, deprecation note
+    let cases: [(&[&str], u64, usize, Option<&str>); 6] = [
+        (&["-v"], 3, 1, Some(DEPRECATED_V)),
+        (&["-v", "-c", "2"], 2, 2, None),
+        (&["-v", "--dump", "2"], 3, 2, Some(DEPRECATED_V)),
+        (&["-v", "--dump", "0"], 3, 3, Some(DEPRECATED_V)),
+        (&["-v", "-c", "3", "--dump", "1"], 3, 1, None),
+        (&["-v", "--legacy-verbose"], 1, 1, Some(DEPRECATED_LEGACY)),
+    ];
+    for (args, read, dumped, note) in cases {
+        let (output, stderr, dumps) = replay(args);
+        assert!(output.status.success(), "{args:?}: {stderr}");
+        assert!(
+            stderr.contains(&format!("{read} packets read")),
+            "{args:?}: {stderr}"
+        );
+        assert_eq!(dumps, dumped, "{args:?}: {stderr}");
+        for deprecation in [DEPRECATED_V, DEPRECATED_LEGACY] {
+            assert_eq!(
+                stderr.contains(deprecation),
+                note == Some(deprecation),
+                "{args:?}: {stderr}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_dump_needs_verbose() {
+    for args in [&["--dump", "2"][..], &["--legacy-verbose"]] {
+        let (output, stderr, _) = replay(args);
+        assert_eq!(output.status.code(), Some(2), "{args:?}: {stderr}");
+    }
+}
@@ -0,0 +1,73 @@
+//! --input-format vrlp finds VRLP frames recorded back to back, past junk.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use common::{mnc, temp_path};
+
+// A data frame with a stream id and four words of samples, 36 bytes.
+fn frame(seq: u16) -> Vec<u8> {
+    let words: [u32; 6] = [(1 << 28) | 6, 0x1234, 1, 2, 3, 4];
+    let mut frame = b"VRLP".to_vec();
+    frame.extend((((seq as u32 & 0xFFF) << 20) | 9).to_be_bytes());
+    frame.extend(words.iter().flat_map(|word| word.to_be_bytes()));
+    frame.extend(b"VEND");
+    frame
+}
+
+fn replay(name: &str, recording: &[u8]) -> (Option<i32>, String, Vec<u8>) {
+    let input = temp_path(&format!("vrlp-{name}.bin"));
+    let output = temp_path(&format!("vrlp-{name}.out"));
+    std::fs::write(&input, recording).expect("write");
+    let result = mnc()
+        .args(["239.255.77.66", "-p", "39567", "--local", "-t", "vita49"])
+        .args(["--input-format", "vrlp", "--fail-on-gap", "-i"])
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .output()
+        .expect("mnc");
+    let written = std::fs::read(&output).unwrap_or_default();
+    let _ = std::fs::remove_file(&input);
+    let _ = std::fs::remove_file(&output);
+    (
+        result.status.code(),
+        String::from_utf8_lossy(&result.stderr).to_string(),
+        written,
+    )
+}
+
+#[test]
+fn test_frames_found_past_junk() {
+    let mut recording = b"junk".to_vec();
+    for seq in 0..100 {
+        recording.extend(frame(seq));
+        if seq == 50 {
+            recording.extend(b"garbage!");
+        }
+    }
+    let (code, stderr, written) = replay("junk", &recording);
+    // Every frame, in sequence, with nothing skipped by --fail-on-gap
+    assert_eq!(code, Some(0), "{stderr}");
+    assert!(stderr.contains("100 packets read, 100 written"), "{stderr}");
+    assert!(stderr.contains("skipped 12 bytes"), "{stderr}");
+    // Written as binary, each frame after its length
+    assert_eq!(written.len(), 100 * (4 + 36));
+}
+
+#[test]
+fn test_broken_frame_is_skipped() {
+    let mut recording = Vec::new();
+    for seq in 0..10 {
+        let mut frame = frame(seq);
+        if seq == 5 {
+            frame.truncate(32);
+            frame.extend(b"XEND");
+        }
+        recording.extend(frame);
+    }
+    let (code, stderr, _) = replay("broken", &recording);
+    // Frame 5 goes missing, which --fail-on-gap sees
+    assert_eq!(code, Some(6), "{stderr}");
+    assert!(stderr.contains("skipped 36 bytes"), "{stderr}");
+}
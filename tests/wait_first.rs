@@ -0,0 +1,80 @@
+//! --wait-first times --duration from the first packet, --start-timeout gives
+//! up on one that never comes, and -c counts the same either way.
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use std::net::UdpSocket;
+use std::process::{Child, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use common::mnc;
+
+const GROUP: &str = "239.255.77.67";
+
+fn receive(port: &str, args: &[&str]) -> Child {
+    mnc()
+        .args([GROUP, "-p", port])
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn")
+}
+
+// Send a packet every 20ms from after until the receiver exits or for at most
+// five seconds, returning how long it ran and what it logged.
+fn send_after(mut child: Child, port: &str, after: Duration) -> (Duration, bool, String) {
+    let started = Instant::now();
+    sleep(after);
+    let sender = UdpSocket::bind("0.0.0.0:0").expect("bind");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while child.try_wait().expect("try_wait").is_none() && Instant::now() < deadline {
+        sender
+            .send_to(b"reading", format!("{GROUP}:{port}"))
+            .expect("send");
+        sleep(Duration::from_millis(20));
+    }
+    let _ = child.kill();
+    let output = child.wait_with_output().expect("wait");
+    (
+        started.elapsed(),
+        output.status.success(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+#[test]
+fn test_duration_starts_at_the_first_packet() {
+    let child = receive("39568", &["--wait-first", "--duration", "1", "-s"]);
+    let (elapsed, success, stderr) = send_after(child, "39568", Duration::from_millis(1500));
+    assert!(success, "{stderr}");
+    // A full second of packets after the late start
+    assert!(elapsed >= Duration::from_millis(2400), "{elapsed:?}");
+    assert!(stderr.contains("first packet after 1."), "{stderr}");
+    assert!(!stderr.contains(" 0 packets read"), "{stderr}");
+}
+
+#[test]
+fn test_start_timeout_gives_up() {
+    let started = Instant::now();
+    let output = receive("39569", &["--wait-first", "--start-timeout", "1"])
+        .wait_with_output()
+        .expect("wait");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(5), "{stderr}");
+    assert!(stderr.contains("no first packet after 1s"), "{stderr}");
+    assert!(started.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn test_count_is_unchanged_by_wait_first() {
+    let child = receive(
+        "39570",
+        &["--wait-first", "-c", "3", "--start-timeout", "5"],
+    );
+    let (_, success, stderr) = send_after(child, "39570", Duration::from_millis(500));
+    assert!(success, "{stderr}");
+    assert!(stderr.contains("3 packets read, 3 written"), "{stderr}");
+}